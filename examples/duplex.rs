@@ -0,0 +1,73 @@
+use lspower::{
+    jsonrpc::Result,
+    lsp::*,
+    Client,
+    LanguageServer,
+    LspService,
+};
+use serde_json::json;
+
+#[derive(Debug)]
+struct Backend {
+    client: Client,
+}
+
+#[lspower::async_trait]
+impl LanguageServer for Backend {
+    async fn initialize(&self, _: InitializeParams) -> Result<InitializeResult> {
+        Ok(InitializeResult {
+            capabilities: ServerCapabilities {
+                hover_provider: Some(HoverProviderCapability::Simple(true)),
+                ..ServerCapabilities::default()
+            },
+            ..InitializeResult::default()
+        })
+    }
+
+    async fn initialized(&self, _: InitializedParams) {
+        self.client.log_message(MessageType::INFO, "server initialized!").await;
+    }
+
+    async fn shutdown(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn hover(&self, params: HoverParams) -> Result<Option<Hover>> {
+        let position = params.text_document_position_params.position;
+        Ok(Some(Hover {
+            contents: HoverContents::Scalar(MarkedString::String(format!(
+                "hovering {}:{}",
+                position.line, position.character
+            ))),
+            range: None,
+        }))
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    env_logger::init();
+
+    let (service, messages) = LspService::new(|client| Backend { client });
+    let (mut client, server) = lspower::duplex(service, messages);
+    let server = tokio::spawn(server);
+
+    let response = client.request("initialize", json!({ "capabilities": {} })).await.unwrap();
+    println!("initialize: {}", response["result"]);
+    client.notify("initialized", json!({})).await.unwrap();
+
+    let params = json!({
+        "textDocument": { "uri": "file:///example.txt" },
+        "position": { "line": 1, "character": 2 },
+    });
+    let response = client.request("textDocument/hover", params).await.unwrap();
+    println!("hover: {}", response["result"]);
+
+    client.request("shutdown", None).await.unwrap();
+    client.notify("exit", None).await.unwrap();
+    while let Some(message) = client.recv().await {
+        println!("received: {}", message);
+    }
+
+    println!("server exited: {:?}", server.await.unwrap());
+}
@@ -10,7 +10,6 @@ struct Backend {
 impl LanguageServer for Backend {
     async fn initialize(&self, _: InitializeParams) -> Result<InitializeResult> {
         Ok(InitializeResult {
-            server_info: None,
             capabilities: ServerCapabilities {
                 text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::INCREMENTAL)),
                 completion_provider: Some(CompletionOptions {
@@ -31,6 +30,7 @@ impl LanguageServer for Backend {
                 }),
                 ..ServerCapabilities::default()
             },
+            ..InitializeResult::default()
         })
     }
 
@@ -104,9 +104,6 @@ impl LanguageServer for Backend {
 async fn main() {
     env_logger::init();
 
-    let stdin = tokio::io::stdin();
-    let stdout = tokio::io::stdout();
-
     let (service, messages) = LspService::new(|client| Backend { client });
-    Server::new(stdin, stdout).interleave(messages).serve(service).await;
+    Server::stdio().interleave(messages).serve(service).await;
 }
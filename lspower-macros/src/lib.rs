@@ -21,24 +21,29 @@ use syn::{
 /// Macro for generating LSP server implementation from [`lsp-types`](https://docs.rs/lsp-types).
 ///
 /// This procedural macro annotates the `lspower::LanguageServer` trait and generates a
-/// corresponding opaque `ServerRequest` struct along with a `handle_request()` function.
+/// corresponding opaque `ServerRequest` struct along with a `handle_request()` function, as well
+/// as the typed methods of `lspower::ServerProxy` sending the requests and notifications.
 #[proc_macro_attribute]
 pub fn rpc(attr: TokenStream, item: TokenStream) -> TokenStream {
     let attr_args = parse_macro_input!(attr as AttributeArgs);
 
     match attr_args.as_slice() {
         [] => {},
-        [NestedMeta::Meta(meta)] if meta.path().is_ident("name") => return item,
+        [NestedMeta::Meta(meta)] if meta.path().is_ident("name") || meta.path().is_ident("raw") => return item,
         _ => panic!("unexpected attribute arguments"),
     }
 
     let lang_server_trait = parse_macro_input!(item as ItemTrait);
     let method_calls = parse_method_calls(&lang_server_trait);
     let req_types_and_router_fn = gen_server_router(&lang_server_trait.ident, &method_calls);
+    let proxy_methods = gen_proxy_methods(&method_calls);
+    let handler_types = gen_handler_types(&method_calls);
 
     let tokens = quote! {
         #lang_server_trait
         #req_types_and_router_fn
+        #proxy_methods
+        #handler_types
     };
 
     tokens.into()
@@ -46,34 +51,66 @@ pub fn rpc(attr: TokenStream, item: TokenStream) -> TokenStream {
 
 struct MethodCall<'a> {
     rpc_name: String,
+    cfg_attrs: Vec<&'a syn::Attribute>,
+    feature: Option<String>,
     handler_name: &'a syn::Ident,
+    raw_handler_name: Option<&'a syn::Ident>,
     params: Option<&'a syn::Type>,
     result: Option<&'a syn::Type>,
 }
 
-fn parse_method_calls(lang_server_trait: &ItemTrait) -> Vec<MethodCall> {
+fn parse_method_calls(lang_server_trait: &ItemTrait) -> Vec<MethodCall<'_>> {
     let mut calls = Vec::new();
+    let mut raw_handlers = Vec::new();
 
     for item in &lang_server_trait.items {
         let method = match item {
-            TraitItem::Method(m) if m.sig.ident == "request_else" => continue,
+            TraitItem::Method(m) if m.sig.ident == "request_else" || m.sig.ident == "notification_else" => continue,
             TraitItem::Method(m) => m,
             _ => continue,
         };
 
-        let rpc_name = method
+        // Handlers taking the raw parameters of a method, marked with `#[rpc(raw = "foo")]`, are
+        // attached to the handler of that method rather than being methods of their own.
+        let raw_name = method
             .attrs
             .iter()
             .filter_map(|attr| attr.parse_args::<Meta>().ok())
-            .filter(|meta| meta.path().is_ident("name"))
-            .find_map(|meta| match meta {
+            .find(|meta| meta.path().is_ident("raw"));
+        if let Some(meta) = raw_name {
+            match meta {
                 Meta::NameValue(MetaNameValue { lit: Lit::Str(lit), .. }) => {
-                    Some(lit.value().trim_matches('"').to_owned())
+                    raw_handlers.push((lit.value(), &method.sig.ident));
                 },
+                _ => panic!("expected string literal for `#[rpc(raw = ???)]` attribute"),
+            }
+            continue;
+        }
+
+        let rpc_name = method
+            .attrs
+            .iter()
+            .filter_map(|attr| attr.parse_args::<Meta>().ok())
+            .filter(|meta| meta.path().is_ident("name"))
+            .map(|meta| match meta {
+                Meta::NameValue(MetaNameValue { lit: Lit::Str(lit), .. }) => lit.value().trim_matches('"').to_owned(),
                 _ => panic!("expected string literal for `#[rpc(name = ???)]` attribute"),
             })
+            .next()
             .expect("expected `#[rpc(name = \"foo\")]` attribute");
 
+        let cfg_attrs: Vec<_> = method.attrs.iter().filter(|attr| attr.path.is_ident("cfg")).collect();
+
+        let feature = cfg_attrs
+            .iter()
+            .filter_map(|attr| attr.parse_args::<Meta>().ok())
+            .filter(|meta| meta.path().is_ident("feature"))
+            .map(|meta| match meta {
+                Meta::NameValue(MetaNameValue { lit: Lit::Str(lit), .. }) => lit.value(),
+                _ => panic!("expected string literal for `#[cfg(feature = ???)]` attribute"),
+            })
+            .next();
+
         let params = method.sig.inputs.iter().nth(1).and_then(|arg| match arg {
             FnArg::Typed(pat) => Some(&*pat.ty),
             _ => None,
@@ -86,15 +123,97 @@ fn parse_method_calls(lang_server_trait: &ItemTrait) -> Vec<MethodCall> {
 
         calls.push(MethodCall {
             rpc_name,
+            cfg_attrs,
+            feature,
             handler_name: &method.sig.ident,
+            raw_handler_name: None,
             params,
             result,
         });
     }
 
+    for (rpc_name, handler) in raw_handlers {
+        let call = calls
+            .iter_mut()
+            .find(|call| call.rpc_name == rpc_name)
+            .unwrap_or_else(|| panic!("no method found for `#[rpc(raw = {:?})]` attribute", rpc_name));
+        assert!(call.result.is_none(), "`#[rpc(raw = ???)]` is only supported for notifications");
+        call.raw_handler_name = Some(handler);
+    }
+
     calls
 }
 
+/// Generates a method of `ServerProxy` for every trait method, sending the corresponding request or
+/// notification with the same parameter and result types.
+fn gen_proxy_methods(methods: &[MethodCall]) -> proc_macro2::TokenStream {
+    let proxy_methods: proc_macro2::TokenStream = methods
+        .iter()
+        .map(|method| {
+            let rpc_name = &method.rpc_name;
+            let handler = &method.handler_name;
+            let cfg_attrs = &method.cfg_attrs;
+            let kind = if method.result.is_some() { "request" } else { "notification" };
+            let doc = format!("Sends a `{}` {} to the server.", rpc_name, kind);
+            let result = match method.result {
+                Some(result) => quote!(#result),
+                None => quote!(crate::jsonrpc::Result<()>),
+            };
+            let (args, body) = match (method.result.is_some(), method.params) {
+                (true, Some(p)) => (quote!(params: #p), quote!(self.request(#rpc_name, params).await)),
+                (true, None) => (quote!(), quote!(self.request_without_params(#rpc_name).await)),
+                (false, Some(p)) => (quote!(params: #p), quote!(self.notify(#rpc_name, params).await)),
+                (false, None) => (quote!(), quote!(self.notify_without_params(#rpc_name).await)),
+            };
+            quote! {
+                #(#cfg_attrs)*
+                #[doc = #doc]
+                pub async fn #handler(&self, #args) -> #result {
+                    #body
+                }
+            }
+        })
+        .collect();
+
+    quote! {
+        impl<S> crate::ServerProxy<S>
+        where
+            S: tower_service::Service<crate::jsonrpc::Incoming, Response = Option<crate::jsonrpc::Outgoing>>,
+            S::Error: std::fmt::Display,
+        {
+            #proxy_methods
+        }
+    }
+}
+
+/// Generates a function returning the types of the parameters and of the result of the handler of
+/// every request taking parameters, for checking the overrides registered for it.
+fn gen_handler_types(methods: &[MethodCall]) -> proc_macro2::TokenStream {
+    let match_arms: proc_macro2::TokenStream = methods
+        .iter()
+        .filter_map(|method| {
+            let rpc_name = &method.rpc_name;
+            let cfg_attrs = &method.cfg_attrs;
+            let (params, result) = (method.params?, method.result?);
+            Some(quote! {
+                #(#cfg_attrs)*
+                #rpc_name => Some((std::any::TypeId::of::<#params>(), std::any::TypeId::of::<#result>())),
+            })
+        })
+        .collect();
+
+    quote! {
+        /// Returns the types of the parameters and of the result of the handler of the given request,
+        /// if it is a request taking parameters.
+        pub(crate) fn request_handler_types(method: &str) -> Option<(std::any::TypeId, std::any::TypeId)> {
+            match method {
+                #match_arms
+                _ => None,
+            }
+        }
+    }
+}
+
 fn gen_server_router(trait_name: &syn::Ident, methods: &[MethodCall]) -> proc_macro2::TokenStream {
     let variant_names: Vec<syn::Ident> = methods
         .iter()
@@ -113,7 +232,9 @@ fn gen_server_router(trait_name: &syn::Ident, methods: &[MethodCall]) -> proc_ma
                 (false, None) => quote!(#var_name,),
             };
 
+            let cfg_attrs = &method.cfg_attrs;
             quote! {
+                #(#cfg_attrs)*
                 #[serde(rename = #rpc_name)]
                 #variant
             }
@@ -124,9 +245,81 @@ fn gen_server_router(trait_name: &syn::Ident, methods: &[MethodCall]) -> proc_ma
         .iter()
         .zip(variant_names.iter())
         .filter_map(|(method, var_name)| {
+            let cfg_attrs = &method.cfg_attrs;
             method
                 .result
-                .map(|_| quote!(ServerMethod::#var_name { ref id, .. } => Some(id),))
+                .map(|_| quote!(#(#cfg_attrs)* ServerMethod::#var_name { ref id, .. } => Some(id),))
+        })
+        .collect();
+
+    let params_match_arms: proc_macro2::TokenStream = methods
+        .iter()
+        .zip(variant_names.iter())
+        .filter(|(method, _)| method.params.is_some())
+        .map(|(method, var_name)| {
+            let cfg_attrs = &method.cfg_attrs;
+            quote! {
+                #(#cfg_attrs)*
+                ServerMethod::#var_name { ref params, .. } => params.to_value(),
+            }
+        })
+        .collect();
+
+    let params_error_match_arms: proc_macro2::TokenStream = methods
+        .iter()
+        .zip(variant_names.iter())
+        .filter(|(method, _)| method.params.is_some())
+        .map(|(method, var_name)| {
+            let cfg_attrs = &method.cfg_attrs;
+            quote! {
+                #(#cfg_attrs)*
+                ServerMethod::#var_name { params: Params::Invalid(ref error), .. } => Some(error),
+            }
+        })
+        .collect();
+
+    let raw_match_arms: proc_macro2::TokenStream = methods
+        .iter()
+        .zip(variant_names.iter())
+        .filter(|(method, _)| method.raw_handler_name.is_some())
+        .map(|(method, var_name)| {
+            let rpc_name = &method.rpc_name;
+            let cfg_attrs = &method.cfg_attrs;
+            quote! {
+                #(#cfg_attrs)*
+                #rpc_name => ServerMethod::#var_name { params: Params::Raw(RawParams(serde_json::value::to_raw_value(params).ok()?)) },
+            }
+        })
+        .collect();
+
+    let method_match_arms: proc_macro2::TokenStream = methods
+        .iter()
+        .zip(variant_names.iter())
+        .map(|(method, var_name)| {
+            let rpc_name = &method.rpc_name;
+            let cfg_attrs = &method.cfg_attrs;
+            quote!(#(#cfg_attrs)* ServerMethod::#var_name { .. } => #rpc_name,)
+        })
+        .collect();
+
+    let method_infos: proc_macro2::TokenStream = methods
+        .iter()
+        .map(|method| {
+            let rpc_name = &method.rpc_name;
+            let cfg_attrs = &method.cfg_attrs;
+            let kind = if method.result.is_some() {
+                quote!(MethodKind::Request)
+            } else {
+                quote!(MethodKind::Notification)
+            };
+            let feature = match &method.feature {
+                Some(feature) => {
+                    let feature: syn::Ident = syn::parse_str(&feature.to_upper_camel_case()).unwrap();
+                    quote!(Some(crate::spec::SpecFeature::#feature))
+                },
+                None => quote!(None),
+            };
+            quote!(#(#cfg_attrs)* MethodInfo::new(#rpc_name, #kind, #feature),)
         })
         .collect();
 
@@ -136,15 +329,54 @@ fn gen_server_router(trait_name: &syn::Ident, methods: &[MethodCall]) -> proc_ma
         .map(|(method, var_name)| {
             let rpc_name = method.rpc_name.as_str();
             let handler = &method.handler_name;
-            match (method.result.is_some(), method.params.is_some()) {
+            let cfg_attrs = &method.cfg_attrs;
+            let cancels_scopes = rpc_name == "textDocument/didChange" || rpc_name == "textDocument/didClose";
+            let arms = match (method.result.is_some(), method.params.is_some()) {
+                (false, true) if method.raw_handler_name.is_some() => {
+                    let raw_handler = method.raw_handler_name.unwrap();
+                    let (cancel_raw, cancel_valid) = if cancels_scopes {
+                        let cancel_raw = quote! {
+                            if let Some(uri) = raw.document_uri() {
+                                client.document_scopes().cancel(&uri);
+                            }
+                        };
+                        (cancel_raw, quote!(client.document_scopes().cancel(&p.text_document.uri);))
+                    } else {
+                        (quote!(), quote!())
+                    };
+                    quote! {
+                        (ServerMethod::#var_name { params: Raw(raw) }, StateKind::Initialized) => {
+                            #cancel_raw
+                            Box::pin(async move { server.#raw_handler(&raw.0).await; Ok(None) })
+                        }
+                        (ServerMethod::#var_name { params: Valid(p) }, StateKind::Initialized) => {
+                            #cancel_valid
+                            match serde_json::value::to_raw_value(&p) {
+                                Ok(raw) => Box::pin(async move { server.#raw_handler(&raw).await; Ok(None) }),
+                                Err(e) => {
+                                    error!("failed to serialize parameters for {:?} notification: {}", #rpc_name, e);
+                                    future::ok(None).boxed()
+                                },
+                            }
+                        }
+                        (ServerMethod::#var_name { .. }, StateKind::Initialized) => {
+                            warn!("invalid parameters for {:?} notification", #rpc_name);
+                            future::ok(None).boxed()
+                        }
+                    }
+                },
                 (true, true) if rpc_name == "initialize" => quote! {
                     (ServerMethod::#var_name { params: Valid(p), id }, StateKind::Uninitialized) => {
                         state.set(StateKind::Initializing);
+                        client.set_client_capabilities(p.capabilities.clone(), capabilities);
+                        client.set_trace(p.trace.unwrap_or_default());
+                        client.set_negotiated_protocol(crate::NegotiatedProtocol::new(&p));
                         let state = state.clone();
+                        let options = options.clone();
                         Box::pin(async move {
-                            let res = match server.#handler(p).await {
+                            let result = server.#handler(p).await;
+                            let res = match result.and_then(|result| options.complete_initialize_result(result)) {
                                 Ok(result) => {
-                                    let result = serde_json::to_value(result).unwrap();
                                     info!("language server initialized");
                                     state.set(StateKind::Initialized);
                                     Response::ok(id, result)
@@ -165,6 +397,7 @@ fn gen_server_router(trait_name: &syn::Ident, methods: &[MethodCall]) -> proc_ma
                     }
                     (ServerMethod::#var_name { id, .. }, StateKind::Initializing) => {
                         warn!("received duplicate `initialize` request, ignoring");
+                        options.anomalies.report(AnomalyEvent::DuplicateInitialize { id: id.clone(), initialized: false });
                         let res = Response::error(Some(id), Error::invalid_request());
                         future::ok(Some(Outgoing::Response(res))).boxed()
                     }
@@ -173,18 +406,59 @@ fn gen_server_router(trait_name: &syn::Ident, methods: &[MethodCall]) -> proc_ma
                     (ServerMethod::#var_name { id }, StateKind::Initialized) => {
                         info!("shutdown request received, shutting down");
                         state.set(StateKind::ShutDown);
+                        let options = options.clone();
                         pending
-                            .execute(id, async move { server.#handler().await })
+                            .execute(id, #rpc_name, async move {
+                                let result = server.#handler().await;
+                                // Events collected from now on are sent by the final flush below.
+                                client.set_telemetry_timed(false);
+                                client.background_tasks().join(options.shutdown_timeout).await;
+                                options.hooks.run(Transition::Shutdown, &client).await;
+                                client.flush_telemetry().await;
+                                client.fail_pending_requests(crate::jsonrpc::shut_down_error());
+                                result
+                            })
                             .map(|v| Ok(Some(Outgoing::Response(v))))
                             .boxed()
                     }
                 },
+                (true, true) if rpc_name == "workspace/executeCommand" => quote! {
+                    (ServerMethod::#var_name { params: Valid(p), id }, StateKind::Initialized) => {
+                        match options.commands.clone() {
+                            Some(commands) => pending
+                                .execute(id, #rpc_name, async move { commands.execute(p).await })
+                                .map(|v| Ok(Some(Outgoing::Response(v))))
+                                .boxed(),
+                            None => match options.overrides.get(#rpc_name) {
+                                Some(method) => pending
+                                    .execute(id, #rpc_name, method.call(p, move |p| async move { server.#handler(p).await }))
+                                    .map(|v| Ok(Some(Outgoing::Response(v))))
+                                    .boxed(),
+                                None => pending
+                                    .execute(id, #rpc_name, async move { server.#handler(p).await })
+                                    .map(|v| Ok(Some(Outgoing::Response(v))))
+                                    .boxed(),
+                            },
+                        }
+                    }
+                    (ServerMethod::#var_name { params: Invalid(e), id }, StateKind::Initialized) => {
+                        error!("invalid parameters for {:?} request", #rpc_name);
+                        let res = Response::error(Some(id), Error::invalid_params(e));
+                        future::ok(Some(Outgoing::Response(res))).boxed()
+                    }
+                },
                 (true, true) => quote! {
                     (ServerMethod::#var_name { params: Valid(p), id }, StateKind::Initialized) => {
-                        pending
-                            .execute(id, async move { server.#handler(p).await })
-                            .map(|v| Ok(Some(Outgoing::Response(v))))
-                            .boxed()
+                        match options.overrides.get(#rpc_name) {
+                            Some(method) => pending
+                                .execute(id, #rpc_name, method.call(p, move |p| async move { server.#handler(p).await }))
+                                .map(|v| Ok(Some(Outgoing::Response(v))))
+                                .boxed(),
+                            None => pending
+                                .execute(id, #rpc_name, async move { server.#handler(p).await })
+                                .map(|v| Ok(Some(Outgoing::Response(v))))
+                                .boxed(),
+                        }
                     }
                     (ServerMethod::#var_name { params: Invalid(e), id }, StateKind::Initialized) => {
                         error!("invalid parameters for {:?} request", #rpc_name);
@@ -195,11 +469,52 @@ fn gen_server_router(trait_name: &syn::Ident, methods: &[MethodCall]) -> proc_ma
                 (true, false) => quote! {
                     (ServerMethod::#var_name { id }, StateKind::Initialized) => {
                         pending
-                            .execute(id, async move { server.#handler().await })
+                            .execute(id, #rpc_name, async move { server.#handler().await })
                             .map(|v| Ok(Some(Outgoing::Response(v))))
                             .boxed()
                     }
                 },
+                (false, true) if rpc_name == "initialized" => quote! {
+                    (ServerMethod::#var_name { params: Valid(p) }, StateKind::Initialized) => {
+                        let options = options.clone();
+                        Box::pin(async move {
+                            server.#handler(p).await;
+                            options.hooks.run(Transition::Initialized, &client).await;
+                            Ok(None)
+                        })
+                    }
+                    (ServerMethod::#var_name { .. }, StateKind::Initialized) => {
+                        warn!("invalid parameters for {:?} notification", #rpc_name);
+                        future::ok(None).boxed()
+                    }
+                },
+                (false, true) if rpc_name == "workspace/didChangeConfiguration" => quote! {
+                    (ServerMethod::#var_name { params: Valid(p) }, StateKind::Initialized) => {
+                        client.invalidate_configuration();
+                        let settings = options.settings.changed(&p.settings, &client);
+                        Box::pin(async move {
+                            server.#handler(p).await;
+                            // Fetching the settings may take a `workspace/configuration` round-trip
+                            // to the client, which should not hold up the following messages.
+                            client.spawn_background(settings);
+                            Ok(None)
+                        })
+                    }
+                    (ServerMethod::#var_name { .. }, StateKind::Initialized) => {
+                        warn!("invalid parameters for {:?} notification", #rpc_name);
+                        future::ok(None).boxed()
+                    }
+                },
+                (false, true) if cancels_scopes => quote! {
+                    (ServerMethod::#var_name { params: Valid(p) }, StateKind::Initialized) => {
+                        client.document_scopes().cancel(&p.text_document.uri);
+                        Box::pin(async move { server.#handler(p).await; Ok(None) })
+                    }
+                    (ServerMethod::#var_name { .. }, StateKind::Initialized) => {
+                        warn!("invalid parameters for {:?} notification", #rpc_name);
+                        future::ok(None).boxed()
+                    }
+                },
                 (false, true) => quote! {
                     (ServerMethod::#var_name { params: Valid(p) }, StateKind::Initialized) => {
                         Box::pin(async move { server.#handler(p).await; Ok(None) })
@@ -214,7 +529,16 @@ fn gen_server_router(trait_name: &syn::Ident, methods: &[MethodCall]) -> proc_ma
                         Box::pin(async move { server.#handler().await; Ok(None) })
                     }
                 },
-            }
+            };
+
+            // A single trait method may expand into several match arms, so its `cfg` attributes are
+            // repeated on each of them.
+            syn::parse2::<syn::ExprMatch>(quote!(match () { #arms }))
+                .expect("generated invalid match arms")
+                .arms
+                .into_iter()
+                .map(|arm| quote!(#(#cfg_attrs)* #arm))
+                .collect::<proc_macro2::TokenStream>()
         })
         .collect();
 
@@ -224,8 +548,9 @@ fn gen_server_router(trait_name: &syn::Ident, methods: &[MethodCall]) -> proc_ma
             use crate::{
                 client::Client,
                 jsonrpc::{not_initialized_error, Error, ErrorCode, Id, Outgoing, Response, ServerRequests, Version},
+                reflect::{MethodInfo, MethodKind},
                 server::{State, StateKind},
-                service::ExitedError,
+                service::{AnomalyEvent, ExitedError, Interception, ServiceOptions, Transition},
             };
             use futures::{future, FutureExt};
             use log::{error, info, warn};
@@ -235,13 +560,125 @@ fn gen_server_router(trait_name: &syn::Ident, methods: &[MethodCall]) -> proc_ma
             };
             use std::{future::Future, pin::Pin, sync::Arc};
 
-            /// A client-to-server LSP request.
+            /// All LSP methods known to the dispatcher.
+            pub(crate) const METHODS: &[MethodInfo] = &[
+                #method_infos
+                MethodInfo::new("$/cancelRequest", MethodKind::Notification, None),
+                MethodInfo::new("$/setTrace", MethodKind::Notification, None),
+                MethodInfo::new("exit", MethodKind::Notification, None),
+            ];
+
+            /// A client-to-server LSP request or notification.
+            ///
+            /// Requests are usually deserialized as part of an [`Incoming`] message, but can also be
+            /// constructed with [`ServerRequest::new`], e.g. to drive the dispatcher of an
+            /// [`LspService`] directly in tests or fuzzers.
+            ///
+            /// [`Incoming`]: crate::jsonrpc::Incoming
+            /// [`LspService`]: crate::LspService
             #[derive(Clone, Debug, PartialEq, serde::Deserialize)]
             #[cfg_attr(test, derive(serde::Serialize))]
             pub struct ServerRequest {
                 jsonrpc: Version,
                 #[serde(flatten)]
                 kind: RequestKind,
+                // This must follow `kind`, since it claims the `id` and `params` fields, which the
+                // untagged `kind` only borrows.
+                #[serde(flatten)]
+                #[cfg_attr(test, serde(skip_serializing))]
+                envelope: Envelope,
+            }
+
+            /// The parts of a message which the dispatcher ignores, kept for validating it against
+            /// JSON-RPC 2.0.
+            #[derive(Clone, Debug, Default, PartialEq, serde::Deserialize)]
+            struct Envelope {
+                #[serde(default)]
+                id: Option<serde_json::Value>,
+                #[serde(default, deserialize_with = "envelope_params")]
+                params: EnvelopeParams,
+            }
+
+            /// The parts of the parameters which the dispatcher ignores.
+            #[derive(Clone, Debug, Default, PartialEq)]
+            struct EnvelopeParams {
+                /// Whether the parameters are neither an array nor an object.
+                unstructured: bool,
+                /// The `capabilities` of an `initialize` request as sent by the client, including the
+                /// capabilities the types of `lsp-types` do not retain.
+                capabilities: Option<serde_json::Value>,
+            }
+
+            impl EnvelopeParams {
+                fn unstructured() -> Self {
+                    EnvelopeParams {
+                        unstructured: true,
+                        capabilities: None,
+                    }
+                }
+            }
+
+            /// Deserializes the parts of the parameters which the dispatcher ignores.
+            fn envelope_params<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<EnvelopeParams, D::Error> {
+                struct Visitor;
+
+                impl<'de> serde::de::Visitor<'de> for Visitor {
+                    type Value = EnvelopeParams;
+
+                    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                        f.write_str("any value")
+                    }
+
+                    fn visit_map<A: serde::de::MapAccess<'de>>(self, mut map: A) -> Result<EnvelopeParams, A::Error> {
+                        let mut params = EnvelopeParams::default();
+                        while let Some(key) = map.next_key::<String>()? {
+                            if key == "capabilities" {
+                                params.capabilities = Some(map.next_value()?);
+                            } else {
+                                map.next_value::<serde::de::IgnoredAny>()?;
+                            }
+                        }
+                        Ok(params)
+                    }
+
+                    fn visit_seq<A: serde::de::SeqAccess<'de>>(self, mut seq: A) -> Result<EnvelopeParams, A::Error> {
+                        while seq.next_element::<serde::de::IgnoredAny>()?.is_some() {}
+                        Ok(EnvelopeParams::default())
+                    }
+
+                    fn visit_bool<E>(self, _: bool) -> Result<EnvelopeParams, E> {
+                        Ok(EnvelopeParams::unstructured())
+                    }
+
+                    fn visit_i64<E>(self, _: i64) -> Result<EnvelopeParams, E> {
+                        Ok(EnvelopeParams::unstructured())
+                    }
+
+                    fn visit_u64<E>(self, _: u64) -> Result<EnvelopeParams, E> {
+                        Ok(EnvelopeParams::unstructured())
+                    }
+
+                    fn visit_f64<E>(self, _: f64) -> Result<EnvelopeParams, E> {
+                        Ok(EnvelopeParams::unstructured())
+                    }
+
+                    fn visit_str<E>(self, _: &str) -> Result<EnvelopeParams, E> {
+                        Ok(EnvelopeParams::unstructured())
+                    }
+
+                    fn visit_unit<E>(self) -> Result<EnvelopeParams, E> {
+                        Ok(EnvelopeParams::unstructured())
+                    }
+                }
+
+                deserializer.deserialize_any(Visitor)
+            }
+
+            /// The parameters of the `$/setTrace` notification, which `lsp-types` does not define.
+            #[derive(Clone, Debug, PartialEq, serde::Deserialize)]
+            #[cfg_attr(test, derive(serde::Serialize))]
+            struct SetTraceParams {
+                value: TraceOption,
             }
 
             #[derive(Clone, Debug, PartialEq, serde::Deserialize)]
@@ -258,26 +695,254 @@ fn gen_server_router(trait_name: &syn::Ident, methods: &[MethodCall]) -> proc_ma
             enum ServerMethod {
                 #variants
                 #[serde(rename = "$/cancelRequest")]
-                CancelRequest { id: Id },
+                CancelRequest { params: CancelParams },
+                #[serde(rename = "$/setTrace")]
+                SetTrace { params: SetTraceParams },
                 #[serde(rename = "exit")]
                 Exit,
             }
 
+            impl ServerRequest {
+                /// Creates a request to the given method with the given parameters, or a notification
+                /// if `id` is `None`.
+                ///
+                /// Fails with an "invalid request" error if the method is empty, or if the method is
+                /// known to the dispatcher as a request and `id` is `None`, or as a notification and
+                /// `id` is set. Fails with an "invalid params" error if the parameters of a known method
+                /// fail to deserialize. Methods unknown to the dispatcher accept any parameters.
+                ///
+                /// # Example
+                ///
+                /// ```rust
+                /// # use lspower::jsonrpc::{Id, ServerRequest};
+                /// # use serde_json::json;
+                /// let params = json!({ "textDocument": { "uri": "file:///main.rs" }, "position": { "line": 0, "character": 0 } });
+                /// let request = ServerRequest::new("textDocument/hover", Some(params), Some(Id::Number(1))).unwrap();
+                /// assert_eq!(request.method(), "textDocument/hover");
+                ///
+                /// assert!(ServerRequest::new("textDocument/hover", None, Some(Id::Number(2))).is_err());
+                /// assert!(ServerRequest::new("shutdown", None, None).is_err());
+                /// ```
+                pub fn new(
+                    method: impl Into<String>,
+                    params: Option<serde_json::Value>,
+                    id: Option<Id>,
+                ) -> crate::jsonrpc::Result<Self> {
+                    let method = method.into();
+                    if method.is_empty() {
+                        return Err(Error::invalid_request().with_message("empty method name"));
+                    }
+
+                    let kind = crate::reflect::method(&method).map(MethodInfo::kind);
+                    match (kind, &id) {
+                        (Some(MethodKind::Request), None) => {
+                            let message = format!("{:?} is a request, but no ID was given", method);
+                            return Err(Error::invalid_request().with_message(message));
+                        }
+                        (Some(MethodKind::Notification), Some(_)) => {
+                            let message = format!("{:?} is a notification, but an ID was given", method);
+                            return Err(Error::invalid_request().with_message(message));
+                        }
+                        _ => {}
+                    }
+
+                    let mut message = serde_json::json!({ "jsonrpc": "2.0", "method": method });
+                    if let Some(params) = params {
+                        message["params"] = params;
+                    }
+                    if let Some(id) = id {
+                        message["id"] = serde_json::json!(id);
+                    }
+                    let request: ServerRequest = serde_json::from_value(message)
+                        .map_err(|error| Error::invalid_request().with_message(error.to_string()))?;
+
+                    match &request.kind {
+                        RequestKind::Known(known) => match known.params_error() {
+                            Some(error) => Err(Error::invalid_params(error.clone())),
+                            None => Ok(request),
+                        },
+                        RequestKind::Other { .. } if kind.is_some() => {
+                            Err(Error::invalid_params(format!("invalid parameters for {:?}", method)))
+                        }
+                        RequestKind::Other { .. } => Ok(request),
+                    }
+                }
+
+                /// Returns the name of the method to be invoked.
+                pub fn method(&self) -> &str {
+                    match &self.kind {
+                        RequestKind::Known(method) => method.method(),
+                        RequestKind::Other { method, .. } => method,
+                    }
+                }
+
+                /// Returns the request ID, or `None` if this message is a notification.
+                pub fn id(&self) -> Option<&Id> {
+                    match &self.kind {
+                        RequestKind::Known(method) => method.id(),
+                        RequestKind::Other { id, .. } => id.as_ref(),
+                    }
+                }
+
+                /// Returns the parameters of the message as JSON, or `None` if it has none or if they are
+                /// invalid for its method.
+                pub fn params_value(&self) -> Option<serde_json::Value> {
+                    self.params().or_else(|| self.other_params().cloned())
+                }
+
+                /// Replaces the parameters of the message, e.g. to rewrite the URIs it refers to.
+                ///
+                /// Fails like [`ServerRequest::new`] if the parameters are invalid for the method, in
+                /// which case the message is left unchanged.
+                pub fn set_params(&mut self, params: serde_json::Value) -> crate::jsonrpc::Result<()> {
+                    *self = ServerRequest::new(self.method(), Some(params), self.id().cloned())?;
+                    Ok(())
+                }
+
+                /// Returns the parameters of a message with a dedicated handler, if they are valid.
+                pub(crate) fn params(&self) -> Option<serde_json::Value> {
+                    match &self.kind {
+                        RequestKind::Known(method) => method.params(),
+                        RequestKind::Other { .. } => None,
+                    }
+                }
+
+                /// Creates a notification for a method with a raw handler, keeping its parameters as raw
+                /// JSON. Returns `None` for all other methods.
+                pub(crate) fn from_raw(method: &str, params: &serde_json::Value) -> Option<Self> {
+                    let method = match method {
+                        #raw_match_arms
+                        _ => return None,
+                    };
+                    Some(ServerRequest {
+                        jsonrpc: Version,
+                        kind: RequestKind::Known(method),
+                        envelope: Envelope::default(),
+                    })
+                }
+
+                /// Returns how the message violates JSON-RPC 2.0, if it does, although the dispatcher
+                /// accepts it.
+                pub(crate) fn jsonrpc_violation(&self) -> Option<&'static str> {
+                    if self.envelope.params.unstructured {
+                        Some("params must be an array or an object")
+                    } else if self.id().is_none() && self.envelope.id.is_some() {
+                        Some("notifications must not have an id")
+                    } else {
+                        None
+                    }
+                }
+
+                /// Returns the ID of the message, including an ID the dispatcher ignores because the
+                /// method is a notification.
+                pub(crate) fn raw_id(&self) -> Option<Id> {
+                    let id = self.envelope.id.as_ref();
+                    self.id().cloned().or_else(|| id.and_then(|id| serde_json::from_value(id.clone()).ok()))
+                }
+
+                /// Returns the ID of the request to cancel if this is a `$/cancelRequest` notification.
+                pub(crate) fn cancelled_id(&self) -> Option<&Id> {
+                    match &self.kind {
+                        RequestKind::Known(ServerMethod::CancelRequest { params }) => Some(&params.id),
+                        _ => None,
+                    }
+                }
+
+                /// Returns the raw parameters of a method without a dedicated handler.
+                pub(crate) fn other_params(&self) -> Option<&serde_json::Value> {
+                    match &self.kind {
+                        RequestKind::Known(_) => None,
+                        RequestKind::Other { params, .. } => params.as_ref(),
+                    }
+                }
+            }
+
             impl ServerMethod {
+                fn method(&self) -> &'static str {
+                    match *self {
+                        #method_match_arms
+                        ServerMethod::CancelRequest { .. } => "$/cancelRequest",
+                        ServerMethod::SetTrace { .. } => "$/setTrace",
+                        ServerMethod::Exit => "exit",
+                    }
+                }
+
                 fn id(&self) -> Option<&Id> {
                     match *self {
                         #id_match_arms
                         _ => None,
                     }
                 }
+
+                fn params(&self) -> Option<serde_json::Value> {
+                    match *self {
+                        #params_match_arms
+                        _ => None,
+                    }
+                }
+
+                fn params_error(&self) -> Option<&String> {
+                    match *self {
+                        #params_error_match_arms
+                        _ => None,
+                    }
+                }
             }
 
-            #[derive(Clone, Debug, PartialEq)]
+            #[derive(Clone, Debug, PartialEq, serde::Deserialize)]
+            #[cfg_attr(test, derive(serde::Serialize))]
+            struct CancelParams {
+                id: Id,
+            }
+
+            #[derive(Clone, Debug)]
             #[cfg_attr(test, derive(serde::Serialize))]
             enum Params<T> {
                 Valid(T),
                 #[cfg_attr(test, serde(skip_serializing))]
                 Invalid(String),
+                /// Parameters of a method with a raw handler, which are only parsed by that handler.
+                #[cfg_attr(test, serde(skip_serializing))]
+                Raw(RawParams),
+            }
+
+            #[derive(Clone, Debug)]
+            struct RawParams(Box<serde_json::value::RawValue>);
+
+            impl RawParams {
+                /// Parses only the URI of the text document the parameters refer to.
+                fn document_uri(&self) -> Option<Url> {
+                    #[derive(serde::Deserialize)]
+                    #[serde(rename_all = "camelCase")]
+                    struct DocumentParams {
+                        text_document: TextDocumentIdentifier,
+                    }
+
+                    serde_json::from_str::<DocumentParams>(self.0.get()).ok().map(|p| p.text_document.uri)
+                }
+            }
+
+            // Valid parameters are compared by their JSON representation, since not every parameter
+            // type in `lsp-types` implements `PartialEq`. This also makes raw parameters equal to the
+            // same parameters deserialized.
+            impl<T: serde::Serialize> PartialEq for Params<T> {
+                fn eq(&self, other: &Self) -> bool {
+                    match (self, other) {
+                        (Params::Invalid(a), Params::Invalid(b)) => a == b,
+                        (Params::Invalid(_), _) | (_, Params::Invalid(_)) => false,
+                        _ => self.to_value().is_some() && self.to_value() == other.to_value(),
+                    }
+                }
+            }
+
+            impl<T: serde::Serialize> Params<T> {
+                fn to_value(&self) -> Option<serde_json::Value> {
+                    match self {
+                        Params::Valid(p) => serde_json::to_value(p).ok(),
+                        Params::Invalid(_) => None,
+                        Params::Raw(p) => serde_json::from_str(p.0.get()).ok(),
+                    }
+                }
             }
 
             impl<'de, T: serde::Deserialize<'de>> serde::Deserialize<'de> for Params<T> {
@@ -297,38 +962,102 @@ fn gen_server_router(trait_name: &syn::Ident, methods: &[MethodCall]) -> proc_ma
                 server: T,
                 state: &Arc<State>,
                 pending: &ServerRequests,
+                options: &Arc<ServiceOptions>,
+                request: Box<ServerRequest>,
+                client: Client,
+            ) -> Pin<Box<dyn Future<Output = Result<Option<Outgoing>, ExitedError>> + Send>> {
+                // Only the default notification methods of the trait report unhandled notifications
+                // without being passed the sink, so requests are dispatched outside of its scope.
+                if request.id().is_some() {
+                    return dispatch(server, state, pending, options, request, client);
+                }
+                let unhandled = options.unhandled.clone();
+                unhandled
+                    .scope(|| dispatch(server, state, pending, options, request, client))
+                    .boxed()
+            }
+
+            fn dispatch<T: #trait_name>(
+                server: T,
+                state: &Arc<State>,
+                pending: &ServerRequests,
+                options: &Arc<ServiceOptions>,
                 request: Box<ServerRequest>,
                 client: Client,
             ) -> Pin<Box<dyn Future<Output = Result<Option<Outgoing>, ExitedError>> + Send>> {
                 use Params::*;
 
+                if let (Some(id), StateKind::Initialized) = (request.id().cloned(), state.get()) {
+                    match options.interceptors.intercept(&request) {
+                        Interception::PassThrough => {}
+                        Interception::Respond(response) => {
+                            return pending
+                                .execute(id, request.method().to_owned(), response)
+                                .map(|v| Ok(Some(Outgoing::Response(v))))
+                                .boxed();
+                        }
+                        Interception::Reject(error) => {
+                            let res = Response::error(Some(id), error);
+                            return future::ok(Some(Outgoing::Response(res))).boxed();
+                        }
+                    }
+                }
+
                 let method = match request.kind {
                     RequestKind::Known(method) => method,
+                    RequestKind::Other { id: Some(id), method, .. } if options.dollar_methods.rejects(&method) => {
+                        let res = Response::error(Some(id), Error::method_not_found());
+                        return future::ok(Some(Outgoing::Response(res))).boxed();
+                    }
                     RequestKind::Other { id: Some(id), method, params } => {
                        return pending
-                            .execute(id, async move { server.request_else(&method, params).await })
+                            .execute(id, method.clone(), async move { server.request_else(&method, params).await })
                             .map(|v| Ok(Some(Outgoing::Response(v))))
                             .boxed();
                     }
-                    RequestKind::Other { id: None, method, .. } if !method.starts_with("$/") => {
-                        error!("method {:?} not found", method);
+                    RequestKind::Other { id: None, method, params } if options.dollar_methods.ignores(&method) => {
+                        options.unhandled.not_found(&method, params);
                         return future::ok(None).boxed();
                     }
-                    RequestKind::Other { id: None, .. } => return future::ok(None).boxed(),
+                    RequestKind::Other { id: None, method, params } => {
+                        return Box::pin(async move {
+                            server.notification_else(&method, params).await;
+                            Ok(None)
+                        });
+                    }
                 };
 
+                let capabilities = request.envelope.params.capabilities;
                 match (method, state.get()) {
                     #route_match_arms
-                    (ServerMethod::CancelRequest { id }, StateKind::Initialized) => {
-                        pending.cancel(&id);
+                    (ServerMethod::CancelRequest { params }, StateKind::Initialized) => {
+                        let unknown = pending.cancellations().unknown();
+                        pending.cancel(&params.id);
+                        if pending.cancellations().unknown() > unknown {
+                            options.anomalies.unknown_cancellation(client.clock().now());
+                        }
                         future::ok(None).boxed()
                     }
-                    (ServerMethod::Exit, _) => {
+                    (ServerMethod::SetTrace { params }, StateKind::Initialized) => {
+                        client.set_trace(params.value);
+                        future::ok(None).boxed()
+                    }
+                    (ServerMethod::Exit, previous) => {
                         info!("exit notification received, stopping");
+                        if previous != StateKind::ShutDown {
+                            let initialized = previous == StateKind::Initialized;
+                            options.anomalies.report(AnomalyEvent::ExitWithoutShutdown { initialized });
+                        }
                         state.set(StateKind::Exited);
                         pending.cancel_all();
-                        client.close();
-                        future::ok(None).boxed()
+                        client.fail_pending_requests(crate::jsonrpc::exited_error());
+                        client.background_tasks().abort_all();
+                        let options = options.clone();
+                        Box::pin(async move {
+                            options.hooks.run(Transition::Exit, &client).await;
+                            client.close();
+                            Ok(None)
+                        })
                     }
                     (other, StateKind::Uninitialized) => Box::pin(match other.id().cloned() {
                         None => future::ok(None),
@@ -337,13 +1066,27 @@ fn gen_server_router(trait_name: &syn::Ident, methods: &[MethodCall]) -> proc_ma
                             future::ok(Some(Outgoing::Response(res)))
                         }
                     }),
-                    (other, _) => Box::pin(match other.id().cloned() {
-                        None => future::ok(None),
-                        Some(id) => {
-                            let res = Response::error(Some(id), Error::invalid_request());
-                            future::ok(Some(Outgoing::Response(res)))
+                    (other, current) => {
+                        let id = other.id().cloned();
+                        match (current, &id) {
+                            (StateKind::ShutDown, _) => {
+                                let method = other.method().to_owned();
+                                options.anomalies.report(AnomalyEvent::AfterShutdown { method, id: id.clone() });
+                            }
+                            (StateKind::Initialized, Some(id)) if other.method() == "initialize" => {
+                                let id = id.clone();
+                                options.anomalies.report(AnomalyEvent::DuplicateInitialize { id, initialized: true });
+                            }
+                            _ => {}
                         }
-                    }),
+                        Box::pin(match id {
+                            None => future::ok(None),
+                            Some(id) => {
+                                let res = Response::error(Some(id), Error::invalid_request());
+                                future::ok(Some(Outgoing::Response(res)))
+                            }
+                        })
+                    }
                 }
             }
         }
@@ -0,0 +1,437 @@
+//! Running a language server over a directory of files in one-shot batch mode.
+
+use crate::{
+    jsonrpc::{Incoming, Outgoing},
+    uri,
+    ClientEvent,
+    HeadlessClient,
+    LspService,
+    LspServiceBuilder,
+};
+use futures::{
+    channel::oneshot,
+    future,
+    stream::StreamExt,
+};
+use lsp::{Diagnostic, DiagnosticSeverity, Url};
+use serde_json::{json, Value};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::{self, Display, Formatter},
+    fs,
+    io,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tower_service::Service;
+
+/// Drives a language server backend over a directory of files, turning it into a lint tool.
+///
+/// The driver initializes the server in headless mode, see [`HeadlessClient`], opens every file
+/// below the root directory with a `textDocument/didOpen` notification and collects the
+/// diagnostics published in response. Once the server stopped publishing for the configured
+/// settle time, the driver shuts the server down and returns a [`BatchReport`].
+///
+/// Hidden files and directories, i.e. those whose name starts with a `.`, are skipped. If any
+/// languages are registered with [`BatchDriver::language`], only files with one of the registered
+/// extensions are opened. Otherwise, all files are opened with their extension as language ID.
+/// Files which are not valid UTF-8 are skipped.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use lspower::{jsonrpc::Result, lsp::*, BatchDriver, Client, LanguageServer, LspService};
+/// # struct Backend;
+/// # #[lspower::async_trait]
+/// # impl LanguageServer for Backend {
+/// #     async fn initialize(&self, _: InitializeParams) -> Result<InitializeResult> {
+/// #         Ok(InitializeResult::default())
+/// #     }
+/// #     async fn shutdown(&self) -> Result<()> {
+/// #         Ok(())
+/// #     }
+/// # }
+/// # async fn run() -> std::io::Result<()> {
+/// let report = BatchDriver::new("src")
+///     .language("rs", "rust")
+///     .run(LspService::build(|_| Backend))
+///     .await?;
+/// print!("{}", report);
+/// std::process::exit(report.exit_code());
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct BatchDriver {
+    root: PathBuf,
+    languages: HashMap<String, String>,
+    initialization_options: Option<Value>,
+    headless: HeadlessClient,
+    settle_time: Duration,
+    fail_on: DiagnosticSeverity,
+}
+
+impl BatchDriver {
+    /// Creates a new `BatchDriver` for the files below the given root directory.
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        BatchDriver {
+            root: root.into(),
+            languages: HashMap::new(),
+            initialization_options: None,
+            headless: HeadlessClient::new(),
+            settle_time: Duration::from_millis(200),
+            fail_on: DiagnosticSeverity::ERROR,
+        }
+    }
+
+    /// Opens files with the given extension, e.g. `"rs"`, using the given language ID.
+    pub fn language(mut self, extension: impl Into<String>, language_id: impl Into<String>) -> Self {
+        self.languages.insert(extension.into(), language_id.into());
+        self
+    }
+
+    /// Sets the `initializationOptions` sent with the `initialize` request.
+    pub fn initialization_options(mut self, options: Value) -> Self {
+        self.initialization_options = Some(options);
+        self
+    }
+
+    /// Sets the responses to requests sent by the server, defaulting to [`HeadlessClient::new`].
+    pub fn headless(mut self, client: HeadlessClient) -> Self {
+        self.headless = client;
+        self
+    }
+
+    /// Sets how long the server may stay silent after the last file was opened before the driver
+    /// considers the analysis complete, defaulting to 200 milliseconds.
+    pub fn settle_time(mut self, duration: Duration) -> Self {
+        self.settle_time = duration;
+        self
+    }
+
+    /// Sets the minimum severity of diagnostics which make the run fail, defaulting to
+    /// [`DiagnosticSeverity::ERROR`].
+    pub fn fail_on(mut self, severity: DiagnosticSeverity) -> Self {
+        self.fail_on = severity;
+        self
+    }
+
+    /// Runs the server created by the given builder over all files below the root directory.
+    ///
+    /// Fails if the root directory cannot be read or if the server exits or fails to initialize.
+    pub async fn run<T, F>(self, builder: LspServiceBuilder<F>) -> io::Result<BatchReport>
+    where
+        F: FnOnce(crate::Client) -> T,
+        T: crate::LanguageServer,
+    {
+        let root_uri = uri::from_file_path(fs::canonicalize(&self.root)?)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "root is not a valid file path"))?;
+        let mut files = Vec::new();
+        self.collect_files(&self.root, &mut files)?;
+
+        let (mut service, messages) = builder.headless(self.headless.clone()).finish();
+
+        // The service waits for published messages to be consumed, so they need to be collected
+        // concurrently with driving the service. The activity counter tells the driver whether the
+        // server is still publishing.
+        let activity = Arc::new(AtomicUsize::new(0));
+        let (done_tx, done_rx) = oneshot::channel::<()>();
+        let collect = {
+            let activity = activity.clone();
+            messages
+                .events()
+                .take_until(done_rx)
+                .fold(BTreeMap::new(), move |mut diagnostics, event| {
+                    activity.fetch_add(1, Ordering::SeqCst);
+                    if let ClientEvent::Diagnostics(params) = event {
+                        diagnostics.insert(params.uri, params.diagnostics);
+                    }
+                    future::ready(diagnostics)
+                })
+        };
+
+        let drive = async {
+            let result = self.drive(&mut service, root_uri, &files, &activity).await;
+            let _ = done_tx.send(());
+            result
+        };
+
+        let (files, diagnostics) = future::join(drive, collect).await;
+
+        Ok(BatchReport {
+            files: files?,
+            diagnostics,
+            fail_on: self.fail_on,
+        })
+    }
+
+    async fn drive(
+        &self,
+        service: &mut LspService,
+        root_uri: Url,
+        files: &[(PathBuf, String)],
+        activity: &AtomicUsize,
+    ) -> io::Result<usize> {
+        let name = self.root.file_name().map(|name| name.to_string_lossy().into_owned());
+        let initialize = json!({
+            "jsonrpc": "2.0",
+            "method": "initialize",
+            "params": {
+                "processId": null,
+                "rootUri": root_uri,
+                "initializationOptions": self.initialization_options,
+                "capabilities": { "textDocument": { "publishDiagnostics": {} } },
+                "workspaceFolders": [{ "uri": root_uri, "name": name.unwrap_or_default() }],
+            },
+            "id": 1,
+        });
+        request(service, initialize).await?;
+        notify(service, json!({ "jsonrpc": "2.0", "method": "initialized", "params": {} })).await?;
+
+        let mut opened = 0;
+        for (path, language_id) in files {
+            let text = match fs::read_to_string(path) {
+                Ok(text) => text,
+                Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+                    log::warn!("skipping {}: not valid UTF-8", path.display());
+                    continue;
+                },
+                Err(err) => return Err(err),
+            };
+            let uri = fs::canonicalize(path).ok().and_then(uri::from_file_path);
+            let uri = uri.ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "invalid file path"))?;
+            let did_open = json!({
+                "jsonrpc": "2.0",
+                "method": "textDocument/didOpen",
+                "params": {
+                    "textDocument": { "uri": uri, "languageId": language_id, "version": 0, "text": text },
+                },
+            });
+            notify(service, did_open).await?;
+            opened += 1;
+        }
+
+        loop {
+            let seen = activity.load(Ordering::SeqCst);
+            crate::time::sleep(self.settle_time).await;
+            if activity.load(Ordering::SeqCst) == seen {
+                break;
+            }
+        }
+
+        request(service, json!({ "jsonrpc": "2.0", "method": "shutdown", "id": 2 })).await?;
+        notify(service, json!({ "jsonrpc": "2.0", "method": "exit" })).await?;
+
+        Ok(opened)
+    }
+
+    /// Collects the files to open below `dir` along with their language IDs, in sorted order.
+    fn collect_files(&self, dir: &Path, files: &mut Vec<(PathBuf, String)>) -> io::Result<()> {
+        let mut entries = fs::read_dir(dir)?.collect::<io::Result<Vec<_>>>()?;
+        entries.sort_by_key(|entry| entry.file_name());
+
+        for entry in entries {
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+
+            let path = entry.path();
+            if entry.file_type()?.is_dir() {
+                self.collect_files(&path, files)?;
+                continue;
+            }
+
+            let extension = match path.extension() {
+                Some(extension) => extension.to_string_lossy().into_owned(),
+                None => continue,
+            };
+            let language_id = if self.languages.is_empty() {
+                Some(extension)
+            } else {
+                self.languages.get(&extension).cloned()
+            };
+            if let Some(language_id) = language_id {
+                files.push((path, language_id));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Sends a request to the service, failing if the server responds with an error.
+async fn request(service: &mut LspService, message: Value) -> io::Result<()> {
+    match call(service, message).await? {
+        Some(Outgoing::Response(response)) => match response.into_parts().1 {
+            Ok(_) => Ok(()),
+            Err(err) => Err(io::Error::other(err.to_string())),
+        },
+        _ => Ok(()),
+    }
+}
+
+/// Sends a notification to the service.
+async fn notify(service: &mut LspService, message: Value) -> io::Result<()> {
+    call(service, message).await.map(drop)
+}
+
+async fn call(service: &mut LspService, message: Value) -> io::Result<Option<Outgoing>> {
+    // The messages are constructed by the driver itself, so the `unwrap()` call below should never
+    // fail.
+    let message: Incoming = serde_json::from_value(message).unwrap();
+    let exited = io::Error::other;
+    future::poll_fn(|cx| service.poll_ready(cx)).await.map_err(exited)?;
+    service.call(message).await.map_err(exited)
+}
+
+/// The diagnostics collected by a [`BatchDriver`].
+///
+/// The report is displayed as one `path:line:column: severity: message` line per diagnostic.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BatchReport {
+    files: usize,
+    diagnostics: BTreeMap<Url, Vec<Diagnostic>>,
+    fail_on: DiagnosticSeverity,
+}
+
+impl BatchReport {
+    /// Returns the number of files which were opened.
+    pub fn files(&self) -> usize {
+        self.files
+    }
+
+    /// Returns the last diagnostics published for each document.
+    pub fn diagnostics(&self) -> &BTreeMap<Url, Vec<Diagnostic>> {
+        &self.diagnostics
+    }
+
+    /// Returns the number of diagnostics with the given severity.
+    ///
+    /// Diagnostics without a severity are counted as errors.
+    pub fn count(&self, severity: DiagnosticSeverity) -> usize {
+        self.all().filter(|diagnostic| severity_of(diagnostic) == severity).count()
+    }
+
+    /// Returns `true` if no diagnostic is at least as severe as the severity set with
+    /// [`BatchDriver::fail_on`].
+    pub fn is_success(&self) -> bool {
+        self.all().all(|diagnostic| severity_of(diagnostic) > self.fail_on)
+    }
+
+    /// Returns the process exit code for the report, `0` on success and `1` otherwise.
+    pub fn exit_code(&self) -> i32 {
+        if self.is_success() {
+            0
+        } else {
+            1
+        }
+    }
+
+    fn all(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.diagnostics.values().flatten()
+    }
+}
+
+impl Display for BatchReport {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        for (uri, diagnostics) in &self.diagnostics {
+            let path = uri::to_file_path(uri).map(|path| path.display().to_string());
+            let path = path.unwrap_or_else(|| uri.to_string());
+            for diagnostic in diagnostics {
+                let start = diagnostic.range.start;
+                let severity = match severity_of(diagnostic) {
+                    DiagnosticSeverity::WARNING => "warning",
+                    DiagnosticSeverity::INFORMATION => "info",
+                    DiagnosticSeverity::HINT => "hint",
+                    _ => "error",
+                };
+                writeln!(
+                    f,
+                    "{}:{}:{}: {}: {}",
+                    path,
+                    start.line + 1,
+                    start.character + 1,
+                    severity,
+                    diagnostic.message
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn severity_of(diagnostic: &Diagnostic) -> DiagnosticSeverity {
+    diagnostic.severity.unwrap_or(DiagnosticSeverity::ERROR)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{jsonrpc::Result, Client, LanguageServer};
+    use async_trait::async_trait;
+
+    struct Linter(Client);
+
+    #[async_trait]
+    impl LanguageServer for Linter {
+        async fn initialize(&self, _: lsp::InitializeParams) -> Result<lsp::InitializeResult> {
+            Ok(lsp::InitializeResult::default())
+        }
+
+        async fn shutdown(&self) -> Result<()> {
+            Ok(())
+        }
+
+        async fn did_open(&self, params: lsp::DidOpenTextDocumentParams) {
+            let document = params.text_document;
+            let diagnostics = document
+                .text
+                .lines()
+                .enumerate()
+                .filter(|(_, line)| line.contains("TODO"))
+                .map(|(line, _)| Diagnostic {
+                    range: lsp::Range::new(lsp::Position::new(line as u32, 0), lsp::Position::new(line as u32, 4)),
+                    severity: Some(DiagnosticSeverity::WARNING),
+                    message: "unresolved TODO".into(),
+                    ..Diagnostic::default()
+                })
+                .collect();
+            self.0.publish_diagnostics(document.uri, diagnostics, None).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn run() {
+        let root = std::env::temp_dir().join(format!("lspower-batch-{}", std::process::id()));
+        fs::create_dir_all(root.join("sub")).unwrap();
+        fs::create_dir_all(root.join(".hidden")).unwrap();
+        fs::write(root.join("a.txt"), "fine\nTODO\n").unwrap();
+        fs::write(root.join("b.txt"), "fine\n").unwrap();
+        fs::write(root.join("sub").join("c.txt"), "TODO\n").unwrap();
+        fs::write(root.join(".hidden").join("d.txt"), "TODO\n").unwrap();
+        fs::write(root.join("e.bin"), [0xff, 0xfe]).unwrap();
+
+        let driver = BatchDriver::new(&root)
+            .language("txt", "plaintext")
+            .settle_time(Duration::from_millis(10));
+        let report = driver.run(LspService::build(Linter)).await.unwrap();
+        assert_eq!(report.files(), 3);
+        assert_eq!(report.diagnostics().len(), 3);
+        assert_eq!(report.count(DiagnosticSeverity::WARNING), 2);
+        assert_eq!(report.exit_code(), 0);
+        assert_eq!(report.to_string().lines().count(), 2);
+        assert!(report.to_string().contains("a.txt:2:1: warning: unresolved TODO"));
+
+        let driver = BatchDriver::new(&root)
+            .settle_time(Duration::from_millis(10))
+            .fail_on(DiagnosticSeverity::WARNING);
+        let report = driver.run(LspService::build(Linter)).await.unwrap();
+        assert_eq!(report.files(), 3);
+        assert_eq!(report.exit_code(), 1);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,233 @@
+//! Server driven by a blocking message loop, as used by the `lsp-server` crate.
+
+use crate::{
+    jsonrpc::{self, Incoming, Outgoing, Response},
+    ExitReason,
+};
+use futures::{
+    channel::mpsc,
+    select,
+    stream::{self, FusedStream, FuturesUnordered, Stream, StreamExt},
+};
+use serde::{de::DeserializeOwned, Serialize};
+use std::fmt::Display;
+use tower_service::Service;
+
+/// Server exchanging messages through a blocking channel instead of an async byte stream.
+///
+/// This eases the migration of servers built on the `lsp-server` crate, whose `Connection` provides
+/// a blocking receiver and sender of `Message` values: the existing main loop can be replaced with
+/// an [`LspService`] served on the same connection. Messages of any type sharing the JSON
+/// representation of the protocol are accepted, see [`Incoming::from_message`] and
+/// [`Outgoing::into_message`].
+///
+/// Incoming messages are read on a dedicated thread, while request handlers run concurrently on
+/// the calling thread, which is blocked until the client sent the `exit` notification or closed
+/// the connection. With the `runtime-tokio` crate feature, servers relying on timers must be served
+/// within the context of a Tokio runtime, e.g. entered with `Runtime::enter`.
+///
+/// # Example
+///
+/// ```rust,ignore
+/// let (connection, io_threads) = lsp_server::Connection::stdio();
+/// let (service, messages) = LspService::new(|client| Backend { client });
+/// let sender = connection.sender.clone();
+/// BlockingServer::new(connection.receiver, move |message| sender.send(message).is_ok())
+///     .interleave(messages)
+///     .serve(service);
+/// io_threads.join()?;
+/// ```
+///
+/// [`LspService`]: crate::LspService
+#[derive(Debug)]
+pub struct BlockingServer<I, F, S = stream::Empty<Outgoing>> {
+    incoming: I,
+    outgoing: F,
+    interleave: S,
+}
+
+impl<I, F, M> BlockingServer<I, F>
+where
+    I: IntoIterator<Item = M>,
+    F: FnMut(M) -> bool,
+{
+    /// Creates a new `BlockingServer` reading messages from `incoming` and writing them with
+    /// `outgoing`, which returns `false` once the connection was closed.
+    pub fn new(incoming: I, outgoing: F) -> Self {
+        BlockingServer {
+            incoming,
+            outgoing,
+            interleave: stream::empty(),
+        }
+    }
+}
+
+impl<I, F, M, S> BlockingServer<I, F, S>
+where
+    I: IntoIterator<Item = M>,
+    I::IntoIter: Send + 'static,
+    F: FnMut(M) -> bool,
+    M: Serialize + DeserializeOwned + Send + 'static,
+    S: Stream<Item = Outgoing>,
+{
+    /// Interleaves the given stream of messages into `outgoing` together with the responses.
+    pub fn interleave<T>(self, stream: T) -> BlockingServer<I, F, T>
+    where
+        T: Stream<Item = Outgoing>,
+    {
+        BlockingServer {
+            incoming: self.incoming,
+            outgoing: self.outgoing,
+            interleave: stream,
+        }
+    }
+
+    /// Serves the service until the `exit` notification was handled or `incoming` ended, returning
+    /// why the server stopped.
+    pub fn serve<T>(self, mut service: T) -> ExitReason
+    where
+        T: Service<Incoming, Response = Option<Outgoing>>,
+        T::Error: Display,
+    {
+        let (tx, rx) = mpsc::unbounded();
+        let incoming = self.incoming.into_iter();
+        let reader = std::thread::Builder::new().name("lspower-reader".into()).spawn(move || {
+            for message in incoming {
+                if tx.unbounded_send(message).is_err() {
+                    return;
+                }
+            }
+        });
+        if let Err(error) = reader {
+            log::error!("failed to spawn reader thread: {}", error);
+            return ExitReason::InternalError(format!("failed to spawn reader thread: {}", error));
+        }
+
+        let mut outgoing = self.outgoing;
+        let mut write = move |message: Outgoing| match message.into_message() {
+            Ok(message) => outgoing(message),
+            Err(error) => {
+                log::error!("failed to convert outgoing message: {}", error);
+                true
+            },
+        };
+
+        futures::executor::block_on(async move {
+            let mut incoming = rx.fuse();
+            let mut interleave = Box::pin(self.interleave.fuse());
+            let mut responses = FuturesUnordered::new();
+            let mut exited = false;
+
+            loop {
+                // Once the input was closed, or after `exit`, the pending responses are still
+                // written, as are the messages sent by the exit hooks, but no more messages are read.
+                if incoming.is_terminated() && !exited && responses.is_empty() {
+                    return ExitReason::TransportClosed;
+                }
+                if exited && responses.is_empty() && interleave.is_terminated() {
+                    return ExitReason::ClientRequested;
+                }
+
+                let message = select! {
+                    message = incoming.next() => match message {
+                        Some(message) if !exited => message,
+                        _ => continue,
+                    },
+                    response = responses.select_next_some() => {
+                        match response {
+                            Ok(Some(response)) => {
+                                if !write(response) {
+                                    return ExitReason::TransportClosed;
+                                }
+                            },
+                            Ok(None) => {},
+                            Err(error) => log::error!("{}", error),
+                        }
+                        continue;
+                    },
+                    message = interleave.next() => {
+                        if let Some(message) = message {
+                            if !write(message) {
+                                return ExitReason::TransportClosed;
+                            }
+                        }
+                        continue;
+                    },
+                    complete => {
+                        return if exited { ExitReason::ClientRequested } else { ExitReason::TransportClosed };
+                    },
+                };
+
+                let request = match Incoming::from_message(&message) {
+                    Ok(request) => request,
+                    Err(error) => {
+                        log::error!("failed to decode message: {}", error);
+                        let response = Response::error(None, jsonrpc::Error::parse_error());
+                        if !write(Outgoing::Response(response)) {
+                            return ExitReason::TransportClosed;
+                        }
+                        continue;
+                    },
+                };
+                exited = matches!(&request, Incoming::Request(req) if req.method() == "exit");
+
+                if let Err(error) = futures::future::poll_fn(|cx| service.poll_ready(cx)).await {
+                    log::error!("{}", error);
+                    return ExitReason::InternalError(error.to_string());
+                }
+                responses.push(service.call(request));
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LspService;
+    use async_trait::async_trait;
+    use serde_json::{json, Value};
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Debug)]
+    struct Mock;
+
+    #[async_trait]
+    impl crate::LanguageServer for Mock {
+        async fn initialize(&self, _: lsp::InitializeParams) -> jsonrpc::Result<lsp::InitializeResult> {
+            Ok(lsp::InitializeResult::default())
+        }
+
+        async fn shutdown(&self) -> jsonrpc::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn serve() {
+        let incoming = vec![
+            json!({ "jsonrpc": "2.0", "method": "initialize", "params": { "capabilities": {} }, "id": 1 }),
+            json!({ "jsonrpc": "2.0", "method": "initialized", "params": {} }),
+            json!({ "jsonrpc": "2.0", "method": "shutdown", "id": 2 }),
+            json!({ "jsonrpc": "2.0", "method": "exit" }),
+        ];
+        let written = Arc::new(Mutex::new(Vec::<Value>::new()));
+        let outgoing = {
+            let written = written.clone();
+            move |message| {
+                written.lock().unwrap().push(message);
+                true
+            }
+        };
+
+        let (service, messages) = LspService::new(|_| Mock);
+        let reason = BlockingServer::new(incoming, outgoing).interleave(messages).serve(service);
+        assert_eq!(reason, ExitReason::ClientRequested);
+
+        let written = written.lock().unwrap();
+        assert_eq!(*written, vec![
+            json!({ "jsonrpc": "2.0", "result": { "capabilities": {} }, "id": 1 }),
+            json!({ "jsonrpc": "2.0", "result": null, "id": 2 }),
+        ]);
+    }
+}
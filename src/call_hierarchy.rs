@@ -0,0 +1,166 @@
+//! Correlation of prepared call hierarchy items with later call requests.
+
+use serde_json::Value;
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Debug, Formatter},
+    sync::Mutex,
+};
+
+/// Registry of server-side state for prepared [`CallHierarchyItem`]s.
+///
+/// The client sends the items returned from `textDocument/prepareCallHierarchy` back to the server
+/// with every `callHierarchy/incomingCalls` and `callHierarchy/outgoingCalls` request. Instead of
+/// encoding the state needed to resolve the calls into the opaque `data` field by hand, servers can
+/// [`prepare`] each item with a value of type `T`, which stores the value in this registry and a
+/// generated token in the `data` field. The value can later be retrieved with [`get`].
+///
+/// The registry keeps at most `capacity` values; once exceeded, the values of the oldest prepared
+/// items are evicted.
+///
+/// [`CallHierarchyItem`]: lsp::CallHierarchyItem
+/// [`prepare`]: CallHierarchyRegistry::prepare
+/// [`get`]: CallHierarchyRegistry::get
+pub struct CallHierarchyRegistry<T> {
+    inner: Mutex<RegistryInner<T>>,
+    capacity: usize,
+}
+
+struct RegistryInner<T> {
+    next_token: u64,
+    items: BTreeMap<u64, T>,
+}
+
+impl<T: Clone> CallHierarchyRegistry<T> {
+    /// Creates a new `CallHierarchyRegistry` which keeps the values of up to 1024 items.
+    pub fn new() -> Self {
+        CallHierarchyRegistry::with_capacity(1024)
+    }
+
+    /// Creates a new `CallHierarchyRegistry` which keeps the values of up to `capacity` items.
+    pub fn with_capacity(capacity: usize) -> Self {
+        CallHierarchyRegistry {
+            inner: Mutex::new(RegistryInner {
+                next_token: 0,
+                items: BTreeMap::new(),
+            }),
+            capacity,
+        }
+    }
+
+    /// Associates `value` with the item and returns the item with its `data` field set to a
+    /// generated token.
+    ///
+    /// Any existing `data` of the item is overwritten.
+    pub fn prepare(&self, mut item: lsp::CallHierarchyItem, value: T) -> lsp::CallHierarchyItem {
+        let mut inner = self.inner.lock().unwrap();
+        let token = inner.next_token;
+        inner.next_token += 1;
+        inner.items.insert(token, value);
+        while inner.items.len() > self.capacity {
+            let oldest = *inner.items.keys().next().unwrap();
+            inner.items.remove(&oldest);
+        }
+        item.data = Some(Value::from(token));
+        item
+    }
+
+    /// Returns the value associated with a prepared item, if it has not been evicted or removed.
+    pub fn get(&self, item: &lsp::CallHierarchyItem) -> Option<T> {
+        let token = token(item)?;
+        self.inner.lock().unwrap().items.get(&token).cloned()
+    }
+
+    /// Removes the value associated with a prepared item and returns it.
+    pub fn remove(&self, item: &lsp::CallHierarchyItem) -> Option<T> {
+        let token = token(item)?;
+        self.inner.lock().unwrap().items.remove(&token)
+    }
+
+    /// Removes all values, e.g. after the workspace has changed substantially.
+    pub fn clear(&self) {
+        self.inner.lock().unwrap().items.clear();
+    }
+
+    /// Returns the number of values currently kept in the registry.
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().items.len()
+    }
+
+    /// Returns `true` if the registry keeps no values.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T: Clone> Default for CallHierarchyRegistry<T> {
+    fn default() -> Self {
+        CallHierarchyRegistry::new()
+    }
+}
+
+impl<T> Debug for CallHierarchyRegistry<T> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct(stringify!(CallHierarchyRegistry))
+            .field("items", &self.inner.lock().unwrap().items.len())
+            .field("capacity", &self.capacity)
+            .finish()
+    }
+}
+
+fn token(item: &lsp::CallHierarchyItem) -> Option<u64> {
+    item.data.as_ref().and_then(Value::as_u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(name: &str) -> lsp::CallHierarchyItem {
+        lsp::CallHierarchyItem {
+            name: name.into(),
+            kind: lsp::SymbolKind::FUNCTION,
+            tags: None,
+            detail: None,
+            uri: lsp::Url::parse("inmemory::///test").unwrap(),
+            range: Default::default(),
+            selection_range: Default::default(),
+            data: None,
+        }
+    }
+
+    #[test]
+    fn prepare_and_get() {
+        let registry = CallHierarchyRegistry::new();
+        let foo = registry.prepare(item("foo"), "foo".to_string());
+        let bar = registry.prepare(item("bar"), "bar".to_string());
+        assert_eq!(registry.get(&foo).as_deref(), Some("foo"));
+        assert_eq!(registry.get(&bar).as_deref(), Some("bar"));
+        assert_eq!(registry.get(&item("baz")), None);
+        assert_eq!(registry.remove(&foo).as_deref(), Some("foo"));
+        assert_eq!(registry.get(&foo), None);
+    }
+
+    #[test]
+    fn round_trip() {
+        let registry = CallHierarchyRegistry::new();
+        let prepared = registry.prepare(item("foo"), 42);
+        let sent = serde_json::to_string(&prepared).unwrap();
+        let received: lsp::CallHierarchyItem = serde_json::from_str(&sent).unwrap();
+        assert_eq!(registry.get(&received), Some(42));
+    }
+
+    #[test]
+    fn eviction() {
+        let registry = CallHierarchyRegistry::with_capacity(2);
+        let first = registry.prepare(item("first"), 1);
+        let second = registry.prepare(item("second"), 2);
+        let third = registry.prepare(item("third"), 3);
+        assert_eq!(registry.len(), 2);
+        assert_eq!(registry.get(&first), None);
+        assert_eq!(registry.get(&second), Some(2));
+        assert_eq!(registry.get(&third), Some(3));
+        registry.clear();
+        assert!(registry.is_empty());
+    }
+}
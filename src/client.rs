@@ -1,22 +1,65 @@
 //! Types for sending data to and from the language client.
 
+mod backlog;
+mod capabilities;
+mod configuration;
+mod headless;
+mod ids;
+mod pool;
+mod rate_limit;
+mod retry;
+#[cfg(test)]
+mod scripted;
+mod state;
+mod telemetry;
+mod workspace_edit;
+
+pub use self::{
+    capabilities::{ClientRequestError, UnsupportedByClient, UnsupportedRegistration},
+    headless::HeadlessClient,
+    ids::{IdAllocator, IdRange},
+    pool::{ClientId, ClientPool},
+    rate_limit::{OverflowAction, RateLimit, RateLimitPolicy},
+    retry::RetryPolicy,
+    state::{ClientState, Dynamic, Initialized, MaybeInitialized, Uninitialized},
+    telemetry::TelemetryPolicy,
+    workspace_edit::UnsupportedResourceOperations,
+};
+pub(crate) use self::{
+    backlog::{Backlog, Outbox},
+    capabilities::CapabilityRegistry,
+    retry::RetryPolicies,
+};
+use self::{
+    configuration::ConfigurationCache,
+    rate_limit::{Admission, RateLimiter},
+    retry::{ClientCall, RetryLayer},
+    telemetry::{Collected, TelemetryBatcher},
+};
 use futures::{
     channel::{mpsc, oneshot},
-    future::{self, Shared},
+    future::{self, BoxFuture, Shared},
     select,
     sink::SinkExt,
+    stream::{Stream, StreamExt},
     FutureExt,
 };
 use std::{
     convert::TryFrom,
     fmt::{self, Debug, Formatter},
     future::Future,
+    marker::PhantomData,
     pin::Pin,
     sync::{
-        atomic::{AtomicBool, AtomicU64, Ordering},
+        atomic::{AtomicBool, Ordering},
         Arc,
+        Mutex,
     },
+    task::{Context, Poll},
+    time::Duration,
 };
+use tower_layer::Layer;
+use tower_service::Service;
 
 type TokenFuture = Shared<Pin<Box<dyn Future<Output = Result<(), oneshot::Canceled>> + Send>>>;
 
@@ -101,11 +144,81 @@ impl Default for CancellationToken {
     }
 }
 
+/// Configuration for a [`Client`], set through the [`LspServiceBuilder`].
+///
+/// [`LspServiceBuilder`]: crate::LspServiceBuilder
+#[derive(Clone, Debug)]
+pub(crate) struct ClientOptions {
+    pub(crate) retry_policies: RetryPolicies,
+    pub(crate) telemetry_policy: Option<TelemetryPolicy>,
+    pub(crate) rate_limit_policy: Option<RateLimitPolicy>,
+    pub(crate) clock: Arc<dyn crate::Clock>,
+    pub(crate) headless: Option<HeadlessClient>,
+    pub(crate) ids: Arc<dyn IdAllocator>,
+    pub(crate) connection: Option<crate::ConnectionInfo>,
+    pub(crate) configuration_ttl: Option<Duration>,
+    pub(crate) cancel_dropped_requests: bool,
+    pub(crate) backlog: Option<Arc<Backlog>>,
+    pub(crate) log_level: Option<lsp::MessageType>,
+}
+
+impl Default for ClientOptions {
+    fn default() -> Self {
+        ClientOptions {
+            retry_policies: Default::default(),
+            telemetry_policy: None,
+            rate_limit_policy: None,
+            clock: Arc::new(crate::SystemClock),
+            headless: None,
+            ids: Arc::new(IdRange::default()),
+            connection: None,
+            configuration_ttl: None,
+            cancel_dropped_requests: false,
+            backlog: None,
+            log_level: None,
+        }
+    }
+}
+
 struct ClientInner {
     sender: mpsc::Sender<crate::jsonrpc::Outgoing>,
-    request_id: AtomicU64,
     pending_requests: Arc<crate::jsonrpc::ClientRequests>,
     state: Arc<crate::server::State>,
+    options: ClientOptions,
+    capabilities: Mutex<CapabilityRegistry>,
+    protocol: Mutex<Option<crate::NegotiatedProtocol>>,
+    trace: Mutex<lsp::TraceOption>,
+    telemetry: Option<Mutex<TelemetryBatcher>>,
+    rate_limiter: Option<Mutex<RateLimiter>>,
+    retry: RetryLayer,
+    tasks: Arc<crate::task::BackgroundTasks>,
+    scopes: Arc<crate::scope::DocumentScopes>,
+    configuration: ConfigurationCache,
+}
+
+impl ClientInner {
+    /// Sends a message to the client, or queues it if the transport did not attach yet.
+    async fn send(&self, message: crate::jsonrpc::Outgoing) -> Result<(), mpsc::SendError> {
+        match self.queue(message) {
+            Some(message) => self.sender.clone().send(message).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Sends a message to the client without waiting for room in the channel, dropping it if
+    /// there is none.
+    fn try_send(&self, message: crate::jsonrpc::Outgoing) {
+        if let Some(message) = self.queue(message) {
+            let _ = self.sender.clone().try_send(message);
+        }
+    }
+
+    fn queue(&self, message: crate::jsonrpc::Outgoing) -> Option<crate::jsonrpc::Outgoing> {
+        match &self.options.backlog {
+            Some(backlog) => backlog.push(message),
+            None => Some(message),
+        }
+    }
 }
 
 /// Handle for communicating with the language client.
@@ -113,10 +226,44 @@ struct ClientInner {
 /// This type provides a very cheap implementation of [`Clone`] so API consumers can cheaply clone
 /// and pass it around as needed.
 ///
+/// # Protocol State
+///
+/// By default, whether a message may be sent in the current state of the server is checked at
+/// runtime. Code which is only called once the server is initialized can instead opt into
+/// checking this at compile time: the client returned by [`typed`](Client::typed) only offers the
+/// messages which may be sent before the server is initialized, and is turned into a client
+/// offering all messages with [`upgrade`](Client::upgrade) once it is.
+///
+/// ```rust
+/// # use lspower::{Client, Initialized};
+/// # use lspower::lsp::{Diagnostic, MessageType, Url};
+/// async fn publish(client: &Client<Initialized>, uri: Url, diagnostics: Vec<Diagnostic>) {
+///     client.publish_diagnostics(uri, diagnostics, None).await;
+/// }
+///
+/// async fn initialized(client: &Client) {
+///     let client = client.typed();
+///     client.log_message(MessageType::INFO, "initializing").await;
+///     if let Ok(client) = client.upgrade() {
+///         let uri = Url::parse("file:///main.rs").unwrap();
+///         publish(&client, uri, vec![]).await;
+///     }
+/// }
+/// ```
+///
 /// [`Clone`]: trait@std::clone::Clone
-#[derive(Clone)]
-pub struct Client {
+pub struct Client<S = Dynamic> {
     inner: Arc<ClientInner>,
+    _state: PhantomData<S>,
+}
+
+impl<S> Clone for Client<S> {
+    fn clone(&self) -> Self {
+        Client {
+            inner: self.inner.clone(),
+            _state: PhantomData,
+        }
+    }
 }
 
 impl Client {
@@ -124,14 +271,71 @@ impl Client {
         sender: mpsc::Sender<crate::jsonrpc::Outgoing>,
         pending_requests: Arc<crate::jsonrpc::ClientRequests>,
         state: Arc<crate::server::State>,
+        options: ClientOptions,
+        tasks: Arc<crate::task::BackgroundTasks>,
     ) -> Self {
+        let telemetry = options.telemetry_policy.clone().map(|policy| Mutex::new(TelemetryBatcher::new(policy)));
+        let rate_limiter = options.rate_limit_policy.clone().map(|policy| Mutex::new(RateLimiter::new(policy)));
+        let retry = RetryLayer::new(options.retry_policies.clone(), options.clock.clone());
         Client {
             inner: Arc::new(ClientInner {
                 sender,
-                request_id: AtomicU64::new(0),
                 pending_requests,
                 state,
+                options,
+                capabilities: Default::default(),
+                protocol: Default::default(),
+                trace: Default::default(),
+                telemetry,
+                rate_limiter,
+                retry,
+                tasks,
+                scopes: Default::default(),
+                configuration: Default::default(),
             }),
+            _state: PhantomData,
+        }
+    }
+
+    /// Returns a client for this server whose protocol state is checked at compile time, starting
+    /// out as [`Uninitialized`].
+    ///
+    /// See [Protocol State](Client#protocol-state) for details.
+    pub fn typed(&self) -> Client<Uninitialized> {
+        Client {
+            inner: self.inner.clone(),
+            _state: PhantomData,
+        }
+    }
+}
+
+impl Client<Uninitialized> {
+    /// Upgrades the client to the [`Initialized`] state, once the server responded to the
+    /// `initialize` request, such as in [`LanguageServer::initialized`].
+    ///
+    /// Returns the client unchanged if the server is not initialized yet. Since the server may
+    /// still shut down or be reset afterwards, the messages sent through the upgraded client are
+    /// checked at runtime as well.
+    ///
+    /// [`LanguageServer::initialized`]: crate::LanguageServer::initialized
+    pub fn upgrade(self) -> Result<Client<Initialized>, Self> {
+        match self.inner.state.get() {
+            crate::server::StateKind::Initialized | crate::server::StateKind::ShutDown => Ok(Client {
+                inner: self.inner,
+                _state: PhantomData,
+            }),
+            _ => Err(self),
+        }
+    }
+}
+
+impl<S: ClientState> Client<S> {
+    /// Returns a client for this server whose protocol state is checked at runtime, for passing it
+    /// to code which expects a [`Client`].
+    pub fn dynamic(&self) -> Client {
+        Client {
+            inner: self.inner.clone(),
+            _state: PhantomData,
         }
     }
 
@@ -147,17 +351,197 @@ impl Client {
         sender.close_channel();
     }
 
+    /// Returns whether the client was closed, e.g. because the service exited.
+    pub(crate) fn is_closed(&self) -> bool {
+        self.inner.sender.is_closed()
+    }
+
+    /// Fails the requests to the client still waiting for a response with the given error, as well
+    /// as requests sent from now on, since the client is not expected to answer them anymore.
+    pub(crate) fn fail_pending_requests(&self, error: crate::jsonrpc::Error) {
+        self.inner.pending_requests.close(error);
+    }
+
+    /// Spawns a background task tied to the lifetime of the service.
+    ///
+    /// The task is awaited when the client requests a `shutdown` and aborted once the `exit`
+    /// notification is received. See [`Spawner`] for details.
+    ///
+    /// [`Spawner`]: crate::Spawner
+    pub fn spawn_background<F>(&self, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.inner.tasks.spawn(task);
+    }
+
+    pub(crate) fn background_tasks(&self) -> &Arc<crate::task::BackgroundTasks> {
+        &self.inner.tasks
+    }
+
+    /// Returns a handle for spawning background tasks which are aborted once the given document is
+    /// changed or closed. See [`DocumentScope`] for details.
+    ///
+    /// [`DocumentScope`]: crate::DocumentScope
+    pub fn document_scope(&self, uri: lsp::Url) -> crate::DocumentScope {
+        crate::DocumentScope::new(uri, self.inner.scopes.clone(), self.inner.tasks.clone())
+    }
+
+    pub(crate) fn document_scopes(&self) -> &crate::scope::DocumentScopes {
+        &self.inner.scopes
+    }
+
+    /// Returns the clock used for timeouts and delays by the service.
+    ///
+    /// Servers implementing time-dependent behavior, such as debouncing, should use this clock so
+    /// that tests can replace it with a [`MockClock`] through [`LspServiceBuilder::clock`].
+    ///
+    /// [`MockClock`]: crate::MockClock
+    /// [`LspServiceBuilder::clock`]: crate::LspServiceBuilder::clock
+    pub fn clock(&self) -> Arc<dyn crate::Clock> {
+        self.inner.options.clock.clone()
+    }
+
+    /// Returns the information about the connection of the client, if the service was created
+    /// with one through [`LspServiceBuilder::connection`].
+    ///
+    /// [`LspServiceBuilder::connection`]: crate::LspServiceBuilder::connection
+    pub fn connection(&self) -> Option<&crate::ConnectionInfo> {
+        self.inner.options.connection.as_ref()
+    }
+
+    /// Stores the capabilities the client declared in its `initialize` request, along with their
+    /// JSON representation as sent by the client, if available.
+    pub(crate) fn set_client_capabilities(
+        &self,
+        capabilities: lsp::ClientCapabilities,
+        raw: Option<serde_json::Value>,
+    ) {
+        self.inner.capabilities.lock().unwrap().set_capabilities(capabilities, raw);
+    }
+
+    /// Returns the capabilities the client declared in its `initialize` request.
+    ///
+    /// Returns `None` if the server has not received an `initialize` request yet.
+    pub fn client_capabilities(&self) -> Option<lsp::ClientCapabilities> {
+        self.inner.capabilities.lock().unwrap().capabilities().cloned()
+    }
+
+    /// Returns the capabilities the client declared in its `initialize` request as sent, including
+    /// the capabilities `lsp-types` does not retain, or `null` before the request was received.
+    pub(crate) fn declared_capabilities(&self) -> Arc<serde_json::Value> {
+        self.inner.capabilities.lock().unwrap().flags()
+    }
+
+    /// Stores the trace setting of the client, from its `initialize` request or a `$/setTrace`
+    /// notification.
+    pub(crate) fn set_trace(&self, trace: lsp::TraceOption) {
+        *self.inner.trace.lock().unwrap() = trace;
+    }
+
+    /// Returns the trace setting the client requested last, in its `initialize` request or a
+    /// `$/setTrace` notification.
+    ///
+    /// Defaults to [`TraceOption::Off`](lsp::TraceOption::Off) if the client did not request any.
+    pub fn trace(&self) -> lsp::TraceOption {
+        *self.inner.trace.lock().unwrap()
+    }
+
+    /// Stores the protocol negotiated from the client's `initialize` request.
+    pub(crate) fn set_negotiated_protocol(&self, protocol: crate::NegotiatedProtocol) {
+        *self.inner.protocol.lock().unwrap() = Some(protocol);
+    }
+
+    /// Returns the protocol features negotiated from the client's `initialize` request.
+    ///
+    /// Returns `None` if the server has not received an `initialize` request yet.
+    pub fn negotiated_protocol(&self) -> Option<crate::NegotiatedProtocol> {
+        self.inner.protocol.lock().unwrap().clone()
+    }
+
+    /// Forgets the capabilities and protocol declared in the client's `initialize` request, as well
+    /// as all registrations, so that the client can initialize the server again.
+    pub(crate) fn reset(&self) {
+        *self.inner.capabilities.lock().unwrap() = Default::default();
+        *self.inner.protocol.lock().unwrap() = None;
+        *self.inner.trace.lock().unwrap() = Default::default();
+        self.inner.scopes.cancel_all();
+        self.inner.configuration.invalidate();
+        self.set_telemetry_timed(true);
+        if let Some(limiter) = &self.inner.rate_limiter {
+            limiter.lock().unwrap().clear();
+        }
+    }
+
+    /// Forgets the configuration sections cached by [`configuration_scoped`].
+    ///
+    /// This is done automatically when the client sends the `workspace/didChangeConfiguration`
+    /// notification, but servers can also call it when they learn about configuration changes
+    /// otherwise, e.g. from a watched configuration file.
+    ///
+    /// [`configuration_scoped`]: Client::configuration_scoped
+    pub fn invalidate_configuration(&self) {
+        self.inner.configuration.invalidate();
+    }
+
+    /// Returns whether the client declared support for dynamically registering the given method.
+    ///
+    /// If this returns `false`, [`register_capability`] rejects registrations for the method and
+    /// the capability should be declared statically in the [`InitializeResult`] instead. Methods
+    /// without a `dynamicRegistration` client capability, such as custom methods, are always
+    /// considered supported.
+    ///
+    /// [`register_capability`]: Client::register_capability
+    /// [`InitializeResult`]: lsp::InitializeResult
+    pub fn supports_dynamic_registration(&self, method: &str) -> bool {
+        self.inner.capabilities.lock().unwrap().supports_dynamic_registration(method)
+    }
+
+    /// Returns whether the client declared support for the given server-to-client request.
+    ///
+    /// This checks the capabilities gating `window/showDocument` and
+    /// `window/workDoneProgress/create`, for which [`show_document`] and
+    /// [`create_work_done_progress`] return an [`UnsupportedByClient`] error instead of sending
+    /// the request. Other methods are assumed to be supported. Returns `false` for the gated
+    /// methods if the server has not received an `initialize` request yet.
+    ///
+    /// [`show_document`]: Client::show_document
+    /// [`create_work_done_progress`]: Client::create_work_done_progress
+    pub fn supports_request(&self, method: &str) -> bool {
+        self.inner.capabilities.lock().unwrap().supports_request(method)
+    }
+
+    /// Returns the registrations which were accepted by the client and not unregistered since.
+    pub fn registrations(&self) -> Vec<lsp::Registration> {
+        self.inner.capabilities.lock().unwrap().registrations()
+    }
+
     /// Notifies the client to log a particular message.
     ///
     /// This corresponds to the [`window/logMessage`] notification.
     ///
+    /// If a minimum level was set with [`LspServiceBuilder::log_message_level`], less severe
+    /// messages are dropped unless the client requested a `verbose` [`trace`](Client::trace).
+    ///
     /// [`window/logMessage`]: https://microsoft.github.io/language-server-protocol/specification#window_logMessage
+    /// [`LspServiceBuilder::log_message_level`]: crate::LspServiceBuilder::log_message_level
     pub async fn log_message<M: std::fmt::Display>(&self, typ: lsp::MessageType, message: M) {
+        if !self.logs(typ) {
+            return;
+        }
         let message = message.to_string();
         let params = lsp::LogMessageParams { typ, message };
         self.send_notification::<lsp::notification::LogMessage>(params).await;
     }
 
+    /// Returns whether messages of the given type are sent by [`log_message`](Client::log_message).
+    fn logs(&self, typ: lsp::MessageType) -> bool {
+        match self.inner.options.log_level {
+            Some(level) if self.trace() != lsp::TraceOption::Verbose => severity(typ) <= severity(level),
+            _ => true,
+        }
+    }
+
     /// Notifies the client to display a particular message in the user interface.
     ///
     /// This corresponds to the [`window/showMessage`] notification.
@@ -194,19 +578,148 @@ impl Client {
     ///
     /// This corresponds to the [`telemetry/event`] notification.
     ///
+    /// If a [`TelemetryPolicy`] was set with [`LspServiceBuilder::telemetry_policy`], the event is
+    /// sampled and collected into a batch instead of being sent immediately. Batches are sent as a
+    /// single notification whose data is the array of collected events.
+    ///
     /// [`telemetry/event`]: https://microsoft.github.io/language-server-protocol/specification#telemetry_event
-    pub async fn telemetry_event<S: serde::Serialize>(&self, data: S) {
-        match serde_json::to_value(data) {
-            Err(e) => log::error!("invalid JSON in `telemetry/event` notification: {}", e),
-            Ok(mut value) => {
-                if !value.is_null() && !value.is_array() && !value.is_object() {
-                    value = serde_json::Value::Array(vec![value]);
-                }
-                self.send_notification::<lsp::notification::TelemetryEvent>(value).await;
+    /// [`LspServiceBuilder::telemetry_policy`]: crate::LspServiceBuilder::telemetry_policy
+    pub async fn telemetry_event<T: serde::Serialize>(&self, data: T) {
+        let value = match serde_json::to_value(data) {
+            Ok(value) => value,
+            Err(e) => {
+                log::error!("invalid JSON in `telemetry/event` notification: {}", e);
+                return;
+            },
+        };
+
+        let batcher = match &self.inner.telemetry {
+            Some(batcher) => batcher,
+            None => {
+                let value = if !value.is_null() && !value.is_array() && !value.is_object() {
+                    serde_json::Value::Array(vec![value])
+                } else {
+                    value
+                };
+                return self.send_notification::<lsp::notification::TelemetryEvent>(value).await;
+            },
+        };
+
+        let collected = batcher.lock().unwrap().collect(value);
+        match collected {
+            Collected::Dropped | Collected::Buffered { start_timer: false } => {},
+            Collected::Buffered { start_timer: true } => {
+                let client = self.clone();
+                let mut batcher = batcher.lock().unwrap();
+                let interval = batcher.flush_interval();
+                let clock = self.clock();
+                let (timer, handle) = future::abortable(async move {
+                    clock.sleep(interval).await;
+                    client.flush_telemetry().await;
+                });
+                batcher.set_timer(handle);
+                drop(batcher);
+                self.spawn_background(timer.map(drop));
+            },
+            Collected::Full(events) => {
+                let events = serde_json::Value::Array(events);
+                self.send_notification::<lsp::notification::TelemetryEvent>(events).await;
             },
         }
     }
 
+    /// Sends all telemetry events collected since the last batch was sent.
+    ///
+    /// This is called automatically when the client requests a `shutdown`, and does nothing if no
+    /// [`TelemetryPolicy`] was set.
+    pub async fn flush_telemetry(&self) {
+        let events = match &self.inner.telemetry {
+            Some(batcher) => batcher.lock().unwrap().take(),
+            None => return,
+        };
+
+        if !events.is_empty() {
+            let events = serde_json::Value::Array(events);
+            self.send_notification::<lsp::notification::TelemetryEvent>(events).await;
+        }
+    }
+
+    /// Enables or disables flushing telemetry batches on a timer, which is disabled while the
+    /// server shuts down so that the shutdown does not wait for the timer.
+    pub(crate) fn set_telemetry_timed(&self, timed: bool) {
+        if let Some(batcher) = &self.inner.telemetry {
+            batcher.lock().unwrap().set_timed(timed);
+        }
+    }
+}
+
+impl<S: MaybeInitialized> Client<S> {
+    /// Asks the client to display a particular resource referenced by a URI in the user interface
+    /// and returns whether it succeeded.
+    ///
+    /// This corresponds to the [`window/showDocument`] request.
+    ///
+    /// [`window/showDocument`]: https://microsoft.github.io/language-server-protocol/specification#window_showDocument
+    ///
+    /// # Initialization
+    ///
+    /// If the request is sent to client before the server has been initialized, this will
+    /// immediately return `Err` with JSON-RPC error code `-32002` ([read more]).
+    ///
+    /// [read more]: https://microsoft.github.io/language-server-protocol/specification#initialize
+    ///
+    /// # Client Support
+    ///
+    /// If the client declared no support for `window/showDocument`, no request is sent and this
+    /// returns [`ClientRequestError::Unsupported`], carrying the name of the missing capability.
+    /// Other failures are returned as [`ClientRequestError::Failed`].
+    pub async fn show_document(&self, params: lsp::ShowDocumentParams) -> Result<bool, ClientRequestError> {
+        self.check_request::<lsp::request::ShowDocument>()?;
+        let token = CancellationToken::default();
+        let result = self.send_request_initialized::<lsp::request::ShowDocument>(params, token).await?;
+        Ok(result.success)
+    }
+
+    /// Asks the client to create a work done progress, which the server can report through
+    /// `$/progress` notifications with the given token afterwards.
+    ///
+    /// This corresponds to the [`window/workDoneProgress/create`] request.
+    ///
+    /// [`window/workDoneProgress/create`]: https://microsoft.github.io/language-server-protocol/specification#window_workDoneProgress_create
+    ///
+    /// # Initialization
+    ///
+    /// If the request is sent to client before the server has been initialized, this will
+    /// immediately return `Err` with JSON-RPC error code `-32002` ([read more]).
+    ///
+    /// [read more]: https://microsoft.github.io/language-server-protocol/specification#initialize
+    ///
+    /// # Client Support
+    ///
+    /// If the client declared no support for work done progress, no request is sent and this
+    /// returns [`ClientRequestError::Unsupported`], carrying the name of the missing capability.
+    /// Other failures are returned as [`ClientRequestError::Failed`].
+    pub async fn create_work_done_progress(&self, token: lsp::ProgressToken) -> Result<(), ClientRequestError> {
+        self.check_request::<lsp::request::WorkDoneProgressCreate>()?;
+        let params = lsp::WorkDoneProgressCreateParams { token };
+        let token = CancellationToken::default();
+        let result = self.send_request_initialized::<lsp::request::WorkDoneProgressCreate>(params, token).await;
+        Ok(result?)
+    }
+
+    /// Sends the [`window/workDoneProgress/create`] request, named after the request like the
+    /// methods of the [`LanguageServer`] trait.
+    ///
+    /// This is the same as [`create_work_done_progress`], including its checks of the state of
+    /// the server and of the support of the client.
+    ///
+    /// [`window/workDoneProgress/create`]: https://microsoft.github.io/language-server-protocol/specification#window_workDoneProgress_create
+    /// [`LanguageServer`]: crate::LanguageServer
+    /// [`create_work_done_progress`]: Client::create_work_done_progress
+    pub async fn work_done_progress_create(&self, token: lsp::ProgressToken) -> Result<(), ClientRequestError> {
+        self.create_work_done_progress(token).await
+    }
+
     /// Registers a new capability with the client.
     ///
     /// This corresponds to the [`client/registerCapability`] request.
@@ -219,11 +732,23 @@ impl Client {
     /// immediately return `Err` with JSON-RPC error code `-32002` ([read more]).
     ///
     /// [read more]: https://microsoft.github.io/language-server-protocol/specification#initialize
+    ///
+    /// # Dynamic Registration
+    ///
+    /// If the client declared no support for dynamically registering any of the given methods,
+    /// no request is sent and this returns an [`UnsupportedRegistration`] error converted into a
+    /// JSON-RPC error with code `-32600` (invalid request). See
+    /// [`supports_dynamic_registration`](Client::supports_dynamic_registration).
     #[rustfmt::skip]
     pub async fn register_capability(&self, registrations: Vec<lsp::Registration>) -> crate::jsonrpc::Result<()> {
+        if self.inner.state.get() == crate::server::StateKind::Initialized {
+            self.inner.capabilities.lock().unwrap().validate(&registrations)?;
+        }
         let token = CancellationToken::default();
-        let params = lsp::RegistrationParams { registrations };
-        self.send_request_initialized::<lsp::request::RegisterCapability>(params, token).await
+        let params = lsp::RegistrationParams { registrations: registrations.clone() };
+        self.send_request_initialized::<lsp::request::RegisterCapability>(params, token).await?;
+        self.inner.capabilities.lock().unwrap().register(registrations);
+        Ok(())
     }
 
     /// Unregisters a capability with the client.
@@ -244,8 +769,10 @@ impl Client {
         unregisterations: Vec<lsp::Unregistration>,
     ) -> crate::jsonrpc::Result<()> {
         let token = CancellationToken::default();
-        let params = lsp::UnregistrationParams { unregisterations };
-        self.send_request_initialized::<lsp::request::UnregisterCapability>(params, token).await
+        let params = lsp::UnregistrationParams { unregisterations: unregisterations.clone() };
+        self.send_request_initialized::<lsp::request::UnregisterCapability>(params, token).await?;
+        self.inner.capabilities.lock().unwrap().unregister(&unregisterations);
+        Ok(())
     }
 
     /// Fetches the current open list of workspace folders.
@@ -306,6 +833,80 @@ impl Client {
         self.send_request_initialized::<lsp::request::WorkspaceConfiguration>(params, token).await
     }
 
+    /// Fetches a single configuration section from the client and deserializes it, caching the
+    /// section for later calls.
+    ///
+    /// Sections are cached per scope and name until the client sends the
+    /// `workspace/didChangeConfiguration` notification, until [`invalidate_configuration`] is
+    /// called, or until the time set with [`LspServiceBuilder::configuration_ttl`] elapsed.
+    /// Concurrent calls for the same section share a single [`workspace/configuration`] request.
+    ///
+    /// The shared request is not linked to the request being handled by any of the callers: if
+    /// the client cancels a request whose handler is waiting for a section, only that call returns
+    /// an error, while the others keep waiting. The request to the client is canceled once no
+    /// caller is waiting for its response anymore.
+    ///
+    /// [`invalidate_configuration`]: Client::invalidate_configuration
+    /// [`LspServiceBuilder::configuration_ttl`]: crate::LspServiceBuilder::configuration_ttl
+    /// [`workspace/configuration`]: https://microsoft.github.io/language-server-protocol/specification#workspace_configuration
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # use lspower::{jsonrpc::Result, lsp::Url, Client};
+    /// # use serde::Deserialize;
+    /// #[derive(Deserialize)]
+    /// struct Settings {
+    ///     max_problems: usize,
+    /// }
+    ///
+    /// # async fn max_problems(client: &Client, uri: Url) -> Result<usize> {
+    /// let settings: Settings = client.configuration_scoped(Some(uri), "example").await?;
+    /// # Ok(settings.max_problems)
+    /// # }
+    /// ```
+    ///
+    /// # Initialization
+    ///
+    /// If the request is sent to client before the server has been initialized, this will
+    /// immediately return `Err` with JSON-RPC error code `-32002` ([read more]).
+    ///
+    /// [read more]: https://microsoft.github.io/language-server-protocol/specification#initialize
+    pub async fn configuration_scoped<T>(&self, scope_uri: Option<lsp::Url>, section: &str) -> crate::jsonrpc::Result<T>
+    where
+        T: serde::de::DeserializeOwned,
+    {
+        let clock = &self.inner.options.clock;
+        let ttl = self.inner.options.configuration_ttl;
+        let section = (scope_uri, section.to_owned());
+        let fetch = self.inner.configuration.get_or_fetch(&section, clock.now(), ttl, || {
+            let client = self.dynamic();
+            let items = vec![lsp::ConfigurationItem {
+                scope_uri: section.0.clone(),
+                section: Some(section.1.clone()),
+            }];
+            let fetch = async move { Ok(client.configuration(items).await?.pop().unwrap_or_default()) };
+            crate::context::detached(fetch).boxed().shared()
+        });
+
+        let value = match fetch {
+            Ok(value) => value,
+            Err(fetch) => {
+                let (token, context) = (CancellationToken::default(), crate::RequestContext::current());
+                let result = select! {
+                    _ = cancelled(&token, context.as_ref()).fuse() => {
+                        return Err(crate::jsonrpc::Error::request_cancelled());
+                    },
+                    result = fetch.clone().fuse() => result,
+                };
+                self.inner.configuration.complete(&section, &fetch, &result, clock.now());
+                result?
+            },
+        };
+
+        serde_json::from_value(value).map_err(|e| crate::jsonrpc::Error::parse_error().with_message(e.to_string()))
+    }
+
     /// Requests a workspace resource be edited on the client side and returns whether the edit was
     /// applied.
     ///
@@ -330,6 +931,61 @@ impl Client {
         self.send_request_initialized::<lsp::request::ApplyWorkspaceEdit>(params, token).await
     }
 
+    /// Requests a workspace resource be edited on the client side like [`apply_edit`], but first
+    /// adapts the edit to the workspace edit capabilities declared by the client.
+    ///
+    /// If the client does not support `documentChanges`, text document edits are sent as plain
+    /// `changes` instead, dropping their versions and change annotations.
+    ///
+    /// [`apply_edit`]: Client::apply_edit
+    ///
+    /// # Initialization
+    ///
+    /// If the request is sent to client before the server has been initialized, this will
+    /// immediately return `Err` with JSON-RPC error code `-32002` ([read more]).
+    ///
+    /// [read more]: https://microsoft.github.io/language-server-protocol/specification#initialize
+    ///
+    /// # Resource Operations
+    ///
+    /// If the edit creates, renames or deletes files in ways the client declared no support for,
+    /// no request is sent and this returns an [`UnsupportedResourceOperations`] error converted
+    /// into a JSON-RPC error with code `-32600` (invalid request), listing the unsupported
+    /// operations in its data.
+    pub async fn apply_edit_checked(
+        &self,
+        edit: lsp::WorkspaceEdit,
+        label: Option<String>,
+    ) -> crate::jsonrpc::Result<lsp::ApplyWorkspaceEditResponse> {
+        let edit = match self.client_capabilities() {
+            Some(capabilities) => workspace_edit::prepare(edit, &capabilities)?,
+            None => edit,
+        };
+        self.apply_edit(edit, label).await
+    }
+
+    /// Asks the client to refresh all inlay hints currently shown in its editors.
+    ///
+    /// This corresponds to the [`workspace/inlayHint/refresh`] request.
+    ///
+    /// [`workspace/inlayHint/refresh`]: https://microsoft.github.io/language-server-protocol/specifications/specification-3-17/#workspace_inlayHint_refresh
+    ///
+    /// # Initialization
+    ///
+    /// If the request is sent to client before the server has been initialized, this will
+    /// immediately return `Err` with JSON-RPC error code `-32002` ([read more]).
+    ///
+    /// [read more]: https://microsoft.github.io/language-server-protocol/specification#initialize
+    ///
+    /// # Compatibility
+    ///
+    /// This request is only available with the `proposed` crate feature enabled.
+    #[cfg(feature = "proposed")]
+    pub async fn inlay_hint_refresh(&self) -> crate::jsonrpc::Result<()> {
+        let token = CancellationToken::default();
+        self.send_request_initialized::<lsp::request::InlayHintRefreshRequest>((), token).await
+    }
+
     /// Submits validation diagnostics for an open file with the given URI.
     ///
     /// This corresponds to the [`textDocument/publishDiagnostics`] notification.
@@ -357,34 +1013,84 @@ impl Client {
         self.send_notification_initialized::<N>(params).await;
     }
 
-    async fn send_notification<N>(&self, params: N::Params)
-    where
-        N: lsp::notification::Notification,
-    {
-        let mut sender = self.inner.sender.clone();
-        let message = crate::jsonrpc::Outgoing::Request(crate::jsonrpc::ClientRequest::notification::<N>(params));
-        if sender.send(message).await.is_err() {
-            log::error!("failed to send notification")
-        }
-    }
-
-    async fn send_notification_initialized<N>(&self, params: N::Params)
+    /// Reports the result of a request in chunks, as partial results, and returns the final result.
+    ///
+    /// If the client passed a `partialResultToken` with the request, each chunk produced by
+    /// `results` is sent to the client as a partial result in a `$/progress` notification. As the
+    /// specification requires, the final result returned afterwards is empty then. Otherwise, the
+    /// chunks are collected into the final result. This allows handlers of requests with large
+    /// results, such as `workspace/symbol` in huge repositories, to report results while they are
+    /// still being computed.
+    ///
+    /// If the client cancels the request being handled by the caller, no further chunks are taken
+    /// from `results` and [`Error::request_cancelled`] is returned.
+    ///
+    /// [`Error::request_cancelled`]: crate::jsonrpc::Error::request_cancelled
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # use lspower::{jsonrpc::Result, lsp::*, Client};
+    /// # use futures::stream::{self, Stream};
+    /// # fn search(_: &str) -> impl Stream<Item = Vec<SymbolInformation>> { stream::empty() }
+    /// # type Response = Result<Option<Vec<SymbolInformation>>>;
+    /// async fn symbol(client: &Client, params: WorkspaceSymbolParams) -> Response {
+    ///     let token = params.partial_result_params.partial_result_token;
+    ///     let symbols = client.send_partial_results(token, search(&params.query)).await?;
+    ///     Ok(Some(symbols))
+    /// }
+    /// ```
+    ///
+    /// # Initialization
+    ///
+    /// Partial results will only be sent if the server is initialized.
+    pub async fn send_partial_results<T, R>(
+        &self,
+        token: Option<lsp::ProgressToken>,
+        results: R,
+    ) -> crate::jsonrpc::Result<Vec<T>>
     where
-        N: lsp::notification::Notification,
+        T: serde::Serialize,
+        R: Stream<Item = Vec<T>>,
     {
-        if let crate::server::StateKind::Initialized | crate::server::StateKind::ShutDown = self.inner.state.get() {
-            self.send_notification::<N>(params).await;
-        } else {
-            let msg = crate::jsonrpc::ClientRequest::notification::<N>(params);
-            log::trace!("server not initialized, supressing message: {}", msg);
+        let (cancel, context) = (CancellationToken::default(), crate::RequestContext::current());
+        let results = results.fuse();
+        futures::pin_mut!(results);
+
+        let mut collected = Vec::new();
+        loop {
+            let chunk = select! {
+                _ = cancelled(&cancel, context.as_ref()).fuse() => {
+                    return Err(crate::jsonrpc::Error::request_cancelled());
+                },
+                chunk = results.next() => chunk,
+            };
+            match (chunk, &token) {
+                (Some(chunk), _) if chunk.is_empty() => {},
+                (Some(chunk), Some(token)) => {
+                    let value = serde_json::to_value(chunk).map_err(|e| {
+                        crate::jsonrpc::Error::internal_error().with_message(e.to_string())
+                    })?;
+                    let params = PartialResultParams { token: token.clone(), value };
+                    self.send_notification_initialized::<PartialResult>(params).await;
+                },
+                (Some(chunk), None) => collected.extend(chunk),
+                (None, _) => return Ok(collected),
+            }
         }
     }
 
     /// Sends a custom request to the client.
     ///
+    /// The request is canceled with `$/cancelRequest` once the given token is cancelled, or, if
+    /// enabled with [`LspServiceBuilder::cancel_dropped_requests`], if the returned future is
+    /// dropped before the response arrived.
+    ///
     /// # Initialization
     ///
     /// This request will only be sent if the server is initialized.
+    ///
+    /// [`LspServiceBuilder::cancel_dropped_requests`]: crate::LspServiceBuilder::cancel_dropped_requests
     pub async fn send_custom_request<R>(
         &self,
         params: R::Params,
@@ -395,46 +1101,185 @@ impl Client {
     {
         self.send_request_initialized::<R>(params, token).await
     }
+}
+
+impl<S: ClientState> Client<S> {
+    async fn send_notification<N>(&self, params: N::Params)
+    where
+        N: lsp::notification::Notification,
+    {
+        let message = crate::jsonrpc::ClientRequest::notification::<N>(params);
+        let limiter = match &self.inner.rate_limiter {
+            Some(limiter) => limiter,
+            None => return self.send_notification_message(message).await,
+        };
+
+        let clock = self.clock();
+        let admission = limiter.lock().unwrap().admit(message, clock.now());
+        match admission {
+            Admission::Send(message) => self.send_notification_message(message).await,
+            Admission::Delay(message, wait) => {
+                clock.sleep(wait).await;
+                self.send_notification_message(message).await;
+            },
+            Admission::Dropped => log::trace!("rate limit exceeded, dropping {:?} notification", N::METHOD),
+            Admission::Coalesced { schedule: None } => {},
+            Admission::Coalesced { schedule: Some(wait) } => {
+                let client = self.clone();
+                let sleep = clock.sleep(wait);
+                self.spawn_background(async move {
+                    sleep.await;
+                    while let Some(limiter) = &client.inner.rate_limiter {
+                        let pending = limiter.lock().unwrap().take_pending(N::METHOD, clock.now());
+                        let (message, schedule) = match pending {
+                            Some(pending) => pending,
+                            None => break,
+                        };
+                        client.send_notification_message(message).await;
+                        match schedule {
+                            Some(wait) => clock.sleep(wait).await,
+                            None => break,
+                        }
+                    }
+                });
+            },
+        }
+    }
+
+    /// Sends a request whose type is only known at runtime, e.g. because another framework sent
+    /// it, without retrying it.
+    #[cfg(feature = "tower-lsp-compat")]
+    pub(crate) async fn send_raw_request(
+        &self,
+        method: &'static str,
+        params: serde_json::Value,
+    ) -> crate::jsonrpc::Result<serde_json::Value> {
+        let token = CancellationToken::default();
+        self.send_request_once(method, params, &token, None).await
+    }
+
+    /// Sends a notification whose type is only known at runtime, bypassing the rate limit.
+    #[cfg(feature = "tower-lsp-compat")]
+    pub(crate) async fn send_raw_notification(
+        &self,
+        method: std::borrow::Cow<'static, str>,
+        params: serde_json::Value,
+    ) {
+        let message = crate::jsonrpc::ClientRequest::notification_raw(method, params);
+        self.send_notification_message(message).await
+    }
+
+    async fn send_notification_message(&self, message: crate::jsonrpc::ClientRequest) {
+        if self.inner.send(crate::jsonrpc::Outgoing::Request(message)).await.is_err() {
+            log::error!("failed to send notification")
+        }
+    }
+
+    async fn send_notification_initialized<N>(&self, params: N::Params)
+    where
+        N: lsp::notification::Notification,
+    {
+        if let crate::server::StateKind::Initialized | crate::server::StateKind::ShutDown = self.inner.state.get() {
+            self.send_notification::<N>(params).await;
+        } else {
+            let msg = crate::jsonrpc::ClientRequest::notification::<N>(params);
+            log::trace!("server not initialized, supressing message: {}", msg);
+        }
+    }
 
     async fn send_request<R>(&self, params: R::Params, token: CancellationToken) -> crate::jsonrpc::Result<R::Result>
     where
         R: lsp::request::Request,
     {
-        let id = self.inner.request_id.fetch_add(1, Ordering::Relaxed);
-        let message = crate::jsonrpc::Outgoing::Request(crate::jsonrpc::ClientRequest::request::<R>(id, params));
+        let call = ClientCall {
+            method: R::METHOD,
+            params: crate::jsonrpc::lsp_value(params),
+            token,
+            context: crate::RequestContext::current(),
+        };
+        let mut service = self.inner.retry.layer(SendRequest(self.dynamic()));
+        let result = match future::poll_fn(|cx| service.poll_ready(cx)).await {
+            Ok(()) => service.call(call).await,
+            Err(error) => Err(error),
+        };
+
+        result.and_then(|v| {
+            serde_json::from_value(v).map_err(|e| crate::jsonrpc::Error::parse_error().with_message(e.to_string()))
+        })
+    }
+
+    async fn send_request_once(
+        &self,
+        method: &'static str,
+        params: serde_json::Value,
+        token: &CancellationToken,
+        context: Option<&crate::RequestContext>,
+    ) -> crate::jsonrpc::Result<serde_json::Value> {
+        if let Some(headless) = &self.inner.options.headless {
+            log::trace!("answering {:?} request in headless mode", method);
+            return headless.respond_to(method, &params);
+        }
 
-        let response_waiter = self.inner.pending_requests.wait(crate::jsonrpc::Id::Number(id));
+        if let Some(error) = self.inner.pending_requests.closed() {
+            log::debug!("server shut down, failing {:?} request", method);
+            return Err(error);
+        }
+
+        let (id, response_waiter) = self.next_request_id(method)?;
+        let request = crate::jsonrpc::ClientRequest::request_raw(method.into(), id, params);
+        let message = crate::jsonrpc::Outgoing::Request(request);
 
-        if self.inner.sender.clone().send(message).await.is_err() {
+        let _guard = CancelOnDrop { inner: &self.inner, id };
+
+        if self.inner.send(message).await.is_err() {
             log::error!("failed to send request");
             return Err(crate::jsonrpc::Error::internal_error());
         }
 
         select! {
-            _ = token.wait() => {
-                if self.inner.pending_requests.0.remove(&crate::jsonrpc::Id::Number(id)).is_none() {
-                    log::warn!("received response with unknown request ID: {}", id);
-                }
-                let params = {
-                    let id = i32::try_from(id).expect("error converting u64 to i32");
-                    lsp::CancelParams { id: lsp::NumberOrString::Number(id) }
-                };
-                self.send_notification::<lsp::notification::Cancel>(params).await;
+            _ = cancelled(token, context).fuse() => {
+                self.inner.pending_requests.forget(&crate::jsonrpc::Id::Number(id));
+                self.send_notification::<lsp::notification::Cancel>(cancel_params(id)).await;
                 Err(crate::jsonrpc::Error::request_cancelled())
             },
             response = response_waiter.fuse() => {
                 let (_, result) = response.into_parts();
-                result.and_then(|v| {
-                    serde_json::from_value(v).map_err(|e| crate::jsonrpc::Error {
-                        code: crate::jsonrpc::ErrorCode::ParseError,
-                        message: e.to_string(),
-                        data: None,
-                    })
-                })
+                result
             },
         }
     }
 
+    /// Allocates the ID of a request and marks it as pending, skipping IDs of requests which are
+    /// still pending once the IDs wrapped around.
+    ///
+    /// Since each skipped ID belongs to a pending request, a free ID is found unless the allocator
+    /// keeps handing out pending IDs, in which case the request fails.
+    fn next_request_id(
+        &self,
+        method: &'static str,
+    ) -> crate::jsonrpc::Result<(u64, impl Future<Output = crate::jsonrpc::Response> + Send + 'static)> {
+        let pending = &self.inner.pending_requests;
+        for _ in 0 ..= pending.len() {
+            let id = self.inner.options.ids.next_id();
+            match pending.wait(crate::jsonrpc::Id::Number(id), method) {
+                Some(response_waiter) => return Ok((id, response_waiter)),
+                None => log::debug!("request ID {} is still pending, skipping it", id),
+            }
+        }
+
+        log::error!("no request ID is available for {:?} request", method);
+        Err(crate::jsonrpc::Error::internal_error().with_message("request IDs exhausted"))
+    }
+
+    /// Checks that the client declared support for requests of type `R`, once its capabilities
+    /// are known.
+    fn check_request<R: lsp::request::Request>(&self) -> Result<(), UnsupportedByClient> {
+        if self.inner.state.get() == crate::server::StateKind::Initialized {
+            self.inner.capabilities.lock().unwrap().check_request(R::METHOD)?;
+        }
+        Ok(())
+    }
+
     async fn send_request_initialized<R>(
         &self,
         params: R::Params,
@@ -446,18 +1291,123 @@ impl Client {
         if let crate::server::StateKind::Initialized | crate::server::StateKind::ShutDown = self.inner.state.get() {
             self.send_request::<R>(params, token).await
         } else {
-            let id = self.inner.request_id.load(Ordering::SeqCst) + 1;
-            let msg = crate::jsonrpc::ClientRequest::request::<R>(id, params);
-            log::trace!("server not initialized, supressing message: {}", msg);
+            log::trace!("server not initialized, supressing {:?} request", R::METHOD);
             Err(crate::jsonrpc::not_initialized_error())
         }
     }
 }
 
-impl Debug for Client {
+/// The send path of requests to the client, which sends each request once.
+#[derive(Clone, Debug)]
+struct SendRequest(Client);
+
+impl Service<ClientCall> for SendRequest {
+    type Response = serde_json::Value;
+    type Error = crate::jsonrpc::Error;
+    type Future = BoxFuture<'static, crate::jsonrpc::Result<serde_json::Value>>;
+
+    fn poll_ready(&mut self, _: &mut Context) -> Poll<crate::jsonrpc::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, call: ClientCall) -> Self::Future {
+        let client = self.0.clone();
+        async move {
+            let context = call.context.as_ref();
+            client.send_request_once(call.method, call.params, &call.token, context).await
+        }
+        .boxed()
+    }
+}
+
+/// Resolves once the given token is cancelled, or once the request being handled by the caller is
+/// cancelled by the client.
+async fn cancelled(token: &CancellationToken, context: Option<&crate::RequestContext>) {
+    let linked = async {
+        match context {
+            // The token of a request context also resolves once its handler finished, in which case
+            // requests sent on its behalf from spawned tasks are not canceled.
+            Some(context) if context.token().wait().await.is_ok() => {},
+            _ => future::pending().await,
+        }
+    };
+    futures::pin_mut!(linked);
+    future::select(token.wait(), linked).await;
+}
+
+/// A `$/progress` notification reporting a partial result, whose value is a chunk of the result.
+enum PartialResult {}
+
+impl lsp::notification::Notification for PartialResult {
+    type Params = PartialResultParams;
+
+    const METHOD: &'static str = "$/progress";
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+struct PartialResultParams {
+    token: lsp::ProgressToken,
+    value: serde_json::Value,
+}
+
+fn cancel_params(id: u64) -> lsp::CancelParams {
+    // IDs beyond the range of the protocol can only be handed out by a misbehaving allocator.
+    let id = match i32::try_from(id) {
+        Ok(id) => lsp::NumberOrString::Number(id),
+        Err(_) => {
+            log::error!("request ID {} exceeds `i32::MAX`, the client may fail to cancel it", id);
+            lsp::NumberOrString::String(id.to_string())
+        },
+    };
+    lsp::CancelParams { id }
+}
+
+/// Ranks message types from the most severe, `ERROR`, to the least severe, `LOG`, which unknown
+/// types are treated like.
+fn severity(typ: lsp::MessageType) -> u8 {
+    match typ {
+        lsp::MessageType::ERROR => 0,
+        lsp::MessageType::WARNING => 1,
+        lsp::MessageType::INFO => 2,
+        _ => 3,
+    }
+}
+
+/// Cancels a request to the client if the future waiting for its response is dropped before the
+/// response arrived, e.g. because the request handler which sent it was canceled or because the
+/// caller stopped waiting for it in a `select!`.
+///
+/// The request is always forgotten, but `$/cancelRequest` is only sent if enabled with
+/// [`LspServiceBuilder::cancel_dropped_requests`].
+///
+/// [`LspServiceBuilder::cancel_dropped_requests`]: crate::LspServiceBuilder::cancel_dropped_requests
+struct CancelOnDrop<'a> {
+    inner: &'a ClientInner,
+    id: u64,
+}
+
+impl Drop for CancelOnDrop<'_> {
+    fn drop(&mut self) {
+        let id = crate::jsonrpc::Id::Number(self.id);
+        if !self.inner.pending_requests.forget(&id) {
+            return;
+        }
+        if !self.inner.options.cancel_dropped_requests {
+            log::debug!("request {} was dropped before receiving a response, forgetting it", id);
+            return;
+        }
+
+        log::debug!("request {} was dropped before receiving a response, canceling it", id);
+        let params = cancel_params(self.id);
+        let message = crate::jsonrpc::ClientRequest::notification::<lsp::notification::Cancel>(params);
+        self.inner.try_send(crate::jsonrpc::Outgoing::Request(message));
+    }
+}
+
+impl<S> Debug for Client<S> {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         f.debug_struct(stringify!(Client))
-            .field("request_id", &self.inner.request_id)
+            .field("ids", &self.inner.options.ids)
             .field("pending_requests", &self.inner.pending_requests)
             .field("state", &self.inner.state)
             .finish()
@@ -493,10 +1443,18 @@ mod tests {
             use futures::channel::mpsc;
 
             pub(super) fn client(initialize: bool) -> (Client, mpsc::Receiver<Outgoing>) {
+                client_with_options(initialize, Default::default())
+            }
+
+            pub(super) fn client_with_options(
+                initialize: bool,
+                options: ClientOptions,
+            ) -> (Client, mpsc::Receiver<Outgoing>) {
                 let state = Arc::new(crate::server::State::new());
                 let (tx, rx) = mpsc::channel(4);
                 let pending_client = Arc::new(crate::jsonrpc::ClientRequests::new());
-                let client = crate::client::Client::new(tx, pending_client, state);
+                let tasks = Arc::new(crate::task::BackgroundTasks::new(None, options.clock.clone()));
+                let client = crate::client::Client::new(tx, pending_client, state, options, tasks);
                 if initialize {
                     client.inner.state.set(crate::server::StateKind::Initialized);
                 }
@@ -548,12 +1506,80 @@ mod tests {
             Ok(())
         }
 
+        #[tokio::test]
+        async fn headless() {
+            let headless = HeadlessClient::new()
+                .select_first_action(true)
+                .configuration("example", json!({ "enabled": true }));
+            let options = ClientOptions {
+                headless: Some(headless),
+                ..Default::default()
+            };
+            let (client, mut rx) = helper::client_with_options(true, options);
+
+            let typ = lsp::MessageType::INFO;
+            let actions = vec![lsp::MessageActionItem {
+                title: "Reload".into(),
+                properties: Default::default(),
+            }];
+            let result = client.show_message_request(typ, "Reload?", Some(actions.clone())).await;
+            assert_eq!(result, Ok(actions.into_iter().next()));
+
+            let items = vec![lsp::ConfigurationItem {
+                scope_uri: None,
+                section: Some("example".into()),
+            }];
+            assert_eq!(client.configuration(items).await, Ok(vec![json!({ "enabled": true })]));
+            assert!(rx.try_recv().is_err());
+        }
+
         #[test]
         fn display() {
             let client = helper::client(true).0;
             let _ = format!("{:?}", client);
         }
 
+        #[tokio::test]
+        async fn request_ids() {
+            let options = ClientOptions {
+                ids: Arc::new(IdRange::new(1_000 .. 2_000)),
+                ..Default::default()
+            };
+            let (client, mut rx) = helper::client_with_options(true, options);
+
+            let req = client.workspace_folders();
+            let rsp = async {
+                let message = serde_json::to_value(rx.next().await.unwrap()).unwrap();
+                assert_eq!(message["id"], 1_000);
+                client.inner.pending_requests.insert(Response::ok(Id::Number(1_000), json!(null)));
+            };
+            let (result, ()) = futures::future::join(req, rsp).await;
+            assert_eq!(result, Ok(None));
+        }
+
+        #[tokio::test]
+        async fn request_ids_skip_pending() {
+            let options = ClientOptions {
+                ids: Arc::new(IdRange::new(5 .. 7)),
+                ..Default::default()
+            };
+            let (client, mut rx) = helper::client_with_options(true, options);
+            let _first = client.inner.pending_requests.wait(Id::Number(5), "custom/request").unwrap();
+
+            let req = client.workspace_folders();
+            let rsp = async {
+                let message = serde_json::to_value(rx.next().await.unwrap()).unwrap();
+                assert_eq!(message["id"], 6);
+                assert!(client.inner.pending_requests.wait(Id::Number(6), "custom/request").is_none());
+
+                let exhausted = client.workspace_folders().await.unwrap_err();
+                assert_eq!(exhausted.message, "request IDs exhausted");
+                client.inner.pending_requests.insert(Response::ok(Id::Number(6), json!(null)));
+            };
+            let (result, ()) = futures::future::join(req, rsp).await;
+            assert_eq!(result, Ok(None));
+        }
+
         #[test]
         fn new() {
             let client = helper::client(false).0;
@@ -612,6 +1638,213 @@ mod tests {
             Ok(())
         }
 
+        #[tokio::test]
+        async fn register_capability_dynamic() -> anyhow::Result<()> {
+            let (client, _rx) = helper::client(true);
+            let capabilities = serde_json::from_value(json!({
+                "textDocument": { "hover": { "dynamicRegistration": true } },
+            }))?;
+            client.set_client_capabilities(capabilities, None);
+
+            let req = {
+                let registrations = vec![lsp::Registration {
+                    id: "hover".into(),
+                    method: "textDocument/hover".into(),
+                    register_options: None,
+                }];
+                client.register_capability(registrations)
+            };
+            let rsp = async {
+                let id = Id::Number(0);
+                let result = serde_json::to_value(()).unwrap();
+                client.inner.pending_requests.insert(Response::ok(id, result));
+            };
+            let (result, ()) = futures::future::join(req, rsp).await;
+            assert_eq!(result, Ok(()));
+            assert_eq!(client.registrations().len(), 1);
+
+            Ok(())
+        }
+
+        #[tokio::test]
+        async fn register_capability_unsupported() {
+            let (client, mut rx) = helper::client(true);
+            let registrations = vec![lsp::Registration {
+                id: "formatting".into(),
+                method: "textDocument/formatting".into(),
+                register_options: None,
+            }];
+            let result = client.register_capability(registrations).await;
+            let error = result.unwrap_err();
+            assert_eq!(error.code, crate::jsonrpc::ErrorCode::InvalidRequest);
+            assert_eq!(error.data, Some(json!({ "method": "textDocument/formatting" })));
+            assert!(rx.try_recv().is_err());
+            assert!(client.registrations().is_empty());
+        }
+
+        #[tokio::test]
+        async fn apply_edit_checked_unsupported() -> anyhow::Result<()> {
+            let (client, mut rx) = helper::client(true);
+            let capabilities = serde_json::from_value(json!({
+                "workspace": { "workspaceEdit": { "documentChanges": true, "resourceOperations": ["create"] } },
+            }))?;
+            client.set_client_capabilities(capabilities, None);
+
+            let edit = lsp::WorkspaceEdit {
+                document_changes: Some(lsp::DocumentChanges::Operations(vec![lsp::DocumentChangeOperation::Op(
+                    lsp::ResourceOp::Delete(lsp::DeleteFile {
+                        uri: lsp::Url::parse("file:///a.rs")?,
+                        options: None,
+                    }),
+                )])),
+                ..Default::default()
+            };
+            let error = client.apply_edit_checked(edit, None).await.unwrap_err();
+            assert_eq!(error.code, crate::jsonrpc::ErrorCode::InvalidRequest);
+            assert_eq!(error.data, Some(json!({ "operations": ["delete"] })));
+            assert!(rx.try_recv().is_err());
+
+            Ok(())
+        }
+
+        #[tokio::test]
+        async fn show_document() -> anyhow::Result<()> {
+            let (client, _rx) = helper::client(true);
+            let capabilities = serde_json::from_value(json!({
+                "window": { "showDocument": { "support": true } },
+            }))?;
+            client.set_client_capabilities(capabilities, None);
+
+            let req = {
+                let params = lsp::ShowDocumentParams {
+                    uri: lsp::Url::parse("file:///a.rs")?,
+                    external: None,
+                    take_focus: Some(true),
+                    selection: None,
+                };
+                client.show_document(params)
+            };
+            let rsp = async {
+                let id = Id::Number(0);
+                let result = json!({ "success": true });
+                client.inner.pending_requests.insert(Response::ok(id, result));
+            };
+            let (result, ()) = futures::future::join(req, rsp).await;
+            assert_eq!(result, Ok(true));
+
+            Ok(())
+        }
+
+        #[tokio::test]
+        async fn create_work_done_progress() -> anyhow::Result<()> {
+            let (client, mut rx) = helper::client(false);
+            let token = lsp::ProgressToken::String("indexing".into());
+            let result = client.create_work_done_progress(token.clone()).await;
+            assert_eq!(result, Err(ClientRequestError::Failed(crate::jsonrpc::not_initialized_error())));
+            assert!(rx.try_recv().is_err());
+
+            client.inner.state.set(crate::server::StateKind::Initialized);
+            let capabilities = serde_json::from_value(json!({ "window": { "workDoneProgress": true } }))?;
+            client.set_client_capabilities(capabilities, None);
+            let rsp = async {
+                client.inner.pending_requests.insert(Response::ok(Id::Number(0), serde_json::Value::Null));
+            };
+            let (result, ()) = futures::future::join(client.create_work_done_progress(token), rsp).await;
+            assert_eq!(result, Ok(()));
+
+            let request = rx.try_recv()?.into_message::<serde_json::Value>()?;
+            assert_eq!(request["method"], "window/workDoneProgress/create");
+            assert_eq!(request["params"], json!({ "token": "indexing" }));
+
+            Ok(())
+        }
+
+        #[tokio::test]
+        async fn work_done_progress_create() -> anyhow::Result<()> {
+            let (client, mut rx) = helper::client(false);
+            let token = lsp::ProgressToken::Number(1);
+            let result = client.work_done_progress_create(token.clone()).await;
+            assert_eq!(result, Err(ClientRequestError::Failed(crate::jsonrpc::not_initialized_error())));
+            assert!(rx.try_recv().is_err());
+
+            client.inner.state.set(crate::server::StateKind::Initialized);
+            let result = client.work_done_progress_create(token.clone()).await.unwrap_err();
+            assert!(matches!(result, ClientRequestError::Unsupported(_)));
+            assert!(rx.try_recv().is_err());
+
+            let capabilities = serde_json::from_value(json!({ "window": { "workDoneProgress": true } }))?;
+            client.set_client_capabilities(capabilities, None);
+            let rsp = async {
+                client.inner.pending_requests.insert(Response::ok(Id::Number(0), serde_json::Value::Null));
+            };
+            let (result, ()) = futures::future::join(client.work_done_progress_create(token), rsp).await;
+            assert_eq!(result, Ok(()));
+
+            let request = rx.try_recv()?.into_message::<serde_json::Value>()?;
+            assert_eq!(request["method"], "window/workDoneProgress/create");
+            assert_eq!(request["params"], json!({ "token": 1 }));
+
+            Ok(())
+        }
+
+        #[tokio::test]
+        async fn create_work_done_progress_unsupported() {
+            let (client, mut rx) = helper::client(true);
+            client.set_client_capabilities(Default::default(), None);
+
+            let token = lsp::ProgressToken::String("indexing".into());
+            let error = match client.create_work_done_progress(token).await {
+                Err(ClientRequestError::Unsupported(error)) => error,
+                result => panic!("unexpected result: {:?}", result),
+            };
+            assert_eq!(error.method(), "window/workDoneProgress/create");
+            assert_eq!(error.capability(), "window.workDoneProgress");
+
+            let error = crate::jsonrpc::Error::from(ClientRequestError::from(error));
+            assert_eq!(error.code, crate::jsonrpc::ErrorCode::InvalidRequest);
+            let data = json!({ "method": "window/workDoneProgress/create", "capability": "window.workDoneProgress" });
+            assert_eq!(error.data, Some(data));
+            assert!(rx.try_recv().is_err());
+        }
+
+        #[tokio::test]
+        async fn retry_policy() {
+            use std::time::Duration;
+
+            let mut options = ClientOptions::default();
+            let policy = RetryPolicy::new(2).backoff(Duration::from_millis(1));
+            options.retry_policies.insert("workspace/workspaceFolders", policy);
+            let (client, _rx) = helper::client_with_options(true, options);
+
+            let req = client.workspace_folders();
+            let rsp = async {
+                let error = crate::jsonrpc::Error::content_modified();
+                client.inner.pending_requests.insert(Response::error(Some(Id::Number(0)), error));
+                while !client.inner.pending_requests.0.contains_key(&Id::Number(1)) {
+                    tokio::time::sleep(Duration::from_millis(1)).await;
+                }
+                let result = serde_json::to_value(None::<Vec<lsp::WorkspaceFolder>>).unwrap();
+                client.inner.pending_requests.insert(Response::ok(Id::Number(1), result));
+            };
+            let (result, ()) = futures::future::join(req, rsp).await;
+            assert_eq!(result, Ok(None));
+        }
+
+        #[tokio::test]
+        async fn retry_policy_exhausted() {
+            let mut options = ClientOptions::default();
+            options.retry_policies.set_default(RetryPolicy::new(1));
+            let (client, _rx) = helper::client_with_options(true, options);
+
+            let req = client.workspace_folders();
+            let rsp = async {
+                let error = crate::jsonrpc::Error::content_modified();
+                client.inner.pending_requests.insert(Response::error(Some(Id::Number(0)), error));
+            };
+            let (result, ()) = futures::future::join(req, rsp).await;
+            assert_eq!(result, Err(crate::jsonrpc::Error::content_modified()));
+        }
+
         #[tokio::test]
         async fn send_notification_initialized_when_uninitialized() {
             let (client, _rx) = helper::client(false);
@@ -761,6 +1994,129 @@ mod tests {
             assert_eq!(result, Err(crate::jsonrpc::Error::request_cancelled()));
         }
 
+        #[tokio::test]
+        async fn send_request_linked_to_request_context() {
+            let mut canceller = TokenCanceller::new();
+            let context = crate::RequestContext::new(Id::Number(7), "textDocument/hover".into(), canceller.token());
+
+            let (client, mut rx) = helper::client(true);
+            let req = context.scope(client.configuration(vec![]));
+            let cancel = async {
+                assert!(matches!(rx.next().await, Some(Outgoing::Request(_))));
+                canceller.cancel();
+            };
+
+            let (result, ()) = futures::future::join(req, cancel).await;
+            assert_eq!(result, Err(crate::jsonrpc::Error::request_cancelled()));
+            let params = cancel_params(0);
+            let message = ClientRequest::notification::<lsp::notification::Cancel>(params);
+            assert_eq!(rx.next().await, Some(Outgoing::Request(message)));
+        }
+
+        #[tokio::test]
+        async fn configuration_scoped() {
+            #[derive(Debug, serde::Deserialize, PartialEq)]
+            struct Settings {
+                enabled: bool,
+            }
+
+            let mut canceller = TokenCanceller::new();
+            let context = crate::RequestContext::new(Id::Number(7), "textDocument/hover".into(), canceller.token());
+
+            let (client, mut rx) = helper::client(true);
+            let canceled = context.scope(client.configuration_scoped::<Settings>(None, "example"));
+            let shared = client.configuration_scoped::<Settings>(None, "example");
+            futures::pin_mut!(canceled, shared);
+            assert!(futures::poll!(&mut canceled).is_pending());
+            assert!(futures::poll!(&mut shared).is_pending());
+            assert!(matches!(rx.next().await, Some(Outgoing::Request(_))));
+
+            // Canceling one caller keeps the request alive for the other one.
+            canceller.cancel();
+            assert_eq!(canceled.await, Err(crate::jsonrpc::Error::request_cancelled()));
+            let result = json!([{ "enabled": true }]);
+            client.inner.pending_requests.insert(Response::ok(Id::Number(0), result));
+            assert_eq!(shared.await, Ok(Settings { enabled: true }));
+
+            let cached = client.configuration_scoped::<Settings>(None, "example").await;
+            assert_eq!(cached, Ok(Settings { enabled: true }));
+            assert!(rx.try_recv().is_err());
+
+            client.invalidate_configuration();
+            let refetch = client.configuration_scoped::<Settings>(None, "example");
+            futures::pin_mut!(refetch);
+            assert!(futures::poll!(&mut refetch).is_pending());
+            assert!(matches!(rx.next().await, Some(Outgoing::Request(_))));
+        }
+
+        #[tokio::test]
+        async fn send_partial_results() {
+            use futures::stream;
+
+            let (client, mut rx) = helper::client(true);
+            let chunks = stream::iter(vec![vec![1, 2], vec![], vec![3]]);
+            assert_eq!(client.send_partial_results(None, chunks.clone()).await, Ok(vec![1, 2, 3]));
+            assert!(rx.try_recv().is_err());
+
+            let token = lsp::ProgressToken::String("symbols".into());
+            let results = client.send_partial_results(Some(token), chunks);
+            let (result, messages) = futures::future::join(results, rx.by_ref().take(2).collect::<Vec<_>>()).await;
+            assert_eq!(result, Ok(Vec::<i32>::new()));
+            let values: Vec<_> = messages
+                .into_iter()
+                .map(|message| serde_json::to_value(message).unwrap())
+                .map(|message| (message["method"].clone(), message["params"].clone()))
+                .collect();
+            assert_eq!(values, vec![
+                (json!("$/progress"), json!({ "token": "symbols", "value": [1, 2] })),
+                (json!("$/progress"), json!({ "token": "symbols", "value": [3] })),
+            ]);
+
+            let mut canceller = TokenCanceller::new();
+            let context = crate::RequestContext::new(Id::Number(7), "workspace/symbol".into(), canceller.token());
+            let chunks = stream::iter(vec![vec![1]]).chain(stream::pending());
+            let results = context.scope(client.send_partial_results(None, chunks));
+            futures::pin_mut!(results);
+            assert!(futures::poll!(&mut results).is_pending());
+            canceller.cancel();
+            assert_eq!(results.await, Err(crate::jsonrpc::Error::request_cancelled()));
+        }
+
+        #[tokio::test]
+        async fn dropped_request_is_canceled() {
+            let options = ClientOptions {
+                cancel_dropped_requests: true,
+                ..Default::default()
+            };
+            let (client, mut rx) = helper::client_with_options(true, options);
+            {
+                let req = client.configuration(vec![]);
+                futures::pin_mut!(req);
+                assert!(futures::poll!(req).is_pending());
+            }
+
+            assert!(matches!(rx.next().await, Some(Outgoing::Request(_))));
+            let params = cancel_params(0);
+            let message = ClientRequest::notification::<lsp::notification::Cancel>(params);
+            assert_eq!(rx.next().await, Some(Outgoing::Request(message)));
+            assert!(client.inner.pending_requests.0.is_empty());
+        }
+
+        #[tokio::test]
+        async fn dropped_request_without_cancellation() {
+            let (client, mut rx) = helper::client(true);
+            {
+                let token = CancellationToken::default();
+                let req = client.send_custom_request::<lsp::request::WorkspaceFoldersRequest>((), token);
+                futures::pin_mut!(req);
+                assert!(futures::poll!(req).is_pending());
+            }
+
+            assert!(matches!(rx.next().await, Some(Outgoing::Request(_))));
+            assert!(rx.try_recv().is_err());
+            assert!(client.inner.pending_requests.0.is_empty());
+        }
+
         #[tokio::test]
         async fn show_message() {
             let (client, mut rx) = helper::client(true);
@@ -806,6 +2162,84 @@ mod tests {
             }
         }
 
+        #[tokio::test]
+        async fn telemetry_event_batched() {
+            use std::time::Duration;
+
+            let notification =
+                |params| Outgoing::Request(ClientRequest::notification::<lsp::notification::TelemetryEvent>(params));
+
+            let policy = TelemetryPolicy::new().max_batch_size(2).flush_interval(Duration::from_secs(60));
+            let options = ClientOptions {
+                telemetry_policy: Some(policy),
+                ..Default::default()
+            };
+            let (client, mut rx) = helper::client_with_options(true, options);
+            client.telemetry_event(1u8).await;
+            client.telemetry_event(json!({ "event": 2 })).await;
+            assert_eq!(rx.next().await, Some(notification(json!([1, { "event": 2 }]))));
+            client.telemetry_event(3u8).await;
+            client.flush_telemetry().await;
+            assert_eq!(rx.next().await, Some(notification(json!([3]))));
+            client.flush_telemetry().await;
+            assert!(rx.try_recv().is_err());
+
+            let policy = TelemetryPolicy::new().flush_interval(Duration::from_millis(10));
+            let options = ClientOptions {
+                telemetry_policy: Some(policy),
+                ..Default::default()
+            };
+            let (client, mut rx) = helper::client_with_options(true, options);
+            client.telemetry_event(1u8).await;
+            client.telemetry_event(2u8).await;
+            assert_eq!(rx.next().await, Some(notification(json!([1, 2]))));
+        }
+
+        #[tokio::test]
+        async fn rate_limit_policy() {
+            use std::time::Duration;
+
+            let log = |message: &str| {
+                let typ = lsp::MessageType::LOG;
+                let params = lsp::LogMessageParams { typ, message: message.into() };
+                Outgoing::Request(ClientRequest::notification::<lsp::notification::LogMessage>(params))
+            };
+
+            let clock = crate::MockClock::new();
+            let limit = RateLimit::new(1, Duration::from_secs(1)).on_overflow(OverflowAction::Coalesce);
+            let options = ClientOptions {
+                rate_limit_policy: Some(RateLimitPolicy::new().default_limit(limit)),
+                clock: Arc::new(clock.clone()),
+                ..Default::default()
+            };
+            let (client, mut rx) = helper::client_with_options(true, options);
+            let typ = lsp::MessageType::LOG;
+            client.log_message(typ, "1").await;
+            client.log_message(typ, "2").await;
+            client.log_message(typ, "3").await;
+            assert_eq!(rx.next().await, Some(log("1")));
+            assert!(rx.try_recv().is_err());
+            clock.advance(Duration::from_secs(1));
+            assert_eq!(rx.next().await, Some(log("3")));
+        }
+
+        #[tokio::test]
+        async fn typed_state() {
+            let (client, mut rx) = helper::client(false);
+            let typed = client.typed().upgrade().unwrap_err();
+            typed.log_message(lsp::MessageType::INFO, "uninitialized").await;
+            assert!(rx.next().await.is_some());
+
+            client.inner.state.set(crate::server::StateKind::Initialized);
+            let typed = typed.upgrade().unwrap();
+            let uri = lsp::Url::parse("file:///main.rs").unwrap();
+            typed.publish_diagnostics(uri.clone(), vec![], None).await;
+            let params = lsp::PublishDiagnosticsParams::new(uri, vec![], None);
+            let message =
+                Outgoing::Request(ClientRequest::notification::<lsp::notification::PublishDiagnostics>(params));
+            assert_eq!(rx.next().await, Some(message));
+        }
+
         #[tokio::test]
         async fn unregister_capability() -> anyhow::Result<()> {
             let (client, _rx) = helper::client(true);
@@ -848,13 +2282,13 @@ mod tests {
         #[test]
         fn debug() {
             let canceller = TokenCanceller::new();
-            format!("{:?}", canceller);
+            let _ = format!("{:?}", canceller);
         }
 
         #[test]
         fn default() {
             let canceller = TokenCanceller::default();
-            format!("{:?}", canceller);
+            let _ = format!("{:?}", canceller);
         }
     }
 }
@@ -0,0 +1,145 @@
+//! Queueing of the messages the server sends before the transport polls the [`MessageStream`].
+//!
+//! [`MessageStream`]: crate::MessageStream
+
+use crate::jsonrpc::Outgoing;
+use futures::{
+    channel::mpsc,
+    stream::{FusedStream, Stream},
+};
+use std::{
+    collections::VecDeque,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+/// Messages sent by the [`Client`] before the transport attached, enabled with
+/// [`LspServiceBuilder::buffer_until_attached`].
+///
+/// The queue is replaced with `None` once the transport polls the [`Outbox`] for the first time,
+/// and later messages go through the channel as usual.
+///
+/// [`Client`]: crate::Client
+/// [`LspServiceBuilder::buffer_until_attached`]: crate::LspServiceBuilder::buffer_until_attached
+#[derive(Debug)]
+pub(crate) struct Backlog {
+    queue: Mutex<Option<VecDeque<Outgoing>>>,
+    high_water: usize,
+}
+
+impl Backlog {
+    /// Creates an empty backlog warning once it holds `high_water` messages.
+    pub(crate) fn new(high_water: usize) -> Self {
+        Backlog {
+            queue: Mutex::new(Some(VecDeque::new())),
+            high_water,
+        }
+    }
+
+    /// Queues the message if the transport did not attach yet, or returns it to be sent through
+    /// the channel otherwise.
+    pub(crate) fn push(&self, message: Outgoing) -> Option<Outgoing> {
+        let mut queue = self.queue.lock().unwrap();
+        let queue = match queue.as_mut() {
+            Some(queue) => queue,
+            None => return Some(message),
+        };
+        queue.push_back(message);
+        if queue.len() == self.high_water {
+            log::warn!(
+                "{} messages to the client are queued because the transport did not attach yet",
+                queue.len()
+            );
+        }
+        None
+    }
+
+    /// Marks the transport as attached, returning the queued messages.
+    fn attach(&self) -> VecDeque<Outgoing> {
+        self.queue.lock().unwrap().take().unwrap_or_default()
+    }
+}
+
+/// The receiving end of the messages from the [`Client`], which replays the [`Backlog`] when it is
+/// polled for the first time.
+///
+/// [`Client`]: crate::Client
+#[derive(Debug)]
+pub(crate) struct Outbox {
+    rx: mpsc::Receiver<Outgoing>,
+    backlog: Option<Arc<Backlog>>,
+    replay: VecDeque<Outgoing>,
+}
+
+impl Outbox {
+    pub(crate) fn new(rx: mpsc::Receiver<Outgoing>, backlog: Option<Arc<Backlog>>) -> Self {
+        Outbox {
+            rx,
+            backlog,
+            replay: VecDeque::new(),
+        }
+    }
+}
+
+impl From<mpsc::Receiver<Outgoing>> for Outbox {
+    fn from(rx: mpsc::Receiver<Outgoing>) -> Self {
+        Outbox::new(rx, None)
+    }
+}
+
+impl Stream for Outbox {
+    type Item = Outgoing;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let this = self.as_mut().get_mut();
+        if let Some(backlog) = this.backlog.take() {
+            this.replay = backlog.attach();
+        }
+        match this.replay.pop_front() {
+            Some(message) => Poll::Ready(Some(message)),
+            None => Pin::new(&mut this.rx).poll_next(cx),
+        }
+    }
+}
+
+impl FusedStream for Outbox {
+    fn is_terminated(&self) -> bool {
+        self.replay.is_empty() && self.rx.is_terminated()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jsonrpc::ClientRequest;
+    use futures::{SinkExt, StreamExt};
+
+    fn message(text: &str) -> Outgoing {
+        let params = lsp::LogMessageParams {
+            typ: lsp::MessageType::LOG,
+            message: text.into(),
+        };
+        Outgoing::Request(ClientRequest::notification::<lsp::notification::LogMessage>(params))
+    }
+
+    #[tokio::test]
+    async fn replays_backlog_before_channel() {
+        let (mut tx, rx) = mpsc::channel(1);
+        let backlog = Arc::new(Backlog::new(2));
+        let mut outbox = Outbox::new(rx, Some(backlog.clone()));
+
+        for text in ["a", "b", "c"] {
+            assert_eq!(backlog.push(message(text)), None);
+        }
+        assert_eq!(outbox.next().await, Some(message("a")));
+
+        // Messages sent after the transport attached go through the channel, behind the backlog.
+        let late = backlog.push(message("d")).unwrap();
+        tx.send(late).await.unwrap();
+        drop(tx);
+        assert!(!outbox.is_terminated());
+        let rest: Vec<_> = outbox.collect().await;
+        assert_eq!(rest, [message("b"), message("c"), message("d")]);
+    }
+}
@@ -0,0 +1,374 @@
+//! Validation and bookkeeping of dynamic capability registrations.
+
+use crate::jsonrpc::Error;
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    fmt::{self, Display, Formatter},
+    sync::Arc,
+};
+
+/// Paths of the `dynamicRegistration` flags within the client capabilities, keyed by method name.
+const DYNAMIC_REGISTRATION_FLAGS: &[(&str, &[&str])] = &[
+    ("workspace/didChangeConfiguration", &["workspace", "didChangeConfiguration"]),
+    ("workspace/didChangeWatchedFiles", &["workspace", "didChangeWatchedFiles"]),
+    ("workspace/symbol", &["workspace", "symbol"]),
+    ("workspace/executeCommand", &["workspace", "executeCommand"]),
+    ("workspace/didCreateFiles", &["workspace", "fileOperations"]),
+    ("workspace/willCreateFiles", &["workspace", "fileOperations"]),
+    ("workspace/didRenameFiles", &["workspace", "fileOperations"]),
+    ("workspace/willRenameFiles", &["workspace", "fileOperations"]),
+    ("workspace/didDeleteFiles", &["workspace", "fileOperations"]),
+    ("workspace/willDeleteFiles", &["workspace", "fileOperations"]),
+    ("textDocument/didOpen", &["textDocument", "synchronization"]),
+    ("textDocument/didChange", &["textDocument", "synchronization"]),
+    ("textDocument/willSave", &["textDocument", "synchronization"]),
+    ("textDocument/willSaveWaitUntil", &["textDocument", "synchronization"]),
+    ("textDocument/didSave", &["textDocument", "synchronization"]),
+    ("textDocument/didClose", &["textDocument", "synchronization"]),
+    ("textDocument/completion", &["textDocument", "completion"]),
+    ("textDocument/hover", &["textDocument", "hover"]),
+    ("textDocument/signatureHelp", &["textDocument", "signatureHelp"]),
+    ("textDocument/declaration", &["textDocument", "declaration"]),
+    ("textDocument/definition", &["textDocument", "definition"]),
+    ("textDocument/typeDefinition", &["textDocument", "typeDefinition"]),
+    ("textDocument/implementation", &["textDocument", "implementation"]),
+    ("textDocument/references", &["textDocument", "references"]),
+    ("textDocument/documentHighlight", &["textDocument", "documentHighlight"]),
+    ("textDocument/documentSymbol", &["textDocument", "documentSymbol"]),
+    ("textDocument/codeAction", &["textDocument", "codeAction"]),
+    ("textDocument/codeLens", &["textDocument", "codeLens"]),
+    ("textDocument/documentLink", &["textDocument", "documentLink"]),
+    ("textDocument/documentColor", &["textDocument", "colorProvider"]),
+    ("textDocument/formatting", &["textDocument", "formatting"]),
+    ("textDocument/rangeFormatting", &["textDocument", "rangeFormatting"]),
+    ("textDocument/onTypeFormatting", &["textDocument", "onTypeFormatting"]),
+    ("textDocument/rename", &["textDocument", "rename"]),
+    ("textDocument/foldingRange", &["textDocument", "foldingRange"]),
+    ("textDocument/selectionRange", &["textDocument", "selectionRange"]),
+    ("textDocument/linkedEditingRange", &["textDocument", "linkedEditingRange"]),
+    ("textDocument/prepareCallHierarchy", &["textDocument", "callHierarchy"]),
+    ("textDocument/semanticTokens", &["textDocument", "semanticTokens"]),
+    ("textDocument/moniker", &["textDocument", "moniker"]),
+    ("textDocument/inlayHint", &["textDocument", "inlayHint"]),
+    ("textDocument/inlineCompletion", &["textDocument", "inlineCompletion"]),
+];
+
+/// Paths of the flags declaring support for server-to-client requests, keyed by method name.
+const CLIENT_REQUEST_FLAGS: &[(&str, &[&str])] = &[
+    ("window/showDocument", &["window", "showDocument", "support"]),
+    ("window/workDoneProgress/create", &["window", "workDoneProgress"]),
+];
+
+/// Error returned when registering a capability the client cannot register dynamically.
+///
+/// The client declared `dynamicRegistration: false` (or omitted the flag) for the method, so it
+/// would silently ignore the registration. The capability should be declared statically in the
+/// [`InitializeResult`] instead.
+///
+/// [`InitializeResult`]: lsp::InitializeResult
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct UnsupportedRegistration {
+    method: String,
+}
+
+impl UnsupportedRegistration {
+    /// Returns the method of the rejected registration.
+    pub fn method(&self) -> &str {
+        &self.method
+    }
+}
+
+impl Display for UnsupportedRegistration {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "client does not support dynamic registration of {:?}, declare the capability statically instead",
+            self.method
+        )
+    }
+}
+
+impl std::error::Error for UnsupportedRegistration {
+}
+
+impl From<UnsupportedRegistration> for Error {
+    fn from(error: UnsupportedRegistration) -> Self {
+        let data = json!({ "method": error.method });
+        Error::invalid_request().with_message(error.to_string()).with_data(data)
+    }
+}
+
+/// Error returned when sending a request the client declared no support for.
+///
+/// Instead of waiting for a response which may never arrive, the request is not sent at all. See
+/// [`Client::supports_request`] for the requests which are checked.
+///
+/// [`Client::supports_request`]: crate::Client::supports_request
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct UnsupportedByClient {
+    method: String,
+    capability: String,
+}
+
+impl UnsupportedByClient {
+    /// Returns the method of the rejected request.
+    pub fn method(&self) -> &str {
+        &self.method
+    }
+
+    /// Returns the path of the client capability the request requires, such as
+    /// `window.showDocument.support`.
+    pub fn capability(&self) -> &str {
+        &self.capability
+    }
+}
+
+impl Display for UnsupportedByClient {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "client does not support {:?} requests (capability {:?} is not set)",
+            self.method, self.capability
+        )
+    }
+}
+
+impl std::error::Error for UnsupportedByClient {
+}
+
+impl From<UnsupportedByClient> for Error {
+    fn from(error: UnsupportedByClient) -> Self {
+        let data = json!({ "method": error.method, "capability": error.capability });
+        Error::invalid_request().with_message(error.to_string()).with_data(data)
+    }
+}
+
+/// Error returned by the [`Client`] methods sending requests gated on client capabilities.
+///
+/// Distinguishes requests which were not sent since the client declared no support for them from
+/// requests which failed otherwise. Converts into a [`jsonrpc::Error`] so that it can be
+/// propagated from [`LanguageServer`] methods with `?`.
+///
+/// [`Client`]: crate::Client
+/// [`jsonrpc::Error`]: crate::jsonrpc::Error
+/// [`LanguageServer`]: crate::LanguageServer
+#[derive(Clone, Debug, PartialEq)]
+pub enum ClientRequestError {
+    /// The client declared no support for the request, which was not sent.
+    Unsupported(UnsupportedByClient),
+    /// The request could not be sent or the client responded with an error.
+    Failed(Error),
+}
+
+impl Display for ClientRequestError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            ClientRequestError::Unsupported(error) => Display::fmt(error, f),
+            ClientRequestError::Failed(error) => Display::fmt(error, f),
+        }
+    }
+}
+
+impl std::error::Error for ClientRequestError {
+}
+
+impl From<UnsupportedByClient> for ClientRequestError {
+    fn from(error: UnsupportedByClient) -> Self {
+        ClientRequestError::Unsupported(error)
+    }
+}
+
+impl From<Error> for ClientRequestError {
+    fn from(error: Error) -> Self {
+        ClientRequestError::Failed(error)
+    }
+}
+
+impl From<ClientRequestError> for Error {
+    fn from(error: ClientRequestError) -> Self {
+        match error {
+            ClientRequestError::Unsupported(error) => error.into(),
+            ClientRequestError::Failed(error) => error,
+        }
+    }
+}
+
+/// Client capabilities received with `initialize`, along with the currently active registrations.
+#[derive(Debug, Default)]
+pub(crate) struct CapabilityRegistry {
+    capabilities: Option<lsp::ClientCapabilities>,
+    /// The capabilities as declared by the client, including the ones `lsp-types` does not retain.
+    flags: Arc<Value>,
+    registrations: HashMap<String, lsp::Registration>,
+}
+
+impl CapabilityRegistry {
+    /// Stores the capabilities the client declared in its `initialize` request, along with their
+    /// JSON representation as sent by the client, if available.
+    pub(crate) fn set_capabilities(&mut self, capabilities: lsp::ClientCapabilities, raw: Option<Value>) {
+        self.flags = Arc::new(raw.unwrap_or_else(|| crate::jsonrpc::lsp_value(&capabilities)));
+        self.capabilities = Some(capabilities);
+        self.registrations.clear();
+    }
+
+    pub(crate) fn capabilities(&self) -> Option<&lsp::ClientCapabilities> {
+        self.capabilities.as_ref()
+    }
+
+    /// Returns the capabilities as declared by the client, or `null` before the client declared
+    /// them.
+    pub(crate) fn flags(&self) -> Arc<Value> {
+        self.flags.clone()
+    }
+
+    /// Returns whether the client can register the given method dynamically.
+    ///
+    /// Methods for which the specification defines no `dynamicRegistration` flag, such as custom
+    /// methods, are assumed to be supported.
+    pub(crate) fn supports_dynamic_registration(&self, method: &str) -> bool {
+        match DYNAMIC_REGISTRATION_FLAGS.iter().find(|(name, _)| *name == method) {
+            Some((_, path)) => path
+                .iter()
+                .fold(&*self.flags, |value, key| &value[key])
+                .get("dynamicRegistration")
+                .and_then(Value::as_bool)
+                .unwrap_or(false),
+            None => true,
+        }
+    }
+
+    /// Returns whether the client declared support for the given server-to-client request.
+    ///
+    /// Requests which every client must support, such as custom methods, are assumed to be
+    /// supported.
+    pub(crate) fn supports_request(&self, method: &str) -> bool {
+        self.check_request(method).is_ok()
+    }
+
+    /// Checks that the client declared support for the given server-to-client request.
+    pub(crate) fn check_request(&self, method: &str) -> Result<(), UnsupportedByClient> {
+        let path = match CLIENT_REQUEST_FLAGS.iter().find(|(name, _)| *name == method) {
+            Some((_, path)) => path,
+            None => return Ok(()),
+        };
+        let flag = path.iter().fold(&*self.flags, |value, key| &value[key]);
+        if flag.as_bool().unwrap_or(false) {
+            Ok(())
+        } else {
+            Err(UnsupportedByClient {
+                method: method.to_owned(),
+                capability: path.join("."),
+            })
+        }
+    }
+
+    /// Checks that the client can register all of the given registrations dynamically.
+    pub(crate) fn validate(&self, registrations: &[lsp::Registration]) -> Result<(), UnsupportedRegistration> {
+        match registrations
+            .iter()
+            .find(|registration| !self.supports_dynamic_registration(&registration.method))
+        {
+            Some(registration) => Err(UnsupportedRegistration {
+                method: registration.method.clone(),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) fn register(&mut self, registrations: Vec<lsp::Registration>) {
+        for registration in registrations {
+            self.registrations.insert(registration.id.clone(), registration);
+        }
+    }
+
+    pub(crate) fn unregister(&mut self, unregistrations: &[lsp::Unregistration]) {
+        for unregistration in unregistrations {
+            self.registrations.remove(&unregistration.id);
+        }
+    }
+
+    pub(crate) fn registrations(&self) -> Vec<lsp::Registration> {
+        self.registrations.values().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jsonrpc::ErrorCode;
+
+    fn registration(id: &str, method: &str) -> lsp::Registration {
+        lsp::Registration {
+            id: id.into(),
+            method: method.into(),
+            register_options: None,
+        }
+    }
+
+    fn registry() -> CapabilityRegistry {
+        let mut registry = CapabilityRegistry::default();
+        let capabilities = serde_json::from_value(json!({
+            "textDocument": {
+                "hover": { "dynamicRegistration": true },
+                "formatting": { "dynamicRegistration": false },
+            },
+        }))
+        .unwrap();
+        registry.set_capabilities(capabilities, None);
+        registry
+    }
+
+    #[test]
+    fn supports_dynamic_registration() {
+        let registry = registry();
+        assert!(registry.supports_dynamic_registration("textDocument/hover"));
+        assert!(!registry.supports_dynamic_registration("textDocument/formatting"));
+        assert!(!registry.supports_dynamic_registration("textDocument/rename"));
+        assert!(registry.supports_dynamic_registration("custom/method"));
+    }
+
+    #[test]
+    fn validate() {
+        let registry = registry();
+        assert_eq!(registry.validate(&[registration("1", "textDocument/hover")]), Ok(()));
+        let error = registry
+            .validate(&[
+                registration("1", "textDocument/hover"),
+                registration("2", "textDocument/formatting"),
+            ])
+            .unwrap_err();
+        assert_eq!(error.method(), "textDocument/formatting");
+        assert_eq!(Error::from(error).code, ErrorCode::InvalidRequest);
+    }
+
+    #[test]
+    fn check_request() {
+        let mut registry = registry();
+        assert!(registry.supports_request("custom/method"));
+        let error = registry.check_request("window/showDocument").unwrap_err();
+        assert_eq!(error.method(), "window/showDocument");
+        assert_eq!(error.capability(), "window.showDocument.support");
+        assert_eq!(Error::from(error).code, ErrorCode::InvalidRequest);
+
+        let capabilities = serde_json::from_value(json!({
+            "window": { "showDocument": { "support": true }, "workDoneProgress": true },
+        }))
+        .unwrap();
+        registry.set_capabilities(capabilities, None);
+        assert_eq!(registry.check_request("window/showDocument"), Ok(()));
+        assert_eq!(registry.check_request("window/workDoneProgress/create"), Ok(()));
+    }
+
+    #[test]
+    fn register_and_unregister() {
+        let mut registry = registry();
+        registry.register(vec![registration("1", "textDocument/hover")]);
+        assert_eq!(registry.registrations().len(), 1);
+        registry.unregister(&[lsp::Unregistration {
+            id: "1".into(),
+            method: "textDocument/hover".into(),
+        }]);
+        assert!(registry.registrations().is_empty());
+    }
+}
@@ -0,0 +1,127 @@
+//! Caching of configuration sections fetched from the client.
+
+use crate::jsonrpc::Result;
+use futures::future::{BoxFuture, Shared, WeakShared};
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    fmt::{self, Debug, Formatter},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// A `workspace/configuration` request for a single section, shared by all callers waiting for it.
+pub(crate) type Fetch = Shared<BoxFuture<'static, Result<Value>>>;
+
+/// The scope and name of a configuration section.
+pub(crate) type Section = (Option<lsp::Url>, String);
+
+enum Entry {
+    /// The section is being fetched. Once every caller waiting for it is gone, the request is
+    /// canceled and the entry is stale.
+    Fetching(WeakShared<BoxFuture<'static, Result<Value>>>),
+    /// The section was fetched at the given instant.
+    Cached(Value, Instant),
+}
+
+/// The configuration sections fetched with [`Client::configuration_scoped`].
+///
+/// [`Client::configuration_scoped`]: crate::Client::configuration_scoped
+#[derive(Default)]
+pub(crate) struct ConfigurationCache(Mutex<HashMap<Section, Entry>>);
+
+impl ConfigurationCache {
+    /// Returns the cached value of the section if it is younger than `ttl`, or the request fetching
+    /// it, starting one with `fetch` unless one is in flight already.
+    pub(crate) fn get_or_fetch(
+        &self,
+        section: &Section,
+        now: Instant,
+        ttl: Option<Duration>,
+        fetch: impl FnOnce() -> Fetch,
+    ) -> std::result::Result<Value, Fetch> {
+        let mut entries = self.0.lock().unwrap();
+        match entries.get(section) {
+            Some(Entry::Cached(value, at)) if ttl.is_none_or(|ttl| now.saturating_duration_since(*at) < ttl) => {
+                return Ok(value.clone());
+            },
+            Some(Entry::Fetching(weak)) => {
+                if let Some(fetch) = weak.upgrade() {
+                    return Err(fetch);
+                }
+            },
+            _ => {},
+        }
+
+        let fetch = fetch();
+        if let Some(weak) = fetch.downgrade() {
+            entries.insert(section.clone(), Entry::Fetching(weak));
+        }
+        Err(fetch)
+    }
+
+    /// Caches the result of the given request, unless the cache was invalidated since it started.
+    pub(crate) fn complete(&self, section: &Section, fetch: &Fetch, result: &Result<Value>, now: Instant) {
+        let mut entries = self.0.lock().unwrap();
+        let current = match entries.get(section) {
+            Some(Entry::Fetching(weak)) => weak.upgrade().is_some_and(|current| current.ptr_eq(fetch)),
+            _ => false,
+        };
+        if current {
+            match result {
+                Ok(value) => entries.insert(section.clone(), Entry::Cached(value.clone(), now)),
+                Err(_) => entries.remove(section),
+            };
+        }
+    }
+
+    /// Forgets all cached sections, e.g. once the client announced a change of the configuration.
+    ///
+    /// Requests in flight are still answered, but their results are not cached.
+    pub(crate) fn invalidate(&self) {
+        self.0.lock().unwrap().clear();
+    }
+}
+
+impl Debug for ConfigurationCache {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_tuple(stringify!(ConfigurationCache))
+            .field(&self.0.lock().unwrap().len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::FutureExt;
+    use serde_json::json;
+
+    fn fetch(value: Value) -> Fetch {
+        futures::future::ok(value).boxed().shared()
+    }
+
+    #[tokio::test]
+    async fn caches_until_expired_or_invalidated() {
+        let cache = ConfigurationCache::default();
+        let section = (None, "example".to_owned());
+        let ttl = Some(Duration::from_secs(10));
+        let now = Instant::now();
+
+        let pending = cache.get_or_fetch(&section, now, ttl, || fetch(json!(1))).unwrap_err();
+        let joined = cache.get_or_fetch(&section, now, ttl, || unreachable!()).unwrap_err();
+        assert!(pending.ptr_eq(&joined));
+
+        let result = pending.clone().await;
+        cache.complete(&section, &pending, &result, now);
+        assert_eq!(cache.get_or_fetch(&section, now, ttl, || unreachable!()).ok(), Some(json!(1)));
+
+        let later = now + Duration::from_secs(10);
+        let refetch = cache.get_or_fetch(&section, later, ttl, || fetch(json!(2))).unwrap_err();
+        assert_eq!(refetch.clone().await, Ok(json!(2)));
+
+        cache.invalidate();
+        cache.complete(&section, &refetch, &Ok(json!(2)), later);
+        assert!(cache.get_or_fetch(&section, later, ttl, || fetch(json!(3))).is_err());
+    }
+}
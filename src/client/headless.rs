@@ -0,0 +1,138 @@
+//! Answering server-to-client requests without an editor attached.
+
+use crate::jsonrpc::{Error, Result};
+use lsp::request::{ApplyWorkspaceEdit, Request, ShowDocument, ShowMessageRequest, WorkspaceConfiguration};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+/// Requests which are answered with `null` unless a different response was configured.
+const NULL_RESPONSES: &[&str] = &[
+    "client/registerCapability",
+    "client/unregisterCapability",
+    "window/workDoneProgress/create",
+    "workspace/codeLens/refresh",
+    "workspace/diagnostic/refresh",
+    "workspace/inlayHint/refresh",
+    "workspace/inlineValue/refresh",
+    "workspace/semanticTokens/refresh",
+    "workspace/workspaceFolders",
+];
+
+/// Responses to server-to-client requests for running a server without an editor attached.
+///
+/// In headless mode, which is enabled with [`LspServiceBuilder::headless`], requests sent through
+/// the [`Client`] are answered locally instead of being sent to the client, so that the same
+/// backend code can run in batch or CI analysis tools. Notifications are still written to the
+/// [`MessageStream`].
+///
+/// Without further configuration, requests are answered as follows:
+///
+/// * `window/showMessageRequest` with `null`, i.e. no action was selected
+/// * `window/showDocument` with `{ "success": false }`
+/// * `workspace/applyEdit` with `{ "applied": false }`
+/// * `workspace/configuration` with `null` for each requested item
+/// * capability registrations, progress creation, refresh requests and `workspace/workspaceFolders`
+///   with `null`
+/// * all other requests with a "method not found" error (`-32601`)
+///
+/// [`LspServiceBuilder::headless`]: crate::LspServiceBuilder::headless
+/// [`Client`]: crate::Client
+/// [`MessageStream`]: crate::MessageStream
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HeadlessClient {
+    responses: HashMap<&'static str, Value>,
+    configuration: HashMap<String, Value>,
+    select_first_action: bool,
+}
+
+impl HeadlessClient {
+    /// Creates a new `HeadlessClient` with the default responses.
+    pub fn new() -> Self {
+        HeadlessClient::default()
+    }
+
+    /// Answers requests of type `R` with the given result.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # use lspower::{lsp, HeadlessClient};
+    /// let result = lsp::ShowDocumentResult { success: true };
+    /// let client = HeadlessClient::new().respond::<lsp::request::ShowDocument>(result);
+    /// ```
+    pub fn respond<R>(mut self, result: R::Result) -> Self
+    where
+        R: Request,
+    {
+        self.responses.insert(R::METHOD, crate::jsonrpc::lsp_value(result));
+        self
+    }
+
+    /// Answers `workspace/configuration` requests for the given section with `value`.
+    ///
+    /// Items requesting other sections are answered with `null`.
+    pub fn configuration(mut self, section: impl Into<String>, value: Value) -> Self {
+        self.configuration.insert(section.into(), value);
+        self
+    }
+
+    /// Answers `window/showMessageRequest` requests with their first action, if any.
+    pub fn select_first_action(mut self, select: bool) -> Self {
+        self.select_first_action = select;
+        self
+    }
+
+    /// Returns the result of the request with the given method and parameters.
+    pub(crate) fn respond_to(&self, method: &str, params: &Value) -> Result<Value> {
+        if let Some(result) = self.responses.get(method) {
+            return Ok(result.clone());
+        }
+
+        match method {
+            ShowMessageRequest::METHOD if self.select_first_action => Ok(params["actions"][0].clone()),
+            ShowMessageRequest::METHOD => Ok(Value::Null),
+            ShowDocument::METHOD => Ok(json!({ "success": false })),
+            ApplyWorkspaceEdit::METHOD => Ok(json!({ "applied": false })),
+            WorkspaceConfiguration::METHOD => {
+                let items = params["items"].as_array().map(Vec::as_slice).unwrap_or_default();
+                let values = items.iter().map(|item| match item["section"].as_str() {
+                    Some(section) => self.configuration.get(section).cloned().unwrap_or_default(),
+                    None => Value::Null,
+                });
+                Ok(Value::Array(values.collect()))
+            },
+            _ if NULL_RESPONSES.contains(&method) => Ok(Value::Null),
+            _ => Err(Error::method_not_found()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn respond_to() {
+        let client = HeadlessClient::new();
+        let params = json!({ "type": 1, "message": "Reload?", "actions": [{ "title": "Yes" }] });
+        let none = json!({});
+        assert_eq!(client.respond_to("window/showMessageRequest", &params), Ok(Value::Null));
+        let result = client.respond_to("window/showDocument", &none);
+        assert_eq!(result, Ok(json!({ "success": false })));
+        assert_eq!(client.respond_to("client/registerCapability", &none), Ok(Value::Null));
+        let result = client.respond_to("custom/request", &none);
+        assert_eq!(result, Err(Error::method_not_found()));
+
+        let client = HeadlessClient::new()
+            .select_first_action(true)
+            .configuration("rust", json!({ "checkOnSave": false }))
+            .respond::<ShowDocument>(lsp::ShowDocumentResult { success: true });
+        let result = client.respond_to("window/showMessageRequest", &params);
+        assert_eq!(result, Ok(json!({ "title": "Yes" })));
+        let result = client.respond_to("window/showDocument", &none);
+        assert_eq!(result, Ok(json!({ "success": true })));
+        let params = json!({ "items": [{ "section": "rust" }, { "section": "python" }, {}] });
+        let result = client.respond_to("workspace/configuration", &params);
+        assert_eq!(result, Ok(json!([{ "checkOnSave": false }, null, null])));
+    }
+}
@@ -0,0 +1,105 @@
+//! Allocation of the IDs of server-to-client requests.
+
+use std::{
+    fmt::Debug,
+    ops::Range,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// The largest request ID, since the protocol represents IDs as 32-bit signed integers.
+const MAX_ID: u64 = i32::MAX as u64;
+
+/// Source of the IDs of requests sent from the server to the client.
+///
+/// The [`Client`] uses an [`IdRange`] covering all valid IDs unless a different allocator is set
+/// with [`LspServiceBuilder::request_ids`]. Embedders which forward requests of other servers over
+/// the same connection, such as proxies, can partition the ID space so that the IDs of their own
+/// requests never collide with those of the forwarded ones.
+///
+/// [`Client`]: crate::Client
+/// [`LspServiceBuilder::request_ids`]: crate::LspServiceBuilder::request_ids
+pub trait IdAllocator: Debug + Send + Sync + 'static {
+    /// Returns the ID of the next request.
+    ///
+    /// The ID must not exceed `i32::MAX`, since the `$/cancelRequest` notification only carries
+    /// 32-bit IDs. The [`Client`] skips IDs still in use by requests waiting for their response and
+    /// asks for the next one instead.
+    ///
+    /// [`Client`]: crate::Client
+    fn next_id(&self) -> u64;
+}
+
+/// An [`IdAllocator`] handing out consecutive IDs from a range, starting over at the start of the
+/// range once its end was reached.
+///
+/// Once the IDs started over, the [`Client`] skips those of requests still waiting for their
+/// response, so that a request fails only if every ID of the range is in use.
+///
+/// # Example
+///
+/// ```rust
+/// # use lspower::{IdAllocator, IdRange};
+/// let ids = IdRange::new(1_000 .. 2_000);
+/// assert_eq!(ids.next_id(), 1_000);
+/// assert_eq!(ids.next_id(), 1_001);
+/// ```
+///
+/// [`Client`]: crate::Client
+#[derive(Debug)]
+pub struct IdRange {
+    range: Range<u64>,
+    next: AtomicU64,
+}
+
+impl IdRange {
+    /// Creates a new `IdRange` handing out the IDs in the given range.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range is empty or exceeds `i32::MAX`.
+    pub fn new(range: Range<u64>) -> Self {
+        assert!(!range.is_empty(), "empty request ID range: {:?}", range);
+        assert!(range.end <= MAX_ID + 1, "request ID range exceeds `i32::MAX`: {:?}", range);
+        IdRange {
+            next: AtomicU64::new(range.start),
+            range,
+        }
+    }
+
+    /// Returns the range of IDs handed out.
+    pub fn range(&self) -> Range<u64> {
+        self.range.clone()
+    }
+}
+
+impl Default for IdRange {
+    fn default() -> Self {
+        IdRange::new(0 .. MAX_ID + 1)
+    }
+}
+
+impl IdAllocator for IdRange {
+    fn next_id(&self) -> u64 {
+        let next = |id: u64| Some(if id + 1 < self.range.end { id + 1 } else { self.range.start });
+        // The closure always returns `Some`, so the update never fails.
+        self.next.fetch_update(Ordering::Relaxed, Ordering::Relaxed, next).unwrap_or_else(|id| id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wraps_around() {
+        let ids = IdRange::new(5 .. 7);
+        let allocated: Vec<_> = (0 .. 5).map(|_| ids.next_id()).collect();
+        assert_eq!(allocated, vec![5, 6, 5, 6, 5]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn exceeds_protocol_range() {
+        IdRange::new(0 .. MAX_ID + 2);
+    }
+}
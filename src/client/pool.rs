@@ -0,0 +1,203 @@
+//! Broadcasting to the clients of multiple language server sessions.
+
+use super::{CancellationToken, Client};
+use futures::future;
+use lsp::notification::PublishDiagnostics;
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Debug, Formatter},
+    sync::{Arc, Mutex},
+};
+
+/// Identifier of a [`Client`] within a [`ClientPool`].
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct ClientId(u64);
+
+/// A set of [`Client`]s, one for each session of a server shared by multiple editors.
+///
+/// Servers which accept multiple connections create one [`LspService`] per connection, but often
+/// share state, such as an index of the workspace, between them. Each session adds its `Client` to
+/// a shared `ClientPool`, which then fans out notifications derived from the shared state to all
+/// connected editors, and sends requests to all of them, collecting the individual responses.
+///
+/// The pool is cheap to clone and safe to use concurrently. Clients whose connection was closed,
+/// e.g. because the editor sent `exit`, are removed from the pool automatically when broadcasting.
+///
+/// [`LspService`]: crate::LspService
+#[derive(Clone, Default)]
+pub struct ClientPool {
+    inner: Arc<Mutex<PoolInner>>,
+}
+
+#[derive(Default)]
+struct PoolInner {
+    next_id: u64,
+    clients: BTreeMap<ClientId, Client>,
+}
+
+impl ClientPool {
+    /// Creates a new, empty `ClientPool`.
+    pub fn new() -> Self {
+        ClientPool::default()
+    }
+
+    /// Adds a client to the pool, returning its identifier.
+    pub fn insert(&self, client: Client) -> ClientId {
+        let mut inner = self.inner.lock().unwrap();
+        let id = ClientId(inner.next_id);
+        inner.next_id += 1;
+        inner.clients.insert(id, client);
+        id
+    }
+
+    /// Removes a client from the pool, returning it if it was present.
+    pub fn remove(&self, id: ClientId) -> Option<Client> {
+        self.inner.lock().unwrap().clients.remove(&id)
+    }
+
+    /// Returns the client with the given identifier.
+    pub fn get(&self, id: ClientId) -> Option<Client> {
+        self.inner.lock().unwrap().clients.get(&id).cloned()
+    }
+
+    /// Returns the number of clients in the pool.
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().clients.len()
+    }
+
+    /// Returns `true` if the pool contains no clients.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the connected clients, removing all clients whose connection was closed.
+    pub fn clients(&self) -> Vec<(ClientId, Client)> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.clients.retain(|_, client| !client.is_closed());
+        inner.clients.iter().map(|(id, client)| (*id, client.clone())).collect()
+    }
+
+    /// Sends a notification to all connected clients concurrently.
+    ///
+    /// As with [`Client::send_custom_notification`], the notification is only sent to clients of
+    /// initialized sessions.
+    pub async fn broadcast_notification<N>(&self, params: N::Params)
+    where
+        N: lsp::notification::Notification,
+        N::Params: Clone,
+    {
+        let clients = self.clients();
+        let notifications = clients
+            .iter()
+            .map(|(_, client)| client.send_custom_notification::<N>(params.clone()));
+        future::join_all(notifications).await;
+    }
+
+    /// Publishes diagnostics for a document to all connected clients.
+    ///
+    /// See [`Client::publish_diagnostics`] for details.
+    pub async fn publish_diagnostics(&self, uri: lsp::Url, diags: Vec<lsp::Diagnostic>, version: Option<i32>) {
+        let params = lsp::PublishDiagnosticsParams::new(uri, diags, version);
+        self.broadcast_notification::<PublishDiagnostics>(params).await;
+    }
+
+    /// Sends a request to all connected clients concurrently, returning their responses.
+    ///
+    /// Cancelling `token` cancels the requests to all clients. As with
+    /// [`Client::send_custom_request`], requests to clients of sessions which are not initialized
+    /// fail with a "server not initialized" error (`-32002`).
+    pub async fn broadcast_request<R>(
+        &self,
+        params: R::Params,
+        token: CancellationToken,
+    ) -> Vec<(ClientId, crate::jsonrpc::Result<R::Result>)>
+    where
+        R: lsp::request::Request,
+        R::Params: Clone,
+    {
+        let clients = self.clients();
+        let requests = clients.iter().map(|(id, client)| {
+            let response = client.send_custom_request::<R>(params.clone(), token.clone());
+            async move { (*id, response.await) }
+        });
+        future::join_all(requests).await
+    }
+}
+
+impl Debug for ClientPool {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let inner = self.inner.lock().unwrap();
+        f.debug_struct(stringify!(ClientPool))
+            .field("clients", &inner.clients.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        client::ClientOptions,
+        jsonrpc::{ClientRequest, Id, Outgoing, Response},
+    };
+    use futures::{channel::mpsc, StreamExt};
+    use serde_json::json;
+
+    fn client() -> (Client, mpsc::Receiver<Outgoing>) {
+        let state = Arc::new(crate::server::State::new());
+        state.set(crate::server::StateKind::Initialized);
+        let (tx, rx) = mpsc::channel(4);
+        let pending = Arc::new(crate::jsonrpc::ClientRequests::new());
+        let options = ClientOptions::default();
+        let tasks = Arc::new(crate::task::BackgroundTasks::new(None, options.clock.clone()));
+        (Client::new(tx, pending, state, options, tasks), rx)
+    }
+
+    #[tokio::test]
+    async fn broadcast_notification() {
+        let pool = ClientPool::new();
+        let (first, mut first_rx) = client();
+        let (second, mut second_rx) = client();
+        let (closed, closed_rx) = client();
+        pool.insert(first);
+        let id = pool.insert(second);
+        pool.insert(closed);
+        drop(closed_rx);
+
+        let uri = lsp::Url::parse("inmemory::///test").unwrap();
+        pool.publish_diagnostics(uri.clone(), vec![], None).await;
+        let params = lsp::PublishDiagnosticsParams::new(uri, vec![], None);
+        let message = Outgoing::Request(ClientRequest::notification::<PublishDiagnostics>(params));
+        assert_eq!(first_rx.next().await.as_ref(), Some(&message));
+        assert_eq!(second_rx.next().await.as_ref(), Some(&message));
+        assert_eq!(pool.len(), 2);
+
+        assert!(pool.remove(id).is_some());
+        assert!(pool.get(id).is_none());
+        assert_eq!(pool.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn broadcast_request() {
+        let pool = ClientPool::new();
+        let (first, mut first_rx) = client();
+        let (second, mut second_rx) = client();
+        let first_id = pool.insert(first.clone());
+        let second_id = pool.insert(second.clone());
+
+        let request = pool.broadcast_request::<lsp::request::WorkspaceFoldersRequest>((), Default::default());
+        let respond = async {
+            first_rx.next().await.unwrap();
+            let response = Response::ok(Id::Number(0), json!(null));
+            first.inner.pending_requests.insert(response);
+            second_rx.next().await.unwrap();
+            let response = Response::error(Some(Id::Number(0)), crate::jsonrpc::Error::internal_error());
+            second.inner.pending_requests.insert(response);
+        };
+        let (responses, ()) = future::join(request, respond).await;
+        assert_eq!(responses, vec![
+            (first_id, Ok(None)),
+            (second_id, Err(crate::jsonrpc::Error::internal_error()))
+        ]);
+    }
+}
@@ -0,0 +1,328 @@
+//! Rate limiting of server-to-client notifications.
+
+use crate::jsonrpc::ClientRequest;
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+/// What happens to notifications which exceed the [`RateLimit`] of their method.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum OverflowAction {
+    /// Drops excess notifications.
+    #[default]
+    Drop,
+    /// Keeps only the latest excess notification, which is sent once the limit allows it.
+    ///
+    /// Notifications about different subjects are kept apart: `$/progress` notifications are
+    /// coalesced per progress token and `textDocument/publishDiagnostics` notifications per
+    /// document, so that the latest notification of each token or document is still sent.
+    Coalesce,
+    /// Delays excess notifications until the limit allows them, preserving their order.
+    ///
+    /// The futures sending delayed notifications only complete once they were sent, which slows
+    /// down the code producing them.
+    Delay,
+}
+
+/// A token bucket limiting the rate at which notifications of a method are sent.
+///
+/// A limit of `count` notifications per `period` allows bursts of up to `count` notifications,
+/// after which further notifications are allowed at an even pace of `count` per `period`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct RateLimit {
+    interval: Duration,
+    tolerance: Duration,
+    action: OverflowAction,
+}
+
+impl RateLimit {
+    /// Creates a limit of `count` notifications per `period`, dropping excess notifications.
+    ///
+    /// A count of `0` is treated as `1`.
+    pub fn new(count: u32, period: Duration) -> Self {
+        let count = count.max(1);
+        let interval = period / count;
+        RateLimit {
+            interval,
+            tolerance: interval * (count - 1),
+            action: OverflowAction::default(),
+        }
+    }
+
+    /// Sets what happens to notifications which exceed this limit.
+    pub fn on_overflow(mut self, action: OverflowAction) -> Self {
+        self.action = action;
+        self
+    }
+
+    /// Returns what happens to notifications which exceed this limit.
+    pub fn action(&self) -> OverflowAction {
+        self.action
+    }
+}
+
+/// A policy limiting the rate of notifications sent to the client, per method.
+///
+/// This protects editors from being flooded by runaway loops in the server, e.g. one calling
+/// [`Client::log_message`] for every line of a large file. Each method has its own [`RateLimit`],
+/// either set explicitly with [`limit`](RateLimitPolicy::limit) or the one set with
+/// [`default_limit`](RateLimitPolicy::default_limit). Without either, notifications of the method
+/// are not limited.
+///
+/// # Example
+///
+/// ```rust
+/// # use lspower::{OverflowAction, RateLimit, RateLimitPolicy};
+/// # use lspower::lsp::notification::{LogMessage, Notification, Progress};
+/// # use std::time::Duration;
+/// let policy = RateLimitPolicy::new()
+///     .default_limit(RateLimit::new(100, Duration::from_secs(1)))
+///     .limit(LogMessage::METHOD, RateLimit::new(10, Duration::from_secs(1)))
+///     .limit(
+///         Progress::METHOD,
+///         RateLimit::new(20, Duration::from_secs(1)).on_overflow(OverflowAction::Delay),
+///     );
+/// ```
+///
+/// [`Client::log_message`]: crate::Client::log_message
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RateLimitPolicy {
+    limits: HashMap<String, RateLimit>,
+    default: Option<RateLimit>,
+}
+
+impl RateLimitPolicy {
+    /// Creates a policy which does not limit any notifications.
+    pub fn new() -> Self {
+        RateLimitPolicy::default()
+    }
+
+    /// Limits the rate of notifications of the given method.
+    pub fn limit(mut self, method: impl Into<String>, limit: RateLimit) -> Self {
+        self.limits.insert(method.into(), limit);
+        self
+    }
+
+    /// Limits the rate of notifications of every method without a more specific limit.
+    ///
+    /// Each method is still limited separately.
+    pub fn default_limit(mut self, limit: RateLimit) -> Self {
+        self.default = Some(limit);
+        self
+    }
+
+    fn get(&self, method: &str) -> Option<RateLimit> {
+        self.limits.get(method).or(self.default.as_ref()).copied()
+    }
+}
+
+/// Result of passing a notification to a [`RateLimiter`].
+#[derive(Debug, PartialEq)]
+pub(crate) enum Admission {
+    /// The notification can be sent immediately.
+    Send(ClientRequest),
+    /// The notification can be sent once the given duration elapsed.
+    Delay(ClientRequest, Duration),
+    /// The notification was dropped.
+    Dropped,
+    /// The notification replaced the pending notification about the same subject, or was queued
+    /// after the pending notifications of its method. If none were pending, it has to be taken
+    /// with [`RateLimiter::take_pending`] once the given duration elapsed.
+    Coalesced { schedule: Option<Duration> },
+}
+
+/// Returns the subject of a notification, of which only the latest notification is kept when
+/// coalescing, or `None` if all notifications of the method are about the same subject.
+fn subject(message: &ClientRequest) -> Option<&Value> {
+    match message.method() {
+        "$/progress" => message.params().get("token"),
+        "textDocument/publishDiagnostics" => message.params().get("uri"),
+        _ => None,
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    /// The theoretical arrival time of the next notification at an even pace.
+    arrival: Instant,
+    /// The coalesced notifications, in the order of their subjects' first notification.
+    pending: Vec<ClientRequest>,
+}
+
+impl Bucket {
+    /// Returns the time until the next notification conforms to the limit.
+    fn wait(&self, limit: &RateLimit, now: Instant) -> Duration {
+        self.arrival.saturating_duration_since(now).saturating_sub(limit.tolerance)
+    }
+
+    fn consume(&mut self, limit: &RateLimit, now: Instant) {
+        self.arrival = self.arrival.max(now) + limit.interval;
+    }
+}
+
+/// Limits notifications according to a [`RateLimitPolicy`].
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    policy: RateLimitPolicy,
+    buckets: HashMap<String, Bucket>,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(policy: RateLimitPolicy) -> Self {
+        RateLimiter {
+            policy,
+            buckets: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn admit(&mut self, message: ClientRequest, now: Instant) -> Admission {
+        let limit = match self.policy.get(message.method()) {
+            Some(limit) => limit,
+            None => return Admission::Send(message),
+        };
+
+        let bucket = self.buckets.entry(message.method().into()).or_insert(Bucket {
+            arrival: now,
+            pending: Vec::new(),
+        });
+
+        if !bucket.pending.is_empty() {
+            match bucket.pending.iter_mut().find(|pending| subject(pending) == subject(&message)) {
+                Some(pending) => *pending = message,
+                None => bucket.pending.push(message),
+            }
+            return Admission::Coalesced { schedule: None };
+        }
+
+        let wait = bucket.wait(&limit, now);
+        if wait.is_zero() {
+            bucket.consume(&limit, now);
+            return Admission::Send(message);
+        }
+
+        match limit.action {
+            OverflowAction::Drop => Admission::Dropped,
+            OverflowAction::Coalesce => {
+                bucket.pending.push(message);
+                Admission::Coalesced { schedule: Some(wait) }
+            },
+            OverflowAction::Delay => {
+                bucket.consume(&limit, now);
+                Admission::Delay(message, wait)
+            },
+        }
+    }
+
+    /// Takes the next pending notification of the given method, which is sent now.
+    ///
+    /// If further notifications of the method are pending, the next one has to be taken once the
+    /// returned duration elapsed.
+    pub(crate) fn take_pending(&mut self, method: &str, now: Instant) -> Option<(ClientRequest, Option<Duration>)> {
+        let limit = self.policy.get(method)?;
+        let bucket = self.buckets.get_mut(method)?;
+        if bucket.pending.is_empty() {
+            return None;
+        }
+
+        let message = bucket.pending.remove(0);
+        bucket.consume(&limit, now);
+        let schedule = match bucket.pending.is_empty() {
+            true => None,
+            false => Some(bucket.wait(&limit, now)),
+        };
+        Some((message, schedule))
+    }
+
+    /// Forgets the pending notifications and the rates of all methods.
+    pub(crate) fn clear(&mut self) {
+        self.buckets.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lsp::notification::{LogMessage, Progress, ShowMessage};
+
+    fn log(message: &str) -> ClientRequest {
+        ClientRequest::notification::<LogMessage>(lsp::LogMessageParams {
+            typ: lsp::MessageType::LOG,
+            message: message.into(),
+        })
+    }
+
+    #[test]
+    fn drop() {
+        let second = Duration::from_secs(1);
+        let policy = RateLimitPolicy::new().limit("window/logMessage", RateLimit::new(2, second));
+        let mut limiter = RateLimiter::new(policy);
+        let now = Instant::now();
+        assert_eq!(limiter.admit(log("1"), now), Admission::Send(log("1")));
+        assert_eq!(limiter.admit(log("2"), now), Admission::Send(log("2")));
+        assert_eq!(limiter.admit(log("3"), now), Admission::Dropped);
+        assert_eq!(limiter.admit(log("4"), now + second / 2), Admission::Send(log("4")));
+        assert_eq!(limiter.admit(log("5"), now + second / 2), Admission::Dropped);
+        assert_eq!(limiter.admit(log("6"), now + second * 2), Admission::Send(log("6")));
+        assert_eq!(limiter.admit(log("7"), now + second * 2), Admission::Send(log("7")));
+
+        let show = ClientRequest::notification::<ShowMessage>(lsp::ShowMessageParams {
+            typ: lsp::MessageType::INFO,
+            message: "unlimited".into(),
+        });
+        assert_eq!(limiter.admit(show.clone(), now), Admission::Send(show));
+    }
+
+    #[test]
+    fn coalesce_and_delay() {
+        let second = Duration::from_secs(1);
+        let limit = RateLimit::new(1, second).on_overflow(OverflowAction::Coalesce);
+        let mut limiter = RateLimiter::new(RateLimitPolicy::new().default_limit(limit));
+        let now = Instant::now();
+        assert_eq!(limiter.admit(log("1"), now), Admission::Send(log("1")));
+        let schedule = Some(second);
+        assert_eq!(limiter.admit(log("2"), now), Admission::Coalesced { schedule });
+        assert_eq!(limiter.admit(log("3"), now), Admission::Coalesced { schedule: None });
+        assert_eq!(limiter.take_pending("window/logMessage", now + second), Some((log("3"), None)));
+        assert_eq!(limiter.take_pending("window/logMessage", now + second), None);
+
+        let limit = RateLimit::new(1, second).on_overflow(OverflowAction::Delay);
+        let mut limiter = RateLimiter::new(RateLimitPolicy::new().default_limit(limit));
+        assert_eq!(limiter.admit(log("1"), now), Admission::Send(log("1")));
+        assert_eq!(limiter.admit(log("2"), now), Admission::Delay(log("2"), second));
+        assert_eq!(limiter.admit(log("3"), now), Admission::Delay(log("3"), second * 2));
+
+        limiter.clear();
+        assert_eq!(limiter.admit(log("4"), now), Admission::Send(log("4")));
+    }
+
+    #[test]
+    fn coalesce_per_subject() {
+        let progress = |token: i32, percentage: u32| {
+            ClientRequest::notification::<Progress>(lsp::ProgressParams {
+                token: lsp::NumberOrString::Number(token),
+                value: lsp::ProgressParamsValue::WorkDone(lsp::WorkDoneProgress::Report(lsp::WorkDoneProgressReport {
+                    percentage: Some(percentage),
+                    ..Default::default()
+                })),
+            })
+        };
+
+        let second = Duration::from_secs(1);
+        let limit = RateLimit::new(1, second).on_overflow(OverflowAction::Coalesce);
+        let mut limiter = RateLimiter::new(RateLimitPolicy::new().default_limit(limit));
+        let now = Instant::now();
+        assert_eq!(limiter.admit(progress(1, 0), now), Admission::Send(progress(1, 0)));
+        let schedule = Some(second);
+        assert_eq!(limiter.admit(progress(1, 10), now), Admission::Coalesced { schedule });
+        assert_eq!(limiter.admit(progress(2, 10), now), Admission::Coalesced { schedule: None });
+        assert_eq!(limiter.admit(progress(1, 20), now), Admission::Coalesced { schedule: None });
+
+        let next = Some((progress(1, 20), Some(second)));
+        assert_eq!(limiter.take_pending("$/progress", now + second), next);
+        let next = Some((progress(2, 10), None));
+        assert_eq!(limiter.take_pending("$/progress", now + second * 2), next);
+        assert_eq!(limiter.take_pending("$/progress", now + second * 2), None);
+    }
+}
@@ -0,0 +1,367 @@
+//! Retry policies for server-to-client requests, applied by a [`Layer`] over the send path.
+
+use super::{cancelled, CancellationToken};
+use crate::{
+    jsonrpc::{Error, ErrorCode},
+    Clock,
+    RequestContext,
+};
+use futures::{
+    future::{self, BoxFuture, FutureExt},
+    select,
+};
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    fmt::{self, Debug, Formatter},
+    sync::Arc,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tower_layer::Layer;
+use tower_service::Service;
+
+/// A policy describing how failed server-to-client requests should be retried.
+///
+/// Requests are only retried if the client responded with one of the configured error codes. The
+/// delay between attempts grows exponentially, starting from the initial backoff and capped at the
+/// maximum backoff.
+///
+/// By default, requests are attempted up to 3 times without a deadline, and only retried when the
+/// client responds with a "content modified" error (`-32801`).
+#[derive(Clone, Debug, PartialEq)]
+pub struct RetryPolicy {
+    max_attempts: usize,
+    backoff: Duration,
+    max_backoff: Duration,
+    deadline: Option<Duration>,
+    retry_on: Vec<ErrorCode>,
+}
+
+impl RetryPolicy {
+    /// Creates a new `RetryPolicy` which attempts each request at most `max_attempts` times.
+    pub fn new(max_attempts: usize) -> Self {
+        RetryPolicy {
+            max_attempts,
+            backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+            deadline: None,
+            retry_on: vec![ErrorCode::ContentModified],
+        }
+    }
+
+    /// Sets the delay before the first retry.
+    pub fn backoff(mut self, backoff: Duration) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Sets the upper bound for the delay between two attempts.
+    pub fn max_backoff(mut self, max_backoff: Duration) -> Self {
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    /// Sets the time since the first attempt after which a failed request is no longer retried.
+    ///
+    /// A retry is only attempted if it would start before the deadline. The attempt in flight when
+    /// the deadline passes is not interrupted.
+    pub fn deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Adds an error code which causes a failed request to be retried.
+    pub fn retry_on(mut self, code: ErrorCode) -> Self {
+        if !self.retry_on.contains(&code) {
+            self.retry_on.push(code);
+        }
+        self
+    }
+
+    /// Returns the maximum number of times a request is attempted.
+    pub fn max_attempts(&self) -> usize {
+        self.max_attempts
+    }
+
+    /// Returns whether a request which failed with `error` on the given attempt should be retried.
+    ///
+    /// Attempts are counted starting from `1`.
+    pub fn should_retry(&self, attempt: usize, error: &Error) -> bool {
+        attempt < self.max_attempts && self.retry_on.contains(&error.code)
+    }
+
+    /// Returns the delay to wait for after the given failed attempt.
+    ///
+    /// Attempts are counted starting from `1`.
+    pub fn delay(&self, attempt: usize) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(31) as u32;
+        self.backoff.saturating_mul(1 << exponent).min(self.max_backoff)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy::new(3)
+    }
+}
+
+/// Retry policies for server-to-client requests, keyed by method name.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct RetryPolicies {
+    default: Option<RetryPolicy>,
+    methods: HashMap<&'static str, RetryPolicy>,
+}
+
+impl RetryPolicies {
+    /// Sets the policy used for requests without a method-specific policy.
+    pub(crate) fn set_default(&mut self, policy: RetryPolicy) {
+        self.default = Some(policy);
+    }
+
+    /// Sets the policy used for requests with the given method name.
+    pub(crate) fn insert(&mut self, method: &'static str, policy: RetryPolicy) {
+        self.methods.insert(method, policy);
+    }
+
+    /// Returns the policy which applies to requests with the given method name, if any.
+    pub(crate) fn get(&self, method: &str) -> Option<&RetryPolicy> {
+        self.methods.get(method).or(self.default.as_ref())
+    }
+}
+
+/// A request to the client, as passed through the [`Service`] layers of the send path.
+#[derive(Clone, Debug)]
+pub(crate) struct ClientCall {
+    pub(crate) method: &'static str,
+    pub(crate) params: Value,
+    pub(crate) token: CancellationToken,
+    /// The context of the request being handled on whose behalf the request is sent, whose
+    /// cancellation also cancels this request.
+    pub(crate) context: Option<RequestContext>,
+}
+
+/// A [`Layer`] retrying failed requests to the client according to the policy for their method.
+#[derive(Clone)]
+pub(crate) struct RetryLayer {
+    policies: Arc<RetryPolicies>,
+    clock: Arc<dyn Clock>,
+}
+
+impl RetryLayer {
+    /// Creates a layer applying the given policies, waiting between attempts on the given clock.
+    pub(crate) fn new(policies: RetryPolicies, clock: Arc<dyn Clock>) -> Self {
+        RetryLayer {
+            policies: Arc::new(policies),
+            clock,
+        }
+    }
+}
+
+impl<S> Layer<S> for RetryLayer {
+    type Service = Retry<S>;
+
+    fn layer(&self, inner: S) -> Retry<S> {
+        Retry {
+            inner,
+            policies: self.policies.clone(),
+            clock: self.clock.clone(),
+        }
+    }
+}
+
+impl Debug for RetryLayer {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct(stringify!(RetryLayer))
+            .field("policies", &self.policies)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A [`Service`] retrying failed requests to the client, created by a [`RetryLayer`].
+///
+/// Retries are abandoned once the request is canceled while waiting for the next attempt.
+#[derive(Clone)]
+pub(crate) struct Retry<S> {
+    inner: S,
+    policies: Arc<RetryPolicies>,
+    clock: Arc<dyn Clock>,
+}
+
+impl<S> Service<ClientCall> for Retry<S>
+where
+    S: Service<ClientCall, Response = Value, Error = Error> + Clone + Send + 'static,
+    S::Future: Send,
+{
+    type Response = Value;
+    type Error = Error;
+    type Future = BoxFuture<'static, Result<Value, Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, call: ClientCall) -> Self::Future {
+        // The inner service is ready for the first attempt, so it is taken along in place of a clone.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let policy = self.policies.get(call.method).cloned();
+        let clock = self.clock.clone();
+
+        async move {
+            let started = clock.now();
+            let mut attempt = 1;
+            loop {
+                let error = match inner.call(call.clone()).await {
+                    Err(error) => error,
+                    result => return result,
+                };
+                let policy = match &policy {
+                    Some(policy) if policy.should_retry(attempt, &error) => policy,
+                    _ => return Err(error),
+                };
+
+                let delay = policy.delay(attempt);
+                let elapsed = clock.now().saturating_duration_since(started);
+                if policy.deadline.is_some_and(|deadline| elapsed + delay > deadline) {
+                    log::debug!(
+                        "request {:?} failed on attempt {} ({}), not retrying past its deadline",
+                        call.method,
+                        attempt,
+                        error
+                    );
+                    return Err(error);
+                }
+
+                log::debug!(
+                    "request {:?} failed on attempt {} ({}), retrying in {:?}",
+                    call.method,
+                    attempt,
+                    error,
+                    delay
+                );
+                select! {
+                    _ = cancelled(&call.token, call.context.as_ref()).fuse() => return Err(Error::request_cancelled()),
+                    _ = clock.sleep(delay).fuse() => {},
+                }
+                future::poll_fn(|cx| inner.poll_ready(cx)).await?;
+                attempt += 1;
+            }
+        }
+        .boxed()
+    }
+}
+
+impl<S: Debug> Debug for Retry<S> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct(stringify!(Retry))
+            .field("inner", &self.inner)
+            .field("policies", &self.policies)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delay() {
+        let policy = RetryPolicy::new(5)
+            .backoff(Duration::from_millis(10))
+            .max_backoff(Duration::from_millis(50));
+        assert_eq!(policy.delay(1), Duration::from_millis(10));
+        assert_eq!(policy.delay(2), Duration::from_millis(20));
+        assert_eq!(policy.delay(3), Duration::from_millis(40));
+        assert_eq!(policy.delay(4), Duration::from_millis(50));
+    }
+
+    #[test]
+    fn should_retry() {
+        let policy = RetryPolicy::new(2).retry_on(ErrorCode::InternalError);
+        assert!(policy.should_retry(1, &Error::content_modified()));
+        assert!(policy.should_retry(1, &Error::internal_error()));
+        assert!(!policy.should_retry(1, &Error::invalid_request()));
+        assert!(!policy.should_retry(2, &Error::internal_error()));
+    }
+
+    #[test]
+    fn policies() {
+        let mut policies = RetryPolicies::default();
+        assert_eq!(policies.get("foo"), None);
+        policies.set_default(RetryPolicy::new(2));
+        policies.insert("foo", RetryPolicy::new(4));
+        assert_eq!(policies.get("foo").map(RetryPolicy::max_attempts), Some(4));
+        assert_eq!(policies.get("bar").map(RetryPolicy::max_attempts), Some(2));
+    }
+
+    #[tokio::test]
+    async fn layer() {
+        use crate::MockClock;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        /// Fails with "content modified" until the given number of attempts were made.
+        #[derive(Clone, Debug)]
+        struct Flaky {
+            attempts: Arc<AtomicUsize>,
+            failures: usize,
+        }
+
+        impl Service<ClientCall> for Flaky {
+            type Response = Value;
+            type Error = Error;
+            type Future = future::Ready<Result<Value, Error>>;
+
+            fn poll_ready(&mut self, _: &mut Context) -> Poll<Result<(), Error>> {
+                Poll::Ready(Ok(()))
+            }
+
+            fn call(&mut self, _: ClientCall) -> Self::Future {
+                let attempt = self.attempts.fetch_add(1, Ordering::SeqCst) + 1;
+                if attempt <= self.failures {
+                    future::err(Error::content_modified())
+                } else {
+                    future::ok(Value::from(attempt))
+                }
+            }
+        }
+
+        let call = |method| ClientCall {
+            method,
+            params: Value::Null,
+            token: CancellationToken::default(),
+            context: None,
+        };
+        let mut policies = RetryPolicies::default();
+        policies.insert("retried", RetryPolicy::new(3).backoff(Duration::from_millis(10)));
+        let deadline = Duration::from_millis(25);
+        let policy = RetryPolicy::new(5).backoff(Duration::from_millis(10)).deadline(deadline);
+        policies.insert("deadline", policy);
+
+        let send = |method, failures| {
+            let clock = MockClock::new();
+            let attempts = Arc::new(AtomicUsize::new(0));
+            let layer = RetryLayer::new(policies.clone(), Arc::new(clock.clone()));
+            let flaky = Flaky {
+                attempts: attempts.clone(),
+                failures,
+            };
+            let mut response = layer.layer(flaky).call(call(method));
+            let result = loop {
+                if let Some(result) = (&mut response).now_or_never() {
+                    break result;
+                }
+                clock.advance(Duration::from_millis(1));
+            };
+            (result, attempts.load(Ordering::SeqCst), clock.elapsed())
+        };
+
+        let backoff = Duration::from_millis(30);
+        assert_eq!(send("retried", 2), (Ok(Value::from(3)), 3, backoff));
+        assert_eq!(send("retried", 3), (Err(Error::content_modified()), 3, backoff));
+        // The second retry would only start after 30ms, past the deadline.
+        let backoff = Duration::from_millis(10);
+        assert_eq!(send("deadline", 5), (Err(Error::content_modified()), 2, backoff));
+        assert_eq!(send("other", 5), (Err(Error::content_modified()), 1, Duration::default()));
+    }
+}
@@ -0,0 +1,226 @@
+//! Scripted client for testing the flows of requests from the server to the client, such as
+//! dynamic capability registration.
+
+use super::Client;
+use crate::jsonrpc::{ClientRequest, Error, Id, Outgoing, Response};
+use futures::{channel::mpsc, StreamExt};
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+/// The client end of a [`Client`], which receives the messages the server sends and answers its
+/// requests as the test script dictates.
+pub(crate) struct ScriptedClient {
+    client: Client,
+    messages: mpsc::Receiver<Outgoing>,
+}
+
+impl ScriptedClient {
+    /// Creates an initialized client with the given capabilities.
+    pub(crate) fn new(capabilities: Value) -> Self {
+        let state = Arc::new(crate::server::State::new());
+        let (tx, messages) = mpsc::channel(4);
+        let pending = Arc::new(crate::jsonrpc::ClientRequests::new());
+        let options = super::ClientOptions::default();
+        let tasks = Arc::new(crate::task::BackgroundTasks::new(None, options.clock.clone()));
+        let client = Client::new(tx, pending, state, options, tasks);
+        client.inner.state.set(crate::server::StateKind::Initialized);
+        client.set_client_capabilities(serde_json::from_value(capabilities.clone()).unwrap(), Some(capabilities));
+        ScriptedClient { client, messages }
+    }
+
+    /// Returns the handle the server uses to send messages to this client.
+    pub(crate) fn client(&self) -> &Client {
+        &self.client
+    }
+
+    /// Waits for the next message from the server and asserts that it is a request to the given
+    /// method with the given parameters, returning its ID.
+    pub(crate) async fn expect_request(&mut self, method: &str, params: Value) -> Id {
+        let request = self.next_request().await;
+        assert_eq!(request.method(), method);
+        assert_eq!(request.params(), &params);
+        request.id().cloned().expect("expected a request, got a notification")
+    }
+
+    /// Waits for the next message from the server, which must be a request or notification.
+    pub(crate) async fn next_request(&mut self) -> ClientRequest {
+        match self.messages.next().await {
+            Some(Outgoing::Request(request)) => request,
+            Some(Outgoing::Response(response)) => panic!("expected a request, got {:?}", response),
+            None => panic!("expected a request, but the server stopped sending messages"),
+        }
+    }
+
+    /// Asserts that the server sent no further messages so far.
+    pub(crate) fn expect_silence(&mut self) {
+        if let Ok(message) = self.messages.try_recv() {
+            panic!("expected no message, got {:?}", message);
+        }
+    }
+
+    /// Answers the request with the given ID successfully with the given result.
+    pub(crate) fn reply_ok(&self, id: Id, result: Value) {
+        self.client.inner.pending_requests.insert(Response::ok(id, result));
+    }
+
+    /// Answers the request with the given ID with the given error.
+    pub(crate) fn reply_err(&self, id: Id, error: Error) {
+        self.client.inner.pending_requests.insert(Response::error(Some(id), error));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn registration(id: &str, method: &str) -> lsp::Registration {
+        lsp::Registration {
+            id: id.into(),
+            method: method.into(),
+            register_options: Some(json!({ "documentSelector": [{ "language": "rust" }] })),
+        }
+    }
+
+    fn unregistration(id: &str, method: &str) -> lsp::Unregistration {
+        lsp::Unregistration {
+            id: id.into(),
+            method: method.into(),
+        }
+    }
+
+    fn capabilities() -> Value {
+        json!({
+            "textDocument": {
+                "hover": { "dynamicRegistration": true },
+                "formatting": { "dynamicRegistration": true },
+            },
+        })
+    }
+
+    fn registered_ids(client: &Client) -> Vec<String> {
+        let mut ids: Vec<_> = client.registrations().into_iter().map(|registration| registration.id).collect();
+        ids.sort();
+        ids
+    }
+
+    #[tokio::test]
+    async fn register_accepted() {
+        let mut scripted = ScriptedClient::new(capabilities());
+        let registrations = vec![registration("hover", "textDocument/hover")];
+
+        let client = scripted.client().clone();
+        let register = client.register_capability(registrations.clone());
+        let script = async {
+            let params = json!({ "registrations": registrations });
+            let id = scripted.expect_request("client/registerCapability", params).await;
+            scripted.reply_ok(id, Value::Null);
+        };
+        let (result, ()) = futures::join!(register, script);
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(client.registrations(), registrations);
+        scripted.expect_silence();
+    }
+
+    #[tokio::test]
+    async fn register_rejected() {
+        let mut scripted = ScriptedClient::new(capabilities());
+        let client = scripted.client().clone();
+
+        let register = client.register_capability(vec![registration("hover", "textDocument/hover")]);
+        let script = async {
+            let id = scripted.next_request().await.id().cloned().unwrap();
+            scripted.reply_ok(id, Value::Null);
+        };
+        let (result, ()) = futures::join!(register, script);
+        assert_eq!(result, Ok(()));
+
+        // A rejected registration leaves the registrations accepted before untouched.
+        let registrations = vec![
+            registration("formatting", "textDocument/formatting"),
+            registration("hover", "textDocument/hover"),
+        ];
+        let register = client.register_capability(registrations.clone());
+        let script = async {
+            let params = json!({ "registrations": registrations });
+            let id = scripted.expect_request("client/registerCapability", params).await;
+            scripted.reply_err(id, Error::internal_error().with_message("registration failed"));
+        };
+        let (result, ()) = futures::join!(register, script);
+
+        let error = result.unwrap_err();
+        assert_eq!(error.message, "registration failed");
+        assert_eq!(client.registrations(), [registration("hover", "textDocument/hover")]);
+    }
+
+    #[tokio::test]
+    async fn register_canceled() {
+        let mut scripted = ScriptedClient::new(capabilities());
+        let client = scripted.client().clone();
+
+        // A registration whose caller stops waiting for the response is not recorded, even if the
+        // client accepts it later.
+        let mut register = Box::pin(client.register_capability(vec![registration("hover", "textDocument/hover")]));
+        assert!(futures::poll!(&mut register).is_pending());
+        drop(register);
+
+        let id = scripted.expect_request("client/registerCapability", json!({ "registrations": [registration("hover", "textDocument/hover")] })).await;
+        scripted.reply_ok(id, Value::Null);
+        assert!(client.registrations().is_empty());
+    }
+
+    #[tokio::test]
+    async fn register_unsupported() {
+        let mut scripted = ScriptedClient::new(capabilities());
+        let client = scripted.client().clone();
+
+        let registrations = vec![
+            registration("hover", "textDocument/hover"),
+            registration("rename", "textDocument/rename"),
+        ];
+        let error = client.register_capability(registrations).await.unwrap_err();
+
+        assert_eq!(error.data, Some(json!({ "method": "textDocument/rename" })));
+        assert!(client.registrations().is_empty());
+        scripted.expect_silence();
+    }
+
+    #[tokio::test]
+    async fn unregister() {
+        let mut scripted = ScriptedClient::new(capabilities());
+        let client = scripted.client().clone();
+
+        let registrations = vec![
+            registration("formatting", "textDocument/formatting"),
+            registration("hover", "textDocument/hover"),
+        ];
+        let register = client.register_capability(registrations);
+        let script = async {
+            let id = scripted.next_request().await.id().cloned().unwrap();
+            scripted.reply_ok(id, Value::Null);
+        };
+        let (result, ()) = futures::join!(register, script);
+        assert_eq!(result, Ok(()));
+
+        // A rejected unregistration keeps the registration.
+        let unregistrations = vec![unregistration("hover", "textDocument/hover")];
+        let unregister = client.unregister_capability(unregistrations.clone());
+        let script = async {
+            let params = json!({ "unregisterations": unregistrations });
+            let id = scripted.expect_request("client/unregisterCapability", params).await;
+            scripted.reply_err(id, Error::invalid_params("unknown registration"));
+        };
+        let (result, ()) = futures::join!(unregister, script);
+        assert!(result.is_err());
+        assert_eq!(registered_ids(&client), ["formatting", "hover"]);
+
+        let unregister = client.unregister_capability(unregistrations);
+        let script = async {
+            let id = scripted.next_request().await.id().cloned().unwrap();
+            scripted.reply_ok(id, Value::Null);
+        };
+        let (result, ()) = futures::join!(unregister, script);
+        assert_eq!(result, Ok(()));
+        assert_eq!(registered_ids(&client), ["formatting"]);
+    }
+}
@@ -0,0 +1,90 @@
+//! Type-level protocol states of a [`Client`].
+//!
+//! [`Client`]: crate::Client
+
+mod sealed {
+    pub trait Sealed {
+    }
+
+    impl Sealed for super::Dynamic {
+    }
+
+    impl Sealed for super::Uninitialized {
+    }
+
+    impl Sealed for super::Initialized {
+    }
+}
+
+/// The protocol state of a [`Client`], tracked at compile time.
+///
+/// This trait is sealed and implemented by [`Dynamic`], [`Uninitialized`] and [`Initialized`].
+///
+/// [`Client`]: crate::Client
+pub trait ClientState: sealed::Sealed + Send + Sync + 'static {
+}
+
+/// Protocol states of a [`Client`] in which it offers the messages which require an initialized
+/// server, such as [`Client::publish_diagnostics`].
+///
+/// Implemented by [`Initialized`], and by [`Dynamic`], for which the state is checked at runtime.
+///
+/// [`Client`]: crate::Client
+/// [`Client::publish_diagnostics`]: crate::Client::publish_diagnostics
+pub trait MaybeInitialized: ClientState {
+}
+
+/// The state of a [`Client`] whose protocol state is only checked at runtime.
+///
+/// This is the default state of a `Client`, as passed to [`LanguageServer`] implementations.
+/// Messages which require an initialized server fail at runtime if the server is not initialized
+/// yet.
+///
+/// [`Client`]: crate::Client
+/// [`LanguageServer`]: crate::LanguageServer
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Dynamic;
+
+impl ClientState for Dynamic {
+}
+
+impl MaybeInitialized for Dynamic {
+}
+
+/// The state of a [`Client`] which may be used before the server is initialized.
+///
+/// Such a client only offers the messages the specification allows before the server responded to
+/// the `initialize` request, like [`Client::log_message`]. It is created with [`Client::typed`].
+///
+/// ```rust,compile_fail
+/// # use lspower::{Client, Uninitialized};
+/// # use lspower::lsp::Url;
+/// async fn publish(client: &Client<Uninitialized>, uri: Url) {
+///     // Diagnostics may only be published once the server is initialized
+///     client.publish_diagnostics(uri, vec![], None).await;
+/// }
+/// ```
+///
+/// [`Client`]: crate::Client
+/// [`Client::log_message`]: crate::Client::log_message
+/// [`Client::typed`]: crate::Client::typed
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Uninitialized;
+
+impl ClientState for Uninitialized {
+}
+
+/// The state of a [`Client`] of an initialized server, which offers all messages.
+///
+/// It is created with [`Client::upgrade`], once the server is initialized.
+///
+/// [`Client`]: crate::Client
+/// [`Client::upgrade`]: crate::Client::upgrade
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Initialized;
+
+impl ClientState for Initialized {
+}
+
+impl MaybeInitialized for Initialized {
+}
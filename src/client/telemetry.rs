@@ -0,0 +1,192 @@
+//! Batching and sampling of `telemetry/event` notifications.
+
+use futures::future::AbortHandle;
+use serde_json::Value;
+use std::{mem, time::Duration};
+
+/// A policy describing how `telemetry/event` notifications are batched and sampled.
+///
+/// Instead of sending every event immediately, events are collected and sent as a single
+/// notification whose data is the array of collected events. A batch is sent once it reaches the
+/// maximum batch size, once the flush interval elapsed since its first event was collected, or
+/// when the client requests a `shutdown`, whichever happens first.
+///
+/// Events can additionally be sampled, in which case only the given fraction of them is collected.
+/// Sampling is deterministic: with a rate of `0.25`, the first of every four events is kept.
+///
+/// By default, events are flushed every second in batches of at most 32 events, and all events are
+/// kept.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TelemetryPolicy {
+    flush_interval: Duration,
+    max_batch_size: usize,
+    sample_rate: f64,
+}
+
+impl TelemetryPolicy {
+    /// Creates a new `TelemetryPolicy` with the default settings.
+    pub fn new() -> Self {
+        TelemetryPolicy {
+            flush_interval: Duration::from_secs(1),
+            max_batch_size: 32,
+            sample_rate: 1.0,
+        }
+    }
+
+    /// Sets the time after which a batch is sent, counted from its first event.
+    pub fn flush_interval(mut self, interval: Duration) -> Self {
+        self.flush_interval = interval;
+        self
+    }
+
+    /// Sets the number of events after which a batch is sent immediately.
+    ///
+    /// A size of `0` is treated as `1`.
+    pub fn max_batch_size(mut self, size: usize) -> Self {
+        self.max_batch_size = size.max(1);
+        self
+    }
+
+    /// Sets the fraction of events which are kept, between `0.0` (none) and `1.0` (all).
+    ///
+    /// Values outside of this range are clamped.
+    pub fn sample_rate(mut self, rate: f64) -> Self {
+        self.sample_rate = if rate.is_nan() { 0.0 } else { rate.clamp(0.0, 1.0) };
+        self
+    }
+}
+
+impl Default for TelemetryPolicy {
+    fn default() -> Self {
+        TelemetryPolicy::new()
+    }
+}
+
+/// Result of collecting an event with a [`TelemetryBatcher`].
+#[derive(Debug, PartialEq)]
+pub(crate) enum Collected {
+    /// The event was not sampled.
+    Dropped,
+    /// The event was added to the current batch, whose flush timer has to be started if
+    /// `start_timer` is set.
+    Buffered { start_timer: bool },
+    /// The event completed the batch, which has to be sent now.
+    Full(Vec<Value>),
+}
+
+/// Collects telemetry events according to a [`TelemetryPolicy`].
+#[derive(Debug)]
+pub(crate) struct TelemetryBatcher {
+    policy: TelemetryPolicy,
+    events: Vec<Value>,
+    seen: u64,
+    timer: Option<AbortHandle>,
+    timed: bool,
+}
+
+impl TelemetryBatcher {
+    pub(crate) fn new(policy: TelemetryPolicy) -> Self {
+        TelemetryBatcher {
+            policy,
+            events: Vec::new(),
+            seen: 0,
+            timer: None,
+            timed: true,
+        }
+    }
+
+    pub(crate) fn flush_interval(&self) -> Duration {
+        self.policy.flush_interval
+    }
+
+    pub(crate) fn collect(&mut self, event: Value) -> Collected {
+        if !self.sample() {
+            return Collected::Dropped;
+        }
+
+        self.events.push(event);
+        if self.events.len() >= self.policy.max_batch_size {
+            Collected::Full(self.take())
+        } else {
+            Collected::Buffered {
+                start_timer: self.timed && self.events.len() == 1,
+            }
+        }
+    }
+
+    /// Sets the timer which flushes the current batch once the flush interval elapsed.
+    pub(crate) fn set_timer(&mut self, timer: AbortHandle) {
+        self.timer = Some(timer);
+    }
+
+    /// Takes the current batch, cancelling its flush timer.
+    pub(crate) fn take(&mut self) -> Vec<Value> {
+        if let Some(timer) = self.timer.take() {
+            timer.abort();
+        }
+        mem::take(&mut self.events)
+    }
+
+    /// Enables or disables flushing batches on a timer.
+    ///
+    /// Disabling the timers aborts the current one, e.g. while the server shuts down, so that
+    /// waiting for background tasks does not wait for the timer.
+    pub(crate) fn set_timed(&mut self, timed: bool) {
+        self.timed = timed;
+        if let (false, Some(timer)) = (timed, self.timer.take()) {
+            timer.abort();
+        }
+    }
+
+    /// Returns whether the next event is kept, spreading the kept events evenly.
+    fn sample(&mut self) -> bool {
+        let rate = self.policy.sample_rate;
+        let seen = self.seen as f64;
+        self.seen += 1;
+        (seen * rate).ceil() < ((seen + 1.0) * rate).ceil()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn batching() {
+        let mut batcher = TelemetryBatcher::new(TelemetryPolicy::new().max_batch_size(3));
+        assert_eq!(batcher.collect(json!(1)), Collected::Buffered { start_timer: true });
+        assert_eq!(batcher.collect(json!(2)), Collected::Buffered { start_timer: false });
+        let full = Collected::Full(vec![json!(1), json!(2), json!(3)]);
+        assert_eq!(batcher.collect(json!(3)), full);
+        assert_eq!(batcher.collect(json!(4)), Collected::Buffered { start_timer: true });
+        assert_eq!(batcher.take(), vec![json!(4)]);
+        assert!(batcher.take().is_empty());
+
+        batcher.set_timed(false);
+        assert_eq!(batcher.collect(json!(5)), Collected::Buffered { start_timer: false });
+        batcher.set_timed(true);
+        assert_eq!(batcher.collect(json!(6)), Collected::Buffered { start_timer: false });
+        assert_eq!(batcher.take(), vec![json!(5), json!(6)]);
+        assert_eq!(batcher.collect(json!(7)), Collected::Buffered { start_timer: true });
+    }
+
+    #[test]
+    fn sampling() {
+        let mut batcher = TelemetryBatcher::new(TelemetryPolicy::new().sample_rate(0.25).max_batch_size(2));
+        let collected: Vec<_> = (0 .. 8).map(|i| batcher.collect(json!(i))).collect();
+        assert_eq!(collected, vec![
+            Collected::Buffered { start_timer: true },
+            Collected::Dropped,
+            Collected::Dropped,
+            Collected::Dropped,
+            Collected::Full(vec![json!(0), json!(4)]),
+            Collected::Dropped,
+            Collected::Dropped,
+            Collected::Dropped,
+        ]);
+
+        let mut batcher = TelemetryBatcher::new(TelemetryPolicy::new().sample_rate(0.0));
+        assert!((0 .. 8).all(|i| batcher.collect(json!(i)) == Collected::Dropped));
+    }
+}
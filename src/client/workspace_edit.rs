@@ -0,0 +1,182 @@
+//! Validation of workspace edits against the capabilities of the client.
+
+use crate::jsonrpc::Error;
+use lsp::{DocumentChangeOperation, DocumentChanges, OneOf, ResourceOp, ResourceOperationKind, TextEdit};
+use serde_json::json;
+use std::{
+    collections::HashMap,
+    fmt::{self, Display, Formatter},
+};
+
+/// Error returned when applying a workspace edit with resource operations the client cannot apply.
+///
+/// The client either declared no support for `documentChanges` in workspace edits, which are
+/// required for resource operations, or did not list the operations in its `resourceOperations`
+/// capability, so it would fail to apply the edit.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct UnsupportedResourceOperations {
+    operations: Vec<ResourceOperationKind>,
+}
+
+impl UnsupportedResourceOperations {
+    /// Returns the kinds of the rejected operations, in the order they first occur in the edit.
+    pub fn operations(&self) -> &[ResourceOperationKind] {
+        &self.operations
+    }
+}
+
+impl Display for UnsupportedResourceOperations {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let operations = self.operations.iter().map(|kind| match kind {
+            ResourceOperationKind::Create => "create",
+            ResourceOperationKind::Rename => "rename",
+            ResourceOperationKind::Delete => "delete",
+        });
+        write!(
+            f,
+            "client does not support the resource operations {:?} in workspace edits",
+            operations.collect::<Vec<_>>()
+        )
+    }
+}
+
+impl std::error::Error for UnsupportedResourceOperations {
+}
+
+impl From<UnsupportedResourceOperations> for Error {
+    fn from(error: UnsupportedResourceOperations) -> Self {
+        let data = json!({ "operations": error.operations });
+        Error::invalid_request().with_message(error.to_string()).with_data(data)
+    }
+}
+
+/// Adapts the given edit to the capabilities of the client.
+///
+/// If the client does not support `documentChanges`, text document edits are downgraded to plain
+/// `changes`, dropping their versions and change annotations, and appended to the `changes` the
+/// edit may already have. Fails if the edit contains resource
+/// operations the client does not support.
+pub(crate) fn prepare(
+    mut edit: lsp::WorkspaceEdit,
+    capabilities: &lsp::ClientCapabilities,
+) -> Result<lsp::WorkspaceEdit, UnsupportedResourceOperations> {
+    let client = capabilities.workspace.as_ref().and_then(|w| w.workspace_edit.as_ref());
+    let document_changes = client.and_then(|c| c.document_changes).unwrap_or(false);
+    let supported = client.and_then(|c| c.resource_operations.as_deref()).unwrap_or_default();
+
+    let mut operations = Vec::new();
+    if let Some(DocumentChanges::Operations(changes)) = &edit.document_changes {
+        for change in changes {
+            let kind = match change {
+                DocumentChangeOperation::Op(ResourceOp::Create(_)) => ResourceOperationKind::Create,
+                DocumentChangeOperation::Op(ResourceOp::Rename(_)) => ResourceOperationKind::Rename,
+                DocumentChangeOperation::Op(ResourceOp::Delete(_)) => ResourceOperationKind::Delete,
+                DocumentChangeOperation::Edit(_) => continue,
+            };
+            let unsupported = !document_changes || !supported.contains(&kind);
+            if unsupported && !operations.contains(&kind) {
+                operations.push(kind);
+            }
+        }
+    }
+
+    if !operations.is_empty() {
+        return Err(UnsupportedResourceOperations { operations });
+    }
+
+    if !document_changes {
+        if let Some(changes) = edit.document_changes.take() {
+            log::debug!("client does not support document changes, downgrading workspace edit");
+            downgrade(changes, edit.changes.get_or_insert_with(HashMap::new));
+            edit.change_annotations = None;
+        }
+    }
+
+    Ok(edit)
+}
+
+/// Appends document changes without resource operations to the plain text edits per document.
+fn downgrade(changes: DocumentChanges, into: &mut HashMap<lsp::Url, Vec<TextEdit>>) {
+    let edits = match changes {
+        DocumentChanges::Edits(edits) => edits,
+        DocumentChanges::Operations(operations) => operations
+            .into_iter()
+            .filter_map(|operation| match operation {
+                DocumentChangeOperation::Edit(edit) => Some(edit),
+                DocumentChangeOperation::Op(_) => None,
+            })
+            .collect(),
+    };
+
+    for edit in edits {
+        let text_edits = edit.edits.into_iter().map(|edit| match edit {
+            OneOf::Left(edit) => edit,
+            OneOf::Right(annotated) => annotated.text_edit,
+        });
+        into.entry(edit.text_document.uri).or_default().extend(text_edits);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lsp::{CreateFile, OptionalVersionedTextDocumentIdentifier, Position, Range, TextDocumentEdit, Url};
+
+    fn capabilities(value: serde_json::Value) -> lsp::ClientCapabilities {
+        serde_json::from_value(json!({ "workspace": { "workspaceEdit": value } })).unwrap()
+    }
+
+    #[test]
+    fn prepare() {
+        let uri = Url::parse("file:///a.rs").unwrap();
+        let text_edit = TextEdit::new(Range::new(Position::new(0, 0), Position::new(0, 0)), "x".into());
+        let document_edit = TextDocumentEdit {
+            text_document: OptionalVersionedTextDocumentIdentifier {
+                uri: uri.clone(),
+                version: Some(1),
+            },
+            edits: vec![OneOf::Left(text_edit.clone())],
+        };
+        let create = ResourceOp::Create(CreateFile {
+            uri: Url::parse("file:///b.rs").unwrap(),
+            options: None,
+            annotation_id: None,
+        });
+        let edit = lsp::WorkspaceEdit {
+            document_changes: Some(DocumentChanges::Operations(vec![
+                DocumentChangeOperation::Op(create),
+                DocumentChangeOperation::Edit(document_edit.clone()),
+            ])),
+            ..Default::default()
+        };
+
+        let full = capabilities(json!({ "documentChanges": true, "resourceOperations": ["create"] }));
+        assert_eq!(super::prepare(edit.clone(), &full), Ok(edit.clone()));
+
+        let expected = UnsupportedResourceOperations {
+            operations: vec![ResourceOperationKind::Create],
+        };
+        let partial = capabilities(json!({ "documentChanges": true, "resourceOperations": ["delete"] }));
+        assert_eq!(super::prepare(edit.clone(), &partial), Err(expected.clone()));
+        let none = capabilities(json!({}));
+        assert_eq!(super::prepare(edit, &none), Err(expected.clone()));
+        let error = Error::from(expected);
+        assert_eq!(error.data, Some(json!({ "operations": ["create"] })));
+
+        let edit = lsp::WorkspaceEdit {
+            document_changes: Some(DocumentChanges::Edits(vec![document_edit])),
+            ..Default::default()
+        };
+        let downgraded = lsp::WorkspaceEdit::new(HashMap::from([(uri.clone(), vec![text_edit.clone()])]));
+        assert_eq!(super::prepare(edit.clone(), &none), Ok(downgraded));
+
+        // Existing plain changes are kept.
+        let other = Url::parse("file:///c.rs").unwrap();
+        let edit = lsp::WorkspaceEdit {
+            changes: Some(HashMap::from([(uri.clone(), vec![text_edit.clone()]), (other.clone(), Vec::new())])),
+            ..edit
+        };
+        let changes = HashMap::from([(uri, vec![text_edit.clone(), text_edit]), (other, Vec::new())]);
+        assert_eq!(super::prepare(edit, &none), Ok(lsp::WorkspaceEdit::new(changes)));
+    }
+}
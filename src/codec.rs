@@ -1,3 +1,9 @@
+#[cfg(feature = "compression")]
+mod compression;
+
+#[cfg(feature = "compression")]
+pub use self::compression::{Compression, ContentEncoding};
+
 #[cfg(feature = "runtime-agnostic")]
 use async_codec_lite::{Decoder, Encoder};
 #[cfg(feature = "runtime-tokio")]
@@ -6,11 +12,24 @@ use tokio_util::codec::{Decoder, Encoder};
 use bytes::{Buf, BufMut, BytesMut};
 
 use std::{
+    fmt::{self, Debug, Formatter},
     io::{self, Write},
     marker::PhantomData,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
 };
 use thiserror::Error;
 
+use crate::{
+    jsonrpc::Incoming,
+    traffic::{Direction, TrafficLogger},
+};
+
+/// The number of bytes of invalid input kept for error reporting.
+const ERROR_SNIPPET_LEN: usize = 256;
+
 /// Errors that can occur when processing an LSP request.
 #[derive(Debug, Error)]
 pub enum ParseError {
@@ -29,9 +48,23 @@ pub enum ParseError {
     /// Request lacks the required `Content-Length` header.
     #[error("missing required `Content-Length` header")]
     MissingHeader,
+    /// The `Content-Length` of the message exceeds the maximum frame size of the server.
+    #[error("message of {0} bytes exceeds the maximum frame size")]
+    TooLarge(usize),
+    /// The body of the message was cut off by the headers of the next message.
+    #[error("message body was cut off by the next message")]
+    Truncated,
     /// Request contains invalid UTF8.
     #[error("request contains invalid UTF-8: {0}")]
     Utf8(std::str::Utf8Error),
+    /// The `Content-Encoding` header names an unsupported compression.
+    #[cfg(feature = "compression")]
+    #[error("unsupported content encoding: {0}")]
+    UnsupportedEncoding(String),
+    /// Failed to decompress the body.
+    #[cfg(feature = "compression")]
+    #[error("failed to decompress body: {0}")]
+    Decompress(io::Error),
 }
 
 impl From<io::Error> for ParseError {
@@ -52,29 +85,206 @@ impl From<std::str::Utf8Error> for ParseError {
     }
 }
 
+/// Messages which can be decoded by the [`LanguageServerCodec`].
+pub trait DecodeJson: Sized {
+    /// Deserializes a message from its JSON text.
+    fn from_json(message: &str) -> serde_json::Result<Self>;
+}
+
+impl DecodeJson for serde_json::Value {
+    fn from_json(message: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(message)
+    }
+}
+
+impl DecodeJson for Incoming {
+    fn from_json(message: &str) -> serde_json::Result<Self> {
+        Incoming::from_json(message)
+    }
+}
+
+/// A message as it is read from or written to the transport, passed to the inspector set with
+/// [`ServerBuilder::inspect_frames`].
+///
+/// [`ServerBuilder::inspect_frames`]: crate::ServerBuilder::inspect_frames
+#[derive(Clone, Copy, Debug)]
+pub struct Frame<'a> {
+    direction: Direction,
+    headers: &'a [u8],
+    body: &'a [u8],
+}
+
+impl<'a> Frame<'a> {
+    /// Returns whether the message was read from or written to the transport.
+    pub fn direction(&self) -> Direction {
+        self.direction
+    }
+
+    /// Returns the headers of the message, including the empty line ending them.
+    pub fn headers(&self) -> &'a [u8] {
+        self.headers
+    }
+
+    /// Returns the body of the message, which is compressed if the headers name a
+    /// `Content-Encoding`.
+    pub fn body(&self) -> &'a [u8] {
+        self.body
+    }
+}
+
+/// Callback invoked with each [`Frame`] passing through a [`LanguageServerCodec`].
+#[derive(Clone)]
+pub(crate) struct FrameInspector(Arc<dyn Fn(&Frame) + Send + Sync>);
+
+impl FrameInspector {
+    pub(crate) fn new<F>(inspect: F) -> Self
+    where
+        F: Fn(&Frame) + Send + Sync + 'static,
+    {
+        FrameInspector(Arc::new(inspect))
+    }
+
+    fn inspect(&self, direction: Direction, frame: &[u8], body_len: usize) {
+        let (headers, body) = frame.split_at(frame.len() - body_len);
+        (self.0)(&Frame { direction, headers, body });
+    }
+}
+
+impl Debug for FrameInspector {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_tuple(stringify!(FrameInspector)).finish_non_exhaustive()
+    }
+}
+
 /// Encodes and decodes Language Server Protocol messages.
 #[derive(Clone, Debug)]
 pub struct LanguageServerCodec<T> {
-    http_error: Option<httparse::Error>,
     headers_len: Option<usize>,
     content_len: Option<usize>,
+    #[cfg(feature = "compression")]
+    content_encoding: Option<String>,
+    #[cfg(feature = "compression")]
+    compression: Option<Compression>,
+    logger: TrafficLogger,
+    error_snippet: Option<Vec<u8>>,
+    resync: Option<usize>,
+    scanned: usize,
+    /// The number of bytes of the body of a rejected message which were not skipped yet.
+    discard: usize,
+    skipped_bytes: Arc<AtomicU64>,
+    inspector: Option<FrameInspector>,
+    max_frame_size: Option<usize>,
     _marker: PhantomData<T>,
 }
 
 impl<T> LanguageServerCodec<T> {
+    /// Creates a new codec which logs the messages passing through it with the given logger.
+    pub fn with_logger(logger: TrafficLogger) -> Self {
+        LanguageServerCodec {
+            logger,
+            ..Default::default()
+        }
+    }
+
+    /// Compresses the bodies of the encoded messages, if they are large enough.
+    #[cfg(feature = "compression")]
+    pub fn with_compression(mut self, compression: Option<Compression>) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Returns the start of the input which failed to decode last, if any.
+    pub fn take_error_snippet(&mut self) -> Option<Vec<u8>> {
+        self.error_snippet.take()
+    }
+
+    /// Adds the number of bytes of invalid input skipped to resynchronize with the stream of
+    /// messages, such as garbage between messages or the remains of torn messages, to the given
+    /// counter.
+    pub fn with_skipped_bytes(mut self, counter: Arc<AtomicU64>) -> Self {
+        self.skipped_bytes = counter;
+        self
+    }
+
+    /// Invokes the given inspector with each message read or written, before it is decoded or
+    /// after it was encoded.
+    pub(crate) fn with_inspector(mut self, inspector: Option<FrameInspector>) -> Self {
+        self.inspector = inspector;
+        self
+    }
+
+    /// Rejects messages read whose `Content-Length` exceeds the given number of bytes, skipping
+    /// their bodies without buffering them.
+    pub(crate) fn with_max_frame_size(mut self, max_frame_size: Option<usize>) -> Self {
+        self.max_frame_size = max_frame_size;
+        self
+    }
+
     fn reset(&mut self) {
-        self.http_error = None;
         self.headers_len = None;
         self.content_len = None;
+        self.scanned = 0;
+        #[cfg(feature = "compression")]
+        {
+            self.content_encoding = None;
+        }
+    }
+
+    fn write_message(&mut self, msg: &str, dst: &mut BytesMut) -> Result<(), ParseError> {
+        self.logger.log(Direction::Outgoing, msg);
+        let start = dst.len();
+        let body_len = self.write_frame(msg, dst)?;
+        if let Some(inspector) = &self.inspector {
+            inspector.inspect(Direction::Outgoing, &dst[start ..], body_len);
+        }
+        Ok(())
+    }
+
+    /// Writes the headers and body of the message, returning the length of the body.
+    fn write_frame(&mut self, msg: &str, dst: &mut BytesMut) -> Result<usize, ParseError> {
+        #[cfg(feature = "compression")]
+        if let Some(encoding) = self.compression.and_then(|compression| compression.encoding_for(msg.len())) {
+            let body = encoding.compress(msg.as_bytes())?;
+            let mut writer = dst.writer();
+            write!(
+                writer,
+                "Content-Length: {}\r\nContent-Encoding: {}\r\n\r\n",
+                body.len(),
+                encoding.name()
+            )?;
+            writer.write_all(&body)?;
+            writer.flush()?;
+            return Ok(body.len());
+        }
+
+        // Reserve just enough space to hold the `Content-Length: ` and `\r\n\r\n` constants,
+        // the length of the message, and the message body.
+        dst.reserve(msg.len() + number_of_digits(msg.len()) + 20);
+        let mut writer = dst.writer();
+        write!(writer, "Content-Length: {}\r\n\r\n{}", msg.len(), msg)?;
+        writer.flush()?;
+
+        Ok(msg.len())
     }
 }
 
 impl<T> Default for LanguageServerCodec<T> {
     fn default() -> Self {
         LanguageServerCodec {
-            http_error: None,
             headers_len: None,
             content_len: None,
+            #[cfg(feature = "compression")]
+            content_encoding: None,
+            #[cfg(feature = "compression")]
+            compression: None,
+            logger: TrafficLogger::default(),
+            error_snippet: None,
+            resync: None,
+            scanned: 0,
+            discard: 0,
+            skipped_bytes: Default::default(),
+            inspector: None,
+            max_frame_size: None,
             _marker: PhantomData,
         }
     }
@@ -87,16 +297,7 @@ impl<T: serde::Serialize> Encoder for LanguageServerCodec<T> {
 
     fn encode(&mut self, item: Self::Item, dst: &mut BytesMut) -> Result<(), Self::Error> {
         let msg = serde_json::to_string(&item)?;
-        log::trace!("-> {}", msg);
-
-        // Reserve just enough space to hold the `Content-Length: ` and `\r\n\r\n` constants,
-        // the length of the message, and the message body.
-        dst.reserve(msg.len() + number_of_digits(msg.len()) + 20);
-        let mut writer = dst.writer();
-        write!(writer, "Content-Length: {}\r\n\r\n{}", msg.len(), msg)?;
-        writer.flush()?;
-
-        Ok(())
+        self.write_message(&msg, dst)
     }
 }
 
@@ -106,16 +307,21 @@ impl<T: serde::Serialize> Encoder<T> for LanguageServerCodec<T> {
 
     fn encode(&mut self, item: T, dst: &mut BytesMut) -> Result<(), Self::Error> {
         let msg = serde_json::to_string(&item)?;
-        log::trace!("-> {}", msg);
-
-        // Reserve just enough space to hold the `Content-Length: ` and `\r\n\r\n` constants,
-        // the length of the message, and the message body.
-        dst.reserve(msg.len() + number_of_digits(msg.len()) + 20);
-        let mut writer = dst.writer();
-        write!(writer, "Content-Length: {}\r\n\r\n{}", msg.len(), msg)?;
-        writer.flush()?;
+        self.write_message(&msg, dst)
+    }
+}
 
-        Ok(())
+/// Invokes `f` with the codec error of a framed reader, which wraps it together with the errors of
+/// the underlying reader.
+#[cfg(feature = "runtime-agnostic")]
+pub(crate) fn with_parse_error<R>(error: &(dyn std::error::Error + 'static), f: impl FnOnce(&ParseError) -> R) -> R {
+    let source = error.source();
+    match source.and_then(|source| source.downcast_ref::<ParseError>()) {
+        Some(error) => f(error),
+        None => {
+            let kind = source.and_then(|source| source.downcast_ref::<io::Error>()).map(io::Error::kind);
+            f(&ParseError::Encode(io::Error::new(kind.unwrap_or(io::ErrorKind::Other), error.to_string())))
+        },
     }
 }
 
@@ -131,40 +337,93 @@ fn number_of_digits(mut n: usize) -> usize {
     num_digits
 }
 
-impl<T: serde::de::DeserializeOwned> Decoder for LanguageServerCodec<T> {
+#[cfg(feature = "compression")]
+fn decompress(name: &str, body: &[u8], limit: Option<usize>) -> Result<Vec<u8>, ParseError> {
+    let encoding = ContentEncoding::from_name(name).ok_or_else(|| ParseError::UnsupportedEncoding(name.into()))?;
+    encoding.decompress(body, limit).map_err(ParseError::Decompress)
+}
+
+impl<T: DecodeJson> Decoder for LanguageServerCodec<T> {
     type Error = ParseError;
     type Item = T;
 
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        // Keep the start of the buffer, which the decoder advances past invalid messages
+        let mut snippet = [0; ERROR_SNIPPET_LEN];
+        let snippet_len = src.len().min(ERROR_SNIPPET_LEN);
+        snippet[.. snippet_len].copy_from_slice(&src[.. snippet_len]);
+
+        let result = self.decode_message(src);
+        if result.is_err() {
+            self.error_snippet = Some(snippet[.. snippet_len].to_vec());
+        }
+        result
+    }
+}
+
+impl<T: DecodeJson> LanguageServerCodec<T> {
+    fn decode_message(&mut self, src: &mut BytesMut) -> Result<Option<T>, ParseError> {
+        // Skip the body of a message which was too large as it arrives
+        if self.discard > 0 {
+            let len = self.discard.min(src.len());
+            src.advance(len);
+            self.skipped_bytes.fetch_add(len as u64, Ordering::SeqCst);
+            self.discard -= len;
+            if self.discard > 0 {
+                return Ok(None);
+            }
+        }
+
+        // After invalid input, skip ahead to the earliest complete set of headers
+        if let Some(from) = self.resync {
+            match find_headers(src, from) {
+                Ok(offset) => {
+                    self.skip(src, offset);
+                    self.resync = None;
+                },
+                Err(resume) => {
+                    // Keep the start of a header which may be completed by more input
+                    self.skip(src, resume);
+                    self.resync = Some(0);
+                    return Ok(None);
+                },
+            }
+        }
+
         // Parse the headers first if necessary
         if self.headers_len.is_none() {
-            {
-                // Placeholder used for parsing headers into
-                let dst = &mut [httparse::EMPTY_HEADER; 2];
-
-                // Parse the headers and try to extract values
-                match httparse::parse_headers(src, dst) {
-                    // A complete set of headers was parsed succesfully
-                    Ok(httparse::Status::Complete((header_len, headers))) => {
-                        // If some headers were parsed successefully, set the headers length
-                        self.headers_len = Some(header_len);
-                        // Scan through the headers
-                        for header in headers {
-                            // If the "Content-Length" header is found, parse the value as a usize
-                            if header.name == "Content-Length" {
-                                let content_len = std::str::from_utf8(header.value)?;
-                                let content_len = content_len.parse().map_err(|_| ParseError::InvalidLength)?;
-                                self.content_len = Some(content_len);
-                            }
-                        }
-                    },
-                    // No errors occurred during parsing yet but no complete set of headers were parsed
-                    Ok(httparse::Status::Partial) => return Ok(None),
-                    // An error occurred during parsing of the headers
-                    Err(error) => {
-                        self.http_error = Some(error);
-                    },
-                }
+            // Placeholder used for parsing headers into
+            let dst = &mut [httparse::EMPTY_HEADER; 3];
+
+            // Parse the headers and try to extract values
+            match httparse::parse_headers(src, dst) {
+                // A complete set of headers was parsed succesfully
+                Ok(httparse::Status::Complete((header_len, headers))) => {
+                    // If some headers were parsed successefully, set the headers length
+                    self.headers_len = Some(header_len);
+                    // Parse the value of the "Content-Length" header as a usize
+                    match content_length(headers) {
+                        Ok(Some(content_len)) if self.max_frame_size.is_some_and(|max| content_len > max) => {
+                            // The headers are valid, so the body is skipped by its length
+                            self.reset();
+                            self.skip(src, header_len);
+                            self.discard = content_len;
+                            return Err(ParseError::TooLarge(content_len));
+                        },
+                        Ok(content_len) => self.content_len = content_len,
+                        Err(error) => return Err(self.fail(error)),
+                    }
+                    // If the "Content-Encoding" header is found, the body is decompressed
+                    #[cfg(feature = "compression")]
+                    if let Some(header) = headers.iter().find(|header| header.name == "Content-Encoding") {
+                        let name = String::from_utf8_lossy(header.value);
+                        self.content_encoding = Some(name.trim().to_owned());
+                    }
+                },
+                // No errors occurred during parsing yet but no complete set of headers were parsed
+                Ok(httparse::Status::Partial) => return Ok(None),
+                // An error occurred during parsing of the headers
+                Err(error) => return Err(self.fail(ParseError::Httparse(error))),
             }
         }
 
@@ -174,52 +433,128 @@ impl<T: serde::de::DeserializeOwned> Decoder for LanguageServerCodec<T> {
 
             // Source doesn't contain the full content yet so return and wait for more input
             if src.len() < delta {
-                return Ok(None);
+                // Unless the body is cut off by the headers of the next message, which cannot occur
+                // in valid JSON, since control characters have to be escaped in strings
+                return match find_headers(src, self.scanned.max(headers_len)) {
+                    Ok(offset) => {
+                        self.reset();
+                        self.skip(src, offset);
+                        Err(ParseError::Truncated)
+                    },
+                    Err(resume) => {
+                        self.scanned = resume;
+                        Ok(None)
+                    },
+                };
+            }
+
+            if let Some(inspector) = &self.inspector {
+                inspector.inspect(Direction::Incoming, &src[.. delta], content_len);
             }
 
             // Parse the JSON-RPC message bytes as JSON
             let message = &src[headers_len .. delta];
-            let message = std::str::from_utf8(message)?;
-
-            log::trace!("<- {}", message);
+            #[cfg(feature = "compression")]
+            let decompressed = match self.content_encoding.as_deref().map(|name| decompress(name, message, self.max_frame_size)) {
+                Some(Err(error)) => {
+                    // Skip the message, since its headers were valid
+                    self.reset();
+                    src.advance(delta);
+                    return Err(error);
+                },
+                decompressed => decompressed.and_then(Result::ok),
+            };
+            #[cfg(feature = "compression")]
+            let message = decompressed.as_deref().unwrap_or(&src[headers_len .. delta]);
 
             // Deserialize the JSON-RPC message JSON as data
-            let data = match serde_json::from_str(message) {
-                Ok(parsed) => Ok(Some(parsed)),
+            let data = match std::str::from_utf8(message) {
+                Ok(message) => {
+                    self.logger.log(Direction::Incoming, message);
+                    T::from_json(message).map(Some).map_err(ParseError::from)
+                },
                 Err(err) => Err(err.into()),
             };
 
             // Reset the codec state
             self.reset();
 
-            // Advance the buffer
-            src.advance(delta);
+            // If the message is invalid and contains the headers of the next message, it was cut
+            // off by them, so retry from there instead of skipping the next message as well
+            match data.as_ref().err().map(|_| find_headers(src, headers_len)) {
+                Some(Ok(offset)) if offset < delta => self.skip(src, offset),
+                Some(Err(offset)) if offset < delta => {
+                    // The headers of the next message may be incomplete yet
+                    self.skip(src, offset);
+                    self.resync = Some(0);
+                },
+                _ => src.advance(delta),
+            }
 
             // Return the deserialized data
             data
 
         // Headers were parsed but "Content-Length" wasn't found
         } else {
-            // Reset the codec state
-            self.reset();
+            Err(self.fail(ParseError::MissingHeader))
+        }
+    }
 
-            // Maybe there are garbage bytes so try to scan ahead for another "Content-Length"
-            if let Some(offset) = twoway::find_bytes(src, b"Content-Length") {
-                src.advance(offset);
-            }
+    /// Resets the codec to scan for the next set of headers after the input failed to decode.
+    fn fail(&mut self, error: ParseError) -> ParseError {
+        self.reset();
+        // The headers at the start of the input are invalid, so the scan starts after them
+        self.resync = Some(1);
+        error
+    }
 
-            // Handle the conditions that caused decoding to fail
-            if let Some(http_error) = self.http_error {
-                // There was an error parsing the headers
-                Err(ParseError::Httparse(http_error))
-            } else {
-                // There was no "Content-Length" header found
-                Err(ParseError::MissingHeader)
-            }
+    /// Skips the given number of bytes of invalid input.
+    fn skip(&mut self, src: &mut BytesMut, len: usize) {
+        if len > 0 {
+            log::warn!("skipping {} bytes of invalid input", len);
+            src.advance(len);
+            self.skipped_bytes.fetch_add(len as u64, Ordering::SeqCst);
         }
     }
 }
 
+/// Parses the value of the `Content-Length` header, if any.
+fn content_length(headers: &[httparse::Header]) -> Result<Option<usize>, ParseError> {
+    match headers.iter().find(|header| header.name == "Content-Length") {
+        Some(header) => {
+            let content_len = std::str::from_utf8(header.value)?;
+            content_len.parse().map(Some).map_err(|_| ParseError::InvalidLength)
+        },
+        None => Ok(None),
+    }
+}
+
+/// Finds the earliest complete set of headers with a valid `Content-Length` in `src`, starting the
+/// search at `from`.
+///
+/// Returns the offset of the headers, or otherwise the offset of the input which may still turn
+/// into such headers once more input arrived.
+fn find_headers(src: &[u8], mut from: usize) -> Result<usize, usize> {
+    const CONTENT_LENGTH: &[u8] = b"Content-Length";
+
+    while let Some(offset) = src.get(from ..).and_then(|src| twoway::find_bytes(src, CONTENT_LENGTH)) {
+        let start = from + offset;
+        let dst = &mut [httparse::EMPTY_HEADER; 3];
+        match httparse::parse_headers(&src[start ..], dst) {
+            Ok(httparse::Status::Complete((_, headers))) if matches!(content_length(headers), Ok(Some(_))) => {
+                return Ok(start);
+            },
+            Ok(httparse::Status::Partial) => return Err(start),
+            _ => from = start + 1,
+        }
+    }
+
+    // The end of the input may still be the start of a header
+    let tail = src.len().saturating_sub(CONTENT_LENGTH.len() - 1).max(from);
+    let partial = (tail .. src.len()).find(|&start| CONTENT_LENGTH.starts_with(&src[start ..]));
+    Err(partial.unwrap_or(src.len()))
+}
+
 #[cfg(test)]
 mod tests {
     use bytes::BytesMut;
@@ -233,7 +568,7 @@ mod tests {
         let content_len = "Content-Length: foo".to_string();
         let encoded = format!("{}\r\n\r\n{}", content_len, decoded);
 
-        let mut codec = LanguageServerCodec::<()>::default();
+        let mut codec = LanguageServerCodec::<Value>::default();
         let mut buffer = BytesMut::from(encoded.as_str());
         let message = codec.decode(&mut buffer);
         if let Err(ParseError::InvalidLength) = message {
@@ -277,7 +612,7 @@ mod tests {
         let content_len = "Content-Length: 42".to_string();
         let encoded = format!("{}\r\n\r\n", content_len);
 
-        let mut codec = LanguageServerCodec::<()>::default();
+        let mut codec = LanguageServerCodec::<Value>::default();
         let mut buffer = BytesMut::from(encoded.as_str());
         let message = codec.decode(&mut buffer);
         if let Ok(None) = message {
@@ -306,9 +641,8 @@ mod tests {
 
     #[test]
     fn parse_error_from_io_error() {
-        let kind = std::io::ErrorKind::Other;
         let error = "test error";
-        let error = std::io::Error::new(kind, error);
+        let error = std::io::Error::other(error);
         let _ = ParseError::from(error);
     }
 
@@ -322,6 +656,121 @@ mod tests {
         }
     }
 
+    #[cfg(feature = "compression")]
+    #[test]
+    fn encode_and_decode_compressed() {
+        let padding = "data".repeat(500);
+        let decoded: Value = serde_json::json!({ "jsonrpc": "2.0", "method": "foo", "params": padding });
+        let body = serde_json::to_string(&decoded).unwrap();
+
+        for encoding in [ContentEncoding::Gzip, ContentEncoding::Zstd] {
+            let mut codec = LanguageServerCodec::default().with_compression(Some(Compression::new(encoding)));
+            let mut buffer = BytesMut::new();
+            codec.encode(decoded.clone(), &mut buffer).unwrap();
+
+            let header = format!("\r\nContent-Encoding: {}\r\n\r\n", encoding.name());
+            assert!(twoway::find_bytes(&buffer, header.as_bytes()).is_some());
+            assert!(buffer.len() < body.len());
+
+            let message = codec.decode(&mut buffer).unwrap();
+            assert_eq!(message, Some(decoded.clone()));
+            assert!(buffer.is_empty());
+        }
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn skips_compression_of_small_messages() {
+        let decoded = r#"{"jsonrpc":"2.0","method":"exit"}"#.to_string();
+        let encoded = format!("Content-Length: {}\r\n\r\n{}", decoded.len(), decoded);
+
+        let compression = Compression::new(ContentEncoding::Gzip);
+        let mut codec = LanguageServerCodec::default().with_compression(Some(compression));
+        let mut buffer = BytesMut::new();
+        let item: Value = serde_json::from_str(&decoded).unwrap();
+        codec.encode(item, &mut buffer).unwrap();
+        assert_eq!(buffer, BytesMut::from(encoded.as_str()));
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn decodes_unsupported_encoding() {
+        let decoded = r#"{"jsonrpc":"2.0","method":"exit"}"#.to_string();
+        let encoded = format!("Content-Length: {}\r\nContent-Encoding: br\r\n\r\n{}", decoded.len(), decoded);
+
+        let mut codec = LanguageServerCodec::<Value>::default();
+        let mut buffer = BytesMut::from(encoded.as_str());
+        assert!(matches!(codec.decode(&mut buffer), Err(ParseError::UnsupportedEncoding(name)) if name == "br"));
+        assert!(buffer.is_empty());
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn recovers_from_decompression_error() {
+        let decoded = r#"{"jsonrpc":"2.0","method":"exit"}"#.to_string();
+        let corrupt = "not gzip";
+        let encoded = format!(
+            "Content-Length: {}\r\nContent-Encoding: gzip\r\n\r\n{}Content-Length: {}\r\n\r\n{}",
+            corrupt.len(),
+            corrupt,
+            decoded.len(),
+            decoded
+        );
+
+        let mut codec = LanguageServerCodec::default();
+        let mut buffer = BytesMut::from(encoded.as_str());
+        assert!(matches!(codec.decode(&mut buffer), Err(ParseError::Decompress(_))));
+
+        let message = codec.decode(&mut buffer).unwrap();
+        let decoded: Value = serde_json::from_str(&decoded).unwrap();
+        assert_eq!(message, Some(decoded));
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn rejects_oversized_decompressed_body() {
+        let padding = "data".repeat(500);
+        let decoded: Value = serde_json::json!({ "jsonrpc": "2.0", "method": "foo", "params": padding });
+        let body = serde_json::to_string(&decoded).unwrap();
+
+        for encoding in [ContentEncoding::Gzip, ContentEncoding::Zstd] {
+            let mut codec = LanguageServerCodec::default().with_compression(Some(Compression::new(encoding)));
+            let mut buffer = BytesMut::new();
+            codec.encode(decoded.clone(), &mut buffer).unwrap();
+            codec.encode(decoded.clone(), &mut buffer).unwrap();
+
+            let mut codec = codec.with_max_frame_size(Some(body.len() - 1));
+            let error = codec.decode(&mut buffer).unwrap_err();
+            assert!(matches!(error, ParseError::Decompress(e) if e.kind() == io::ErrorKind::InvalidData));
+
+            let mut codec = codec.with_max_frame_size(Some(body.len()));
+            assert_eq!(codec.decode(&mut buffer).unwrap(), Some(decoded.clone()));
+            assert!(buffer.is_empty());
+        }
+    }
+
+    #[test]
+    fn skips_oversized_body() {
+        let decoded = r#"{"jsonrpc":"2.0","method":"exit"}"#.to_string();
+        let encoded = format!("Content-Length: {}\r\n\r\n{}", decoded.len(), decoded);
+        let large = format!("Content-Length: {}\r\n\r\n", encoded.len() + 10);
+
+        let skipped = Arc::new(AtomicU64::new(0));
+        let codec = LanguageServerCodec::<Value>::default().with_skipped_bytes(skipped.clone());
+        let mut codec = codec.with_max_frame_size(Some(decoded.len()));
+        let mut buffer = BytesMut::from(large.as_str());
+        assert!(matches!(codec.decode(&mut buffer), Err(ParseError::TooLarge(len)) if len == encoded.len() + 10));
+
+        // The body is skipped by its length, even if it looks like a message.
+        buffer.extend_from_slice(encoded.as_bytes());
+        assert_eq!(codec.decode(&mut buffer).unwrap(), None);
+        buffer.extend_from_slice(format!("0123456789{}", encoded).as_bytes());
+        let message = codec.decode(&mut buffer).unwrap();
+        assert_eq!(message, Some(serde_json::from_str(&decoded).unwrap()));
+        assert!(buffer.is_empty());
+        assert_eq!(skipped.load(Ordering::SeqCst), (large.len() + encoded.len() + 10) as u64);
+    }
+
     #[test]
     fn recovers_from_parse_error() {
         let decoded = r#"{"jsonrpc":"2.0","method":"exit"}"#.to_string();
@@ -332,9 +781,85 @@ mod tests {
         let mut buffer = BytesMut::from(mixed.as_str());
 
         assert!(matches!(codec.decode(&mut buffer), Err(ParseError::MissingHeader)));
+        assert_eq!(codec.take_error_snippet().unwrap(), mixed.as_bytes());
+        assert_eq!(codec.take_error_snippet(), None);
 
         let message = codec.decode(&mut buffer).unwrap();
         let decoded: Value = serde_json::from_str(&decoded).unwrap();
         assert_eq!(message, Some(decoded));
+        assert_eq!(codec.skipped_bytes.load(Ordering::SeqCst), 18);
+    }
+
+    #[test]
+    fn recovers_from_torn_frames() {
+        let decoded = r#"{"jsonrpc":"2.0","method":"exit"}"#.to_string();
+        let encoded = format!("Content-Length: {}\r\n\r\n{}", decoded.len(), decoded);
+        let decoded: Value = serde_json::from_str(&decoded).unwrap();
+
+        // The torn body is shorter than the next message
+        let torn = "Content-Length: 40\r\n\r\n{\"jsonrpc\"";
+        let mut codec = LanguageServerCodec::default();
+        let mut buffer = BytesMut::from(format!("{}{}", torn, encoded).as_str());
+        assert!(matches!(codec.decode(&mut buffer), Err(ParseError::Body(_))));
+        assert_eq!(codec.decode(&mut buffer).unwrap(), Some(decoded.clone()));
+        assert!(buffer.is_empty());
+        assert_eq!(codec.skipped_bytes.load(Ordering::SeqCst), torn.len() as u64);
+
+        // The torn body claims more input than follows
+        let torn = "Content-Length: 4000\r\n\r\n{\"jsonrpc\"";
+        let mut codec = LanguageServerCodec::default();
+        let mut buffer = BytesMut::from(format!("{}{}", torn, encoded).as_str());
+        assert!(matches!(codec.decode(&mut buffer), Err(ParseError::Truncated)));
+        assert_eq!(codec.decode(&mut buffer).unwrap(), Some(decoded.clone()));
+        assert!(buffer.is_empty());
+
+        // The torn header runs into the next header
+        let torn = "Content-Length: 12";
+        let mut codec = LanguageServerCodec::default();
+        let mut buffer = BytesMut::from(format!("{}{}", torn, encoded).as_str());
+        assert!(matches!(codec.decode(&mut buffer), Err(ParseError::InvalidLength)));
+        assert_eq!(codec.decode(&mut buffer).unwrap(), Some(decoded));
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn recovers_from_garbage_bursts() {
+        let messages: Vec<Value> = (0 .. 4).map(|i| serde_json::json!({ "jsonrpc": "2.0", "id": i })).collect();
+        let encode = |message: &Value| {
+            let body = message.to_string();
+            format!("Content-Length: {}\r\n\r\n{}", body.len(), body)
+        };
+        let garbage = [
+            "\u{0}\u{ff}noise",
+            "Content-Length: 5\r\n\r\n{\"js",
+            "Content-Length: x\r\nContent-Len",
+            "\r\n\r\nfoo: bar\r\n\r\n",
+        ];
+        let mut input = String::new();
+        for (garbage, message) in garbage.iter().zip(&messages) {
+            input.push_str(garbage);
+            input.push_str(&encode(message));
+        }
+
+        // Feed the input in chunks of various sizes, as received from a slow transport
+        for chunk_len in [1, 7, 64, input.len()] {
+            let mut codec = LanguageServerCodec::<Value>::default();
+            let mut buffer = BytesMut::new();
+            let mut decoded = Vec::new();
+            for chunk in input.as_bytes().chunks(chunk_len) {
+                buffer.extend_from_slice(chunk);
+                loop {
+                    match codec.decode(&mut buffer) {
+                        Ok(Some(message)) => decoded.push(message),
+                        Ok(None) => break,
+                        Err(_) => continue,
+                    }
+                }
+            }
+            assert_eq!(decoded, messages, "chunks of {} bytes", chunk_len);
+            assert!(buffer.is_empty());
+            let skipped = garbage.iter().map(|garbage| garbage.len() as u64).sum::<u64>();
+            assert_eq!(codec.skipped_bytes.load(Ordering::SeqCst), skipped, "chunks of {} bytes", chunk_len);
+        }
     }
 }
@@ -0,0 +1,108 @@
+//! Compression of message bodies for remote transports.
+
+use std::io::{self, Read, Write};
+
+/// Compression algorithm of the body of a message, announced by its `Content-Encoding` header.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ContentEncoding {
+    /// The `gzip` format, for peers without `zstd` support.
+    Gzip,
+    /// The `zstd` format, which compresses faster and usually smaller than `gzip`.
+    Zstd,
+}
+
+impl ContentEncoding {
+    /// Returns the value of the `Content-Encoding` header of messages compressed with this encoding.
+    pub fn name(self) -> &'static str {
+        match self {
+            ContentEncoding::Gzip => "gzip",
+            ContentEncoding::Zstd => "zstd",
+        }
+    }
+
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "gzip" => Some(ContentEncoding::Gzip),
+            "zstd" => Some(ContentEncoding::Zstd),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn compress(self, data: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            ContentEncoding::Gzip => {
+                let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()
+            },
+            ContentEncoding::Zstd => zstd::encode_all(data, 0),
+        }
+    }
+
+    /// Decompresses a message body, failing with [`io::ErrorKind::InvalidData`] once it exceeds
+    /// `limit` bytes, so that a small body cannot expand without bounds.
+    pub(crate) fn decompress(self, data: &[u8], limit: Option<usize>) -> io::Result<Vec<u8>> {
+        let limit = limit.map_or(u64::MAX, |limit| limit as u64);
+        let mut decompressed = Vec::new();
+        match self {
+            ContentEncoding::Gzip => flate2::read::GzDecoder::new(data)
+                .take(limit.saturating_add(1))
+                .read_to_end(&mut decompressed)?,
+            ContentEncoding::Zstd => zstd::Decoder::new(data)?
+                .take(limit.saturating_add(1))
+                .read_to_end(&mut decompressed)?,
+        };
+        if decompressed.len() as u64 > limit {
+            let message = format!("decompressed body exceeds the maximum frame size of {} bytes", limit);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+        }
+        Ok(decompressed)
+    }
+}
+
+/// Compression of the messages written by a [`Server`], for high-latency remote connections where
+/// large payloads such as semantic tokens or diagnostics dominate.
+///
+/// Set with [`Server::compression`]. Requires the `compression` feature.
+///
+/// The protocol has no way to negotiate compression, so both peers must agree on it out-of-band,
+/// for instance through a command line argument of the server. Compressed messages carry a
+/// `Content-Encoding` header next to their `Content-Length`, which counts the compressed bytes.
+/// Messages smaller than [`min_size`](Compression::min_size) are written uncompressed, since the
+/// compression would not pay off. Incoming messages are decompressed according to their
+/// `Content-Encoding` header, whether or not compression of the outgoing ones is enabled. The
+/// [`max_frame_size`](crate::ServerBuilder::max_frame_size) bounds their decompressed size as well.
+///
+/// [`Server`]: crate::Server
+/// [`Server::compression`]: crate::Server::compression
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Compression {
+    encoding: ContentEncoding,
+    min_size: usize,
+}
+
+impl Compression {
+    /// Creates a new `Compression` with the given encoding, compressing messages of at least 1 KiB.
+    pub fn new(encoding: ContentEncoding) -> Self {
+        Compression {
+            encoding,
+            min_size: 1024,
+        }
+    }
+
+    /// Sets the size in bytes from which the bodies of messages are compressed.
+    pub fn min_size(mut self, bytes: usize) -> Self {
+        self.min_size = bytes;
+        self
+    }
+
+    /// Returns the encoding of the compressed messages.
+    pub fn encoding(&self) -> ContentEncoding {
+        self.encoding
+    }
+
+    /// Returns the encoding to compress a message body of the given length with, if any.
+    pub(crate) fn encoding_for(&self, len: usize) -> Option<ContentEncoding> {
+        Some(self.encoding).filter(|_| len >= self.min_size)
+    }
+}
@@ -0,0 +1,210 @@
+//! Dispatching of `workspace/executeCommand` requests to typed command handlers.
+
+use crate::jsonrpc::{Error, Result};
+use futures::future::{BoxFuture, FutureExt};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{json, Value};
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Debug, Formatter},
+    future::Future,
+    sync::Arc,
+};
+
+type CommandHandler = Arc<dyn Fn(Vec<Value>) -> BoxFuture<'static, Result<Option<Value>>> + Send + Sync>;
+
+/// Registry of commands executed through the [`workspace/executeCommand`] request.
+///
+/// Each command is registered with an async handler taking a typed argument. When a registry is
+/// set with [`LspServiceBuilder::commands`], `workspace/executeCommand` requests are dispatched to
+/// the registered handlers instead of [`LanguageServer::execute_command`], and the list of
+/// commands is filled into [`ServerCapabilities::execute_command_provider`] of the
+/// `initialize` response unless the server set it explicitly.
+///
+/// The `arguments` of the request are deserialized into the argument type of the handler. Both
+/// tuples, which are deserialized from the whole argument list, and single values, which are
+/// deserialized from the only argument, are supported. Handlers taking `()` accept an empty
+/// argument list.
+///
+/// # Example
+///
+/// ```rust
+/// # use lspower::CommandRegistry;
+/// # #[derive(serde::Deserialize)]
+/// # struct Location { line: u32 }
+/// let commands = CommandRegistry::new()
+///     .command("example.greet", |(name, excited): (String, bool)| async move {
+///         Ok(if excited { format!("Hello, {}!", name) } else { format!("Hello, {}.", name) })
+///     })
+///     .command("example.jump", |location: Location| async move { Ok(location.line) })
+///     .command("example.reload", |()| async { Ok(()) });
+/// ```
+///
+/// [`workspace/executeCommand`]: https://microsoft.github.io/language-server-protocol/specification#workspace_executeCommand
+/// [`LspServiceBuilder::commands`]: crate::LspServiceBuilder::commands
+/// [`LanguageServer::execute_command`]: crate::LanguageServer::execute_command
+/// [`ServerCapabilities::execute_command_provider`]: lsp::ServerCapabilities::execute_command_provider
+#[derive(Clone, Default)]
+pub struct CommandRegistry {
+    commands: BTreeMap<String, CommandHandler>,
+}
+
+impl CommandRegistry {
+    /// Creates a new, empty `CommandRegistry`.
+    pub fn new() -> Self {
+        CommandRegistry::default()
+    }
+
+    /// Registers a command with the given name and handler.
+    ///
+    /// If a command with the same name was registered before, its handler is replaced.
+    pub fn command<A, R, F, Fut>(mut self, name: impl Into<String>, handler: F) -> Self
+    where
+        A: DeserializeOwned + Send + 'static,
+        R: Serialize,
+        F: Fn(A) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<R>> + Send + 'static,
+    {
+        let name = name.into();
+        let command = name.clone();
+        let handler = Arc::new(handler);
+        let handler: CommandHandler = Arc::new(move |arguments| {
+            let handler = handler.clone();
+            let arguments = deserialize_arguments::<A>(&command, arguments);
+            async move {
+                let result = handler(arguments?).await?;
+                match serde_json::to_value(result) {
+                    Ok(Value::Null) => Ok(None),
+                    Ok(value) => Ok(Some(value)),
+                    Err(error) => {
+                        log::error!("failed to serialize command result: {}", error);
+                        Err(Error::internal_error())
+                    },
+                }
+            }
+            .boxed()
+        });
+        self.commands.insert(name, handler);
+        self
+    }
+
+    /// Returns the names of all registered commands, in lexicographic order.
+    pub fn commands(&self) -> Vec<String> {
+        self.commands.keys().cloned().collect()
+    }
+
+    /// Returns the options to be advertised as [`ServerCapabilities::execute_command_provider`].
+    ///
+    /// [`ServerCapabilities::execute_command_provider`]: lsp::ServerCapabilities::execute_command_provider
+    pub fn options(&self) -> lsp::ExecuteCommandOptions {
+        lsp::ExecuteCommandOptions {
+            commands: self.commands(),
+            work_done_progress_options: Default::default(),
+        }
+    }
+
+    /// Executes the command requested by `params`.
+    ///
+    /// Returns an "invalid params" error (`-32602`) if the command is unknown or its arguments
+    /// cannot be deserialized. The `data` of the error contains the name of the command, along with
+    /// the list of known commands in case the command is unknown.
+    pub async fn execute(&self, params: lsp::ExecuteCommandParams) -> Result<Option<Value>> {
+        match self.commands.get(&params.command) {
+            Some(handler) => handler(params.arguments).await,
+            None => {
+                let message = format!("unknown command {:?}", params.command);
+                let data = json!({ "command": params.command, "commands": self.commands() });
+                Err(Error::invalid_params(message).with_data(data))
+            },
+        }
+    }
+}
+
+impl Debug for CommandRegistry {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct(stringify!(CommandRegistry))
+            .field("commands", &self.commands.keys())
+            .finish()
+    }
+}
+
+fn deserialize_arguments<A: DeserializeOwned>(command: &str, arguments: Vec<Value>) -> Result<A> {
+    let single = match arguments.as_slice() {
+        [] => Some(Value::Null),
+        [argument] => Some(argument.clone()),
+        _ => None,
+    };
+
+    serde_json::from_value(Value::Array(arguments))
+        .or_else(|error| match single {
+            Some(argument) => serde_json::from_value(argument),
+            None => Err(error),
+        })
+        .map_err(|error| {
+            let message = format!("invalid arguments for command {:?}: {}", command, error);
+            Error::invalid_params(message).with_data(json!({ "command": command }))
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jsonrpc::ErrorCode;
+    use serde::Deserialize;
+
+    #[derive(Deserialize)]
+    struct Point {
+        x: i32,
+        y: i32,
+    }
+
+    fn params(command: &str, arguments: Vec<Value>) -> lsp::ExecuteCommandParams {
+        lsp::ExecuteCommandParams {
+            command: command.into(),
+            arguments,
+            work_done_progress_params: Default::default(),
+        }
+    }
+
+    fn registry() -> CommandRegistry {
+        CommandRegistry::new()
+            .command("add", |(a, b): (i32, i32)| async move { Ok(a + b) })
+            .command("norm", |point: Point| async move { Ok(point.x.abs() + point.y.abs()) })
+            .command("noop", |()| async { Ok(()) })
+    }
+
+    #[tokio::test]
+    async fn execute() {
+        let registry = registry();
+        let result = registry.execute(params("add", vec![json!(1), json!(2)])).await;
+        assert_eq!(result, Ok(Some(json!(3))));
+        let result = registry.execute(params("norm", vec![json!({ "x": -1, "y": 2 })])).await;
+        assert_eq!(result, Ok(Some(json!(3))));
+        let result = registry.execute(params("noop", vec![])).await;
+        assert_eq!(result, Ok(None));
+    }
+
+    #[tokio::test]
+    async fn invalid_arguments() {
+        let registry = registry();
+        let error = registry.execute(params("add", vec![json!("1")])).await.unwrap_err();
+        assert_eq!(error.code, ErrorCode::InvalidParams);
+        assert_eq!(error.data, Some(json!({ "command": "add" })));
+    }
+
+    #[tokio::test]
+    async fn unknown_command() {
+        let registry = registry();
+        let error = registry.execute(params("unknown", vec![])).await.unwrap_err();
+        assert_eq!(error.code, ErrorCode::InvalidParams);
+        assert_eq!(
+            error.data,
+            Some(json!({ "command": "unknown", "commands": ["add", "noop", "norm"] }))
+        );
+    }
+
+    #[test]
+    fn options() {
+        assert_eq!(registry().options().commands, vec!["add", "noop", "norm"]);
+    }
+}
@@ -0,0 +1,276 @@
+//! Compatibility shims easing the migration from other LSP frameworks.
+
+/// Items of `tower-lsp` under their original paths.
+///
+/// Since `lspower` started as a fork of `tower-lsp`, backends written against it mostly compile
+/// after replacing `tower_lsp` with `lspower::compat::tower_lsp` in their imports. This module
+/// re-exports the `lspower` counterparts under the paths and names used by `tower-lsp`, including
+/// `lsp_types` and the three-argument `Server::new`, so that large backends can switch first and
+/// adopt the `lspower` APIs incrementally afterwards.
+///
+/// # Example
+///
+/// ```rust
+/// use lspower::compat::tower_lsp::{jsonrpc::Result, lsp_types::*, Client, LanguageServer, LspService, Server};
+///
+/// #[derive(Debug)]
+/// struct Backend {
+///     client: Client,
+/// }
+///
+/// #[tower_lsp::async_trait]
+/// impl LanguageServer for Backend {
+///     async fn initialize(&self, _: InitializeParams) -> Result<InitializeResult> {
+///         Ok(InitializeResult::default())
+///     }
+///
+///     async fn shutdown(&self) -> Result<()> {
+///         Ok(())
+///     }
+/// }
+/// # use lspower::compat::tower_lsp;
+///
+/// # async fn serve() {
+/// let (stdin, stdout) = (tokio::io::stdin(), tokio::io::stdout());
+/// let (service, socket) = LspService::new(|client| Backend { client });
+/// Server::new(stdin, stdout, socket).serve(service).await;
+/// # }
+/// ```
+///
+/// Backends which cannot switch their imports yet, for instance because they also depend on other
+/// crates built on `tower-lsp`, can be served unchanged through [`TowerLspBackend`] instead.
+pub mod tower_lsp {
+    pub use crate::{async_trait, jsonrpc, lsp as lsp_types, Client, LanguageServer, LspService};
+
+    #[cfg(feature = "tower-lsp-compat")]
+    mod adapter;
+
+    #[cfg(feature = "tower-lsp-compat")]
+    pub use self::adapter::TowerLspBackend;
+
+    /// Stream of messages from the server to the client, called `ClientSocket` in `tower-lsp`.
+    pub type ClientSocket = crate::MessageStream;
+
+    /// Constructor of [`crate::Server`] taking the client socket, as in `tower-lsp`.
+    #[derive(Debug)]
+    pub struct Server(());
+
+    #[cfg(feature = "runtime-tokio")]
+    impl Server {
+        /// Creates a new server with the given `stdin` and `stdout` handles, interleaving the
+        /// messages of the given socket into `stdout`.
+        #[allow(clippy::new_ret_no_self)]
+        pub fn new<I, O>(stdin: I, stdout: O, socket: ClientSocket) -> crate::Server<I, O, ClientSocket>
+        where
+            I: tokio::io::AsyncRead + Unpin,
+            O: tokio::io::AsyncWrite,
+        {
+            crate::Server::new(stdin, stdout).interleave(socket)
+        }
+    }
+
+    #[cfg(all(feature = "runtime-agnostic", not(feature = "runtime-tokio")))]
+    impl Server {
+        /// Creates a new server with the given `stdin` and `stdout` handles, interleaving the
+        /// messages of the given socket into `stdout`.
+        #[allow(clippy::new_ret_no_self)]
+        pub fn new<I, O>(stdin: I, stdout: O, socket: ClientSocket) -> crate::Server<I, O, ClientSocket>
+        where
+            I: futures::io::AsyncRead + Unpin,
+            O: futures::io::AsyncWrite,
+        {
+            crate::Server::new(stdin, stdout).interleave(socket)
+        }
+    }
+}
+
+/// Names of `lsp-types` 0.91 items which changed in the version `lspower::lsp` re-exports.
+///
+/// `lspower::lsp` always re-exports a single version of `lsp-types`, and `lspower` upgrades it in
+/// its own breaking releases. Where an upgrade renames items servers use everywhere, this module
+/// keeps the previous names working for one release cycle as deprecated shims, so that backends
+/// can upgrade `lspower` first and migrate to the new names guided by the deprecation warnings.
+/// Shims are removed with the next `lsp-types` upgrade.
+///
+/// `lsp-types` 0.92 turned the enums of numeric LSP values, such as [`MessageType`] and
+/// [`SymbolKind`], into structs with associated constants, so that values unknown to `lsp-types`
+/// survive deserialization. Importing the adapter traits of this module makes the variant names of
+/// 0.91 resolve to these constants, in expressions as well as patterns.
+///
+/// [`MessageType`]: crate::lsp::MessageType
+/// [`SymbolKind`]: crate::lsp::SymbolKind
+///
+/// # Example
+///
+/// ```rust
+/// # #![allow(deprecated)]
+/// use lspower::compat::lsp_types_0_91::*;
+/// use lspower::lsp::{MessageType, SymbolKind};
+///
+/// assert_eq!(MessageType::Info, MessageType::INFO);
+/// assert!(matches!(SymbolKind::FUNCTION, SymbolKind::Function));
+/// ```
+///
+/// Each use of a previous name is reported as deprecated:
+///
+/// ```rust,compile_fail
+/// #![deny(deprecated)]
+/// use lspower::compat::lsp_types_0_91::*;
+///
+/// let _ = lspower::lsp::MessageType::Warning;
+/// ```
+pub mod lsp_types_0_91 {
+    use crate::lsp;
+
+    macro_rules! adapter {
+        ($(#[$meta:meta])* $adapter:ident for $ty:ident { $($old:ident => $new:ident,)* }) => {
+            $(#[$meta])*
+            #[allow(non_upper_case_globals)]
+            pub trait $adapter {
+                $(
+                    #[doc = concat!("Renamed to [`", stringify!($new), "`](lsp::", stringify!($ty), "::", stringify!($new), ").")]
+                    #[deprecated(note = "renamed to the upper case constant in lsp-types 0.92")]
+                    const $old: lsp::$ty;
+                )*
+            }
+
+            #[allow(deprecated)]
+            impl $adapter for lsp::$ty {
+                $(const $old: lsp::$ty = lsp::$ty::$new;)*
+            }
+        };
+    }
+
+    adapter! {
+        /// Variant names of [`lsp::CompletionItemKind`] in `lsp-types` 0.91.
+        CompletionItemKindAdapter for CompletionItemKind {
+            Text => TEXT,
+            Method => METHOD,
+            Function => FUNCTION,
+            Constructor => CONSTRUCTOR,
+            Field => FIELD,
+            Variable => VARIABLE,
+            Class => CLASS,
+            Interface => INTERFACE,
+            Module => MODULE,
+            Property => PROPERTY,
+            Unit => UNIT,
+            Value => VALUE,
+            Enum => ENUM,
+            Keyword => KEYWORD,
+            Snippet => SNIPPET,
+            Color => COLOR,
+            File => FILE,
+            Reference => REFERENCE,
+            Folder => FOLDER,
+            EnumMember => ENUM_MEMBER,
+            Constant => CONSTANT,
+            Struct => STRUCT,
+            Event => EVENT,
+            Operator => OPERATOR,
+            TypeParameter => TYPE_PARAMETER,
+        }
+    }
+
+    adapter! {
+        /// Variant names of [`lsp::DiagnosticSeverity`] in `lsp-types` 0.91.
+        DiagnosticSeverityAdapter for DiagnosticSeverity {
+            Error => ERROR,
+            Warning => WARNING,
+            Information => INFORMATION,
+            Hint => HINT,
+        }
+    }
+
+    adapter! {
+        /// Variant names of [`lsp::DiagnosticTag`] in `lsp-types` 0.91.
+        DiagnosticTagAdapter for DiagnosticTag {
+            Unnecessary => UNNECESSARY,
+            Deprecated => DEPRECATED,
+        }
+    }
+
+    adapter! {
+        /// Variant names of [`lsp::FileChangeType`] in `lsp-types` 0.91.
+        FileChangeTypeAdapter for FileChangeType {
+            Created => CREATED,
+            Changed => CHANGED,
+            Deleted => DELETED,
+        }
+    }
+
+    adapter! {
+        /// Variant names of [`lsp::InsertTextFormat`] in `lsp-types` 0.91.
+        InsertTextFormatAdapter for InsertTextFormat {
+            PlainText => PLAIN_TEXT,
+            Snippet => SNIPPET,
+        }
+    }
+
+    adapter! {
+        /// Variant names of [`lsp::MessageType`] in `lsp-types` 0.91.
+        MessageTypeAdapter for MessageType {
+            Error => ERROR,
+            Warning => WARNING,
+            Info => INFO,
+            Log => LOG,
+        }
+    }
+
+    adapter! {
+        /// Variant names of [`lsp::SymbolKind`] in `lsp-types` 0.91.
+        SymbolKindAdapter for SymbolKind {
+            File => FILE,
+            Module => MODULE,
+            Namespace => NAMESPACE,
+            Package => PACKAGE,
+            Class => CLASS,
+            Method => METHOD,
+            Property => PROPERTY,
+            Field => FIELD,
+            Constructor => CONSTRUCTOR,
+            Enum => ENUM,
+            Interface => INTERFACE,
+            Function => FUNCTION,
+            Variable => VARIABLE,
+            Constant => CONSTANT,
+            String => STRING,
+            Number => NUMBER,
+            Boolean => BOOLEAN,
+            Array => ARRAY,
+            Object => OBJECT,
+            Key => KEY,
+            Null => NULL,
+            EnumMember => ENUM_MEMBER,
+            Struct => STRUCT,
+            Event => EVENT,
+            Operator => OPERATOR,
+            TypeParameter => TYPE_PARAMETER,
+        }
+    }
+
+    adapter! {
+        /// Variant names of [`lsp::SymbolTag`] in `lsp-types` 0.91.
+        SymbolTagAdapter for SymbolTag {
+            Deprecated => DEPRECATED,
+        }
+    }
+
+    adapter! {
+        /// Variant names of [`lsp::TextDocumentSaveReason`] in `lsp-types` 0.91.
+        TextDocumentSaveReasonAdapter for TextDocumentSaveReason {
+            Manual => MANUAL,
+            AfterDelay => AFTER_DELAY,
+            FocusOut => FOCUS_OUT,
+        }
+    }
+
+    adapter! {
+        /// Variant names of [`lsp::TextDocumentSyncKind`] in `lsp-types` 0.91.
+        TextDocumentSyncKindAdapter for TextDocumentSyncKind {
+            None => NONE,
+            Full => FULL,
+            Incremental => INCREMENTAL,
+        }
+    }
+}
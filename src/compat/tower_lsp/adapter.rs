@@ -0,0 +1,394 @@
+//! Serving backends written against `tower-lsp` with `lspower`.
+
+use crate::{
+    jsonrpc::Error,
+    lsp::{notification::*, request::*},
+    Client,
+};
+use futures::{
+    future::{self, AbortHandle, Abortable},
+    lock::Mutex,
+    FutureExt,
+    SinkExt,
+    StreamExt,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use std::{
+    borrow::Cow,
+    fmt::{self, Debug, Formatter},
+    sync::atomic::{AtomicI64, Ordering},
+};
+use tower_service::Service;
+
+/// Adapter implementing [`LanguageServer`] for a backend implementing the `LanguageServer` trait
+/// of `tower-lsp`, so that it can be served by `lspower` unchanged.
+///
+/// The backend is created by the `init` closure passed to [`new`], which receives a
+/// `tower_lsp::Client` like the closure passed to `tower_lsp::LspService::new`. The messages this
+/// client sends are forwarded through the [`Client`] of the `lspower` service, and the responses of
+/// the editor are passed back to it. Requests and notifications of the editor reach the backend
+/// through a `tower_lsp::LspService`, which keeps track of the lifecycle of the server as in
+/// `tower-lsp`. Methods unknown to `lspower` are passed to the backend as well. Messages the backend
+/// sends once it handled the `shutdown` request are discarded, so that they do not hold up the
+/// shutdown of the service.
+///
+/// Since `tower-lsp` depends on another version of `lsp-types` than `lspower`, parameters and
+/// results are converted through their JSON representation. Requires the `tower-lsp-compat`
+/// feature.
+///
+/// # Example
+///
+/// ```rust
+/// use lspower::{compat::tower_lsp::TowerLspBackend, LspService, Server};
+/// use tower_lsp::{jsonrpc::Result, lsp_types::*};
+///
+/// #[derive(Debug)]
+/// struct Backend {
+///     client: tower_lsp::Client,
+/// }
+///
+/// #[tower_lsp::async_trait]
+/// impl tower_lsp::LanguageServer for Backend {
+///     async fn initialize(&self, _: InitializeParams) -> Result<InitializeResult> {
+///         Ok(InitializeResult::default())
+///     }
+///
+///     async fn shutdown(&self) -> Result<()> {
+///         Ok(())
+///     }
+/// }
+///
+/// # async fn serve() {
+/// let (stdin, stdout) = (tokio::io::stdin(), tokio::io::stdout());
+/// let (service, messages) =
+///     LspService::new(|client| TowerLspBackend::new(client, |client| Backend { client }));
+/// Server::new(stdin, stdout).interleave(messages).serve(service).await;
+/// # }
+/// ```
+///
+/// [`LanguageServer`]: crate::LanguageServer
+/// [`new`]: TowerLspBackend::new
+pub struct TowerLspBackend<T: tower_lsp::LanguageServer> {
+    service: Mutex<tower_lsp::LspService<T>>,
+    next_id: AtomicI64,
+    forwarding: AbortHandle,
+}
+
+impl<T: tower_lsp::LanguageServer> TowerLspBackend<T> {
+    /// Creates the backend with the given `init` closure, forwarding the messages of the
+    /// `tower_lsp::Client` it receives through the given client.
+    pub fn new<F>(client: Client, init: F) -> Self
+    where
+        F: FnOnce(tower_lsp::Client) -> T,
+    {
+        let (service, socket) = tower_lsp::LspService::new(init);
+        let (forwarding, registration) = AbortHandle::new_pair();
+        let task = Abortable::new(forward_client(client.clone(), socket), registration);
+        client.spawn_background(task.map(drop));
+        TowerLspBackend {
+            service: Mutex::new(service),
+            next_id: AtomicI64::new(0),
+            forwarding,
+        }
+    }
+
+    async fn request<P, R>(&self, method: &'static str, params: P) -> crate::jsonrpc::Result<R>
+    where
+        P: Serialize,
+        R: DeserializeOwned,
+    {
+        let params = convert(params).map_err(|error| Error::invalid_params(error.to_string()))?;
+        let result = self.call(method.into(), Some(params), true).await?;
+        convert(result.unwrap_or_default()).map_err(|error| {
+            log::error!("invalid result of {:?} request: {}", method, error);
+            Error::internal_error().with_message(error.to_string())
+        })
+    }
+
+    async fn notify<P: Serialize>(&self, method: &'static str, params: P) {
+        match convert(params) {
+            Ok(params) => self.notify_value(method.into(), Some(params)).await,
+            Err(error) => log::error!("invalid parameters of {:?} notification: {}", method, error),
+        }
+    }
+
+    async fn notify_value(&self, method: Cow<'static, str>, params: Option<Value>) {
+        if let Err(error) = self.call(method.clone(), params, false).await {
+            log::error!("failed to handle {:?} notification: {}", method, error);
+        }
+    }
+
+    /// Calls the service of the backend, returning the result of a request.
+    async fn call(
+        &self,
+        method: Cow<'static, str>,
+        params: Option<Value>,
+        request: bool,
+    ) -> crate::jsonrpc::Result<Option<Value>> {
+        let mut builder = tower_lsp::jsonrpc::Request::build(method);
+        if let Some(params) = params {
+            builder = builder.params(params);
+        }
+        if request {
+            builder = builder.id(self.next_id.fetch_add(1, Ordering::Relaxed));
+        }
+
+        let response = {
+            let mut service = self.service.lock().await;
+            future::poll_fn(|cx| service.poll_ready(cx)).await.map_err(service_error)?;
+            service.call(builder.finish())
+        };
+        match response.await.map_err(service_error)? {
+            Some(response) => match response.into_parts().1 {
+                Ok(result) => Ok(Some(result)),
+                Err(error) => Err(convert(error).unwrap_or_else(|_| Error::internal_error())),
+            },
+            None => Ok(None),
+        }
+    }
+}
+
+impl<T: tower_lsp::LanguageServer> Debug for TowerLspBackend<T> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct(stringify!(TowerLspBackend))
+            .field("next_id", &self.next_id)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Forwards the messages of the client of a `tower-lsp` backend through the given client.
+async fn forward_client(client: Client, socket: tower_lsp::ClientSocket) {
+    let (requests, responses) = socket.split();
+    let responses = Mutex::new(responses);
+    requests
+        .for_each_concurrent(None, |request| async {
+            let (method, id, params) = request.into_parts();
+            let params = params.unwrap_or_default();
+            let id = match id {
+                Some(id) => id,
+                None => return client.send_raw_notification(method, params).await,
+            };
+
+            // The client of `tower-lsp` only sends requests of `lsp-types`, whose methods are static.
+            let result = match method {
+                Cow::Borrowed(method) => client.send_raw_request(method, params).await,
+                Cow::Owned(method) => {
+                    Err(Error::invalid_request().with_message(format!("unknown method {:?}", method)))
+                },
+            };
+            let result = result.map_err(|error| {
+                convert(error).unwrap_or_else(|_| tower_lsp::jsonrpc::Error::internal_error())
+            });
+            let response = tower_lsp::jsonrpc::Response::from_parts(id, result);
+            if responses.lock().await.send(response).await.is_err() {
+                log::debug!("tower-lsp backend was dropped, discarding response");
+            }
+        })
+        .await;
+}
+
+/// Converts a value between the types of `lspower` and `tower-lsp`, which share their JSON
+/// representation.
+fn convert<A: Serialize, B: DeserializeOwned>(value: A) -> serde_json::Result<B> {
+    serde_json::to_value(value).and_then(serde_json::from_value)
+}
+
+fn service_error<E: std::fmt::Display>(error: E) -> Error {
+    Error::internal_error().with_message(error.to_string())
+}
+
+macro_rules! forward {
+    (
+        requests {
+            $($(#[$req_attr:meta])* $req:ident($req_type:ty);)*
+        }
+        notifications {
+            $($notif:ident($notif_type:ty);)*
+        }
+    ) => {
+        #[crate::async_trait]
+        impl<T: tower_lsp::LanguageServer> crate::LanguageServer for TowerLspBackend<T> {
+            async fn shutdown(&self) -> crate::jsonrpc::Result<()> {
+                let result = self.call("shutdown".into(), None, true).await.map(drop);
+                self.forwarding.abort();
+                result
+            }
+
+            $(
+                $(#[$req_attr])*
+                async fn $req(
+                    &self,
+                    params: <$req_type as Request>::Params,
+                ) -> crate::jsonrpc::Result<<$req_type as Request>::Result> {
+                    self.request(<$req_type as Request>::METHOD, params).await
+                }
+            )*
+
+            $(
+                async fn $notif(&self, params: <$notif_type as Notification>::Params) {
+                    self.notify(<$notif_type as Notification>::METHOD, params).await
+                }
+            )*
+
+            async fn request_else(&self, method: &str, params: Option<Value>) -> crate::jsonrpc::Result<Option<Value>> {
+                self.call(method.to_owned().into(), params, true).await
+            }
+
+            async fn notification_else(&self, method: &str, params: Option<Value>) {
+                self.notify_value(method.to_owned().into(), params).await
+            }
+        }
+    };
+}
+
+forward! {
+    requests {
+        initialize(Initialize);
+        symbol(WorkspaceSymbol);
+        execute_command(ExecuteCommand);
+        will_save_wait_until(WillSaveWaitUntil);
+        completion(Completion);
+        completion_resolve(ResolveCompletionItem);
+        hover(HoverRequest);
+        signature_help(SignatureHelpRequest);
+        goto_declaration(GotoDeclaration);
+        goto_definition(GotoDefinition);
+        goto_type_definition(GotoTypeDefinition);
+        goto_implementation(GotoImplementation);
+        references(References);
+        document_highlight(DocumentHighlightRequest);
+        document_symbol(DocumentSymbolRequest);
+        code_action(CodeActionRequest);
+        code_lens(CodeLensRequest);
+        code_lens_resolve(CodeLensResolve);
+        document_link(DocumentLinkRequest);
+        document_link_resolve(DocumentLinkResolve);
+        document_color(DocumentColor);
+        color_presentation(ColorPresentationRequest);
+        formatting(Formatting);
+        range_formatting(RangeFormatting);
+        on_type_formatting(OnTypeFormatting);
+        rename(Rename);
+        prepare_rename(PrepareRenameRequest);
+        folding_range(FoldingRangeRequest);
+        selection_range(SelectionRangeRequest);
+        incoming_calls(CallHierarchyIncomingCalls);
+        outgoing_calls(CallHierarchyOutgoingCalls);
+        prepare_call_hierarchy(CallHierarchyPrepare);
+        semantic_tokens_full(SemanticTokensFullRequest);
+        semantic_tokens_full_delta(SemanticTokensFullDeltaRequest);
+        semantic_tokens_range(SemanticTokensRangeRequest);
+        code_action_resolve(CodeActionResolveRequest);
+        #[cfg(feature = "proposed")]
+        inlay_hint(InlayHintRequest);
+        #[cfg(feature = "proposed")]
+        inlay_hint_resolve(InlayHintResolveRequest);
+    }
+    notifications {
+        initialized(Initialized);
+        did_change_workspace_folders(DidChangeWorkspaceFolders);
+        did_change_configuration(DidChangeConfiguration);
+        did_change_watched_files(DidChangeWatchedFiles);
+        did_open(DidOpenTextDocument);
+        did_change(DidChangeTextDocument);
+        will_save(WillSaveTextDocument);
+        did_save(DidSaveTextDocument);
+        did_close(DidCloseTextDocument);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LspService;
+    use serde_json::json;
+    use std::task::Poll;
+    use tower_test::mock::Spawn;
+
+    #[derive(Debug)]
+    struct Backend(tower_lsp::Client);
+
+    #[tower_lsp::async_trait]
+    impl tower_lsp::LanguageServer for Backend {
+        async fn initialize(
+            &self,
+            _: tower_lsp::lsp_types::InitializeParams,
+        ) -> tower_lsp::jsonrpc::Result<tower_lsp::lsp_types::InitializeResult> {
+            let capabilities = tower_lsp::lsp_types::ServerCapabilities {
+                hover_provider: Some(tower_lsp::lsp_types::HoverProviderCapability::Simple(true)),
+                ..Default::default()
+            };
+            Ok(tower_lsp::lsp_types::InitializeResult {
+                capabilities,
+                ..Default::default()
+            })
+        }
+
+        async fn shutdown(&self) -> tower_lsp::jsonrpc::Result<()> {
+            Ok(())
+        }
+
+        async fn hover(
+            &self,
+            params: tower_lsp::lsp_types::HoverParams,
+        ) -> tower_lsp::jsonrpc::Result<Option<tower_lsp::lsp_types::Hover>> {
+            let uri = params.text_document_position_params.text_document.uri;
+            self.0.log_message(tower_lsp::lsp_types::MessageType::INFO, &uri).await;
+            let contents = tower_lsp::lsp_types::MarkedString::String(uri.to_string());
+            Ok(Some(tower_lsp::lsp_types::Hover {
+                contents: tower_lsp::lsp_types::HoverContents::Scalar(contents),
+                range: None,
+            }))
+        }
+
+        async fn moniker(
+            &self,
+            _: tower_lsp::lsp_types::MonikerParams,
+        ) -> tower_lsp::jsonrpc::Result<Option<Vec<tower_lsp::lsp_types::Moniker>>> {
+            Ok(Some(Vec::new()))
+        }
+    }
+
+    async fn call(service: &mut Spawn<LspService>, message: Value) -> Option<Value> {
+        let message = serde_json::from_value(message).unwrap();
+        assert_eq!(service.poll_ready(), Poll::Ready(Ok(())));
+        let response = service.call(message).await.unwrap();
+        response.map(|response| serde_json::to_value(response).unwrap())
+    }
+
+    #[tokio::test]
+    async fn forwards_to_backend() {
+        let (service, mut messages) = LspService::new(|client| TowerLspBackend::new(client, Backend));
+        let mut service = Spawn::new(service);
+
+        let initialize = json!({ "jsonrpc": "2.0", "method": "initialize", "params": { "capabilities": {} }, "id": 1 });
+        let response = call(&mut service, initialize).await.unwrap();
+        assert_eq!(response["result"]["capabilities"]["hoverProvider"], true);
+        let initialized = json!({ "jsonrpc": "2.0", "method": "initialized", "params": {} });
+        assert_eq!(call(&mut service, initialized).await, None);
+
+        let position = json!({ "textDocument": { "uri": "file:///a.rs" }, "position": { "line": 0, "character": 0 } });
+        let hover = json!({ "jsonrpc": "2.0", "method": "textDocument/hover", "params": position, "id": 2 });
+        let response = call(&mut service, hover).await.unwrap();
+        assert_eq!(response["result"]["contents"], "file:///a.rs");
+
+        // The backend logs through its own client, which is forwarded to the editor.
+        let message = serde_json::to_value(messages.next().await.unwrap()).unwrap();
+        assert_eq!(message["method"], "window/logMessage");
+        assert_eq!(message["params"]["message"], "file:///a.rs");
+
+        // Methods unknown to `lspower` reach the backend as well.
+        let moniker = json!({ "jsonrpc": "2.0", "method": "textDocument/moniker", "params": position, "id": 3 });
+        let response = call(&mut service, moniker).await.unwrap();
+        assert_eq!(response["result"], json!([]));
+
+        let unknown = json!({ "jsonrpc": "2.0", "method": "unknown", "id": 4 });
+        let response = call(&mut service, unknown).await.unwrap();
+        assert_eq!(response["error"]["code"], -32601);
+
+        let shutdown = json!({ "jsonrpc": "2.0", "method": "shutdown", "id": 5 });
+        let response = call(&mut service, shutdown).await.unwrap();
+        assert_eq!(response["result"], Value::Null);
+    }
+}
@@ -0,0 +1,218 @@
+//! Typed access to the `data` of completion items, which round-trips through the client between
+//! `textDocument/completion` and `completionItem/resolve`.
+//!
+//! Servers which resolve completion items lazily keep the state needed for resolving an item in
+//! its `data` field. [`with_data`] and [`parse_data`] serialize this state from and deserialize it
+//! into a typed value. State which is too large to send to the client with every item can instead
+//! be kept in a [`CompletionStash`], which only sends a small token.
+//!
+//! # Example
+//!
+//! ```rust
+//! # use lspower::{completion, jsonrpc::Result, lsp::*};
+//! # use serde::{Deserialize, Serialize};
+//! #[derive(Deserialize, Serialize)]
+//! struct ResolveData {
+//!     symbol_id: u64,
+//! }
+//!
+//! fn item(label: &str, symbol_id: u64) -> Result<CompletionItem> {
+//!     completion::with_data(CompletionItem::new_simple(label.into(), "".into()), &ResolveData { symbol_id })
+//! }
+//!
+//! async fn completion_resolve(mut item: CompletionItem) -> Result<CompletionItem> {
+//!     let data: ResolveData = completion::parse_data(&item)?;
+//!     item.detail = Some(format!("symbol {}", data.symbol_id));
+//!     Ok(item)
+//! }
+//! ```
+
+use crate::jsonrpc::{Error, Result};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{json, Value};
+use std::{
+    any::Any,
+    collections::{HashMap, VecDeque},
+    fmt::{self, Debug, Formatter},
+    sync::{Arc, Mutex},
+};
+
+/// The key of the token in the `data` of items whose state is kept in a [`CompletionStash`].
+const STASH_KEY: &str = "stash";
+
+/// Sets the `data` of the completion item to the serialized value.
+///
+/// Fails with an internal error if the value cannot be serialized to JSON.
+pub fn with_data<T: Serialize>(mut item: lsp::CompletionItem, data: &T) -> Result<lsp::CompletionItem> {
+    let data = serde_json::to_value(data).map_err(|e| Error::internal_error().with_message(e.to_string()))?;
+    item.data = Some(data);
+    Ok(item)
+}
+
+/// Deserializes the `data` of the completion item, as set with [`with_data`].
+///
+/// Fails with an invalid params error if the item has no `data` or if it does not deserialize,
+/// e.g. because the client modified it.
+pub fn parse_data<T: DeserializeOwned>(item: &lsp::CompletionItem) -> Result<T> {
+    let data = item.data.clone().ok_or_else(|| Error::invalid_params("completion item has no `data`"))?;
+    serde_json::from_value(data).map_err(|e| Error::invalid_params(format!("invalid completion item `data`: {}", e)))
+}
+
+struct StashInner {
+    next_token: u64,
+    capacity: usize,
+    payloads: HashMap<u64, Arc<dyn Any + Send + Sync>>,
+    order: VecDeque<u64>,
+}
+
+/// Keeps the state of completion items on the server, sending only a small token to the client in
+/// their `data`.
+///
+/// This reduces the size of completion responses whose items need large state to be resolved.
+/// The state is kept for the `capacity` most recently stashed items, so that the stash does not
+/// grow with every completion request. An item whose state was evicted can no longer be resolved
+/// from the stash, in which case servers usually return the item unchanged.
+///
+/// The stash is cheap to clone, with all clones sharing the same state.
+///
+/// # Example
+///
+/// ```rust
+/// # use lspower::{completion::CompletionStash, jsonrpc::Result, lsp::*};
+/// struct Signature {
+///     detail: String,
+///     documentation: String,
+/// }
+///
+/// let stash = CompletionStash::new(1024);
+/// let signature = Signature { detail: "fn()".into(), documentation: "Does nothing.".into() };
+/// let item = stash.stash(CompletionItem::new_simple("noop".into(), "".into()), signature);
+///
+/// // Later, in `completion_resolve`:
+/// let mut resolved = item.clone();
+/// if let Some(signature) = stash.get::<Signature>(&item) {
+///     resolved.detail = Some(signature.detail.clone());
+/// }
+/// ```
+#[derive(Clone)]
+pub struct CompletionStash(Arc<Mutex<StashInner>>);
+
+impl CompletionStash {
+    /// Creates a stash keeping the state of up to `capacity` items.
+    ///
+    /// A capacity of `0` is treated as `1`.
+    pub fn new(capacity: usize) -> Self {
+        CompletionStash(Arc::new(Mutex::new(StashInner {
+            next_token: 0,
+            capacity: capacity.max(1),
+            payloads: HashMap::new(),
+            order: VecDeque::new(),
+        })))
+    }
+
+    /// Keeps the given state for the completion item, replacing its `data` with a token
+    /// referring to it.
+    pub fn stash<T: Any + Send + Sync>(&self, mut item: lsp::CompletionItem, payload: T) -> lsp::CompletionItem {
+        let mut inner = self.0.lock().unwrap();
+        let token = inner.next_token;
+        inner.next_token += 1;
+
+        if inner.order.len() == inner.capacity {
+            if let Some(evicted) = inner.order.pop_front() {
+                inner.payloads.remove(&evicted);
+            }
+        }
+        inner.order.push_back(token);
+        inner.payloads.insert(token, Arc::new(payload));
+
+        item.data = Some(json!({ STASH_KEY: token }));
+        item
+    }
+
+    /// Returns the state kept for the completion item, if it was stashed, has not been evicted
+    /// yet, and has the type `T`.
+    pub fn get<T: Any + Send + Sync>(&self, item: &lsp::CompletionItem) -> Option<Arc<T>> {
+        let token = item.data.as_ref().and_then(|data| data.get(STASH_KEY)).and_then(Value::as_u64)?;
+        let payload = self.0.lock().unwrap().payloads.get(&token)?.clone();
+        payload.downcast().ok()
+    }
+
+    /// Forgets the state of all items.
+    pub fn clear(&self) {
+        let mut inner = self.0.lock().unwrap();
+        inner.payloads.clear();
+        inner.order.clear();
+    }
+
+    /// Returns the number of items whose state is kept.
+    pub fn len(&self) -> usize {
+        self.0.lock().unwrap().payloads.len()
+    }
+
+    /// Returns `true` if the state of no items is kept.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for CompletionStash {
+    /// Creates a stash keeping the state of up to 1024 items.
+    fn default() -> Self {
+        CompletionStash::new(1024)
+    }
+}
+
+impl Debug for CompletionStash {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let inner = self.0.lock().unwrap();
+        f.debug_struct(stringify!(CompletionStash))
+            .field("capacity", &inner.capacity)
+            .field("len", &inner.payloads.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    fn item(label: &str) -> lsp::CompletionItem {
+        lsp::CompletionItem::new_simple(label.into(), String::new())
+    }
+
+    #[test]
+    fn data() {
+        #[derive(Debug, Deserialize, PartialEq, Serialize)]
+        struct Data {
+            id: u64,
+        }
+
+        let with = with_data(item("a"), &Data { id: 7 }).unwrap();
+        assert_eq!(with.data, Some(json!({ "id": 7 })));
+        assert_eq!(parse_data::<Data>(&with), Ok(Data { id: 7 }));
+        assert_eq!(parse_data::<Data>(&item("b")).unwrap_err().code, crate::jsonrpc::ErrorCode::InvalidParams);
+        let invalid = with_data(item("c"), &"id").unwrap();
+        assert!(parse_data::<Data>(&invalid).is_err());
+    }
+
+    #[test]
+    fn stash() {
+        let stash = CompletionStash::new(2);
+        let a = stash.stash(item("a"), String::from("payload a"));
+        let b = stash.stash(item("b"), 2u32);
+        assert_eq!(a.data, Some(json!({ "stash": 0 })));
+        assert_eq!(stash.get::<String>(&a).as_deref(), Some(&String::from("payload a")));
+        assert_eq!(stash.get::<u32>(&b).as_deref(), Some(&2));
+        assert_eq!(stash.get::<String>(&b), None);
+        assert_eq!(stash.get::<String>(&item("c")), None);
+
+        let c = stash.stash(item("c"), 3u32);
+        assert_eq!(stash.get::<String>(&a), None);
+        assert_eq!(stash.get::<u32>(&c).as_deref(), Some(&3));
+        assert_eq!(stash.len(), 2);
+
+        stash.clear();
+        assert!(stash.is_empty());
+    }
+}
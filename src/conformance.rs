@@ -0,0 +1,674 @@
+//! Scripted protocol conformance checks for language servers built on [`LspService`].
+//!
+//! The [`Conformance`] runner plays a set of standard LSP interactions against fresh instances of
+//! a service and reports every deviation from the protocol it observes. This allows downstream
+//! servers to check that their handlers, combined with the dispatching done by this crate, handle
+//! edge cases like requests before initialization, cancellation storms and the shutdown/exit
+//! sequence correctly.
+//!
+//! This module is only available with the `conformance` crate feature enabled.
+//!
+//! # Example
+//!
+//! ```rust
+//! # use lspower::{conformance::Conformance, jsonrpc::Result, lsp::*, LanguageServer, LspService};
+//! # struct Backend;
+//! # #[lspower::async_trait]
+//! # impl LanguageServer for Backend {
+//! #     async fn initialize(&self, _: InitializeParams) -> Result<InitializeResult> {
+//! #         Ok(InitializeResult::default())
+//! #     }
+//! #     async fn shutdown(&self) -> Result<()> {
+//! #         Ok(())
+//! #     }
+//! # }
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() {
+//! let report = Conformance::new(|| LspService::new(|_| Backend)).run().await;
+//! assert!(report.is_conformant(), "{}", report);
+//! # }
+//! ```
+//!
+//! The capabilities a server announces can also be checked on their own with
+//! [`check_initialize_result`].
+//!
+//! [`LspService`]: crate::LspService
+
+#[cfg(feature = "runtime-agnostic")]
+use async_codec_lite::Decoder;
+#[cfg(feature = "runtime-tokio")]
+use tokio_util::codec::Decoder;
+
+use crate::{
+    codec::LanguageServerCodec,
+    jsonrpc::{Error, ErrorCode, Id, Incoming, Outgoing},
+    ExitedError,
+    LspService,
+    MessageStream,
+};
+use bytes::BytesMut;
+use futures::{
+    future::{self, BoxFuture, Either, FutureExt},
+    StreamExt,
+};
+use serde_json::{json, Value};
+use std::{
+    collections::HashSet,
+    fmt::{self, Debug, Display, Formatter},
+};
+use tower_service::Service;
+
+const UNKNOWN_METHOD: &str = "lspower/conformanceUnknownMethod";
+
+/// Capabilities added by this crate which are not part of the protocol types yet.
+const PROPOSED_CAPABILITIES: &[&str] = &["capabilities.inlineCompletionProvider"];
+
+/// Runner for the conformance scenarios.
+///
+/// Every scenario is played against a fresh service created by the factory passed to
+/// [`Conformance::new`].
+pub struct Conformance<F> {
+    factory: F,
+    initialize_params: Value,
+    storm_size: u64,
+}
+
+impl<F> Conformance<F>
+where
+    F: Fn() -> (LspService, MessageStream),
+{
+    /// Creates a new `Conformance` runner for services created by `factory`.
+    pub fn new(factory: F) -> Self {
+        Conformance {
+            factory,
+            initialize_params: json!({ "capabilities": {} }),
+            storm_size: 64,
+        }
+    }
+
+    /// Sets the parameters of the `initialize` requests sent by the scenarios.
+    ///
+    /// Defaults to `{ "capabilities": {} }`.
+    pub fn initialize_params(mut self, params: Value) -> Self {
+        self.initialize_params = params;
+        self
+    }
+
+    /// Sets the number of requests sent and cancelled during the cancellation storm scenario.
+    ///
+    /// Defaults to 64.
+    pub fn cancellation_storm_size(mut self, size: u64) -> Self {
+        self.storm_size = size;
+        self
+    }
+
+    /// Runs all scenarios and returns a report of the observed violations.
+    pub async fn run(&self) -> ConformanceReport {
+        let mut scenarios = Vec::new();
+
+        scenarios.push(self.scenario("initialize handshake", |s| s.initialize_handshake().boxed()).await);
+        scenarios.push(self.scenario("initialize with string id", |s| s.initialize_string_id().boxed()).await);
+        scenarios.push(self.scenario("duplicate initialize", |s| s.duplicate_initialize().boxed()).await);
+        scenarios.push(self.scenario("request before initialize", |s| s.before_initialize().boxed()).await);
+        scenarios.push(self.scenario("invalid params", |s| s.invalid_params().boxed()).await);
+        scenarios.push(self.scenario("unknown methods", |s| s.unknown_methods().boxed()).await);
+        scenarios.push(self.scenario("cancellation storm", |s| s.cancellation_storm().boxed()).await);
+        scenarios.push(self.scenario("capability round-trip", |s| s.capability_round_trip().boxed()).await);
+        scenarios.push(self.scenario("shutdown and exit", |s| s.shutdown_and_exit().boxed()).await);
+        scenarios.push(self.scenario("exit without shutdown", |s| s.exit_without_shutdown().boxed()).await);
+        scenarios.push(ScenarioReport {
+            name: "malformed messages",
+            violations: malformed_messages(),
+        });
+
+        ConformanceReport { scenarios }
+    }
+
+    async fn scenario<S>(&self, name: &'static str, script: S) -> ScenarioReport
+    where
+        S: for<'a> FnOnce(&'a mut Session) -> BoxFuture<'a, ()>,
+    {
+        let (service, messages) = (self.factory)();
+        let mut session = Session {
+            service,
+            initialize_params: self.initialize_params.clone(),
+            storm_size: self.storm_size,
+            violations: Vec::new(),
+        };
+
+        // Server-to-client messages are drained while the script runs so that a server sending
+        // notifications does not block on the bounded message channel.
+        let drain = messages.for_each(|_| future::ready(()));
+        match future::select(script(&mut session), drain).await {
+            Either::Left(((), _)) => {},
+            Either::Right(((), script)) => script.await,
+        }
+
+        ScenarioReport {
+            name,
+            violations: session.violations,
+        }
+    }
+}
+
+impl<F> Debug for Conformance<F> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct(stringify!(Conformance))
+            .field("initialize_params", &self.initialize_params)
+            .field("storm_size", &self.storm_size)
+            .finish()
+    }
+}
+
+/// Outcome of a single conformance scenario.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ScenarioReport {
+    name: &'static str,
+    violations: Vec<String>,
+}
+
+impl ScenarioReport {
+    /// Returns the name of the scenario.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Returns the protocol violations observed during the scenario.
+    pub fn violations(&self) -> &[String] {
+        &self.violations
+    }
+
+    /// Returns `true` if no violations were observed.
+    pub fn passed(&self) -> bool {
+        self.violations.is_empty()
+    }
+}
+
+/// Outcome of all conformance scenarios, returned from [`Conformance::run`].
+///
+/// The `Display` implementation renders a human readable summary listing all violations.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ConformanceReport {
+    scenarios: Vec<ScenarioReport>,
+}
+
+impl ConformanceReport {
+    /// Returns the outcomes of the individual scenarios.
+    pub fn scenarios(&self) -> &[ScenarioReport] {
+        &self.scenarios
+    }
+
+    /// Returns `true` if no violations were observed in any scenario.
+    pub fn is_conformant(&self) -> bool {
+        self.scenarios.iter().all(ScenarioReport::passed)
+    }
+}
+
+impl Display for ConformanceReport {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        for scenario in &self.scenarios {
+            let status = if scenario.passed() { "ok" } else { "FAILED" };
+            writeln!(f, "{} ... {}", scenario.name, status)?;
+            for violation in &scenario.violations {
+                writeln!(f, "    - {}", violation)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+struct Session {
+    service: LspService,
+    initialize_params: Value,
+    storm_size: u64,
+    violations: Vec<String>,
+}
+
+type CallResult = Result<Option<Outgoing>, ExitedError>;
+
+impl Session {
+    fn violation(&mut self, message: impl Into<String>) {
+        self.violations.push(message.into());
+    }
+
+    fn call(&mut self, message: Value) -> BoxFuture<'static, CallResult> {
+        let message: Incoming = match serde_json::from_value(message.clone()) {
+            Ok(message) => message,
+            Err(error) => {
+                self.violation(format!("failed to deserialize message {}: {}", message, error));
+                return future::ok(None).boxed();
+            },
+        };
+        self.service.call(message)
+    }
+
+    async fn send(&mut self, message: Value) -> CallResult {
+        let service = &mut self.service;
+        future::poll_fn(|cx| service.poll_ready(cx)).await?;
+        self.call(message).await
+    }
+
+    async fn request(&mut self, id: Value, method: &str, params: Value) -> Option<Result<Value, Error>> {
+        let message = json!({ "jsonrpc": "2.0", "method": method, "params": params, "id": id.clone() });
+        let result = self.send(message).await;
+        self.expect_response(method, id, result)
+    }
+
+    async fn notify(&mut self, method: &str, params: Value) {
+        let message = json!({ "jsonrpc": "2.0", "method": method, "params": params });
+        match self.send(message).await {
+            Ok(None) => {},
+            Ok(Some(message)) => self.violation(format!("{:?} notification was answered with {}", method, message)),
+            Err(error) => self.violation(format!("{:?} notification failed: {}", method, error)),
+        }
+    }
+
+    fn expect_response(&mut self, method: &str, id: Value, result: CallResult) -> Option<Result<Value, Error>> {
+        let id: Id = serde_json::from_value(id).expect("request IDs are valid");
+        match result {
+            Ok(Some(Outgoing::Response(response))) => match response.into_parts() {
+                (Some(response_id), body) if response_id == id => Some(body),
+                (response_id, _) => {
+                    self.violation(format!(
+                        "response to {:?} request {} has ID {:?}",
+                        method, id, response_id
+                    ));
+                    None
+                },
+            },
+            Ok(Some(message)) => {
+                self.violation(format!("{:?} request {} was answered with {}", method, id, message));
+                None
+            },
+            Ok(None) => {
+                self.violation(format!("{:?} request {} was not answered", method, id));
+                None
+            },
+            Err(error) => {
+                self.violation(format!("{:?} request {} failed: {}", method, id, error));
+                None
+            },
+        }
+    }
+
+    fn expect_error(&mut self, context: &str, body: Option<Result<Value, Error>>, code: ErrorCode) {
+        match body {
+            Some(Err(error)) if error.code == code => {},
+            Some(Err(error)) => {
+                let message = format!("{}: expected error {}, got error {}", context, code, error.code);
+                self.violation(message)
+            },
+            Some(Ok(result)) => {
+                let message = format!("{}: expected error {}, got result {}", context, code, result);
+                self.violation(message)
+            },
+            None => {},
+        }
+    }
+
+    async fn initialize(&mut self, id: Value) -> bool {
+        let params = self.initialize_params.clone();
+        match self.request(id, "initialize", params).await {
+            Some(Ok(result)) if result["capabilities"].is_object() => true,
+            Some(Ok(result)) => {
+                self.violation(format!("`initialize` result lacks a `capabilities` object: {}", result));
+                true
+            },
+            Some(Err(error)) => {
+                self.violation(format!("`initialize` failed: {}", error));
+                false
+            },
+            None => false,
+        }
+    }
+
+    async fn initialize_handshake(&mut self) {
+        if self.initialize(json!(1)).await {
+            self.notify("initialized", json!({})).await;
+        }
+    }
+
+    async fn capability_round_trip(&mut self) {
+        let params = self.initialize_params.clone();
+        if let Some(Ok(result)) = self.request(json!(1), "initialize", params).await {
+            check_initialize_value(&result).into_iter().for_each(|violation| self.violation(violation));
+        }
+    }
+
+    async fn initialize_string_id(&mut self) {
+        self.initialize(json!("initialize")).await;
+    }
+
+    async fn duplicate_initialize(&mut self) {
+        if self.initialize(json!(1)).await {
+            let params = self.initialize_params.clone();
+            let body = self.request(json!(2), "initialize", params).await;
+            self.expect_error("second `initialize` request", body, ErrorCode::InvalidRequest);
+        }
+    }
+
+    async fn before_initialize(&mut self) {
+        let body = self.request(json!(1), "textDocument/hover", hover_params()).await;
+        self.expect_error("request before `initialize`", body, ErrorCode::ServerError(-32002));
+        self.notify("textDocument/didOpen", did_open_params()).await;
+        self.initialize(json!(2)).await;
+    }
+
+    async fn invalid_params(&mut self) {
+        if self.initialize(json!(1)).await {
+            self.notify("initialized", json!({})).await;
+            let body = self.request(json!(2), "textDocument/hover", json!({ "invalid": true })).await;
+            self.expect_error("request with invalid params", body, ErrorCode::InvalidParams);
+        }
+    }
+
+    async fn unknown_methods(&mut self) {
+        if self.initialize(json!(1)).await {
+            self.notify("initialized", json!({})).await;
+            self.request(json!(2), UNKNOWN_METHOD, json!({})).await;
+            self.notify(UNKNOWN_METHOD, json!({})).await;
+            self.notify("$/lspowerConformanceUnknown", json!({})).await;
+        }
+    }
+
+    async fn cancellation_storm(&mut self) {
+        if !self.initialize(json!(1)).await {
+            return;
+        }
+        self.notify("initialized", json!({})).await;
+
+        let ids: Vec<u64> = (100 .. 100 + self.storm_size).collect();
+        let mut requests = Vec::new();
+        for id in &ids {
+            let service = &mut self.service;
+            if let Err(error) = future::poll_fn(|cx| service.poll_ready(cx)).await {
+                self.violation(format!("service stopped during cancellation storm: {}", error));
+                return;
+            }
+            let params = json!({ "query": "" });
+            let message = json!({ "jsonrpc": "2.0", "method": "workspace/symbol", "params": params, "id": id });
+            requests.push(self.call(message));
+        }
+
+        for id in &ids {
+            self.notify("$/cancelRequest", json!({ "id": id })).await;
+        }
+        self.notify("$/cancelRequest", json!({ "id": u64::MAX })).await;
+
+        let mut answered = HashSet::new();
+        for (id, result) in ids.iter().zip(future::join_all(requests).await) {
+            if let Some(body) = self.expect_response("workspace/symbol", json!(id), result) {
+                answered.insert(*id);
+                if let Err(error) = body {
+                    if error.code != ErrorCode::RequestCancelled && error.code != ErrorCode::MethodNotFound {
+                        self.violation(format!("cancelled request {} failed with error {}", id, error.code));
+                    }
+                }
+            }
+        }
+
+        if answered.len() != ids.len() {
+            let missing = ids.len() - answered.len();
+            self.violation(format!("{} of {} cancelled requests were not answered", missing, ids.len()));
+        }
+    }
+
+    async fn shutdown_and_exit(&mut self) {
+        if !self.initialize(json!(1)).await {
+            return;
+        }
+        self.notify("initialized", json!({})).await;
+
+        match self.request(json!(2), "shutdown", Value::Null).await {
+            Some(Ok(Value::Null)) | None => {},
+            Some(Ok(result)) => self.violation(format!("`shutdown` returned non-null result {}", result)),
+            Some(Err(error)) => self.violation(format!("`shutdown` failed: {}", error)),
+        }
+
+        let body = self.request(json!(3), "textDocument/hover", hover_params()).await;
+        self.expect_error("request after `shutdown`", body, ErrorCode::InvalidRequest);
+
+        self.notify("exit", Value::Null).await;
+        self.expect_exited().await;
+    }
+
+    async fn exit_without_shutdown(&mut self) {
+        if self.initialize(json!(1)).await {
+            self.notify("exit", Value::Null).await;
+            self.expect_exited().await;
+        }
+    }
+
+    async fn expect_exited(&mut self) {
+        let service = &mut self.service;
+        if future::poll_fn(|cx| service.poll_ready(cx)).await.is_ok() {
+            self.violation("service still accepts messages after `exit`");
+        }
+    }
+}
+
+fn hover_params() -> Value {
+    json!({
+        "textDocument": { "uri": "file:///conformance.txt" },
+        "position": { "line": 0, "character": 0 },
+    })
+}
+
+fn did_open_params() -> Value {
+    json!({
+        "textDocument": {
+            "uri": "file:///conformance.txt",
+            "languageId": "plaintext",
+            "version": 1,
+            "text": "",
+        },
+    })
+}
+
+/// Checks that an `initialize` result only has shapes which the specification allows, returning
+/// a description of every deviation.
+///
+/// The result is serialized and parsed again, which reports:
+///
+/// * properties serialized as `null` instead of being omitted, which some clients reject,
+/// * properties which are not part of the protocol, e.g. due to misspelled custom capabilities,
+/// * values which change when parsed, e.g. because of an unexpected shape.
+///
+/// The checks are stricter than most clients: trigger characters must be single characters and
+/// the kind of text document synchronization must be one of the kinds defined by the
+/// specification. The `experimental` capabilities are not checked.
+///
+/// # Example
+///
+/// ```rust
+/// # use lspower::{conformance::check_initialize_result, lsp::*};
+/// let result = InitializeResult {
+///     capabilities: ServerCapabilities {
+///         hover_provider: Some(HoverProviderCapability::Simple(true)),
+///         ..ServerCapabilities::default()
+///     },
+///     ..InitializeResult::default()
+/// };
+/// assert_eq!(check_initialize_result(&result), Vec::<String>::new());
+/// ```
+pub fn check_initialize_result(result: &lsp::InitializeResult) -> Vec<String> {
+    check_initialize_value(&serde_json::to_value(result).unwrap())
+}
+
+fn check_initialize_value(result: &Value) -> Vec<String> {
+    let mut violations = Vec::new();
+    match serde_json::from_value::<lsp::InitializeResult>(result.clone()) {
+        Ok(parsed) => compare("", result, &serde_json::to_value(parsed).unwrap(), &mut violations),
+        Err(error) => violations.push(format!("`initialize` result does not parse: {}", error)),
+    }
+    check_capabilities(&result["capabilities"], &mut violations);
+    violations
+}
+
+/// Compares a serialized value to the result of parsing and serializing it again.
+fn compare(path: &str, original: &Value, parsed: &Value, violations: &mut Vec<String>) {
+    let join = |key: &str| if path.is_empty() { key.to_owned() } else { format!("{}.{}", path, key) };
+    match (original, parsed) {
+        (Value::Null, Value::Null) => violations.push(format!("`{}` is `null` instead of being omitted", path)),
+        (Value::Object(original), Value::Object(parsed)) => {
+            for (key, value) in original {
+                let path = join(key);
+                if path == "capabilities.experimental" || PROPOSED_CAPABILITIES.contains(&path.as_str()) {
+                    continue;
+                }
+                match parsed.get(key) {
+                    Some(parsed) => compare(&path, value, parsed, violations),
+                    None if value.is_null() => {
+                        violations.push(format!("`{}` is `null` instead of being omitted", path));
+                    },
+                    None => violations.push(format!("`{}` is not part of the protocol", path)),
+                }
+            }
+            for (key, value) in parsed.iter().filter(|(key, _)| !original.contains_key(*key)) {
+                violations.push(format!("`{}` is missing and defaults to {}", join(key), value));
+            }
+        },
+        (Value::Array(original), Value::Array(parsed)) if original.len() == parsed.len() => {
+            for (index, (original, parsed)) in original.iter().zip(parsed).enumerate() {
+                compare(&format!("{}[{}]", path, index), original, parsed, violations);
+            }
+        },
+        (original, parsed) if original == parsed => {},
+        (original, parsed) => {
+            violations.push(format!("`{}` changes from {} to {} when parsed", path, original, parsed));
+        },
+    }
+}
+
+/// Checks the values of server capabilities whose shape the protocol types do not restrict.
+fn check_capabilities(capabilities: &Value, violations: &mut Vec<String>) {
+    let sync = &capabilities["textDocumentSync"];
+    if let Some(kind) = sync.as_u64().or_else(|| sync["change"].as_u64()) {
+        if kind > 2 {
+            violations.push(format!("`capabilities.textDocumentSync` has unknown kind {}", kind));
+        }
+    }
+
+    let characters = [
+        ("completionProvider", "triggerCharacters"),
+        ("completionProvider", "allCommitCharacters"),
+        ("signatureHelpProvider", "triggerCharacters"),
+        ("signatureHelpProvider", "retriggerCharacters"),
+        ("documentOnTypeFormattingProvider", "firstTriggerCharacter"),
+        ("documentOnTypeFormattingProvider", "moreTriggerCharacter"),
+    ];
+    for (provider, property) in characters {
+        let value = &capabilities[provider][property];
+        let values = match value {
+            Value::Array(values) => values.iter().enumerate().map(|(i, v)| (format!("[{}]", i), v)).collect(),
+            Value::Null => Vec::new(),
+            value => vec![(String::new(), value)],
+        };
+        for (index, value) in values {
+            if value.as_str().is_none_or(|value| value.chars().count() != 1) {
+                let path = format!("capabilities.{}.{}{}", provider, property, index);
+                violations.push(format!("`{}` is {} instead of a single character", path, value));
+            }
+        }
+    }
+}
+
+/// Checks that the codec rejects malformed messages and recovers for the messages following them.
+fn malformed_messages() -> Vec<String> {
+    let valid = r#"{"jsonrpc":"2.0","method":"exit"}"#;
+    let frame = |body: &str| format!("Content-Length: {}\r\n\r\n{}", body.len(), body);
+    let cases = [
+        ("garbage before headers", format!("garbage{}", frame(valid))),
+        ("truncated JSON body", format!("{}{}", frame(r#"{"jsonrpc":"#), frame(valid))),
+        ("invalid JSON-RPC version", format!("{}{}", frame(r#"{"jsonrpc":"1.0","method":"exit"}"#), frame(valid))),
+    ];
+
+    let mut violations = Vec::new();
+    for (name, input) in cases.iter() {
+        let mut codec = LanguageServerCodec::<Incoming>::default();
+        let mut buffer = BytesMut::from(input.as_str());
+        if let Ok(message) = codec.decode(&mut buffer) {
+            violations.push(format!("{}: accepted as {:?}", name, message));
+        }
+        match codec.decode(&mut buffer) {
+            Ok(Some(_)) => {},
+            Ok(None) => violations.push(format!("{}: following message was not decoded", name)),
+            Err(error) => violations.push(format!("{}: following message failed to decode: {}", name, error)),
+        }
+    }
+    violations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{jsonrpc::Result, LanguageServer};
+    use async_trait::async_trait;
+
+    struct Mock;
+
+    #[async_trait]
+    impl LanguageServer for Mock {
+        async fn initialize(&self, _: lsp::InitializeParams) -> Result<lsp::InitializeResult> {
+            Ok(lsp::InitializeResult::default())
+        }
+
+        async fn shutdown(&self) -> Result<()> {
+            Ok(())
+        }
+
+        async fn symbol(&self, _: lsp::WorkspaceSymbolParams) -> Result<Option<Vec<lsp::SymbolInformation>>> {
+            future::pending().await
+        }
+    }
+
+    #[tokio::test]
+    async fn conformant() {
+        let report = Conformance::new(|| LspService::new(|_| Mock)).run().await;
+        assert!(report.is_conformant(), "{}", report);
+        assert_eq!(report.scenarios().len(), 11);
+    }
+
+    #[test]
+    fn initialize_result() {
+        assert_eq!(check_initialize_result(&lsp::InitializeResult::default()), Vec::<String>::new());
+
+        let result = json!({
+            "capabilities": {
+                "textDocumentSync": 5,
+                "completionProvider": { "triggerCharacters": [".", "::"] },
+                "hoverProvider": null,
+                "colourProvider": true,
+                "experimental": { "custom": null },
+            },
+            "serverInfo": { "name": "mock", "version": null },
+        });
+        assert_eq!(check_initialize_value(&result), vec![
+            "`capabilities.hoverProvider` is `null` instead of being omitted",
+            "`capabilities.colourProvider` is not part of the protocol",
+            "`serverInfo.version` is `null` instead of being omitted",
+            "`capabilities.textDocumentSync` has unknown kind 5",
+            "`capabilities.completionProvider.triggerCharacters[1]` is \"::\" instead of a single character",
+        ]);
+    }
+
+    #[tokio::test]
+    async fn failing_initialize() {
+        struct Failing;
+
+        #[async_trait]
+        impl LanguageServer for Failing {
+            async fn initialize(&self, _: lsp::InitializeParams) -> Result<lsp::InitializeResult> {
+                Err(Error::internal_error())
+            }
+
+            async fn shutdown(&self) -> Result<()> {
+                Ok(())
+            }
+        }
+
+        let report = Conformance::new(|| LspService::new(|_| Failing)).run().await;
+        assert!(!report.is_conformant());
+        assert!(report.to_string().contains("initialize handshake ... FAILED"));
+    }
+}
@@ -0,0 +1,191 @@
+//! Information about the connection a language server is serving.
+
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    fmt::{self, Debug, Display, Formatter},
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// The transport over which a client is connected.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub enum TransportKind {
+    /// Standard input and output of the server process.
+    Stdio,
+    /// A TCP socket.
+    Tcp,
+    /// A WebSocket.
+    WebSocket,
+    /// HTTP requests.
+    Http,
+    /// An in-memory connection, e.g. created with [`duplex`](crate::duplex).
+    Duplex,
+    /// Any other transport, described by the given name.
+    Other(String),
+}
+
+impl Display for TransportKind {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            TransportKind::Stdio => f.write_str("stdio"),
+            TransportKind::Tcp => f.write_str("tcp"),
+            TransportKind::WebSocket => f.write_str("websocket"),
+            TransportKind::Http => f.write_str("http"),
+            TransportKind::Duplex => f.write_str("duplex"),
+            TransportKind::Other(name) => f.write_str(name),
+        }
+    }
+}
+
+/// Information about the connection of a single client, for servers accepting multiple
+/// connections.
+///
+/// The information is set with [`LspServiceBuilder::connection`] or passed to the `init` closure
+/// of [`LspService::new_with_connection`], and is available to the backend through
+/// [`Client::connection`]. This allows backends to tell sessions apart, e.g. to enforce
+/// authentication per peer or to label their logs. Arbitrary values can be attached with
+/// [`with_data`](ConnectionInfo::with_data), such as the identity of an authenticated user.
+///
+/// Each `ConnectionInfo` is assigned an identifier which is unique within the process. Its
+/// [`Display`] implementation formats the identifier, transport and peer address for log labels.
+///
+/// # Example
+///
+/// ```rust
+/// # use lspower::{jsonrpc::Result, lsp::*, Client, ConnectionInfo, LanguageServer, LspService, TransportKind};
+/// # use std::net::SocketAddr;
+/// #[derive(Debug)]
+/// struct Backend {
+///     client: Client,
+///     label: String,
+/// }
+/// # #[lspower::async_trait]
+/// # impl LanguageServer for Backend {
+/// #     async fn initialize(&self, _: InitializeParams) -> Result<InitializeResult> {
+/// #         Ok(InitializeResult::default())
+/// #     }
+/// #     async fn shutdown(&self) -> Result<()> {
+/// #         Ok(())
+/// #     }
+/// # }
+///
+/// # fn accept(peer: SocketAddr) {
+/// let connection = ConnectionInfo::new(TransportKind::Tcp).with_peer_addr(peer);
+/// let (service, messages) = LspService::new_with_connection(connection, |client, connection| Backend {
+///     client,
+///     label: connection.to_string(),
+/// });
+/// # }
+/// ```
+///
+/// [`LspServiceBuilder::connection`]: crate::LspServiceBuilder::connection
+/// [`LspService::new_with_connection`]: crate::LspService::new_with_connection
+/// [`Client::connection`]: crate::Client::connection
+#[derive(Clone)]
+pub struct ConnectionInfo {
+    id: u64,
+    transport: TransportKind,
+    peer_addr: Option<SocketAddr>,
+    data: HashMap<TypeId, Arc<dyn Any + Send + Sync>>,
+}
+
+impl ConnectionInfo {
+    /// Creates the information about a new connection over the given transport.
+    pub fn new(transport: TransportKind) -> Self {
+        ConnectionInfo {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            transport,
+            peer_addr: None,
+            data: HashMap::new(),
+        }
+    }
+
+    /// Sets the address of the peer, for connections over a network.
+    pub fn with_peer_addr(mut self, addr: SocketAddr) -> Self {
+        self.peer_addr = Some(addr);
+        self
+    }
+
+    /// Overrides the identifier of the connection, e.g. with one assigned by the embedder.
+    pub fn with_id(mut self, id: u64) -> Self {
+        self.id = id;
+        self
+    }
+
+    /// Attaches a value to the connection, replacing any previous value of the same type.
+    pub fn with_data<T: Any + Send + Sync>(mut self, value: T) -> Self {
+        self.data.insert(TypeId::of::<T>(), Arc::new(value));
+        self
+    }
+
+    /// Returns the identifier of the connection.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Returns the transport over which the client is connected.
+    pub fn transport(&self) -> &TransportKind {
+        &self.transport
+    }
+
+    /// Returns the address of the peer, if it was set.
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer_addr
+    }
+
+    /// Returns the value of type `T` attached with [`with_data`](ConnectionInfo::with_data).
+    pub fn data<T: Any + Send + Sync>(&self) -> Option<&T> {
+        self.data.get(&TypeId::of::<T>()).and_then(|value| value.downcast_ref())
+    }
+}
+
+impl Debug for ConnectionInfo {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct(stringify!(ConnectionInfo))
+            .field("id", &self.id)
+            .field("transport", &self.transport)
+            .field("peer_addr", &self.peer_addr)
+            .field("data", &self.data.len())
+            .finish()
+    }
+}
+
+impl Display for ConnectionInfo {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "#{} {}", self.id, self.transport)?;
+        if let Some(addr) = self.peer_addr {
+            write!(f, " {}", addr)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn connection_info() {
+        #[derive(Debug, PartialEq)]
+        struct User(&'static str);
+
+        let first = ConnectionInfo::new(TransportKind::Stdio);
+        let second = ConnectionInfo::new(TransportKind::Tcp);
+        assert_ne!(first.id(), second.id());
+
+        let addr = "127.0.0.1:9257".parse().unwrap();
+        let info = second.with_id(7).with_peer_addr(addr).with_data(User("alice"));
+        assert_eq!(info.transport(), &TransportKind::Tcp);
+        assert_eq!(info.peer_addr(), Some(addr));
+        assert_eq!(info.data::<User>(), Some(&User("alice")));
+        assert_eq!(info.data::<String>(), None);
+        assert_eq!(info.to_string(), "#7 tcp 127.0.0.1:9257");
+        assert_eq!(ConnectionInfo::new(TransportKind::Other("pipe".into())).with_id(1).to_string(), "#1 pipe");
+    }
+}
@@ -0,0 +1,120 @@
+//! Information about the client request whose handler is currently running.
+
+use crate::{jsonrpc::Id, CancellationToken};
+use futures::future;
+use std::{borrow::Cow, cell::RefCell, future::Future};
+
+thread_local! {
+    static CURRENT: RefCell<Option<RequestContext>> = const { RefCell::new(None) };
+}
+
+/// The client-to-server request being handled by the calling request handler.
+///
+/// The context is available through [`RequestContext::current`] while a request handler is being
+/// polled. Requests sent through the [`Client`] from within the handler are linked to the context:
+/// once the client cancels the originating request, pending requests to the client are canceled
+/// as well, instead of waiting for responses nobody is interested in anymore.
+///
+/// # Example
+///
+/// ```rust
+/// # use lspower::{jsonrpc::Result, lsp::*, RequestContext};
+/// # async fn hover(_: HoverParams) -> Result<Option<Hover>> {
+/// if let Some(context) = RequestContext::current() {
+///     log::debug!("handling {} request {}", context.method(), context.id());
+/// }
+/// # Ok(None)
+/// # }
+/// ```
+///
+/// [`Client`]: crate::Client
+#[derive(Clone, Debug)]
+pub struct RequestContext {
+    id: Id,
+    method: Cow<'static, str>,
+    token: CancellationToken,
+}
+
+impl RequestContext {
+    pub(crate) fn new(id: Id, method: Cow<'static, str>, token: CancellationToken) -> Self {
+        RequestContext { id, method, token }
+    }
+
+    /// Returns the context of the request whose handler is currently being polled, if any.
+    ///
+    /// Returns `None` outside of request handlers, e.g. in notification handlers or in tasks
+    /// spawned by a request handler.
+    pub fn current() -> Option<RequestContext> {
+        CURRENT.with(|current| current.borrow().clone())
+    }
+
+    /// Returns the ID of the request.
+    pub fn id(&self) -> &Id {
+        &self.id
+    }
+
+    /// Returns the method of the request.
+    pub fn method(&self) -> &str {
+        &self.method
+    }
+
+    /// Returns a token which is cancelled once the client cancels the request.
+    pub fn token(&self) -> &CancellationToken {
+        &self.token
+    }
+
+    /// Makes this context the current one whenever the given future is polled.
+    pub(crate) fn scope<F: Future>(self, fut: F) -> impl Future<Output = F::Output> {
+        let mut fut = Box::pin(fut);
+        future::poll_fn(move |cx| {
+            let _guard = Enter::new(self.clone());
+            fut.as_mut().poll(cx)
+        })
+    }
+}
+
+/// Polls the given future outside of any request context, so that requests it sends to the client
+/// are not linked to the request being handled by the caller.
+pub(crate) fn detached<F: Future>(fut: F) -> impl Future<Output = F::Output> {
+    let mut fut = Box::pin(fut);
+    future::poll_fn(move |cx| {
+        let _guard = Enter(CURRENT.with(|current| current.replace(None)));
+        fut.as_mut().poll(cx)
+    })
+}
+
+/// Restores the previously current context when dropped.
+struct Enter(Option<RequestContext>);
+
+impl Enter {
+    fn new(context: RequestContext) -> Self {
+        Enter(CURRENT.with(|current| current.replace(Some(context))))
+    }
+}
+
+impl Drop for Enter {
+    fn drop(&mut self) {
+        CURRENT.with(|current| *current.borrow_mut() = self.0.take());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn scope() {
+        assert!(RequestContext::current().is_none());
+
+        let context = RequestContext::new(Id::Number(1), "textDocument/hover".into(), Default::default());
+        let method = context
+            .scope(async {
+                tokio::task::yield_now().await;
+                RequestContext::current().map(|context| context.method().to_owned())
+            })
+            .await;
+
+        assert_eq!(method.as_deref(), Some("textDocument/hover"));
+        assert!(RequestContext::current().is_none());
+    }
+}
@@ -0,0 +1,272 @@
+//! Result IDs and "unchanged" reports for pulled diagnostics.
+//!
+//! With the pull model of the specification 3.17, clients request the diagnostics of a document
+//! with `textDocument/diagnostic`, passing the result ID of the report they received last. If the
+//! diagnostics did not change since, the server answers with an "unchanged" report referring to
+//! that ID instead of sending them again. A [`DiagnosticsCache`] keeps the diagnostics last
+//! reported for each document and chooses between both kinds of reports, given the current
+//! diagnostics of the document.
+//!
+//! `lsp-types` does not provide the types of the pull model yet, so this module defines the
+//! reports following its conventions. Since the `textDocument/diagnostic` request has no method on
+//! the [`LanguageServer`] trait, it can be handled in [`request_else`].
+//!
+//! # Example
+//!
+//! ```rust
+//! # use lspower::{diagnostics::{DiagnosticsCache, DocumentDiagnosticReport}, lsp::*};
+//! let cache = DiagnosticsCache::new();
+//! let uri = Url::parse("file:///a.rs").unwrap();
+//! let unused = Diagnostic::new_simple(Range::default(), "unused variable".into());
+//!
+//! // The client has no previous result.
+//! let report = cache.report(&uri, None, vec![unused.clone()]);
+//! let result_id = report.result_id().unwrap().to_owned();
+//! assert!(matches!(report, DocumentDiagnosticReport::Full(_)));
+//!
+//! // The diagnostics did not change since the result the client refers to.
+//! let report = cache.report(&uri, Some(&result_id), vec![unused]);
+//! assert!(matches!(report, DocumentDiagnosticReport::Unchanged(_)));
+//! ```
+//!
+//! [`LanguageServer`]: crate::LanguageServer
+//! [`request_else`]: crate::LanguageServer::request_else
+
+use crate::result_cache::ResultCache;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fmt::{self, Debug, Formatter},
+    sync::Mutex,
+};
+
+/// A diagnostic report with a full set of problems.
+///
+/// @since 3.17.0
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FullDocumentDiagnosticReport {
+    /// An optional result ID. If provided, it is sent on the next diagnostic request for the
+    /// same document.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub result_id: Option<String>,
+    /// The actual items.
+    pub items: Vec<lsp::Diagnostic>,
+}
+
+/// A diagnostic report indicating that the last returned report is still accurate.
+///
+/// @since 3.17.0
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct UnchangedDocumentDiagnosticReport {
+    /// A result ID which is sent on the next diagnostic request for the same document.
+    pub result_id: String,
+}
+
+/// A full or an unchanged diagnostic report of a related document.
+///
+/// @since 3.17.0
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum DocumentDiagnosticReportKind {
+    /// A report with a full set of problems.
+    Full(FullDocumentDiagnosticReport),
+    /// A report indicating that the last returned report is still accurate.
+    Unchanged(UnchangedDocumentDiagnosticReport),
+}
+
+/// A full diagnostic report with a set of related documents.
+///
+/// @since 3.17.0
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RelatedFullDocumentDiagnosticReport {
+    /// Diagnostics of related documents, e.g. of the headers included by a C file.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub related_documents: Option<HashMap<lsp::Url, DocumentDiagnosticReportKind>>,
+    /// The report of the requested document.
+    #[serde(flatten)]
+    pub full_document_diagnostic_report: FullDocumentDiagnosticReport,
+}
+
+/// An unchanged diagnostic report with a set of related documents.
+///
+/// @since 3.17.0
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RelatedUnchangedDocumentDiagnosticReport {
+    /// Diagnostics of related documents, e.g. of the headers included by a C file.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub related_documents: Option<HashMap<lsp::Url, DocumentDiagnosticReportKind>>,
+    /// The report of the requested document.
+    #[serde(flatten)]
+    pub unchanged_document_diagnostic_report: UnchangedDocumentDiagnosticReport,
+}
+
+/// The result of a `textDocument/diagnostic` request.
+///
+/// @since 3.17.0
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum DocumentDiagnosticReport {
+    /// A report with a full set of problems.
+    Full(RelatedFullDocumentDiagnosticReport),
+    /// A report indicating that the last returned report is still accurate.
+    Unchanged(RelatedUnchangedDocumentDiagnosticReport),
+}
+
+impl DocumentDiagnosticReport {
+    /// Returns the result ID of the report, if it has one.
+    pub fn result_id(&self) -> Option<&str> {
+        match self {
+            DocumentDiagnosticReport::Full(report) => report.full_document_diagnostic_report.result_id.as_deref(),
+            DocumentDiagnosticReport::Unchanged(report) => {
+                Some(&report.unchanged_document_diagnostic_report.result_id)
+            },
+        }
+    }
+}
+
+/// Cache of the diagnostics last reported to the client for each document, for answering
+/// `textDocument/diagnostic` requests.
+///
+/// Documents are keyed by their [normalized] URIs and should be [removed] once the client closes
+/// them.
+///
+/// [normalized]: crate::uri::normalize
+/// [removed]: DiagnosticsCache::remove
+pub struct DiagnosticsCache {
+    inner: Mutex<ResultCache<Vec<lsp::Diagnostic>>>,
+}
+
+impl DiagnosticsCache {
+    /// Creates a cache without diagnostics.
+    pub fn new() -> Self {
+        DiagnosticsCache::default()
+    }
+
+    /// Returns the report of the given diagnostics of the document, for a request referring to the
+    /// given previous result ID.
+    ///
+    /// If the diagnostics are equal to those last reported under the previous result ID, the report
+    /// is "unchanged". Otherwise, it is a full report, under a new result ID if the diagnostics
+    /// changed, and the diagnostics are kept for the next request.
+    pub fn report(
+        &self,
+        uri: &lsp::Url,
+        previous_result_id: Option<&str>,
+        diagnostics: Vec<lsp::Diagnostic>,
+    ) -> DocumentDiagnosticReport {
+        let mut inner = self.inner.lock().unwrap();
+        let result_id = match inner.get(uri) {
+            Some(cached) if cached.value == diagnostics => {
+                if previous_result_id == Some(&cached.result_id) {
+                    return DocumentDiagnosticReport::Unchanged(RelatedUnchangedDocumentDiagnosticReport {
+                        related_documents: None,
+                        unchanged_document_diagnostic_report: UnchangedDocumentDiagnosticReport {
+                            result_id: cached.result_id.clone(),
+                        },
+                    });
+                }
+                cached.result_id.clone()
+            },
+            _ => inner.insert(uri, diagnostics.clone()),
+        };
+
+        DocumentDiagnosticReport::Full(RelatedFullDocumentDiagnosticReport {
+            related_documents: None,
+            full_document_diagnostic_report: FullDocumentDiagnosticReport {
+                result_id: Some(result_id),
+                items: diagnostics,
+            },
+        })
+    }
+
+    /// Returns the diagnostics last reported for the document, along with their result ID.
+    pub fn get(&self, uri: &lsp::Url) -> Option<(String, Vec<lsp::Diagnostic>)> {
+        let inner = self.inner.lock().unwrap();
+        let cached = inner.get(uri)?;
+        Some((cached.result_id.clone(), cached.value.clone()))
+    }
+
+    /// Forgets the diagnostics of the document, e.g. once the client closed it.
+    pub fn remove(&self, uri: &lsp::Url) {
+        self.inner.lock().unwrap().remove(uri);
+    }
+
+    /// Forgets the diagnostics of all documents, so that the next reports are full ones.
+    pub fn clear(&self) {
+        self.inner.lock().unwrap().clear();
+    }
+
+    /// Returns the number of documents whose diagnostics are kept.
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().len()
+    }
+
+    /// Returns `true` if the diagnostics of no documents are kept.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for DiagnosticsCache {
+    fn default() -> Self {
+        DiagnosticsCache {
+            inner: Mutex::new(ResultCache::new(usize::MAX)),
+        }
+    }
+}
+
+impl Debug for DiagnosticsCache {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct(stringify!(DiagnosticsCache))
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn diagnostic(message: &str) -> lsp::Diagnostic {
+        lsp::Diagnostic::new_simple(lsp::Range::default(), message.into())
+    }
+
+    #[test]
+    fn report() {
+        let cache = DiagnosticsCache::new();
+        let uri = lsp::Url::parse("file:///a.rs").unwrap();
+
+        let report = cache.report(&uri, None, vec![diagnostic("unused")]);
+        assert_eq!(report.result_id(), Some("0"));
+        assert!(matches!(report, DocumentDiagnosticReport::Full(_)));
+        let report = cache.report(&uri, Some("0"), vec![diagnostic("unused")]);
+        let expected = json!({ "kind": "unchanged", "resultId": "0" });
+        assert_eq!(serde_json::to_value(&report).unwrap(), expected);
+
+        // The client refers to an outdated result, but the diagnostics did not change.
+        let report = cache.report(&uri, Some("outdated"), vec![diagnostic("unused")]);
+        assert!(matches!(report, DocumentDiagnosticReport::Full(_)));
+        assert_eq!(report.result_id(), Some("0"));
+
+        let report = cache.report(&uri, Some("0"), Vec::new());
+        let expected = json!({ "kind": "full", "resultId": "1", "items": [] });
+        assert_eq!(serde_json::to_value(&report).unwrap(), expected);
+        assert_eq!(serde_json::from_value::<DocumentDiagnosticReport>(expected).unwrap(), report);
+        assert_eq!(cache.get(&uri), Some(("1".into(), Vec::new())));
+
+        // Other spellings of the URI refer to the same document.
+        let spelled = lsp::Url::parse("file:///%61.rs").unwrap();
+        assert_eq!(cache.report(&spelled, Some("1"), Vec::new()).result_id(), Some("1"));
+
+        cache.remove(&uri);
+        assert!(cache.is_empty());
+        assert_eq!(cache.report(&uri, Some("1"), Vec::new()).result_id(), Some("2"));
+        cache.clear();
+        assert_eq!(cache.len(), 0);
+    }
+}
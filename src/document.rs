@@ -0,0 +1,295 @@
+//! Storage of the text documents the client opened.
+//!
+//! A [`DocumentStore`] keeps the contents of the open documents up to date from the
+//! `textDocument/didOpen`, `textDocument/didChange` and `textDocument/didClose` notifications.
+//! Request handlers take a [`DocumentSnapshot`] of the document they operate on, which holds the
+//! contents and the version of the document at the time it was taken. Snapshots are immutable and
+//! cheap to clone, since changes replace the contents of a document rather than modifying them, so
+//! a handler sees consistent contents for its whole duration, even while changes of the document
+//! arrive concurrently. Handlers can compare the [`version`] of their snapshot with the current
+//! one to find out whether their result is outdated.
+//!
+//! # Example
+//!
+//! ```rust
+//! # use lspower::{document::DocumentStore, jsonrpc::Result, lsp::*, LanguageServer};
+//! #[derive(Debug, Default)]
+//! struct Backend {
+//!     documents: DocumentStore,
+//! }
+//!
+//! #[lspower::async_trait]
+//! impl LanguageServer for Backend {
+//!     async fn initialize(&self, _: InitializeParams) -> Result<InitializeResult> {
+//!         Ok(InitializeResult::default())
+//!     }
+//!
+//!     async fn shutdown(&self) -> Result<()> {
+//!         Ok(())
+//!     }
+//!
+//!     async fn did_open(&self, params: DidOpenTextDocumentParams) {
+//!         self.documents.did_open(&params);
+//!     }
+//!
+//!     async fn did_change(&self, params: DidChangeTextDocumentParams) {
+//!         self.documents.did_change(&params);
+//!     }
+//!
+//!     async fn did_close(&self, params: DidCloseTextDocumentParams) {
+//!         self.documents.did_close(&params);
+//!     }
+//!
+//!     async fn hover(&self, params: HoverParams) -> Result<Option<Hover>> {
+//!         let position = params.text_document_position_params;
+//!         let document = match self.documents.snapshot(&position.text_document.uri) {
+//!             Some(document) => document,
+//!             None => return Ok(None),
+//!         };
+//!         let offset = document.offset_at(position.position);
+//!         let word = document.text()[offset ..].split_whitespace().next().unwrap_or_default();
+//!         Ok(Some(Hover {
+//!             contents: HoverContents::Scalar(MarkedString::String(word.into())),
+//!             range: None,
+//!         }))
+//!     }
+//! }
+//! ```
+//!
+//! [`version`]: DocumentSnapshot::version
+
+mod text;
+
+use self::text::Text;
+use crate::uri;
+use lsp::Url;
+use std::{
+    collections::HashMap,
+    fmt::{self, Debug, Formatter},
+    sync::{Arc, Mutex},
+};
+
+/// The text documents the client opened, keyed by their [normalized] URIs.
+///
+/// Positions are interpreted in UTF-16 code units, as the protocol requires. Cloning the store
+/// returns a handle to the same documents.
+///
+/// The text of a document is stored as a single string, so that each change copies it. With the
+/// `rope` crate feature, it is stored as a rope from the `ropey` crate instead, which makes changes
+/// of large documents cheap, at the expense of assembling the string returned by
+/// [`DocumentSnapshot::text`] on first use. The API is the same with either storage.
+///
+/// [normalized]: crate::uri::normalize
+#[derive(Clone, Default)]
+pub struct DocumentStore {
+    documents: Arc<Mutex<HashMap<Url, DocumentSnapshot>>>,
+}
+
+impl DocumentStore {
+    /// Creates a store without documents.
+    pub fn new() -> Self {
+        DocumentStore::default()
+    }
+
+    /// Adds the document opened with a `textDocument/didOpen` notification, replacing the document
+    /// if it was open already.
+    pub fn did_open(&self, params: &lsp::DidOpenTextDocumentParams) {
+        let document = &params.text_document;
+        let snapshot = DocumentSnapshot(Arc::new(Document {
+            uri: document.uri.clone(),
+            language_id: document.language_id.clone(),
+            version: document.version,
+            text: Text::new(document.text.clone()),
+        }));
+        self.documents.lock().unwrap().insert(uri::normalize(&document.uri), snapshot);
+    }
+
+    /// Applies the changes of a `textDocument/didChange` notification to the document.
+    ///
+    /// The changes are applied in order, each to the result of the previous one. Changes of
+    /// documents which are not open are logged and dropped. Snapshots taken before keep the
+    /// previous contents.
+    pub fn did_change(&self, params: &lsp::DidChangeTextDocumentParams) {
+        let uri = uri::normalize(&params.text_document.uri);
+        let mut documents = self.documents.lock().unwrap();
+        let current = match documents.get(&uri) {
+            Some(current) => current,
+            None => {
+                log::warn!("received changes of {}, which is not open", uri);
+                return;
+            },
+        };
+
+        let mut text = current.0.text.clone();
+        for change in &params.content_changes {
+            text.replace(change.range, &change.text);
+        }
+        let snapshot = DocumentSnapshot(Arc::new(Document {
+            uri: current.0.uri.clone(),
+            language_id: current.0.language_id.clone(),
+            version: params.text_document.version,
+            text,
+        }));
+        documents.insert(uri, snapshot);
+    }
+
+    /// Removes the document closed with a `textDocument/didClose` notification.
+    pub fn did_close(&self, params: &lsp::DidCloseTextDocumentParams) {
+        self.documents.lock().unwrap().remove(&uri::normalize(&params.text_document.uri));
+    }
+
+    /// Returns a snapshot of the current contents of the document, or `None` if it is not open.
+    pub fn snapshot(&self, uri: &Url) -> Option<DocumentSnapshot> {
+        self.documents.lock().unwrap().get(&uri::normalize(uri)).cloned()
+    }
+
+    /// Returns the URIs of all open documents, in no particular order.
+    pub fn uris(&self) -> Vec<Url> {
+        let documents = self.documents.lock().unwrap();
+        documents.values().map(|document| document.uri().clone()).collect()
+    }
+
+    /// Returns the number of open documents.
+    pub fn len(&self) -> usize {
+        self.documents.lock().unwrap().len()
+    }
+
+    /// Returns `true` if no documents are open.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Debug for DocumentStore {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct(stringify!(DocumentStore))
+            .field("documents", &self.len())
+            .finish()
+    }
+}
+
+struct Document {
+    uri: Url,
+    language_id: String,
+    version: i32,
+    text: Text,
+}
+
+/// The contents and the version of a document in a [`DocumentStore`] at some point in time.
+///
+/// Snapshots do not change as the document changes, and cloning them is cheap.
+#[derive(Clone)]
+pub struct DocumentSnapshot(Arc<Document>);
+
+impl DocumentSnapshot {
+    /// Returns the URI of the document, as sent by the client.
+    pub fn uri(&self) -> &Url {
+        &self.0.uri
+    }
+
+    /// Returns the language identifier of the document.
+    pub fn language_id(&self) -> &str {
+        &self.0.language_id
+    }
+
+    /// Returns the version of the document at the time of the snapshot.
+    pub fn version(&self) -> i32 {
+        self.0.version
+    }
+
+    /// Returns the text of the document at the time of the snapshot.
+    pub fn text(&self) -> &str {
+        self.0.text.as_str()
+    }
+
+    /// Returns the length of the text in bytes.
+    pub fn len(&self) -> usize {
+        self.0.text.len()
+    }
+
+    /// Returns `true` if the text is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the byte offset of the given position in the text.
+    ///
+    /// Positions past the end of a line refer to the end of the line, positions past the last line
+    /// to the end of the text, and positions within a character to the start of the character.
+    pub fn offset_at(&self, position: lsp::Position) -> usize {
+        self.0.text.offset_at(position)
+    }
+
+    /// Returns the position of the given byte offset in the text.
+    ///
+    /// Offsets past the end of the text, or within a character, refer to the end of the text and
+    /// the start of the character.
+    pub fn position_at(&self, offset: usize) -> lsp::Position {
+        self.0.text.position_at(offset)
+    }
+}
+
+impl Debug for DocumentSnapshot {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct(stringify!(DocumentSnapshot))
+            .field("uri", &self.0.uri)
+            .field("language_id", &self.0.language_id)
+            .field("version", &self.0.version)
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position(line: u32, character: u32) -> lsp::Position {
+        lsp::Position { line, character }
+    }
+
+    fn change(range: Option<(lsp::Position, lsp::Position)>, text: &str) -> lsp::TextDocumentContentChangeEvent {
+        lsp::TextDocumentContentChangeEvent {
+            range: range.map(|(start, end)| lsp::Range { start, end }),
+            range_length: None,
+            text: text.into(),
+        }
+    }
+
+    #[test]
+    fn snapshots() {
+        let store = DocumentStore::new();
+        let uri = Url::parse("file:///C%3A/main.rs").unwrap();
+        store.did_open(&lsp::DidOpenTextDocumentParams {
+            text_document: lsp::TextDocumentItem::new(uri.clone(), "rust".into(), 1, "fn main() {}\n".into()),
+        });
+        let before = store.snapshot(&Url::parse("file:///c:/main.rs").unwrap()).unwrap();
+
+        store.did_change(&lsp::DidChangeTextDocumentParams {
+            text_document: lsp::VersionedTextDocumentIdentifier::new(uri.clone(), 2),
+            content_changes: vec![
+                change(Some((position(0, 3), position(0, 7))), "start"),
+                change(Some((position(1, 0), position(1, 0))), "// end\n"),
+            ],
+        });
+        let after = store.snapshot(&uri).unwrap();
+        assert_eq!((before.version(), before.text()), (1, "fn main() {}\n"));
+        assert_eq!((after.version(), after.text()), (2, "fn start() {}\n// end\n"));
+        assert_eq!(after.language_id(), "rust");
+        assert_eq!(after.uri(), &uri);
+
+        store.did_change(&lsp::DidChangeTextDocumentParams {
+            text_document: lsp::VersionedTextDocumentIdentifier::new(uri.clone(), 3),
+            content_changes: vec![change(None, "")],
+        });
+        assert_eq!(store.snapshot(&uri).unwrap().text(), "");
+        assert_eq!(store.uris(), vec![uri.clone()]);
+
+        store.did_close(&lsp::DidCloseTextDocumentParams {
+            text_document: lsp::TextDocumentIdentifier::new(uri.clone()),
+        });
+        assert!(store.snapshot(&uri).is_none());
+        assert!(store.is_empty());
+        assert_eq!(after.text(), "fn start() {}\n// end\n");
+    }
+}
@@ -0,0 +1,201 @@
+//! Storage of the text of a document, either as a string or, with the `rope` feature, as a rope.
+
+#[cfg(feature = "rope")]
+use std::sync::OnceLock;
+
+/// The text of a document, addressed by LSP positions in UTF-16 code units.
+///
+/// Only `\n` and `\r\n` end lines, in both representations.
+#[cfg(not(feature = "rope"))]
+#[derive(Clone)]
+pub(super) struct Text(String);
+
+/// The text of a document, addressed by LSP positions in UTF-16 code units.
+///
+/// Only `\n` and `\r\n` end lines, in both representations. The text is assembled into a string
+/// the first time it is borrowed as a whole.
+#[cfg(feature = "rope")]
+pub(super) struct Text {
+    rope: ropey::Rope,
+    string: OnceLock<String>,
+}
+
+#[cfg(not(feature = "rope"))]
+impl Text {
+    pub(super) fn new(text: String) -> Self {
+        Text(text)
+    }
+
+    pub(super) fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub(super) fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Replaces the given range of the text, or the whole text if there is none.
+    pub(super) fn replace(&mut self, range: Option<lsp::Range>, text: &str) {
+        match range {
+            Some(range) => {
+                let start = self.offset_at(range.start);
+                let end = self.offset_at(range.end).max(start);
+                self.0.replace_range(start .. end, text);
+            },
+            None => self.0 = text.into(),
+        }
+    }
+
+    pub(super) fn offset_at(&self, position: lsp::Position) -> usize {
+        let text = &self.0;
+        let mut start = 0;
+        for _ in 0 .. position.line {
+            match text[start ..].find('\n') {
+                Some(end) => start += end + 1,
+                None => return text.len(),
+            }
+        }
+
+        let line = &text[start ..];
+        let line = &line[.. line.find('\n').unwrap_or(line.len())];
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        let mut units = 0;
+        for (offset, c) in line.char_indices() {
+            units += c.len_utf16() as u32;
+            if units > position.character {
+                return start + offset;
+            }
+        }
+        start + line.len()
+    }
+
+    pub(super) fn position_at(&self, offset: usize) -> lsp::Position {
+        let text = &self.0;
+        let mut offset = offset.min(text.len());
+        while !text.is_char_boundary(offset) {
+            offset -= 1;
+        }
+        let before = &text[.. offset];
+        let start = before.rfind('\n').map_or(0, |end| end + 1);
+        lsp::Position {
+            line: before.matches('\n').count() as u32,
+            character: before[start ..].encode_utf16().count() as u32,
+        }
+    }
+}
+
+#[cfg(feature = "rope")]
+impl Text {
+    pub(super) fn new(text: String) -> Self {
+        Text {
+            rope: ropey::Rope::from_str(&text),
+            string: OnceLock::new(),
+        }
+    }
+
+    pub(super) fn as_str(&self) -> &str {
+        self.string.get_or_init(|| self.rope.to_string())
+    }
+
+    pub(super) fn len(&self) -> usize {
+        self.rope.len_bytes()
+    }
+
+    /// Replaces the given range of the text, or the whole text if there is none.
+    pub(super) fn replace(&mut self, range: Option<lsp::Range>, text: &str) {
+        self.string = OnceLock::new();
+        match range {
+            Some(range) => {
+                let start = self.offset_at(range.start);
+                let end = self.offset_at(range.end).max(start);
+                let (start, end) = (self.rope.byte_to_char(start), self.rope.byte_to_char(end));
+                self.rope.remove(start .. end);
+                self.rope.insert(start, text);
+            },
+            None => self.rope = ropey::Rope::from_str(text),
+        }
+    }
+
+    pub(super) fn offset_at(&self, position: lsp::Position) -> usize {
+        let rope = &self.rope;
+        let line = position.line as usize;
+        if line >= rope.len_lines() {
+            return rope.len_bytes();
+        }
+
+        let slice = rope.line(line);
+        let mut len = slice.len_chars();
+        if len > 0 && slice.char(len - 1) == '\n' {
+            len -= 1;
+            if len > 0 && slice.char(len - 1) == '\r' {
+                len -= 1;
+            }
+        }
+        let start = rope.line_to_char(line);
+        let end = rope.char_to_utf16_cu(start + len);
+        let unit = (rope.char_to_utf16_cu(start) + position.character as usize).min(end);
+        rope.char_to_byte(rope.utf16_cu_to_char(unit))
+    }
+
+    pub(super) fn position_at(&self, offset: usize) -> lsp::Position {
+        let rope = &self.rope;
+        let c = rope.byte_to_char(offset.min(rope.len_bytes()));
+        let line = rope.char_to_line(c);
+        let start = rope.line_to_char(line);
+        lsp::Position {
+            line: line as u32,
+            character: (rope.char_to_utf16_cu(c) - rope.char_to_utf16_cu(start)) as u32,
+        }
+    }
+}
+
+/// Clones share the rope, which is cheap, but not the string assembled from it, which would double
+/// the memory held by each clone.
+#[cfg(feature = "rope")]
+impl Clone for Text {
+    fn clone(&self) -> Self {
+        Text {
+            rope: self.rope.clone(),
+            string: OnceLock::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn position(line: u32, character: u32) -> lsp::Position {
+        lsp::Position { line, character }
+    }
+
+    #[test]
+    fn offsets() {
+        let text = Text::new("a𐐀b\r\nline\n".into());
+        assert_eq!(text.offset_at(position(0, 1)), 1);
+        assert_eq!(text.offset_at(position(0, 2)), 1);
+        assert_eq!(text.offset_at(position(0, 3)), 5);
+        assert_eq!(text.offset_at(position(0, 9)), 6);
+        assert_eq!(text.offset_at(position(1, 2)), 10);
+        assert_eq!(text.offset_at(position(5, 0)), text.len());
+        assert_eq!(text.position_at(5), position(0, 3));
+        assert_eq!(text.position_at(2), position(0, 1));
+        assert_eq!(text.position_at(10), position(1, 2));
+        assert_eq!(text.position_at(100), position(2, 0));
+    }
+
+    #[test]
+    fn replace() {
+        let mut text = Text::new("fn main() {}\n".into());
+        assert_eq!(text.as_str(), "fn main() {}\n");
+        let range = lsp::Range::new(position(0, 3), position(0, 7));
+        text.replace(Some(range), "start");
+        text.replace(Some(lsp::Range::new(position(1, 0), position(1, 0))), "// 𐐀\n");
+        assert_eq!(text.as_str(), "fn start() {}\n// 𐐀\n");
+        assert_eq!(text.position_at(text.len()), position(2, 0));
+        let clone = text.clone();
+        text.replace(None, "");
+        assert_eq!(text.as_str(), "");
+        assert_eq!(clone.as_str(), "fn start() {}\n// 𐐀\n");
+    }
+}
@@ -0,0 +1,359 @@
+//! In-process connection between a language server and a client, through the full transport.
+
+#[cfg(feature = "runtime-agnostic")]
+use async_codec_lite::{FramedRead, FramedWrite};
+#[cfg(feature = "runtime-agnostic")]
+use futures::io::{AsyncRead, AsyncWrite};
+
+#[cfg(feature = "runtime-tokio")]
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+#[cfg(feature = "runtime-tokio")]
+use tokio_util::codec::{FramedRead, FramedWrite};
+
+use crate::{codec::LanguageServerCodec, ExitReason, LspService, MessageStream, Server};
+use bytes::{Buf, BytesMut};
+use futures::{future::BoxFuture, FutureExt, SinkExt, StreamExt};
+use serde_json::{json, Value};
+use std::{
+    collections::VecDeque,
+    fmt::{self, Debug, Formatter},
+    io,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+};
+
+/// The number of bytes buffered by each direction of the connection.
+const PIPE_CAPACITY: usize = 64 * 1024;
+
+/// Connects the service to a [`DuplexClient`] in the same process, through in-memory byte pipes.
+///
+/// Unlike calling the service directly, messages pass through the whole transport: they are
+/// framed and encoded by the client, decoded and dispatched by a [`Server`], and the responses and
+/// the messages of the stream are encoded by the server and decoded by the client. This makes for
+/// fast integration tests of a language server without spawning processes or opening sockets.
+///
+/// The returned future serves the service until the client sends `exit` or is dropped, and must be
+/// polled for messages to be exchanged, e.g. by spawning it or by joining it with the client code.
+///
+/// # Example
+///
+/// ```rust
+/// # use lspower::{jsonrpc::Result, lsp::*, LanguageServer, LspService};
+/// # use serde_json::json;
+/// # #[derive(Debug)]
+/// # struct Backend;
+/// # #[lspower::async_trait]
+/// # impl LanguageServer for Backend {
+/// #     async fn initialize(&self, _: InitializeParams) -> Result<InitializeResult> {
+/// #         Ok(InitializeResult::default())
+/// #     }
+/// #     async fn shutdown(&self) -> Result<()> {
+/// #         Ok(())
+/// #     }
+/// # }
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// let (service, messages) = LspService::new(|_| Backend);
+/// let (mut client, server) = lspower::duplex(service, messages);
+/// let client = async move {
+///     let response = client.request("initialize", json!({ "capabilities": {} })).await.unwrap();
+///     assert_eq!(response["result"], json!({ "capabilities": {} }));
+///     client.notify("exit", None).await.unwrap();
+/// };
+/// let ((), reason) = futures::join!(client, server);
+/// assert_eq!(reason, lspower::ExitReason::ClientRequested);
+/// # }
+/// ```
+pub fn duplex(service: LspService, messages: MessageStream) -> (DuplexClient, BoxFuture<'static, ExitReason>) {
+    let (client_writer, server_reader) = pipe();
+    let (server_writer, client_reader) = pipe();
+    let server = Server::new(server_reader, server_writer)
+        .interleave(messages)
+        .serve(service)
+        .boxed();
+    let client = DuplexClient {
+        reader: FramedRead::new(client_reader, LanguageServerCodec::default()),
+        writer: FramedWrite::new(client_writer, LanguageServerCodec::default()),
+        received: VecDeque::new(),
+        next_id: 0,
+    };
+    (client, server)
+}
+
+/// The client end of a connection created with [`duplex`], exchanging JSON-RPC messages as JSON
+/// values.
+pub struct DuplexClient {
+    reader: FramedRead<PipeReader, LanguageServerCodec<Value>>,
+    writer: FramedWrite<PipeWriter, LanguageServerCodec<Value>>,
+    received: VecDeque<Value>,
+    next_id: u64,
+}
+
+impl DuplexClient {
+    /// Sends the given message to the server.
+    pub async fn send(&mut self, message: Value) -> io::Result<()> {
+        self.writer.send(message).await.map_err(|error| io::Error::other(error.to_string()))
+    }
+
+    /// Receives the next message from the server, or `None` once the server stopped writing.
+    ///
+    /// This includes the messages received while waiting for the response to a
+    /// [`request`](DuplexClient::request).
+    pub async fn recv(&mut self) -> Option<Value> {
+        if let Some(message) = self.received.pop_front() {
+            return Some(message);
+        }
+        match self.reader.next().await? {
+            Ok(message) => Some(message),
+            Err(error) => {
+                log::error!("failed to decode message from server: {}", error);
+                None
+            },
+        }
+    }
+
+    /// Sends a request with the given method and params, returning the response of the server.
+    ///
+    /// The requests and notifications the server sends in the meantime are kept for
+    /// [`recv`](DuplexClient::recv). Requests of the server must not block its response, since
+    /// they are not answered while waiting.
+    pub async fn request(&mut self, method: &str, params: impl Into<Option<Value>>) -> io::Result<Value> {
+        let id = self.next_id;
+        self.next_id += 1;
+        let mut request = json!({ "jsonrpc": "2.0", "method": method, "id": id });
+        if let Some(params) = params.into() {
+            request["params"] = params;
+        }
+        self.send(request).await?;
+
+        loop {
+            let message = match self.reader.next().await {
+                Some(Ok(message)) => message,
+                Some(Err(error)) => return Err(io::Error::new(io::ErrorKind::InvalidData, error.to_string())),
+                None => return Err(io::ErrorKind::UnexpectedEof.into()),
+            };
+            if message.get("method").is_none() && message["id"] == id {
+                return Ok(message);
+            }
+            self.received.push_back(message);
+        }
+    }
+
+    /// Sends a notification with the given method and params.
+    pub async fn notify(&mut self, method: &str, params: impl Into<Option<Value>>) -> io::Result<()> {
+        let mut notification = json!({ "jsonrpc": "2.0", "method": method });
+        if let Some(params) = params.into() {
+            notification["params"] = params;
+        }
+        self.send(notification).await
+    }
+}
+
+impl Debug for DuplexClient {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct(stringify!(DuplexClient))
+            .field("received", &self.received)
+            .field("next_id", &self.next_id)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Default)]
+struct PipeState {
+    buffer: BytesMut,
+    /// Whether the writer was dropped or shut down.
+    closed: bool,
+    /// Whether the reader was dropped.
+    abandoned: bool,
+    reader: Option<Waker>,
+    writer: Option<Waker>,
+}
+
+/// Creates an in-memory byte pipe, whose reader reaches the end once the writer was dropped.
+fn pipe() -> (PipeWriter, PipeReader) {
+    let state = Arc::new(Mutex::new(PipeState::default()));
+    (PipeWriter(state.clone()), PipeReader(state))
+}
+
+struct PipeReader(Arc<Mutex<PipeState>>);
+
+impl PipeReader {
+    fn read(&self, cx: &mut Context, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        let mut state = self.0.lock().unwrap();
+        if state.buffer.is_empty() && !state.closed {
+            state.reader = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+        let len = buf.len().min(state.buffer.len());
+        buf[.. len].copy_from_slice(&state.buffer[.. len]);
+        state.buffer.advance(len);
+        if let Some(writer) = state.writer.take() {
+            writer.wake();
+        }
+        Poll::Ready(Ok(len))
+    }
+}
+
+impl Drop for PipeReader {
+    fn drop(&mut self) {
+        let mut state = self.0.lock().unwrap();
+        state.abandoned = true;
+        if let Some(writer) = state.writer.take() {
+            writer.wake();
+        }
+    }
+}
+
+#[cfg(feature = "runtime-agnostic")]
+impl AsyncRead for PipeReader {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        self.read(cx, buf)
+    }
+}
+
+#[cfg(feature = "runtime-tokio")]
+impl AsyncRead for PipeReader {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context, buf: &mut ReadBuf) -> Poll<io::Result<()>> {
+        let len = futures::ready!(self.read(cx, buf.initialize_unfilled()))?;
+        buf.advance(len);
+        Poll::Ready(Ok(()))
+    }
+}
+
+struct PipeWriter(Arc<Mutex<PipeState>>);
+
+impl PipeWriter {
+    fn write(&self, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let mut state = self.0.lock().unwrap();
+        if state.abandoned || state.closed {
+            return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+        }
+        let len = buf.len().min(PIPE_CAPACITY - state.buffer.len());
+        if len == 0 && !buf.is_empty() {
+            state.writer = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+        state.buffer.extend_from_slice(&buf[.. len]);
+        if let Some(reader) = state.reader.take() {
+            reader.wake();
+        }
+        Poll::Ready(Ok(len))
+    }
+
+    fn close(&self) {
+        let mut state = self.0.lock().unwrap();
+        state.closed = true;
+        if let Some(reader) = state.reader.take() {
+            reader.wake();
+        }
+    }
+}
+
+impl Drop for PipeWriter {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+#[cfg(feature = "runtime-agnostic")]
+impl AsyncWrite for PipeWriter {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        self.write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _: &mut Context) -> Poll<io::Result<()>> {
+        self.close();
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(feature = "runtime-tokio")]
+impl AsyncWrite for PipeWriter {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        self.write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context) -> Poll<io::Result<()>> {
+        self.close();
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{jsonrpc::Result, LanguageServer};
+    use async_trait::async_trait;
+
+    #[derive(Debug)]
+    struct Mock(crate::Client);
+
+    #[async_trait]
+    impl LanguageServer for Mock {
+        async fn initialize(&self, _: lsp::InitializeParams) -> Result<lsp::InitializeResult> {
+            Ok(lsp::InitializeResult::default())
+        }
+
+        async fn initialized(&self, _: lsp::InitializedParams) {
+            self.0.log_message(lsp::MessageType::INFO, "initialized").await;
+        }
+
+        async fn shutdown(&self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn exchanges_messages() {
+        let (service, messages) = LspService::new(Mock);
+        let (mut client, server) = duplex(service, messages);
+
+        let client = async move {
+            let response = client.request("initialize", json!({ "capabilities": {} })).await.unwrap();
+            assert_eq!(response, json!({ "jsonrpc": "2.0", "result": { "capabilities": {} }, "id": 0 }));
+            client.notify("initialized", json!({})).await.unwrap();
+
+            // The log message may arrive before or after the response to `shutdown`.
+            let response = client.request("shutdown", None).await.unwrap();
+            assert_eq!(response["id"], 1);
+            let message = client.recv().await.unwrap();
+            assert_eq!(message["method"], "window/logMessage");
+            assert_eq!(message["params"]["message"], "initialized");
+            drop(client);
+        };
+        let ((), reason) = futures::join!(client, server);
+        assert_eq!(reason, ExitReason::TransportClosed);
+    }
+
+    #[tokio::test]
+    async fn pipe_backpressure() {
+        let (mut writer, reader) = pipe();
+        let data = vec![1; PIPE_CAPACITY + 1];
+        let written = futures::future::poll_fn(|cx| Pin::new(&mut writer).poll_write(cx, &data)).await;
+        assert_eq!(written.unwrap(), PIPE_CAPACITY);
+        let waker = futures::task::noop_waker();
+        let blocked = Pin::new(&mut writer).poll_write(&mut Context::from_waker(&waker), &data);
+        assert!(blocked.is_pending());
+
+        drop(writer);
+        let mut read = Vec::new();
+        let mut buf = [0; 1024];
+        loop {
+            let len = futures::future::poll_fn(|cx| reader.read(cx, &mut buf)).await.unwrap();
+            if len == 0 {
+                break;
+            }
+            read.extend_from_slice(&buf[.. len]);
+        }
+        assert_eq!(read.len(), PIPE_CAPACITY);
+    }
+}
@@ -0,0 +1,154 @@
+//! Bounded fan-out of events to any number of subscribers.
+
+use futures::stream::{FusedStream, Stream};
+use std::{
+    collections::VecDeque,
+    fmt::{self, Debug, Formatter},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+};
+
+#[derive(Debug)]
+struct Queue<T> {
+    events: VecDeque<T>,
+    waker: Option<Waker>,
+    closed: bool,
+}
+
+/// The subscribers of a feed, which are closed once the last handle to the feed is dropped.
+struct Subscribers<T>(Mutex<Vec<Arc<Mutex<Queue<T>>>>>);
+
+impl<T> Drop for Subscribers<T> {
+    fn drop(&mut self) {
+        for queue in self.0.get_mut().unwrap().drain(..) {
+            let mut queue = queue.lock().unwrap();
+            queue.closed = true;
+            if let Some(waker) = queue.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+/// Publishes events to all subscribers, each of which buffers at most `capacity` events.
+///
+/// Subscribers falling further behind miss the oldest events, so that a subscriber which is not
+/// polled neither stalls the publisher nor grows without bounds.
+pub(crate) struct Feed<T> {
+    subscribers: Arc<Subscribers<T>>,
+    capacity: usize,
+}
+
+impl<T: Clone> Feed<T> {
+    /// Creates a feed whose subscribers buffer the given number of events.
+    ///
+    /// A capacity of `0` is treated as `1`.
+    pub(crate) fn new(capacity: usize) -> Self {
+        Feed {
+            subscribers: Arc::new(Subscribers(Mutex::new(Vec::new()))),
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// Returns a stream of the events published from now on.
+    pub(crate) fn subscribe(&self) -> Subscription<T> {
+        let queue = Arc::new(Mutex::new(Queue {
+            events: VecDeque::new(),
+            waker: None,
+            closed: false,
+        }));
+        self.subscribers.0.lock().unwrap().push(queue.clone());
+        Subscription(queue)
+    }
+
+    /// Publishes the event created by `event`, which is only called if there are subscribers.
+    pub(crate) fn publish(&self, event: impl FnOnce() -> T) {
+        let mut subscribers = self.subscribers.0.lock().unwrap();
+        subscribers.retain(|queue| Arc::strong_count(queue) > 1);
+        if subscribers.is_empty() {
+            return;
+        }
+
+        let event = event();
+        for queue in subscribers.iter() {
+            let mut queue = queue.lock().unwrap();
+            if queue.events.len() == self.capacity {
+                log::warn!("event subscriber lagged behind, dropping the oldest event");
+                queue.events.pop_front();
+            }
+            queue.events.push_back(event.clone());
+            if let Some(waker) = queue.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+impl<T> Clone for Feed<T> {
+    fn clone(&self) -> Self {
+        Feed {
+            subscribers: self.subscribers.clone(),
+            capacity: self.capacity,
+        }
+    }
+}
+
+impl<T> Debug for Feed<T> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct(stringify!(Feed))
+            .field("subscribers", &self.subscribers.0.lock().unwrap().len())
+            .field("capacity", &self.capacity)
+            .finish()
+    }
+}
+
+/// Stream of the events of a [`Feed`], which ends once all handles to the feed were dropped.
+pub(crate) struct Subscription<T>(Arc<Mutex<Queue<T>>>);
+
+impl<T> Stream for Subscription<T> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<T>> {
+        let mut queue = self.0.lock().unwrap();
+        match queue.events.pop_front() {
+            Some(event) => Poll::Ready(Some(event)),
+            None if queue.closed => Poll::Ready(None),
+            None => {
+                queue.waker = Some(cx.waker().clone());
+                Poll::Pending
+            },
+        }
+    }
+}
+
+impl<T> FusedStream for Subscription<T> {
+    fn is_terminated(&self) -> bool {
+        let queue = self.0.lock().unwrap();
+        queue.closed && queue.events.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+
+    #[tokio::test]
+    async fn drops_oldest() {
+        let feed = Feed::new(2);
+        feed.publish(|| unreachable!("no subscribers"));
+
+        let mut first = feed.subscribe();
+        let second = feed.subscribe();
+        feed.publish(|| 1);
+        assert_eq!(first.next().await, Some(1));
+        feed.publish(|| 2);
+        feed.publish(|| 3);
+        drop(feed);
+
+        assert_eq!(first.by_ref().collect::<Vec<_>>().await, vec![2, 3]);
+        assert!(first.is_terminated());
+        assert_eq!(second.collect::<Vec<_>>().await, vec![2, 3]);
+    }
+}
@@ -0,0 +1,228 @@
+//! Adapter for embedding an [`LspService`] into HTTP servers.
+
+use crate::{
+    jsonrpc::{Error, Incoming, Outgoing, Response},
+    ExitedError,
+    LspService,
+};
+use bytes::Bytes;
+use futures::future::{self, BoxFuture, FutureExt};
+use http::{header, Method, Request, StatusCode};
+use http_body::Body;
+use http_body_util::{BodyExt, Full, LengthLimitError, Limited};
+use std::{
+    convert::Infallible,
+    fmt::{self, Debug, Formatter},
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+use tower_service::Service;
+
+/// Adapter serving an [`LspService`] as a [`tower_service::Service`] over HTTP requests.
+///
+/// Each `POST` request carries a single JSON-RPC message in its body. Responses to client requests
+/// are returned with status `200 OK` and a JSON body, while notifications and responses to
+/// server-to-client requests are acknowledged with `204 No Content`. Bodies which are not valid
+/// JSON-RPC messages are answered with a JSON-RPC "parse error" (`-32700`), and requests arriving
+/// after the server exited are answered with `503 Service Unavailable`.
+///
+/// The adapter is compatible with `hyper` 1.x and `axum` 0.7 or later, where it can be mounted on
+/// a route with `Router::route_service("/lsp", HttpService::new(service))`. Messages sent from the
+/// server to the client are not part of the HTTP responses, so the [`MessageStream`] returned along
+/// with the `LspService` has to be forwarded to the client separately, e.g. over a WebSocket or a
+/// server-sent events endpoint.
+///
+/// This type is only available with the `http` crate feature enabled.
+///
+/// [`MessageStream`]: crate::MessageStream
+#[derive(Clone)]
+pub struct HttpService {
+    service: Arc<Mutex<LspService>>,
+    max_body_size: usize,
+}
+
+impl HttpService {
+    /// Creates a new `HttpService` wrapping the given service.
+    pub fn new(service: LspService) -> Self {
+        HttpService {
+            service: Arc::new(Mutex::new(service)),
+            max_body_size: 16 * 1024 * 1024,
+        }
+    }
+
+    /// Sets the maximum size of request bodies in bytes, defaulting to 16 MiB.
+    ///
+    /// Larger requests are rejected with `413 Payload Too Large`.
+    pub fn max_body_size(mut self, size: usize) -> Self {
+        self.max_body_size = size;
+        self
+    }
+}
+
+impl From<LspService> for HttpService {
+    fn from(service: LspService) -> Self {
+        HttpService::new(service)
+    }
+}
+
+impl Debug for HttpService {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct(stringify!(HttpService))
+            .field("service", &self.service)
+            .field("max_body_size", &self.max_body_size)
+            .finish()
+    }
+}
+
+impl<B> Service<Request<B>> for HttpService
+where
+    B: Body + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    type Error = Infallible;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+    type Response = http::Response<Full<Bytes>>;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        // Once the server exited, requests are still accepted and answered with an error status.
+        self.service.lock().unwrap().poll_ready(cx).map(|_| Ok(()))
+    }
+
+    fn call(&mut self, request: Request<B>) -> Self::Future {
+        let service = self.service.clone();
+        let max_body_size = self.max_body_size;
+
+        async move {
+            if request.method() != Method::POST {
+                let mut response = status(StatusCode::METHOD_NOT_ALLOWED);
+                let allow = header::HeaderValue::from_static("POST");
+                response.headers_mut().insert(header::ALLOW, allow);
+                return Ok(response);
+            }
+
+            let body = match Limited::new(request.into_body(), max_body_size).collect().await {
+                Ok(body) => body.to_bytes(),
+                Err(error) if error.is::<LengthLimitError>() => return Ok(status(StatusCode::PAYLOAD_TOO_LARGE)),
+                Err(error) => {
+                    log::error!("failed to read request body: {}", error);
+                    return Ok(status(StatusCode::BAD_REQUEST));
+                },
+            };
+
+            let message = std::str::from_utf8(&body).map_err(serde::de::Error::custom);
+            let message = match message.and_then(Incoming::from_json) {
+                Ok(message) => message,
+                Err(error) => {
+                    log::error!("failed to decode message: {}", error);
+                    let response = Response::error(None, Error::parse_error());
+                    return Ok(json(&Outgoing::Response(response)));
+                },
+            };
+
+            // Clones of the adapter share the service, so readiness is only known to hold while the
+            // service is locked for the call.
+            let mut message = Some(message);
+            let response = future::poll_fn(|cx| {
+                let mut service = service.lock().unwrap();
+                service.poll_ready(cx).map_ok(|()| service.call(message.take().unwrap()))
+            });
+            let response = match response.await {
+                Ok(response) => response.await,
+                Err(error) => Err(error),
+            };
+            Ok(match response {
+                Ok(Some(outgoing)) => json(&outgoing),
+                Ok(None) => status(StatusCode::NO_CONTENT),
+                Err(ExitedError) => status(StatusCode::SERVICE_UNAVAILABLE),
+            })
+        }
+        .boxed()
+    }
+}
+
+fn status(status: StatusCode) -> http::Response<Full<Bytes>> {
+    let mut response = http::Response::new(Full::default());
+    *response.status_mut() = status;
+    response
+}
+
+fn json(message: &Outgoing) -> http::Response<Full<Bytes>> {
+    // Since `Outgoing` only contains JSON values, the `unwrap()` call below should never fail.
+    let body = serde_json::to_vec(message).unwrap();
+    let mut response = http::Response::new(Full::new(Bytes::from(body)));
+    let content_type = header::HeaderValue::from_static("application/json");
+    response.headers_mut().insert(header::CONTENT_TYPE, content_type);
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{jsonrpc::Result, LanguageServer};
+    use serde_json::{json, Value};
+
+    struct Mock;
+
+    #[async_trait::async_trait]
+    impl LanguageServer for Mock {
+        async fn initialize(&self, _: lsp::InitializeParams) -> Result<lsp::InitializeResult> {
+            Ok(lsp::InitializeResult::default())
+        }
+
+        async fn shutdown(&self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    fn post(body: impl Into<Bytes>) -> Request<Full<Bytes>> {
+        Request::post("/lsp").body(Full::new(body.into())).unwrap()
+    }
+
+    async fn body(response: http::Response<Full<Bytes>>) -> Value {
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn messages() {
+        let (service, _messages) = LspService::new(|_| Mock);
+        let mut service = HttpService::new(service);
+
+        let initialize = json!({ "jsonrpc": "2.0", "method": "initialize", "params": { "capabilities": {} }, "id": 1 });
+        let response = service.call(post(initialize.to_string())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+        assert_eq!(body(response).await["id"], json!(1));
+
+        let initialized = json!({ "jsonrpc": "2.0", "method": "initialized", "params": {} });
+        let response = service.call(post(initialized.to_string())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+
+        let response = service.call(post("not json")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(body(response).await["error"]["code"], json!(-32700));
+
+        let exit = json!({ "jsonrpc": "2.0", "method": "exit" });
+        service.call(post(exit.to_string())).await.unwrap();
+        let response = service.call(post(initialized.to_string())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+        let ready = Service::<Request<Full<Bytes>>>::poll_ready(&mut service, &mut cx);
+        assert_eq!(ready, Poll::Ready(Ok(())));
+    }
+
+    #[tokio::test]
+    async fn invalid_requests() {
+        let (service, _messages) = LspService::new(|_| Mock);
+        let mut service = HttpService::new(service).max_body_size(8);
+
+        let request = Request::get("/lsp").body(Full::<Bytes>::default()).unwrap();
+        let response = service.call(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(response.headers()[header::ALLOW], "POST");
+
+        let response = service.call(post(vec![b' '; 9])).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+}
@@ -1,10 +1,21 @@
 //! A subset of JSON-RPC types used by the Language Server Protocol.
 
 mod error;
+mod forward;
+mod handlers;
+mod partial;
 mod pending;
 
-pub use self::error::{Error, ErrorCode};
-pub(crate) use self::pending::{ClientRequests, ServerRequests};
+pub use self::{
+    error::{Error, ErrorCode},
+    forward::RequestIdMap,
+    handlers::ClientRequestHandlers,
+    partial::{PartialResultStream, PartialResults},
+    pending::{CancellationCounts, PendingRequests, UnexpectedResponse, UnexpectedResponseCounts},
+};
+pub use crate::generated_impl::ServerRequest;
+pub use serde_json::value::RawValue;
+pub(crate) use self::pending::{ClientRequests, SerializationHook, ServerRequests, UnexpectedResponseHook};
 use serde::{
     de::{self, Deserializer},
     ser::Serializer,
@@ -90,6 +101,26 @@ impl Response {
             ResponseKind::Err { ref id, .. } => id.as_ref(),
         }
     }
+
+    /// Maps the result of a successful response, leaving error responses unchanged.
+    pub(crate) fn map_result(self, f: impl FnOnce(Value) -> Value) -> Self {
+        match self.kind {
+            ResponseKind::Ok { id, result } => Response::ok(id, f(result)),
+            ResponseKind::Err { .. } => self,
+        }
+    }
+
+    /// Replaces the error of a failed response with the result returned by `f`, if any, leaving
+    /// successful responses and errors without a request ID unchanged.
+    pub(crate) fn recover(self, f: impl FnOnce(&Error) -> Option<Value>) -> Self {
+        match self.kind {
+            ResponseKind::Err { id: Some(ref id), ref error } => match f(error) {
+                Some(result) => Response::ok(id.clone(), result),
+                None => self,
+            },
+            _ => self,
+        }
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
@@ -112,6 +143,44 @@ pub enum Incoming {
     Response(Response),
 }
 
+impl From<ServerRequest> for Incoming {
+    fn from(request: ServerRequest) -> Self {
+        Incoming::Request(Box::new(request))
+    }
+}
+
+impl Incoming {
+    /// Converts a message of another JSON-RPC implementation sharing the JSON representation, such
+    /// as the `Message` type of the `lsp-server` crate.
+    pub fn from_message<M: Serialize>(message: &M) -> serde_json::Result<Self> {
+        serde_json::to_value(message).and_then(serde_json::from_value)
+    }
+
+    /// Deserializes a message from its JSON text.
+    ///
+    /// The parameters of notifications with a raw handler, such as
+    /// [`LanguageServer::did_change_raw`], are kept as raw JSON rather than being deserialized.
+    ///
+    /// [`LanguageServer::did_change_raw`]: crate::LanguageServer::did_change_raw
+    pub(crate) fn from_json(message: &str) -> serde_json::Result<Self> {
+        let message: Value = serde_json::from_str(message)?;
+
+        // Messages which violate JSON-RPC 2.0 are deserialized fully, so that they can be detected.
+        if let Some(object) = message.as_object().filter(|object| !object.contains_key("id")) {
+            let version = object.get("jsonrpc").and_then(Value::as_str);
+            let method = object.get("method").and_then(Value::as_str);
+            let params = object.get("params").filter(|params| params.is_object() || params.is_array());
+            if let (Some("2.0"), Some(method), Some(params)) = (version, method, params) {
+                if let Some(request) = crate::generated_impl::ServerRequest::from_raw(method, params) {
+                    return Ok(Incoming::Request(Box::new(request)));
+                }
+            }
+        }
+
+        serde_json::from_value(message)
+    }
+}
+
 /// A server-to-client LSP request.
 #[derive(Clone, Debug, PartialEq, Serialize)]
 #[cfg_attr(test, derive(Deserialize))]
@@ -124,31 +193,87 @@ pub struct ClientRequest {
 
 impl ClientRequest {
     /// Constructs a JSON-RPC request from its corresponding LSP type.
+    #[cfg(test)]
     pub(crate) fn request<R: lsp::request::Request>(id: u64, params: R::Params) -> Self {
-        // Since `R::Params` come from the `lsp-types` crate and validity is enforced via the
-        // `Request` trait, the `unwrap()` call below should never fail.
         ClientRequest {
             jsonrpc: Version,
             method: R::METHOD.into(),
             kind: ClientMethod::Request {
-                params: serde_json::to_value(params).unwrap(),
+                params: lsp_value(params),
+                id: Id::Number(id),
+            },
+        }
+    }
+
+    /// Constructs a JSON-RPC request from a method name and already serialized parameters.
+    pub(crate) fn request_raw(method: Cow<'static, str>, id: u64, params: Value) -> Self {
+        ClientRequest {
+            jsonrpc: Version,
+            method,
+            kind: ClientMethod::Request {
+                params,
                 id: Id::Number(id),
             },
         }
     }
 
+    /// Constructs a JSON-RPC notification from a method name and already serialized parameters.
+    #[cfg(feature = "tower-lsp-compat")]
+    pub(crate) fn notification_raw(method: Cow<'static, str>, params: Value) -> Self {
+        ClientRequest {
+            jsonrpc: Version,
+            method,
+            kind: ClientMethod::Notification { params },
+        }
+    }
+
     /// Constructs a JSON-RPC notification from its corresponding LSP type.
     pub(crate) fn notification<N: lsp::notification::Notification>(params: N::Params) -> Self {
-        // Since `N::Params` comes from the `lsp-types` crate and validity is enforced via the
-        // `Notification` trait, the `unwrap()` call below should never fail.
         ClientRequest {
             jsonrpc: Version,
             method: N::METHOD.into(),
             kind: ClientMethod::Notification {
-                params: serde_json::to_value(params).unwrap(),
+                params: lsp_value(params),
             },
         }
     }
+
+    /// Returns the name of the method to be invoked.
+    pub fn method(&self) -> &str {
+        &self.method
+    }
+
+    /// Replaces the ID of the request, e.g. to forward it to another peer. Notifications are left
+    /// unchanged.
+    pub(crate) fn set_id(&mut self, new_id: Id) {
+        if let ClientMethod::Request { ref mut id, .. } = self.kind {
+            *id = new_id;
+        }
+    }
+
+    /// Replaces the parameters of the request or notification.
+    pub(crate) fn set_params(&mut self, new_params: Value) {
+        match self.kind {
+            ClientMethod::Request { ref mut params, .. } => *params = new_params,
+            ClientMethod::Notification { ref mut params } => *params = new_params,
+        }
+    }
+
+    /// Returns the request ID, or `None` if this message is a notification.
+    pub fn id(&self) -> Option<&Id> {
+        match self.kind {
+            ClientMethod::Request { ref id, .. } => Some(id),
+            ClientMethod::Notification { .. } => None,
+        }
+    }
+
+    /// Returns the parameters of the request or notification.
+    pub fn params(&self) -> &Value {
+        match self.kind {
+            ClientMethod::Request { ref params, .. } => params,
+            ClientMethod::Notification { ref params } => params,
+        }
+    }
 }
 
 impl Display for ClientRequest {
@@ -177,6 +302,14 @@ pub enum Outgoing {
     Request(ClientRequest),
 }
 
+impl Outgoing {
+    /// Converts this message into a message of another JSON-RPC implementation sharing the JSON
+    /// representation, such as the `Message` type of the `lsp-server` crate.
+    pub fn into_message<M: de::DeserializeOwned>(self) -> serde_json::Result<M> {
+        serde_json::to_value(self).and_then(serde_json::from_value)
+    }
+}
+
 impl Display for Outgoing {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         let mut w = WriterFormatter { inner: f };
@@ -193,7 +326,7 @@ impl<'a, 'b> std::io::Write for WriterFormatter<'a, 'b> {
         fn io_error<E>(_: E) -> std::io::Error {
             // Error value does not matter because fmt::Display impl below just
             // maps it to fmt::Error
-            std::io::Error::new(std::io::ErrorKind::Other, "fmt error")
+            std::io::Error::other("fmt error")
         }
         let s = std::str::from_utf8(buf).map_err(io_error)?;
         self.inner.write_str(s).map_err(io_error)?;
@@ -229,16 +362,33 @@ impl Serialize for Version {
     }
 }
 
+/// Serializes a value of a type from the `lsp-types` crate, e.g. the parameters or the result of a
+/// request.
+pub(crate) fn lsp_value<T: Serialize>(value: T) -> Value {
+    // Since `T` comes from the `lsp-types` crate, whose types all serialize to JSON, the `unwrap()`
+    // call below should never fail.
+    serde_json::to_value(value).unwrap()
+}
+
 /// Error response returned for every request received before the server is initialized.
 ///
 /// See [here](https://microsoft.github.io/language-server-protocol/specification#initialize)
 /// for reference.
 pub(crate) fn not_initialized_error() -> Error {
-    Error {
-        code: ErrorCode::ServerError(-32002),
-        message: "Server not initialized".to_string(),
-        data: None,
-    }
+    Error::server_error(-32002, "Server not initialized")
+}
+
+/// Error failing requests to the client which are pending once the server shut down, or which are
+/// sent afterwards.
+pub(crate) fn shut_down_error() -> Error {
+    let data = serde_json::json!({ "reason": "shutdown" });
+    Error::request_cancelled().with_message("Server shut down").with_data(data)
+}
+
+/// Error failing requests to the client which are pending once the server exited.
+pub(crate) fn exited_error() -> Error {
+    let data = serde_json::json!({ "reason": "exit" });
+    Error::request_cancelled().with_message("Server exited").with_data(data)
 }
 
 #[cfg(test)]
@@ -252,7 +402,7 @@ mod tests {
         fn display() {
             let id = 0;
             let request = ClientRequest::request::<lsp::request::Shutdown>(id, ());
-            format!("{}", request);
+            let _ = format!("{}", request);
         }
     }
 
@@ -346,6 +496,89 @@ mod tests {
         }
     }
 
+    mod server_request {
+        use super::*;
+        use serde_json::json;
+
+        #[test]
+        fn new_known() {
+            let params = json!({ "textDocument": { "uri": "file:///main.rs" }, "position": { "line": 0, "character": 0 } });
+            let request = ServerRequest::new("textDocument/hover", Some(params.clone()), Some(Id::Number(1))).unwrap();
+            let raw = json!({ "jsonrpc": "2.0", "method": "textDocument/hover", "params": params, "id": 1 });
+            assert_eq!(Incoming::from(request.clone()), serde_json::from_value(raw).unwrap());
+            assert_eq!(request.method(), "textDocument/hover");
+            assert_eq!(request.id(), Some(&Id::Number(1)));
+
+            let request = ServerRequest::new("exit", None, None).unwrap();
+            assert_eq!(request.id(), None);
+        }
+
+        #[test]
+        fn new_unknown() {
+            let request = ServerRequest::new("custom/request", Some(json!([1, 2])), Some(Id::Number(1))).unwrap();
+            assert_eq!(request.method(), "custom/request");
+            assert_eq!(request.other_params(), Some(&json!([1, 2])));
+            assert!(ServerRequest::new("custom/notification", None, None).is_ok());
+        }
+
+        #[test]
+        fn new_invalid() {
+            let code = |result: Result<ServerRequest>| result.unwrap_err().code;
+            assert_eq!(code(ServerRequest::new("", None, None)), ErrorCode::InvalidRequest);
+            assert_eq!(code(ServerRequest::new("shutdown", None, None)), ErrorCode::InvalidRequest);
+            let params = Some(json!({}));
+            assert_eq!(code(ServerRequest::new("initialized", params, Some(Id::Number(1)))), ErrorCode::InvalidRequest);
+            let params = Some(json!({ "textDocument": {} }));
+            assert_eq!(code(ServerRequest::new("textDocument/hover", params, Some(Id::Number(1)))), ErrorCode::InvalidParams);
+            let params = Some(json!({ "id": [] }));
+            assert_eq!(code(ServerRequest::new("$/cancelRequest", params, None)), ErrorCode::InvalidParams);
+        }
+
+        #[test]
+        fn jsonrpc_violation() {
+            let violation = |raw: &str| match Incoming::from_json(raw).unwrap() {
+                Incoming::Request(request) => (request.jsonrpc_violation(), request.raw_id()),
+                Incoming::Response(_) => panic!("expected a request"),
+            };
+            let raw = r#"{ "jsonrpc": "2.0", "method": "textDocument/didSave", "params": { "textDocument": { "uri": "file:///main.rs" } } }"#;
+            assert_eq!(violation(raw), (None, None));
+            let raw = r#"{ "jsonrpc": "2.0", "method": "shutdown", "id": 1 }"#;
+            assert_eq!(violation(raw), (None, Some(Id::Number(1))));
+            let raw = r#"{ "jsonrpc": "2.0", "method": "initialized", "params": {}, "id": 2 }"#;
+            let expected = (Some("notifications must not have an id"), Some(Id::Number(2)));
+            assert_eq!(violation(raw), expected);
+            let raw = r#"{ "jsonrpc": "2.0", "method": "textDocument/didChange", "params": {}, "id": 3 }"#;
+            assert_eq!(violation(raw), (Some("notifications must not have an id"), Some(Id::Number(3))));
+            let raw = r#"{ "jsonrpc": "2.0", "method": "custom/request", "params": "foo", "id": 4 }"#;
+            assert_eq!(violation(raw), (Some("params must be an array or an object"), Some(Id::Number(4))));
+            let raw = r#"{ "jsonrpc": "2.0", "method": "textDocument/didOpen", "params": 5 }"#;
+            assert_eq!(violation(raw), (Some("params must be an array or an object"), None));
+        }
+
+        #[test]
+        fn envelope_follows_kind() {
+            // The flattened envelope only sees the `id` and `params` fields the request kind leaves
+            // behind if it is declared after the kind.
+            let raw = r#"{ "jsonrpc": "2.0", "method": "shutdown", "params": "foo", "id": 1 }"#;
+            let request = match Incoming::from_json(raw).unwrap() {
+                Incoming::Request(request) => request,
+                Incoming::Response(_) => panic!("expected a request"),
+            };
+            assert_eq!(request.id(), Some(&Id::Number(1)));
+            assert_eq!(request.raw_id(), Some(Id::Number(1)));
+            assert_eq!(request.jsonrpc_violation(), Some("params must be an array or an object"));
+
+            let raw = r#"{ "jsonrpc": "2.0", "method": "workspace/symbol", "params": { "query": "" }, "id": 2 }"#;
+            let request = match Incoming::from_json(raw).unwrap() {
+                Incoming::Request(request) => request,
+                Incoming::Response(_) => panic!("expected a request"),
+            };
+            assert_eq!(request.id(), Some(&Id::Number(2)));
+            assert_eq!(request.params(), Some(json!({ "query": "" })));
+            assert_eq!(request.jsonrpc_violation(), None);
+        }
+    }
+
     mod version {
         use super::*;
 
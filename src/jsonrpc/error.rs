@@ -1,6 +1,11 @@
 //! Error types defined by the JSON-RPC specification.
 
-use serde::{de::Deserializer, ser::Serializer, Deserialize, Serialize};
+use serde::{
+    de::{DeserializeOwned, Deserializer},
+    ser::Serializer,
+    Deserialize,
+    Serialize,
+};
 use serde_json::Value;
 use std::fmt::{self, Display, Formatter};
 
@@ -34,6 +39,16 @@ pub enum ErrorCode {
 }
 
 impl ErrorCode {
+    /// The server cancelled the request, e.g. because it is overloaded.
+    ///
+    /// There is no variant for this code, so it is a [`ServerError`](ErrorCode::ServerError) and
+    /// `ErrorCode::from(-32802)` returns it.
+    ///
+    /// # Compatibility
+    ///
+    /// This error code is specific to the Language Server Protocol since version 3.17.
+    pub const SERVER_CANCELLED: ErrorCode = ErrorCode::ServerError(-32802);
+
     /// Returns the integer error code value.
     pub fn code(&self) -> i64 {
         match *self {
@@ -175,6 +190,67 @@ impl Error {
     pub fn content_modified() -> Self {
         Error::new(ErrorCode::ContentModified)
     }
+
+    /// Creates a new "server cancelled" error (`-32802`).
+    ///
+    /// # Compatibility
+    ///
+    /// This error code is defined by the Language Server Protocol since version 3.17.
+    pub fn server_cancelled() -> Self {
+        Error::new(ErrorCode::SERVER_CANCELLED).with_message("Server cancelled")
+    }
+
+    /// Creates a new error with the given code and message.
+    ///
+    /// Codes from `-32099` to `-32000` are reserved for implementation-defined server errors. Codes
+    /// predefined by JSON-RPC or the Language Server Protocol map to their respective `ErrorCode`.
+    pub fn server_error<M>(code: i64, message: M) -> Self
+    where
+        M: Into<String>,
+    {
+        Error {
+            code: ErrorCode::from(code),
+            message: message.into(),
+            data: None,
+        }
+    }
+
+    /// Replaces the message of this error.
+    pub fn with_message<M>(mut self, message: M) -> Self
+    where
+        M: Into<String>,
+    {
+        self.message = message.into();
+        self
+    }
+
+    /// Attaches additional information about the error, e.g. suggested fixes or an error ID.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # use lspower::jsonrpc::Error;
+    /// # use serde_json::json;
+    /// let error = Error::server_error(-32099, "Build failed").with_data(json!({ "errorId": "E0425" }));
+    /// assert_eq!(error.data_as::<serde_json::Value>(), Some(json!({ "errorId": "E0425" })));
+    /// ```
+    pub fn with_data<D>(mut self, data: D) -> Self
+    where
+        D: Into<Value>,
+    {
+        self.data = Some(data.into());
+        self
+    }
+
+    /// Returns the additional information about the error deserialized as `T`.
+    ///
+    /// Returns `None` if the error carries no data or if the data does not match `T`.
+    pub fn data_as<T>(&self) -> Option<T>
+    where
+        T: DeserializeOwned,
+    {
+        self.data.as_ref().and_then(|data| T::deserialize(data).ok())
+    }
 }
 
 impl Display for Error {
@@ -265,6 +341,15 @@ mod tests {
         assert_eq!(code.description(), error.message);
     }
 
+    #[test]
+    fn server_cancelled() {
+        let code = ErrorCode::SERVER_CANCELLED;
+        assert_eq!(code, ErrorCode::from(-32802));
+        let error = Error::server_cancelled();
+        assert_eq!(code, error.code);
+        assert_eq!("Server cancelled", error.message);
+    }
+
     #[test]
     fn server_error() {
         let code = ErrorCode::ServerError(42);
@@ -274,6 +359,25 @@ mod tests {
         assert_eq!(code.description(), error.message);
     }
 
+    #[test]
+    fn server_error_with_data() {
+        use serde_json::json;
+
+        let error = Error::server_error(-32099, "Build failed").with_data(json!({ "errorId": 7 }));
+        assert_eq!(ErrorCode::ServerError(-32099), error.code);
+        assert_eq!("Build failed", error.message);
+        assert_eq!(Some(json!({ "errorId": 7 })), error.data_as::<Value>());
+        assert_eq!(None, error.data_as::<String>());
+
+        let error = Error::server_error(-32602, "Missing field");
+        assert_eq!(ErrorCode::InvalidParams, error.code);
+        assert_eq!(None, error.data_as::<Value>());
+
+        let error = Error::internal_error().with_message("Index unavailable");
+        assert_eq!(ErrorCode::InternalError, error.code);
+        assert_eq!("Index unavailable", error.message);
+    }
+
     #[test]
     fn server_not_initialized() {
         let code = ErrorCode::ServerError(-32002);
@@ -0,0 +1,232 @@
+//! Correlation of the IDs of requests forwarded from one peer to another.
+
+use super::{Id, Response};
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    fmt::{self, Debug, Formatter},
+    sync::Mutex,
+};
+
+/// The method of the notification canceling a request.
+const CANCEL_REQUEST: &str = "$/cancelRequest";
+
+/// Maps the IDs of requests forwarded from an origin peer to a target peer, such as the requests a
+/// proxy forwards from its client to a downstream server.
+///
+/// Forwarded requests get new IDs in a namespace of the map, since IDs of different origins may
+/// collide on the target. The IDs are allocated deterministically, counting up from `0`, so that
+/// forwarded traffic can be recorded and replayed. Responses from the target are mapped back to the
+/// ID of the origin with [`complete`], and `$/cancelRequest` notifications of the origin are
+/// rewritten to cancel the forwarded request with [`forward_notification`].
+///
+/// A proxy forwarding requests in both directions uses one map per direction: one for requests
+/// from its client to the downstream server and one for requests from the downstream server to the
+/// client, so that each side can cancel the requests it sent.
+///
+/// # Example
+///
+/// ```rust
+/// # use lspower::jsonrpc::{Id, RequestIdMap, Response};
+/// # use serde_json::json;
+/// let upstream = RequestIdMap::with_namespace("proxy");
+///
+/// // The client's request 7 is forwarded downstream as request "proxy/0".
+/// let downstream = upstream.forward(Id::Number(7));
+/// assert_eq!(downstream, Id::String("proxy/0".into()));
+///
+/// // Canceling request 7 cancels the forwarded request.
+/// let params = upstream.forward_notification("$/cancelRequest", json!({ "id": 7 }));
+/// assert_eq!(params, Some(json!({ "id": "proxy/0" })));
+///
+/// // The downstream response answers request 7.
+/// let response = upstream.complete(Response::ok(downstream, json!(null)));
+/// assert_eq!(response, Some(Response::ok(Id::Number(7), json!(null))));
+/// ```
+///
+/// [`complete`]: RequestIdMap::complete
+/// [`forward_notification`]: RequestIdMap::forward_notification
+pub struct RequestIdMap {
+    namespace: Option<String>,
+    inner: Mutex<IdMapInner>,
+}
+
+#[derive(Default)]
+struct IdMapInner {
+    next_id: u64,
+    targets: HashMap<Id, Id>,
+    origins: HashMap<Id, Id>,
+}
+
+impl RequestIdMap {
+    /// Creates a map which forwards requests with numeric IDs.
+    pub fn new() -> Self {
+        RequestIdMap {
+            namespace: None,
+            inner: Default::default(),
+        }
+    }
+
+    /// Creates a map which forwards requests with string IDs of the form `<namespace>/<number>`.
+    ///
+    /// This keeps the forwarded requests apart from the requests the forwarding peer sends to the
+    /// target itself, which usually have numeric IDs.
+    pub fn with_namespace<N: Into<String>>(namespace: N) -> Self {
+        RequestIdMap {
+            namespace: Some(namespace.into()),
+            inner: Default::default(),
+        }
+    }
+
+    /// Allocates the ID of the forwarded request for a request of the origin.
+    ///
+    /// Forwarding a request whose ID is still pending returns the ID it was forwarded with before.
+    pub fn forward(&self, origin: Id) -> Id {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(target) = inner.targets.get(&origin) {
+            log::warn!("request {} was already forwarded as {}", origin, target);
+            return target.clone();
+        }
+
+        let number = inner.next_id;
+        inner.next_id += 1;
+        let target = match &self.namespace {
+            Some(namespace) => Id::String(format!("{}/{}", namespace, number)),
+            None => Id::Number(number),
+        };
+        inner.targets.insert(origin.clone(), target.clone());
+        inner.origins.insert(target.clone(), origin);
+        target
+    }
+
+    /// Returns the ID the request of the origin was forwarded with, if it is still pending.
+    pub fn target(&self, origin: &Id) -> Option<Id> {
+        self.inner.lock().unwrap().targets.get(origin).cloned()
+    }
+
+    /// Returns the ID of the request of the origin which was forwarded with the given ID, if it is
+    /// still pending.
+    pub fn origin(&self, target: &Id) -> Option<Id> {
+        self.inner.lock().unwrap().origins.get(target).cloned()
+    }
+
+    /// Maps a response of the target to a response to the request of the origin, which is no
+    /// longer pending afterwards.
+    ///
+    /// Returns `None` if the response does not answer a pending forwarded request.
+    pub fn complete(&self, response: Response) -> Option<Response> {
+        let (target, body) = response.into_parts();
+        let target = target?;
+        let mut inner = self.inner.lock().unwrap();
+        let origin = match inner.origins.remove(&target) {
+            Some(origin) => origin,
+            None => {
+                log::debug!("received response to {}, which is not a pending forwarded request", target);
+                return None;
+            },
+        };
+        inner.targets.remove(&origin);
+        Some(Response::from_parts(origin, body))
+    }
+
+    /// Maps the parameters of a notification of the origin to the parameters to forward to the
+    /// target.
+    ///
+    /// The ID of `$/cancelRequest` notifications is replaced with the ID of the forwarded request,
+    /// and cancellations of requests which are not pending are dropped by returning `None`. Other
+    /// notifications are forwarded unchanged.
+    pub fn forward_notification(&self, method: &str, mut params: Value) -> Option<Value> {
+        if method != CANCEL_REQUEST {
+            return Some(params);
+        }
+
+        let origin = params.get("id").cloned().and_then(|id| serde_json::from_value(id).ok());
+        let target = origin.as_ref().and_then(|origin| self.target(origin));
+        match target {
+            Some(target) => {
+                params["id"] = serde_json::to_value(target).unwrap();
+                Some(params)
+            },
+            None => {
+                log::debug!("dropping cancellation of {:?}, which is not a pending forwarded request", params["id"]);
+                None
+            },
+        }
+    }
+
+    /// Forgets all pending forwarded requests, e.g. because the target exited, and returns the IDs
+    /// of the requests of the origin which are left without a response.
+    pub fn drain(&self) -> Vec<Id> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.origins.clear();
+        inner.targets.drain().map(|(origin, _)| origin).collect()
+    }
+
+    /// Returns the number of forwarded requests still waiting for a response.
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().targets.len()
+    }
+
+    /// Returns `true` if no forwarded requests are waiting for a response.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for RequestIdMap {
+    fn default() -> Self {
+        RequestIdMap::new()
+    }
+}
+
+impl Debug for RequestIdMap {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct(stringify!(RequestIdMap))
+            .field("namespace", &self.namespace)
+            .field("pending", &self.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jsonrpc::Error;
+    use serde_json::json;
+
+    #[test]
+    fn forward() {
+        let map = RequestIdMap::new();
+        assert_eq!(map.forward(Id::String("a".into())), Id::Number(0));
+        assert_eq!(map.forward(Id::Number(0)), Id::Number(1));
+        assert_eq!(map.forward(Id::String("a".into())), Id::Number(0));
+        assert_eq!(map.target(&Id::Number(0)), Some(Id::Number(1)));
+        assert_eq!(map.origin(&Id::Number(0)), Some(Id::String("a".into())));
+        assert_eq!(map.len(), 2);
+
+        let response = Response::error(Some(Id::Number(1)), Error::request_cancelled());
+        let expected = Response::error(Some(Id::Number(0)), Error::request_cancelled());
+        assert_eq!(map.complete(response.clone()), Some(expected));
+        assert_eq!(map.complete(response), None);
+        assert_eq!(map.complete(Response::error(None, Error::parse_error())), None);
+        assert_eq!(map.target(&Id::Number(0)), None);
+
+        assert_eq!(map.drain(), [Id::String("a".into())]);
+        assert!(map.is_empty());
+        assert_eq!(map.forward(Id::Number(5)), Id::Number(2));
+    }
+
+    #[test]
+    fn forward_notification() {
+        let map = RequestIdMap::with_namespace("proxy");
+        map.forward(Id::Number(3));
+
+        let params = json!({ "id": 3 });
+        assert_eq!(map.forward_notification(CANCEL_REQUEST, params), Some(json!({ "id": "proxy/0" })));
+        assert_eq!(map.forward_notification(CANCEL_REQUEST, json!({ "id": 4 })), None);
+        assert_eq!(map.forward_notification(CANCEL_REQUEST, json!({})), None);
+
+        let params = json!({ "textDocument": { "uri": "file:///a.rs" } });
+        assert_eq!(map.forward_notification("textDocument/didSave", params.clone()), Some(params));
+    }
+}
@@ -0,0 +1,138 @@
+//! Answering of server-to-client requests with typed handlers.
+
+use super::{ClientRequest, Error, Response, Result};
+use futures::future::{BoxFuture, FutureExt};
+use serde_json::Value;
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Debug, Formatter},
+    future::Future,
+    sync::Arc,
+};
+
+type RequestHandler = Arc<dyn Fn(Value) -> BoxFuture<'static, Result<Value>> + Send + Sync>;
+
+/// Answers the requests which a language server sends to its client with async handlers.
+///
+/// This is useful when lspower acts as the client of another language server, e.g. through a
+/// [`ServerProxy`]: the server asks its client for settings with `workspace/configuration`, or to
+/// apply edits with `workspace/applyEdit`, and waits for the responses. Each request method is
+/// registered with a handler taking the typed parameters of the request, and requests without a
+/// handler are left to the caller.
+///
+/// # Example
+///
+/// ```rust
+/// # use lspower::{jsonrpc::ClientRequestHandlers, lsp::{request::*, *}};
+/// # use serde_json::json;
+/// let handlers = ClientRequestHandlers::new()
+///     .handler::<WorkspaceConfiguration, _, _>(|params: ConfigurationParams| async move {
+///         Ok(params.items.iter().map(|_| json!({ "checkOnSave": true })).collect())
+///     })
+///     .handler::<ApplyWorkspaceEdit, _, _>(|_| async {
+///         Ok(ApplyWorkspaceEditResponse {
+///             applied: false,
+///             failure_reason: Some("read-only workspace".into()),
+///             failed_change: None,
+///         })
+///     });
+/// ```
+///
+/// [`ServerProxy`]: crate::ServerProxy
+#[derive(Clone, Default)]
+pub struct ClientRequestHandlers {
+    handlers: BTreeMap<String, RequestHandler>,
+}
+
+impl ClientRequestHandlers {
+    /// Creates a new `ClientRequestHandlers` without registered handlers.
+    pub fn new() -> Self {
+        ClientRequestHandlers::default()
+    }
+
+    /// Registers the handler of requests of type `R`.
+    ///
+    /// If a handler was registered for the same method before, it is replaced.
+    pub fn handler<R, F, Fut>(mut self, handler: F) -> Self
+    where
+        R: lsp::request::Request,
+        F: Fn(R::Params) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<R::Result>> + Send + 'static,
+    {
+        let handler: RequestHandler = Arc::new(move |params| {
+            let future = serde_json::from_value(params)
+                .map_err(|error| Error::invalid_params(error.to_string()))
+                .map(&handler);
+            async move {
+                let result = future?.await?;
+                serde_json::to_value(result).map_err(|error| {
+                    log::error!("failed to serialize result of {:?}: {}", R::METHOD, error);
+                    Error::internal_error()
+                })
+            }
+            .boxed()
+        });
+        self.handlers.insert(R::METHOD.into(), handler);
+        self
+    }
+
+    /// Returns the methods of all registered handlers, in lexicographic order.
+    pub fn methods(&self) -> Vec<String> {
+        self.handlers.keys().cloned().collect()
+    }
+
+    /// Answers the given request with its registered handler.
+    ///
+    /// Returns `None` if the message is a notification, or if no handler is registered for its
+    /// method. Parameters which cannot be deserialized are answered with an "invalid params" error
+    /// (`-32602`).
+    pub async fn answer(&self, request: &ClientRequest) -> Option<Response> {
+        let id = request.id()?.clone();
+        let handler = self.handlers.get(request.method())?;
+        Some(match handler(request.params().clone()).await {
+            Ok(result) => Response::ok(id, result),
+            Err(error) => Response::error(Some(id), error),
+        })
+    }
+}
+
+impl Debug for ClientRequestHandlers {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct(stringify!(ClientRequestHandlers))
+            .field("methods", &self.handlers.keys())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jsonrpc::{ErrorCode, Id};
+    use lsp::request::{Request, WorkspaceConfiguration};
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn answer() {
+        let handlers = ClientRequestHandlers::new().handler::<WorkspaceConfiguration, _, _>(|params| async move {
+            Ok(params.items.into_iter().map(|item| json!(item.section)).collect())
+        });
+        assert_eq!(handlers.methods(), [WorkspaceConfiguration::METHOD]);
+
+        let params = json!({ "items": [{ "section": "a" }, {}] });
+        let request = ClientRequest::request_raw(WorkspaceConfiguration::METHOD.into(), 1, params);
+        let response = handlers.answer(&request).await;
+        assert_eq!(response, Some(Response::ok(Id::Number(1), json!(["a", null]))));
+
+        let request = ClientRequest::request_raw(WorkspaceConfiguration::METHOD.into(), 2, json!({}));
+        let (_, result) = handlers.answer(&request).await.unwrap().into_parts();
+        assert_eq!(result.unwrap_err().code, ErrorCode::InvalidParams);
+
+        let request = ClientRequest::request_raw("workspace/applyEdit".into(), 3, json!({}));
+        assert_eq!(handlers.answer(&request).await, None);
+        let notification = ClientRequest::notification::<lsp::notification::LogMessage>(lsp::LogMessageParams {
+            typ: lsp::MessageType::INFO,
+            message: "".into(),
+        });
+        assert_eq!(handlers.answer(&notification).await, None);
+    }
+}
@@ -0,0 +1,169 @@
+//! Routing of partial results reported through `$/progress` notifications.
+
+use super::Outgoing;
+use futures::{
+    channel::mpsc,
+    stream::{FusedStream, Stream},
+};
+use lsp::ProgressToken;
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    fmt::{self, Debug, Formatter},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+        Mutex,
+    },
+    task::{Context, Poll},
+};
+
+/// Routes the partial results of requests, reported by `$/progress` notifications, to the
+/// [`PartialResultStream`] registered for their `partialResultToken`.
+///
+/// This is useful when lspower sends requests to another language server, e.g. through a
+/// [`ServerProxy`]: the messages produced by that server are passed through [`route`], which
+/// consumes the partial results of registered tokens and returns all other messages.
+///
+/// [`ServerProxy`]: crate::ServerProxy
+/// [`route`]: PartialResults::route
+#[derive(Clone, Default)]
+pub struct PartialResults(Arc<Mutex<HashMap<ProgressToken, (u64, mpsc::UnboundedSender<Value>)>>>);
+
+/// The source of the IDs telling apart the streams registered for the same token over time.
+static NEXT_STREAM: AtomicU64 = AtomicU64::new(0);
+
+impl PartialResults {
+    /// Creates a new `PartialResults` without registered tokens.
+    pub fn new() -> Self {
+        PartialResults::default()
+    }
+
+    /// Returns the stream of the partial results reported with the given token.
+    ///
+    /// The stream ends once the token is [finished](PartialResults::finish) or
+    /// [unregistered](PartialResults::unregister), and the token is unregistered once the stream is
+    /// dropped. Registering a token again ends the stream previously registered for it.
+    pub fn register(&self, token: ProgressToken) -> PartialResultStream {
+        let (tx, rx) = mpsc::unbounded();
+        let id = NEXT_STREAM.fetch_add(1, Ordering::Relaxed);
+        self.0.lock().unwrap().insert(token.clone(), (id, tx));
+        PartialResultStream {
+            token,
+            id,
+            rx,
+            results: self.clone(),
+        }
+    }
+
+    /// Stops routing the partial results of the given token, ending its stream once the results
+    /// routed so far were received.
+    ///
+    /// This should be called once the response to the request was received, since the protocol
+    /// forbids partial results after the response.
+    pub fn unregister(&self, token: &ProgressToken) {
+        self.0.lock().unwrap().remove(token);
+    }
+
+    /// Ends the stream of the given token once the results routed so far were received, like
+    /// [`unregister`](PartialResults::unregister), but keeps the token registered until the stream
+    /// is dropped.
+    ///
+    /// Partial results precede the response to their request, but the response may be received
+    /// before the messages carrying them were routed. Results of a finished token which are routed
+    /// while its stream is still alive are consumed rather than returned as messages for the
+    /// client, which does not know the token.
+    pub fn finish(&self, token: &ProgressToken) {
+        if let Some((_, tx)) = self.0.lock().unwrap().get(token) {
+            tx.close_channel();
+        }
+    }
+
+    /// Returns whether partial results are routed for the given token.
+    pub fn is_registered(&self, token: &ProgressToken) -> bool {
+        self.0.lock().unwrap().contains_key(token)
+    }
+
+    /// Routes the value of a `$/progress` notification with a registered token to its stream,
+    /// returning any other message.
+    pub fn route(&self, message: Outgoing) -> Option<Outgoing> {
+        let (token, value) = match &message {
+            Outgoing::Request(request) if request.method() == "$/progress" && request.id().is_none() => {
+                // Partial results have arbitrary values, so only the token is validated.
+                let params = request.params();
+                match serde_json::from_value(params["token"].clone()) {
+                    Ok(token) => (token, params["value"].clone()),
+                    Err(_) => return Some(message),
+                }
+            },
+            _ => return Some(message),
+        };
+
+        let streams = self.0.lock().unwrap();
+        match streams.get(&token) {
+            Some((_, tx)) => {
+                if tx.unbounded_send(value).is_err() {
+                    log::debug!("dropping partial result of finished token {:?}", token);
+                }
+                None
+            },
+            None => Some(message),
+        }
+    }
+}
+
+impl Debug for PartialResults {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let streams = self.0.lock().unwrap();
+        f.debug_set().entries(streams.keys()).finish()
+    }
+}
+
+/// Stream of the partial results reported with a token registered with [`PartialResults`].
+#[must_use = "streams do nothing unless polled"]
+pub struct PartialResultStream {
+    token: ProgressToken,
+    id: u64,
+    rx: mpsc::UnboundedReceiver<Value>,
+    results: PartialResults,
+}
+
+impl PartialResultStream {
+    /// Returns the token whose partial results are received.
+    pub fn token(&self) -> &ProgressToken {
+        &self.token
+    }
+}
+
+impl Stream for PartialResultStream {
+    type Item = Value;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.rx).poll_next(cx)
+    }
+}
+
+impl FusedStream for PartialResultStream {
+    fn is_terminated(&self) -> bool {
+        self.rx.is_terminated()
+    }
+}
+
+impl Drop for PartialResultStream {
+    fn drop(&mut self) {
+        let mut streams = self.results.0.lock().unwrap();
+        // The token may have been registered again for another stream in the meantime.
+        if streams.get(&self.token).is_some_and(|(id, _)| *id == self.id) {
+            streams.remove(&self.token);
+        }
+    }
+}
+
+impl Debug for PartialResultStream {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct(stringify!(PartialResultStream))
+            .field("token", &self.token)
+            .finish_non_exhaustive()
+    }
+}
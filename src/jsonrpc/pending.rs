@@ -1,44 +1,136 @@
 //! Hashmaps for tracking pending JSON-RPC requests.
 
 use super::{Error, Id, Response, Result};
+use crate::{RequestContext, TokenCanceller};
 use dashmap::{mapref::entry::Entry, DashMap};
 use futures::{channel::oneshot, future};
 use serde::Serialize;
 use std::{
-    fmt::{self, Debug, Formatter},
+    borrow::Cow,
+    collections::{BTreeMap, HashSet, VecDeque},
+    fmt::{self, Debug, Display, Formatter},
     future::Future,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+        Mutex,
+    },
 };
 
+/// A hook observing results of request handlers which failed to serialize, along with the method
+/// of the request.
+pub(crate) type SerializationHook = Arc<dyn Fn(&str, &serde_json::Error) + Send + Sync>;
+
+/// The number of completed request IDs remembered to recognize late cancellations and responses.
+const COMPLETED_CAPACITY: usize = 256;
+
 /// A hashmap containing pending server requests, keyed by request ID.
-pub struct ServerRequests(Arc<DashMap<Id, future::AbortHandle>>);
+#[derive(Clone)]
+pub struct ServerRequests(Arc<DashMap<Id, Handler>>, Option<SerializationHook>, Arc<Cancellations>);
+
+/// Counters of `$/cancelRequest` notifications, along with the IDs of recently completed requests.
+#[derive(Default)]
+struct Cancellations {
+    cancelled: AtomicU64,
+    late: AtomicU64,
+    unknown: AtomicU64,
+    completed: Mutex<CompletedIds>,
+}
+
+/// The IDs of the most recently completed requests, oldest first.
+#[derive(Default)]
+struct CompletedIds {
+    order: VecDeque<Id>,
+    ids: HashSet<Id>,
+}
+
+impl CompletedIds {
+    fn insert(&mut self, id: Id) {
+        if self.ids.insert(id.clone()) {
+            self.order.push_back(id);
+        }
+        // Compact the record so that it doesn't grow with the number of requests handled.
+        while self.order.len() > COMPLETED_CAPACITY {
+            if let Some(id) = self.order.pop_front() {
+                self.ids.remove(&id);
+            }
+        }
+    }
+}
+
+/// A running request handler.
+struct Handler {
+    method: Cow<'static, str>,
+    abort: future::AbortHandle,
+    canceller: TokenCanceller,
+}
+
+impl Handler {
+    /// Cancels the token of the request context before dropping the handler future.
+    fn cancel(&mut self) {
+        self.canceller.cancel();
+        self.abort.abort();
+    }
+}
 
 impl ServerRequests {
     /// Creates a new pending server requests map.
     pub fn new() -> Self {
-        ServerRequests(Arc::new(DashMap::new()))
+        ServerRequests(Arc::new(DashMap::new()), None, Default::default())
     }
 
-    /// Executes the given async request handler, keyed by the given request ID.
+    /// Sets the hook invoked whenever the result of a request handler fails to serialize.
+    pub(crate) fn serialization_hook(mut self, hook: Option<SerializationHook>) -> Self {
+        self.1 = hook;
+        self
+    }
+
+    /// Executes the given async request handler for the given method, keyed by the given request ID.
     ///
     /// If a cancel request is issued before the future is finished resolving, this will resolve to
-    /// a "canceled" error response, and the pending request handler future will be dropped.
-    pub fn execute<F, T>(&self, id: Id, fut: F) -> impl Future<Output = Response> + Send + 'static
+    /// a "canceled" error response, and the pending request handler future will be dropped. If the
+    /// result of the handler fails to serialize, this will resolve to an "internal error" response.
+    ///
+    /// While the handler is polled, its [`RequestContext`] is available.
+    pub fn execute<F, T>(
+        &self,
+        id: Id,
+        method: impl Into<Cow<'static, str>>,
+        fut: F,
+    ) -> impl Future<Output = Response> + Send + 'static
     where
         F: Future<Output = Result<T>> + Send + 'static,
         T: Serialize,
     {
         if let Entry::Vacant(entry) = self.0.entry(id.clone()) {
-            let (handler_fut, abort_handle) = future::abortable(fut);
-            entry.insert(abort_handle);
+            let method = method.into();
+            let canceller = TokenCanceller::new();
+            let context = RequestContext::new(id.clone(), method.clone(), canceller.token());
+            let (handler_fut, abort) = future::abortable(context.scope(fut));
+            entry.insert(Handler {
+                method: method.clone(),
+                abort,
+                canceller,
+            });
 
             let requests = self.0.clone();
+            let hook = self.1.clone();
+            let cancellations = self.2.clone();
             future::Either::Left(async move {
                 let abort_result = handler_fut.await;
                 requests.remove(&id); // Remove abort handle now to avoid double cancellation.
+                cancellations.completed.lock().unwrap().insert(id.clone());
 
                 if let Ok(handler_result) = abort_result {
-                    let result = handler_result.map(|v| serde_json::to_value(v).unwrap());
+                    let result = handler_result.and_then(|v| {
+                        serde_json::to_value(v).map_err(|error| {
+                            log::error!("failed to serialize response to {:?}: {}", method, error);
+                            if let Some(hook) = &hook {
+                                hook(&method, &error);
+                            }
+                            serialization_error(&error)
+                        })
+                    });
                     Response::from_parts(id, result)
                 } else {
                     Response::error(Some(id), Error::request_cancelled())
@@ -53,11 +145,21 @@ impl ServerRequests {
     ///
     /// This will force the future to resolve to a "canceled" error response. If the future has
     /// already completed, this method call will do nothing.
+    ///
+    /// Clients commonly cancel requests whose responses are already on their way, so cancellations
+    /// of recently completed requests are only logged at the `debug` level. Cancellations of IDs
+    /// which are not known to have been handled are still logged as warnings.
     pub fn cancel(&self, id: &Id) {
-        if let Some((_, handle)) = self.0.remove(id) {
-            handle.abort();
+        let cancellations = &self.2;
+        if let Some((_, mut handler)) = self.0.remove(id) {
+            handler.cancel();
+            cancellations.cancelled.fetch_add(1, Ordering::Relaxed);
             log::info!("successfully cancelled request with ID: {}", id);
+        } else if cancellations.completed.lock().unwrap().ids.contains(id) {
+            cancellations.late.fetch_add(1, Ordering::Relaxed);
+            log::debug!("client asked to cancel request {}, which has already completed, ignoring", id);
         } else {
+            cancellations.unknown.fetch_add(1, Ordering::Relaxed);
             log::warn!(
                 "client asked to cancel request {}, but no such pending request exists, ignoring",
                 id
@@ -67,11 +169,35 @@ impl ServerRequests {
 
     /// Cancels all pending request handlers, if any.
     pub fn cancel_all(&self) {
-        self.0.retain(|_, handle| {
-            handle.abort();
+        self.0.retain(|_, handler| {
+            handler.cancel();
             false
         });
     }
+
+    /// Returns the number of request handlers which are still running.
+    pub fn in_flight(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Returns the number of running request handlers per method.
+    pub fn methods(&self) -> BTreeMap<String, usize> {
+        count_methods(self.0.iter().map(|entry| entry.value().method.clone()))
+    }
+
+    /// Returns the number of cancellations received so far.
+    pub fn cancellations(&self) -> CancellationCounts {
+        let cancellations = &self.2;
+        CancellationCounts {
+            cancelled: cancellations.cancelled.load(Ordering::Relaxed),
+            late: cancellations.late.load(Ordering::Relaxed),
+            unknown: cancellations.unknown.load(Ordering::Relaxed),
+        }
+    }
+}
+
+fn serialization_error(error: &serde_json::Error) -> Error {
+    Error::internal_error().with_message(format!("Failed to serialize response: {}", error))
 }
 
 impl Debug for ServerRequests {
@@ -82,44 +208,185 @@ impl Debug for ServerRequests {
     }
 }
 
+/// A hook observing responses from the client which do not answer a pending server request.
+pub(crate) type UnexpectedResponseHook = Arc<dyn Fn(&UnexpectedResponse) + Send + Sync>;
+
 /// A hashmap containing pending client requests, keyed by request ID.
-pub struct ClientRequests(pub(crate) DashMap<Id, oneshot::Sender<Response>>);
+pub struct ClientRequests(
+    pub(crate) DashMap<Id, (&'static str, oneshot::Sender<Response>)>,
+    Option<UnexpectedResponseHook>,
+    Unexpected,
+    Mutex<Option<Error>>,
+);
+
+/// Counters of responses which do not answer a pending request, along with the IDs of recently
+/// answered and abandoned requests.
+#[derive(Default)]
+struct Unexpected {
+    duplicate: AtomicU64,
+    late: AtomicU64,
+    unknown: AtomicU64,
+    answered: Mutex<CompletedIds>,
+    abandoned: Mutex<CompletedIds>,
+}
 
 impl ClientRequests {
     /// Creates a new pending client requests map.
     pub fn new() -> Self {
-        ClientRequests(DashMap::new())
+        ClientRequests(DashMap::new(), None, Default::default(), Mutex::new(None))
+    }
+
+    /// Sets the hook invoked whenever the client sends a response which violates the protocol.
+    pub(crate) fn unexpected_response_hook(mut self, hook: Option<UnexpectedResponseHook>) -> Self {
+        self.1 = hook;
+        self
     }
 
     /// Inserts the given response into the map.
     ///
     /// The corresponding `.wait()` future will then resolve to the given value.
+    ///
+    /// Responses which do not answer a pending request are counted and ignored. Responses to
+    /// requests the server stopped waiting for, e.g. because it canceled them, are expected and
+    /// only logged at the `debug` level, while responses with an ID which was already answered,
+    /// which is unknown or which is `null` are reported as an [`UnexpectedResponse`].
     pub fn insert(&self, r: Response) {
-        match r.id() {
-            None => log::warn!("received response with request ID of `null`, ignoring"),
-            Some(id) => match self.0.remove(id) {
-                Some((_, tx)) => {
-                    let _ = tx.send(r);
-                },
-                None => log::warn!("received response with unknown request ID: {}", id),
+        let unexpected = &self.2;
+        let id = match r.id() {
+            Some(id) => id,
+            None => {
+                log::warn!("received response with request ID of `null`, ignoring: {:?}", r);
+                unexpected.unknown.fetch_add(1, Ordering::Relaxed);
+                return self.report(UnexpectedResponse::NullId);
             },
+        };
+
+        if let Some((_, (_, tx))) = self.0.remove(id) {
+            unexpected.answered.lock().unwrap().insert(id.clone());
+            let _ = tx.send(r);
+        } else if unexpected.abandoned.lock().unwrap().ids.contains(id) {
+            unexpected.late.fetch_add(1, Ordering::Relaxed);
+            log::debug!("received response to request {}, which is no longer awaited, ignoring", id);
+        } else if unexpected.answered.lock().unwrap().ids.contains(id) {
+            unexpected.duplicate.fetch_add(1, Ordering::Relaxed);
+            log::warn!("received duplicate response to request {}, ignoring", id);
+            self.report(UnexpectedResponse::Duplicate(id.clone()));
+        } else {
+            unexpected.unknown.fetch_add(1, Ordering::Relaxed);
+            log::warn!("received response with unknown request ID: {}", id);
+            self.report(UnexpectedResponse::UnknownId(id.clone()));
+        }
+    }
+
+    fn report(&self, response: UnexpectedResponse) {
+        if let Some(hook) = &self.1 {
+            hook(&response);
         }
     }
 
-    /// Marks the given request ID as pending and waits for its corresponding response to arrive.
+    /// Marks the given request ID of the given method as pending and waits for its corresponding
+    /// response to arrive.
     ///
-    /// # Panics
+    /// If the request is [forgotten](ClientRequests::forget) in the meantime, this resolves to a
+    /// "canceled" error response.
     ///
-    /// Panics if the request ID is already in the hashmap and is pending a matching response. This
-    /// should never happen provided that a monotonically increasing `id` value is used.
-    pub fn wait(&self, id: Id) -> impl Future<Output = Response> + Send + 'static {
-        match self.0.entry(id) {
+    /// Returns `None` if a request with the same ID is still pending a matching response, e.g.
+    /// because the IDs wrapped around while it was waiting.
+    pub fn wait(&self, id: Id, method: &'static str) -> Option<impl Future<Output = Response> + Send + 'static> {
+        let rx = match self.0.entry(id.clone()) {
             Entry::Vacant(entry) => {
                 let (tx, rx) = oneshot::channel();
-                entry.insert(tx);
-                async { rx.await.expect("sender already dropped") }
+                entry.insert((method, tx));
+                rx
             },
-            _ => panic!("concurrent waits for the same request ID can't happen, this is a bug"),
+            Entry::Occupied(_) => return None,
+        };
+
+        // Requests made while the requests are being closed fail like the ones pending before.
+        if let Some(error) = self.closed() {
+            if let Some((id, (_, tx))) = self.0.remove(&id) {
+                let _ = tx.send(Response::error(Some(id), error));
+            }
+        }
+        Some(async { rx.await.unwrap_or_else(|_| Response::error(Some(id), Error::request_cancelled())) })
+    }
+
+    /// Returns the number of requests pending a response.
+    pub(crate) fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Stops waiting for a response to the given request, e.g. because the server canceled it.
+    ///
+    /// A response arriving later is ignored without being reported. Returns `false` if the request
+    /// was not pending.
+    pub(crate) fn forget(&self, id: &Id) -> bool {
+        let pending = self.0.remove(id).is_some();
+        if pending {
+            self.2.abandoned.lock().unwrap().insert(id.clone());
+        }
+        pending
+    }
+
+    /// Resolves all requests still waiting for a response to a "canceled" error response, if any.
+    pub fn cancel_all(&self) {
+        self.fail_all(&Error::request_cancelled());
+    }
+
+    /// Resolves all requests still waiting for a response to the given error, and fails requests
+    /// made afterwards with it until [reopened](ClientRequests::reopen).
+    ///
+    /// This is done once the server shut down, since the client is not required to answer requests
+    /// past that point.
+    pub(crate) fn close(&self, error: Error) {
+        let pending = self.0.len();
+        if pending > 0 {
+            log::warn!("failing {} pending request(s) to the client: {}", pending, error.message);
+        }
+        // The error is stored first, so that requests made while the pending ones are failed are
+        // not left waiting.
+        *self.3.lock().unwrap() = Some(error.clone());
+        self.fail_all(&error);
+    }
+
+    /// Accepts requests again after the requests were [closed](ClientRequests::close).
+    pub(crate) fn reopen(&self) {
+        self.3.lock().unwrap().take();
+    }
+
+    /// Returns the error failing new requests, if the requests were
+    /// [closed](ClientRequests::close).
+    pub(crate) fn closed(&self) -> Option<Error> {
+        self.3.lock().unwrap().clone()
+    }
+
+    fn fail_all(&self, error: &Error) {
+        let ids: Vec<_> = self.0.iter().map(|entry| entry.key().clone()).collect();
+        for id in ids {
+            if let Some((id, (_, tx))) = self.0.remove(&id) {
+                self.2.abandoned.lock().unwrap().insert(id.clone());
+                let _ = tx.send(Response::error(Some(id), error.clone()));
+            }
+        }
+    }
+
+    /// Returns the number of requests still waiting for a response per method.
+    pub fn methods(&self) -> BTreeMap<String, usize> {
+        count_methods(self.0.iter().map(|entry| entry.value().0.into()))
+    }
+
+    /// Returns the method of the request with the given ID, if it is still waiting for a response.
+    pub(crate) fn method(&self, id: &Id) -> Option<&'static str> {
+        self.0.get(id).map(|entry| entry.value().0)
+    }
+
+    /// Returns the number of responses received so far which did not answer a pending request.
+    pub fn unexpected_responses(&self) -> UnexpectedResponseCounts {
+        let unexpected = &self.2;
+        UnexpectedResponseCounts {
+            duplicate: unexpected.duplicate.load(Ordering::Relaxed),
+            late: unexpected.late.load(Ordering::Relaxed),
+            unknown: unexpected.unknown.load(Ordering::Relaxed),
         }
     }
 }
@@ -132,6 +399,164 @@ impl Debug for ClientRequests {
     }
 }
 
+/// A response from the client which violates the protocol, reported to the hook registered with
+/// [`LspServiceBuilder::on_unexpected_response`].
+///
+/// [`LspServiceBuilder::on_unexpected_response`]: crate::LspServiceBuilder::on_unexpected_response
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum UnexpectedResponse {
+    /// A further response to a request which the client already answered.
+    Duplicate(Id),
+    /// A response with an ID the server is not known to have sent a request with.
+    UnknownId(Id),
+    /// A response whose ID is `null`, which is only allowed for errors about requests the client
+    /// failed to parse.
+    NullId,
+}
+
+impl Display for UnexpectedResponse {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            UnexpectedResponse::Duplicate(id) => write!(f, "duplicate response to request {}", id),
+            UnexpectedResponse::UnknownId(id) => write!(f, "response with unknown request ID {}", id),
+            UnexpectedResponse::NullId => f.write_str("response with request ID of `null`"),
+        }
+    }
+}
+
+/// A snapshot of the requests in flight between the server and the client.
+///
+/// This is returned by [`LspService::pending_requests`], e.g. for logging or for shedding load
+/// when too many requests are in flight.
+///
+/// [`LspService::pending_requests`]: crate::LspService::pending_requests
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct PendingRequests {
+    incoming: BTreeMap<String, usize>,
+    outgoing: BTreeMap<String, usize>,
+    cancellations: CancellationCounts,
+    unexpected_responses: UnexpectedResponseCounts,
+}
+
+impl PendingRequests {
+    pub(crate) fn new(server: &ServerRequests, client: &ClientRequests) -> Self {
+        PendingRequests {
+            incoming: server.methods(),
+            outgoing: client.methods(),
+            cancellations: server.cancellations(),
+            unexpected_responses: client.unexpected_responses(),
+        }
+    }
+
+    /// Returns the number of client-to-server requests being handled per method.
+    pub fn incoming(&self) -> &BTreeMap<String, usize> {
+        &self.incoming
+    }
+
+    /// Returns the number of server-to-client requests waiting for a response per method.
+    pub fn outgoing(&self) -> &BTreeMap<String, usize> {
+        &self.outgoing
+    }
+
+    /// Returns the total number of client-to-server requests being handled.
+    pub fn incoming_count(&self) -> usize {
+        self.incoming.values().sum()
+    }
+
+    /// Returns the total number of server-to-client requests waiting for a response.
+    pub fn outgoing_count(&self) -> usize {
+        self.outgoing.values().sum()
+    }
+
+    /// Returns the number of `$/cancelRequest` notifications received from the client so far.
+    pub fn cancellations(&self) -> CancellationCounts {
+        self.cancellations
+    }
+
+    /// Returns the number of responses received from the client so far which did not answer a
+    /// pending request.
+    pub fn unexpected_responses(&self) -> UnexpectedResponseCounts {
+        self.unexpected_responses
+    }
+}
+
+/// Counts of the `$/cancelRequest` notifications received from the client, by outcome.
+///
+/// Editors may send bursts of cancellations, e.g. while the user is typing, most of which arrive
+/// after the request was already handled. A high number of late cancellations is therefore benign,
+/// while unknown IDs may point to a client bug.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct CancellationCounts {
+    cancelled: u64,
+    late: u64,
+    unknown: u64,
+}
+
+impl CancellationCounts {
+    /// Returns the number of request handlers which were cancelled while running.
+    pub fn cancelled(&self) -> u64 {
+        self.cancelled
+    }
+
+    /// Returns the number of cancellations of requests which had already completed.
+    pub fn late(&self) -> u64 {
+        self.late
+    }
+
+    /// Returns the number of cancellations of request IDs which are not known to have been handled.
+    pub fn unknown(&self) -> u64 {
+        self.unknown
+    }
+
+    /// Returns the total number of cancellations received.
+    pub fn total(&self) -> u64 {
+        self.cancelled + self.late + self.unknown
+    }
+}
+
+/// Counts of the responses received from the client which did not answer a pending request, by
+/// cause.
+///
+/// Late responses are benign, as the client may answer a request just before it receives the
+/// cancellation. Duplicate responses and unknown IDs, which include `null` IDs, point to a client
+/// bug.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct UnexpectedResponseCounts {
+    duplicate: u64,
+    late: u64,
+    unknown: u64,
+}
+
+impl UnexpectedResponseCounts {
+    /// Returns the number of further responses to requests which the client already answered.
+    pub fn duplicate(&self) -> u64 {
+        self.duplicate
+    }
+
+    /// Returns the number of responses to requests which the server no longer waited for.
+    pub fn late(&self) -> u64 {
+        self.late
+    }
+
+    /// Returns the number of responses with unknown or `null` request IDs.
+    pub fn unknown(&self) -> u64 {
+        self.unknown
+    }
+
+    /// Returns the total number of unexpected responses received.
+    pub fn total(&self) -> u64 {
+        self.duplicate + self.late + self.unknown
+    }
+}
+
+fn count_methods(methods: impl Iterator<Item = Cow<'static, str>>) -> BTreeMap<String, usize> {
+    let mut counts = BTreeMap::new();
+    for method in methods {
+        *counts.entry(method.into_owned()).or_default() += 1;
+    }
+    counts
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -143,16 +568,16 @@ mod tests {
         #[test]
         fn debug() {
             let client_requests = ClientRequests::new();
-            format!("{:?}", client_requests);
+            let _ = format!("{:?}", client_requests);
         }
 
         #[tokio::test]
-        #[should_panic]
         async fn wait_current() {
             let pending = ClientRequests::new();
             let id = Id::Number(1);
-            tokio::spawn(pending.wait(id.clone()));
-            tokio::spawn(pending.wait(id));
+            let _wait_fut = pending.wait(id.clone(), "custom/request").unwrap();
+            assert!(pending.wait(id, "custom/request").is_none());
+            assert_eq!(pending.len(), 1);
         }
 
         #[tokio::test]
@@ -160,7 +585,7 @@ mod tests {
             let pending = ClientRequests::new();
 
             let id = Id::Number(1);
-            let wait_fut = tokio::spawn(pending.wait(id.clone()));
+            let wait_fut = tokio::spawn(pending.wait(id.clone(), "custom/request").unwrap());
 
             let expected = Response::ok(id.clone(), json!({}));
             pending.insert(expected.clone());
@@ -169,6 +594,44 @@ mod tests {
             assert_eq!(expected, actual);
         }
 
+        #[tokio::test]
+        async fn cancel_all() {
+            let pending = ClientRequests::new();
+
+            let id = Id::Number(1);
+            let wait_fut = tokio::spawn(pending.wait(id.clone(), "custom/request").unwrap());
+            pending.cancel_all();
+
+            let expected = Response::error(Some(id), Error::request_cancelled());
+            let actual = wait_fut.await.expect("task panicked");
+            assert_eq!(expected, actual);
+        }
+
+        #[tokio::test]
+        async fn close() {
+            let pending = ClientRequests::new();
+            let error = Error::request_cancelled().with_message("closed");
+
+            let id = Id::Number(1);
+            let wait_fut = tokio::spawn(pending.wait(id.clone(), "custom/request").unwrap());
+            assert_eq!(pending.closed(), None);
+            pending.close(error.clone());
+            assert_eq!(pending.closed(), Some(error.clone()));
+
+            let actual = wait_fut.await.expect("task panicked");
+            assert_eq!(actual, Response::error(Some(id.clone()), error.clone()));
+            assert!(pending.methods().is_empty());
+            pending.insert(Response::ok(id, json!(null)));
+            assert_eq!(pending.unexpected_responses().late(), 1);
+
+            let late = pending.wait(Id::Number(2), "custom/request").unwrap();
+            assert_eq!(late.await, Response::error(Some(Id::Number(2)), error));
+            assert!(pending.methods().is_empty());
+
+            pending.reopen();
+            assert_eq!(pending.closed(), None);
+        }
+
         #[tokio::test]
         async fn unbalanced_insert() {
             let pending = ClientRequests::new();
@@ -176,6 +639,40 @@ mod tests {
             let expected = Response::ok(id, json!({}));
             pending.insert(expected);
         }
+
+        #[tokio::test]
+        async fn unexpected_responses() {
+            let reported = Arc::new(Mutex::new(Vec::new()));
+            let hook = {
+                let reported = reported.clone();
+                Arc::new(move |response: &UnexpectedResponse| reported.lock().unwrap().push(response.clone()))
+            };
+            let pending = ClientRequests::new().unexpected_response_hook(Some(hook));
+
+            let answered = pending.wait(Id::Number(1), "custom/request").unwrap();
+            pending.insert(Response::ok(Id::Number(1), json!(1)));
+            pending.insert(Response::ok(Id::Number(1), json!(2)));
+            assert_eq!(answered.await, Response::ok(Id::Number(1), json!(1)));
+
+            let forgotten = pending.wait(Id::Number(2), "custom/request").unwrap();
+            assert!(pending.forget(&Id::Number(2)));
+            assert!(!pending.forget(&Id::Number(2)));
+            assert_eq!(forgotten.await, Response::error(Some(Id::Number(2)), Error::request_cancelled()));
+            pending.insert(Response::ok(Id::Number(2), json!(null)));
+
+            pending.insert(Response::ok(Id::Number(3), json!(null)));
+            pending.insert(Response::error(None, Error::parse_error()));
+
+            let expected = vec![
+                UnexpectedResponse::Duplicate(Id::Number(1)),
+                UnexpectedResponse::UnknownId(Id::Number(3)),
+                UnexpectedResponse::NullId,
+            ];
+            assert_eq!(*reported.lock().unwrap(), expected);
+            let counts = pending.unexpected_responses();
+            assert_eq!((counts.duplicate(), counts.late(), counts.unknown()), (1, 1, 2));
+            assert_eq!(counts.total(), 4);
+        }
     }
 
     mod server_requests {
@@ -186,7 +683,7 @@ mod tests {
         #[test]
         fn debug() {
             let server_requests = ServerRequests::new();
-            format!("{:?}", server_requests);
+            let _ = format!("{:?}", server_requests);
         }
 
         #[tokio::test]
@@ -194,27 +691,62 @@ mod tests {
             let pending = ServerRequests::new();
 
             let id = Id::Number(1);
-            let response = pending.execute(id.clone(), async { Ok(json!({})) }).await;
+            let response = pending.execute(id.clone(), "test", async { Ok(json!({})) }).await;
 
             assert_eq!(response, Response::ok(id, json!({})));
         }
 
+        #[tokio::test]
+        async fn execute_request_context() {
+            let pending = ServerRequests::new();
+
+            let id = Id::Number(1);
+            let response = pending
+                .execute(id.clone(), "test", async {
+                    let context = RequestContext::current().unwrap();
+                    Ok(json!({ "id": context.id(), "method": context.method() }))
+                })
+                .await;
+
+            assert_eq!(response, Response::ok(id, json!({ "id": 1, "method": "test" })));
+        }
+
         #[tokio::test]
         async fn execute_concurrent() {
             let pending = ServerRequests::new();
             let id = Id::Number(1);
-            let fut0 = pending.execute(id.clone(), async { Ok(json!({})) });
-            let fut1 = pending.execute(id.clone(), async { Ok(json!({})) });
+            let fut0 = pending.execute(id.clone(), "test", async { Ok(json!({})) });
+            let fut1 = pending.execute(id.clone(), "test", async { Ok(json!({})) });
             assert_eq!(fut0.await, Response::ok(id.clone(), json!({})));
             assert_eq!(fut1.await, Response::error(Some(id.clone()), Error::invalid_request()));
         }
 
+        #[tokio::test]
+        async fn execute_serialization_error() {
+            use crate::jsonrpc::ErrorCode;
+            use std::sync::Mutex;
+
+            let errors = Arc::new(Mutex::new(Vec::new()));
+            let recorded = errors.clone();
+            let hook: SerializationHook = Arc::new(move |method, _| recorded.lock().unwrap().push(method.to_owned()));
+            let pending = ServerRequests::new().serialization_hook(Some(hook));
+
+            let id = Id::Number(1);
+            // Maps with non-string keys can not be represented in JSON.
+            let result = std::collections::BTreeMap::from([((1, 2), 3)]);
+            let response = pending.execute(id, "test", async { Ok(result) }).await;
+
+            let (_, result) = response.into_parts();
+            assert_eq!(result.unwrap_err().code, ErrorCode::InternalError);
+            assert_eq!(*errors.lock().unwrap(), vec!["test"]);
+        }
+
         #[tokio::test]
         async fn cancel() {
             let pending = ServerRequests::new();
 
             let id = Id::Number(1);
-            let handler_fut = tokio::spawn(pending.execute(id.clone(), async {
+            let handler_fut = tokio::spawn(pending.execute(id.clone(), "test", async {
                 tokio::time::sleep(Duration::from_secs(50)).await;
                 Ok(json!({}))
             }));
@@ -233,18 +765,39 @@ mod tests {
             pending.cancel(&id);
         }
 
+        #[tokio::test]
+        async fn cancellations() {
+            let pending = ServerRequests::new();
+            let completed = Id::Number(1);
+            let response = pending.execute(completed.clone(), "test", async { Ok(json!({})) }).await;
+            assert_eq!(response, Response::ok(completed.clone(), json!({})));
+
+            let running = Id::Number(2);
+            let handler_fut = pending.execute(running.clone(), "test", futures::future::pending::<Result<()>>());
+            pending.cancel(&running);
+            assert_eq!(handler_fut.await, Response::error(Some(running), Error::request_cancelled()));
+
+            pending.cancel(&completed);
+            pending.cancel(&completed);
+            pending.cancel(&Id::Number(3));
+
+            let counts = pending.cancellations();
+            assert_eq!((counts.cancelled(), counts.late(), counts.unknown()), (1, 2, 1));
+            assert_eq!(counts.total(), 4);
+        }
+
         #[tokio::test]
         async fn cancel_all() {
             let pending = ServerRequests::new();
 
             let id1 = Id::Number(1);
-            let handler_fut1 = tokio::spawn(pending.execute(id1.clone(), async {
+            let handler_fut1 = tokio::spawn(pending.execute(id1.clone(), "test", async {
                 tokio::time::sleep(Duration::from_secs(50)).await;
                 Ok(json!({}))
             }));
 
             let id2 = Id::Number(2);
-            let handler_fut2 = tokio::spawn(pending.execute(id2.clone(), async {
+            let handler_fut2 = tokio::spawn(pending.execute(id2.clone(), "test", async {
                 tokio::time::sleep(Duration::from_secs(50)).await;
                 Ok(json!({}))
             }));
@@ -259,4 +812,21 @@ mod tests {
             assert_eq!(res2, Response::error(Some(id2), Error::request_cancelled()));
         }
     }
+
+    #[test]
+    fn pending_requests() {
+        let server = ServerRequests::new();
+        let client = ClientRequests::new();
+        assert_eq!(PendingRequests::new(&server, &client), PendingRequests::default());
+
+        let _fut0 = server.execute(Id::Number(1), "textDocument/hover", future::pending::<Result<()>>());
+        let _fut1 = server.execute(Id::Number(2), "textDocument/hover", future::pending::<Result<()>>());
+        let _fut2 = client.wait(Id::Number(1), "workspace/configuration").unwrap();
+
+        let pending = PendingRequests::new(&server, &client);
+        assert_eq!(pending.incoming_count(), 2);
+        assert_eq!(pending.incoming()["textDocument/hover"], 2);
+        assert_eq!(pending.outgoing_count(), 1);
+        assert_eq!(pending.outgoing()["workspace/configuration"], 1);
+    }
 }
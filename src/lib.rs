@@ -6,20 +6,166 @@
 #![deny(missing_docs)]
 #![forbid(unsafe_code)]
 
+/// The `lsp-types` crate, in the version used by the [`LanguageServer`] trait.
+///
+/// `lspower` upgrades `lsp-types` only in its own breaking releases. Items an upgrade renames remain
+/// available as deprecated shims in [`compat`] for one release cycle, such as the variant names of
+/// `lsp-types` 0.91 in [`compat::lsp_types_0_91`].
 pub extern crate lsp;
+#[cfg(feature = "tls")]
+pub extern crate tokio_rustls;
 
+mod batch;
+mod blocking;
+mod call_hierarchy;
 mod client;
 mod codec;
+mod command;
+pub mod compat;
+pub mod completion;
+mod connection;
+mod context;
+pub mod diagnostics;
+pub mod document;
+mod duplex;
+mod feed;
+#[cfg(feature = "conformance")]
+pub mod conformance;
+#[cfg(feature = "http")]
+mod http_service;
 pub mod jsonrpc;
+#[cfg(feature = "openrpc")]
+mod openrpc;
+mod protocol;
+#[cfg(feature = "proposed")]
+pub mod proposed;
+mod proxy;
+mod reflect;
+mod result_cache;
+mod router;
+mod scope;
+pub mod selector;
+pub mod semantic_tokens;
 mod server;
 mod service;
+mod spec;
+mod symbol;
+mod task;
+mod time;
+#[cfg(feature = "tls")]
+mod tls;
+mod traffic;
 mod transport;
+pub mod uri;
+pub mod workspace;
 
 pub use self::{
-    client::{CancellationToken, Client, TokenCanceller},
-    service::{ExitedError, LspService, MessageStream},
-    transport::Server,
+    batch::{BatchDriver, BatchReport},
+    blocking::BlockingServer,
+    call_hierarchy::CallHierarchyRegistry,
+    client::{
+        CancellationToken,
+        Client,
+        ClientId,
+        ClientPool,
+        ClientRequestError,
+        ClientState,
+        Dynamic,
+        HeadlessClient,
+        IdAllocator,
+        IdRange,
+        Initialized,
+        MaybeInitialized,
+        OverflowAction,
+        RateLimit,
+        RateLimitPolicy,
+        RetryPolicy,
+        TelemetryPolicy,
+        TokenCanceller,
+        Uninitialized,
+        UnsupportedByClient,
+        UnsupportedRegistration,
+        UnsupportedResourceOperations,
+    },
+    codec::{Frame, ParseError},
+    command::CommandRegistry,
+    connection::{ConnectionInfo, TransportKind},
+    context::RequestContext,
+    duplex::{duplex, DuplexClient},
+    protocol::{DiagnosticModel, NegotiatedProtocol, PositionEncoding, ProgressModel, SpecLevel},
+    proxy::ServerProxy,
+    reflect::{method, methods, MethodInfo, MethodKind},
+    router::{merge_initialize_results, CapabilityConflict, LanguageRouter},
+    scope::DocumentScope,
+    service::{
+        AnomalyEvent,
+        AnomalyEventStream,
+        BackendHandle,
+        ClientEvent,
+        ClientEventStream,
+        CoalescingPolicy,
+        DefaultHandler,
+        DollarMethodPolicy,
+        Drained,
+        ExitCode,
+        ExitedError,
+        InitializingPolicy,
+        Interception,
+        LatencyBudget,
+        LoadSheddingPolicy,
+        LspService,
+        LspServiceBuilder,
+        MessageStream,
+        ProtocolLog,
+        ProtocolViolation,
+        ReplaceError,
+        ResetError,
+        SecurityPolicy,
+        SessionHandle,
+        SettingsChange,
+        StrictMode,
+        UnhandledNotification,
+        UnhandledNotificationPolicy,
+        UnhandledNotificationStream,
+        ViolationAction,
+    },
+    spec::{enabled_features, SpecFeature, SPEC_VERSION},
+    symbol::{
+        flatten_document_symbols,
+        SymbolConverter,
+        WorkspaceLocation,
+        WorkspaceSymbol,
+        WorkspaceSymbolAggregator,
+    },
+    task::Spawner,
+    time::{Clock, MockClock, SystemClock},
+    traffic::{Direction, TrafficLogger},
+    transport::{
+        DecodeErrorAction,
+        DecodeErrorPolicy,
+        ExitReason,
+        FlushStrategy,
+        GroupExit,
+        InterleaveSender,
+        ResponseOrder,
+        Server,
+        ServerBuilder,
+        ServerGroup,
+        Stdin,
+        Stdout,
+        Watchdog,
+        WatchdogEvent,
+        WriteErrorPolicy,
+    },
 };
+#[cfg(feature = "compression")]
+pub use self::codec::{Compression, ContentEncoding};
+#[cfg(feature = "http")]
+pub use self::http_service::HttpService;
+#[cfg(feature = "openrpc")]
+pub use self::openrpc::RpcDescription;
+#[cfg(feature = "tls")]
+pub use self::tls::{accept_tls, serve_tls};
 pub use async_trait::async_trait;
 use auto_impl::auto_impl;
 use lspower_macros::rpc;
@@ -64,6 +210,11 @@ pub trait LanguageServer: Send + Sync + 'static {
     ///
     /// This method is guaranteed to only execute once. If the client sends this request to the
     /// server again, the server will respond with JSON-RPC error code `-32600` (invalid request).
+    ///
+    /// Requests to the client may still be sent while this method runs. Once it returned, requests
+    /// still waiting for a response from the client, as well as requests sent afterwards, fail with
+    /// JSON-RPC error code `-32800` (request cancelled) whose data holds the `reason` why, instead
+    /// of waiting for a response the client is not required to send.
     #[rpc(name = "shutdown")]
     async fn shutdown(&self) -> crate::jsonrpc::Result<()>;
 
@@ -82,17 +233,22 @@ pub trait LanguageServer: Send + Sync + 'static {
     /// [`workspace/didChangeWorkspaceFolders`]: https://microsoft.github.io/language-server-protocol/specification#workspace_didChangeWorkspaceFolders
     /// [`initialize`]: #tymethod.initialize
     #[rpc(name = "workspace/didChangeWorkspaceFolders")]
-    async fn did_change_workspace_folders(&self, _params: lsp::DidChangeWorkspaceFoldersParams) {
-        log::warn!("Got a workspace/didChangeWorkspaceFolders notification, but it is not implemented");
+    async fn did_change_workspace_folders(&self, params: lsp::DidChangeWorkspaceFoldersParams) {
+        crate::service::not_implemented("workspace/didChangeWorkspaceFolders", &params);
     }
 
     /// The [`workspace/didChangeConfiguration`] notification is sent from the client to the server
     /// to signal the change of configuration settings.
     ///
+    /// Servers which only need the new settings deserialized into their own type can register a
+    /// hook with [`LspServiceBuilder::on_settings_changed`] instead, which also reports the sections
+    /// that changed.
+    ///
     /// [`workspace/didChangeConfiguration`]: https://microsoft.github.io/language-server-protocol/specification#workspace_didChangeConfiguration
+    /// [`LspServiceBuilder::on_settings_changed`]: crate::LspServiceBuilder::on_settings_changed
     #[rpc(name = "workspace/didChangeConfiguration")]
-    async fn did_change_configuration(&self, _params: lsp::DidChangeConfigurationParams) {
-        log::warn!("Got a workspace/didChangeConfiguration notification, but it is not implemented");
+    async fn did_change_configuration(&self, params: lsp::DidChangeConfigurationParams) {
+        crate::service::not_implemented("workspace/didChangeConfiguration", &params);
     }
 
     /// The [`workspace/didChangeWatchedFiles`] notification is sent from the client to the server
@@ -105,8 +261,8 @@ pub trait LanguageServer: Send + Sync + 'static {
     /// [`workspace/didChangeWatchedFiles`]: https://microsoft.github.io/language-server-protocol/specification#workspace_didChangeConfiguration
     /// [`initialized`]: #tymethod.initialized
     #[rpc(name = "workspace/didChangeWatchedFiles")]
-    async fn did_change_watched_files(&self, _params: lsp::DidChangeWatchedFilesParams) {
-        log::warn!("Got a workspace/didChangeWatchedFiles notification, but it is not implemented");
+    async fn did_change_watched_files(&self, params: lsp::DidChangeWatchedFilesParams) {
+        crate::service::not_implemented("workspace/didChangeWatchedFiles", &params);
     }
 
     /// The [`workspace/symbol`] request is sent from the client to the server to list project-wide
@@ -147,8 +303,24 @@ pub trait LanguageServer: Send + Sync + 'static {
     ///
     /// [`textDocument/didOpen`]: https://microsoft.github.io/language-server-protocol/specification#textDocument_didOpen
     #[rpc(name = "textDocument/didOpen")]
-    async fn did_open(&self, _params: lsp::DidOpenTextDocumentParams) {
-        log::warn!("Got a textDocument/didOpen notification, but it is not implemented");
+    async fn did_open(&self, params: lsp::DidOpenTextDocumentParams) {
+        crate::service::not_implemented("textDocument/didOpen", &params);
+    }
+
+    /// Handles the [`textDocument/didOpen`] notification given its parameters as raw JSON.
+    ///
+    /// Servers which keep documents in their own data structures, such as ropes, can override this
+    /// method to parse the document text directly from the message, rather than from an owned
+    /// `String`. By default, the parameters are deserialized and passed to [`did_open`].
+    ///
+    /// [`textDocument/didOpen`]: https://microsoft.github.io/language-server-protocol/specification#textDocument_didOpen
+    /// [`did_open`]: LanguageServer::did_open
+    #[rpc(raw = "textDocument/didOpen")]
+    async fn did_open_raw(&self, params: &crate::jsonrpc::RawValue) {
+        match serde_json::from_str(params.get()) {
+            Ok(params) => self.did_open(params).await,
+            Err(error) => log::warn!("invalid parameters for \"textDocument/didOpen\" notification: {}", error),
+        }
     }
 
     /// The [`textDocument/didChange`] notification is sent from the client to the server to signal
@@ -159,8 +331,25 @@ pub trait LanguageServer: Send + Sync + 'static {
     ///
     /// [`textDocument/didChange`]: https://microsoft.github.io/language-server-protocol/specification#textDocument_didChange
     #[rpc(name = "textDocument/didChange")]
-    async fn did_change(&self, _params: lsp::DidChangeTextDocumentParams) {
-        log::warn!("Got a textDocument/didChange notification, but it is not implemented");
+    async fn did_change(&self, params: lsp::DidChangeTextDocumentParams) {
+        crate::service::not_implemented("textDocument/didChange", &params);
+    }
+
+    /// Handles the [`textDocument/didChange`] notification given its parameters as raw JSON.
+    ///
+    /// Since this notification is sent on every keystroke, servers can override this method to
+    /// apply the content changes without allocating a `String` for each of them, e.g. by
+    /// deserializing them into borrowed `&str`s. By default, the parameters are deserialized and
+    /// passed to [`did_change`].
+    ///
+    /// [`textDocument/didChange`]: https://microsoft.github.io/language-server-protocol/specification#textDocument_didChange
+    /// [`did_change`]: LanguageServer::did_change
+    #[rpc(raw = "textDocument/didChange")]
+    async fn did_change_raw(&self, params: &crate::jsonrpc::RawValue) {
+        match serde_json::from_str(params.get()) {
+            Ok(params) => self.did_change(params).await,
+            Err(error) => log::warn!("invalid parameters for \"textDocument/didChange\" notification: {}", error),
+        }
     }
 
     /// The [`textDocument/willSave`] notification is sent from the client to the server before the
@@ -168,8 +357,8 @@ pub trait LanguageServer: Send + Sync + 'static {
     ///
     /// [`textDocument/willSave`]: https://microsoft.github.io/language-server-protocol/specification#textDocument_willSave
     #[rpc(name = "textDocument/willSave")]
-    async fn will_save(&self, _params: lsp::WillSaveTextDocumentParams) {
-        log::warn!("Got a textDocument/willSave notification, but it is not implemented");
+    async fn will_save(&self, params: lsp::WillSaveTextDocumentParams) {
+        crate::service::not_implemented("textDocument/willSave", &params);
     }
 
     /// The [`textDocument/willSaveWaitUntil`] request is sent from the client to the server before
@@ -194,8 +383,8 @@ pub trait LanguageServer: Send + Sync + 'static {
     ///
     /// [`textDocument/didSave`]: https://microsoft.github.io/language-server-protocol/specification#textDocument_didSave
     #[rpc(name = "textDocument/didSave")]
-    async fn did_save(&self, _params: lsp::DidSaveTextDocumentParams) {
-        log::warn!("Got a textDocument/didSave notification, but it is not implemented");
+    async fn did_save(&self, params: lsp::DidSaveTextDocumentParams) {
+        crate::service::not_implemented("textDocument/didSave", &params);
     }
 
     /// The [`textDocument/didClose`] notification is sent from the client to the server when the
@@ -206,8 +395,8 @@ pub trait LanguageServer: Send + Sync + 'static {
     ///
     /// [`textDocument/didClose`]: https://microsoft.github.io/language-server-protocol/specification#textDocument_didClose
     #[rpc(name = "textDocument/didClose")]
-    async fn did_close(&self, _params: lsp::DidCloseTextDocumentParams) {
-        log::warn!("Got a textDocument/didClose notification, but it is not implemented");
+    async fn did_close(&self, params: lsp::DidCloseTextDocumentParams) {
+        crate::service::not_implemented("textDocument/didClose", &params);
     }
 
     /// The [`textDocument/completion`] request is sent from the client to the server to compute
@@ -644,6 +833,13 @@ pub trait LanguageServer: Send + Sync + 'static {
     /// The [`textDocument/foldingRange`] request is sent from the client to the server to return
     /// all folding ranges found in a given text document.
     ///
+    /// If the built-in response filters are enabled with
+    /// [`LspServiceBuilder::builtin_response_filters`], the returned ranges are adjusted to the
+    /// capabilities declared by the client before they are sent: kinds the client did not declare
+    /// are removed, as are the start and end characters if the client only folds complete lines,
+    /// and ranges beyond the `rangeLimit` of the client are dropped. See
+    /// [`LspServiceBuilder::response_filter`] for details.
+    ///
     /// [`textDocument/foldingRange`]: https://microsoft.github.io/language-server-protocol/specification#textDocument_foldingRange
     ///
     /// # Compatibility
@@ -753,6 +949,52 @@ pub trait LanguageServer: Send + Sync + 'static {
         Err(crate::jsonrpc::Error::method_not_found())
     }
 
+    /// The [`textDocument/inlayHint`] request is sent from the client to the server to compute
+    /// inlay hints for a given range of a text document.
+    ///
+    /// This method is only available with the `proposed` crate feature enabled.
+    ///
+    /// [`textDocument/inlayHint`]: https://microsoft.github.io/language-server-protocol/specifications/specification-3-17/#textDocument_inlayHint
+    #[cfg(feature = "proposed")]
+    #[rpc(name = "textDocument/inlayHint")]
+    async fn inlay_hint(&self, _params: lsp::InlayHintParams) -> crate::jsonrpc::Result<Option<Vec<lsp::InlayHint>>> {
+        log::error!("Got a textDocument/inlayHint request, but it is not implemented");
+        Err(crate::jsonrpc::Error::method_not_found())
+    }
+
+    /// The [`inlayHint/resolve`] request is sent from the client to the server to resolve
+    /// additional information for a given inlay hint.
+    ///
+    /// This method is only available with the `proposed` crate feature enabled.
+    ///
+    /// [`inlayHint/resolve`]: https://microsoft.github.io/language-server-protocol/specifications/specification-3-17/#inlayHint_resolve
+    #[cfg(feature = "proposed")]
+    #[rpc(name = "inlayHint/resolve")]
+    async fn inlay_hint_resolve(&self, _params: lsp::InlayHint) -> crate::jsonrpc::Result<lsp::InlayHint> {
+        log::error!("Got a inlayHint/resolve request, but it is not implemented");
+        Err(crate::jsonrpc::Error::method_not_found())
+    }
+
+    /// The [`textDocument/inlineCompletion`] request is sent from the client to the server to
+    /// compute inline completions for a given text document, either explicitly by a user gesture
+    /// or implicitly when typing.
+    ///
+    /// The capability is advertised with [`LspServiceBuilder::inline_completion_provider`], since
+    /// [`ServerCapabilities`](lsp::ServerCapabilities) lacks a field for it.
+    ///
+    /// This method is only available with the `proposed` crate feature enabled.
+    ///
+    /// [`textDocument/inlineCompletion`]: https://microsoft.github.io/language-server-protocol/specifications/lsp/3.18/specification/#textDocument_inlineCompletion
+    #[cfg(feature = "proposed")]
+    #[rpc(name = "textDocument/inlineCompletion")]
+    async fn inline_completion(
+        &self,
+        _params: crate::proposed::InlineCompletionParams,
+    ) -> crate::jsonrpc::Result<Option<crate::proposed::InlineCompletionResponse>> {
+        log::error!("Got a textDocument/inlineCompletion request, but it is not implemented");
+        Err(crate::jsonrpc::Error::method_not_found())
+    }
+
     /// This handler can be used to respond to all requests that are not handled by built in request
     /// handlers.
     async fn request_else(
@@ -766,6 +1008,15 @@ pub trait LanguageServer: Send + Sync + 'static {
         );
         Err(crate::jsonrpc::Error::method_not_found())
     }
+
+    /// This handler can be used to handle all notifications that are not handled by built in
+    /// notification handlers.
+    ///
+    /// Unknown notifications starting with `$/` are ignored without calling this handler, unless
+    /// the [`DollarMethodPolicy`] of the service forwards them.
+    async fn notification_else(&self, method: &str, params: Option<serde_json::Value>) {
+        crate::service::not_found(method, params);
+    }
 }
 
 #[cfg(test)]
@@ -822,7 +1073,7 @@ mod tests {
 
     #[tokio::test]
     async fn initialize() {
-        let (service, _) = LspService::new(|_| Mock::default());
+        let (service, _) = LspService::new(|_| Mock);
         let mut service = Spawn::new(service);
 
         helper::initialize(&mut service).await;
@@ -830,7 +1081,7 @@ mod tests {
 
     #[tokio::test]
     async fn initialized() {
-        let (service, _) = LspService::new(|_| Mock::default());
+        let (service, _) = LspService::new(|_| Mock);
         let mut service = Spawn::new(service);
 
         helper::initialize(&mut service).await;
@@ -843,7 +1094,7 @@ mod tests {
 
     #[tokio::test]
     async fn shutdown() {
-        let (service, _) = LspService::new(|_| Mock::default());
+        let (service, _) = LspService::new(|_| Mock);
         let mut service = Spawn::new(service);
 
         helper::initialize(&mut service).await;
@@ -865,7 +1116,7 @@ mod tests {
 
         #[tokio::test]
         async fn incoming_calls() {
-            let (service, _) = LspService::new(|_| Mock::default());
+            let (service, _) = LspService::new(|_| Mock);
             let mut service = Spawn::new(service);
 
             super::helper::initialize(&mut service).await;
@@ -895,7 +1146,7 @@ mod tests {
 
         #[tokio::test]
         async fn outgoing_calls() {
-            let (service, _) = LspService::new(|_| Mock::default());
+            let (service, _) = LspService::new(|_| Mock);
             let mut service = Spawn::new(service);
 
             super::helper::initialize(&mut service).await;
@@ -932,7 +1183,7 @@ mod tests {
 
         #[tokio::test]
         async fn resolve() {
-            let (service, _) = LspService::new(|_| Mock::default());
+            let (service, _) = LspService::new(|_| Mock);
             let mut service = Spawn::new(service);
 
             super::helper::initialize(&mut service).await;
@@ -956,7 +1207,7 @@ mod tests {
 
         #[tokio::test]
         async fn resolve() {
-            let (service, _) = LspService::new(|_| Mock::default());
+            let (service, _) = LspService::new(|_| Mock);
             let mut service = Spawn::new(service);
 
             super::helper::initialize(&mut service).await;
@@ -972,6 +1223,117 @@ mod tests {
         }
     }
 
+    #[cfg(feature = "proposed")]
+    mod inlay_hint {
+        use super::*;
+        use crate::jsonrpc::{Error, Id, Incoming, Outgoing, Response};
+        use std::task::Poll;
+        use tower_test::mock::Spawn;
+
+        #[tokio::test]
+        async fn inlay_hint() {
+            let (service, _) = LspService::new(|_| Mock);
+            let mut service = Spawn::new(service);
+
+            super::helper::initialize(&mut service).await;
+
+            let params = lsp::InlayHintParams {
+                work_done_progress_params: Default::default(),
+                text_document: lsp::TextDocumentIdentifier {
+                    uri: lsp::Url::parse("inmemory::///test").unwrap(),
+                },
+                range: Default::default(),
+            };
+            let request: Incoming = helper::request("textDocument/inlayHint", params).unwrap();
+            let response = Response::error(Some(Id::Number(1)), Error::method_not_found());
+            assert_eq!(service.poll_ready(), Poll::Ready(Ok(())));
+            assert_eq!(
+                service.call(request.clone()).await,
+                Ok(Some(Outgoing::Response(response)))
+            );
+        }
+
+        #[tokio::test]
+        async fn resolve() {
+            let (service, _) = LspService::new(|_| Mock);
+            let mut service = Spawn::new(service);
+
+            super::helper::initialize(&mut service).await;
+
+            let params = lsp::InlayHint {
+                position: Default::default(),
+                label: lsp::InlayHintLabel::String("hint".into()),
+                kind: None,
+                text_edits: None,
+                tooltip: None,
+                padding_left: None,
+                padding_right: None,
+            };
+            let request: Incoming = helper::request("inlayHint/resolve", params).unwrap();
+            let response = Response::error(Some(Id::Number(1)), Error::method_not_found());
+            assert_eq!(service.poll_ready(), Poll::Ready(Ok(())));
+            assert_eq!(
+                service.call(request.clone()).await,
+                Ok(Some(Outgoing::Response(response)))
+            );
+        }
+    }
+
+    #[cfg(feature = "proposed")]
+    mod inline_completion {
+        use super::*;
+        use crate::{
+            jsonrpc::{Error, Id, Incoming, Outgoing, Response},
+            proposed::*,
+        };
+        use serde_json::json;
+        use std::task::Poll;
+        use tower_test::mock::Spawn;
+
+        #[tokio::test]
+        async fn inline_completion() {
+            let (service, _) = LspService::new(|_| Mock);
+            let mut service = Spawn::new(service);
+
+            super::helper::initialize(&mut service).await;
+
+            let params = InlineCompletionParams {
+                work_done_progress_params: Default::default(),
+                text_document_position: lsp::TextDocumentPositionParams {
+                    text_document: lsp::TextDocumentIdentifier {
+                        uri: lsp::Url::parse("inmemory::///test").unwrap(),
+                    },
+                    position: Default::default(),
+                },
+                context: InlineCompletionContext {
+                    trigger_kind: InlineCompletionTriggerKind::AUTOMATIC,
+                    selected_completion_info: None,
+                },
+            };
+            let request: Incoming = helper::request("textDocument/inlineCompletion", params).unwrap();
+            let response = Response::error(Some(Id::Number(1)), Error::method_not_found());
+            assert_eq!(service.poll_ready(), Poll::Ready(Ok(())));
+            assert_eq!(
+                service.call(request.clone()).await,
+                Ok(Some(Outgoing::Response(response)))
+            );
+        }
+
+        #[tokio::test]
+        async fn provider() {
+            let (service, _) = LspService::build(|_| Mock)
+                .inline_completion_provider(InlineCompletionOptions::default())
+                .finish();
+            let mut service = Spawn::new(service);
+
+            let request: Incoming = helper::request("initialize", json!({ "capabilities": {} })).unwrap();
+            let capabilities = json!({ "inlineCompletionProvider": {} });
+            let response = Response::ok(Id::Number(1), json!({ "capabilities": capabilities }));
+            assert_eq!(service.poll_ready(), Poll::Ready(Ok(())));
+            assert_eq!(service.call(request).await, Ok(Some(Outgoing::Response(response))));
+        }
+    }
+
     mod text_document {
         use super::*;
         use crate::jsonrpc::{Error, Id, Incoming, Outgoing, Response};
@@ -992,7 +1354,7 @@ mod tests {
 
                 #[tokio::test]
                 async fn delta() {
-                    let (service, _) = LspService::new(|_| Mock::default());
+                    let (service, _) = LspService::new(|_| Mock);
                     let mut service = Spawn::new(service);
 
                     super::helper::initialize(&mut service).await;
@@ -1017,7 +1379,7 @@ mod tests {
 
             #[tokio::test]
             async fn full() {
-                let (service, _) = LspService::new(|_| Mock::default());
+                let (service, _) = LspService::new(|_| Mock);
                 let mut service = Spawn::new(service);
 
                 super::helper::initialize(&mut service).await;
@@ -1040,7 +1402,7 @@ mod tests {
 
             #[tokio::test]
             async fn range() {
-                let (service, _) = LspService::new(|_| Mock::default());
+                let (service, _) = LspService::new(|_| Mock);
                 let mut service = Spawn::new(service);
 
                 super::helper::initialize(&mut service).await;
@@ -1064,7 +1426,7 @@ mod tests {
 
             #[tokio::test]
             async fn refresh() {
-                let (service, _) = LspService::new(|_| Mock::default());
+                let (service, _) = LspService::new(|_| Mock);
                 let mut service = Spawn::new(service);
 
                 super::helper::initialize(&mut service).await;
@@ -1081,7 +1443,7 @@ mod tests {
 
         #[tokio::test]
         async fn code_action() {
-            let (service, _) = LspService::new(|_| Mock::default());
+            let (service, _) = LspService::new(|_| Mock);
             let mut service = Spawn::new(service);
 
             super::helper::initialize(&mut service).await;
@@ -1106,7 +1468,7 @@ mod tests {
 
         #[tokio::test]
         async fn code_lens() {
-            let (service, _) = LspService::new(|_| Mock::default());
+            let (service, _) = LspService::new(|_| Mock);
             let mut service = Spawn::new(service);
 
             super::helper::initialize(&mut service).await;
@@ -1129,7 +1491,7 @@ mod tests {
 
         #[tokio::test]
         async fn code_lens_resolve() {
-            let (service, _) = LspService::new(|_| Mock::default());
+            let (service, _) = LspService::new(|_| Mock);
             let mut service = Spawn::new(service);
 
             super::helper::initialize(&mut service).await;
@@ -1150,7 +1512,7 @@ mod tests {
 
         #[tokio::test]
         async fn color_presentation() {
-            let (service, _) = LspService::new(|_| Mock::default());
+            let (service, _) = LspService::new(|_| Mock);
             let mut service = Spawn::new(service);
 
             super::helper::initialize(&mut service).await;
@@ -1180,7 +1542,7 @@ mod tests {
 
         #[tokio::test]
         async fn completion() {
-            let (service, _) = LspService::new(|_| Mock::default());
+            let (service, _) = LspService::new(|_| Mock);
             let mut service = Spawn::new(service);
 
             super::helper::initialize(&mut service).await;
@@ -1207,7 +1569,7 @@ mod tests {
 
         #[tokio::test]
         async fn declaration() {
-            let (service, _) = LspService::new(|_| Mock::default());
+            let (service, _) = LspService::new(|_| Mock);
             let mut service = Spawn::new(service);
 
             super::helper::initialize(&mut service).await;
@@ -1233,7 +1595,7 @@ mod tests {
 
         #[tokio::test]
         async fn definition() {
-            let (service, _) = LspService::new(|_| Mock::default());
+            let (service, _) = LspService::new(|_| Mock);
             let mut service = Spawn::new(service);
 
             super::helper::initialize(&mut service).await;
@@ -1259,7 +1621,7 @@ mod tests {
 
         #[tokio::test]
         async fn did_change() {
-            let (service, _) = LspService::new(|_| Mock::default());
+            let (service, _) = LspService::new(|_| Mock);
             let mut service = Spawn::new(service);
 
             super::helper::initialize(&mut service).await;
@@ -1278,7 +1640,7 @@ mod tests {
 
         #[tokio::test]
         async fn did_close() {
-            let (service, _) = LspService::new(|_| Mock::default());
+            let (service, _) = LspService::new(|_| Mock);
             let mut service = Spawn::new(service);
 
             super::helper::initialize(&mut service).await;
@@ -1295,7 +1657,7 @@ mod tests {
 
         #[tokio::test]
         async fn did_open() {
-            let (service, _) = LspService::new(|_| Mock::default());
+            let (service, _) = LspService::new(|_| Mock);
             let mut service = Spawn::new(service);
 
             super::helper::initialize(&mut service).await;
@@ -1315,7 +1677,7 @@ mod tests {
 
         #[tokio::test]
         async fn did_save() {
-            let (service, _) = LspService::new(|_| Mock::default());
+            let (service, _) = LspService::new(|_| Mock);
             let mut service = Spawn::new(service);
 
             super::helper::initialize(&mut service).await;
@@ -1333,7 +1695,7 @@ mod tests {
 
         #[tokio::test]
         async fn document_color() {
-            let (service, _) = LspService::new(|_| Mock::default());
+            let (service, _) = LspService::new(|_| Mock);
             let mut service = Spawn::new(service);
 
             super::helper::initialize(&mut service).await;
@@ -1356,7 +1718,7 @@ mod tests {
 
         #[tokio::test]
         async fn document_highlight() {
-            let (service, _) = LspService::new(|_| Mock::default());
+            let (service, _) = LspService::new(|_| Mock);
             let mut service = Spawn::new(service);
 
             super::helper::initialize(&mut service).await;
@@ -1382,7 +1744,7 @@ mod tests {
 
         #[tokio::test]
         async fn document_link() {
-            let (service, _) = LspService::new(|_| Mock::default());
+            let (service, _) = LspService::new(|_| Mock);
             let mut service = Spawn::new(service);
 
             super::helper::initialize(&mut service).await;
@@ -1405,7 +1767,7 @@ mod tests {
 
         #[tokio::test]
         async fn document_link_resolve() {
-            let (service, _) = LspService::new(|_| Mock::default());
+            let (service, _) = LspService::new(|_| Mock);
             let mut service = Spawn::new(service);
 
             super::helper::initialize(&mut service).await;
@@ -1427,7 +1789,7 @@ mod tests {
 
         #[tokio::test]
         async fn document_symbol() {
-            let (service, _) = LspService::new(|_| Mock::default());
+            let (service, _) = LspService::new(|_| Mock);
             let mut service = Spawn::new(service);
 
             super::helper::initialize(&mut service).await;
@@ -1450,7 +1812,7 @@ mod tests {
 
         #[tokio::test]
         async fn folding_range() {
-            let (service, _) = LspService::new(|_| Mock::default());
+            let (service, _) = LspService::new(|_| Mock);
             let mut service = Spawn::new(service);
 
             super::helper::initialize(&mut service).await;
@@ -1473,7 +1835,7 @@ mod tests {
 
         #[tokio::test]
         async fn formatting() {
-            let (service, _) = LspService::new(|_| Mock::default());
+            let (service, _) = LspService::new(|_| Mock);
             let mut service = Spawn::new(service);
 
             super::helper::initialize(&mut service).await;
@@ -1496,7 +1858,7 @@ mod tests {
 
         #[tokio::test]
         async fn hover() {
-            let (service, _) = LspService::new(|_| Mock::default());
+            let (service, _) = LspService::new(|_| Mock);
             let mut service = Spawn::new(service);
 
             super::helper::initialize(&mut service).await;
@@ -1521,7 +1883,7 @@ mod tests {
 
         #[tokio::test]
         async fn implementation() {
-            let (service, _) = LspService::new(|_| Mock::default());
+            let (service, _) = LspService::new(|_| Mock);
             let mut service = Spawn::new(service);
 
             super::helper::initialize(&mut service).await;
@@ -1547,7 +1909,7 @@ mod tests {
 
         #[tokio::test]
         async fn on_type_formatting() {
-            let (service, _) = LspService::new(|_| Mock::default());
+            let (service, _) = LspService::new(|_| Mock);
             let mut service = Spawn::new(service);
 
             super::helper::initialize(&mut service).await;
@@ -1573,7 +1935,7 @@ mod tests {
 
         #[tokio::test]
         async fn prepare_call_hierarchy() {
-            let (service, _) = LspService::new(|_| Mock::default());
+            let (service, _) = LspService::new(|_| Mock);
             let mut service = Spawn::new(service);
 
             super::helper::initialize(&mut service).await;
@@ -1598,7 +1960,7 @@ mod tests {
 
         #[tokio::test]
         async fn prepare_rename() {
-            let (service, _) = LspService::new(|_| Mock::default());
+            let (service, _) = LspService::new(|_| Mock);
             let mut service = Spawn::new(service);
 
             super::helper::initialize(&mut service).await;
@@ -1620,7 +1982,7 @@ mod tests {
 
         #[tokio::test]
         async fn range_formatting() {
-            let (service, _) = LspService::new(|_| Mock::default());
+            let (service, _) = LspService::new(|_| Mock);
             let mut service = Spawn::new(service);
 
             super::helper::initialize(&mut service).await;
@@ -1644,7 +2006,7 @@ mod tests {
 
         #[tokio::test]
         async fn references() {
-            let (service, _) = LspService::new(|_| Mock::default());
+            let (service, _) = LspService::new(|_| Mock);
             let mut service = Spawn::new(service);
 
             super::helper::initialize(&mut service).await;
@@ -1673,7 +2035,7 @@ mod tests {
 
         #[tokio::test]
         async fn rename() {
-            let (service, _) = LspService::new(|_| Mock::default());
+            let (service, _) = LspService::new(|_| Mock);
             let mut service = Spawn::new(service);
 
             super::helper::initialize(&mut service).await;
@@ -1699,7 +2061,7 @@ mod tests {
 
         #[tokio::test]
         async fn request_else() {
-            let (service, _) = LspService::new(|_| Mock::default());
+            let (service, _) = LspService::new(|_| Mock);
             let mut service = Spawn::new(service);
 
             super::helper::initialize(&mut service).await;
@@ -1716,7 +2078,7 @@ mod tests {
 
         #[tokio::test]
         async fn selection_range() {
-            let (service, _) = LspService::new(|_| Mock::default());
+            let (service, _) = LspService::new(|_| Mock);
             let mut service = Spawn::new(service);
 
             super::helper::initialize(&mut service).await;
@@ -1740,7 +2102,7 @@ mod tests {
 
         #[tokio::test]
         async fn signature_help() {
-            let (service, _) = LspService::new(|_| Mock::default());
+            let (service, _) = LspService::new(|_| Mock);
             let mut service = Spawn::new(service);
 
             super::helper::initialize(&mut service).await;
@@ -1766,7 +2128,7 @@ mod tests {
 
         #[tokio::test]
         async fn type_definition() {
-            let (service, _) = LspService::new(|_| Mock::default());
+            let (service, _) = LspService::new(|_| Mock);
             let mut service = Spawn::new(service);
 
             super::helper::initialize(&mut service).await;
@@ -1792,7 +2154,7 @@ mod tests {
 
         #[tokio::test]
         async fn will_save() {
-            let (service, _) = LspService::new(|_| Mock::default());
+            let (service, _) = LspService::new(|_| Mock);
             let mut service = Spawn::new(service);
 
             super::helper::initialize(&mut service).await;
@@ -1810,7 +2172,7 @@ mod tests {
 
         #[tokio::test]
         async fn will_save_wait_until() {
-            let (service, _) = LspService::new(|_| Mock::default());
+            let (service, _) = LspService::new(|_| Mock);
             let mut service = Spawn::new(service);
 
             super::helper::initialize(&mut service).await;
@@ -1840,7 +2202,7 @@ mod tests {
 
         #[tokio::test]
         async fn did_change_configuration() {
-            let (service, _) = LspService::new(|_| Mock::default());
+            let (service, _) = LspService::new(|_| Mock);
             let mut service = Spawn::new(service);
 
             super::helper::initialize(&mut service).await;
@@ -1853,7 +2215,7 @@ mod tests {
 
         #[tokio::test]
         async fn did_change_watched_files() {
-            let (service, _) = LspService::new(|_| Mock::default());
+            let (service, _) = LspService::new(|_| Mock);
             let mut service = Spawn::new(service);
 
             super::helper::initialize(&mut service).await;
@@ -1868,7 +2230,7 @@ mod tests {
 
         #[tokio::test]
         async fn did_change_workspace_folders() {
-            let (service, _) = LspService::new(|_| Mock::default());
+            let (service, _) = LspService::new(|_| Mock);
             let mut service = Spawn::new(service);
 
             super::helper::initialize(&mut service).await;
@@ -1881,7 +2243,7 @@ mod tests {
 
         #[tokio::test]
         async fn execute_command() {
-            let (service, _) = LspService::new(|_| Mock::default());
+            let (service, _) = LspService::new(|_| Mock);
             let mut service = Spawn::new(service);
 
             super::helper::initialize(&mut service).await;
@@ -1898,7 +2260,7 @@ mod tests {
 
         #[tokio::test]
         async fn symbol() {
-            let (service, _) = LspService::new(|_| Mock::default());
+            let (service, _) = LspService::new(|_| Mock);
             let mut service = Spawn::new(service);
 
             super::helper::initialize(&mut service).await;
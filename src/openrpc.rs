@@ -0,0 +1,229 @@
+//! Machine-readable description of the RPC surface of a server in the OpenRPC format.
+
+use crate::reflect::{methods, MethodKind};
+use schemars::{
+    gen::{SchemaGenerator, SchemaSettings},
+    JsonSchema,
+};
+use serde_json::{json, Map, Value};
+use std::fmt::{self, Debug, Formatter};
+
+/// The version of the OpenRPC specification the description follows.
+const OPENRPC_VERSION: &str = "1.2.6";
+
+/// The location of the schemas of custom parameters and results within the description.
+const SCHEMAS_PATH: &str = "#/components/schemas/";
+
+/// Builder for an [OpenRPC] description of the methods a server supports, from which typed client
+/// bindings can be generated.
+///
+/// The description lists every method the dispatcher knows about, as returned by
+/// [`methods`](crate::methods), and the custom methods added with
+/// [`request`](RpcDescription::request) and [`notification`](RpcDescription::notification).
+/// The parameters and results of custom methods are described by JSON schemas derived with
+/// `schemars`, so they have to implement [`JsonSchema`]. Since `lsp-types` does not provide
+/// schemas, the parameters and results of standard methods are left open and link to their
+/// section of the specification instead. Notifications are described as methods without a result,
+/// as is the convention in OpenRPC. In either case, the parameters are passed as a single object,
+/// described by a content descriptor named `params`.
+///
+/// This type is only available with the `openrpc` crate feature enabled.
+///
+/// # Example
+///
+/// ```rust
+/// # use lspower::RpcDescription;
+/// # use serde::{Deserialize, Serialize};
+/// #[derive(Deserialize, Serialize, schemars::JsonSchema)]
+/// struct SyntaxTreeParams {
+///     uri: String,
+/// }
+///
+/// enum SyntaxTree {}
+///
+/// impl lspower::lsp::request::Request for SyntaxTree {
+///     type Params = SyntaxTreeParams;
+///     type Result = String;
+///     const METHOD: &'static str = "custom/syntaxTree";
+/// }
+///
+/// let description = RpcDescription::new("my-language-server", "1.0.0").request::<SyntaxTree>().finish();
+/// let methods = description["methods"].as_array().unwrap();
+/// assert!(methods.iter().any(|method| method["name"] == "custom/syntaxTree"));
+/// ```
+///
+/// [OpenRPC]: https://spec.open-rpc.org/
+pub struct RpcDescription {
+    title: String,
+    version: String,
+    custom: Vec<Value>,
+    generator: SchemaGenerator,
+}
+
+impl RpcDescription {
+    /// Creates a description of the server with the given title and version, initially listing
+    /// the standard methods.
+    pub fn new<T, V>(title: T, version: V) -> Self
+    where
+        T: Into<String>,
+        V: Into<String>,
+    {
+        let generator = SchemaSettings::draft07()
+            .with(|settings| settings.definitions_path = SCHEMAS_PATH.into())
+            .into_generator();
+        RpcDescription {
+            title: title.into(),
+            version: version.into(),
+            custom: Vec::new(),
+            generator,
+        }
+    }
+
+    /// Adds the custom request `R`.
+    ///
+    /// A custom request with the name of a standard method replaces the description of the latter.
+    pub fn request<R>(mut self) -> Self
+    where
+        R: lsp::request::Request,
+        R::Params: JsonSchema,
+        R::Result: JsonSchema,
+    {
+        let params = self.generator.subschema_for::<R::Params>();
+        let result = self.generator.subschema_for::<R::Result>();
+        self.custom.push(json!({
+            "name": R::METHOD,
+            "params": [{ "name": "params", "required": true, "schema": params }],
+            "result": { "name": "result", "schema": result },
+        }));
+        self
+    }
+
+    /// Adds the custom notification `N`.
+    ///
+    /// A custom notification with the name of a standard method replaces the description of the
+    /// latter.
+    pub fn notification<N>(mut self) -> Self
+    where
+        N: lsp::notification::Notification,
+        N::Params: JsonSchema,
+    {
+        let params = self.generator.subschema_for::<N::Params>();
+        self.custom.push(json!({
+            "name": N::METHOD,
+            "params": [{ "name": "params", "required": true, "schema": params }],
+        }));
+        self
+    }
+
+    /// Returns the OpenRPC document describing the server.
+    pub fn finish(mut self) -> Value {
+        let custom = &self.custom;
+        let is_custom = |name: &str| custom.iter().any(|method| method["name"] == name);
+        let standard = methods()
+            .iter()
+            .filter(|method| !is_custom(method.name()))
+            .map(|method| standard_method(method.name(), method.kind()));
+        let methods: Vec<_> = standard.chain(self.custom.iter().cloned()).collect();
+
+        let schemas: Map<String, Value> = self
+            .generator
+            .take_definitions()
+            .into_iter()
+            .map(|(name, schema)| (name, serde_json::to_value(schema).unwrap()))
+            .collect();
+
+        json!({
+            "openrpc": OPENRPC_VERSION,
+            "info": { "title": self.title, "version": self.version },
+            "methods": methods,
+            "components": { "schemas": schemas },
+        })
+    }
+}
+
+impl Debug for RpcDescription {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct(stringify!(RpcDescription))
+            .field("title", &self.title)
+            .field("version", &self.version)
+            .field("custom", &self.custom.len())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Describes a standard method, whose parameters and result are specified by the LSP.
+fn standard_method(name: &str, kind: MethodKind) -> Value {
+    let anchor = name.trim_start_matches("$/").replace('/', "_");
+    let url = format!("https://microsoft.github.io/language-server-protocol/specification#{}", anchor);
+    let mut method = json!({
+        "name": name,
+        "params": [{ "name": "params", "schema": {} }],
+        "externalDocs": { "url": url },
+    });
+    if kind == MethodKind::Request {
+        method["result"] = json!({ "name": "result", "schema": {} });
+    }
+    method
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Deserialize, JsonSchema, Serialize)]
+    struct ReloadParams {
+        force: bool,
+    }
+
+    enum Reload {}
+
+    impl lsp::notification::Notification for Reload {
+        type Params = ReloadParams;
+
+        const METHOD: &'static str = "custom/reload";
+    }
+
+    enum Hover {}
+
+    impl lsp::request::Request for Hover {
+        type Params = ReloadParams;
+        type Result = Option<String>;
+
+        const METHOD: &'static str = "textDocument/hover";
+    }
+
+    fn find<'a>(description: &'a Value, name: &str) -> Vec<&'a Value> {
+        let methods = description["methods"].as_array().unwrap();
+        methods.iter().filter(|method| method["name"] == name).collect()
+    }
+
+    #[test]
+    fn finish() {
+        let description = RpcDescription::new("server", "0.1.0")
+            .notification::<Reload>()
+            .request::<Hover>()
+            .finish();
+        assert_eq!(description["openrpc"], OPENRPC_VERSION);
+        assert_eq!(description["info"], json!({ "title": "server", "version": "0.1.0" }));
+
+        let shutdown = find(&description, "shutdown");
+        assert_eq!(shutdown.len(), 1);
+        assert_eq!(shutdown[0]["result"]["name"], "result");
+        let cancel = &find(&description, "$/cancelRequest")[0];
+        assert_eq!(cancel.get("result"), None);
+        let url = "https://microsoft.github.io/language-server-protocol/specification#cancelRequest";
+        assert_eq!(cancel["externalDocs"]["url"], url);
+
+        let reload = &find(&description, "custom/reload")[0];
+        let schema = json!({ "$ref": "#/components/schemas/ReloadParams" });
+        assert_eq!(reload["params"], json!([{ "name": "params", "required": true, "schema": schema }]));
+        assert_eq!(reload.get("result"), None);
+        let params = &description["components"]["schemas"]["ReloadParams"];
+        assert_eq!(params["properties"]["force"]["type"], "boolean");
+
+        let hover = find(&description, "textDocument/hover");
+        assert_eq!(hover.len(), 1);
+        assert_eq!(hover[0]["result"]["schema"]["type"], json!(["string", "null"]));
+    }
+}
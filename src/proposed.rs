@@ -0,0 +1,201 @@
+//! Types of proposed additions to the specification which `lsp-types` does not provide yet.
+//!
+//! This module is only available with the `proposed` crate feature enabled. Its types follow the
+//! conventions of `lsp-types` and will be replaced by their counterparts once they are available
+//! there.
+
+use serde::{Deserialize, Serialize};
+
+/// Client capabilities specific to inline completions.
+///
+/// @since 3.18.0 - proposed state
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InlineCompletionClientCapabilities {
+    /// Whether implementation supports dynamic registration for inline completion providers.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dynamic_registration: Option<bool>,
+}
+
+/// Inline completion options used during static registration, advertised through
+/// [`LspServiceBuilder::inline_completion_provider`].
+///
+/// @since 3.18.0 - proposed state
+///
+/// [`LspServiceBuilder::inline_completion_provider`]: crate::LspServiceBuilder::inline_completion_provider
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InlineCompletionOptions {
+    /// Whether the server reports progress while computing inline completions.
+    #[serde(flatten)]
+    pub work_done_progress_options: lsp::WorkDoneProgressOptions,
+}
+
+/// A parameter literal used in inline completion requests.
+///
+/// @since 3.18.0 - proposed state
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InlineCompletionParams {
+    /// An optional token to report progress through.
+    #[serde(flatten)]
+    pub work_done_progress_params: lsp::WorkDoneProgressParams,
+
+    /// The text document and the position inside it.
+    #[serde(flatten)]
+    pub text_document_position: lsp::TextDocumentPositionParams,
+
+    /// Additional information about the context in which inline completions were requested.
+    pub context: InlineCompletionContext,
+}
+
+/// Provides information about the context in which an inline completion was requested.
+///
+/// @since 3.18.0 - proposed state
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InlineCompletionContext {
+    /// Describes how the inline completion was triggered.
+    pub trigger_kind: InlineCompletionTriggerKind,
+
+    /// Provides information about the currently selected item in the autocomplete widget if it is
+    /// visible.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub selected_completion_info: Option<SelectedCompletionInfo>,
+}
+
+/// Describes how an inline completion request was triggered.
+///
+/// @since 3.18.0 - proposed state
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, Deserialize, Serialize)]
+#[serde(transparent)]
+pub struct InlineCompletionTriggerKind(i32);
+
+impl InlineCompletionTriggerKind {
+    /// Completion was triggered explicitly by a user gesture.
+    pub const INVOKED: InlineCompletionTriggerKind = InlineCompletionTriggerKind(1);
+
+    /// Completion was triggered automatically while editing.
+    pub const AUTOMATIC: InlineCompletionTriggerKind = InlineCompletionTriggerKind(2);
+}
+
+/// Describes the currently selected completion item.
+///
+/// @since 3.18.0 - proposed state
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SelectedCompletionInfo {
+    /// The range that will be replaced if this completion item is accepted.
+    pub range: lsp::Range,
+
+    /// The text the range will be replaced with if this completion is accepted.
+    pub text: String,
+}
+
+/// An inline completion item represents a text snippet that is proposed inline to complete text
+/// that is being typed.
+///
+/// @since 3.18.0 - proposed state
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InlineCompletionItem {
+    /// The text to replace the range with. Must be set.
+    pub insert_text: InlineCompletionText,
+
+    /// A text that is used to decide if this inline completion should be shown. When `None`, the
+    /// `insert_text` is used.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filter_text: Option<String>,
+
+    /// The range to replace. Must begin and end on the same line.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub range: Option<lsp::Range>,
+
+    /// An optional command that is executed *after* inserting this completion.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub command: Option<lsp::Command>,
+}
+
+/// The text inserted by an [`InlineCompletionItem`].
+///
+/// @since 3.18.0 - proposed state
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum InlineCompletionText {
+    /// Plain text, inserted as is.
+    String(String),
+    /// A snippet, which may contain tab stops and placeholders.
+    Snippet(StringValue),
+}
+
+/// A string value used as a snippet, where `$1`, `$2` and `${3:foo}` denote tab stops and
+/// placeholders, `$0` the final cursor position.
+///
+/// @since 3.18.0 - proposed state
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "kind", rename = "snippet")]
+pub struct StringValue {
+    /// The snippet string.
+    pub value: String,
+}
+
+/// Represents a collection of [`InlineCompletionItem`]s to be presented in the editor.
+///
+/// @since 3.18.0 - proposed state
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct InlineCompletionList {
+    /// The inline completion items.
+    pub items: Vec<InlineCompletionItem>,
+}
+
+/// The result of an inline completion request.
+///
+/// @since 3.18.0 - proposed state
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(untagged)]
+pub enum InlineCompletionResponse {
+    /// The inline completion items.
+    Array(Vec<InlineCompletionItem>),
+    /// The inline completion items, as a list.
+    List(InlineCompletionList),
+}
+
+/// The inline completion request is sent from the client to the server to compute inline
+/// completions for a given text document either explicitly by a user gesture or implicitly when
+/// typing.
+///
+/// @since 3.18.0 - proposed state
+#[derive(Debug)]
+pub enum InlineCompletionRequest {}
+
+impl lsp::request::Request for InlineCompletionRequest {
+    type Params = InlineCompletionParams;
+    type Result = Option<InlineCompletionResponse>;
+
+    const METHOD: &'static str = "textDocument/inlineCompletion";
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn inline_completion_item() {
+        let item = InlineCompletionItem {
+            insert_text: InlineCompletionText::Snippet(StringValue {
+                value: "fn ${1:name}() {}".into(),
+            }),
+            filter_text: None,
+            range: None,
+            command: None,
+        };
+        let value = json!({ "insertText": { "kind": "snippet", "value": "fn ${1:name}() {}" } });
+        assert_eq!(serde_json::to_value(&item).unwrap(), value);
+        assert_eq!(serde_json::from_value::<InlineCompletionItem>(value).unwrap(), item);
+
+        let value = json!({ "insertText": "fn main() {}" });
+        let item = serde_json::from_value::<InlineCompletionItem>(value).unwrap();
+        assert_eq!(item.insert_text, InlineCompletionText::String("fn main() {}".into()));
+    }
+}
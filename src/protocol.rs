@@ -0,0 +1,342 @@
+//! Negotiation of the protocol features shared by the client and the server.
+
+use serde_json::Value;
+use std::fmt::{self, Display, Formatter};
+
+/// Version of the LSP specification a client conforms to.
+///
+/// Clients do not announce the version of the specification they implement, so it is inferred from
+/// the capabilities they declare. A client declaring any capability introduced in a version is
+/// assumed to conform to that version.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[non_exhaustive]
+pub enum SpecLevel {
+    /// Version 3.14 or any earlier version of the specification.
+    V3_14,
+    /// Version 3.15, which introduced work done progress and selection ranges.
+    V3_15,
+    /// Version 3.16, which introduced semantic tokens, call hierarchies and file operations.
+    V3_16,
+    /// Version 3.17, which introduced position encodings, pull diagnostics and inlay hints.
+    V3_17,
+}
+
+impl SpecLevel {
+    /// The most recent version of the specification supported by the current build.
+    ///
+    /// This is [`SpecLevel::V3_17`] if the `proposed` crate feature is enabled and
+    /// [`SpecLevel::V3_16`] otherwise.
+    pub const SUPPORTED: SpecLevel = if cfg!(feature = "proposed") {
+        SpecLevel::V3_17
+    } else {
+        SpecLevel::V3_16
+    };
+
+    /// Returns the version number, e.g. `"3.16"`.
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            SpecLevel::V3_14 => "3.14",
+            SpecLevel::V3_15 => "3.15",
+            SpecLevel::V3_16 => "3.16",
+            SpecLevel::V3_17 => "3.17",
+        }
+    }
+}
+
+impl Display for SpecLevel {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Paths of capabilities introduced in each version of the specification, newest version first.
+const LEVEL_MARKERS: &[(SpecLevel, &[&[&str]])] = &[
+    (SpecLevel::V3_17, &[
+        &["capabilities", "general", "positionEncodings"],
+        &["capabilities", "general", "staleRequestSupport"],
+        &["capabilities", "textDocument", "diagnostic"],
+        &["capabilities", "textDocument", "inlayHint"],
+        &["capabilities", "textDocument", "inlineValue"],
+        &["capabilities", "textDocument", "typeHierarchy"],
+        &["capabilities", "workspace", "diagnostics"],
+        &["capabilities", "notebookDocument"],
+    ]),
+    (SpecLevel::V3_16, &[
+        &["capabilities", "general", "regularExpressions"],
+        &["capabilities", "general", "markdown"],
+        &["capabilities", "textDocument", "callHierarchy"],
+        &["capabilities", "textDocument", "semanticTokens"],
+        &["capabilities", "textDocument", "linkedEditingRange"],
+        &["capabilities", "textDocument", "moniker"],
+        &["capabilities", "workspace", "fileOperations"],
+        &["capabilities", "workspace", "semanticTokens"],
+        &["capabilities", "workspace", "codeLens"],
+        &["capabilities", "window", "showDocument"],
+        &["locale"],
+    ]),
+    (SpecLevel::V3_15, &[
+        &["capabilities", "window", "workDoneProgress"],
+        &["capabilities", "textDocument", "selectionRange"],
+        &["clientInfo"],
+    ]),
+];
+
+/// Encoding of the `character` offsets in [`Position`]s.
+///
+/// [`Position`]: lsp::Position
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum PositionEncoding {
+    /// Offsets count UTF-8 code units, i.e. bytes.
+    Utf8,
+    /// Offsets count UTF-16 code units. This is the default which every client must support.
+    Utf16,
+    /// Offsets count UTF-32 code units, i.e. Unicode scalar values.
+    Utf32,
+}
+
+impl PositionEncoding {
+    /// Returns the name of the encoding used in the protocol, e.g. `"utf-16"`.
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            PositionEncoding::Utf8 => "utf-8",
+            PositionEncoding::Utf16 => "utf-16",
+            PositionEncoding::Utf32 => "utf-32",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "utf-8" | "utf8" => Some(PositionEncoding::Utf8),
+            "utf-16" | "utf16" => Some(PositionEncoding::Utf16),
+            "utf-32" | "utf32" => Some(PositionEncoding::Utf32),
+            _ => None,
+        }
+    }
+}
+
+impl Display for PositionEncoding {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// How the server reports the progress of long running operations.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum ProgressModel {
+    /// The client does not support work done progress; progress should be reported through log or
+    /// show message notifications, if at all.
+    Unsupported,
+    /// The client supports server initiated work done progress through
+    /// `window/workDoneProgress/create` and `$/progress`.
+    WorkDone,
+}
+
+/// How the server delivers diagnostics to the client.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum DiagnosticModel {
+    /// Diagnostics are pushed with `textDocument/publishDiagnostics` notifications.
+    Push,
+    /// The client pulls diagnostics with `textDocument/diagnostic` requests.
+    Pull,
+}
+
+/// Summary of the protocol features negotiated with the client during `initialize`.
+///
+/// Instead of checking individual client capabilities throughout the server, handlers can branch on
+/// the effective specification level, the position encodings and the progress and diagnostic models
+/// derived here. The negotiated protocol is also available from [`Client::negotiated_protocol`]
+/// once the `initialize` request was received.
+///
+/// Position encodings are read from `general.positionEncodings`, falling back to the
+/// `offsetEncoding` extension supported by some clients. Since the [`InitializeParams`] of
+/// `lsp-types` do not retain capabilities of newer versions of the specification, such as
+/// `general.positionEncodings` and `textDocument.diagnostic`, servers relying on these should
+/// analyze the raw parameters with [`NegotiatedProtocol::from_json`]. The `offsetEncoding`
+/// extension is only retained with the `proposed` crate feature enabled.
+///
+/// [`Client::negotiated_protocol`]: crate::Client::negotiated_protocol
+/// [`InitializeParams`]: lsp::InitializeParams
+#[derive(Clone, Debug, PartialEq)]
+pub struct NegotiatedProtocol {
+    client_level: SpecLevel,
+    client_info: Option<lsp::ClientInfo>,
+    position_encodings: Vec<PositionEncoding>,
+    work_done_progress: bool,
+    pull_diagnostics: bool,
+}
+
+impl NegotiatedProtocol {
+    /// Analyzes the parameters of an `initialize` request.
+    pub fn new(params: &lsp::InitializeParams) -> Self {
+        NegotiatedProtocol::from_json(&crate::jsonrpc::lsp_value(params))
+    }
+
+    /// Analyzes the raw JSON parameters of an `initialize` request.
+    pub fn from_json(params: &Value) -> Self {
+        let capabilities = &params["capabilities"];
+
+        let client_level = LEVEL_MARKERS
+            .iter()
+            .find(|(_, paths)| paths.iter().any(|path| !lookup(params, path).is_null()))
+            .map_or(SpecLevel::V3_14, |(level, _)| *level);
+
+        let client_info = serde_json::from_value(params["clientInfo"].clone()).ok();
+
+        let names = match &capabilities["general"]["positionEncodings"] {
+            Value::Array(names) => names,
+            _ => capabilities["offsetEncoding"].as_array().map_or(&[][..], Vec::as_slice),
+        };
+        let mut position_encodings = Vec::new();
+        for encoding in names
+            .iter()
+            .filter_map(Value::as_str)
+            .filter_map(PositionEncoding::from_name)
+        {
+            if !position_encodings.contains(&encoding) {
+                position_encodings.push(encoding);
+            }
+        }
+        if !position_encodings.contains(&PositionEncoding::Utf16) {
+            position_encodings.push(PositionEncoding::Utf16);
+        }
+
+        NegotiatedProtocol {
+            client_level,
+            client_info,
+            position_encodings,
+            work_done_progress: capabilities["window"]["workDoneProgress"].as_bool().unwrap_or(false),
+            pull_diagnostics: capabilities["textDocument"]["diagnostic"].is_object(),
+        }
+    }
+
+    /// Returns the version of the specification the client conforms to, as inferred from its
+    /// capabilities.
+    pub fn client_level(&self) -> SpecLevel {
+        self.client_level
+    }
+
+    /// Returns the version of the specification supported by both the client and this build.
+    pub fn spec_level(&self) -> SpecLevel {
+        self.client_level.min(SpecLevel::SUPPORTED)
+    }
+
+    /// Returns the name and version the client identified itself with, if any.
+    pub fn client_info(&self) -> Option<&lsp::ClientInfo> {
+        self.client_info.as_ref()
+    }
+
+    /// Returns the position encodings supported by the client, in order of its preference.
+    ///
+    /// This always contains [`PositionEncoding::Utf16`], which every client must support.
+    pub fn position_encodings(&self) -> &[PositionEncoding] {
+        &self.position_encodings
+    }
+
+    /// Selects the first encoding from `preferred` which is supported by the client, falling back
+    /// to [`PositionEncoding::Utf16`].
+    pub fn select_position_encoding(&self, preferred: &[PositionEncoding]) -> PositionEncoding {
+        preferred
+            .iter()
+            .copied()
+            .find(|encoding| self.position_encodings.contains(encoding))
+            .unwrap_or(PositionEncoding::Utf16)
+    }
+
+    /// Returns how the server should report the progress of long running operations.
+    pub fn progress_model(&self) -> ProgressModel {
+        if self.work_done_progress {
+            ProgressModel::WorkDone
+        } else {
+            ProgressModel::Unsupported
+        }
+    }
+
+    /// Returns how the server should deliver diagnostics.
+    ///
+    /// Pull diagnostics are only selected if both the client and this build support version 3.17
+    /// of the specification.
+    pub fn diagnostic_model(&self) -> DiagnosticModel {
+        if self.pull_diagnostics && self.spec_level() >= SpecLevel::V3_17 {
+            DiagnosticModel::Pull
+        } else {
+            DiagnosticModel::Push
+        }
+    }
+}
+
+fn lookup<'a>(value: &'a Value, path: &[&str]) -> &'a Value {
+    path.iter().fold(value, |value, key| &value[key])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn legacy_client() {
+        let protocol = NegotiatedProtocol::from_json(&json!({ "capabilities": {} }));
+        assert_eq!(protocol.client_level(), SpecLevel::V3_14);
+        assert_eq!(protocol.client_info(), None);
+        assert_eq!(protocol.position_encodings(), &[PositionEncoding::Utf16]);
+        assert_eq!(
+            protocol.select_position_encoding(&[PositionEncoding::Utf8]),
+            PositionEncoding::Utf16
+        );
+        assert_eq!(protocol.progress_model(), ProgressModel::Unsupported);
+        assert_eq!(protocol.diagnostic_model(), DiagnosticModel::Push);
+    }
+
+    #[test]
+    fn modern_client() {
+        let protocol = NegotiatedProtocol::from_json(&json!({
+            "clientInfo": { "name": "editor", "version": "1.0" },
+            "capabilities": {
+                "general": { "positionEncodings": ["utf-32", "utf-8"] },
+                "textDocument": { "diagnostic": {} },
+                "window": { "workDoneProgress": true },
+            },
+        }));
+        assert_eq!(protocol.client_level(), SpecLevel::V3_17);
+        assert_eq!(protocol.spec_level(), SpecLevel::SUPPORTED);
+        assert_eq!(protocol.client_info().unwrap().name, "editor");
+        assert_eq!(protocol.position_encodings(), &[
+            PositionEncoding::Utf32,
+            PositionEncoding::Utf8,
+            PositionEncoding::Utf16
+        ]);
+        assert_eq!(
+            protocol.select_position_encoding(&[PositionEncoding::Utf8, PositionEncoding::Utf32]),
+            PositionEncoding::Utf8
+        );
+        assert_eq!(protocol.progress_model(), ProgressModel::WorkDone);
+        let expected = if cfg!(feature = "proposed") {
+            DiagnosticModel::Pull
+        } else {
+            DiagnosticModel::Push
+        };
+        assert_eq!(protocol.diagnostic_model(), expected);
+    }
+
+    #[test]
+    fn typed_params() {
+        let params: lsp::InitializeParams = serde_json::from_value(json!({
+            "processId": null,
+            "rootUri": null,
+            "capabilities": {
+                "offsetEncoding": ["utf-8", "utf-16"],
+                "textDocument": { "selectionRange": {} },
+            },
+        }))
+        .unwrap();
+        let protocol = NegotiatedProtocol::new(&params);
+        assert_eq!(protocol.client_level(), SpecLevel::V3_15);
+        // The `offsetEncoding` extension is only retained by `lsp-types` with the `proposed` feature.
+        let expected: &[_] = if cfg!(feature = "proposed") {
+            &[PositionEncoding::Utf8, PositionEncoding::Utf16]
+        } else {
+            &[PositionEncoding::Utf16]
+        };
+        assert_eq!(protocol.position_encodings(), expected);
+    }
+}
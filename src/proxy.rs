@@ -0,0 +1,744 @@
+//! Typed calls into a language server service.
+
+use crate::jsonrpc::{
+    ClientRequestHandlers,
+    Error,
+    Incoming,
+    Outgoing,
+    PartialResultStream,
+    PartialResults,
+    RequestIdMap,
+    Response,
+    Result,
+    ServerRequest,
+};
+use futures::{future, lock::Mutex};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{json, Value};
+use std::{
+    fmt::{self, Debug, Display, Formatter},
+    future::Future,
+    sync::atomic::{AtomicU64, Ordering},
+};
+use tower_service::Service;
+
+/// The method of the notification canceling a request.
+const CANCEL_REQUEST: &str = "$/cancelRequest";
+
+/// Sends typed requests and notifications to a language server service.
+///
+/// The proxy wraps any [`Service`] which handles JSON-RPC messages, such as an [`LspService`], and
+/// provides one method per method of the [`LanguageServer`] trait, named and typed after it. The
+/// methods are generated from the same declarations as the dispatcher of the service, so they stay
+/// in sync with the trait. This is useful for proxies forwarding messages to another server, and
+/// for driving a server from tests. Messages of an actual client are passed through with
+/// [`forward`], which also keeps cancellations working in both directions.
+///
+/// Requests and notifications fail with the error returned by the server, or with an "internal
+/// error" (`-32603`) if the service itself failed, e.g. because the server has already exited.
+///
+/// # Example
+///
+/// ```rust
+/// # use lspower::{jsonrpc::Result, lsp::*, LanguageServer, LspService, ServerProxy};
+/// # #[derive(Debug)]
+/// # struct Backend;
+/// # #[lspower::async_trait]
+/// # impl LanguageServer for Backend {
+/// #     async fn initialize(&self, _: InitializeParams) -> Result<InitializeResult> {
+/// #         Ok(InitializeResult::default())
+/// #     }
+/// #     async fn shutdown(&self) -> Result<()> {
+/// #         Ok(())
+/// #     }
+/// # }
+/// # futures::executor::block_on(async {
+/// let (service, _) = LspService::new(|_| Backend);
+/// let server = ServerProxy::new(service);
+/// # let params = serde_json::from_value(serde_json::json!({ "capabilities": {} })).unwrap();
+/// let result = server.initialize(params).await?;
+/// server.initialized(InitializedParams {}).await?;
+/// server.shutdown().await?;
+/// server.exit().await?;
+/// # Result::Ok(())
+/// # }).unwrap();
+/// ```
+///
+/// [`LspService`]: crate::LspService
+/// [`LanguageServer`]: crate::LanguageServer
+/// [`forward`]: ServerProxy::forward
+pub struct ServerProxy<S> {
+    service: Mutex<S>,
+    next_id: AtomicU64,
+    partial_results: PartialResults,
+    request_handlers: ClientRequestHandlers,
+    client_requests: RequestIdMap,
+    server_requests: RequestIdMap,
+}
+
+impl<S> ServerProxy<S> {
+    /// Creates a new proxy sending messages to the given service.
+    pub fn new(service: S) -> Self {
+        ServerProxy {
+            service: Mutex::new(service),
+            next_id: AtomicU64::new(0),
+            partial_results: PartialResults::new(),
+            request_handlers: ClientRequestHandlers::new(),
+            client_requests: RequestIdMap::with_namespace("proxy"),
+            server_requests: RequestIdMap::with_namespace("proxy"),
+        }
+    }
+
+    /// Sets the handlers answering the requests of the server which are passed to [`answer`].
+    ///
+    /// [`answer`]: ServerProxy::answer
+    pub fn with_request_handlers(mut self, handlers: ClientRequestHandlers) -> Self {
+        self.request_handlers = handlers;
+        self
+    }
+
+    /// Returns the router of the partial results of the requests sent with
+    /// [`send_request_with_partial_results`], through which the messages produced by the server
+    /// must be passed.
+    ///
+    /// [`send_request_with_partial_results`]: ServerProxy::send_request_with_partial_results
+    pub fn partial_results(&self) -> &PartialResults {
+        &self.partial_results
+    }
+
+    /// Returns the wrapped service.
+    pub fn into_inner(self) -> S {
+        self.service.into_inner()
+    }
+
+    /// Prepares a message produced by the server for forwarding to the actual client.
+    ///
+    /// Requests of the server are forwarded with IDs of the form `proxy/<number>`, so that the
+    /// responses of the client passed to [`forward`] reach them, and `$/cancelRequest`
+    /// notifications of the server are rewritten to cancel the forwarded request. Cancellations of
+    /// requests which are not pending are dropped by returning `None`.
+    ///
+    /// [`forward`]: ServerProxy::forward
+    pub fn forward_outgoing(&self, message: Outgoing) -> Option<Outgoing> {
+        let mut request = match message {
+            Outgoing::Request(request) => request,
+            Outgoing::Response(_) => return Some(message),
+        };
+        match request.id() {
+            Some(origin) => {
+                let target = self.server_requests.forward(origin.clone());
+                request.set_id(target);
+            },
+            None if request.method() == CANCEL_REQUEST => {
+                let params = self.server_requests.forward_notification(CANCEL_REQUEST, request.params().clone())?;
+                request.set_params(params);
+            },
+            None => {},
+        }
+        Some(Outgoing::Request(request))
+    }
+}
+
+impl<S> ServerProxy<S>
+where
+    S: Service<Incoming, Response = Option<Outgoing>>,
+    S::Error: Display,
+{
+    /// Sends the [`exit`] notification, which asks the server to exit its process.
+    ///
+    /// [`exit`]: https://microsoft.github.io/language-server-protocol/specification#exit
+    pub async fn exit(&self) -> Result<()> {
+        self.notify_without_params("exit").await
+    }
+
+    /// Answers a request of the server with its handler set with [`with_request_handlers`],
+    /// sending the response back to the server, or returns the message if it is not answered.
+    ///
+    /// The messages produced by the server are passed through this method before forwarding them
+    /// to the actual client, if any. Requests the server sends on its own, e.g. for
+    /// `workspace/configuration` while handling a request of the proxy, are only answered once
+    /// their messages are passed through, so this must happen concurrently with sending requests.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use futures::StreamExt;
+    /// # use lspower::{jsonrpc::{ClientRequestHandlers, Result}, lsp::*, LanguageServer, LspService, ServerProxy};
+    /// # use lspower::lsp::request::WorkspaceConfiguration;
+    /// # #[derive(Debug)]
+    /// # struct Backend;
+    /// # #[lspower::async_trait]
+    /// # impl LanguageServer for Backend {
+    /// #     async fn initialize(&self, _: InitializeParams) -> Result<InitializeResult> {
+    /// #         Ok(InitializeResult::default())
+    /// #     }
+    /// #     async fn shutdown(&self) -> Result<()> {
+    /// #         Ok(())
+    /// #     }
+    /// # }
+    /// # async fn example() {
+    /// let handlers = ClientRequestHandlers::new()
+    ///     .handler::<WorkspaceConfiguration, _, _>(|params| async move {
+    ///         Ok(vec![Default::default(); params.items.len()])
+    ///     });
+    /// let (service, messages) = LspService::new(|_| Backend);
+    /// let server = ServerProxy::new(service).with_request_handlers(handlers);
+    ///
+    /// let unanswered = messages.filter_map(|message| server.answer(message));
+    /// # }
+    /// ```
+    ///
+    /// [`with_request_handlers`]: ServerProxy::with_request_handlers
+    pub async fn answer(&self, message: Outgoing) -> Option<Outgoing> {
+        let request = match &message {
+            Outgoing::Request(request) => request,
+            Outgoing::Response(_) => return Some(message),
+        };
+        let response = match self.request_handlers.answer(request).await {
+            Some(response) => response,
+            None => return Some(message),
+        };
+
+        if let Err(error) = self.send(Incoming::Response(response)).await {
+            log::error!("failed to answer {:?} request: {}", request.method(), error);
+        }
+        None
+    }
+
+    /// Forwards a message of the actual client to the server, returning the response to pass back
+    /// to the client, if any.
+    ///
+    /// Requests of the client are forwarded with IDs of the form `proxy/<number>`, which cannot
+    /// collide with the requests sent through the typed methods of the proxy, and their responses
+    /// carry the ID of the client again. `$/cancelRequest` notifications of the client are
+    /// rewritten to cancel the forwarded request, and responses of the client are passed to the
+    /// requests of the server prepared with [`forward_outgoing`].
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use futures::{future, StreamExt};
+    /// # use lspower::{jsonrpc::{Incoming, Result}, lsp::*, LanguageServer, LspService, ServerProxy};
+    /// # #[derive(Debug)]
+    /// # struct Backend;
+    /// # #[lspower::async_trait]
+    /// # impl LanguageServer for Backend {
+    /// #     async fn initialize(&self, _: InitializeParams) -> Result<InitializeResult> {
+    /// #         Ok(InitializeResult::default())
+    /// #     }
+    /// #     async fn shutdown(&self) -> Result<()> {
+    /// #         Ok(())
+    /// #     }
+    /// # }
+    /// # async fn example(message: Incoming) {
+    /// let (service, messages) = LspService::new(|_| Backend);
+    /// let server = ServerProxy::new(service);
+    /// // Passed on to the client.
+    /// let outgoing = messages.filter_map(|message| future::ready(server.forward_outgoing(message)));
+    ///
+    /// // A message read from the client.
+    /// let response = server.forward(message).await;
+    /// # }
+    /// ```
+    ///
+    /// [`forward_outgoing`]: ServerProxy::forward_outgoing
+    pub async fn forward(&self, message: Incoming) -> Option<Outgoing> {
+        let mut request = match message {
+            Incoming::Request(request) => request,
+            Incoming::Response(response) => {
+                let response = self.server_requests.complete(response)?;
+                if let Err(error) = self.send(Incoming::Response(response)).await {
+                    log::error!("failed to forward response: {}", error);
+                }
+                return None;
+            },
+        };
+
+        let method = request.method().to_owned();
+        let origin = match request.id() {
+            Some(origin) => origin.clone(),
+            None => {
+                if let Some(origin) = request.cancelled_id() {
+                    let params = json!({ "id": origin });
+                    let params = self.client_requests.forward_notification(CANCEL_REQUEST, params)?;
+                    if let Err(error) = request.set_params(params) {
+                        log::error!("failed to forward cancellation: {}", error);
+                        return None;
+                    }
+                }
+                if let Err(error) = self.send(Incoming::Request(request)).await {
+                    log::error!("failed to forward {:?} notification: {}", method, error);
+                }
+                return None;
+            },
+        };
+
+        let target = self.client_requests.forward(origin);
+        let response = match ServerRequest::new(method.as_str(), request.params_value(), Some(target.clone())) {
+            Ok(request) => self.send(request.into()).await,
+            Err(error) => Err(error),
+        };
+        let response = match response {
+            Ok(Some(Outgoing::Response(response))) => response,
+            Ok(_) => {
+                let error = Error::internal_error().with_message(format!("no response to {:?}", method));
+                Response::error(Some(target), error)
+            },
+            Err(error) => Response::error(Some(target), error),
+        };
+        self.client_requests.complete(response).map(Outgoing::Response)
+    }
+
+    /// Sends a request of type `R` to the server.
+    pub async fn send_request<R>(&self, params: R::Params) -> Result<R::Result>
+    where
+        R: lsp::request::Request,
+    {
+        self.request(R::METHOD, params).await
+    }
+
+    /// Sends a request of type `R` to the server, returning the stream of its partial results along
+    /// with its response.
+    ///
+    /// The `partialResultToken` of the request is set to a fresh token. The server reports partial
+    /// results through `$/progress` notifications, which reach the stream once the messages of the
+    /// server are passed through [`partial_results`]. The stream ends once the response was
+    /// received and the results routed before were yielded, while the token stays registered until
+    /// the stream is dropped, so that results routed late are not passed on to the client.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use futures::{future, StreamExt};
+    /// # use lspower::{jsonrpc::Result, lsp::{request::References, *}, LanguageServer, LspService, ServerProxy};
+    /// # #[derive(Debug)]
+    /// # struct Backend;
+    /// # #[lspower::async_trait]
+    /// # impl LanguageServer for Backend {
+    /// #     async fn initialize(&self, _: InitializeParams) -> Result<InitializeResult> {
+    /// #         Ok(InitializeResult::default())
+    /// #     }
+    /// #     async fn shutdown(&self) -> Result<()> {
+    /// #         Ok(())
+    /// #     }
+    /// # }
+    /// # async fn example(params: ReferenceParams) -> Result<()> {
+    /// let (service, messages) = LspService::new(|_| Backend);
+    /// let server = ServerProxy::new(service);
+    /// // Forwarded to the client, e.g. through `Server::interleave`.
+    /// let other = messages.filter_map(|message| future::ready(server.partial_results().route(message)));
+    ///
+    /// let (partial, response) = server.send_request_with_partial_results::<References>(params);
+    /// let (partial, locations): (Vec<_>, _) = futures::join!(partial.collect(), response);
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`partial_results`]: ServerProxy::partial_results
+    pub fn send_request_with_partial_results<R>(
+        &self,
+        params: R::Params,
+    ) -> (PartialResultStream, impl Future<Output = Result<R::Result>> + '_)
+    where
+        R: lsp::request::Request,
+    {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let token = lsp::ProgressToken::String(format!("lspower/partial/{}", id));
+        let stream = self.partial_results.register(token.clone());
+
+        let params = to_value(params).and_then(|mut params| match params.as_object_mut() {
+            Some(object) => {
+                object.insert("partialResultToken".into(), json!(token));
+                Ok(params)
+            },
+            None => Err(Error::invalid_params(format!("{:?} does not support partial results", R::METHOD))),
+        });
+        let response = async move {
+            let response = match params {
+                Ok(params) => self.call_request(R::METHOD, Some(params)).await,
+                Err(error) => Err(error),
+            };
+            self.partial_results.finish(&token);
+            response
+        };
+        (stream, response)
+    }
+
+    /// Sends a notification of type `N` to the server.
+    pub async fn send_notification<N>(&self, params: N::Params) -> Result<()>
+    where
+        N: lsp::notification::Notification,
+    {
+        self.notify(N::METHOD, params).await
+    }
+
+    pub(crate) async fn request<P, T>(&self, method: &str, params: P) -> Result<T>
+    where
+        P: Serialize,
+        T: DeserializeOwned,
+    {
+        self.call_request(method, Some(to_value(params)?)).await
+    }
+
+    pub(crate) async fn request_without_params<T>(&self, method: &str) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        self.call_request(method, None).await
+    }
+
+    pub(crate) async fn notify<P>(&self, method: &str, params: P) -> Result<()>
+    where
+        P: Serialize,
+    {
+        self.call(method, Some(to_value(params)?), None).await.map(drop)
+    }
+
+    pub(crate) async fn notify_without_params(&self, method: &str) -> Result<()> {
+        self.call(method, None, None).await.map(drop)
+    }
+
+    async fn call_request<T>(&self, method: &str, params: Option<Value>) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let response = match self.call(method, params, Some(id)).await? {
+            Some(Outgoing::Response(response)) => response,
+            _ => return Err(Error::internal_error().with_message(format!("no response to {:?}", method))),
+        };
+
+        let result = response.into_parts().1?;
+        serde_json::from_value(result).map_err(|error| {
+            log::error!("invalid response to {:?}: {}", method, error);
+            Error::internal_error().with_message(error.to_string())
+        })
+    }
+
+    async fn call(&self, method: &str, params: Option<Value>, id: Option<u64>) -> Result<Option<Outgoing>> {
+        let mut message = json!({ "jsonrpc": "2.0", "method": method });
+        if let Some(params) = params {
+            message["params"] = params;
+        }
+        if let Some(id) = id {
+            message["id"] = id.into();
+        }
+        let message: Incoming = serde_json::from_value(message).map_err(|error| {
+            Error::invalid_request().with_message(format!("invalid message for {:?}: {}", method, error))
+        })?;
+        self.send(message).await
+    }
+
+    async fn send(&self, message: Incoming) -> Result<Option<Outgoing>> {
+        let response = {
+            let mut service = self.service.lock().await;
+            future::poll_fn(|cx| service.poll_ready(cx)).await.map_err(service_error)?;
+            service.call(message)
+        };
+        response.await.map_err(service_error)
+    }
+}
+
+impl<S: Debug> Debug for ServerProxy<S> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct(stringify!(ServerProxy))
+            .field("service", &self.service)
+            .field("next_id", &self.next_id)
+            .field("partial_results", &self.partial_results)
+            .field("request_handlers", &self.request_handlers)
+            .field("client_requests", &self.client_requests)
+            .field("server_requests", &self.server_requests)
+            .finish()
+    }
+}
+
+fn to_value<P: Serialize>(params: P) -> Result<Value> {
+    serde_json::to_value(params).map_err(|error| Error::invalid_params(error.to_string()))
+}
+
+fn service_error<E: Display>(error: E) -> Error {
+    Error::internal_error().with_message(error.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{jsonrpc::ErrorCode, LspService};
+    use async_trait::async_trait;
+
+    #[derive(Debug)]
+    struct Mock;
+
+    #[async_trait]
+    impl crate::LanguageServer for Mock {
+        async fn initialize(&self, _: lsp::InitializeParams) -> Result<lsp::InitializeResult> {
+            let capabilities = lsp::ServerCapabilities {
+                hover_provider: Some(lsp::HoverProviderCapability::Simple(true)),
+                ..Default::default()
+            };
+            Ok(lsp::InitializeResult {
+                capabilities,
+                ..Default::default()
+            })
+        }
+
+        async fn shutdown(&self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn typed_calls() {
+        let (service, _) = LspService::new(|_| Mock);
+        let server = ServerProxy::new(service);
+
+        let params = serde_json::from_value(json!({ "capabilities": {} })).unwrap();
+        let result = server.initialize(params).await.unwrap();
+        assert!(result.capabilities.hover_provider.is_some());
+        assert_eq!(server.initialized(lsp::InitializedParams {}).await, Ok(()));
+
+        let params = lsp::HoverParams {
+            text_document_position_params: lsp::TextDocumentPositionParams::new(
+                lsp::TextDocumentIdentifier::new(lsp::Url::parse("file:///a.rs").unwrap()),
+                lsp::Position::new(0, 0),
+            ),
+            work_done_progress_params: Default::default(),
+        };
+        let error = server.hover(params).await.unwrap_err();
+        assert_eq!(error.code, ErrorCode::MethodNotFound);
+
+        assert_eq!(server.shutdown().await, Ok(()));
+        assert_eq!(server.exit().await, Ok(()));
+        let error = server.shutdown().await.unwrap_err();
+        assert_eq!(error.code, ErrorCode::InternalError);
+    }
+
+    #[derive(Debug)]
+    struct Partial(crate::Client);
+
+    enum PartialResult {}
+
+    impl lsp::notification::Notification for PartialResult {
+        type Params = Value;
+
+        const METHOD: &'static str = "$/progress";
+    }
+
+    #[async_trait]
+    impl crate::LanguageServer for Partial {
+        async fn initialize(&self, _: lsp::InitializeParams) -> Result<lsp::InitializeResult> {
+            Ok(lsp::InitializeResult::default())
+        }
+
+        async fn shutdown(&self) -> Result<()> {
+            Ok(())
+        }
+
+        async fn references(&self, params: lsp::ReferenceParams) -> Result<Option<Vec<lsp::Location>>> {
+            let token = params.partial_result_params.partial_result_token;
+            let location = lsp::Location::new(params.text_document_position.text_document.uri, lsp::Range::default());
+            for _ in 0 .. 2 {
+                let value = json!({ "token": token, "value": [location] });
+                self.0.send_custom_notification::<PartialResult>(value).await;
+            }
+            // Unrelated progress is passed on.
+            let value = json!({ "token": "other", "value": { "kind": "end" } });
+            self.0.send_custom_notification::<PartialResult>(value).await;
+            Ok(Some(Vec::new()))
+        }
+    }
+
+    #[tokio::test]
+    async fn partial_results() {
+        use futures::StreamExt;
+
+        let (service, messages) = LspService::new(Partial);
+        let server = ServerProxy::new(service);
+        let params = serde_json::from_value(json!({ "capabilities": {} })).unwrap();
+        server.initialize(params).await.unwrap();
+        server.initialized(lsp::InitializedParams {}).await.unwrap();
+
+        let uri = lsp::Url::parse("file:///a.rs").unwrap();
+        let params = lsp::ReferenceParams {
+            text_document_position: lsp::TextDocumentPositionParams::new(
+                lsp::TextDocumentIdentifier::new(uri.clone()),
+                lsp::Position::new(0, 0),
+            ),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+            context: lsp::ReferenceContext {
+                include_declaration: true,
+            },
+        };
+        let (partial, response) = server.send_request_with_partial_results::<lsp::request::References>(params);
+        assert!(server.partial_results().is_registered(partial.token()));
+
+        let routed = messages.filter_map(|message| future::ready(server.partial_results().route(message)));
+        let (partial, response, other) = futures::join!(
+            partial.collect::<Vec<_>>(),
+            response,
+            routed.take(1).collect::<Vec<_>>()
+        );
+
+        let location = json!([lsp::Location::new(uri, lsp::Range::default())]);
+        assert_eq!(partial, vec![location.clone(), location]);
+        assert_eq!(response, Ok(Some(Vec::new())));
+        assert_eq!(other.len(), 1);
+        assert_eq!(other[0].clone().into_message::<Value>().unwrap()["params"]["token"], "other");
+        assert_eq!(format!("{:?}", server.partial_results()), "{}");
+    }
+
+    #[tokio::test]
+    async fn finished_partial_results() {
+        use futures::StreamExt;
+
+        let results = PartialResults::new();
+        let token = lsp::ProgressToken::String("late".into());
+        let progress = |value: i32| -> Outgoing {
+            let raw = json!({ "jsonrpc": "2.0", "method": "$/progress", "params": { "token": "late", "value": value } });
+            serde_json::from_value(raw).unwrap()
+        };
+
+        let mut stream = results.register(token.clone());
+        assert_eq!(results.route(progress(1)), None);
+        results.finish(&token);
+        assert_eq!(results.route(progress(2)), None);
+        assert_eq!(stream.next().await, Some(json!(1)));
+        assert_eq!(stream.next().await, None);
+
+        drop(stream);
+        assert!(!results.is_registered(&token));
+        assert_eq!(results.route(progress(3)), Some(progress(3)));
+    }
+
+    #[derive(Debug)]
+    struct Configured(crate::Client);
+
+    #[async_trait]
+    impl crate::LanguageServer for Configured {
+        async fn initialize(&self, _: lsp::InitializeParams) -> Result<lsp::InitializeResult> {
+            Ok(lsp::InitializeResult::default())
+        }
+
+        async fn shutdown(&self) -> Result<()> {
+            Ok(())
+        }
+
+        async fn hover(&self, _: lsp::HoverParams) -> Result<Option<lsp::Hover>> {
+            let item = lsp::ConfigurationItem {
+                scope_uri: None,
+                section: Some("example".into()),
+            };
+            let settings = self.0.configuration(vec![item]).await?;
+            self.0.log_message(lsp::MessageType::INFO, "configured").await;
+            Ok(Some(lsp::Hover {
+                contents: lsp::HoverContents::Scalar(lsp::MarkedString::String(settings[0].to_string())),
+                range: None,
+            }))
+        }
+    }
+
+    #[tokio::test]
+    async fn request_handlers() {
+        use crate::jsonrpc::ClientRequestHandlers;
+        use futures::StreamExt;
+        use lsp::request::WorkspaceConfiguration;
+
+        let handlers = ClientRequestHandlers::new().handler::<WorkspaceConfiguration, _, _>(|params| async move {
+            Ok(params.items.into_iter().map(|item| json!({ "section": item.section })).collect())
+        });
+        let (service, messages) = LspService::new(Configured);
+        let server = ServerProxy::new(service).with_request_handlers(handlers);
+        let params = serde_json::from_value(json!({ "capabilities": {} })).unwrap();
+        server.initialize(params).await.unwrap();
+        server.initialized(lsp::InitializedParams {}).await.unwrap();
+
+        let params = lsp::HoverParams {
+            text_document_position_params: lsp::TextDocumentPositionParams::new(
+                lsp::TextDocumentIdentifier::new(lsp::Url::parse("file:///a.rs").unwrap()),
+                lsp::Position::new(0, 0),
+            ),
+            work_done_progress_params: Default::default(),
+        };
+        let unanswered = messages.filter_map(|message| server.answer(message));
+        let (hover, other) = futures::join!(server.hover(params), unanswered.take(1).collect::<Vec<_>>());
+
+        let expected = lsp::MarkedString::String(r#"{"section":"example"}"#.into());
+        assert_eq!(hover.unwrap().unwrap().contents, lsp::HoverContents::Scalar(expected));
+        assert_eq!(other.len(), 1);
+        assert_eq!(other[0].clone().into_message::<Value>().unwrap()["method"], "window/logMessage");
+    }
+
+    #[derive(Debug)]
+    struct Waiting(crate::Client);
+
+    #[async_trait]
+    impl crate::LanguageServer for Waiting {
+        async fn initialize(&self, _: lsp::InitializeParams) -> Result<lsp::InitializeResult> {
+            Ok(lsp::InitializeResult::default())
+        }
+
+        async fn shutdown(&self) -> Result<()> {
+            Ok(())
+        }
+
+        async fn hover(&self, _: lsp::HoverParams) -> Result<Option<lsp::Hover>> {
+            self.0.configuration(Vec::new()).await?;
+            Ok(None)
+        }
+    }
+
+    #[tokio::test]
+    async fn forward_cancellation() {
+        use crate::jsonrpc::Id;
+        use futures::StreamExt;
+
+        let (service, mut messages) = LspService::build(Waiting).cancel_dropped_requests(true).finish();
+        let server = ServerProxy::new(service);
+        let params = json!({ "capabilities": { "workspace": { "configuration": true } } });
+        let params = serde_json::from_value(params).unwrap();
+        server.initialize(params).await.unwrap();
+        server.initialized(lsp::InitializedParams {}).await.unwrap();
+
+        let hover = json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/hover",
+            "params": { "textDocument": { "uri": "file:///a.rs" }, "position": { "line": 0, "character": 0 } },
+            "id": 7,
+        });
+        let hover = server.forward(serde_json::from_value(hover).unwrap());
+        let client = async {
+            // The request of the server is forwarded to the client with an ID of the proxy.
+            let configuration = match server.forward_outgoing(messages.next().await.unwrap()) {
+                Some(Outgoing::Request(request)) => request,
+                message => panic!("expected request, got {:?}", message),
+            };
+            assert_eq!(configuration.method(), "workspace/configuration");
+            assert_eq!(configuration.id(), Some(&Id::String("proxy/0".into())));
+
+            // Canceling the request of the client cancels the forwarded request.
+            let cancel = json!({ "jsonrpc": "2.0", "method": "$/cancelRequest", "params": { "id": 7 } });
+            assert_eq!(server.forward(serde_json::from_value(cancel).unwrap()).await, None);
+        };
+        let response = match futures::join!(hover, client).0 {
+            Some(Outgoing::Response(response)) => response,
+            message => panic!("expected response, got {:?}", message),
+        };
+        assert_eq!(response.id(), Some(&Id::Number(7)));
+        assert_eq!(response.into_parts().1.unwrap_err().code, ErrorCode::RequestCancelled);
+        assert!(server.client_requests.is_empty());
+
+        // The server in turn cancels its request, which the client knows by the ID of the proxy.
+        let cancel = match server.forward_outgoing(messages.next().await.unwrap()) {
+            Some(Outgoing::Request(request)) => request,
+            message => panic!("expected request, got {:?}", message),
+        };
+        assert_eq!(cancel.method(), "$/cancelRequest");
+        assert_eq!(cancel.params(), &json!({ "id": "proxy/0" }));
+
+        // The late response of the client is passed on, but the server forgot the request.
+        let response = Response::ok(Id::String("proxy/0".into()), Value::Null);
+        assert_eq!(server.forward(Incoming::Response(response)).await, None);
+        assert!(server.server_requests.is_empty());
+    }
+}
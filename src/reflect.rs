@@ -0,0 +1,98 @@
+//! Reflection over the LSP methods known to the dispatcher.
+
+use crate::spec::SpecFeature;
+
+/// Whether an LSP method is a request or a notification.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum MethodKind {
+    /// A request, which expects a response.
+    Request,
+    /// A notification, which does not expect a response.
+    Notification,
+}
+
+/// Description of an LSP method known to the dispatcher.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct MethodInfo {
+    name: &'static str,
+    kind: MethodKind,
+    feature: Option<SpecFeature>,
+}
+
+impl MethodInfo {
+    pub(crate) const fn new(name: &'static str, kind: MethodKind, feature: Option<SpecFeature>) -> Self {
+        MethodInfo { name, kind, feature }
+    }
+
+    /// Returns the name of the method, e.g. `textDocument/hover`.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Returns whether the method is a request or a notification.
+    pub fn kind(&self) -> MethodKind {
+        self.kind
+    }
+
+    /// Returns the optional part of the specification the method belongs to, if any.
+    pub fn feature(&self) -> Option<SpecFeature> {
+        self.feature
+    }
+}
+
+/// Returns the list of all LSP methods the dispatcher knows about.
+///
+/// Requests and notifications for methods not contained in this list are forwarded to
+/// [`LanguageServer::request_else`] and [`LanguageServer::notification_else`], unless they start
+/// with `$/` and are handled by the [`DollarMethodPolicy`](crate::DollarMethodPolicy) instead.
+///
+/// [`LanguageServer::request_else`]: crate::LanguageServer::request_else
+/// [`LanguageServer::notification_else`]: crate::LanguageServer::notification_else
+pub fn methods() -> &'static [MethodInfo] {
+    crate::generated_impl::METHODS
+}
+
+/// Returns the description of the LSP method with the given name, if the dispatcher knows about it.
+pub fn method(name: &str) -> Option<&'static MethodInfo> {
+    methods().iter().find(|method| method.name == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lifecycle_methods() {
+        assert_eq!(method("initialize").map(MethodInfo::kind), Some(MethodKind::Request));
+        assert_eq!(method("initialized").map(MethodInfo::kind), Some(MethodKind::Notification));
+        assert_eq!(method("shutdown").map(MethodInfo::kind), Some(MethodKind::Request));
+        assert_eq!(method("exit").map(MethodInfo::kind), Some(MethodKind::Notification));
+        assert_eq!(
+            method("$/cancelRequest").map(MethodInfo::kind),
+            Some(MethodKind::Notification)
+        );
+    }
+
+    #[test]
+    fn feature_methods() {
+        let inlay_hint = method("textDocument/inlayHint");
+        assert_eq!(inlay_hint.is_some(), SpecFeature::Proposed.is_enabled());
+        if let Some(inlay_hint) = inlay_hint {
+            assert_eq!(inlay_hint.feature(), Some(SpecFeature::Proposed));
+        }
+        assert_eq!(method("textDocument/hover").and_then(MethodInfo::feature), None);
+    }
+
+    #[test]
+    fn unknown_method() {
+        assert_eq!(method("custom/request"), None);
+    }
+
+    #[test]
+    fn unique_names() {
+        let mut names: Vec<_> = methods().iter().map(MethodInfo::name).collect();
+        names.sort_unstable();
+        names.dedup();
+        assert_eq!(names.len(), methods().len());
+    }
+}
@@ -0,0 +1,102 @@
+//! Results last sent to the client for each document, under the result IDs the client refers to.
+
+use crate::uri;
+use std::collections::HashMap;
+
+/// The results last sent for each document, keyed by their [normalized] URIs.
+///
+/// Result IDs are unique across all documents. The results of up to `capacity` documents are kept;
+/// once exceeded, the results of the least recently updated document are evicted.
+///
+/// [normalized]: crate::uri::normalize
+pub(crate) struct ResultCache<T> {
+    next_result_id: u64,
+    next_use: u64,
+    capacity: usize,
+    documents: HashMap<lsp::Url, Cached<T>>,
+}
+
+/// The result last sent for a document.
+pub(crate) struct Cached<T> {
+    pub(crate) result_id: String,
+    pub(crate) value: T,
+    last_use: u64,
+}
+
+impl<T> ResultCache<T> {
+    /// Creates a cache keeping the results of up to `capacity` documents.
+    ///
+    /// A capacity of `0` is treated as `1`.
+    pub(crate) fn new(capacity: usize) -> Self {
+        ResultCache {
+            next_result_id: 0,
+            next_use: 0,
+            capacity: capacity.max(1),
+            documents: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub(crate) fn get(&self, uri: &lsp::Url) -> Option<&Cached<T>> {
+        self.documents.get(&uri::normalize(uri))
+    }
+
+    /// Keeps the result of the document under a new result ID, evicting the least recently updated
+    /// document if the capacity is exceeded, and returns the result ID.
+    pub(crate) fn insert(&mut self, uri: &lsp::Url, value: T) -> String {
+        let result_id = self.next_result_id.to_string();
+        self.next_result_id += 1;
+        let last_use = self.next_use;
+        self.next_use += 1;
+
+        let cached = Cached {
+            result_id: result_id.clone(),
+            value,
+            last_use,
+        };
+        if self.documents.insert(uri::normalize(uri), cached).is_none() && self.documents.len() > self.capacity {
+            let evicted = self.documents.iter().min_by_key(|(_, cached)| cached.last_use).map(|(uri, _)| uri.clone());
+            if let Some(evicted) = evicted {
+                self.documents.remove(&evicted);
+            }
+        }
+        result_id
+    }
+
+    pub(crate) fn remove(&mut self, uri: &lsp::Url) {
+        self.documents.remove(&uri::normalize(uri));
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.documents.clear();
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.documents.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn insert() {
+        let mut cache = ResultCache::new(2);
+        let uri = |path| lsp::Url::parse(path).unwrap();
+        assert_eq!(cache.insert(&uri("file:///C:/a.rs"), 'a'), "0");
+        assert_eq!(cache.insert(&uri("file:///b.rs"), 'b'), "1");
+        assert_eq!(cache.insert(&uri("file:///c%3A/a.rs"), 'A'), "2");
+        assert_eq!(cache.get(&uri("file:///c:/a.rs")).map(|cached| cached.value), Some('A'));
+
+        // The results of `b.rs` were updated least recently.
+        cache.insert(&uri("file:///c.rs"), 'c');
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get(&uri("file:///b.rs")).is_none());
+        cache.remove(&uri("file:///C:/a.rs"));
+        assert_eq!(cache.len(), 1);
+    }
+}
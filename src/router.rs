@@ -0,0 +1,617 @@
+//! Routing of document-scoped messages to per-language backends.
+
+mod merge;
+
+pub use self::merge::{merge_initialize_results, CapabilityConflict};
+use crate::{
+    jsonrpc::{RawValue, Result},
+    LanguageServer,
+};
+use futures::future;
+use serde::Deserialize;
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    fmt::{self, Debug, Formatter},
+    sync::{Arc, Mutex},
+};
+
+/// Language server composed of several backends, each handling the documents of some languages.
+///
+/// The router records the `languageId` of each document opened with `textDocument/didOpen`, and
+/// forwards the requests and notifications about a document to the backend registered for its
+/// language, until the document is closed again. Documents of other languages, and documents which
+/// were not opened, are handled by the default backend.
+///
+/// Messages which do not concern a single document are dispatched as follows:
+///
+/// * `initialize`, `initialized`, `shutdown`, `workspace/didChangeWorkspaceFolders`,
+///   `workspace/didChangeConfiguration` and `workspace/didChangeWatchedFiles` are sent to all
+///   backends concurrently. The `initialize` result of the default backend is returned, so it
+///   should advertise the capabilities of all languages, unless the results of all backends are
+///   merged as enabled with [`merge_capabilities`](LanguageRouter::merge_capabilities).
+/// * `callHierarchy/incomingCalls` and `callHierarchy/outgoingCalls` are routed by the document
+///   of their call hierarchy item.
+/// * All other messages, including `*/resolve` requests, `workspace/symbol`,
+///   `workspace/executeCommand` and custom requests, are handled by the default backend.
+///
+/// # Example
+///
+/// ```rust
+/// # use lspower::{jsonrpc::Result, lsp::*, Client, LanguageRouter, LanguageServer, LspService};
+/// # use std::sync::Arc;
+/// # #[derive(Debug)]
+/// # struct Backend(Client);
+/// # #[lspower::async_trait]
+/// # impl LanguageServer for Backend {
+/// #     async fn initialize(&self, _: InitializeParams) -> Result<InitializeResult> {
+/// #         Ok(InitializeResult::default())
+/// #     }
+/// #     async fn shutdown(&self) -> Result<()> {
+/// #         Ok(())
+/// #     }
+/// # }
+/// # use Backend as Generic;
+/// # use Backend as Rust;
+/// # use Backend as TypeScript;
+/// let (service, messages) = LspService::new(|client| {
+///     // A backend handling several languages is shared through an `Arc`.
+///     let typescript = Arc::new(TypeScript(client.clone()));
+///     LanguageRouter::new(Generic(client.clone()))
+///         .language("rust", Rust(client))
+///         .language("typescript", typescript.clone())
+///         .language("typescriptreact", typescript)
+/// });
+/// ```
+pub struct LanguageRouter {
+    default: Arc<dyn LanguageServer>,
+    languages: HashMap<String, Arc<dyn LanguageServer>>,
+    documents: Mutex<HashMap<lsp::Url, String>>,
+    merge: bool,
+}
+
+impl LanguageRouter {
+    /// Creates a new `LanguageRouter` with the given default backend and no languages.
+    pub fn new<S: LanguageServer>(default: S) -> Self {
+        LanguageRouter {
+            default: Arc::new(default),
+            languages: HashMap::new(),
+            documents: Mutex::new(HashMap::new()),
+            merge: false,
+        }
+    }
+
+    /// Routes the documents with the given language ID to the given backend.
+    ///
+    /// If a backend was registered for the language before, it is replaced.
+    pub fn language<S: LanguageServer>(mut self, language_id: impl Into<String>, backend: S) -> Self {
+        self.languages.insert(language_id.into(), Arc::new(backend));
+        self
+    }
+
+    /// Enables or disables merging the `initialize` results of all backends with
+    /// [`merge_initialize_results`], so that each backend advertises only its own capabilities.
+    ///
+    /// If the capabilities of the backends conflict, the `initialize` request fails with an
+    /// "internal error" naming the capability. Defaults to `false`.
+    pub fn merge_capabilities(mut self, enabled: bool) -> Self {
+        self.merge = enabled;
+        self
+    }
+
+    /// Returns the language ID of the given document, if it is open.
+    pub fn language_of(&self, uri: &lsp::Url) -> Option<String> {
+        self.documents.lock().unwrap().get(uri).cloned()
+    }
+
+    /// Returns the backend handling the given document.
+    fn route(&self, uri: &lsp::Url) -> &dyn LanguageServer {
+        let documents = self.documents.lock().unwrap();
+        let backend = documents.get(uri).and_then(|language| self.languages.get(language));
+        &**backend.unwrap_or(&self.default)
+    }
+
+    /// Returns all backends, starting with the default one.
+    fn backends(&self) -> impl Iterator<Item = &dyn LanguageServer> {
+        std::iter::once(&self.default)
+            .chain(self.languages.values())
+            .map(|backend| &**backend)
+    }
+}
+
+impl Debug for LanguageRouter {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let mut languages: Vec<_> = self.languages.keys().collect();
+        languages.sort();
+        f.debug_struct(stringify!(LanguageRouter))
+            .field("languages", &languages)
+            .field("documents", &self.documents.lock().unwrap().len())
+            .field("merge", &self.merge)
+            .finish()
+    }
+}
+
+#[async_trait::async_trait]
+impl LanguageServer for LanguageRouter {
+    async fn initialize(&self, params: lsp::InitializeParams) -> Result<lsp::InitializeResult> {
+        let results = future::try_join_all(self.backends().map(|backend| backend.initialize(params.clone()))).await?;
+        match self.merge {
+            true => Ok(merge_initialize_results(results)?),
+            false => Ok(results.into_iter().next().unwrap_or_default()),
+        }
+    }
+
+    async fn initialized(&self, params: lsp::InitializedParams) {
+        future::join_all(self.backends().map(|backend| backend.initialized(params))).await;
+    }
+
+    async fn shutdown(&self) -> Result<()> {
+        future::try_join_all(self.backends().map(|backend| backend.shutdown())).await?;
+        Ok(())
+    }
+
+    async fn did_change_workspace_folders(&self, params: lsp::DidChangeWorkspaceFoldersParams) {
+        future::join_all(
+            self.backends()
+                .map(|backend| backend.did_change_workspace_folders(params.clone())),
+        )
+        .await;
+    }
+
+    async fn did_change_configuration(&self, params: lsp::DidChangeConfigurationParams) {
+        future::join_all(
+            self.backends()
+                .map(|backend| backend.did_change_configuration(params.clone())),
+        )
+        .await;
+    }
+
+    async fn did_change_watched_files(&self, params: lsp::DidChangeWatchedFilesParams) {
+        future::join_all(
+            self.backends()
+                .map(|backend| backend.did_change_watched_files(params.clone())),
+        )
+        .await;
+    }
+
+    async fn symbol(&self, params: lsp::WorkspaceSymbolParams) -> Result<Option<Vec<lsp::SymbolInformation>>> {
+        self.default.symbol(params).await
+    }
+
+    async fn execute_command(&self, params: lsp::ExecuteCommandParams) -> Result<Option<Value>> {
+        self.default.execute_command(params).await
+    }
+
+    async fn did_open(&self, params: lsp::DidOpenTextDocumentParams) {
+        let document = &params.text_document;
+        self.documents
+            .lock()
+            .unwrap()
+            .insert(document.uri.clone(), document.language_id.clone());
+        self.route(&document.uri).did_open(params).await
+    }
+
+    async fn did_open_raw(&self, params: &RawValue) {
+        // Only the URI and language of the document are parsed, leaving its text to the backend
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct DidOpen {
+            text_document: Document,
+        }
+
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Document {
+            uri: lsp::Url,
+            language_id: String,
+        }
+
+        match serde_json::from_str::<DidOpen>(params.get()) {
+            Ok(DidOpen { text_document: document }) => {
+                self.documents.lock().unwrap().insert(document.uri.clone(), document.language_id);
+                self.route(&document.uri).did_open_raw(params).await
+            },
+            Err(_) => self.default.did_open_raw(params).await,
+        }
+    }
+
+    async fn did_change(&self, params: lsp::DidChangeTextDocumentParams) {
+        self.route(&params.text_document.uri).did_change(params).await
+    }
+
+    async fn did_change_raw(&self, params: &RawValue) {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct DidChange {
+            text_document: lsp::TextDocumentIdentifier,
+        }
+
+        match serde_json::from_str::<DidChange>(params.get()) {
+            Ok(DidChange { text_document }) => self.route(&text_document.uri).did_change_raw(params).await,
+            Err(_) => self.default.did_change_raw(params).await,
+        }
+    }
+
+    async fn will_save(&self, params: lsp::WillSaveTextDocumentParams) {
+        self.route(&params.text_document.uri).will_save(params).await
+    }
+
+    async fn will_save_wait_until(
+        &self,
+        params: lsp::WillSaveTextDocumentParams,
+    ) -> Result<Option<Vec<lsp::TextEdit>>> {
+        self.route(&params.text_document.uri).will_save_wait_until(params).await
+    }
+
+    async fn did_save(&self, params: lsp::DidSaveTextDocumentParams) {
+        self.route(&params.text_document.uri).did_save(params).await
+    }
+
+    async fn did_close(&self, params: lsp::DidCloseTextDocumentParams) {
+        let uri = params.text_document.uri.clone();
+        self.route(&uri).did_close(params).await;
+        self.documents.lock().unwrap().remove(&uri);
+    }
+
+    async fn completion(&self, params: lsp::CompletionParams) -> Result<Option<lsp::CompletionResponse>> {
+        self.route(&params.text_document_position.text_document.uri)
+            .completion(params)
+            .await
+    }
+
+    async fn completion_resolve(&self, params: lsp::CompletionItem) -> Result<lsp::CompletionItem> {
+        self.default.completion_resolve(params).await
+    }
+
+    async fn hover(&self, params: lsp::HoverParams) -> Result<Option<lsp::Hover>> {
+        self.route(&params.text_document_position_params.text_document.uri)
+            .hover(params)
+            .await
+    }
+
+    async fn signature_help(&self, params: lsp::SignatureHelpParams) -> Result<Option<lsp::SignatureHelp>> {
+        self.route(&params.text_document_position_params.text_document.uri)
+            .signature_help(params)
+            .await
+    }
+
+    async fn goto_declaration(
+        &self,
+        params: lsp::request::GotoDeclarationParams,
+    ) -> Result<Option<lsp::request::GotoDeclarationResponse>> {
+        self.route(&params.text_document_position_params.text_document.uri)
+            .goto_declaration(params)
+            .await
+    }
+
+    async fn goto_definition(&self, params: lsp::GotoDefinitionParams) -> Result<Option<lsp::GotoDefinitionResponse>> {
+        self.route(&params.text_document_position_params.text_document.uri)
+            .goto_definition(params)
+            .await
+    }
+
+    async fn goto_type_definition(
+        &self,
+        params: lsp::request::GotoTypeDefinitionParams,
+    ) -> Result<Option<lsp::request::GotoTypeDefinitionResponse>> {
+        self.route(&params.text_document_position_params.text_document.uri)
+            .goto_type_definition(params)
+            .await
+    }
+
+    async fn goto_implementation(
+        &self,
+        params: lsp::request::GotoImplementationParams,
+    ) -> Result<Option<lsp::request::GotoImplementationResponse>> {
+        self.route(&params.text_document_position_params.text_document.uri)
+            .goto_implementation(params)
+            .await
+    }
+
+    async fn references(&self, params: lsp::ReferenceParams) -> Result<Option<Vec<lsp::Location>>> {
+        self.route(&params.text_document_position.text_document.uri)
+            .references(params)
+            .await
+    }
+
+    async fn document_highlight(
+        &self,
+        params: lsp::DocumentHighlightParams,
+    ) -> Result<Option<Vec<lsp::DocumentHighlight>>> {
+        self.route(&params.text_document_position_params.text_document.uri)
+            .document_highlight(params)
+            .await
+    }
+
+    async fn document_symbol(&self, params: lsp::DocumentSymbolParams) -> Result<Option<lsp::DocumentSymbolResponse>> {
+        self.route(&params.text_document.uri).document_symbol(params).await
+    }
+
+    async fn code_action(&self, params: lsp::CodeActionParams) -> Result<Option<lsp::CodeActionResponse>> {
+        self.route(&params.text_document.uri).code_action(params).await
+    }
+
+    async fn code_lens(&self, params: lsp::CodeLensParams) -> Result<Option<Vec<lsp::CodeLens>>> {
+        self.route(&params.text_document.uri).code_lens(params).await
+    }
+
+    async fn code_lens_resolve(&self, params: lsp::CodeLens) -> Result<lsp::CodeLens> {
+        self.default.code_lens_resolve(params).await
+    }
+
+    async fn document_link(&self, params: lsp::DocumentLinkParams) -> Result<Option<Vec<lsp::DocumentLink>>> {
+        self.route(&params.text_document.uri).document_link(params).await
+    }
+
+    async fn document_link_resolve(&self, params: lsp::DocumentLink) -> Result<lsp::DocumentLink> {
+        self.default.document_link_resolve(params).await
+    }
+
+    async fn document_color(&self, params: lsp::DocumentColorParams) -> Result<Vec<lsp::ColorInformation>> {
+        self.route(&params.text_document.uri).document_color(params).await
+    }
+
+    async fn color_presentation(&self, params: lsp::ColorPresentationParams) -> Result<Vec<lsp::ColorPresentation>> {
+        self.route(&params.text_document.uri).color_presentation(params).await
+    }
+
+    async fn formatting(&self, params: lsp::DocumentFormattingParams) -> Result<Option<Vec<lsp::TextEdit>>> {
+        self.route(&params.text_document.uri).formatting(params).await
+    }
+
+    async fn range_formatting(&self, params: lsp::DocumentRangeFormattingParams) -> Result<Option<Vec<lsp::TextEdit>>> {
+        self.route(&params.text_document.uri).range_formatting(params).await
+    }
+
+    async fn on_type_formatting(
+        &self,
+        params: lsp::DocumentOnTypeFormattingParams,
+    ) -> Result<Option<Vec<lsp::TextEdit>>> {
+        self.route(&params.text_document_position.text_document.uri)
+            .on_type_formatting(params)
+            .await
+    }
+
+    async fn rename(&self, params: lsp::RenameParams) -> Result<Option<lsp::WorkspaceEdit>> {
+        self.route(&params.text_document_position.text_document.uri)
+            .rename(params)
+            .await
+    }
+
+    async fn prepare_rename(
+        &self,
+        params: lsp::TextDocumentPositionParams,
+    ) -> Result<Option<lsp::PrepareRenameResponse>> {
+        self.route(&params.text_document.uri).prepare_rename(params).await
+    }
+
+    async fn folding_range(&self, params: lsp::FoldingRangeParams) -> Result<Option<Vec<lsp::FoldingRange>>> {
+        self.route(&params.text_document.uri).folding_range(params).await
+    }
+
+    async fn selection_range(&self, params: lsp::SelectionRangeParams) -> Result<Option<Vec<lsp::SelectionRange>>> {
+        self.route(&params.text_document.uri).selection_range(params).await
+    }
+
+    async fn incoming_calls(
+        &self,
+        params: lsp::CallHierarchyIncomingCallsParams,
+    ) -> Result<Option<Vec<lsp::CallHierarchyIncomingCall>>> {
+        self.route(&params.item.uri).incoming_calls(params).await
+    }
+
+    async fn outgoing_calls(
+        &self,
+        params: lsp::CallHierarchyOutgoingCallsParams,
+    ) -> Result<Option<Vec<lsp::CallHierarchyOutgoingCall>>> {
+        self.route(&params.item.uri).outgoing_calls(params).await
+    }
+
+    async fn prepare_call_hierarchy(
+        &self,
+        params: lsp::CallHierarchyPrepareParams,
+    ) -> Result<Option<Vec<lsp::CallHierarchyItem>>> {
+        self.route(&params.text_document_position_params.text_document.uri)
+            .prepare_call_hierarchy(params)
+            .await
+    }
+
+    async fn semantic_tokens_full(
+        &self,
+        params: lsp::SemanticTokensParams,
+    ) -> Result<Option<lsp::SemanticTokensResult>> {
+        self.route(&params.text_document.uri).semantic_tokens_full(params).await
+    }
+
+    async fn semantic_tokens_full_delta(
+        &self,
+        params: lsp::SemanticTokensDeltaParams,
+    ) -> Result<Option<lsp::SemanticTokensFullDeltaResult>> {
+        self.route(&params.text_document.uri)
+            .semantic_tokens_full_delta(params)
+            .await
+    }
+
+    async fn semantic_tokens_range(
+        &self,
+        params: lsp::SemanticTokensRangeParams,
+    ) -> Result<Option<lsp::SemanticTokensRangeResult>> {
+        self.route(&params.text_document.uri)
+            .semantic_tokens_range(params)
+            .await
+    }
+
+    async fn semantic_tokens_refresh(&self) -> Result<()> {
+        self.default.semantic_tokens_refresh().await
+    }
+
+    async fn code_action_resolve(&self, params: lsp::CodeAction) -> Result<lsp::CodeAction> {
+        self.default.code_action_resolve(params).await
+    }
+
+    #[cfg(feature = "proposed")]
+    async fn inlay_hint(&self, params: lsp::InlayHintParams) -> Result<Option<Vec<lsp::InlayHint>>> {
+        self.route(&params.text_document.uri).inlay_hint(params).await
+    }
+
+    #[cfg(feature = "proposed")]
+    async fn inlay_hint_resolve(&self, params: lsp::InlayHint) -> Result<lsp::InlayHint> {
+        self.default.inlay_hint_resolve(params).await
+    }
+
+    #[cfg(feature = "proposed")]
+    async fn inline_completion(
+        &self,
+        params: crate::proposed::InlineCompletionParams,
+    ) -> Result<Option<crate::proposed::InlineCompletionResponse>> {
+        self.route(&params.text_document_position.text_document.uri)
+            .inline_completion(params)
+            .await
+    }
+
+    async fn request_else(&self, method: &str, params: Option<Value>) -> Result<Option<Value>> {
+        self.default.request_else(method, params).await
+    }
+
+    async fn notification_else(&self, method: &str, params: Option<Value>) {
+        self.default.notification_else(method, params).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jsonrpc::Error;
+
+    #[derive(Debug, Default)]
+    struct Mock {
+        name: &'static str,
+        opened: Mutex<Vec<lsp::Url>>,
+        initialized: Mutex<bool>,
+        capabilities: serde_json::Value,
+    }
+
+    impl Mock {
+        fn new(name: &'static str) -> Arc<Self> {
+            Arc::new(Mock {
+                name,
+                ..Mock::default()
+            })
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl LanguageServer for Mock {
+        async fn initialize(&self, _: lsp::InitializeParams) -> Result<lsp::InitializeResult> {
+            *self.initialized.lock().unwrap() = true;
+            Ok(lsp::InitializeResult {
+                capabilities: serde_json::from_value(self.capabilities.clone()).unwrap_or_default(),
+                server_info: Some(lsp::ServerInfo {
+                    name: self.name.into(),
+                    version: None,
+                }),
+                #[cfg(feature = "proposed")]
+                offset_encoding: None,
+            })
+        }
+
+        async fn shutdown(&self) -> Result<()> {
+            Err(Error::internal_error())
+        }
+
+        async fn did_open(&self, params: lsp::DidOpenTextDocumentParams) {
+            self.opened.lock().unwrap().push(params.text_document.uri);
+        }
+
+        async fn hover(&self, _: lsp::HoverParams) -> Result<Option<lsp::Hover>> {
+            Ok(Some(lsp::Hover {
+                contents: lsp::HoverContents::Scalar(lsp::MarkedString::String(self.name.into())),
+                range: None,
+            }))
+        }
+    }
+
+    async fn open(router: &LanguageRouter, uri: &str, language_id: &str) -> lsp::Url {
+        let uri = lsp::Url::parse(uri).unwrap();
+        let text_document = lsp::TextDocumentItem::new(uri.clone(), language_id.into(), 0, String::new());
+        router.did_open(lsp::DidOpenTextDocumentParams { text_document }).await;
+        uri
+    }
+
+    async fn hover(router: &LanguageRouter, uri: &lsp::Url) -> lsp::HoverContents {
+        let params = lsp::HoverParams {
+            text_document_position_params: lsp::TextDocumentPositionParams::new(
+                lsp::TextDocumentIdentifier::new(uri.clone()),
+                lsp::Position::default(),
+            ),
+            work_done_progress_params: Default::default(),
+        };
+        router.hover(params).await.unwrap().unwrap().contents
+    }
+
+    fn contents(name: &str) -> lsp::HoverContents {
+        lsp::HoverContents::Scalar(lsp::MarkedString::String(name.into()))
+    }
+
+    #[tokio::test]
+    async fn routes_by_language() {
+        let default = Mock::new("default");
+        let rust = Mock::new("rust");
+        let typescript = Mock::new("typescript");
+        let router = LanguageRouter::new(default.clone())
+            .language("rust", rust.clone())
+            .language("typescript", typescript.clone())
+            .language("typescriptreact", typescript.clone());
+
+        let params = serde_json::from_value(serde_json::json!({ "capabilities": {} })).unwrap();
+        let result = router.initialize(params).await.unwrap();
+        assert_eq!(result.server_info.unwrap().name, "default");
+        assert!(*rust.initialized.lock().unwrap() && *typescript.initialized.lock().unwrap());
+
+        let main = open(&router, "file:///main.rs", "rust").await;
+        let app = open(&router, "file:///app.tsx", "typescriptreact").await;
+        let readme = open(&router, "file:///README.md", "markdown").await;
+        assert_eq!(*rust.opened.lock().unwrap(), vec![main.clone()]);
+        assert_eq!(*typescript.opened.lock().unwrap(), vec![app.clone()]);
+        assert_eq!(*default.opened.lock().unwrap(), vec![readme.clone()]);
+        assert_eq!(router.language_of(&app).as_deref(), Some("typescriptreact"));
+
+        assert_eq!(hover(&router, &main).await, contents("rust"));
+        assert_eq!(hover(&router, &app).await, contents("typescript"));
+        assert_eq!(hover(&router, &readme).await, contents("default"));
+
+        let text_document = lsp::TextDocumentIdentifier::new(main.clone());
+        router
+            .did_close(lsp::DidCloseTextDocumentParams { text_document })
+            .await;
+        assert_eq!(router.language_of(&main), None);
+        assert_eq!(hover(&router, &main).await, contents("default"));
+
+        assert_eq!(router.shutdown().await, Err(Error::internal_error()));
+    }
+
+    #[tokio::test]
+    async fn merges_capabilities() {
+        let backend = |name, capabilities| {
+            Arc::new(Mock {
+                name,
+                capabilities,
+                ..Mock::default()
+            })
+        };
+        let default = backend("default", serde_json::json!({ "textDocumentSync": 1, "hoverProvider": true }));
+        let rust = backend("rust", serde_json::json!({ "textDocumentSync": 1, "definitionProvider": true }));
+        let router = LanguageRouter::new(default.clone()).language("rust", rust).merge_capabilities(true);
+
+        let params: lsp::InitializeParams = serde_json::from_value(serde_json::json!({ "capabilities": {} })).unwrap();
+        let result = router.initialize(params.clone()).await.unwrap();
+        assert_eq!(result.server_info.unwrap().name, "default");
+        assert_eq!(result.capabilities.hover_provider, Some(lsp::HoverProviderCapability::Simple(true)));
+        assert_eq!(result.capabilities.definition_provider, Some(lsp::OneOf::Left(true)));
+
+        let markdown = backend("markdown", serde_json::json!({ "textDocumentSync": 2 }));
+        let router = LanguageRouter::new(default).language("markdown", markdown).merge_capabilities(true);
+        let error = router.initialize(params).await.unwrap_err();
+        assert_eq!(error.code, crate::jsonrpc::ErrorCode::InternalError);
+    }
+}
@@ -0,0 +1,214 @@
+//! Merging of the capabilities advertised by several backends.
+
+use crate::jsonrpc::Error;
+use serde_json::{json, Map, Value};
+use std::fmt::{self, Display, Formatter};
+
+/// Capabilities whose arrays are indexed by the client, so that they cannot be combined.
+const ORDERED: &[&str] = &[
+    "semanticTokensProvider.legend.tokenTypes",
+    "semanticTokensProvider.legend.tokenModifiers",
+];
+
+/// Error returned when backends advertise incompatible values for the same capability.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CapabilityConflict {
+    path: String,
+    values: Box<(Value, Value)>,
+}
+
+impl CapabilityConflict {
+    /// Returns the path of the capability within the server capabilities, such as
+    /// `textDocumentSync.change`.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Returns the conflicting values, as merged from the backends before and as advertised by the
+    /// backend they conflict with.
+    pub fn values(&self) -> (&Value, &Value) {
+        (&self.values.0, &self.values.1)
+    }
+}
+
+impl Display for CapabilityConflict {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "backends advertise conflicting values for the capability `{}`: {} and {}",
+            self.path, self.values.0, self.values.1
+        )
+    }
+}
+
+impl std::error::Error for CapabilityConflict {
+}
+
+impl From<CapabilityConflict> for Error {
+    fn from(error: CapabilityConflict) -> Self {
+        let data = json!({ "capability": error.path, "values": [error.values.0, error.values.1] });
+        Error::internal_error().with_message(error.to_string()).with_data(data)
+    }
+}
+
+/// Combines the `initialize` results of several backends into a single result advertising the
+/// capabilities of all of them.
+///
+/// Capabilities advertised by only some backends are taken as they are. Where several backends
+/// advertise the same capability, options take precedence over a plain `true`, options are merged
+/// field by field, and lists such as trigger characters or commands are combined. Differing values
+/// which cannot be combined, such as different `textDocumentSync` kinds or semantic token legends,
+/// are a [`CapabilityConflict`]. The server info is taken from the first result, and the offset
+/// encoding of the `proposed` feature from the first result setting one.
+///
+/// # Example
+///
+/// ```rust
+/// # use lspower::{lsp::*, merge_initialize_results};
+/// let completion = |trigger: &str| InitializeResult {
+///     capabilities: ServerCapabilities {
+///         completion_provider: Some(CompletionOptions {
+///             trigger_characters: Some(vec![trigger.into()]),
+///             ..Default::default()
+///         }),
+///         ..Default::default()
+///     },
+///     ..Default::default()
+/// };
+/// let merged = merge_initialize_results(vec![completion("."), completion("::")]).unwrap();
+/// let provider = merged.capabilities.completion_provider.unwrap();
+/// assert_eq!(provider.trigger_characters, Some(vec![".".into(), "::".into()]));
+/// ```
+pub fn merge_initialize_results<I>(results: I) -> Result<lsp::InitializeResult, CapabilityConflict>
+where
+    I: IntoIterator<Item = lsp::InitializeResult>,
+{
+    let mut results = results.into_iter();
+    let first = match results.next() {
+        Some(first) => first,
+        None => return Ok(lsp::InitializeResult::default()),
+    };
+
+    let mut merged = capabilities(first.capabilities);
+    #[cfg(feature = "proposed")]
+    let mut offset_encoding = first.offset_encoding;
+    for result in results {
+        merge(&mut merged, capabilities(result.capabilities), "")?;
+        #[cfg(feature = "proposed")]
+        {
+            offset_encoding = offset_encoding.or(result.offset_encoding);
+        }
+    }
+    Ok(lsp::InitializeResult {
+        // Merging valid capabilities only replaces values by values of the same capability.
+        capabilities: serde_json::from_value(merged).expect("merged capabilities are valid"),
+        server_info: first.server_info,
+        #[cfg(feature = "proposed")]
+        offset_encoding,
+    })
+}
+
+/// Serializes the capabilities, spelling out a `textDocumentSync` kind as options.
+fn capabilities(capabilities: lsp::ServerCapabilities) -> Value {
+    let mut value = serde_json::to_value(capabilities).unwrap();
+    if let Some(kind) = value.get("textDocumentSync").filter(|sync| sync.is_number()).cloned() {
+        value["textDocumentSync"] = json!({ "openClose": true, "change": kind });
+    }
+    value
+}
+
+fn merge(merged: &mut Value, other: Value, path: &str) -> Result<(), CapabilityConflict> {
+    match (&mut *merged, other) {
+        (merged, other) if *merged == other => {},
+        (_, Value::Null | Value::Bool(false)) | (Value::Object(_), Value::Bool(true)) => {},
+        (merged @ (Value::Null | Value::Bool(_)), other @ (Value::Bool(true) | Value::Object(_))) => *merged = other,
+        (Value::Object(merged), Value::Object(other)) => {
+            for (key, value) in other {
+                merge_field(merged, key, value, path)?;
+            }
+        },
+        (Value::Array(merged), Value::Array(other)) if !ORDERED.contains(&path) => {
+            for value in other {
+                if !merged.contains(&value) {
+                    merged.push(value);
+                }
+            }
+        },
+        (merged, other) => {
+            return Err(CapabilityConflict {
+                path: path.into(),
+                values: Box::new((merged.clone(), other)),
+            });
+        },
+    }
+    Ok(())
+}
+
+fn merge_field(merged: &mut Map<String, Value>, key: String, value: Value, path: &str) -> Result<(), CapabilityConflict> {
+    let path = match path {
+        "" => key.clone(),
+        path => format!("{}.{}", path, key),
+    };
+    match merged.get_mut(&key) {
+        Some(merged) => merge(merged, value, &path),
+        None => {
+            merged.insert(key, value);
+            Ok(())
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(capabilities: Value) -> lsp::InitializeResult {
+        lsp::InitializeResult {
+            capabilities: serde_json::from_value(capabilities).unwrap(),
+            server_info: None,
+            #[cfg(feature = "proposed")]
+            offset_encoding: None,
+        }
+    }
+
+    #[test]
+    fn merges_capabilities() {
+        let results = vec![
+            result(json!({ "textDocumentSync": 2, "hoverProvider": true, "executeCommandProvider": { "commands": ["a"] } })),
+            result(json!({
+                "textDocumentSync": { "change": 2, "save": true },
+                "hoverProvider": { "workDoneProgress": true },
+                "definitionProvider": true,
+                "executeCommandProvider": { "commands": ["b", "a"] },
+            })),
+            result(json!({ "hoverProvider": false, "renameProvider": false })),
+        ];
+        let merged = serde_json::to_value(merge_initialize_results(results).unwrap().capabilities).unwrap();
+        assert_eq!(merged["textDocumentSync"], json!({ "openClose": true, "change": 2, "save": true }));
+        assert_eq!(merged["hoverProvider"], json!({ "workDoneProgress": true }));
+        assert_eq!(merged["definitionProvider"], json!(true));
+        assert_eq!(merged["executeCommandProvider"]["commands"], json!(["a", "b"]));
+        assert_eq!(merged["renameProvider"], json!(false));
+    }
+
+    #[test]
+    fn detects_conflicts() {
+        let conflict = merge_initialize_results(vec![
+            result(json!({ "textDocumentSync": 1 })),
+            result(json!({ "textDocumentSync": { "change": 2 } })),
+        ])
+        .unwrap_err();
+        assert_eq!(conflict.path(), "textDocumentSync.change");
+        assert_eq!(conflict.values(), (&json!(1), &json!(2)));
+
+        let legend = |types: Value| json!({ "semanticTokensProvider": { "legend": { "tokenTypes": types, "tokenModifiers": [] }, "full": true } });
+        let conflict = merge_initialize_results(vec![result(legend(json!(["type"]))), result(legend(json!(["function"])))]);
+        assert_eq!(conflict.unwrap_err().path(), "semanticTokensProvider.legend.tokenTypes");
+
+        let error = Error::from(CapabilityConflict {
+            path: "hoverProvider".into(),
+            values: Box::new((json!(1), json!(2))),
+        });
+        assert_eq!(error.data, Some(json!({ "capability": "hoverProvider", "values": [1, 2] })));
+    }
+}
@@ -0,0 +1,172 @@
+//! Background tasks scoped to the current contents of a document.
+
+use crate::task::BackgroundTasks;
+use futures::future::{AbortHandle, Abortable, FutureExt};
+use std::{
+    collections::HashMap,
+    fmt::{self, Debug, Formatter},
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+        Mutex,
+    },
+};
+
+struct ScopedTask {
+    handle: AbortHandle,
+    finished: Arc<AtomicBool>,
+}
+
+/// Tasks spawned through [`DocumentScope`], keyed by the URI of their document.
+#[derive(Default)]
+pub(crate) struct DocumentScopes(Mutex<HashMap<lsp::Url, Vec<ScopedTask>>>);
+
+impl DocumentScopes {
+    fn register(&self, uri: &lsp::Url, task: ScopedTask) {
+        let mut scopes = self.0.lock().unwrap();
+        let tasks = scopes.entry(uri.clone()).or_default();
+        tasks.retain(|task| !task.finished.load(Ordering::SeqCst));
+        tasks.push(task);
+    }
+
+    /// Aborts all tasks scoped to the given document.
+    pub(crate) fn cancel(&self, uri: &lsp::Url) {
+        let tasks = self.0.lock().unwrap().remove(uri).unwrap_or_default();
+        let running: Vec<_> = tasks
+            .iter()
+            .filter(|task| !task.finished.load(Ordering::SeqCst))
+            .collect();
+        if !running.is_empty() {
+            log::debug!("canceling {} tasks scoped to {}", running.len(), uri);
+        }
+        for task in running {
+            task.handle.abort();
+        }
+    }
+
+    /// Aborts the tasks scoped to any document.
+    pub(crate) fn cancel_all(&self) {
+        for (_, tasks) in self.0.lock().unwrap().drain() {
+            for task in tasks {
+                task.handle.abort();
+            }
+        }
+    }
+}
+
+impl Debug for DocumentScopes {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let scopes = self.0.lock().unwrap();
+        f.debug_map()
+            .entries(scopes.iter().map(|(uri, tasks)| (uri.as_str(), tasks.len())))
+            .finish()
+    }
+}
+
+/// Handle for spawning background tasks tied to the current contents of a document.
+///
+/// Work derived from a document, such as computing diagnostics or indexing it, is stale as soon as
+/// the document changes. Tasks spawned through a scope are aborted once the client sends a
+/// `textDocument/didChange` or `textDocument/didClose` notification for its document, before the
+/// corresponding handler of the [`LanguageServer`] is invoked, so that the handler can spawn
+/// tasks for the new contents right away.
+///
+/// Scoped tasks are background tasks like any other, so they are also awaited on `shutdown` and
+/// aborted on `exit`, as described for the [`Spawner`].
+///
+/// This is obtained from [`Client::document_scope`].
+///
+/// # Example
+///
+/// ```rust
+/// # use lspower::{lsp::*, Client};
+/// # async fn compute_diagnostics(_: &Url, _: &str) -> Vec<Diagnostic> { Vec::new() }
+/// async fn did_change(client: &Client, params: DidChangeTextDocumentParams) {
+///     let uri = params.text_document.uri;
+///     let version = params.text_document.version;
+///     let text = params.content_changes.into_iter().last().map(|change| change.text);
+///     let scope = client.document_scope(uri.clone());
+///     let client = client.clone();
+///     scope.spawn(async move {
+///         let diagnostics = compute_diagnostics(&uri, text.as_deref().unwrap_or_default()).await;
+///         client.publish_diagnostics(uri, diagnostics, Some(version)).await;
+///     });
+/// }
+/// ```
+///
+/// [`LanguageServer`]: crate::LanguageServer
+/// [`Spawner`]: crate::Spawner
+/// [`Client::document_scope`]: crate::Client::document_scope
+#[derive(Clone)]
+pub struct DocumentScope {
+    uri: lsp::Url,
+    scopes: Arc<DocumentScopes>,
+    tasks: Arc<BackgroundTasks>,
+}
+
+impl DocumentScope {
+    pub(crate) fn new(uri: lsp::Url, scopes: Arc<DocumentScopes>, tasks: Arc<BackgroundTasks>) -> Self {
+        DocumentScope { uri, scopes, tasks }
+    }
+
+    /// Returns the URI of the document this scope is tied to.
+    pub fn uri(&self) -> &lsp::Url {
+        &self.uri
+    }
+
+    /// Spawns a background task which is aborted once the document is changed or closed.
+    pub fn spawn<F>(&self, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let (handle, registration) = AbortHandle::new_pair();
+        let finished = Arc::new(AtomicBool::new(false));
+        let flag = finished.clone();
+        self.scopes.register(&self.uri, ScopedTask { handle, finished });
+        let task = Abortable::new(task, registration).map(move |_| flag.store(true, Ordering::SeqCst));
+        self.tasks.spawn(task);
+    }
+
+    /// Aborts all tasks scoped to the document, e.g. when its contents changed on disk.
+    pub fn cancel(&self) {
+        self.scopes.cancel(&self.uri);
+    }
+}
+
+impl Debug for DocumentScope {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct(stringify!(DocumentScope))
+            .field("uri", &self.uri.as_str())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{task::pending_task, SystemClock};
+
+    #[tokio::test]
+    async fn cancel() {
+        let scopes = Arc::new(DocumentScopes::default());
+        let tasks = Arc::new(BackgroundTasks::new(None, Arc::new(SystemClock)));
+        let a = lsp::Url::parse("file:///a.rs").unwrap();
+        let b = lsp::Url::parse("file:///b.rs").unwrap();
+        let scope_a = DocumentScope::new(a.clone(), scopes.clone(), tasks.clone());
+        let scope_b = DocumentScope::new(b, scopes.clone(), tasks);
+
+        let (task_a, rx_a) = pending_task();
+        scope_a.spawn(task_a);
+        let (task_b, mut rx_b) = pending_task();
+        scope_b.spawn(task_b);
+
+        scopes.cancel(&a);
+        assert!(rx_a.await.is_err());
+        tokio::task::yield_now().await;
+        assert_eq!(rx_b.try_recv(), Ok(None));
+
+        scope_b.cancel();
+        assert!(rx_b.await.is_err());
+    }
+}
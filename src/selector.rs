@@ -0,0 +1,405 @@
+//! Matching of documents against [`DocumentSelector`]s and glob patterns.
+//!
+//! Document selectors appear in dynamic registrations and in the registration options of many
+//! capabilities, while glob patterns also appear in file system watchers and file operation
+//! filters. [`matches()`] tells whether a document is selected, and [`Glob`] matches paths against
+//! a pattern with the syntax of the specification:
+//!
+//! * `*` matches zero or more characters in a path segment,
+//! * `?` matches one character in a path segment,
+//! * `**` matches any number of path segments, including none,
+//! * `{}` groups alternatives, e.g. `**/*.{ts,js}`,
+//! * `[]` declares a range of characters, e.g. `example.[0-9]`, or excludes it, e.g. `[!0-9]`.
+//!
+//! [`DocumentSelector`]: lsp::DocumentSelector
+
+use lsp::{DocumentFilter, DocumentSelector, Url};
+use percent_encoding::percent_decode_str;
+use std::{
+    error::Error,
+    fmt::{self, Display, Formatter},
+};
+
+/// Returns whether the document with the given URI and language is selected by any filter of the
+/// selector.
+///
+/// An empty selector selects no document. See [`matches_filter`] for how filters are matched.
+///
+/// # Example
+///
+/// ```rust
+/// # use lspower::{lsp::{DocumentFilter, Url}, selector};
+/// let selector = vec![DocumentFilter {
+///     language: Some("rust".into()),
+///     scheme: Some("file".into()),
+///     pattern: Some("**/src/**/*.rs".into()),
+/// }];
+/// let uri = Url::parse("file:///home/user/project/src/main.rs").unwrap();
+/// assert!(selector::matches(&selector, &uri, "rust"));
+/// assert!(!selector::matches(&selector, &uri, "toml"));
+/// ```
+pub fn matches(selector: &DocumentSelector, uri: &Url, language_id: &str) -> bool {
+    selector.iter().any(|filter| matches_filter(filter, uri, language_id))
+}
+
+/// Returns whether the document with the given URI and language is selected by the filter.
+///
+/// Each of the language, scheme and pattern of the filter must match, if present, so a filter
+/// without any of them selects every document. The pattern is matched against the percent-decoded
+/// path of the URI, as described in [`Glob::is_match`]. Filters with an invalid pattern select no
+/// document.
+pub fn matches_filter(filter: &DocumentFilter, uri: &Url, language_id: &str) -> bool {
+    if filter.language.as_deref().is_some_and(|language| language != language_id) {
+        return false;
+    }
+    if filter.scheme.as_deref().is_some_and(|scheme| scheme != uri.scheme()) {
+        return false;
+    }
+    filter.pattern.as_deref().is_none_or(|pattern| match Glob::new(pattern) {
+        Ok(glob) => glob.is_match(&percent_decode_str(uri.path()).decode_utf8_lossy()),
+        Err(error) => {
+            log::debug!("ignoring document filter with invalid pattern: {}", error);
+            false
+        },
+    })
+}
+
+/// Error that occurs when parsing an invalid glob pattern.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GlobError {
+    pattern: String,
+    reason: &'static str,
+}
+
+impl Display for GlobError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "invalid glob pattern `{}`: {}", self.pattern, self.reason)
+    }
+}
+
+impl Error for GlobError {
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    /// A character matched literally.
+    Char(char),
+    /// `?`, matching one character other than `/`.
+    Any,
+    /// `*`, matching zero or more characters other than `/`.
+    Star,
+    /// `**/`, matching zero or more path segments along with their trailing `/`.
+    Segments,
+    /// `**` which is not followed by `/`, matching any characters.
+    GlobStar,
+    /// `[...]`, matching one character other than `/` within, or without if negated, the ranges.
+    Class { negated: bool, ranges: Vec<(char, char)> },
+}
+
+/// A compiled glob pattern, as used in document filters, file system watchers and file operation
+/// filters.
+///
+/// See the [module documentation](self) for the supported syntax.
+///
+/// # Example
+///
+/// ```rust
+/// # use lspower::selector::Glob;
+/// let glob = Glob::new("**/*.{ts,js}").unwrap();
+/// assert!(glob.is_match("/project/src/index.ts"));
+/// assert!(!glob.is_match("/project/src/index.rs"));
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct Glob {
+    pattern: String,
+    /// The patterns resulting from expanding the alternatives of `{}` groups.
+    alternatives: Vec<Vec<Token>>,
+}
+
+impl Glob {
+    /// Parses the given glob pattern.
+    pub fn new(pattern: &str) -> Result<Self, GlobError> {
+        let error = |reason| GlobError {
+            pattern: pattern.to_owned(),
+            reason,
+        };
+        let alternatives = expand(pattern).map_err(error)?;
+        let alternatives = alternatives
+            .iter()
+            .map(|alternative| tokenize(alternative))
+            .collect::<Result<_, _>>()
+            .map_err(error)?;
+        Ok(Glob {
+            pattern: pattern.to_owned(),
+            alternatives,
+        })
+    }
+
+    /// Returns the pattern this glob was parsed from.
+    pub fn as_str(&self) -> &str {
+        &self.pattern
+    }
+
+    /// Returns whether the given `/`-separated path matches the pattern.
+    ///
+    /// Patterns which are relative, i.e. which start neither with `/` nor with `**`, also match the
+    /// trailing segments of the path, so that `src/*.rs` matches `/project/src/main.rs`, and a
+    /// pattern like `*.{ts,js}` matches files with the given extension in any directory.
+    pub fn is_match(&self, path: &str) -> bool {
+        let path: Vec<char> = path.chars().collect();
+        self.alternatives.iter().any(|tokens| {
+            let relative = !matches!(tokens.first(), Some(Token::Char('/') | Token::Segments | Token::GlobStar));
+            let mut matcher = Matcher::new(tokens, &path);
+            matcher.matches(0, 0)
+                || relative
+                    && (0 .. path.len())
+                        .filter(|&index| path[index] == '/')
+                        .any(|index| matcher.matches(0, index + 1))
+        })
+    }
+}
+
+impl Display for Glob {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str(&self.pattern)
+    }
+}
+
+/// The maximum number of patterns the `{}` groups of a pattern may expand into.
+const MAX_ALTERNATIVES: usize = 1024;
+
+/// Expands the `{}` groups of a pattern into the patterns of all their combinations.
+fn expand(pattern: &str) -> Result<Vec<String>, &'static str> {
+    let open = match pattern.find('{') {
+        Some(open) => open,
+        None if pattern.contains('}') => return Err("unmatched `}`"),
+        None => return Ok(vec![pattern.to_owned()]),
+    };
+
+    // Find the matching closing brace and the commas separating the top-level alternatives.
+    let mut depth = 0;
+    let mut separators = vec![open];
+    let mut close = None;
+    for (index, c) in pattern[open ..].char_indices().map(|(index, c)| (open + index, c)) {
+        match c {
+            '{' => depth += 1,
+            '}' if depth == 1 => {
+                close = Some(index);
+                break;
+            },
+            '}' => depth -= 1,
+            ',' if depth == 1 => separators.push(index),
+            _ => {},
+        }
+    }
+    let close = close.ok_or("unclosed `{`")?;
+    separators.push(close);
+
+    let (prefix, suffix) = (&pattern[.. open], &pattern[close + 1 ..]);
+    let mut expanded = Vec::new();
+    for bounds in separators.windows(2) {
+        let alternative = format!("{}{}{}", prefix, &pattern[bounds[0] + 1 .. bounds[1]], suffix);
+        expanded.extend(expand(&alternative)?);
+        if expanded.len() > MAX_ALTERNATIVES {
+            return Err("too many alternatives");
+        }
+    }
+    Ok(expanded)
+}
+
+/// Parses a pattern without `{}` groups.
+fn tokenize(pattern: &str) -> Result<Vec<Token>, &'static str> {
+    let mut tokens = Vec::new();
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        let token = match c {
+            '?' => Token::Any,
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                if chars.peek() == Some(&'/') {
+                    chars.next();
+                    Token::Segments
+                } else {
+                    Token::GlobStar
+                }
+            },
+            '*' => Token::Star,
+            '[' => {
+                let negated = chars.next_if(|&c| c == '!').is_some();
+                let mut ranges = Vec::new();
+                loop {
+                    let start = match chars.next() {
+                        Some(']') if !ranges.is_empty() => break,
+                        Some(c) => c,
+                        None => return Err("unclosed `[`"),
+                    };
+                    let end = match chars.next_if_eq(&'-') {
+                        Some(_) => match chars.next() {
+                            // A trailing `-` is matched literally.
+                            Some(']') => {
+                                ranges.push((start, start));
+                                ranges.push(('-', '-'));
+                                break;
+                            },
+                            Some(end) => end,
+                            None => return Err("unclosed `[`"),
+                        },
+                        None => start,
+                    };
+                    ranges.push((start, end));
+                }
+                Token::Class { negated, ranges }
+            },
+            c => Token::Char(c),
+        };
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+/// Matches the tokens of a pattern against a path, remembering the outcome for each position in
+/// both, so that patterns with many wildcards take time proportional to the product of their
+/// lengths rather than exponential time.
+struct Matcher<'a> {
+    tokens: &'a [Token],
+    path: &'a [char],
+    memo: Vec<Option<bool>>,
+}
+
+impl<'a> Matcher<'a> {
+    fn new(tokens: &'a [Token], path: &'a [char]) -> Self {
+        let memo = vec![None; (tokens.len() + 1) * (path.len() + 1)];
+        Matcher { tokens, path, memo }
+    }
+
+    /// Returns whether the tokens from `token` on match the path from `start` on.
+    fn matches(&mut self, token: usize, start: usize) -> bool {
+        let key = token * (self.path.len() + 1) + start;
+        if let Some(matched) = self.memo[key] {
+            return matched;
+        }
+        let matched = self.compute(token, start);
+        self.memo[key] = Some(matched);
+        matched
+    }
+
+    fn compute(&mut self, token: usize, start: usize) -> bool {
+        let path = &self.path[start ..];
+        let next = token + 1;
+        match self.tokens.get(token) {
+            None => path.is_empty(),
+            Some(Token::Char(c)) => path.first() == Some(c) && self.matches(next, start + 1),
+            Some(Token::Any) => path.first().is_some_and(|&c| c != '/') && self.matches(next, start + 1),
+            Some(Token::Star) => {
+                let segment = path.iter().position(|&c| c == '/').unwrap_or(path.len());
+                (0 ..= segment).any(|len| self.matches(next, start + len))
+            },
+            Some(Token::Segments) => {
+                self.matches(next, start)
+                    || (start .. self.path.len())
+                        .filter(|&index| self.path[index] == '/')
+                        .any(|index| self.matches(next, index + 1))
+            },
+            Some(Token::GlobStar) => (start ..= self.path.len()).any(|index| self.matches(next, index)),
+            Some(Token::Class { negated, ranges }) => match path.first() {
+                Some(&c) if c != '/' => {
+                    let contained = ranges.iter().any(|&(low, high)| low <= c && c <= high);
+                    contained != *negated && self.matches(next, start + 1)
+                },
+                _ => false,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_match(pattern: &str, path: &str) -> bool {
+        Glob::new(pattern).unwrap().is_match(path)
+    }
+
+    #[test]
+    fn wildcards() {
+        assert!(is_match("/src/*.rs", "/src/main.rs"));
+        assert!(!is_match("/src/*.rs", "/src/bin/main.rs"));
+        assert!(is_match("/src/ma?n.rs", "/src/main.rs"));
+        assert!(!is_match("/src/ma?n.rs", "/src/man.rs"));
+        assert!(!is_match("/src?main.rs", "/src/main.rs"));
+    }
+
+    #[test]
+    fn globstars() {
+        assert!(is_match("**/*.rs", "/src/bin/main.rs"));
+        assert!(is_match("/src/**/*.rs", "/src/main.rs"));
+        assert!(is_match("/src/**/*.rs", "/src/bin/nested/main.rs"));
+        assert!(!is_match("/src/**/*.rs", "/tests/main.rs"));
+        assert!(is_match("/target/**", "/target/debug/build"));
+        assert!(!is_match("/target/**", "/src/target"));
+    }
+
+    #[test]
+    fn relative_patterns() {
+        assert!(is_match("*.rs", "/project/src/main.rs"));
+        assert!(is_match("src/*.rs", "/project/src/main.rs"));
+        assert!(!is_match("src/*.rs", "/project/tests/src.rs"));
+        assert!(!is_match("/*.rs", "/project/main.rs"));
+    }
+
+    #[test]
+    fn alternatives() {
+        assert!(is_match("**/*.{ts,js}", "/index.ts"));
+        assert!(is_match("**/*.{ts,js}", "/index.js"));
+        assert!(!is_match("**/*.{ts,js}", "/index.rs"));
+        assert!(is_match("**/{src,tests/{unit,e2e}}/*.rs", "/tests/e2e/main.rs"));
+        assert!(is_match("**/*.{,d.}ts", "/index.d.ts"));
+    }
+
+    #[test]
+    fn ranges() {
+        assert!(is_match("**/example.[0-9]", "/example.5"));
+        assert!(!is_match("**/example.[0-9]", "/example.a"));
+        assert!(is_match("**/example.[!0-9]", "/example.a"));
+        assert!(!is_match("**/example.[!0-9]", "/example.5"));
+        assert!(is_match("**/[ab-]", "/-"));
+        assert!(!is_match("/src[/]main.rs", "/src/main.rs"));
+    }
+
+    #[test]
+    fn invalid_patterns() {
+        assert_eq!(Glob::new("*.{ts,js").unwrap_err().to_string(), "invalid glob pattern `*.{ts,js`: unclosed `{`");
+        assert!(Glob::new("*.ts}").is_err());
+        assert!(Glob::new("*.[0-9").is_err());
+        assert_eq!(Glob::new(&"{a,b}".repeat(11)).unwrap_err().reason, "too many alternatives");
+    }
+
+    #[test]
+    fn many_wildcards() {
+        let pattern = "*a".repeat(20) + "b";
+        assert!(!is_match(&pattern, &"a".repeat(64)));
+        assert!(is_match(&format!("**/{}", "**/a".repeat(10)), &"/a".repeat(40)));
+    }
+
+    #[test]
+    fn document_filters() {
+        let uri = Url::parse("file:///home/user/My%20Project/src/main.rs").unwrap();
+        let filter = |language: Option<&str>, scheme: Option<&str>, pattern: Option<&str>| DocumentFilter {
+            language: language.map(Into::into),
+            scheme: scheme.map(Into::into),
+            pattern: pattern.map(Into::into),
+        };
+
+        assert!(matches_filter(&filter(None, None, None), &uri, "rust"));
+        assert!(matches_filter(&filter(Some("rust"), Some("file"), None), &uri, "rust"));
+        assert!(!matches_filter(&filter(Some("rust"), Some("untitled"), None), &uri, "rust"));
+        assert!(!matches_filter(&filter(Some("toml"), None, None), &uri, "rust"));
+        assert!(matches_filter(&filter(None, None, Some("**/My Project/**")), &uri, "rust"));
+        assert!(!matches_filter(&filter(None, None, Some("**/*.{rs")), &uri, "rust"));
+
+        let selector = vec![filter(Some("toml"), None, None), filter(None, None, Some("*.rs"))];
+        assert!(matches(&selector, &uri, "rust"));
+        assert!(!matches(&selector[.. 1].to_vec(), &uri, "rust"));
+        assert!(!matches(&Vec::new(), &uri, "rust"));
+    }
+}
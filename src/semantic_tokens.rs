@@ -0,0 +1,355 @@
+//! Encoding of semantic tokens and incremental `textDocument/semanticTokens/full/delta` responses.
+//!
+//! Semantic tokens are sent to the client relative to the previous token. A
+//! [`SemanticTokensBuilder`] encodes tokens given at absolute positions, in any order. Servers
+//! which support deltas keep the last tokens sent for each document in a [`SemanticTokensCache`],
+//! which hands out the result IDs, validates the `previousResultId` of delta requests and answers
+//! them with the edits computed by [`diff`], or with all tokens if the previous result is unknown.
+//!
+//! # Example
+//!
+//! ```rust
+//! # use lspower::{jsonrpc::Result, lsp::*, semantic_tokens::{SemanticTokensBuilder, SemanticTokensCache}};
+//! fn tokens(text: &str) -> Vec<SemanticToken> {
+//!     let mut builder = SemanticTokensBuilder::new();
+//!     for (line, content) in text.lines().enumerate() {
+//!         if let Some(start) = content.find("fn") {
+//!             builder.push(Position::new(line as u32, start as u32), 2, 0, 0);
+//!         }
+//!     }
+//!     builder.build()
+//! }
+//!
+//! let cache = SemanticTokensCache::default();
+//! let uri = Url::parse("file:///a.rs").unwrap();
+//!
+//! // In `semantic_tokens_full`:
+//! let full = cache.full(&uri, tokens("fn main() {}"));
+//!
+//! // In `semantic_tokens_full_delta`:
+//! let previous_result_id = full.result_id.unwrap();
+//! let delta = cache.delta(&uri, &previous_result_id, tokens("fn main() {}\nfn foo() {}"));
+//! assert!(matches!(delta, SemanticTokensFullDeltaResult::TokensDelta(_)));
+//! ```
+
+use crate::result_cache::ResultCache;
+use std::{
+    fmt::{self, Debug, Formatter},
+    sync::Mutex,
+};
+
+/// Builds the relative encoding of semantic tokens from tokens at absolute positions.
+#[derive(Clone, Debug, Default)]
+pub struct SemanticTokensBuilder {
+    tokens: Vec<AbsoluteToken>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct AbsoluteToken {
+    line: u32,
+    start: u32,
+    length: u32,
+    token_type: u32,
+    token_modifiers_bitset: u32,
+}
+
+impl SemanticTokensBuilder {
+    /// Creates a builder without tokens.
+    pub fn new() -> Self {
+        SemanticTokensBuilder::default()
+    }
+
+    /// Adds a token of the given length starting at the given position.
+    ///
+    /// The type is the index into the token types, and each bit set in the modifiers refers to an
+    /// index into the token modifiers of the legend the server declared in its capabilities.
+    pub fn push(&mut self, start: lsp::Position, length: u32, token_type: u32, token_modifiers_bitset: u32) {
+        self.tokens.push(AbsoluteToken {
+            line: start.line,
+            start: start.character,
+            length,
+            token_type,
+            token_modifiers_bitset,
+        });
+    }
+
+    /// Returns the number of tokens added so far.
+    pub fn len(&self) -> usize {
+        self.tokens.len()
+    }
+
+    /// Returns `true` if no tokens were added.
+    pub fn is_empty(&self) -> bool {
+        self.tokens.is_empty()
+    }
+
+    /// Returns the tokens sorted by position and encoded relative to each other.
+    pub fn build(mut self) -> Vec<lsp::SemanticToken> {
+        self.tokens.sort_by_key(|token| (token.line, token.start));
+
+        let mut previous = (0, 0);
+        let tokens = self.tokens.into_iter().map(|token| {
+            let (line, start) = previous;
+            previous = (token.line, token.start);
+            lsp::SemanticToken {
+                delta_line: token.line - line,
+                delta_start: if token.line == line { token.start - start } else { token.start },
+                length: token.length,
+                token_type: token.token_type,
+                token_modifiers_bitset: token.token_modifiers_bitset,
+            }
+        });
+        tokens.collect()
+    }
+}
+
+/// Returns the edits turning the previous tokens into the current ones.
+///
+/// The tokens which differ between the common prefix and suffix of both are replaced by a single
+/// edit, which is empty if the tokens are equal. As required by the protocol, the offsets of the
+/// edit count the integers of the encoded tokens, five per token.
+pub fn diff(previous: &[lsp::SemanticToken], current: &[lsp::SemanticToken]) -> Vec<lsp::SemanticTokensEdit> {
+    let prefix = previous.iter().zip(current).take_while(|(a, b)| a == b).count();
+    let suffix = previous[prefix ..]
+        .iter()
+        .rev()
+        .zip(current[prefix ..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let deleted = previous.len() - prefix - suffix;
+    let inserted = &current[prefix .. current.len() - suffix];
+    if deleted == 0 && inserted.is_empty() {
+        return Vec::new();
+    }
+
+    vec![lsp::SemanticTokensEdit {
+        start: 5 * prefix as u32,
+        delete_count: 5 * deleted as u32,
+        data: if inserted.is_empty() { None } else { Some(inserted.to_vec()) },
+    }]
+}
+
+/// Cache of the semantic tokens last sent to the client for each document, for answering
+/// `textDocument/semanticTokens/full/delta` requests.
+///
+/// The cache keeps the tokens of up to `capacity` documents; once exceeded, the tokens of the
+/// least recently requested document are evicted. Documents are keyed by their [normalized] URIs
+/// and should be [removed] once the client closes them.
+///
+/// [normalized]: crate::uri::normalize
+/// [removed]: SemanticTokensCache::remove
+pub struct SemanticTokensCache {
+    inner: Mutex<ResultCache<Vec<lsp::SemanticToken>>>,
+}
+
+impl SemanticTokensCache {
+    /// Creates a cache keeping the tokens of up to `capacity` documents.
+    ///
+    /// A capacity of `0` is treated as `1`.
+    pub fn new(capacity: usize) -> Self {
+        SemanticTokensCache {
+            inner: Mutex::new(ResultCache::new(capacity)),
+        }
+    }
+
+    /// Keeps the tokens of the document and returns them as a full response, with a new result ID
+    /// which the client can refer to in its next delta request.
+    pub fn full(&self, uri: &lsp::Url, tokens: Vec<lsp::SemanticToken>) -> lsp::SemanticTokens {
+        let result_id = self.inner.lock().unwrap().insert(uri, tokens.clone());
+        lsp::SemanticTokens {
+            result_id: Some(result_id),
+            data: tokens,
+        }
+    }
+
+    /// Keeps the tokens of the document and returns the edits from the tokens of the previous
+    /// result, with a new result ID.
+    ///
+    /// If the previous result is not the last one kept for the document, e.g. because it was
+    /// evicted or the client refers to an outdated result, all tokens are returned instead.
+    pub fn delta(
+        &self,
+        uri: &lsp::Url,
+        previous_result_id: &str,
+        tokens: Vec<lsp::SemanticToken>,
+    ) -> lsp::SemanticTokensFullDeltaResult {
+        let mut inner = self.inner.lock().unwrap();
+        let edits = match inner.get(uri) {
+            Some(cached) if cached.result_id == previous_result_id => diff(&cached.value, &tokens),
+            cached => {
+                log::debug!(
+                    "unknown previous semantic tokens result {:?} for {} (last: {:?}), sending all tokens",
+                    previous_result_id,
+                    uri,
+                    cached.map(|cached| &cached.result_id)
+                );
+                let result_id = inner.insert(uri, tokens.clone());
+                return lsp::SemanticTokensFullDeltaResult::Tokens(lsp::SemanticTokens {
+                    result_id: Some(result_id),
+                    data: tokens,
+                });
+            },
+        };
+
+        let result_id = inner.insert(uri, tokens);
+        lsp::SemanticTokensFullDeltaResult::TokensDelta(lsp::SemanticTokensDelta {
+            result_id: Some(result_id),
+            edits,
+        })
+    }
+
+    /// Returns the tokens last kept for the document, along with their result ID.
+    pub fn get(&self, uri: &lsp::Url) -> Option<(String, Vec<lsp::SemanticToken>)> {
+        let inner = self.inner.lock().unwrap();
+        let cached = inner.get(uri)?;
+        Some((cached.result_id.clone(), cached.value.clone()))
+    }
+
+    /// Forgets the tokens of the document, e.g. once the client closed it.
+    pub fn remove(&self, uri: &lsp::Url) {
+        self.inner.lock().unwrap().remove(uri);
+    }
+
+    /// Forgets the tokens of all documents, e.g. after the legend changed.
+    pub fn clear(&self) {
+        self.inner.lock().unwrap().clear();
+    }
+
+    /// Returns the number of documents whose tokens are kept.
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().len()
+    }
+
+    /// Returns `true` if the tokens of no documents are kept.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for SemanticTokensCache {
+    /// Creates a cache keeping the tokens of up to 128 documents.
+    fn default() -> Self {
+        SemanticTokensCache::new(128)
+    }
+}
+
+impl Debug for SemanticTokensCache {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let inner = self.inner.lock().unwrap();
+        f.debug_struct(stringify!(SemanticTokensCache))
+            .field("capacity", &inner.capacity())
+            .field("len", &inner.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn token(delta_line: u32, delta_start: u32, length: u32) -> lsp::SemanticToken {
+        lsp::SemanticToken {
+            delta_line,
+            delta_start,
+            length,
+            token_type: 0,
+            token_modifiers_bitset: 0,
+        }
+    }
+
+    fn uri(path: &str) -> lsp::Url {
+        lsp::Url::parse(&format!("file:///{}", path)).unwrap()
+    }
+
+    #[test]
+    fn build() {
+        let mut builder = SemanticTokensBuilder::new();
+        builder.push(lsp::Position::new(2, 4), 3, 0, 0);
+        builder.push(lsp::Position::new(0, 2), 1, 0, 0);
+        builder.push(lsp::Position::new(0, 8), 2, 0, 0);
+        builder.push(lsp::Position::new(2, 10), 5, 0, 0);
+        assert_eq!(builder.len(), 4);
+        assert_eq!(builder.build(), [token(0, 2, 1), token(0, 6, 2), token(2, 4, 3), token(0, 6, 5)]);
+        assert!(SemanticTokensBuilder::new().build().is_empty());
+    }
+
+    #[test]
+    fn diff() {
+        let previous = [token(0, 1, 1), token(1, 0, 2), token(1, 0, 3)];
+        assert_eq!(super::diff(&previous, &previous), []);
+
+        let inserted = [token(0, 1, 1), token(1, 0, 2), token(0, 4, 4), token(1, 0, 3)];
+        let expected = lsp::SemanticTokensEdit {
+            start: 10,
+            delete_count: 0,
+            data: Some(vec![token(0, 4, 4)]),
+        };
+        assert_eq!(super::diff(&previous, &inserted), [expected]);
+
+        let expected = lsp::SemanticTokensEdit {
+            start: 5,
+            delete_count: 5,
+            data: None,
+        };
+        assert_eq!(super::diff(&previous, &[token(0, 1, 1), token(1, 0, 3)]), [expected]);
+
+        let expected = lsp::SemanticTokensEdit {
+            start: 0,
+            delete_count: 15,
+            data: Some(vec![token(2, 0, 1)]),
+        };
+        assert_eq!(super::diff(&previous, &[token(2, 0, 1)]), [expected]);
+    }
+
+    #[test]
+    fn delta() {
+        let cache = SemanticTokensCache::new(1);
+        let full = cache.full(&uri("a.rs"), vec![token(0, 1, 1)]);
+        assert_eq!(full.result_id.as_deref(), Some("0"));
+
+        let delta = cache.delta(&uri("a.rs"), "0", vec![token(0, 1, 1), token(1, 0, 2)]);
+        let expected = lsp::SemanticTokensDelta {
+            result_id: Some("1".into()),
+            edits: vec![lsp::SemanticTokensEdit {
+                start: 5,
+                delete_count: 0,
+                data: Some(vec![token(1, 0, 2)]),
+            }],
+        };
+        assert_eq!(delta, lsp::SemanticTokensFullDeltaResult::TokensDelta(expected));
+
+        // The client refers to an outdated result.
+        let delta = cache.delta(&uri("a.rs"), "0", vec![token(0, 1, 1)]);
+        let expected = lsp::SemanticTokens {
+            result_id: Some("2".into()),
+            data: vec![token(0, 1, 1)],
+        };
+        assert_eq!(delta, lsp::SemanticTokensFullDeltaResult::Tokens(expected));
+
+        // The tokens of `a.rs` are evicted.
+        cache.full(&uri("b.rs"), Vec::new());
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.get(&uri("a.rs")), None);
+        let delta = cache.delta(&uri("a.rs"), "2", Vec::new());
+        assert!(matches!(delta, lsp::SemanticTokensFullDeltaResult::Tokens(_)));
+
+        cache.remove(&uri("a.rs"));
+        assert_eq!(cache.get(&uri("b.rs")), None);
+        cache.clear();
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn eviction() {
+        let cache = SemanticTokensCache::new(2);
+        cache.full(&uri("a.rs"), Vec::new());
+        cache.full(&uri("b.rs"), Vec::new());
+        cache.delta(&uri("a.rs"), "0", vec![token(0, 0, 1)]);
+        cache.full(&uri("c.rs"), Vec::new());
+
+        assert_eq!(cache.get(&uri("a.rs")), Some(("2".into(), vec![token(0, 0, 1)])));
+        assert_eq!(cache.get(&uri("b.rs")), None);
+        assert!(cache.get(&uri("c.rs")).is_some());
+    }
+}
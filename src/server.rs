@@ -2,21 +2,32 @@
 
 use std::{
     fmt,
-    sync::atomic::{AtomicUsize, Ordering},
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
 };
 
 /// Atomic value which represents the current state of the server.
-pub(crate) struct State(AtomicUsize);
+pub(crate) struct State(AtomicUsize, AtomicBool);
 
 impl State {
     pub(crate) const fn new() -> Self {
-        State(AtomicUsize::new(StateKind::Uninitialized as usize))
+        State(AtomicUsize::new(StateKind::Uninitialized as usize), AtomicBool::new(false))
     }
 
     pub(crate) fn set(&self, state: StateKind) {
+        match state {
+            StateKind::Uninitialized => self.1.store(false, Ordering::SeqCst),
+            StateKind::ShutDown => self.1.store(true, Ordering::SeqCst),
+            _ => {},
+        }
         self.0.store(state as usize, Ordering::SeqCst);
     }
 
+    /// Returns whether the server received a `shutdown` request since it was last reset, even if
+    /// it has exited since.
+    pub(crate) fn is_shut_down(&self) -> bool {
+        self.1.load(Ordering::SeqCst)
+    }
+
     pub(crate) fn get(&self) -> StateKind {
         match self.0.load(Ordering::SeqCst) {
             0 => StateKind::Uninitialized,
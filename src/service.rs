@@ -1,5 +1,52 @@
 //! Service abstraction for language servers.
 
+mod anomaly;
+mod broadcast;
+mod coalesce;
+mod dollar;
+mod fallback;
+mod filters;
+mod hooks;
+mod intercept;
+mod latency;
+mod overrides;
+mod protocol_log;
+mod replay;
+mod security;
+mod session;
+mod settings;
+mod shedding;
+mod strict;
+mod swap;
+mod unhandled;
+
+pub(crate) use self::{
+    anomaly::Anomalies,
+    fallback::EmptyResults,
+    filters::ResponseFilters,
+    hooks::{LifecycleHooks, Transition},
+    intercept::{DispatchHooks, RequestInterceptors},
+    overrides::MethodOverrides,
+    settings::SettingsHooks,
+    unhandled::{not_found, not_implemented, UnhandledNotifications},
+};
+pub use self::{
+    anomaly::{AnomalyEvent, AnomalyEventStream},
+    coalesce::CoalescingPolicy,
+    dollar::DollarMethodPolicy,
+    intercept::Interception,
+    latency::LatencyBudget,
+    overrides::DefaultHandler,
+    protocol_log::ProtocolLog,
+    replay::InitializingPolicy,
+    security::SecurityPolicy,
+    session::SessionHandle,
+    settings::SettingsChange,
+    shedding::LoadSheddingPolicy,
+    strict::{ProtocolViolation, StrictMode, ViolationAction},
+    swap::{BackendHandle, Drained, ReplaceError},
+    unhandled::{UnhandledNotification, UnhandledNotificationPolicy, UnhandledNotificationStream},
+};
 use futures::{
     channel::mpsc,
     future,
@@ -7,18 +54,28 @@ use futures::{
     FutureExt,
 };
 use std::{
+    collections::HashSet,
     error::Error,
     fmt::{self, Debug, Display, Formatter},
     future::Future,
     pin::Pin,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
     task::{Context, Poll},
+    time::Duration,
 };
 use tower_service::Service;
 
 use crate::Client;
 
 /// Error that occurs when attempting to call the language server after it has already exited.
+///
+/// The [`Server`] serving the language server reports why it exited as an [`ExitReason`].
+///
+/// [`Server`]: crate::Server
+/// [`ExitReason`]: crate::ExitReason
 #[derive(Clone, Debug, PartialEq)]
 pub struct ExitedError;
 
@@ -31,21 +88,297 @@ impl Display for ExitedError {
 impl Error for ExitedError {
 }
 
+/// Error that occurs when attempting to reset a language server which cannot be restarted.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ResetError {
+    /// The language server has already exited.
+    Exited,
+    /// The service was not built with [`LspServiceBuilder::restartable`].
+    NotRestartable,
+}
+
+impl Display for ResetError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            ResetError::Exited => f.write_str("language server has exited"),
+            ResetError::NotRestartable => f.write_str("language server is not restartable"),
+        }
+    }
+}
+
+impl Error for ResetError {
+}
+
+/// Handle reporting the exit code recommended for the server process, obtained with
+/// [`LspService::exit_code`].
+///
+/// The specification asks the server to exit with code `0` if it received a `shutdown` request
+/// before the `exit` notification, and with code `1` otherwise. This includes the cases where the
+/// connection closed or the [`Watchdog`] stopped the server before the client shut it down.
+///
+/// The handle stays valid once the service was moved into [`Server::serve`], so that binaries can
+/// comply with the specification without tracking the lifecycle themselves.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use lspower::{jsonrpc::Result, lsp::*, LanguageServer, LspService, Server};
+/// # #[derive(Debug)]
+/// # struct Backend;
+/// # #[lspower::async_trait]
+/// # impl LanguageServer for Backend {
+/// #     async fn initialize(&self, _: InitializeParams) -> Result<InitializeResult> {
+/// #         Ok(InitializeResult::default())
+/// #     }
+/// #     async fn shutdown(&self) -> Result<()> {
+/// #         Ok(())
+/// #     }
+/// # }
+/// #[tokio::main]
+/// async fn main() {
+///     let (service, messages) = LspService::new(|_| Backend);
+///     let exit_code = service.exit_code();
+///
+///     let (stdin, stdout) = (tokio::io::stdin(), tokio::io::stdout());
+///     Server::new(stdin, stdout).interleave(messages).serve(service).await;
+///     std::process::exit(exit_code.get());
+/// }
+/// ```
+///
+/// [`Watchdog`]: crate::Watchdog
+/// [`Server::serve`]: crate::Server::serve
+#[derive(Clone)]
+pub struct ExitCode(Arc<crate::server::State>);
+
+impl ExitCode {
+    /// Returns `0` if the server received a `shutdown` request, and `1` otherwise.
+    ///
+    /// The code is only final once the service terminated. Before, it reflects whether the server
+    /// was shut down so far.
+    pub fn get(&self) -> i32 {
+        if self.is_success() {
+            0
+        } else {
+            1
+        }
+    }
+
+    /// Returns whether the server received a `shutdown` request, i.e. whether [`get`] returns `0`.
+    ///
+    /// [`get`]: ExitCode::get
+    pub fn is_success(&self) -> bool {
+        self.0.is_shut_down()
+    }
+}
+
+impl Debug for ExitCode {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_tuple(stringify!(ExitCode)).field(&self.get()).finish()
+    }
+}
+
+/// Creates a fresh backend when the service is reset.
+type Factory = Arc<dyn Fn(Client) -> Arc<dyn crate::LanguageServer> + Send + Sync>;
+
 /// Stream of messages produced by the language server.
+///
+/// By default, this stream is the only consumer of the messages. A service created with
+/// [`LspService::new_with_broadcast`] or [`LspServiceBuilder::broadcast`] instead allows further
+/// consumers, such as audit logs or metrics, to [`subscribe`](MessageStream::subscribe) to the
+/// messages alongside the transport.
 #[derive(Debug)]
 #[must_use = "streams do nothing unless polled"]
-pub struct MessageStream(mpsc::Receiver<crate::jsonrpc::Outgoing>);
+pub struct MessageStream {
+    source: Source,
+    log: Option<(ProtocolLog, Arc<crate::server::State>)>,
+}
+
+#[derive(Debug)]
+enum Source {
+    Single(crate::client::Outbox),
+    Broadcast(broadcast::Subscriber),
+}
 
 impl Stream for MessageStream {
     type Item = crate::jsonrpc::Outgoing;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
-        let recv = &mut self.as_mut().0;
-        Pin::new(recv).poll_next(cx)
+        let this = self.as_mut().get_mut();
+        let log = |message: &_| {
+            if let Some((log, state)) = &this.log {
+                log.outgoing(message, None, state.get());
+            }
+        };
+        match &mut this.source {
+            Source::Single(rx) => {
+                let message = Pin::new(rx).poll_next(cx);
+                if let Poll::Ready(Some(message)) = &message {
+                    log(message);
+                }
+                message
+            },
+            // Each message is only logged once, by the subscriber which received it first.
+            Source::Broadcast(subscriber) => subscriber.poll_next(cx, log),
+        }
+    }
+}
+
+impl MessageStream {
+    /// Converts this stream into a stream of typed [`ClientEvent`] values.
+    ///
+    /// This is useful for tests and embedders which want to observe the server-to-client traffic
+    /// without matching on serialized JSON-RPC messages.
+    pub fn events(self) -> ClientEventStream {
+        ClientEventStream(self)
+    }
+
+    /// Returns another stream receiving every message produced from now on, or `None` if the
+    /// service was not created in broadcast mode.
+    ///
+    /// Subscribers are independent of each other: a subscriber falling behind by more than the
+    /// broadcast capacity misses the oldest messages rather than stalling the others. Messages
+    /// are only held back from the client while no subscriber is polled at all.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # use lspower::{jsonrpc::Result, lsp::*, Client, LanguageServer, LspService, Server};
+    /// # use futures::StreamExt;
+    /// # #[derive(Debug)]
+    /// # struct Backend;
+    /// # #[lspower::async_trait]
+    /// # impl LanguageServer for Backend {
+    /// #     async fn initialize(&self, _: InitializeParams) -> Result<InitializeResult> {
+    /// #         Ok(InitializeResult::default())
+    /// #     }
+    /// #     async fn shutdown(&self) -> Result<()> {
+    /// #         Ok(())
+    /// #     }
+    /// # }
+    /// # async fn run() {
+    /// let (service, messages) = LspService::new_with_broadcast(|_: Client| Backend, 64);
+    /// let audit = messages.subscribe().unwrap();
+    /// tokio::spawn(audit.for_each(|message| async move { log::info!("sent {:?}", message) }));
+    ///
+    /// let (stdin, stdout) = (tokio::io::stdin(), tokio::io::stdout());
+    /// Server::new(stdin, stdout).interleave(messages).serve(service).await;
+    /// # }
+    /// ```
+    pub fn subscribe(&self) -> Option<MessageStream> {
+        match &self.source {
+            Source::Single(_) => None,
+            Source::Broadcast(subscriber) => Some(MessageStream {
+                source: Source::Broadcast(subscriber.subscribe()),
+                log: self.log.clone(),
+            }),
+        }
     }
 }
 
 impl FusedStream for MessageStream {
+    fn is_terminated(&self) -> bool {
+        match &self.source {
+            Source::Single(rx) => rx.is_terminated(),
+            Source::Broadcast(subscriber) => subscriber.is_terminated(),
+        }
+    }
+}
+
+/// A typed view of a message sent from the language server to the client.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ClientEvent {
+    /// A [`textDocument/publishDiagnostics`] notification.
+    ///
+    /// [`textDocument/publishDiagnostics`]: https://microsoft.github.io/language-server-protocol/specification#textDocument_publishDiagnostics
+    Diagnostics(lsp::PublishDiagnosticsParams),
+    /// A [`window/logMessage`] notification.
+    ///
+    /// [`window/logMessage`]: https://microsoft.github.io/language-server-protocol/specification#window_logMessage
+    LogMessage(lsp::LogMessageParams),
+    /// A [`window/showMessage`] notification.
+    ///
+    /// [`window/showMessage`]: https://microsoft.github.io/language-server-protocol/specification#window_showMessage
+    ShowMessage(lsp::ShowMessageParams),
+    /// A [`telemetry/event`] notification.
+    ///
+    /// [`telemetry/event`]: https://microsoft.github.io/language-server-protocol/specification#telemetry_event
+    Telemetry(serde_json::Value),
+    /// Any other notification sent to the client.
+    Notification {
+        /// The method name of the notification.
+        method: String,
+        /// The raw parameters of the notification.
+        params: serde_json::Value,
+    },
+    /// A request sent to the client.
+    Request {
+        /// The request ID used to correlate the response from the client.
+        id: crate::jsonrpc::Id,
+        /// The method name of the request.
+        method: String,
+        /// The raw parameters of the request.
+        params: serde_json::Value,
+    },
+    /// A response to a client-to-server request.
+    Response(crate::jsonrpc::Response),
+}
+
+impl From<crate::jsonrpc::Outgoing> for ClientEvent {
+    fn from(message: crate::jsonrpc::Outgoing) -> Self {
+        use lsp::notification::{LogMessage, Notification, PublishDiagnostics, ShowMessage, TelemetryEvent};
+
+        fn parse<N: Notification>(params: &serde_json::Value) -> Option<N::Params> {
+            serde_json::from_value(params.clone()).ok()
+        }
+
+        let request = match message {
+            crate::jsonrpc::Outgoing::Response(response) => return ClientEvent::Response(response),
+            crate::jsonrpc::Outgoing::Request(request) => request,
+        };
+
+        let method = request.method();
+        let params = request.params();
+
+        if let Some(id) = request.id() {
+            return ClientEvent::Request {
+                id: id.clone(),
+                method: method.to_owned(),
+                params: params.clone(),
+            };
+        }
+
+        let event = match method {
+            PublishDiagnostics::METHOD => parse::<PublishDiagnostics>(params).map(ClientEvent::Diagnostics),
+            LogMessage::METHOD => parse::<LogMessage>(params).map(ClientEvent::LogMessage),
+            ShowMessage::METHOD => parse::<ShowMessage>(params).map(ClientEvent::ShowMessage),
+            TelemetryEvent::METHOD => Some(ClientEvent::Telemetry(params.clone())),
+            _ => None,
+        };
+
+        event.unwrap_or_else(|| ClientEvent::Notification {
+            method: method.to_owned(),
+            params: params.clone(),
+        })
+    }
+}
+
+/// Stream of typed [`ClientEvent`] values produced by the language server.
+///
+/// This stream is created by the [`MessageStream::events`] method.
+#[derive(Debug)]
+#[must_use = "streams do nothing unless polled"]
+pub struct ClientEventStream(MessageStream);
+
+impl Stream for ClientEventStream {
+    type Item = ClientEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let stream = &mut self.as_mut().0;
+        Pin::new(stream).poll_next(cx).map(|message| message.map(ClientEvent::from))
+    }
+}
+
+impl FusedStream for ClientEventStream {
     fn is_terminated(&self) -> bool {
         self.0.is_terminated()
     }
@@ -69,11 +402,18 @@ impl FusedStream for MessageStream {
 /// The service shuts down and stops serving requests after the [`exit`] notification is received.
 /// [`exit`]: https://microsoft.github.io/language-server-protocol/specification#exit
 pub struct LspService {
-    server: Arc<dyn crate::LanguageServer>,
+    backend: swap::SharedBackend,
     pending_server: crate::jsonrpc::ServerRequests,
     pending_client: Arc<crate::jsonrpc::ClientRequests>,
     client: Client,
     state: Arc<crate::server::State>,
+    authenticated: Arc<AtomicBool>,
+    reconnected: Arc<AtomicBool>,
+    documents: HashSet<lsp::Url>,
+    in_flight: Arc<coalesce::InFlight>,
+    replay: Arc<replay::ReplayQueue>,
+    options: Arc<ServiceOptions>,
+    restart: Option<Factory>,
 }
 
 impl LspService {
@@ -84,175 +424,2682 @@ impl LspService {
         F: FnOnce(crate::client::Client) -> T,
         T: crate::LanguageServer,
     {
-        let state = Arc::new(crate::server::State::new());
-        let (tx, rx) = mpsc::channel(1);
-        let messages = MessageStream(rx);
+        LspService::build(init).finish()
+    }
 
-        let pending_client = Arc::new(crate::jsonrpc::ClientRequests::new());
-        let client = crate::client::Client::new(tx, pending_client.clone(), state.clone());
+    /// Creates a new `LspService` like [`LspService::new`], whose stream of messages allows
+    /// multiple consumers with [`MessageStream::subscribe`].
+    ///
+    /// See [`LspServiceBuilder::broadcast`] for the meaning of `capacity`.
+    pub fn new_with_broadcast<T, F>(init: F, capacity: usize) -> (Self, MessageStream)
+    where
+        F: FnOnce(crate::client::Client) -> T,
+        T: crate::LanguageServer,
+    {
+        LspService::build(init).broadcast(capacity).finish()
+    }
 
-        let service = LspService {
-            server: Arc::from(init(client.clone())),
-            pending_server: crate::jsonrpc::ServerRequests::new(),
-            pending_client,
-            state,
-            client,
-        };
+    /// Creates a new `LspService` like [`LspService::new`] for the given connection, which is also
+    /// passed to the `init` closure.
+    ///
+    /// See [`ConnectionInfo`] for details.
+    ///
+    /// [`ConnectionInfo`]: crate::ConnectionInfo
+    pub fn new_with_connection<T, F>(connection: crate::ConnectionInfo, init: F) -> (Self, MessageStream)
+    where
+        F: FnOnce(crate::client::Client, &crate::ConnectionInfo) -> T,
+        T: crate::LanguageServer,
+    {
+        let info = connection.clone();
+        LspService::build(move |client| init(client, &info)).connection(connection).finish()
+    }
 
-        (service, messages)
+    /// Returns a snapshot of the requests currently in flight in either direction.
+    pub fn pending_requests(&self) -> crate::jsonrpc::PendingRequests {
+        crate::jsonrpc::PendingRequests::new(&self.pending_server, &self.pending_client)
     }
-}
 
-impl Service<crate::jsonrpc::Incoming> for LspService {
-    type Error = ExitedError;
-    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
-    type Response = Option<crate::jsonrpc::Outgoing>;
+    /// Returns a handle reporting the exit code recommended for the server process once the
+    /// service terminated.
+    ///
+    /// See [`ExitCode`] for details.
+    pub fn exit_code(&self) -> ExitCode {
+        ExitCode(self.state.clone())
+    }
 
-    fn poll_ready(&mut self, _: &mut Context) -> Poll<Result<(), Self::Error>> {
-        if self.state.get() == crate::server::StateKind::Exited {
-            Poll::Ready(Err(ExitedError))
-        } else {
-            Poll::Ready(Ok(()))
+    /// Returns a stream of the notifications which the server did not handle, either because their
+    /// [`LanguageServer`](crate::LanguageServer) method is not implemented or because their method
+    /// is unknown.
+    ///
+    /// Each call returns a new stream receiving all notifications reported from then on. How these
+    /// notifications are logged is configured with [`LspServiceBuilder::unhandled_notification_policy`].
+    pub fn unhandled_notifications(&self) -> UnhandledNotificationStream {
+        self.options.unhandled.subscribe()
+    }
+
+    /// Returns a stream of the anomalies in the lifecycle of the protocol caused by the client, such
+    /// as a second `initialize` request or an `exit` notification without a `shutdown` request.
+    ///
+    /// Each call returns a new stream receiving all anomalies reported from then on.
+    pub fn anomalies(&self) -> AnomalyEventStream {
+        self.options.anomalies.subscribe()
+    }
+
+    /// Returns a handle starting a new session once the client reconnected to the server.
+    ///
+    /// See [`SessionHandle`] for details.
+    pub fn session(&self) -> SessionHandle {
+        SessionHandle {
+            state: self.state.clone(),
+            client: self.client.clone(),
+            pending_client: self.pending_client.clone(),
+            authenticated: self.authenticated.clone(),
+            reconnected: self.reconnected.clone(),
         }
     }
 
-    fn call(&mut self, request: crate::jsonrpc::Incoming) -> Self::Future {
+    /// Returns a handle for spawning background tasks tied to the lifetime of this service.
+    pub fn spawner(&self) -> crate::task::Spawner {
+        crate::task::Spawner::new(self.client.background_tasks().clone())
+    }
+
+    /// Resets the service to its state before the `initialize` request, so that the client can
+    /// restart the server on the same transport, e.g. after the toolchain changed.
+    ///
+    /// Pending requests on both sides are canceled and background tasks are aborted. The current
+    /// backend is dropped and a fresh one is created by calling the `init` closure again, which
+    /// requires the service to be built with [`LspServiceBuilder::restartable`]. The capabilities
+    /// the client declared in its `initialize` request and all dynamic registrations are forgotten.
+    ///
+    /// Fails if the service was not built as restartable or if the server has already exited.
+    pub fn reset(&mut self) -> Result<(), ResetError> {
+        let restart = self.restart.as_ref().ok_or(ResetError::NotRestartable)?;
         if self.state.get() == crate::server::StateKind::Exited {
-            future::err(ExitedError).boxed()
-        } else {
-            match request {
-                crate::jsonrpc::Incoming::Request(req) => super::generated_impl::handle_request(
-                    self.server.clone(),
-                    &self.state,
-                    &self.pending_server,
-                    req,
-                    self.client.clone(),
-                ),
-                crate::jsonrpc::Incoming::Response(res) => {
-                    log::trace!("received client response: {:?}", res);
-                    self.pending_client.insert(res);
-                    future::ok(None).boxed()
-                },
-            }
+            return Err(ResetError::Exited);
         }
+
+        log::info!("resetting language server");
+        self.pending_server.cancel_all();
+        self.pending_client.cancel_all();
+        self.pending_client.reopen();
+        self.replay.clear();
+        self.documents.clear();
+        self.in_flight.clear();
+        self.options.settings.clear();
+        self.client.background_tasks().abort_running();
+        self.client.reset();
+        drop(self.backend.replace(restart(self.client.clone())));
+        self.state.set(crate::server::StateKind::Uninitialized);
+
+        Ok(())
     }
-}
 
-impl Debug for LspService {
-    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        f.debug_struct(stringify!(LspService))
-            .field("pending_server", &self.pending_server)
-            .field("pending_client", &self.pending_client)
-            .field("state", &self.state)
-            .finish()
+    /// Replaces the backend of the running service, e.g. to load new language logic without
+    /// dropping the connection to the client.
+    ///
+    /// Messages received from then on are handled by the new backend, which is created by calling
+    /// the `init` closure with the client of the service. Messages already dispatched to the
+    /// previous backend are neither canceled nor redirected, but handled to completion by it. The
+    /// returned future resolves once they are, so that resources shared with the new backend can
+    /// be released safely afterwards.
+    ///
+    /// Unlike [`reset`](Self::reset), this preserves the state of the service: the new backend is
+    /// not sent an `initialize` request, and pending requests on both sides, the capabilities the
+    /// client declared, dynamic registrations and background tasks carry over. Backends which need
+    /// the contents of the open documents should share a [`DocumentStore`] for this reason.
+    ///
+    /// Fails if the server is handling the `initialize` request or if it has already exited. Once
+    /// the service is served, replace its backend through the handle returned by
+    /// [`backend`](Self::backend) instead.
+    ///
+    /// [`DocumentStore`]: crate::document::DocumentStore
+    pub fn replace_backend<T, F>(&self, init: F) -> Result<Drained, ReplaceError>
+    where
+        F: FnOnce(crate::client::Client) -> T,
+        T: crate::LanguageServer,
+    {
+        self.backend().replace(init)
+    }
+
+    /// Returns a handle replacing the backend of the service, which remains usable once the
+    /// service is served.
+    ///
+    /// See [`BackendHandle`] for details.
+    pub fn backend(&self) -> BackendHandle {
+        BackendHandle {
+            backend: self.backend.clone(),
+            state: self.state.clone(),
+            client: self.client.clone(),
+        }
+    }
+
+    /// Starts building a new `LspService` with the given server backend.
+    ///
+    /// This allows configuring the service before it is created. Call
+    /// [`LspServiceBuilder::finish`] to obtain the service and its stream of notifications.
+    pub fn build<T, F>(init: F) -> LspServiceBuilder<F>
+    where
+        F: FnOnce(crate::client::Client) -> T,
+        T: crate::LanguageServer,
+    {
+        LspServiceBuilder {
+            init,
+            client_options: Default::default(),
+            options: Default::default(),
+            spawn: None,
+            on_serialization_error: None,
+            on_unexpected_response: None,
+            restart: None,
+        }
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use async_trait::async_trait;
-    use serde_json::json;
-    use tower_test::mock::Spawn;
+/// Builder for configuring an [`LspService`] before it is created.
+///
+/// This is created by the [`LspService::build`] method.
+pub struct LspServiceBuilder<F> {
+    init: F,
+    client_options: crate::client::ClientOptions,
+    options: ServiceOptions,
+    spawn: Option<crate::task::SpawnFn>,
+    on_serialization_error: Option<crate::jsonrpc::SerializationHook>,
+    on_unexpected_response: Option<crate::jsonrpc::UnexpectedResponseHook>,
+    restart: Option<Factory>,
+}
 
-    const INITIALIZE_REQUEST: &str =
-        r#"{ "jsonrpc": "2.0", "method": "initialize", "params": { "capabilities": {} }, "id": 1 }"#;
-    const INITIALIZED_NOTIF: &str = r#"{ "jsonrpc": "2.0", "method": "initialized", "params": {} }"#;
-    const SHUTDOWN_REQUEST: &str = r#"{ "jsonrpc": "2.0", "method": "shutdown", "id": 1 }"#;
-    const EXIT_NOTIF: &str = r#"{ "jsonrpc": "2.0", "method": "exit" }"#;
+/// Configuration for an [`LspService`], set through the [`LspServiceBuilder`].
+#[derive(Clone, Debug)]
+pub(crate) struct ServiceOptions {
+    pub(crate) commands: Option<Arc<crate::command::CommandRegistry>>,
+    pub(crate) shutdown_timeout: Duration,
+    pub(crate) hooks: LifecycleHooks,
+    pub(crate) dispatch_hooks: DispatchHooks,
+    pub(crate) interceptors: RequestInterceptors,
+    pub(crate) overrides: MethodOverrides,
+    pub(crate) settings: SettingsHooks,
+    pub(crate) security: Option<SecurityPolicy>,
+    pub(crate) strict: Option<StrictMode>,
+    pub(crate) strict_jsonrpc: bool,
+    pub(crate) initializing: InitializingPolicy,
+    pub(crate) load_shedding: Option<LoadSheddingPolicy>,
+    pub(crate) coalescing: Option<CoalescingPolicy>,
+    pub(crate) latency: Option<LatencyBudget>,
+    pub(crate) protocol_log: Option<ProtocolLog>,
+    pub(crate) broadcast: Option<usize>,
+    pub(crate) filters: ResponseFilters,
+    pub(crate) empty_results: EmptyResults,
+    pub(crate) unhandled: UnhandledNotifications,
+    pub(crate) dollar_methods: DollarMethodPolicy,
+    pub(crate) anomalies: Anomalies,
+    #[cfg(feature = "proposed")]
+    pub(crate) inline_completion: Option<crate::proposed::InlineCompletionOptions>,
+}
 
-    #[derive(Debug, Default)]
-    struct Mock;
+impl Default for ServiceOptions {
+    fn default() -> Self {
+        ServiceOptions {
+            commands: None,
+            shutdown_timeout: Duration::from_secs(5),
+            hooks: Default::default(),
+            dispatch_hooks: Default::default(),
+            interceptors: Default::default(),
+            overrides: Default::default(),
+            settings: Default::default(),
+            security: None,
+            strict: None,
+            strict_jsonrpc: false,
+            initializing: Default::default(),
+            load_shedding: None,
+            coalescing: None,
+            latency: None,
+            protocol_log: None,
+            broadcast: None,
+            filters: Default::default(),
+            empty_results: Default::default(),
+            unhandled: Default::default(),
+            dollar_methods: Default::default(),
+            anomalies: Default::default(),
+            #[cfg(feature = "proposed")]
+            inline_completion: None,
+        }
+    }
+}
 
-    #[async_trait]
-    impl crate::LanguageServer for Mock {
-        async fn initialize(&self, _: lsp::InitializeParams) -> crate::jsonrpc::Result<lsp::InitializeResult> {
-            Ok(lsp::InitializeResult::default())
+impl ServiceOptions {
+    /// Fills the capabilities derived from the service configuration into the `initialize` result,
+    /// unless the server already set them explicitly, and serializes it.
+    pub(crate) fn complete_initialize_result(
+        &self,
+        mut result: lsp::InitializeResult,
+    ) -> Result<serde_json::Value, crate::jsonrpc::Error> {
+        if let Some(commands) = &self.commands {
+            let provider = &mut result.capabilities.execute_command_provider;
+            provider.get_or_insert_with(|| commands.options());
         }
 
-        async fn shutdown(&self) -> crate::jsonrpc::Result<()> {
-            Ok(())
+        #[cfg_attr(not(feature = "proposed"), allow(unused_mut))]
+        let mut result = serde_json::to_value(result).map_err(|e| {
+            log::error!("failed to serialize `initialize` result: {}", e);
+            crate::jsonrpc::Error::internal_error()
+        })?;
+        #[cfg(feature = "proposed")]
+        if let (Some(options), Some(capabilities)) = (&self.inline_completion, result["capabilities"].as_object_mut()) {
+            let provider = serde_json::to_value(options).map_err(|e| {
+                log::error!("failed to serialize `inlineCompletionProvider` capability: {}", e);
+                crate::jsonrpc::Error::internal_error()
+            })?;
+            capabilities.insert("inlineCompletionProvider".into(), provider);
         }
+        Ok(result)
     }
+}
 
-    #[tokio::test]
-    async fn call_response() {
-        use crate::jsonrpc::{Id, Incoming, Response};
+impl<T, F> LspServiceBuilder<F>
+where
+    F: FnOnce(crate::client::Client) -> T,
+    T: crate::LanguageServer,
+{
+    /// Sets the retry policy used for server-to-client requests of type `R`.
+    ///
+    /// This takes precedence over the policy set with [`default_retry_policy`].
+    ///
+    /// [`default_retry_policy`]: Self::default_retry_policy
+    pub fn retry_policy<R>(mut self, policy: crate::client::RetryPolicy) -> Self
+    where
+        R: lsp::request::Request,
+    {
+        self.client_options.retry_policies.insert(R::METHOD, policy);
+        self
+    }
 
-        let (service, _) = LspService::new(|_| Mock::default());
-        let mut service = Spawn::new(service);
+    /// Sets the retry policy used for all server-to-client requests without a more specific policy.
+    pub fn default_retry_policy(mut self, policy: crate::client::RetryPolicy) -> Self {
+        self.client_options.retry_policies.set_default(policy);
+        self
+    }
 
-        let initialize: crate::jsonrpc::Incoming = serde_json::from_str(INITIALIZE_REQUEST).unwrap();
-        let raw = json!({ "jsonrpc": "2.0", "result": { "capabilities": {} }, "id": 1 });
-        let ok = serde_json::from_value(raw).unwrap();
-        assert_eq!(service.poll_ready(), Poll::Ready(Ok(())));
-        assert_eq!(service.call(initialize.clone()).await, Ok(Some(ok)));
+    /// Batches and samples `telemetry/event` notifications according to the given policy.
+    ///
+    /// Without a policy, every event passed to [`Client::telemetry_event`] is sent immediately.
+    /// Collected events are flushed when the client requests a `shutdown`.
+    ///
+    /// [`Client::telemetry_event`]: crate::Client::telemetry_event
+    pub fn telemetry_policy(mut self, policy: crate::client::TelemetryPolicy) -> Self {
+        self.client_options.telemetry_policy = Some(policy);
+        self
+    }
 
-        let incoming = Response::ok(Id::Number(0), json!(null));
-        assert_eq!(service.call(Incoming::Response(incoming)).await, Ok(None));
+    /// Limits the rate of notifications sent to the client according to the given policy.
+    ///
+    /// Without a policy, notifications are sent as fast as the server produces them. See
+    /// [`RateLimitPolicy`] for details.
+    ///
+    /// [`RateLimitPolicy`]: crate::RateLimitPolicy
+    pub fn rate_limit_policy(mut self, policy: crate::client::RateLimitPolicy) -> Self {
+        self.client_options.rate_limit_policy = Some(policy);
+        self
     }
 
-    #[test]
-    fn debug() {
-        let (service, _) = LspService::new(|_| Mock::default());
-        format!("{:?}", service);
+    /// Answers server-to-client requests locally instead of sending them to the client, for running
+    /// the server without an editor attached.
+    ///
+    /// See [`HeadlessClient`] for details.
+    ///
+    /// [`HeadlessClient`]: crate::HeadlessClient
+    pub fn headless(mut self, client: crate::client::HeadlessClient) -> Self {
+        self.client_options.headless = Some(client);
+        self
     }
 
-    #[tokio::test]
-    async fn initializes_only_once() {
-        let (service, _) = LspService::new(|_| Mock::default());
-        let mut service = Spawn::new(service);
+    /// Dispatches `workspace/executeCommand` requests to the given command registry.
+    ///
+    /// See [`CommandRegistry`] for details.
+    ///
+    /// [`CommandRegistry`]: crate::CommandRegistry
+    pub fn commands(mut self, commands: crate::command::CommandRegistry) -> Self {
+        self.options.commands = Some(Arc::new(commands));
+        self
+    }
 
-        let initialize: crate::jsonrpc::Incoming = serde_json::from_str(INITIALIZE_REQUEST).unwrap();
-        let raw = json!({ "jsonrpc": "2.0", "result": { "capabilities": {} }, "id": 1 });
-        let ok = serde_json::from_value(raw).unwrap();
-        assert_eq!(service.poll_ready(), Poll::Ready(Ok(())));
-        assert_eq!(service.call(initialize.clone()).await, Ok(Some(ok)));
+    /// Restricts the methods the client may invoke according to the given policy.
+    ///
+    /// See [`SecurityPolicy`] for details.
+    pub fn security_policy(mut self, policy: SecurityPolicy) -> Self {
+        self.options.security = Some(policy);
+        self
+    }
 
-        let raw = json!({ "jsonrpc": "2.0", "error": { "code": -32600, "message": "Invalid request" }, "id": 1 });
-        let err = serde_json::from_value(raw).unwrap();
-        assert_eq!(service.poll_ready(), Poll::Ready(Ok(())));
-        assert_eq!(service.call(initialize).await, Ok(Some(err)));
+    /// Detects client messages which violate the ordering of document notifications, according to
+    /// the given mode.
+    ///
+    /// See [`StrictMode`] for details.
+    pub fn strict_mode(mut self, mode: StrictMode) -> Self {
+        self.options.strict = Some(mode);
+        self
     }
 
-    #[tokio::test]
-    async fn refuses_requests_after_shutdown() {
-        let (service, _) = LspService::new(|_| Mock::default());
-        let mut service = Spawn::new(service);
+    /// Logs the notifications which the server does not handle according to the given policy.
+    ///
+    /// See [`UnhandledNotificationPolicy`] for details.
+    pub fn unhandled_notification_policy(mut self, policy: UnhandledNotificationPolicy) -> Self {
+        self.options.unhandled = UnhandledNotifications::new(policy);
+        self
+    }
 
-        let initialize: crate::jsonrpc::Incoming = serde_json::from_str(INITIALIZE_REQUEST).unwrap();
-        let raw = json!({ "jsonrpc": "2.0", "result": { "capabilities": {} }, "id":1 });
-        let ok = serde_json::from_value(raw).unwrap();
-        assert_eq!(service.poll_ready(), Poll::Ready(Ok(())));
-        assert_eq!(service.call(initialize.clone()).await, Ok(Some(ok)));
+    /// Handles messages to unknown methods starting with `$/` according to the given policy,
+    /// defaulting to ignoring such notifications and passing such requests to `request_else`.
+    ///
+    /// See [`DollarMethodPolicy`] for details.
+    pub fn dollar_method_policy(mut self, policy: DollarMethodPolicy) -> Self {
+        self.options.dollar_methods = policy;
+        self
+    }
 
-        let shutdown: crate::jsonrpc::Incoming = serde_json::from_str(SHUTDOWN_REQUEST).unwrap();
-        let raw = json!({ "jsonrpc": "2.0", "result": null, "id": 1 });
-        let ok = serde_json::from_value(raw).unwrap();
-        assert_eq!(service.poll_ready(), Poll::Ready(Ok(())));
-        assert_eq!(service.call(shutdown.clone()).await, Ok(Some(ok)));
+    /// Enables or disables rejecting client messages which the dispatcher accepts although they
+    /// violate JSON-RPC 2.0: notifications carrying an `id`, and messages whose `params` are
+    /// neither an array nor an object.
+    ///
+    /// When enabled, such messages are not passed to the server, and an "invalid request" error is
+    /// sent instead, with the ID of the message if it has one. When disabled, notifications with an
+    /// `id` are handled like any other notification, as lenient clients expect.
+    ///
+    /// Defaults to `false`.
+    pub fn strict_jsonrpc(mut self, enabled: bool) -> Self {
+        self.options.strict_jsonrpc = enabled;
+        self
+    }
 
-        let raw = json!({ "jsonrpc": "2.0", "error": { "code": -32600, "message": "Invalid request" }, "id": 1 });
-        let err = serde_json::from_value(raw).unwrap();
-        assert_eq!(service.poll_ready(), Poll::Ready(Ok(())));
-        assert_eq!(service.call(shutdown).await, Ok(Some(err)));
+    /// Advertises support for `textDocument/inlineCompletion` requests with the given options in
+    /// the `initialize` result, since [`ServerCapabilities`](lsp::ServerCapabilities) lacks a field
+    /// for it.
+    ///
+    /// This method is only available with the `proposed` crate feature enabled.
+    #[cfg(feature = "proposed")]
+    pub fn inline_completion_provider(mut self, options: crate::proposed::InlineCompletionOptions) -> Self {
+        self.options.inline_completion = Some(options);
+        self
     }
 
-    #[tokio::test]
-    async fn exit_notification() {
-        let (service, _) = LspService::new(|_| Mock::default());
-        let mut service = Spawn::new(service);
+    /// Answers low-priority requests with a "server cancelled" error while the server is
+    /// overloaded, according to the given policy.
+    ///
+    /// See [`LoadSheddingPolicy`] for details.
+    pub fn load_shedding_policy(mut self, policy: LoadSheddingPolicy) -> Self {
+        self.options.load_shedding = Some(policy);
+        self
+    }
 
-        let initialized: crate::jsonrpc::Incoming = serde_json::from_str(INITIALIZED_NOTIF).unwrap();
-        assert_eq!(service.poll_ready(), Poll::Ready(Ok(())));
-        assert_eq!(service.call(initialized.clone()).await, Ok(None));
+    /// Answers requests which arrive while an identical request is in flight with the response to
+    /// that request, according to the given policy.
+    ///
+    /// See [`CoalescingPolicy`] for details.
+    pub fn coalescing_policy(mut self, policy: CoalescingPolicy) -> Self {
+        self.options.coalescing = Some(policy);
+        self
+    }
 
-        let exit: crate::jsonrpc::Incoming = serde_json::from_str(EXIT_NOTIF).unwrap();
-        assert_eq!(service.poll_ready(), Poll::Ready(Ok(())));
-        assert_eq!(service.call(exit).await, Ok(None));
+    /// Registers a filter which adapts the results of requests of type `R` to the capabilities
+    /// declared by the client, before they are sent.
+    ///
+    /// The filter receives the parameters of the request, the result returned by the handler and
+    /// the client capabilities, and returns the result to send instead. Filters only apply to
+    /// successful results, and run in registration order after the built-in filters, if those were
+    /// enabled with [`builtin_response_filters`].
+    ///
+    /// The built-in filters cover the following requests:
+    ///
+    /// * `textDocument/documentSymbol`: nested symbols are flattened for clients without
+    ///   `hierarchicalDocumentSymbolSupport`, and tags the client does not support are removed, as
+    ///   they are from the results of `workspace/symbol`.
+    /// * `textDocument/completion` and `completionItem/resolve`: snippets are converted to plain
+    ///   text for clients without `snippetSupport`, and insert-and-replace edits to plain edits for
+    ///   clients without `insertReplaceSupport`.
+    /// * `textDocument/codeAction`: code actions are replaced with their commands for clients
+    ///   without `codeActionLiteralSupport`.
+    /// * `textDocument/foldingRange`: kinds the client did not declare are removed, as are start
+    ///   and end characters for clients folding complete lines only, and ranges beyond the
+    ///   `rangeLimit` of the client are dropped.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # use lspower::{jsonrpc::Result, lsp::*, LanguageServer, LspService};
+    /// # #[derive(Debug)]
+    /// # struct Backend;
+    /// # #[lspower::async_trait]
+    /// # impl LanguageServer for Backend {
+    /// #     async fn initialize(&self, _: InitializeParams) -> Result<InitializeResult> {
+    /// #         Ok(InitializeResult::default())
+    /// #     }
+    /// #     async fn shutdown(&self) -> Result<()> {
+    /// #         Ok(())
+    /// #     }
+    /// # }
+    /// use lspower::lsp::request::HoverRequest;
+    ///
+    /// let (service, messages) = LspService::build(|_| Backend)
+    ///     .response_filter::<HoverRequest, _>(|_, hover, capabilities| {
+    ///         let markdown = capabilities
+    ///             .text_document
+    ///             .as_ref()
+    ///             .and_then(|text_document| text_document.hover.as_ref())
+    ///             .and_then(|hover| hover.content_format.as_ref())
+    ///             .is_some_and(|formats| formats.contains(&MarkupKind::Markdown));
+    ///         hover.filter(|_| markdown)
+    ///     })
+    ///     .finish();
+    /// ```
+    ///
+    /// [`builtin_response_filters`]: LspServiceBuilder::builtin_response_filters
+    pub fn response_filter<R, H>(mut self, filter: H) -> Self
+    where
+        R: lsp::request::Request + 'static,
+        H: Fn(&R::Params, R::Result, &lsp::ClientCapabilities) -> R::Result + Send + Sync + 'static,
+    {
+        self.options.filters.insert::<R, H>(filter);
+        self
+    }
 
-        assert_eq!(service.poll_ready(), Poll::Ready(Err(ExitedError)));
-        assert_eq!(service.call(initialized).await, Err(ExitedError));
+    /// Responds to requests of type `R` with the default value of their result, such as `null` or
+    /// an empty list, if the server does not implement them.
+    ///
+    /// By default, requests for methods the server does not implement fail with a
+    /// `MethodNotFound` error. Clients usually only send requests the server declared support for,
+    /// but some send requests such as `workspace/symbol` or `textDocument/documentSymbol`
+    /// regardless, and report the errors to the user. The empty result also passes through the
+    /// response filters, so it is adapted to the capabilities of the client like any other result.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # use lspower::{jsonrpc::Result, lsp::*, LanguageServer, LspService};
+    /// # #[derive(Debug)]
+    /// # struct Backend;
+    /// # #[lspower::async_trait]
+    /// # impl LanguageServer for Backend {
+    /// #     async fn initialize(&self, _: InitializeParams) -> Result<InitializeResult> {
+    /// #         Ok(InitializeResult::default())
+    /// #     }
+    /// #     async fn shutdown(&self) -> Result<()> {
+    /// #         Ok(())
+    /// #     }
+    /// # }
+    /// use lspower::lsp::request::{DocumentSymbolRequest, WorkspaceSymbol};
+    ///
+    /// let (service, messages) = LspService::build(|_| Backend)
+    ///     .empty_result_when_unimplemented::<DocumentSymbolRequest>()
+    ///     .empty_result_when_unimplemented::<WorkspaceSymbol>()
+    ///     .finish();
+    /// ```
+    pub fn empty_result_when_unimplemented<R>(mut self) -> Self
+    where
+        R: lsp::request::Request,
+        R::Result: Default,
+    {
+        self.options.empty_results.insert::<R>();
+        self
+    }
+
+    /// Enables or disables the built-in response filters described for [`response_filter`].
+    ///
+    /// Defaults to `false`, so that results are sent as the handlers return them.
+    ///
+    /// [`response_filter`]: LspServiceBuilder::response_filter
+    pub fn builtin_response_filters(mut self, enabled: bool) -> Self {
+        self.options.filters.set_builtin(enabled);
+        self
+    }
+
+    /// Logs a warning for requests whose handlers take longer than the given budgets.
+    ///
+    /// See [`LatencyBudget`] for details.
+    pub fn latency_budget(mut self, budget: LatencyBudget) -> Self {
+        self.options.latency = Some(budget);
+        self
+    }
+
+    /// Records the messages exchanged with the client, together with the state of the server and
+    /// any violations of the protocol lifecycle, to the given conformance log.
+    ///
+    /// See [`ProtocolLog`] for details.
+    pub fn protocol_log(mut self, log: ProtocolLog) -> Self {
+        self.options.protocol_log = Some(log);
+        self
+    }
+
+    /// Sets the information about the connection the service is serving, which the backend
+    /// obtains through [`Client::connection`].
+    ///
+    /// See [`ConnectionInfo`] for details.
+    ///
+    /// [`Client::connection`]: crate::Client::connection
+    /// [`ConnectionInfo`]: crate::ConnectionInfo
+    pub fn connection(mut self, connection: crate::ConnectionInfo) -> Self {
+        self.client_options.connection = Some(connection);
+        self
+    }
+
+    /// Sets how long configuration sections fetched with [`Client::configuration_scoped`] are
+    /// cached, in addition to being invalidated by the `workspace/didChangeConfiguration`
+    /// notification.
+    ///
+    /// By default, sections are cached until they are invalidated.
+    ///
+    /// [`Client::configuration_scoped`]: crate::Client::configuration_scoped
+    pub fn configuration_ttl(mut self, ttl: Duration) -> Self {
+        self.client_options.configuration_ttl = Some(ttl);
+        self
+    }
+
+    /// Enables or disables sending `$/cancelRequest` for requests to the client whose futures are
+    /// dropped before the response arrived, e.g. because the handler stopped waiting for the
+    /// response in a `select!` or was canceled itself.
+    ///
+    /// Dropped requests are always forgotten by the server, so a late response is ignored. When
+    /// enabled, the client is also asked to stop working on the request, which would otherwise
+    /// stay pending in the editor, e.g. as a dialog nobody waits for anymore.
+    ///
+    /// Defaults to `false`.
+    pub fn cancel_dropped_requests(mut self, enabled: bool) -> Self {
+        self.client_options.cancel_dropped_requests = enabled;
+        self
+    }
+
+    /// Drops messages sent with [`Client::log_message`] which are less severe than `level`, unless
+    /// the client requested a `verbose` trace in its `initialize` request or a later `$/setTrace`
+    /// notification.
+    ///
+    /// This allows servers to log freely, e.g. with [`MessageType::LOG`], without flooding the
+    /// output panes of editors in production, while users debugging the server can still turn on
+    /// all messages from their editor. By default, all messages are sent.
+    ///
+    /// [`MessageType::LOG`]: lsp::MessageType::LOG
+    pub fn log_message_level(mut self, level: lsp::MessageType) -> Self {
+        self.client_options.log_level = Some(level);
+        self
+    }
+
+    /// Allows multiple consumers of the messages produced by the server, which subscribe with
+    /// [`MessageStream::subscribe`].
+    ///
+    /// Up to `capacity` recent messages are kept for subscribers which have not received them yet.
+    /// A capacity of `0` is treated as `1`.
+    pub fn broadcast(mut self, capacity: usize) -> Self {
+        self.options.broadcast = Some(capacity);
+        self
+    }
+
+    /// Queues the messages the server sends until the transport polls the [`MessageStream`] for
+    /// the first time, and replays them before any later message.
+    ///
+    /// The channel to the transport only holds a single message, so a backend sending messages
+    /// before [`Server::serve`] starts polling the stream, e.g. from a background task spawned in
+    /// the `init` closure, or in a test or embedding which polls the stream late, waits until the
+    /// stream is polled, or deadlocks if the stream is only polled after the backend is done. With
+    /// this option, such messages never wait. A warning is logged once `high_water` messages are
+    /// queued, since a transport which never attaches leaves the queue growing without bound.
+    ///
+    /// [`Server::serve`]: crate::Server::serve
+    pub fn buffer_until_attached(mut self, high_water: usize) -> Self {
+        self.client_options.backlog = Some(Arc::new(crate::client::Backlog::new(high_water)));
+        self
+    }
+
+    /// Sets how messages are handled which arrive after the `initialize` request, but before the
+    /// server responded to it.
+    ///
+    /// Defaults to [`InitializingPolicy::Reject`].
+    pub fn initializing_policy(mut self, policy: InitializingPolicy) -> Self {
+        self.options.initializing = policy;
+        self
+    }
+
+    /// Sets the function used to hand background tasks over to an executor.
+    ///
+    /// See [`Spawner`] for details.
+    ///
+    /// [`Spawner`]: crate::Spawner
+    pub fn spawner<S>(mut self, spawn: S) -> Self
+    where
+        S: Fn(Pin<Box<dyn Future<Output = ()> + Send>>) + Send + Sync + 'static,
+    {
+        self.spawn = Some(Arc::new(spawn));
+        self
+    }
+
+    /// Registers a hook which is invoked after the `initialized` notification was handled by
+    /// [`LanguageServer::initialized`].
+    ///
+    /// Hooks registered for the same transition run one after another, in registration order.
+    ///
+    /// [`LanguageServer::initialized`]: crate::LanguageServer::initialized
+    pub fn on_initialized<H, Fut>(mut self, hook: H) -> Self
+    where
+        H: Fn(Client) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.options.hooks.insert(Transition::Initialized, hook);
+        self
+    }
+
+    /// Registers a hook which is invoked on a `shutdown` request, e.g. to persist caches.
+    ///
+    /// The hooks run after [`LanguageServer::shutdown`] returned and all background tasks finished
+    /// or were aborted, but before the response is sent to the client. Hooks registered for the
+    /// same transition run one after another, in registration order.
+    ///
+    /// [`LanguageServer::shutdown`]: crate::LanguageServer::shutdown
+    pub fn on_shutdown<H, Fut>(mut self, hook: H) -> Self
+    where
+        H: Fn(Client) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.options.hooks.insert(Transition::Shutdown, hook);
+        self
+    }
+
+    /// Registers a hook which is invoked on the `exit` notification.
+    ///
+    /// The hooks run after pending requests and background tasks were aborted, but before the
+    /// client is closed. Hooks registered for the same transition run one after another, in
+    /// registration order.
+    pub fn on_exit<H, Fut>(mut self, hook: H) -> Self
+    where
+        H: Fn(Client) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.options.hooks.insert(Transition::Exit, hook);
+        self
+    }
+
+    /// Registers a hook which receives the settings of type `S` whenever they change, as delivered
+    /// by the `workspace/didChangeConfiguration` notification.
+    ///
+    /// The settings are the field `section` of the `settings` in the notification, or all of them
+    /// if `section` is `None`. Clients which send no settings with the notification, as the pull
+    /// model of the specification suggests, are asked for the section with
+    /// [`Client::configuration_scoped`] instead. The hook runs in the background once
+    /// [`LanguageServer::did_change_configuration`] returned, in the order of the notifications,
+    /// and only if the settings differ from the ones it received last. The [`SettingsChange`] it receives holds the previous and the new
+    /// settings, as well as the names of the top-level fields which changed. Settings which fail to
+    /// deserialize are logged and ignored.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # use lspower::{jsonrpc::Result, lsp::*, LanguageServer, LspService};
+    /// # #[derive(Debug)]
+    /// # struct Backend;
+    /// # #[lspower::async_trait]
+    /// # impl LanguageServer for Backend {
+    /// #     async fn initialize(&self, _: InitializeParams) -> Result<InitializeResult> {
+    /// #         Ok(InitializeResult::default())
+    /// #     }
+    /// #     async fn shutdown(&self) -> Result<()> {
+    /// #         Ok(())
+    /// #     }
+    /// # }
+    /// #[derive(serde::Deserialize)]
+    /// struct Settings {
+    ///     check_on_save: bool,
+    /// }
+    ///
+    /// let (service, messages) = LspService::build(|_| Backend)
+    ///     .on_settings_changed(Some("example"), |client, change: lspower::SettingsChange<Settings>| async move {
+    ///         if change.is_changed("check_on_save") && change.current().check_on_save {
+    ///             client.log_message(MessageType::INFO, "checking on save").await;
+    ///         }
+    ///     })
+    ///     .finish();
+    /// ```
+    ///
+    /// [`Client::configuration_scoped`]: crate::Client::configuration_scoped
+    /// [`LanguageServer::did_change_configuration`]: crate::LanguageServer::did_change_configuration
+    pub fn on_settings_changed<S, H, Fut>(mut self, section: Option<&str>, hook: H) -> Self
+    where
+        S: serde::de::DeserializeOwned + Send + 'static,
+        H: Fn(Client, SettingsChange<S>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.options.settings.insert(section, hook);
+        self
+    }
+
+    /// Registers a hook which is invoked with each client message before it is dispatched to the
+    /// server, after it passed the [`SecurityPolicy`].
+    ///
+    /// The hook can rewrite the parameters of the message with [`ServerRequest::set_params`], e.g.
+    /// to translate the paths of a container to the paths of the host, or to fill in defaults. By
+    /// returning [`ControlFlow::Break`], the hook vetoes the message and the given response is sent
+    /// instead, unless the message is a notification. Hooks run in registration order, until one of
+    /// them vetoes the message.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # use lspower::{jsonrpc::Result, lsp::*, LanguageServer, LspService};
+    /// # use std::ops::ControlFlow;
+    /// # #[derive(Debug)]
+    /// # struct Backend;
+    /// # #[lspower::async_trait]
+    /// # impl LanguageServer for Backend {
+    /// #     async fn initialize(&self, _: InitializeParams) -> Result<InitializeResult> {
+    /// #         Ok(InitializeResult::default())
+    /// #     }
+    /// #     async fn shutdown(&self) -> Result<()> {
+    /// #         Ok(())
+    /// #     }
+    /// # }
+    /// let (service, messages) = LspService::build(|_| Backend)
+    ///     .before_dispatch(|request| {
+    ///         if let Some(mut params) = request.params_value() {
+    ///             if let Some(uri) = params.pointer_mut("/textDocument/uri") {
+    ///                 let host = uri.as_str().unwrap_or_default().replace("file:///workspace/", "file:///home/me/");
+    ///                 *uri = host.into();
+    ///                 let _ = request.set_params(params);
+    ///             }
+    ///         }
+    ///         ControlFlow::Continue(())
+    ///     })
+    ///     .finish();
+    /// ```
+    ///
+    /// [`ServerRequest::set_params`]: crate::jsonrpc::ServerRequest::set_params
+    /// [`ControlFlow::Break`]: std::ops::ControlFlow::Break
+    pub fn before_dispatch<H>(mut self, hook: H) -> Self
+    where
+        H: Fn(&mut crate::jsonrpc::ServerRequest) -> std::ops::ControlFlow<crate::jsonrpc::Response>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.options.dispatch_hooks.insert(hook);
+        self
+    }
+
+    /// Registers an interceptor which decides for every request, whether its method is known or
+    /// not, if it is dispatched as usual, answered by a handler of its own, or rejected.
+    ///
+    /// Unlike [`request_else`](crate::LanguageServer::request_else), the interceptor also sees the
+    /// requests the [`LanguageServer`] implementation handles, so that built-in handlers can be
+    /// disabled or replaced at runtime, e.g. behind feature flags. Interceptors are the last stage
+    /// of the [`before_dispatch`] hooks: they only see requests once the server is initialized and
+    /// the hooks let them through, never see the `shutdown` request, and can answer requests
+    /// asynchronously, which the client can cancel like other requests. Interceptors run in
+    /// registration order, until one of them does not pass the request through.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # use lspower::{jsonrpc::{Error, Result}, lsp::*, Interception, LanguageServer, LspService};
+    /// # use std::sync::{atomic::{AtomicBool, Ordering}, Arc};
+    /// # #[derive(Debug)]
+    /// # struct Backend;
+    /// # #[lspower::async_trait]
+    /// # impl LanguageServer for Backend {
+    /// #     async fn initialize(&self, _: InitializeParams) -> Result<InitializeResult> {
+    /// #         Ok(InitializeResult::default())
+    /// #     }
+    /// #     async fn shutdown(&self) -> Result<()> {
+    /// #         Ok(())
+    /// #     }
+    /// # }
+    /// let inlay_hints = Arc::new(AtomicBool::new(false));
+    /// let enabled = inlay_hints.clone();
+    /// let (service, messages) = LspService::build(|_| Backend)
+    ///     .on_request(move |request| match request.method() {
+    ///         "textDocument/inlayHint" if !enabled.load(Ordering::Relaxed) => {
+    ///             Interception::Reject(Error::method_not_found())
+    ///         },
+    ///         _ => Interception::PassThrough,
+    ///     })
+    ///     .finish();
+    /// ```
+    ///
+    /// [`before_dispatch`]: LspServiceBuilder::before_dispatch
+    /// [`LanguageServer`]: crate::LanguageServer
+    pub fn on_request<H>(mut self, interceptor: H) -> Self
+    where
+        H: Fn(&crate::jsonrpc::ServerRequest) -> Interception + Send + Sync + 'static,
+    {
+        self.options.interceptors.insert(interceptor);
+        self
+    }
+
+    /// Wraps the handler of requests of type `R` of the [`LanguageServer`] implementation, e.g. to
+    /// add caching around `textDocument/hover` without implementing the trait again.
+    ///
+    /// The override receives the typed parameters of each request along with a [`DefaultHandler`],
+    /// which calls the method of the `LanguageServer` implementation when it is needed. Registering
+    /// another override for the same method replaces the previous one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `R` is the `initialize` or `shutdown` request, which the service handles itself, or
+    /// if its method or its types do not match those of a request handler of `LanguageServer`.
+    ///
+    /// # Example
+    ///
+    /// ```rust
+    /// # use lspower::{jsonrpc::Result, lsp::*, LanguageServer, LspService};
+    /// # use std::{collections::HashMap, sync::{Arc, Mutex}};
+    /// # #[derive(Debug)]
+    /// # struct Backend;
+    /// # #[lspower::async_trait]
+    /// # impl LanguageServer for Backend {
+    /// #     async fn initialize(&self, _: InitializeParams) -> Result<InitializeResult> {
+    /// #         Ok(InitializeResult::default())
+    /// #     }
+    /// #     async fn shutdown(&self) -> Result<()> {
+    /// #         Ok(())
+    /// #     }
+    /// # }
+    /// let cache = Arc::new(Mutex::new(HashMap::new()));
+    /// let (service, messages) = LspService::build(|_| Backend)
+    ///     .override_method::<request::HoverRequest, _, _>(move |inner, params| {
+    ///         let cache = cache.clone();
+    ///         async move {
+    ///             let position = &params.text_document_position_params;
+    ///             let key = (position.text_document.uri.clone(), position.position.line, position.position.character);
+    ///             if let Some(hover) = cache.lock().unwrap().get(&key).cloned() {
+    ///                 return Ok(hover);
+    ///             }
+    ///             let hover = inner.call(params).await?;
+    ///             cache.lock().unwrap().insert(key, hover.clone());
+    ///             Ok(hover)
+    ///         }
+    ///     })
+    ///     .finish();
+    /// ```
+    ///
+    /// [`LanguageServer`]: crate::LanguageServer
+    pub fn override_method<R, H, Fut>(mut self, handler: H) -> Self
+    where
+        R: lsp::request::Request,
+        R::Params: Send + 'static,
+        R::Result: Send + 'static,
+        H: Fn(DefaultHandler<R>, R::Params) -> Fut + Send + Sync + 'static,
+        Fut: std::future::Future<Output = crate::jsonrpc::Result<R::Result>> + Send + 'static,
+    {
+        self.options.overrides.insert(handler);
+        self
+    }
+
+    /// Registers a hook which is invoked when the result of a request handler fails to serialize,
+    /// e.g. because it contains a map with non-string keys.
+    ///
+    /// The hook receives the method of the request and the serialization error. Regardless of the
+    /// hook, the client receives an "internal error" response (`-32603`) describing the failure.
+    pub fn on_serialization_error<H>(mut self, hook: H) -> Self
+    where
+        H: Fn(&str, &serde_json::Error) + Send + Sync + 'static,
+    {
+        self.on_serialization_error = Some(Arc::new(hook));
+        self
+    }
+
+    /// Registers a hook which is invoked when the client sends a response which does not answer a
+    /// pending request, such as a second response to the same request.
+    ///
+    /// Such responses are ignored and counted in [`PendingRequests::unexpected_responses`]
+    /// regardless of the hook. Responses to requests which the server canceled before the response
+    /// arrived are expected and not reported.
+    ///
+    /// [`PendingRequests::unexpected_responses`]: crate::jsonrpc::PendingRequests::unexpected_responses
+    pub fn on_unexpected_response<H>(mut self, hook: H) -> Self
+    where
+        H: Fn(&crate::jsonrpc::UnexpectedResponse) + Send + Sync + 'static,
+    {
+        self.on_unexpected_response = Some(Arc::new(hook));
+        self
+    }
+
+    /// Sets how long background tasks are awaited on `shutdown` before they are aborted.
+    ///
+    /// Defaults to 5 seconds.
+    pub fn shutdown_timeout(mut self, timeout: Duration) -> Self {
+        self.options.shutdown_timeout = timeout;
+        self
+    }
+
+    /// Sets the allocator of the IDs of requests sent from the server to the client.
+    ///
+    /// Defaults to an [`IdRange`] covering all valid IDs, starting at `0`.
+    ///
+    /// [`IdRange`]: crate::IdRange
+    pub fn request_ids<A: crate::IdAllocator>(mut self, allocator: A) -> Self {
+        self.client_options.ids = Arc::new(allocator);
+        self
+    }
+
+    /// Sets the clock used for timeouts and delays, such as the shutdown timeout, retry delays and
+    /// telemetry flush intervals.
+    ///
+    /// Defaults to [`SystemClock`]. Tests can use a [`MockClock`] to avoid waiting for real timers.
+    ///
+    /// [`SystemClock`]: crate::SystemClock
+    /// [`MockClock`]: crate::MockClock
+    pub fn clock<C: crate::Clock>(mut self, clock: C) -> Self {
+        self.client_options.clock = Arc::new(clock);
+        self
+    }
+
+    /// Creates the `LspService`, also returning a stream of notifications from the server back to
+    /// the client.
+    pub fn finish(self) -> (LspService, MessageStream) {
+        let state = Arc::new(crate::server::State::new());
+        let (tx, rx) = mpsc::channel(1);
+        let rx = crate::client::Outbox::new(rx, self.client_options.backlog.clone());
+        let source = match self.options.broadcast {
+            Some(capacity) => Source::Broadcast(broadcast::Subscriber::new(rx, capacity)),
+            None => Source::Single(rx),
+        };
+        let messages = MessageStream {
+            source,
+            log: self.options.protocol_log.clone().map(|log| (log, state.clone())),
+        };
+
+        let pending_client = crate::jsonrpc::ClientRequests::new();
+        let pending_client = Arc::new(pending_client.unexpected_response_hook(self.on_unexpected_response));
+        let tasks = Arc::new(crate::task::BackgroundTasks::new(self.spawn, self.client_options.clock.clone()));
+        let client = crate::client::Client::new(tx, pending_client.clone(), state.clone(), self.client_options, tasks);
+
+        let service = LspService {
+            backend: swap::SharedBackend::new(Arc::from((self.init)(client.clone()))),
+            pending_server: crate::jsonrpc::ServerRequests::new().serialization_hook(self.on_serialization_error),
+            pending_client,
+            state,
+            authenticated: Default::default(),
+            reconnected: Default::default(),
+            documents: Default::default(),
+            in_flight: Default::default(),
+            replay: Default::default(),
+            client,
+            options: Arc::new(self.options),
+            restart: self.restart,
+        };
+
+        (service, messages)
+    }
+}
+
+impl<F> Debug for LspServiceBuilder<F> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct(stringify!(LspServiceBuilder))
+            .field("client_options", &self.client_options)
+            .field("options", &self.options)
+            .field("spawn", &self.spawn.is_some())
+            .field("on_serialization_error", &self.on_serialization_error.is_some())
+            .field("on_unexpected_response", &self.on_unexpected_response.is_some())
+            .field("restartable", &self.restart.is_some())
+            .finish()
+    }
+}
+
+impl<T, F> LspServiceBuilder<F>
+where
+    F: Fn(crate::client::Client) -> T + Clone + Send + Sync + 'static,
+    T: crate::LanguageServer,
+{
+    /// Allows the service to be restarted with [`LspService::reset`], which calls the `init`
+    /// closure again to create a fresh backend.
+    ///
+    /// Closures written inline in the call to [`LspService::build`] are inferred to be `FnOnce`,
+    /// so the `init` closure needs to be bound to a variable first.
+    pub fn restartable(mut self) -> Self {
+        let init = self.init.clone();
+        self.restart = Some(Arc::new(move |client| Arc::new(init(client))));
+        self
+    }
+}
+
+impl Service<crate::jsonrpc::Incoming> for LspService {
+    type Error = ExitedError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+    type Response = Option<crate::jsonrpc::Outgoing>;
+
+    fn poll_ready(&mut self, _: &mut Context) -> Poll<Result<(), Self::Error>> {
+        if self.state.get() == crate::server::StateKind::Exited {
+            Poll::Ready(Err(ExitedError))
+        } else {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    fn call(&mut self, request: crate::jsonrpc::Incoming) -> Self::Future {
+        let log = match &self.options.protocol_log {
+            Some(log) => log.clone(),
+            None => return self.dispatch(request),
+        };
+
+        log.incoming(&request, self.state.get(), &self.pending_client);
+        let method = match &request {
+            crate::jsonrpc::Incoming::Request(req) => Some(req.method().to_owned()),
+            crate::jsonrpc::Incoming::Response(_) => None,
+        };
+        let state = self.state.clone();
+        self.dispatch(request)
+            .map(move |response| {
+                if let Ok(Some(message)) = &response {
+                    log.outgoing(message, method.as_deref(), state.get());
+                }
+                response
+            })
+            .boxed()
+    }
+}
+
+impl LspService {
+    /// Dispatches the given message to the server, unless it has already exited.
+    fn dispatch(&mut self, request: crate::jsonrpc::Incoming) -> <Self as Service<crate::jsonrpc::Incoming>>::Future {
+        if self.reconnected.swap(false, Ordering::SeqCst) {
+            // The state of the previous session which only the service itself holds.
+            self.replay.clear();
+            self.documents.clear();
+            self.in_flight.clear();
+            self.options.settings.clear();
+        }
+
+        if self.state.get() == crate::server::StateKind::Exited {
+            future::err(ExitedError).boxed()
+        } else {
+            match request {
+                crate::jsonrpc::Incoming::Request(mut req) => {
+                    if let Some(violation) = req.jsonrpc_violation().filter(|_| self.options.strict_jsonrpc) {
+                        log::warn!("rejecting invalid {:?} message: {}", req.method(), violation);
+                        let error = crate::jsonrpc::Error::invalid_request().with_message(violation);
+                        let response = crate::jsonrpc::Response::error(req.raw_id(), error);
+                        return future::ok(Some(crate::jsonrpc::Outgoing::Response(response))).boxed();
+                    }
+
+                    let security = self.options.security.as_ref();
+                    if let Some(response) = security.and_then(|policy| policy.intercept(&req, &self.authenticated)) {
+                        return response.map(Ok).boxed();
+                    }
+
+                    if let Some(response) = self.options.dispatch_hooks.run(&mut req) {
+                        return future::ok(response.map(crate::jsonrpc::Outgoing::Response)).boxed();
+                    }
+
+                    let strict = self.options.strict.as_ref();
+                    if let Some(response) = strict.and_then(|mode| mode.intercept(&req, &mut self.documents)) {
+                        return future::ok(response).boxed();
+                    }
+
+                    let shedding = self.options.load_shedding.as_ref();
+                    if let Some(response) = shedding.and_then(|policy| policy.intercept(&req, &self.pending_server)) {
+                        return future::ok(Some(response)).boxed();
+                    }
+
+                    if let Some(origin) = req.cancelled_id().and_then(|id| self.in_flight.origin(id)) {
+                        log::debug!("canceling request {}, which coalesced requests are answered with", origin);
+                        if let Err(error) = req.set_params(serde_json::json!({ "id": origin })) {
+                            log::error!("failed to cancel coalesced request: {}", error);
+                        }
+                    }
+
+                    let coalescing = self.options.coalescing.as_ref();
+                    let key = coalescing.and_then(|policy| policy.key(&req)).zip(req.id().cloned());
+                    if let Some((key, id)) = &key {
+                        if let Some(response) = self.in_flight.join(key, id) {
+                            return response;
+                        }
+                    }
+
+                    let latency = self.options.latency.as_ref();
+                    let stopwatch = latency.and_then(|budget| budget.start(&req, &self.client));
+                    let empty_result = self.options.empty_results.start(&req);
+                    let filters = self.options.filters.start(&req, &self.client);
+
+                    let (server, dispatched) = self.backend.current();
+                    let response = if self.options.initializing == InitializingPolicy::Queue {
+                        self.queue_or_dispatch(server, req)
+                    } else {
+                        super::generated_impl::handle_request(
+                            server,
+                            &self.state,
+                            &self.pending_server,
+                            &self.options,
+                            req,
+                            self.client.clone(),
+                        )
+                    };
+
+                    let response = dispatched.track(response).boxed();
+                    let response = match empty_result {
+                        Some(empty_result) => empty_result.apply(response),
+                        None => response,
+                    };
+                    let response = match filters {
+                        Some(filters) => filters.apply(response),
+                        None => response,
+                    };
+
+                    let response = match stopwatch {
+                        Some(stopwatch) => stopwatch.time(response),
+                        None => response,
+                    };
+
+                    match key {
+                        Some((key, id)) => self.in_flight.register(key, id, response),
+                        None => response,
+                    }
+                },
+                crate::jsonrpc::Incoming::Response(res) => {
+                    log::trace!("received client response: {:?}", res);
+                    self.pending_client.insert(res);
+                    future::ok(None).boxed()
+                },
+            }
+        }
+    }
+
+    /// Dispatches the given message according to [`InitializingPolicy::Queue`], replaying the
+    /// queued messages once the server responded to the `initialize` request.
+    fn queue_or_dispatch(
+        &mut self,
+        server: Arc<dyn crate::LanguageServer>,
+        request: Box<super::generated_impl::ServerRequest>,
+    ) -> <Self as Service<crate::jsonrpc::Incoming>>::Future {
+        let request = match self.replay.push(request, &self.state) {
+            Ok(queued) => return queued,
+            Err(request) => request,
+        };
+
+        let method = request.method();
+        let initialize = method == "initialize" && self.state.get() == crate::server::StateKind::Uninitialized;
+        if method == "exit" {
+            self.replay.clear();
+        }
+
+        let state = self.state.clone();
+        let pending = self.pending_server.clone();
+        let options = self.options.clone();
+        let client = self.client.clone();
+        let dispatch = move |request| {
+            if state.get() == crate::server::StateKind::Exited {
+                return future::err(ExitedError).boxed();
+            }
+            super::generated_impl::handle_request(server.clone(), &state, &pending, &options, request, client.clone())
+        };
+
+        let response = dispatch(request);
+        if !initialize {
+            return response;
+        }
+
+        let replay = self.replay.clone();
+        response
+            .map(move |response| {
+                replay.replay(dispatch);
+                response
+            })
+            .boxed()
+    }
+}
+
+impl Debug for LspService {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct(stringify!(LspService))
+            .field("pending_server", &self.pending_server)
+            .field("pending_client", &self.pending_client)
+            .field("replay", &self.replay)
+            .field("state", &self.state)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use serde_json::json;
+    use tower_test::mock::Spawn;
+
+    const INITIALIZE_REQUEST: &str =
+        r#"{ "jsonrpc": "2.0", "method": "initialize", "params": { "capabilities": {} }, "id": 1 }"#;
+    const INITIALIZED_NOTIF: &str = r#"{ "jsonrpc": "2.0", "method": "initialized", "params": {} }"#;
+    const SHUTDOWN_REQUEST: &str = r#"{ "jsonrpc": "2.0", "method": "shutdown", "id": 1 }"#;
+    const EXIT_NOTIF: &str = r#"{ "jsonrpc": "2.0", "method": "exit" }"#;
+
+    #[derive(Debug, Default)]
+    struct Mock;
+
+    #[async_trait]
+    impl crate::LanguageServer for Mock {
+        async fn initialize(&self, _: lsp::InitializeParams) -> crate::jsonrpc::Result<lsp::InitializeResult> {
+            Ok(lsp::InitializeResult::default())
+        }
+
+        async fn shutdown(&self) -> crate::jsonrpc::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn call_response() {
+        use crate::jsonrpc::{Id, Incoming, Response};
+
+        let (service, _) = LspService::new(|_| Mock);
+        let mut service = Spawn::new(service);
+
+        let initialize: crate::jsonrpc::Incoming = serde_json::from_str(INITIALIZE_REQUEST).unwrap();
+        let raw = json!({ "jsonrpc": "2.0", "result": { "capabilities": {} }, "id": 1 });
+        let ok = serde_json::from_value(raw).unwrap();
+        assert_eq!(service.poll_ready(), Poll::Ready(Ok(())));
+        assert_eq!(service.call(initialize.clone()).await, Ok(Some(ok)));
+
+        let incoming = Response::ok(Id::Number(0), json!(null));
+        assert_eq!(service.call(Incoming::Response(incoming)).await, Ok(None));
+    }
+
+    #[test]
+    fn debug() {
+        let (service, _) = LspService::new(|_| Mock);
+        let _ = format!("{:?}", service);
+    }
+
+    #[test]
+    fn connection() {
+        let connection = crate::ConnectionInfo::new(crate::TransportKind::Tcp).with_id(3);
+        let (service, _) = LspService::new_with_connection(connection, |client, connection| {
+            assert_eq!(connection.id(), 3);
+            assert_eq!(client.connection().map(|connection| connection.id()), Some(3));
+            Mock
+        });
+        assert_eq!(service.client.connection().unwrap().transport(), &crate::TransportKind::Tcp);
+
+        let (service, _) = LspService::new(|_| Mock);
+        assert!(service.client.connection().is_none());
+    }
+
+    #[tokio::test]
+    async fn initializes_only_once() {
+        let (service, _) = LspService::new(|_| Mock);
+        let mut service = Spawn::new(service);
+
+        let initialize: crate::jsonrpc::Incoming = serde_json::from_str(INITIALIZE_REQUEST).unwrap();
+        let raw = json!({ "jsonrpc": "2.0", "result": { "capabilities": {} }, "id": 1 });
+        let ok = serde_json::from_value(raw).unwrap();
+        assert_eq!(service.poll_ready(), Poll::Ready(Ok(())));
+        assert_eq!(service.call(initialize.clone()).await, Ok(Some(ok)));
+
+        let raw = json!({ "jsonrpc": "2.0", "error": { "code": -32600, "message": "Invalid request" }, "id": 1 });
+        let err = serde_json::from_value(raw).unwrap();
+        assert_eq!(service.poll_ready(), Poll::Ready(Ok(())));
+        assert_eq!(service.call(initialize).await, Ok(Some(err)));
+    }
+
+    #[tokio::test]
+    async fn refuses_requests_after_shutdown() {
+        let (service, _) = LspService::new(|_| Mock);
+        let mut service = Spawn::new(service);
+
+        let initialize: crate::jsonrpc::Incoming = serde_json::from_str(INITIALIZE_REQUEST).unwrap();
+        let raw = json!({ "jsonrpc": "2.0", "result": { "capabilities": {} }, "id":1 });
+        let ok = serde_json::from_value(raw).unwrap();
+        assert_eq!(service.poll_ready(), Poll::Ready(Ok(())));
+        assert_eq!(service.call(initialize.clone()).await, Ok(Some(ok)));
+
+        let shutdown: crate::jsonrpc::Incoming = serde_json::from_str(SHUTDOWN_REQUEST).unwrap();
+        let raw = json!({ "jsonrpc": "2.0", "result": null, "id": 1 });
+        let ok = serde_json::from_value(raw).unwrap();
+        assert_eq!(service.poll_ready(), Poll::Ready(Ok(())));
+        assert_eq!(service.call(shutdown.clone()).await, Ok(Some(ok)));
+
+        let raw = json!({ "jsonrpc": "2.0", "error": { "code": -32600, "message": "Invalid request" }, "id": 1 });
+        let err = serde_json::from_value(raw).unwrap();
+        assert_eq!(service.poll_ready(), Poll::Ready(Ok(())));
+        assert_eq!(service.call(shutdown).await, Ok(Some(err)));
+    }
+
+    #[tokio::test]
+    async fn exit_notification() {
+        let (service, _) = LspService::new(|_| Mock);
+        let mut service = Spawn::new(service);
+
+        let initialized: crate::jsonrpc::Incoming = serde_json::from_str(INITIALIZED_NOTIF).unwrap();
+        assert_eq!(service.poll_ready(), Poll::Ready(Ok(())));
+        assert_eq!(service.call(initialized.clone()).await, Ok(None));
+
+        let exit: crate::jsonrpc::Incoming = serde_json::from_str(EXIT_NOTIF).unwrap();
+        assert_eq!(service.poll_ready(), Poll::Ready(Ok(())));
+        assert_eq!(service.call(exit).await, Ok(None));
+
+        assert_eq!(service.poll_ready(), Poll::Ready(Err(ExitedError)));
+        assert_eq!(service.call(initialized).await, Err(ExitedError));
+    }
+
+    #[tokio::test]
+    async fn exit_code() {
+        let (mut service, _) = LspService::new(|_| Mock);
+        let exit_code = service.exit_code();
+        let exit: crate::jsonrpc::Incoming = serde_json::from_str(EXIT_NOTIF).unwrap();
+        assert_eq!(service.call(exit.clone()).await, Ok(None));
+        assert_eq!(exit_code.get(), 1);
+
+        let (mut service, _) = LspService::new(|_| Mock);
+        let exit_code = service.exit_code();
+        let initialize: crate::jsonrpc::Incoming = serde_json::from_str(INITIALIZE_REQUEST).unwrap();
+        assert!(service.call(initialize).await.is_ok());
+        assert!(!exit_code.is_success());
+
+        let shutdown: crate::jsonrpc::Incoming = serde_json::from_str(SHUTDOWN_REQUEST).unwrap();
+        assert!(service.call(shutdown).await.is_ok());
+        assert_eq!(service.call(exit).await, Ok(None));
+        assert_eq!(exit_code.get(), 0);
+        assert_eq!(format!("{:?}", exit_code), "ExitCode(0)");
+    }
+
+    #[tokio::test]
+    async fn commands() {
+        let commands = crate::CommandRegistry::new().command("add", |(a, b): (i32, i32)| async move { Ok(a + b) });
+        let (service, _) = LspService::build(|_| Mock).commands(commands).finish();
+        let mut service = Spawn::new(service);
+
+        let initialize: crate::jsonrpc::Incoming = serde_json::from_str(INITIALIZE_REQUEST).unwrap();
+        let raw = json!({
+            "jsonrpc": "2.0",
+            "result": { "capabilities": { "executeCommandProvider": { "commands": ["add"] } } },
+            "id": 1
+        });
+        let ok = serde_json::from_value(raw).unwrap();
+        assert_eq!(service.poll_ready(), Poll::Ready(Ok(())));
+        assert_eq!(service.call(initialize).await, Ok(Some(ok)));
+
+        let raw = json!({
+            "jsonrpc": "2.0",
+            "method": "workspace/executeCommand",
+            "params": { "command": "add", "arguments": [1, 2] },
+            "id": 2
+        });
+        let execute: crate::jsonrpc::Incoming = serde_json::from_value(raw).unwrap();
+        let ok = serde_json::from_value(json!({ "jsonrpc": "2.0", "result": 3, "id": 2 })).unwrap();
+        assert_eq!(service.poll_ready(), Poll::Ready(Ok(())));
+        assert_eq!(service.call(execute).await, Ok(Some(ok)));
+    }
+
+    #[tokio::test]
+    async fn exit_aborts_background_tasks() {
+        let (service, _) = LspService::new(|_| Mock);
+        let (task, rx) = crate::task::pending_task();
+        service.spawner().spawn(task);
+        let mut service = Spawn::new(service);
+
+        let exit: crate::jsonrpc::Incoming = serde_json::from_str(EXIT_NOTIF).unwrap();
+        assert_eq!(service.poll_ready(), Poll::Ready(Ok(())));
+        assert_eq!(service.call(exit).await, Ok(None));
+        assert!(rx.await.is_err());
+    }
+
+    #[tokio::test]
+    async fn reset() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let (mut service, _) = LspService::new(|_| Mock);
+        assert_eq!(service.reset(), Err(ResetError::NotRestartable));
+
+        let created = Arc::new(AtomicUsize::new(0));
+        let init = {
+            let created = created.clone();
+            move |_: Client| {
+                created.fetch_add(1, Ordering::SeqCst);
+                Mock
+            }
+        };
+        let (mut service, _) = LspService::build(init).restartable().finish();
+        assert_eq!(created.load(Ordering::SeqCst), 1);
+
+        let initialize: crate::jsonrpc::Incoming = serde_json::from_str(INITIALIZE_REQUEST).unwrap();
+        let raw = json!({ "jsonrpc": "2.0", "result": { "capabilities": {} }, "id": 1 });
+        let ok: crate::jsonrpc::Outgoing = serde_json::from_value(raw).unwrap();
+        assert_eq!(service.call(initialize.clone()).await, Ok(Some(ok.clone())));
+
+        let (task, rx) = crate::task::pending_task();
+        service.spawner().spawn(task);
+
+        assert_eq!(service.reset(), Ok(()));
+        assert_eq!(created.load(Ordering::SeqCst), 2);
+        assert!(rx.await.is_err());
+        assert_eq!(service.call(initialize).await, Ok(Some(ok)));
+
+        let exit: crate::jsonrpc::Incoming = serde_json::from_str(EXIT_NOTIF).unwrap();
+        assert_eq!(service.call(exit).await, Ok(None));
+        assert_eq!(service.reset(), Err(ResetError::Exited));
+    }
+
+    #[tokio::test]
+    async fn session_reconnected() {
+        let (mut service, _messages) = LspService::new(|_| Mock);
+        let session = service.session();
+
+        let initialize: crate::jsonrpc::Incoming = serde_json::from_str(INITIALIZE_REQUEST).unwrap();
+        let raw = json!({ "jsonrpc": "2.0", "result": { "capabilities": {} }, "id": 1 });
+        let ok: crate::jsonrpc::Outgoing = serde_json::from_value(raw).unwrap();
+        assert_eq!(service.call(initialize.clone()).await, Ok(Some(ok.clone())));
+
+        let client = service.client.clone();
+        let request = tokio::spawn(async move { client.workspace_folders().await });
+        while service.pending_requests().outgoing_count() == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        session.reconnected();
+        assert_eq!(request.await.unwrap(), Err(crate::jsonrpc::Error::request_cancelled()));
+        assert_eq!(service.call(initialize).await, Ok(Some(ok)));
+
+        let exit: crate::jsonrpc::Incoming = serde_json::from_str(EXIT_NOTIF).unwrap();
+        assert_eq!(service.call(exit).await, Ok(None));
+        session.reconnected();
+        assert_eq!(service.state.get(), crate::server::StateKind::Exited);
+    }
+
+    #[tokio::test]
+    async fn replace_backend() {
+        #[derive(Debug)]
+        struct Named(&'static str, Arc<futures::lock::Mutex<()>>);
+
+        #[async_trait]
+        impl crate::LanguageServer for Named {
+            async fn initialize(&self, _: lsp::InitializeParams) -> crate::jsonrpc::Result<lsp::InitializeResult> {
+                Ok(lsp::InitializeResult::default())
+            }
+
+            async fn shutdown(&self) -> crate::jsonrpc::Result<()> {
+                Ok(())
+            }
+
+            async fn hover(&self, _: lsp::HoverParams) -> crate::jsonrpc::Result<Option<lsp::Hover>> {
+                let _gate = self.1.lock().await;
+                Ok(Some(lsp::Hover {
+                    contents: lsp::HoverContents::Scalar(lsp::MarkedString::String(self.0.into())),
+                    range: None,
+                }))
+            }
+        }
+
+        let hover = |id: i64| {
+            let raw = json!({
+                "jsonrpc": "2.0",
+                "method": "textDocument/hover",
+                "params": { "textDocument": { "uri": "file:///main.rs" }, "position": { "line": 0, "character": 0 } },
+                "id": id,
+            });
+            serde_json::from_value::<crate::jsonrpc::Incoming>(raw).unwrap()
+        };
+        let contents = |name: &str, id: i64| {
+            let raw = json!({ "jsonrpc": "2.0", "result": { "contents": name }, "id": id });
+            Ok(Some(serde_json::from_value::<crate::jsonrpc::Outgoing>(raw).unwrap()))
+        };
+
+        let gate = Arc::new(futures::lock::Mutex::new(()));
+        let (mut service, _) = LspService::new({
+            let gate = gate.clone();
+            move |_| Named("before", gate)
+        });
+        let initialize: crate::jsonrpc::Incoming = serde_json::from_str(INITIALIZE_REQUEST).unwrap();
+        assert!(service.call(initialize).await.unwrap().is_some());
+
+        let backend = service.backend();
+        let closed = gate.lock().await;
+        let before = service.call(hover(2));
+        let mut drained = backend.replace(|_| Named("after", Default::default())).unwrap();
+        assert_eq!(service.call(hover(3)).await, contents("after", 3));
+        assert_eq!(drained.in_flight(), 1);
+        assert!((&mut drained).now_or_never().is_none());
+
+        drop(closed);
+        assert_eq!(before.await, contents("before", 2));
+        drained.await;
+
+        let exit: crate::jsonrpc::Incoming = serde_json::from_str(EXIT_NOTIF).unwrap();
+        assert_eq!(service.call(exit).await, Ok(None));
+        let error = service.replace_backend(|_| Mock).unwrap_err();
+        assert_eq!(error, ReplaceError::Exited);
+    }
+
+    #[tokio::test]
+    async fn did_change_cancels_document_scope() {
+        let (mut service, _) = LspService::new(|_| Mock);
+        let initialize: crate::jsonrpc::Incoming = serde_json::from_str(INITIALIZE_REQUEST).unwrap();
+        assert!(service.call(initialize).await.is_ok());
+
+        let uri = lsp::Url::parse("file:///a.rs").unwrap();
+        let (task, rx) = crate::task::pending_task();
+        service.client.document_scope(uri).spawn(task);
+
+        let raw = json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didChange",
+            "params": { "textDocument": { "uri": "file:///a.rs", "version": 2 }, "contentChanges": [] },
+        });
+        let did_change: crate::jsonrpc::Incoming = serde_json::from_value(raw).unwrap();
+        assert_eq!(service.call(did_change).await, Ok(None));
+        assert!(rx.await.is_err());
+    }
+
+    #[tokio::test]
+    async fn latency_budget() {
+        use futures::StreamExt;
+
+        #[derive(Debug)]
+        struct Slow(crate::MockClock);
+
+        #[async_trait]
+        impl crate::LanguageServer for Slow {
+            async fn initialize(&self, _: lsp::InitializeParams) -> crate::jsonrpc::Result<lsp::InitializeResult> {
+                self.0.advance(Duration::from_secs(2));
+                Ok(lsp::InitializeResult::default())
+            }
+
+            async fn shutdown(&self) -> crate::jsonrpc::Result<()> {
+                Ok(())
+            }
+        }
+
+        let clock = crate::MockClock::new();
+        let budget = LatencyBudget::new()
+            .default_budget(Duration::from_secs(5))
+            .budget("initialize", Duration::from_secs(1))
+            .log_to_client(true);
+        let init = {
+            let clock = clock.clone();
+            move |_| Slow(clock)
+        };
+        let (mut service, mut messages) = LspService::build(init).clock(clock).latency_budget(budget).finish();
+
+        let initialize: crate::jsonrpc::Incoming = serde_json::from_str(INITIALIZE_REQUEST).unwrap();
+        assert!(service.call(initialize).await.is_ok());
+
+        let message = serde_json::to_value(messages.next().await.unwrap()).unwrap();
+        assert_eq!(message["method"], "window/logMessage");
+        assert_eq!(message["params"]["type"], 2);
+        let text = message["params"]["message"].as_str().unwrap();
+        assert!(text.contains(r#"method="initialize" id=1 elapsed=2s"#), "{}", text);
+    }
+
+    #[tokio::test]
+    async fn response_filter() {
+        #[derive(Debug)]
+        struct Folding;
+
+        #[async_trait]
+        impl crate::LanguageServer for Folding {
+            async fn initialize(&self, _: lsp::InitializeParams) -> crate::jsonrpc::Result<lsp::InitializeResult> {
+                Ok(lsp::InitializeResult::default())
+            }
+
+            async fn shutdown(&self) -> crate::jsonrpc::Result<()> {
+                Ok(())
+            }
+
+            async fn folding_range(
+                &self,
+                _: lsp::FoldingRangeParams,
+            ) -> crate::jsonrpc::Result<Option<Vec<lsp::FoldingRange>>> {
+                let range = |start_line| lsp::FoldingRange {
+                    start_line,
+                    end_line: start_line + 1,
+                    kind: Some(lsp::FoldingRangeKind::Region),
+                    ..Default::default()
+                };
+                Ok(Some(vec![range(0), range(2), range(4)]))
+            }
+        }
+
+        let (mut service, _) = LspService::build(|_| Folding)
+            .builtin_response_filters(true)
+            .response_filter::<lsp::request::FoldingRangeRequest, _>(|_, ranges, _| {
+                let mut ranges = ranges?;
+                ranges.reverse();
+                Some(ranges)
+            })
+            .finish();
+
+        let raw = json!({
+            "jsonrpc": "2.0",
+            "method": "initialize",
+            "params": {
+                "capabilities": {
+                    "textDocument": {
+                        "foldingRange": { "rangeLimit": 2, "foldingRangeKind": { "valueSet": ["comment"] } },
+                    },
+                },
+            },
+            "id": 1,
+        });
+        let initialize: crate::jsonrpc::Incoming = serde_json::from_value(raw).unwrap();
+        assert!(service.call(initialize).await.is_ok());
+
+        let raw = json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/foldingRange",
+            "params": { "textDocument": { "uri": "file:///a.rs" } },
+            "id": 2,
+        });
+        let folding_range: crate::jsonrpc::Incoming = serde_json::from_value(raw).unwrap();
+        let raw = json!({
+            "jsonrpc": "2.0",
+            "result": [{ "startLine": 2, "endLine": 3 }, { "startLine": 0, "endLine": 1 }],
+            "id": 2,
+        });
+        let filtered = serde_json::from_value(raw).unwrap();
+        assert_eq!(service.call(folding_range).await, Ok(Some(filtered)));
+    }
+
+    #[tokio::test]
+    #[allow(deprecated)]
+    async fn symbol_compatibility() {
+        #[derive(Debug)]
+        struct Outline;
+
+        #[async_trait]
+        impl crate::LanguageServer for Outline {
+            async fn initialize(&self, _: lsp::InitializeParams) -> crate::jsonrpc::Result<lsp::InitializeResult> {
+                Ok(lsp::InitializeResult::default())
+            }
+
+            async fn shutdown(&self) -> crate::jsonrpc::Result<()> {
+                Ok(())
+            }
+
+            async fn document_symbol(
+                &self,
+                _: lsp::DocumentSymbolParams,
+            ) -> crate::jsonrpc::Result<Option<lsp::DocumentSymbolResponse>> {
+                let range = lsp::Range::new(lsp::Position::new(0, 0), lsp::Position::new(1, 0));
+                let symbol = |name: &str, children| lsp::DocumentSymbol {
+                    name: name.into(),
+                    detail: None,
+                    kind: lsp::SymbolKind::FUNCTION,
+                    tags: Some(vec![lsp::SymbolTag::DEPRECATED]),
+                    deprecated: Some(true),
+                    range,
+                    selection_range: range,
+                    children,
+                };
+                let outer = symbol("outer", Some(vec![symbol("inner", None)]));
+                Ok(Some(lsp::DocumentSymbolResponse::Nested(vec![outer])))
+            }
+        }
+
+        async fn symbols(capabilities: serde_json::Value, empty: bool) -> Vec<serde_json::Value> {
+            let mut builder = LspService::build(|_| Outline).builtin_response_filters(true);
+            if empty {
+                builder = builder.empty_result_when_unimplemented::<lsp::request::WorkspaceSymbol>();
+            }
+            let (mut service, _) = builder.finish();
+
+            let params = json!({ "capabilities": capabilities });
+            let raw = json!({ "jsonrpc": "2.0", "method": "initialize", "params": params, "id": 1 });
+            let initialize: crate::jsonrpc::Incoming = serde_json::from_value(raw).unwrap();
+            assert!(service.call(initialize).await.is_ok());
+
+            let mut responses = Vec::new();
+            let requests = [
+                json!({
+                    "method": "textDocument/documentSymbol",
+                    "params": { "textDocument": { "uri": "file:///a.rs" } },
+                }),
+                json!({ "method": "workspace/symbol", "params": { "query": "" } }),
+            ];
+            for (id, mut raw) in requests.into_iter().enumerate() {
+                raw["jsonrpc"] = json!("2.0");
+                raw["id"] = json!(id + 2);
+                let request: crate::jsonrpc::Incoming = serde_json::from_value(raw).unwrap();
+                let response = service.call(request).await.unwrap().unwrap();
+                responses.push(serde_json::to_value(response).unwrap());
+            }
+            responses
+        }
+
+        // Clients predating hierarchical symbols and tags only understand `deprecated`.
+        let legacy = symbols(json!({}), false).await;
+        let flat = &legacy[0]["result"];
+        assert_eq!(flat.as_array().map(Vec::len), Some(2));
+        assert_eq!(flat[1]["name"], "inner");
+        assert_eq!(flat[1]["containerName"], "outer");
+        assert_eq!(flat[1]["deprecated"], true);
+        assert!(flat[1].get("tags").is_none());
+        assert_eq!(legacy[1]["error"]["code"], -32601);
+
+        let capabilities = json!({
+            "textDocument": {
+                "documentSymbol": { "hierarchicalDocumentSymbolSupport": true, "tagSupport": { "valueSet": [1] } },
+            },
+        });
+        let current = symbols(capabilities, true).await;
+        let nested = &current[0]["result"];
+        assert_eq!(nested.as_array().map(Vec::len), Some(1));
+        assert_eq!(nested[0]["children"][0]["name"], "inner");
+        assert_eq!(nested[0]["tags"], json!([1]));
+        assert_eq!(current[1]["result"], serde_json::Value::Null);
+        assert!(current[1].get("error").is_none());
+    }
+
+    #[tokio::test]
+    async fn protocol_log() {
+        #[derive(Clone, Default)]
+        struct Buffer(Arc<std::sync::Mutex<Vec<u8>>>);
+
+        impl std::io::Write for Buffer {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().write(buf)
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let buffer = Buffer::default();
+        let (mut service, _) = LspService::build(|_| Mock)
+            .protocol_log(ProtocolLog::new(buffer.clone()))
+            .finish();
+
+        let shutdown: crate::jsonrpc::Incoming = serde_json::from_str(SHUTDOWN_REQUEST).unwrap();
+        assert!(service.call(shutdown).await.is_ok());
+        let initialize: crate::jsonrpc::Incoming = serde_json::from_str(INITIALIZE_REQUEST).unwrap();
+        assert!(service.call(initialize).await.is_ok());
+
+        let buffer = buffer.0.lock().unwrap();
+        let records: Vec<serde_json::Value> = std::str::from_utf8(&buffer)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records, vec![
+            json!({
+                "direction": "incoming",
+                "kind": "request",
+                "method": "shutdown",
+                "id": 1,
+                "state": "uninitialized",
+                "warnings": ["request before initialize"],
+            }),
+            json!({
+                "direction": "outgoing",
+                "kind": "response",
+                "method": "shutdown",
+                "id": 1,
+                "state": "uninitialized",
+                "warnings": [],
+                "error": -32002,
+            }),
+            json!({
+                "direction": "incoming",
+                "kind": "request",
+                "method": "initialize",
+                "id": 1,
+                "state": "uninitialized",
+                "warnings": [],
+            }),
+            json!({
+                "direction": "outgoing",
+                "kind": "response",
+                "method": "initialize",
+                "id": 1,
+                "state": "initialized",
+                "warnings": [],
+            }),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn initializing_policy() {
+        let initialize: crate::jsonrpc::Incoming = serde_json::from_str(INITIALIZE_REQUEST).unwrap();
+        let shutdown: crate::jsonrpc::Incoming = serde_json::from_str(SHUTDOWN_REQUEST).unwrap();
+        let raw = json!({ "jsonrpc": "2.0", "result": { "capabilities": {} }, "id": 1 });
+        let initialized: crate::jsonrpc::Outgoing = serde_json::from_value(raw).unwrap();
+
+        let (mut service, _) = LspService::new(|_| Mock);
+        let initialize_fut = service.call(initialize.clone());
+        let raw = json!({ "jsonrpc": "2.0", "error": { "code": -32600, "message": "Invalid request" }, "id": 1 });
+        let err = serde_json::from_value(raw).unwrap();
+        assert_eq!(service.call(shutdown.clone()).await, Ok(Some(err)));
+        assert_eq!(initialize_fut.await, Ok(Some(initialized.clone())));
+
+        let (mut service, _) = LspService::build(|_| Mock)
+            .initializing_policy(InitializingPolicy::Queue)
+            .finish();
+        let initialize_fut = service.call(initialize);
+        let shutdown_fut = service.call(shutdown);
+        let raw = json!({ "jsonrpc": "2.0", "method": "workspace/symbol", "params": { "query": "" }, "id": 2 });
+        let symbol_fut = service.call(serde_json::from_value(raw).unwrap());
+        let raw = json!({ "jsonrpc": "2.0", "method": "$/cancelRequest", "params": { "id": 2 } });
+        assert_eq!(service.call(serde_json::from_value(raw).unwrap()).await, Ok(None));
+        let raw = json!({ "jsonrpc": "2.0", "error": { "code": -32800, "message": "Canceled" }, "id": 2 });
+        assert_eq!(symbol_fut.await, Ok(Some(serde_json::from_value(raw).unwrap())));
+        assert_eq!(format!("{:?}", service.replay), r#"["shutdown"]"#);
+        assert_eq!(initialize_fut.await, Ok(Some(initialized)));
+        let raw = json!({ "jsonrpc": "2.0", "result": null, "id": 1 });
+        let ok = serde_json::from_value(raw).unwrap();
+        assert_eq!(shutdown_fut.await, Ok(Some(ok)));
+    }
+
+    #[tokio::test]
+    async fn lifecycle_hooks() {
+        use std::sync::Mutex;
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let hook = |name: &'static str| {
+            let events = events.clone();
+            move |_| {
+                let events = events.clone();
+                async move { events.lock().unwrap().push(name) }
+            }
+        };
+
+        let (service, _) = LspService::build(|_| Mock)
+            .on_initialized(hook("initialized"))
+            .on_shutdown(hook("shutdown 1"))
+            .on_shutdown(hook("shutdown 2"))
+            .on_exit(hook("exit"))
+            .finish();
+        let mut service = Spawn::new(service);
+
+        for message in &[INITIALIZE_REQUEST, INITIALIZED_NOTIF, SHUTDOWN_REQUEST, EXIT_NOTIF] {
+            let message: crate::jsonrpc::Incoming = serde_json::from_str(message).unwrap();
+            assert_eq!(service.poll_ready(), Poll::Ready(Ok(())));
+            assert!(service.call(message).await.is_ok());
+        }
+
+        let events = events.lock().unwrap().clone();
+        assert_eq!(events, vec!["initialized", "shutdown 1", "shutdown 2", "exit"]);
+    }
+
+    #[tokio::test]
+    async fn security_policy() {
+        let policy = SecurityPolicy::allow_all()
+            .deny("workspace/executeCommand")
+            .authenticate(|params| async move { params == Some(json!({ "token": "secret" })) });
+        let (service, _) = LspService::build(|_| Mock).security_policy(policy).finish();
+        let mut service = Spawn::new(service);
+
+        let initialize: crate::jsonrpc::Incoming = serde_json::from_str(INITIALIZE_REQUEST).unwrap();
+        assert_eq!(service.poll_ready(), Poll::Ready(Ok(())));
+        assert!(service.call(initialize).await.unwrap().is_some());
+
+        let shutdown: crate::jsonrpc::Incoming = serde_json::from_str(SHUTDOWN_REQUEST).unwrap();
+        let error = json!({ "code": -32000, "message": "Authentication required" });
+        let err = serde_json::from_value(json!({ "jsonrpc": "2.0", "error": error, "id": 1 })).unwrap();
+        assert_eq!(service.call(shutdown.clone()).await, Ok(Some(err)));
+
+        let params = json!({ "token": "guess" });
+        let raw = json!({ "jsonrpc": "2.0", "method": "lspower/authenticate", "params": params, "id": 2 });
+        let authenticate = serde_json::from_value(raw).unwrap();
+        let error = json!({ "code": -32000, "message": "Authentication failed" });
+        let err = serde_json::from_value(json!({ "jsonrpc": "2.0", "error": error, "id": 2 })).unwrap();
+        assert_eq!(service.call(authenticate).await, Ok(Some(err)));
+
+        let params = json!({ "token": "secret" });
+        let raw = json!({ "jsonrpc": "2.0", "method": "lspower/authenticate", "params": params, "id": 3 });
+        let authenticate = serde_json::from_value(raw).unwrap();
+        let ok = serde_json::from_value(json!({ "jsonrpc": "2.0", "result": null, "id": 3 })).unwrap();
+        assert_eq!(service.call(authenticate).await, Ok(Some(ok)));
+
+        let raw = json!({
+            "jsonrpc": "2.0",
+            "method": "workspace/executeCommand",
+            "params": { "command": "rm", "arguments": [] },
+            "id": 4
+        });
+        let execute = serde_json::from_value(raw).unwrap();
+        let raw = json!({ "jsonrpc": "2.0", "error": { "code": -32601, "message": "Method not found" }, "id": 4 });
+        let err = serde_json::from_value(raw).unwrap();
+        assert_eq!(service.call(execute).await, Ok(Some(err)));
+
+        let ok = serde_json::from_value(json!({ "jsonrpc": "2.0", "result": null, "id": 1 })).unwrap();
+        assert_eq!(service.call(shutdown).await, Ok(Some(ok)));
+    }
+
+    #[tokio::test]
+    async fn coalescing_policy() {
+        #[derive(Debug, Default)]
+        struct Hover(std::sync::atomic::AtomicUsize);
+
+        #[async_trait]
+        impl crate::LanguageServer for Hover {
+            async fn initialize(&self, _: lsp::InitializeParams) -> crate::jsonrpc::Result<lsp::InitializeResult> {
+                Ok(lsp::InitializeResult::default())
+            }
+
+            async fn shutdown(&self) -> crate::jsonrpc::Result<()> {
+                Ok(())
+            }
+
+            async fn hover(&self, _: lsp::HoverParams) -> crate::jsonrpc::Result<Option<lsp::Hover>> {
+                let calls = self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst) + 1;
+                Ok(Some(lsp::Hover {
+                    contents: lsp::HoverContents::Scalar(lsp::MarkedString::String(calls.to_string())),
+                    range: None,
+                }))
+            }
+        }
+
+        let policy = CoalescingPolicy::new().coalesce("textDocument/hover");
+        let (service, _) = LspService::build(|_| Hover::default())
+            .coalescing_policy(policy)
+            .finish();
+        let mut service = Spawn::new(service);
+
+        let initialize: crate::jsonrpc::Incoming = serde_json::from_str(INITIALIZE_REQUEST).unwrap();
+        assert_eq!(service.poll_ready(), Poll::Ready(Ok(())));
+        assert!(service.call(initialize).await.unwrap().is_some());
+
+        let hover = |id| {
+            let position = json!({ "line": 0, "character": 0 });
+            let params = json!({ "textDocument": { "uri": "file:///a.rs" }, "position": position });
+            let raw = json!({ "jsonrpc": "2.0", "method": "textDocument/hover", "params": params, "id": id });
+            serde_json::from_value::<crate::jsonrpc::Incoming>(raw).unwrap()
+        };
+        let response = |contents, id| {
+            let raw = json!({ "jsonrpc": "2.0", "result": { "contents": contents }, "id": id });
+            Ok(Some(serde_json::from_value(raw).unwrap()))
+        };
+
+        let first = service.call(hover(2));
+        let second = service.call(hover(3));
+        let (first, second) = futures::join!(first, second);
+        assert_eq!(first, response("1", 2));
+        assert_eq!(second, response("1", 3));
+
+        assert_eq!(service.call(hover(4)).await, response("2", 4));
+
+        // Canceling a coalesced request cancels the request it was coalesced with.
+        let first = service.call(hover(5));
+        let second = service.call(hover(6));
+        let raw = json!({ "jsonrpc": "2.0", "method": "$/cancelRequest", "params": { "id": 6 } });
+        assert_eq!(service.call(serde_json::from_value(raw).unwrap()).await, Ok(None));
+        let cancelled = |id| {
+            let raw = json!({ "jsonrpc": "2.0", "error": { "code": -32800, "message": "Canceled" }, "id": id });
+            Ok(Some(serde_json::from_value(raw).unwrap()))
+        };
+        let (first, second) = futures::join!(first, second);
+        assert_eq!(first, cancelled(5));
+        assert_eq!(second, cancelled(6));
+        assert_eq!(format!("{:?}", service.get_ref().in_flight), "InFlight(0)");
+    }
+
+    #[tokio::test]
+    async fn raw_handlers() {
+        use serde::Deserialize;
+        use std::borrow::Cow;
+
+        #[derive(Debug)]
+        struct Editor(Arc<std::sync::Mutex<Vec<String>>>);
+
+        #[async_trait]
+        impl crate::LanguageServer for Editor {
+            async fn initialize(&self, _: lsp::InitializeParams) -> crate::jsonrpc::Result<lsp::InitializeResult> {
+                Ok(lsp::InitializeResult::default())
+            }
+
+            async fn shutdown(&self) -> crate::jsonrpc::Result<()> {
+                Ok(())
+            }
+
+            async fn did_open(&self, params: lsp::DidOpenTextDocumentParams) {
+                self.0.lock().unwrap().push(params.text_document.text);
+            }
+
+            async fn did_change(&self, _: lsp::DidChangeTextDocumentParams) {
+                unreachable!("`did_change_raw` is overridden");
+            }
+
+            async fn did_change_raw(&self, params: &crate::jsonrpc::RawValue) {
+                #[derive(Deserialize)]
+                #[serde(rename_all = "camelCase")]
+                struct DidChange<'a> {
+                    #[serde(borrow)]
+                    content_changes: Vec<Change<'a>>,
+                }
+
+                #[derive(Deserialize)]
+                struct Change<'a> {
+                    #[serde(borrow)]
+                    text: Cow<'a, str>,
+                }
+
+                let params: DidChange = serde_json::from_str(params.get()).unwrap();
+                let mut texts = self.0.lock().unwrap();
+                texts.extend(params.content_changes.into_iter().map(|change| change.text.into_owned()));
+            }
+        }
+
+        let texts = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let (service, _) = LspService::new(|_| Editor(texts.clone()));
+        let mut service = Spawn::new(service);
+
+        let initialize: crate::jsonrpc::Incoming = serde_json::from_str(INITIALIZE_REQUEST).unwrap();
+        assert_eq!(service.poll_ready(), Poll::Ready(Ok(())));
+        assert!(service.call(initialize).await.unwrap().is_some());
+
+        let document = json!({ "uri": "file:///a.rs", "languageId": "rust", "version": 0, "text": "fn" });
+        let raw = json!({ "jsonrpc": "2.0", "method": "textDocument/didOpen", "params": { "textDocument": document } });
+        let did_open = crate::jsonrpc::Incoming::from_json(&raw.to_string()).unwrap();
+        assert_eq!(did_open, serde_json::from_value(raw).unwrap());
+        assert_eq!(service.call(did_open).await, Ok(None));
+
+        let did_change = |text: &str| {
+            let document = json!({ "uri": "file:///a.rs", "version": 1 });
+            let params = json!({ "textDocument": document, "contentChanges": [{ "text": text }] });
+            json!({ "jsonrpc": "2.0", "method": "textDocument/didChange", "params": params })
+        };
+        let raw = crate::jsonrpc::Incoming::from_json(&did_change("fn main").to_string()).unwrap();
+        assert_eq!(service.call(raw).await, Ok(None));
+        let parsed = serde_json::from_value(did_change("fn main() {}")).unwrap();
+        assert_eq!(service.call(parsed).await, Ok(None));
+
+        assert_eq!(*texts.lock().unwrap(), ["fn", "fn main", "fn main() {}"]);
+    }
+
+    #[tokio::test]
+    async fn strict_mode() {
+        let violations = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = violations.clone();
+        let strict = StrictMode::new(ViolationAction::Reject)
+            .on_violation(move |violation| recorded.lock().unwrap().push(violation.clone()));
+        let (service, _) = LspService::build(|_| Mock).strict_mode(strict).finish();
+        let mut service = Spawn::new(service);
+
+        let initialize: crate::jsonrpc::Incoming = serde_json::from_str(INITIALIZE_REQUEST).unwrap();
+        assert_eq!(service.poll_ready(), Poll::Ready(Ok(())));
+        assert!(service.call(initialize).await.unwrap().is_some());
+
+        let uri = lsp::Url::parse("file:///main.rs").unwrap();
+        let raw = json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didChange",
+            "params": { "textDocument": { "uri": uri, "version": 1 }, "contentChanges": [] },
+        });
+        let did_change: crate::jsonrpc::Incoming = serde_json::from_value(raw).unwrap();
+        assert_eq!(service.call(did_change.clone()).await, Ok(None));
+
+        let raw = json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/hover",
+            "params": { "textDocument": { "uri": uri }, "position": { "line": 0, "character": 0 } },
+            "id": 2
+        });
+        let hover: crate::jsonrpc::Incoming = serde_json::from_value(raw).unwrap();
+        let message = format!("\"textDocument/hover\" request for {}, which is not open", uri);
+        let error = json!({ "code": -32600, "message": message });
+        let err = serde_json::from_value(json!({ "jsonrpc": "2.0", "error": error, "id": 2 })).unwrap();
+        assert_eq!(service.call(hover.clone()).await, Ok(Some(err)));
+
+        let raw = json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didOpen",
+            "params": { "textDocument": { "uri": uri, "languageId": "rust", "version": 0, "text": "" } },
+        });
+        let did_open: crate::jsonrpc::Incoming = serde_json::from_value(raw).unwrap();
+        assert_eq!(service.call(did_open.clone()).await, Ok(None));
+        assert_eq!(service.call(did_open).await, Ok(None));
+        assert_eq!(service.call(did_change).await, Ok(None));
+
+        let error = json!({ "code": -32601, "message": "Method not found" });
+        let err = serde_json::from_value(json!({ "jsonrpc": "2.0", "error": error, "id": 2 })).unwrap();
+        assert_eq!(service.call(hover).await, Ok(Some(err)));
+
+        let violations = violations.lock().unwrap().clone();
+        assert_eq!(
+            violations,
+            vec![
+                ProtocolViolation::NotificationForClosedDocument {
+                    method: "textDocument/didChange".into(),
+                    uri: uri.clone(),
+                },
+                ProtocolViolation::RequestForClosedDocument {
+                    method: "textDocument/hover".into(),
+                    uri: uri.clone(),
+                },
+                ProtocolViolation::DuplicateOpen { uri },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn unhandled_notifications() {
+        use futures::StreamExt;
+
+        let policy = UnhandledNotificationPolicy::new().level(None);
+        let (service, _) = LspService::build(|_| Mock).unhandled_notification_policy(policy).finish();
+        let mut unhandled = service.unhandled_notifications();
+        let mut service = Spawn::new(service);
+
+        let initialize: crate::jsonrpc::Incoming = serde_json::from_str(INITIALIZE_REQUEST).unwrap();
+        assert_eq!(service.poll_ready(), Poll::Ready(Ok(())));
+        assert!(service.call(initialize).await.unwrap().is_some());
+
+        let raw = json!({ "jsonrpc": "2.0", "method": "workspace/didChangeWatchedFiles", "params": { "changes": [] } });
+        let did_change_watched_files: crate::jsonrpc::Incoming = serde_json::from_value(raw).unwrap();
+        assert_eq!(service.call(did_change_watched_files).await, Ok(None));
+
+        let raw = json!({ "jsonrpc": "2.0", "method": "custom/notification", "params": { "foo": "bar" } });
+        let custom: crate::jsonrpc::Incoming = serde_json::from_value(raw).unwrap();
+        assert_eq!(service.call(custom).await, Ok(None));
+
+        let notification = unhandled.next().await.unwrap();
+        assert_eq!(notification.method(), "workspace/didChangeWatchedFiles");
+        assert_eq!(notification.params(), Some(&json!({ "changes": [] })));
+        assert!(notification.is_known());
+
+        let notification = unhandled.next().await.unwrap();
+        assert_eq!(notification.method(), "custom/notification");
+        assert_eq!(notification.params(), Some(&json!({ "foo": "bar" })));
+        assert!(!notification.is_known());
+    }
+
+    #[tokio::test]
+    async fn dollar_method_policy() {
+        #[derive(Debug, Default)]
+        struct Extensions(Arc<std::sync::Mutex<Vec<String>>>);
+
+        #[async_trait]
+        impl crate::LanguageServer for Extensions {
+            async fn initialize(&self, _: lsp::InitializeParams) -> crate::jsonrpc::Result<lsp::InitializeResult> {
+                Ok(lsp::InitializeResult::default())
+            }
+
+            async fn shutdown(&self) -> crate::jsonrpc::Result<()> {
+                Ok(())
+            }
+
+            async fn request_else(
+                &self,
+                method: &str,
+                _: Option<serde_json::Value>,
+            ) -> crate::jsonrpc::Result<Option<serde_json::Value>> {
+                Ok(Some(json!(method)))
+            }
+
+            async fn notification_else(&self, method: &str, _: Option<serde_json::Value>) {
+                self.0.lock().unwrap().push(method.into());
+            }
+        }
+
+        for (forward, reject) in [(false, false), (true, false), (false, true)] {
+            let policy = DollarMethodPolicy::new().forward(forward).reject_requests(reject);
+            let received = Arc::new(std::sync::Mutex::new(Vec::new()));
+            let handled = received.clone();
+            let (service, _) = LspService::build(move |_| Extensions(handled))
+                .dollar_method_policy(policy.clone())
+                .finish();
+            let mut service = Spawn::new(service);
+
+            let initialize: crate::jsonrpc::Incoming = serde_json::from_str(INITIALIZE_REQUEST).unwrap();
+            assert_eq!(service.poll_ready(), Poll::Ready(Ok(())));
+            assert!(service.call(initialize).await.unwrap().is_some());
+
+            for method in ["$/custom", "custom/reload"] {
+                let raw = json!({ "jsonrpc": "2.0", "method": method, "params": {} });
+                let notification: crate::jsonrpc::Incoming = serde_json::from_value(raw).unwrap();
+                assert_eq!(service.call(notification).await, Ok(None));
+            }
+            let raw = json!({ "jsonrpc": "2.0", "method": "$/custom", "params": {}, "id": 2 });
+            let request: crate::jsonrpc::Incoming = serde_json::from_value(raw).unwrap();
+            let response = match reject {
+                false => json!({ "jsonrpc": "2.0", "result": "$/custom", "id": 2 }),
+                true => json!({ "jsonrpc": "2.0", "error": { "code": -32601, "message": "Method not found" }, "id": 2 }),
+            };
+            assert_eq!(service.call(request).await, Ok(Some(serde_json::from_value(response).unwrap())));
+
+            let expected: &[&str] = if forward { &["$/custom", "custom/reload"] } else { &["custom/reload"] };
+            assert_eq!(*received.lock().unwrap(), expected);
+            let counts = (u64::from(!forward), u64::from(reject));
+            assert_eq!((policy.ignored_notifications(), policy.rejected_requests()), counts);
+        }
+    }
+
+    #[tokio::test]
+    async fn anomalies() {
+        use crate::jsonrpc::Id;
+        use futures::StreamExt;
+
+        let (service, _) = LspService::new(|_| Mock);
+        let mut anomalies = service.anomalies();
+        let mut service = Spawn::new(service);
+
+        let initialize: crate::jsonrpc::Incoming = serde_json::from_str(INITIALIZE_REQUEST).unwrap();
+        assert_eq!(service.poll_ready(), Poll::Ready(Ok(())));
+        assert!(service.call(initialize.clone()).await.unwrap().is_some());
+        assert!(service.call(initialize).await.unwrap().is_some());
+        let shutdown: crate::jsonrpc::Incoming = serde_json::from_str(SHUTDOWN_REQUEST).unwrap();
+        assert!(service.call(shutdown.clone()).await.unwrap().is_some());
+        assert!(service.call(shutdown).await.unwrap().is_some());
+        let exit: crate::jsonrpc::Incoming = serde_json::from_str(EXIT_NOTIF).unwrap();
+        assert_eq!(service.call(exit.clone()).await, Ok(None));
+
+        let (service, _) = LspService::new(|_| Mock);
+        let mut exited = service.anomalies();
+        let mut service = Spawn::new(service);
+        assert_eq!(service.poll_ready(), Poll::Ready(Ok(())));
+        assert_eq!(service.call(exit).await, Ok(None));
+
+        let expected = [
+            AnomalyEvent::DuplicateInitialize {
+                id: Id::Number(1),
+                initialized: true,
+            },
+            AnomalyEvent::AfterShutdown {
+                method: "shutdown".into(),
+                id: Some(Id::Number(1)),
+            },
+        ];
+        for event in expected {
+            assert_eq!(anomalies.next().await, Some(event));
+        }
+        let event = exited.next().await.unwrap();
+        assert_eq!(event, AnomalyEvent::ExitWithoutShutdown { initialized: false });
+        assert_eq!(event.to_string(), "`exit` notification received without shutdown");
+    }
+
+    #[tokio::test]
+    async fn strict_jsonrpc() {
+        for strict in [false, true] {
+            let (service, _) = LspService::build(|_| Mock).strict_jsonrpc(strict).finish();
+            let mut service = Spawn::new(service);
+
+            let initialize: crate::jsonrpc::Incoming = serde_json::from_str(INITIALIZE_REQUEST).unwrap();
+            assert_eq!(service.poll_ready(), Poll::Ready(Ok(())));
+            assert!(service.call(initialize).await.unwrap().is_some());
+
+            let raw = json!({ "jsonrpc": "2.0", "method": "initialized", "params": {}, "id": 2 });
+            let initialized: crate::jsonrpc::Incoming = serde_json::from_value(raw).unwrap();
+            let error = json!({ "code": -32600, "message": "notifications must not have an id" });
+            let err = serde_json::from_value(json!({ "jsonrpc": "2.0", "error": error, "id": 2 })).unwrap();
+            let expected = if strict { Some(err) } else { None };
+            assert_eq!(service.call(initialized).await, Ok(expected));
+
+            let raw = json!({ "jsonrpc": "2.0", "method": "custom/notification", "params": 3 });
+            let custom: crate::jsonrpc::Incoming = serde_json::from_value(raw).unwrap();
+            let error = json!({ "code": -32600, "message": "params must be an array or an object" });
+            let err = serde_json::from_value(json!({ "jsonrpc": "2.0", "error": error, "id": null })).unwrap();
+            let expected = if strict { Some(err) } else { None };
+            assert_eq!(service.call(custom).await, Ok(expected));
+        }
+    }
+
+    #[tokio::test]
+    async fn before_dispatch() {
+        use std::ops::ControlFlow;
+
+        let (service, _) = LspService::build(|_| Mock)
+            .before_dispatch(|request| {
+                if request.method() != "initialize" {
+                    return ControlFlow::Continue(());
+                }
+                let mut params = request.params_value().unwrap();
+                params["capabilities"]["experimental"] = json!(true);
+                request.set_params(params).unwrap();
+                ControlFlow::Continue(())
+            })
+            .before_dispatch(|request| match request.id() {
+                Some(id) if request.method() == "shutdown" => {
+                    let error = crate::jsonrpc::Error::invalid_request().with_message("vetoed");
+                    ControlFlow::Break(crate::jsonrpc::Response::error(Some(id.clone()), error))
+                },
+                _ => ControlFlow::Continue(()),
+            })
+            .finish();
+        let mut service = Spawn::new(service);
+
+        let initialize: crate::jsonrpc::Incoming = serde_json::from_str(INITIALIZE_REQUEST).unwrap();
+        assert_eq!(service.poll_ready(), Poll::Ready(Ok(())));
+        assert!(service.call(initialize).await.unwrap().is_some());
+        let capabilities = service.get_ref().client.client_capabilities().unwrap();
+        assert_eq!(capabilities.experimental, Some(json!(true)));
+
+        let shutdown: crate::jsonrpc::Incoming = serde_json::from_str(SHUTDOWN_REQUEST).unwrap();
+        let error = json!({ "code": -32600, "message": "vetoed" });
+        let err = serde_json::from_value(json!({ "jsonrpc": "2.0", "error": error, "id": 1 })).unwrap();
+        assert_eq!(service.call(shutdown).await, Ok(Some(err)));
+        assert_eq!(service.get_ref().state.get(), crate::server::StateKind::Initialized);
+    }
+
+    #[tokio::test]
+    async fn on_request() {
+        let (service, _) = LspService::build(|_| Mock)
+            .on_request(|request| match request.method() {
+                "workspace/symbol" => Interception::Respond(future::ok(json!([])).boxed()),
+                "custom/request" => Interception::Reject(crate::jsonrpc::Error::invalid_params("disabled")),
+                _ => Interception::PassThrough,
+            })
+            .finish();
+        let mut service = Spawn::new(service);
+        let request = |method: &str, id: u64| {
+            let raw = json!({ "jsonrpc": "2.0", "method": method, "params": { "query": "" }, "id": id });
+            serde_json::from_value::<crate::jsonrpc::Incoming>(raw).unwrap()
+        };
+
+        // Requests are not intercepted before the server is initialized.
+        let error = json!({ "code": -32002, "message": "Server not initialized" });
+        let err = serde_json::from_value(json!({ "jsonrpc": "2.0", "error": error, "id": 2 })).unwrap();
+        assert_eq!(service.poll_ready(), Poll::Ready(Ok(())));
+        assert_eq!(service.call(request("workspace/symbol", 2)).await, Ok(Some(err)));
+
+        let initialize: crate::jsonrpc::Incoming = serde_json::from_str(INITIALIZE_REQUEST).unwrap();
+        assert!(service.call(initialize).await.unwrap().is_some());
+
+        let ok = serde_json::from_value(json!({ "jsonrpc": "2.0", "result": [], "id": 3 })).unwrap();
+        assert_eq!(service.call(request("workspace/symbol", 3)).await, Ok(Some(ok)));
+
+        let error = json!({ "code": -32602, "message": "disabled" });
+        let err = serde_json::from_value(json!({ "jsonrpc": "2.0", "error": error, "id": 4 })).unwrap();
+        assert_eq!(service.call(request("custom/request", 4)).await, Ok(Some(err)));
+
+        let shutdown: crate::jsonrpc::Incoming = serde_json::from_str(SHUTDOWN_REQUEST).unwrap();
+        assert!(service.call(shutdown).await.unwrap().is_some());
+        assert_eq!(service.get_ref().state.get(), crate::server::StateKind::ShutDown);
+    }
+
+    #[tokio::test]
+    async fn log_message_level() {
+        use futures::StreamExt;
+
+        let slot = Arc::new(std::sync::Mutex::new(None));
+        let captured = slot.clone();
+        let (service, messages) = LspService::build(move |client| {
+            captured.lock().unwrap().replace(client);
+            Mock
+        })
+        .log_message_level(lsp::MessageType::INFO)
+        .buffer_until_attached(8)
+        .finish();
+        let client: crate::Client = slot.lock().unwrap().take().unwrap();
+        let mut service = Spawn::new(service);
+
+        let initialize: crate::jsonrpc::Incoming = serde_json::from_str(INITIALIZE_REQUEST).unwrap();
+        assert_eq!(service.poll_ready(), Poll::Ready(Ok(())));
+        assert!(service.call(initialize).await.unwrap().is_some());
+        assert_eq!(client.trace(), lsp::TraceOption::Off);
+
+        client.log_message(lsp::MessageType::LOG, "dropped").await;
+        client.log_message(lsp::MessageType::WARNING, "warning").await;
+
+        let set_trace = |value| {
+            let raw = json!({ "jsonrpc": "2.0", "method": "$/setTrace", "params": { "value": value } });
+            serde_json::from_value::<crate::jsonrpc::Incoming>(raw).unwrap()
+        };
+        assert_eq!(service.call(set_trace("verbose")).await, Ok(None));
+        assert_eq!(client.trace(), lsp::TraceOption::Verbose);
+        client.log_message(lsp::MessageType::LOG, "verbose").await;
+
+        assert_eq!(service.call(set_trace("messages")).await, Ok(None));
+        client.log_message(lsp::MessageType::LOG, "dropped").await;
+        client.close();
+
+        let sent: Vec<_> = messages
+            .map(|message| match message {
+                crate::jsonrpc::Outgoing::Request(sent) => sent.params()["message"].clone(),
+                other => panic!("expected a notification, got {:?}", other),
+            })
+            .collect()
+            .await;
+        assert_eq!(sent, [json!("warning"), json!("verbose")]);
+    }
+
+    #[tokio::test]
+    async fn shutdown_flushes_telemetry() {
+        use futures::StreamExt;
+
+        let slot = Arc::new(std::sync::Mutex::new(None));
+        let captured = slot.clone();
+        let (service, mut messages) = LspService::build(move |client| {
+            captured.lock().unwrap().replace(client);
+            Mock
+        })
+        .telemetry_policy(crate::TelemetryPolicy::new().flush_interval(Duration::from_secs(60)))
+        .shutdown_timeout(Duration::from_secs(60))
+        .buffer_until_attached(8)
+        .finish();
+        let client: crate::Client = slot.lock().unwrap().take().unwrap();
+        let mut service = Spawn::new(service);
+
+        let initialize: crate::jsonrpc::Incoming = serde_json::from_str(INITIALIZE_REQUEST).unwrap();
+        assert_eq!(service.poll_ready(), Poll::Ready(Ok(())));
+        assert!(service.call(initialize).await.unwrap().is_some());
+        client.telemetry_event(1u8).await;
+
+        // The shutdown neither waits for the flush timer nor for the shutdown timeout.
+        let shutdown: crate::jsonrpc::Incoming = serde_json::from_str(SHUTDOWN_REQUEST).unwrap();
+        let response = tokio::time::timeout(Duration::from_secs(5), service.call(shutdown)).await;
+        assert!(response.unwrap().unwrap().is_some());
+        match messages.next().await {
+            Some(crate::jsonrpc::Outgoing::Request(sent)) => assert_eq!(sent.params(), &json!([1])),
+            other => panic!("expected a notification, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn on_settings_changed() {
+        #[derive(Clone, Debug, PartialEq, serde::Deserialize)]
+        struct Settings {
+            enabled: bool,
+            level: u32,
+        }
+
+        let changes = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = changes.clone();
+        let (service, _) = LspService::build(|_| Mock)
+            .on_settings_changed(Some("example"), move |_, change: SettingsChange<Settings>| {
+                recorded.lock().unwrap().push(change);
+                async {}
+            })
+            .finish();
+        let mut service = Spawn::new(service);
+
+        let initialize: crate::jsonrpc::Incoming = serde_json::from_str(INITIALIZE_REQUEST).unwrap();
+        assert_eq!(service.poll_ready(), Poll::Ready(Ok(())));
+        assert!(service.call(initialize).await.unwrap().is_some());
+
+        for (enabled, level) in [(true, 1), (true, 1), (false, 1), (false, 2)] {
+            let settings = json!({ "example": { "enabled": enabled, "level": level }, "other": {} });
+            let raw = json!({ "jsonrpc": "2.0", "method": "workspace/didChangeConfiguration", "params": { "settings": settings } });
+            let notification: crate::jsonrpc::Incoming = serde_json::from_value(raw).unwrap();
+            assert_eq!(service.call(notification).await, Ok(None));
+        }
+
+        let raw = json!({ "jsonrpc": "2.0", "method": "workspace/didChangeConfiguration", "params": { "settings": { "example": 3 } } });
+        let invalid: crate::jsonrpc::Incoming = serde_json::from_value(raw).unwrap();
+        assert_eq!(service.call(invalid).await, Ok(None));
+
+        // The hooks run in the background, after the last notification was handled.
+        while changes.lock().unwrap().len() < 3 {
+            tokio::task::yield_now().await;
+        }
+        let changes = changes.lock().unwrap().clone();
+        let settings = |enabled, level| Settings { enabled, level };
+        assert_eq!(changes.len(), 3);
+        assert_eq!(changes[0].previous(), None);
+        assert_eq!(changes[0].current(), &settings(true, 1));
+        assert_eq!(changes[0].changed_sections(), ["enabled", "level"]);
+        assert_eq!(changes[1].previous(), Some(&settings(true, 1)));
+        assert_eq!(changes[1].current(), &settings(false, 1));
+        assert_eq!(changes[1].changed_sections(), ["enabled"]);
+        assert!(changes[2].is_changed("level"));
+        assert!(!changes[2].is_changed("enabled"));
+    }
+
+    #[tokio::test]
+    async fn override_method() {
+        let (service, _) = LspService::build(|_| Mock)
+            .override_method::<lsp::request::HoverRequest, _, _>(|inner, params| async move {
+                match params.text_document_position_params.position.line {
+                    0 => Ok(Some(lsp::Hover {
+                        contents: lsp::HoverContents::Scalar(lsp::MarkedString::String("overridden".into())),
+                        range: None,
+                    })),
+                    _ => inner.call(params).await,
+                }
+            })
+            .finish();
+        let mut service = Spawn::new(service);
+
+        let initialize: crate::jsonrpc::Incoming = serde_json::from_str(INITIALIZE_REQUEST).unwrap();
+        assert_eq!(service.poll_ready(), Poll::Ready(Ok(())));
+        assert!(service.call(initialize).await.unwrap().is_some());
+
+        let hover = |line: u32, id: u32| {
+            let position = json!({ "textDocument": { "uri": "file:///a.rs" }, "position": { "line": line, "character": 0 } });
+            let raw = json!({ "jsonrpc": "2.0", "method": "textDocument/hover", "params": position, "id": id });
+            serde_json::from_value::<crate::jsonrpc::Incoming>(raw).unwrap()
+        };
+
+        let raw = json!({ "jsonrpc": "2.0", "result": { "contents": "overridden" }, "id": 2 });
+        let overridden = serde_json::from_value(raw).unwrap();
+        assert_eq!(service.call(hover(0, 2)).await, Ok(Some(overridden)));
+
+        // The override falls back to the trait implementation, which does not implement hovers.
+        let raw = json!({ "jsonrpc": "2.0", "error": { "code": -32601, "message": "Method not found" }, "id": 3 });
+        let not_found = serde_json::from_value(raw).unwrap();
+        assert_eq!(service.call(hover(1, 3)).await, Ok(Some(not_found)));
+    }
+
+    #[tokio::test]
+    async fn shutdown_fails_pending_client_requests() {
+        use futures::StreamExt;
+
+        let slot = Arc::new(std::sync::Mutex::new(None));
+        let captured = slot.clone();
+        let (service, mut messages) = LspService::new(move |client| {
+            captured.lock().unwrap().replace(client);
+            Mock
+        });
+        let client: crate::Client = slot.lock().unwrap().take().unwrap();
+        let mut service = Spawn::new(service);
+
+        let initialize: crate::jsonrpc::Incoming = serde_json::from_str(INITIALIZE_REQUEST).unwrap();
+        assert_eq!(service.poll_ready(), Poll::Ready(Ok(())));
+        assert!(service.call(initialize).await.unwrap().is_some());
+
+        let request = {
+            let client = client.clone();
+            tokio::spawn(async move { client.show_message_request(lsp::MessageType::INFO, "message", None).await })
+        };
+        match messages.next().await {
+            Some(crate::jsonrpc::Outgoing::Request(sent)) => assert_eq!(sent.method(), "window/showMessageRequest"),
+            other => panic!("expected a request, got {:?}", other),
+        }
+
+        let shutdown: crate::jsonrpc::Incoming = serde_json::from_str(SHUTDOWN_REQUEST).unwrap();
+        assert!(service.call(shutdown).await.unwrap().is_some());
+
+        let error = request.await.unwrap().unwrap_err();
+        assert_eq!(error.code, crate::jsonrpc::ErrorCode::RequestCancelled);
+        assert_eq!(error.data, Some(json!({ "reason": "shutdown" })));
+
+        // Requests sent after the shutdown fail right away instead of waiting for a response.
+        let error = client.show_message_request(lsp::MessageType::INFO, "message", None).await.unwrap_err();
+        assert_eq!(error.data, Some(json!({ "reason": "shutdown" })));
+    }
+
+    #[tokio::test]
+    async fn buffer_until_attached() {
+        use futures::StreamExt;
+
+        let slot = Arc::new(std::sync::Mutex::new(None));
+        let captured = slot.clone();
+        let (service, messages) = LspService::build(move |client| {
+            captured.lock().unwrap().replace(client);
+            Mock
+        })
+        .buffer_until_attached(2)
+        .finish();
+        let client: crate::Client = slot.lock().unwrap().take().unwrap();
+        let mut service = Spawn::new(service);
+
+        let initialize: crate::jsonrpc::Incoming = serde_json::from_str(INITIALIZE_REQUEST).unwrap();
+        assert_eq!(service.poll_ready(), Poll::Ready(Ok(())));
+        assert!(service.call(initialize).await.unwrap().is_some());
+
+        // Without the backlog, the second message would wait for the stream to be polled.
+        for message in ["a", "b", "c"] {
+            client.log_message(lsp::MessageType::INFO, message).await;
+        }
+        client.close();
+
+        let sent: Vec<_> = messages
+            .map(|message| match message {
+                crate::jsonrpc::Outgoing::Request(sent) => sent.params()["message"].clone(),
+                other => panic!("expected a notification, got {:?}", other),
+            })
+            .collect()
+            .await;
+        assert_eq!(sent, [json!("a"), json!("b"), json!("c")]);
+    }
+
+    mod client_event {
+        use super::*;
+        use crate::jsonrpc::{ClientRequest, Id, Outgoing, Response};
+        use futures::StreamExt;
+
+        enum CustomNotification {}
+
+        impl lsp::notification::Notification for CustomNotification {
+            type Params = lsp::WorkDoneProgressCreateParams;
+
+            const METHOD: &'static str = "custom/notification";
+        }
+
+        #[test]
+        fn from_notification() {
+            let params = lsp::WorkDoneProgressCreateParams {
+                token: lsp::NumberOrString::Number(1),
+            };
+            let request = ClientRequest::notification::<CustomNotification>(params);
+            let event = ClientEvent::from(Outgoing::Request(request));
+            assert_eq!(event, ClientEvent::Notification {
+                method: "custom/notification".into(),
+                params: json!({ "token": 1 }),
+            });
+        }
+
+        #[test]
+        fn from_telemetry() {
+            let params = json!({ "foo": "bar" });
+            let request = ClientRequest::notification::<lsp::notification::TelemetryEvent>(params.clone());
+            let event = ClientEvent::from(Outgoing::Request(request));
+            assert_eq!(event, ClientEvent::Telemetry(params));
+        }
+
+        #[test]
+        fn from_request() {
+            let request = ClientRequest::request::<lsp::request::WorkspaceFoldersRequest>(3, ());
+            let event = ClientEvent::from(Outgoing::Request(request));
+            assert_eq!(event, ClientEvent::Request {
+                id: Id::Number(3),
+                method: "workspace/workspaceFolders".into(),
+                params: json!(null),
+            });
+        }
+
+        #[test]
+        fn from_response() {
+            let response = Response::ok(Id::Number(1), json!(null));
+            let event = ClientEvent::from(Outgoing::Response(response.clone()));
+            assert_eq!(event, ClientEvent::Response(response));
+        }
+
+        #[tokio::test]
+        async fn events() {
+            let mut client = None;
+            let (_service, messages) = LspService::new(|c| {
+                client = Some(c);
+                Mock
+            });
+            let client = client.unwrap();
+            let mut events = messages.events();
+
+            let typ = lsp::MessageType::INFO;
+            client.log_message(typ, "hello").await;
+            let expected = ClientEvent::LogMessage(lsp::LogMessageParams {
+                typ,
+                message: "hello".into(),
+            });
+            assert_eq!(events.next().await, Some(expected));
+        }
     }
 
     mod exited_error {
@@ -272,7 +3119,7 @@ mod tests {
 
         #[tokio::test]
         async fn is_terminated() {
-            let (_, mut messages) = LspService::new(|_| Mock::default());
+            let (_, mut messages) = LspService::new(|_| Mock);
             assert!(!messages.is_terminated());
             while messages.next().await.is_some() {}
             assert!(messages.is_terminated());
@@ -280,7 +3127,7 @@ mod tests {
 
         #[tokio::test]
         async fn poll_next() {
-            let (_, mut messages) = LspService::new(|_| Mock::default());
+            let (_, mut messages) = LspService::new(|_| Mock);
             messages.next().await;
         }
     }
@@ -0,0 +1,194 @@
+//! Reporting of anomalies in the lifecycle of the protocol.
+
+use crate::jsonrpc::Id;
+use futures::{
+    channel::mpsc,
+    stream::{FusedStream, Stream},
+};
+use std::{
+    fmt::{self, Debug, Display, Formatter},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+/// The number of cancellations of unknown requests within [`FLOOD_WINDOW`] which is reported as a
+/// [`AnomalyEvent::CancellationFlood`].
+const FLOOD_THRESHOLD: u32 = 32;
+
+/// The period over which cancellations of unknown requests are counted.
+const FLOOD_WINDOW: Duration = Duration::from_secs(1);
+
+/// An anomaly in the lifecycle of the protocol caused by the client, as reported by
+/// [`LspService::anomalies`].
+///
+/// The service handles each of these as the specification requires, e.g. by rejecting a second
+/// `initialize` request, so these events only inform about clients which misbehave.
+///
+/// [`LspService::anomalies`]: crate::LspService::anomalies
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum AnomalyEvent {
+    /// An `initialize` request received after the server was initialized, or while it was being
+    /// initialized.
+    DuplicateInitialize {
+        /// The ID of the duplicate request.
+        id: Id,
+        /// Whether the first `initialize` request had completed.
+        initialized: bool,
+    },
+    /// A request or a notification other than `exit` received after the `shutdown` request.
+    AfterShutdown {
+        /// The method of the message.
+        method: String,
+        /// The ID of the request, or `None` for a notification.
+        id: Option<Id>,
+    },
+    /// An `exit` notification received without a preceding `shutdown` request, which makes the
+    /// server exit with a failure code.
+    ExitWithoutShutdown {
+        /// Whether the server had been initialized.
+        initialized: bool,
+    },
+    /// Many `$/cancelRequest` notifications for requests which are not known to have been received,
+    /// within a short period.
+    ///
+    /// This is reported once per period in which the number of such cancellations reaches the
+    /// threshold.
+    CancellationFlood {
+        /// The number of cancellations of unknown requests within the period.
+        count: u32,
+        /// The length of the period.
+        window: Duration,
+    },
+}
+
+impl AnomalyEvent {
+    /// Returns the method of the message which caused the anomaly.
+    pub fn method(&self) -> &str {
+        match self {
+            AnomalyEvent::DuplicateInitialize { .. } => "initialize",
+            AnomalyEvent::AfterShutdown { method, .. } => method,
+            AnomalyEvent::ExitWithoutShutdown { .. } => "exit",
+            AnomalyEvent::CancellationFlood { .. } => "$/cancelRequest",
+        }
+    }
+}
+
+impl Display for AnomalyEvent {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            AnomalyEvent::DuplicateInitialize { id, initialized: true } => {
+                write!(f, "`initialize` request {} received after initialization", id)
+            },
+            AnomalyEvent::DuplicateInitialize { id, initialized: false } => {
+                write!(f, "`initialize` request {} received during initialization", id)
+            },
+            AnomalyEvent::AfterShutdown { method, id: Some(id) } => {
+                write!(f, "{:?} request {} received after shutdown", method, id)
+            },
+            AnomalyEvent::AfterShutdown { method, id: None } => {
+                write!(f, "{:?} notification received after shutdown", method)
+            },
+            AnomalyEvent::ExitWithoutShutdown { .. } => write!(f, "`exit` notification received without shutdown"),
+            AnomalyEvent::CancellationFlood { count, window } => {
+                write!(f, "{} cancellations of unknown requests within {:?}", count, window)
+            },
+        }
+    }
+}
+
+/// Sends anomalies to all subscribers and detects floods of cancellations.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Anomalies {
+    subscribers: Arc<Mutex<Vec<mpsc::UnboundedSender<AnomalyEvent>>>>,
+    /// The start of the current period and the number of unknown cancellations within it.
+    cancellations: Arc<Mutex<Option<(Instant, u32)>>>,
+}
+
+impl Anomalies {
+    pub(crate) fn subscribe(&self) -> AnomalyEventStream {
+        let (tx, rx) = mpsc::unbounded();
+        self.subscribers.lock().unwrap().push(tx);
+        AnomalyEventStream(rx)
+    }
+
+    pub(crate) fn report(&self, event: AnomalyEvent) {
+        log::debug!("protocol anomaly: {}", event);
+        let mut subscribers = self.subscribers.lock().unwrap();
+        subscribers.retain(|tx| tx.unbounded_send(event.clone()).is_ok());
+    }
+
+    /// Counts a cancellation of an unknown request at the given instant, reporting a flood once
+    /// the threshold is reached within the current period.
+    pub(crate) fn unknown_cancellation(&self, now: Instant) {
+        let mut cancellations = self.cancellations.lock().unwrap();
+        let (start, count) = match *cancellations {
+            Some((start, count)) if now.saturating_duration_since(start) < FLOOD_WINDOW => (start, count + 1),
+            _ => (now, 1),
+        };
+        *cancellations = Some((start, count));
+        drop(cancellations);
+
+        if count == FLOOD_THRESHOLD {
+            self.report(AnomalyEvent::CancellationFlood {
+                count,
+                window: FLOOD_WINDOW,
+            });
+        }
+    }
+}
+
+/// Stream of the anomalies in the lifecycle of the protocol, returned by
+/// [`LspService::anomalies`].
+///
+/// [`LspService::anomalies`]: crate::LspService::anomalies
+#[must_use = "streams do nothing unless polled"]
+pub struct AnomalyEventStream(mpsc::UnboundedReceiver<AnomalyEvent>);
+
+impl Stream for AnomalyEventStream {
+    type Item = AnomalyEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.0).poll_next(cx)
+    }
+}
+
+impl FusedStream for AnomalyEventStream {
+    fn is_terminated(&self) -> bool {
+        self.0.is_terminated()
+    }
+}
+
+impl Debug for AnomalyEventStream {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct(stringify!(AnomalyEventStream)).finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+
+    #[tokio::test]
+    async fn cancellation_flood() {
+        let anomalies = Anomalies::default();
+        let mut stream = anomalies.subscribe();
+        let start = Instant::now();
+        for _ in 0 .. 2 * FLOOD_THRESHOLD {
+            anomalies.unknown_cancellation(start);
+        }
+        anomalies.unknown_cancellation(start + FLOOD_WINDOW);
+        drop(anomalies);
+
+        let events = stream.by_ref().collect::<Vec<_>>().await;
+        let flood = AnomalyEvent::CancellationFlood {
+            count: FLOOD_THRESHOLD,
+            window: FLOOD_WINDOW,
+        };
+        assert_eq!(events, vec![flood]);
+        assert_eq!(events[0].method(), "$/cancelRequest");
+    }
+}
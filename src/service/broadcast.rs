@@ -0,0 +1,193 @@
+//! Fan-out of the messages produced by the language server to multiple subscribers.
+
+use crate::{client::Outbox, jsonrpc::Outgoing};
+use futures::stream::Stream;
+use std::{
+    collections::VecDeque,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+};
+
+#[derive(Debug)]
+struct Shared {
+    rx: Outbox,
+    /// The most recent messages, kept for subscribers which have not received them yet.
+    buffer: VecDeque<Outgoing>,
+    /// Sequence number of the first message in `buffer`.
+    head: u64,
+    closed: bool,
+    /// Subscribers waiting for the next message.
+    waiters: Vec<Waker>,
+}
+
+impl Shared {
+    fn tail(&self) -> u64 {
+        self.head + self.buffer.len() as u64
+    }
+
+    fn wake_all(&mut self) {
+        self.waiters.drain(..).for_each(Waker::wake);
+    }
+}
+
+/// A subscriber receiving every message produced by the language server from the point it
+/// subscribed on.
+///
+/// Whichever subscriber is polled first takes the next message from the channel and keeps it in a
+/// buffer of the given capacity for the others. Subscribers falling further behind than this
+/// capacity miss the oldest messages, so that a slow subscriber never stalls the others.
+#[derive(Debug)]
+pub(crate) struct Subscriber {
+    shared: Arc<Mutex<Shared>>,
+    capacity: usize,
+    next: u64,
+}
+
+impl Subscriber {
+    /// Creates the first subscriber of the messages received on `rx`.
+    ///
+    /// A capacity of `0` is treated as `1`.
+    pub(crate) fn new(rx: Outbox, capacity: usize) -> Self {
+        let shared = Shared {
+            rx,
+            buffer: VecDeque::new(),
+            head: 0,
+            closed: false,
+            waiters: Vec::new(),
+        };
+        Subscriber {
+            shared: Arc::new(Mutex::new(shared)),
+            capacity: capacity.max(1),
+            next: 0,
+        }
+    }
+
+    /// Creates another subscriber, which receives the messages produced from now on.
+    pub(crate) fn subscribe(&self) -> Self {
+        let next = self.shared.lock().unwrap().tail();
+        Subscriber {
+            shared: self.shared.clone(),
+            capacity: self.capacity,
+            next,
+        }
+    }
+
+    /// Polls for the next message, calling `received` once for every message taken from the
+    /// channel, regardless of the subscriber taking it.
+    pub(crate) fn poll_next(&mut self, cx: &mut Context, received: impl FnOnce(&Outgoing)) -> Poll<Option<Outgoing>> {
+        let mut shared = self.shared.lock().unwrap();
+
+        if self.next < shared.head {
+            log::warn!("message subscriber lagged behind, skipping {} messages", shared.head - self.next);
+            self.next = shared.head;
+        }
+
+        if self.next < shared.tail() {
+            let message = shared.buffer[(self.next - shared.head) as usize].clone();
+            self.next += 1;
+            return Poll::Ready(Some(message));
+        }
+
+        if shared.closed {
+            return Poll::Ready(None);
+        }
+
+        match Pin::new(&mut shared.rx).poll_next(cx) {
+            Poll::Ready(Some(message)) => {
+                received(&message);
+                shared.buffer.push_back(message.clone());
+                if shared.buffer.len() > self.capacity {
+                    shared.buffer.pop_front();
+                    shared.head += 1;
+                }
+                self.next = shared.tail();
+                shared.wake_all();
+                Poll::Ready(Some(message))
+            },
+            Poll::Ready(None) => {
+                shared.closed = true;
+                shared.wake_all();
+                Poll::Ready(None)
+            },
+            Poll::Pending => {
+                // The channel only wakes the subscriber which polled it last, which wakes the rest.
+                if !shared.waiters.iter().any(|waker| waker.will_wake(cx.waker())) {
+                    shared.waiters.push(cx.waker().clone());
+                }
+                Poll::Pending
+            },
+        }
+    }
+
+    pub(crate) fn is_terminated(&self) -> bool {
+        let shared = self.shared.lock().unwrap();
+        shared.closed && self.next >= shared.tail()
+    }
+}
+
+impl Drop for Subscriber {
+    fn drop(&mut self) {
+        // This subscriber may have been the one the channel wakes, so another one takes its place.
+        if let Ok(mut shared) = self.shared.lock() {
+            shared.wake_all();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{channel::mpsc, future, SinkExt};
+    use lsp::notification::LogMessage;
+
+    fn log(message: &str) -> Outgoing {
+        Outgoing::Request(crate::jsonrpc::ClientRequest::notification::<LogMessage>(lsp::LogMessageParams {
+            typ: lsp::MessageType::LOG,
+            message: message.into(),
+        }))
+    }
+
+    async fn next(subscriber: &mut Subscriber) -> Option<Outgoing> {
+        future::poll_fn(|cx| subscriber.poll_next(cx, |_| ())).await
+    }
+
+    #[tokio::test]
+    async fn fans_out_messages() {
+        let (mut tx, rx) = mpsc::channel(4);
+        let mut first = Subscriber::new(rx.into(), 2);
+        tx.send(log("1")).await.unwrap();
+        assert_eq!(next(&mut first).await, Some(log("1")));
+
+        let mut second = first.subscribe();
+        for message in ["2", "3", "4"] {
+            tx.send(log(message)).await.unwrap();
+        }
+        assert_eq!(next(&mut first).await, Some(log("2")));
+        assert_eq!(next(&mut first).await, Some(log("3")));
+        assert_eq!(next(&mut first).await, Some(log("4")));
+
+        // The second subscriber lagged behind by more than the capacity.
+        assert_eq!(next(&mut second).await, Some(log("3")));
+        assert_eq!(next(&mut second).await, Some(log("4")));
+
+        drop(tx);
+        assert_eq!(next(&mut second).await, None);
+        assert!(second.is_terminated());
+        assert_eq!(next(&mut first).await, None);
+    }
+
+    #[tokio::test]
+    async fn wakes_waiting_subscribers() {
+        let (mut tx, rx) = mpsc::channel(4);
+        let mut first = Subscriber::new(rx.into(), 4);
+        let mut second = first.subscribe();
+
+        let waiting = tokio::spawn(async move { next(&mut second).await });
+        tokio::task::yield_now().await;
+        let sender = tokio::spawn(async move { tx.send(log("1")).await });
+        assert_eq!(next(&mut first).await, Some(log("1")));
+        assert_eq!(waiting.await.unwrap(), Some(log("1")));
+        sender.await.unwrap().unwrap();
+    }
+}
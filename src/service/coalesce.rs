@@ -0,0 +1,215 @@
+//! Coalescing identical requests which are in flight at the same time.
+
+use super::ExitedError;
+use crate::{
+    generated_impl::ServerRequest,
+    jsonrpc::{Id, Outgoing, Response},
+};
+use futures::future::{BoxFuture, FutureExt, Shared};
+use serde_json::Value;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::{self, Debug, Formatter, Write},
+    sync::{Arc, Mutex, Weak},
+};
+
+type Dispatch = BoxFuture<'static, Result<Option<Outgoing>, ExitedError>>;
+
+/// A policy coalescing requests which arrive while an identical request is still being handled,
+/// as sent by flaky clients or during hover storms.
+///
+/// Requests to the methods listed with [`CoalescingPolicy::coalesce`] are identical if their
+/// parameters are equal, regardless of the order of object keys. An identical request is not
+/// passed to the server; instead, it is answered with the response to the request in flight, once
+/// that is available. Canceling any of the coalesced requests cancels the request in flight, failing
+/// all requests coalesced with it.
+///
+/// Only methods without side effects, whose results depend on their parameters alone, should be
+/// coalesced.
+///
+/// # Example
+///
+/// ```rust
+/// # use lspower::CoalescingPolicy;
+/// # use lspower::lsp::request::{HoverRequest, Request, SemanticTokensFullRequest};
+/// let policy = CoalescingPolicy::new()
+///     .coalesce(HoverRequest::METHOD)
+///     .coalesce(SemanticTokensFullRequest::METHOD);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CoalescingPolicy {
+    methods: HashSet<String>,
+}
+
+impl CoalescingPolicy {
+    /// Creates a policy which does not coalesce any requests.
+    pub fn new() -> Self {
+        CoalescingPolicy::default()
+    }
+
+    /// Coalesces identical requests to the given method.
+    pub fn coalesce(mut self, method: impl Into<String>) -> Self {
+        self.methods.insert(method.into());
+        self
+    }
+
+    /// Returns the key identifying requests identical to the given one, if it is to be coalesced.
+    pub(crate) fn key(&self, request: &ServerRequest) -> Option<String> {
+        let method = request.method();
+        if request.id().is_none() || !self.methods.contains(method) {
+            return None;
+        }
+
+        let mut key = format!("{}\0", method);
+        let params = request.params().or_else(|| request.other_params().cloned());
+        write_canonical(&mut key, params.as_ref().unwrap_or(&Value::Null));
+        Some(key)
+    }
+}
+
+/// Writes the given value as JSON with sorted object keys.
+fn write_canonical(out: &mut String, value: &Value) {
+    match value {
+        Value::Array(values) => {
+            out.push('[');
+            for (i, value) in values.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                write_canonical(out, value);
+            }
+            out.push(']');
+        },
+        Value::Object(map) => {
+            let mut entries: Vec<_> = map.iter().collect();
+            entries.sort_by_key(|(key, _)| *key);
+            out.push('{');
+            for (i, (key, value)) in entries.into_iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                let _ = write!(out, "{}:", Value::from(key.as_str()));
+                write_canonical(out, value);
+            }
+            out.push('}');
+        },
+        value => {
+            let _ = write!(out, "{}", value);
+        },
+    }
+}
+
+/// The responses to coalesced requests which are in flight, keyed by [`CoalescingPolicy::key`].
+#[derive(Default)]
+pub(crate) struct InFlight(Mutex<Requests>);
+
+#[derive(Default)]
+struct Requests {
+    responses: HashMap<String, (Id, Shared<Dispatch>)>,
+    /// The IDs of the requests in flight which coalesced requests were answered with, keyed by the
+    /// IDs of the coalesced requests.
+    origins: HashMap<Id, Id>,
+}
+
+impl InFlight {
+    /// Returns the response to an identical request in flight, answering the request with the
+    /// given ID instead.
+    pub(crate) fn join(&self, key: &str, id: &Id) -> Option<Dispatch> {
+        let mut requests = self.0.lock().unwrap();
+        let (origin, response) = requests.responses.get(key)?.clone();
+        log::debug!("coalescing request {} with identical request {} in flight", id, origin);
+        requests.origins.insert(id.clone(), origin);
+        let id = id.clone();
+        let response = response.map(move |response| match response {
+            Ok(Some(Outgoing::Response(response))) => {
+                let (_, body) = response.into_parts();
+                Ok(Some(Outgoing::Response(Response::from_parts(id, body))))
+            },
+            response => response,
+        });
+        Some(response.boxed())
+    }
+
+    /// Registers the response to the request with the given ID, which identical requests are
+    /// coalesced with until it is available.
+    pub(crate) fn register(self: &Arc<Self>, key: String, id: Id, response: Dispatch) -> Dispatch {
+        let in_flight = Arc::downgrade(self);
+        let entry = key.clone();
+        let origin = id.clone();
+        let response = response
+            .map(move |response| {
+                if let Some(in_flight) = Weak::upgrade(&in_flight) {
+                    let mut requests = in_flight.0.lock().unwrap();
+                    requests.responses.remove(&entry);
+                    requests.origins.retain(|_, id| *id != origin);
+                }
+                response
+            })
+            .boxed()
+            .shared();
+        self.0.lock().unwrap().responses.insert(key, (id, response.clone()));
+        response.boxed()
+    }
+
+    /// Returns the ID of the request in flight which the request with the given ID was coalesced
+    /// with, so that canceling any of the coalesced requests cancels the shared request.
+    pub(crate) fn origin(&self, id: &Id) -> Option<Id> {
+        self.0.lock().unwrap().origins.get(id).cloned()
+    }
+
+    /// Forgets all requests in flight, e.g. when the service is reset.
+    pub(crate) fn clear(&self) {
+        let mut requests = self.0.lock().unwrap();
+        requests.responses.clear();
+        requests.origins.clear();
+    }
+}
+
+impl Debug for InFlight {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_tuple(stringify!(InFlight))
+            .field(&self.0.lock().unwrap().responses.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn key() {
+        let policy = CoalescingPolicy::new().coalesce("textDocument/hover");
+        let parse = |raw| serde_json::from_value::<ServerRequest>(raw).unwrap();
+        let hover = parse(json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/hover",
+            "params": { "textDocument": { "uri": "file:///a.rs" }, "position": { "line": 0, "character": 0 } },
+            "id": 1,
+        }));
+        let reordered = parse(json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/hover",
+            "params": { "position": { "character": 0, "line": 0 }, "textDocument": { "uri": "file:///a.rs" } },
+            "id": 2,
+        }));
+        let moved = parse(json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/hover",
+            "params": { "textDocument": { "uri": "file:///a.rs" }, "position": { "line": 0, "character": 1 } },
+            "id": 3,
+        }));
+        let code_lens = parse(json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/codeLens",
+            "params": { "textDocument": { "uri": "file:///a.rs" } },
+            "id": 4,
+        }));
+
+        assert!(policy.key(&hover).is_some());
+        assert_eq!(policy.key(&hover), policy.key(&reordered));
+        assert_ne!(policy.key(&hover), policy.key(&moved));
+        assert_eq!(policy.key(&code_lens), None);
+    }
+}
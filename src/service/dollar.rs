@@ -0,0 +1,145 @@
+//! Handling of `$/` methods which the dispatcher does not know about.
+
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+/// The prefix of methods whose support is implementation-dependent.
+const PREFIX: &str = "$/";
+
+/// A policy for messages to methods starting with `$/` which the dispatcher does not know about.
+///
+/// Support for these methods is implementation-dependent, so the specification requires servers to
+/// ignore such notifications and to answer such requests with a "method not found" error. By
+/// default, the policy ignores such notifications, while such requests are passed to
+/// [`LanguageServer::request_else`] like requests to other unknown methods, whose default
+/// implementation answers them with this error. The notifications ignored and the requests
+/// rejected are counted and returned by [`ignored_notifications`] and [`rejected_requests`].
+/// Clones of a policy share the same counts. Ignored notifications are still reported through
+/// [`LspService::unhandled_notifications`].
+///
+/// Servers implementing their own `$/` extensions can have these notifications passed to
+/// [`LanguageServer::notification_else`] instead with [`forward`], while servers whose
+/// `request_else` handles other methods can reject `$/` requests before they reach it with
+/// [`reject_requests`].
+///
+/// # Example
+///
+/// ```rust
+/// # use lspower::{jsonrpc::Result, lsp::*, Client, DollarMethodPolicy, LanguageServer, LspService};
+/// # #[derive(Debug)]
+/// # struct Backend;
+/// # #[lspower::async_trait]
+/// # impl LanguageServer for Backend {
+/// #     async fn initialize(&self, _: InitializeParams) -> Result<InitializeResult> {
+/// #         Ok(InitializeResult::default())
+/// #     }
+/// #     async fn shutdown(&self) -> Result<()> {
+/// #         Ok(())
+/// #     }
+/// # }
+/// let policy = DollarMethodPolicy::new();
+/// let (service, messages) = LspService::build(|_: Client| Backend)
+///     .dollar_method_policy(policy.clone())
+///     .finish();
+/// assert_eq!(policy.ignored_notifications(), 0);
+/// ```
+///
+/// [`forward`]: DollarMethodPolicy::forward
+/// [`reject_requests`]: DollarMethodPolicy::reject_requests
+/// [`ignored_notifications`]: DollarMethodPolicy::ignored_notifications
+/// [`rejected_requests`]: DollarMethodPolicy::rejected_requests
+/// [`LspService::unhandled_notifications`]: crate::LspService::unhandled_notifications
+/// [`LanguageServer::request_else`]: crate::LanguageServer::request_else
+/// [`LanguageServer::notification_else`]: crate::LanguageServer::notification_else
+#[derive(Clone, Debug, Default)]
+pub struct DollarMethodPolicy {
+    forward: bool,
+    reject_requests: bool,
+    ignored: Arc<AtomicU64>,
+    rejected: Arc<AtomicU64>,
+}
+
+impl DollarMethodPolicy {
+    /// Creates a policy which ignores unknown `$/` notifications and passes unknown `$/` requests to
+    /// [`LanguageServer::request_else`].
+    ///
+    /// [`LanguageServer::request_else`]: crate::LanguageServer::request_else
+    pub fn new() -> Self {
+        DollarMethodPolicy::default()
+    }
+
+    /// Enables or disables passing unknown `$/` notifications to
+    /// [`LanguageServer::notification_else`].
+    ///
+    /// Defaults to `false`.
+    ///
+    /// [`LanguageServer::notification_else`]: crate::LanguageServer::notification_else
+    pub fn forward(mut self, enabled: bool) -> Self {
+        self.forward = enabled;
+        self
+    }
+
+    /// Enables or disables answering unknown `$/` requests with a "method not found" error instead
+    /// of passing them to [`LanguageServer::request_else`].
+    ///
+    /// Defaults to `false`, so that `request_else` receives these requests as it always did.
+    ///
+    /// [`LanguageServer::request_else`]: crate::LanguageServer::request_else
+    pub fn reject_requests(mut self, enabled: bool) -> Self {
+        self.reject_requests = enabled;
+        self
+    }
+
+    /// Returns the number of unknown `$/` notifications ignored by services using this policy.
+    pub fn ignored_notifications(&self) -> u64 {
+        self.ignored.load(Ordering::SeqCst)
+    }
+
+    /// Returns the number of unknown `$/` requests rejected by services using this policy.
+    pub fn rejected_requests(&self) -> u64 {
+        self.rejected.load(Ordering::SeqCst)
+    }
+
+    /// Returns whether the notification to the given unknown method is ignored, counting it if so.
+    pub(crate) fn ignores(&self, method: &str) -> bool {
+        applies(!self.forward, method, &self.ignored)
+    }
+
+    /// Returns whether the request to the given unknown method is rejected, counting it if so.
+    pub(crate) fn rejects(&self, method: &str) -> bool {
+        applies(self.reject_requests, method, &self.rejected)
+    }
+}
+
+/// Returns whether an enabled rule applies to the given method, counting it if so.
+fn applies(enabled: bool, method: &str, counter: &AtomicU64) -> bool {
+    let applies = enabled && method.starts_with(PREFIX);
+    if applies {
+        counter.fetch_add(1, Ordering::SeqCst);
+    }
+    applies
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts() {
+        let policy = DollarMethodPolicy::new();
+        let shared = policy.clone();
+        assert!(policy.ignores("$/progress"));
+        assert!(!policy.ignores("custom/reload"));
+        assert!(!policy.rejects("$/custom"));
+        assert_eq!(shared.ignored_notifications(), 1);
+        assert_eq!(shared.rejected_requests(), 0);
+
+        let policy = DollarMethodPolicy::new().forward(true).reject_requests(true);
+        assert!(!policy.ignores("$/progress"));
+        assert!(policy.rejects("$/custom"));
+        assert!(!policy.rejects("custom/reload"));
+        assert_eq!((policy.ignored_notifications(), policy.rejected_requests()), (0, 1));
+    }
+}
@@ -0,0 +1,110 @@
+//! Empty results for requests the server does not implement.
+
+use super::ExitedError;
+use crate::{
+    generated_impl::ServerRequest,
+    jsonrpc::{ErrorCode, Outgoing},
+};
+use futures::future::{BoxFuture, FutureExt};
+use lsp::request::Request;
+use serde_json::Value;
+use std::collections::HashMap;
+
+type Dispatch = BoxFuture<'static, Result<Option<Outgoing>, ExitedError>>;
+
+/// Results sent in place of `MethodNotFound` errors, keyed by method.
+///
+/// Some clients treat errors for requests they send on their own, such as `workspace/symbol` or
+/// `textDocument/documentSymbol`, as failures worth reporting to the user, even though the server
+/// simply does not implement them.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct EmptyResults(HashMap<&'static str, Value>);
+
+impl EmptyResults {
+    pub(crate) fn insert<R>(&mut self)
+    where
+        R: Request,
+        R::Result: Default,
+    {
+        let empty = serde_json::to_value(R::Result::default()).unwrap_or(Value::Null);
+        self.0.insert(R::METHOD, empty);
+    }
+
+    /// Looks up the empty result for the given request upon its receipt, if one was registered.
+    pub(crate) fn start(&self, request: &ServerRequest) -> Option<EmptyResult> {
+        let (method, empty) = self.0.get_key_value(request.method())?;
+        Some(EmptyResult {
+            method,
+            empty: empty.clone(),
+        })
+    }
+}
+
+/// The empty result for a single request.
+pub(crate) struct EmptyResult {
+    method: &'static str,
+    empty: Value,
+}
+
+impl EmptyResult {
+    /// Wraps the dispatched handler, replacing a `MethodNotFound` error with the empty result.
+    pub(crate) fn apply(self, dispatch: Dispatch) -> Dispatch {
+        dispatch
+            .map(move |response| match response {
+                Ok(Some(Outgoing::Response(response))) => {
+                    let response = response.recover(|error| {
+                        let not_found = error.code == ErrorCode::MethodNotFound;
+                        if not_found {
+                            log::debug!("responding to unimplemented {:?} with an empty result", self.method);
+                        }
+                        not_found.then_some(self.empty)
+                    });
+                    Ok(Some(Outgoing::Response(response)))
+                },
+                response => response,
+            })
+            .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jsonrpc::{Error, Id, Response};
+    use futures::future;
+    use lsp::request::{DocumentSymbolRequest, WorkspaceSymbol};
+    use serde_json::json;
+
+    fn request(method: &str) -> ServerRequest {
+        let raw = json!({ "jsonrpc": "2.0", "method": method, "params": {}, "id": 1 });
+        serde_json::from_value(raw).unwrap()
+    }
+
+    fn respond(empty: &EmptyResults, method: &str, response: Response) -> Dispatch {
+        let dispatch = future::ok(Some(Outgoing::Response(response))).boxed();
+        match empty.start(&request(method)) {
+            Some(empty) => empty.apply(dispatch),
+            None => dispatch,
+        }
+    }
+
+    #[tokio::test]
+    async fn replaces_method_not_found() {
+        let mut empty = EmptyResults::default();
+        empty.insert::<WorkspaceSymbol>();
+        let not_found = Response::error(Some(Id::Number(1)), Error::method_not_found());
+        let expected = Outgoing::Response(Response::ok(Id::Number(1), Value::Null));
+        let response = respond(&empty, "workspace/symbol", not_found.clone()).await;
+        assert_eq!(response, Ok(Some(expected)));
+
+        let failed = Response::error(Some(Id::Number(1)), Error::internal_error());
+        let response = respond(&empty, "workspace/symbol", failed.clone()).await;
+        assert_eq!(response, Ok(Some(Outgoing::Response(failed))));
+
+        let response = respond(&empty, "textDocument/documentSymbol", not_found.clone()).await;
+        assert_eq!(response, Ok(Some(Outgoing::Response(not_found))));
+
+        empty.insert::<DocumentSymbolRequest>();
+        assert_eq!(empty.0[DocumentSymbolRequest::METHOD], Value::Null);
+    }
+}
@@ -0,0 +1,638 @@
+//! Adaptation of request results to the capabilities declared by the client.
+
+use super::ExitedError;
+use crate::{
+    generated_impl::ServerRequest,
+    jsonrpc::Outgoing,
+    Client,
+};
+use futures::future::{BoxFuture, FutureExt};
+use lsp::request::{
+    CodeActionRequest,
+    Completion,
+    DocumentSymbolRequest,
+    FoldingRangeRequest,
+    Request,
+    ResolveCompletionItem,
+    WorkspaceSymbol,
+};
+use serde::Deserialize;
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    fmt::{self, Debug, Formatter},
+    iter::Peekable,
+    str::Chars,
+    sync::Arc,
+};
+
+type Filter = Arc<dyn Fn(&Value, Value, &Capabilities) -> Value + Send + Sync>;
+
+type Dispatch = BoxFuture<'static, Result<Option<Outgoing>, ExitedError>>;
+
+/// Filters registered through the [`LspServiceBuilder`], keyed by method.
+///
+/// The built-in filters run first, followed by the custom filters in registration order.
+///
+/// [`LspServiceBuilder`]: crate::LspServiceBuilder
+#[derive(Clone, Default)]
+pub(crate) struct ResponseFilters {
+    builtin: HashMap<&'static str, Filter>,
+    custom: HashMap<&'static str, Vec<Filter>>,
+}
+
+impl ResponseFilters {
+    pub(crate) fn insert<R, F>(&mut self, filter: F)
+    where
+        R: Request + 'static,
+        F: Fn(&R::Params, R::Result, &lsp::ClientCapabilities) -> R::Result + Send + Sync + 'static,
+    {
+        self.custom.entry(R::METHOD).or_default().push(with_capabilities::<R, F>(filter));
+    }
+
+    pub(crate) fn set_builtin(&mut self, enabled: bool) {
+        self.builtin = if enabled { builtin_filters() } else { HashMap::new() };
+    }
+
+    /// Collects the filters for the given request upon its receipt, if there are any.
+    pub(crate) fn start(&self, request: &ServerRequest, client: &Client) -> Option<FilterChain> {
+        let method = request.method();
+        let builtin = self.builtin.get(method).into_iter();
+        let custom = self.custom.get(method).into_iter().flatten();
+        let filters: Vec<_> = builtin.chain(custom).cloned().collect();
+        if filters.is_empty() {
+            return None;
+        }
+
+        Some(FilterChain {
+            filters,
+            params: request.params()?,
+            client: client.clone(),
+        })
+    }
+}
+
+impl Debug for ResponseFilters {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let mut builtin: Vec<_> = self.builtin.keys().collect();
+        builtin.sort();
+        let mut custom: Vec<_> = self.custom.iter().map(|(method, filters)| (method, filters.len())).collect();
+        custom.sort();
+        f.debug_struct(stringify!(ResponseFilters))
+            .field("builtin", &builtin)
+            .field("custom", &custom)
+            .finish()
+    }
+}
+
+/// The capabilities declared by the client, as passed to the filters.
+struct Capabilities {
+    typed: lsp::ClientCapabilities,
+    /// The capabilities as sent by the client, including the ones `lsp-types` does not retain.
+    declared: Arc<Value>,
+}
+
+/// Filters applying to the result of a single request.
+pub(crate) struct FilterChain {
+    filters: Vec<Filter>,
+    params: Value,
+    client: Client,
+}
+
+impl FilterChain {
+    /// Wraps the dispatched handler, passing its result through the filters once it resolves.
+    ///
+    /// Error responses are passed on unchanged, as are all responses before the client declared
+    /// its capabilities.
+    pub(crate) fn apply(self, dispatch: Dispatch) -> Dispatch {
+        dispatch
+            .map(move |response| match response {
+                Ok(Some(Outgoing::Response(response))) => {
+                    let capabilities = match self.client.client_capabilities() {
+                        Some(typed) => Capabilities {
+                            typed,
+                            declared: self.client.declared_capabilities(),
+                        },
+                        None => return Ok(Some(Outgoing::Response(response))),
+                    };
+                    let response = response.map_result(|result| {
+                        self.filters
+                            .iter()
+                            .fold(result, |result, filter| filter(&self.params, result, &capabilities))
+                    });
+                    Ok(Some(Outgoing::Response(response)))
+                },
+                response => response,
+            })
+            .boxed()
+    }
+}
+
+/// Wraps a filter of typed results into a filter of their JSON representation.
+///
+/// Results which do not match the type of the request are passed on unchanged.
+fn typed<R, F>(filter: F) -> Filter
+where
+    R: Request + 'static,
+    F: Fn(&R::Params, R::Result, &Capabilities) -> R::Result + Send + Sync + 'static,
+{
+    Arc::new(move |params, result, capabilities| {
+        let typed = R::Params::deserialize(params).and_then(|params| Ok((params, R::Result::deserialize(&result)?)));
+        let (params, typed) = match typed {
+            Ok(typed) => typed,
+            Err(error) => {
+                log::warn!("skipping response filter for {:?}: {}", R::METHOD, error);
+                return result;
+            },
+        };
+        match serde_json::to_value(filter(&params, typed, capabilities)) {
+            Ok(filtered) => filtered,
+            Err(error) => {
+                log::error!("failed to serialize filtered result of {:?}: {}", R::METHOD, error);
+                result
+            },
+        }
+    })
+}
+
+/// Wraps a filter of typed results which only needs the typed client capabilities.
+fn with_capabilities<R, F>(filter: F) -> Filter
+where
+    R: Request + 'static,
+    F: Fn(&R::Params, R::Result, &lsp::ClientCapabilities) -> R::Result + Send + Sync + 'static,
+{
+    typed::<R, _>(move |params, result, capabilities| filter(params, result, &capabilities.typed))
+}
+
+fn builtin_filters() -> HashMap<&'static str, Filter> {
+    let mut filters = HashMap::new();
+    filters.insert(CodeActionRequest::METHOD, with_capabilities::<CodeActionRequest, _>(code_actions));
+    filters.insert(Completion::METHOD, with_capabilities::<Completion, _>(completions));
+    filters.insert(DocumentSymbolRequest::METHOD, with_capabilities::<DocumentSymbolRequest, _>(document_symbols));
+    filters.insert(FoldingRangeRequest::METHOD, typed::<FoldingRangeRequest, _>(folding_ranges));
+    filters.insert(ResolveCompletionItem::METHOD, with_capabilities::<ResolveCompletionItem, _>(resolved_completion));
+    filters.insert(WorkspaceSymbol::METHOD, with_capabilities::<WorkspaceSymbol, _>(workspace_symbols));
+    filters
+}
+
+/// Replaces code actions with their commands for clients without `codeActionLiteralSupport`,
+/// dropping the code actions without a command.
+fn code_actions(
+    _: &lsp::CodeActionParams,
+    actions: Option<lsp::CodeActionResponse>,
+    capabilities: &lsp::ClientCapabilities,
+) -> Option<lsp::CodeActionResponse> {
+    let literals = capabilities
+        .text_document
+        .as_ref()
+        .and_then(|text_document| text_document.code_action.as_ref())
+        .and_then(|code_action| code_action.code_action_literal_support.as_ref());
+    if literals.is_some() {
+        return actions;
+    }
+
+    let actions = actions?
+        .into_iter()
+        .filter_map(|action| match action {
+            lsp::CodeActionOrCommand::CodeAction(action) => action.command.map(lsp::CodeActionOrCommand::Command),
+            command => Some(command),
+        })
+        .collect();
+    Some(actions)
+}
+
+fn completions(
+    _: &lsp::CompletionParams,
+    response: Option<lsp::CompletionResponse>,
+    capabilities: &lsp::ClientCapabilities,
+) -> Option<lsp::CompletionResponse> {
+    let mut response = response?;
+    let items = match &mut response {
+        lsp::CompletionResponse::Array(items) => items,
+        lsp::CompletionResponse::List(list) => &mut list.items,
+    };
+    for item in items {
+        restrict_completion_item(item, capabilities);
+    }
+    Some(response)
+}
+
+fn resolved_completion(
+    _: &lsp::CompletionItem,
+    mut item: lsp::CompletionItem,
+    capabilities: &lsp::ClientCapabilities,
+) -> lsp::CompletionItem {
+    restrict_completion_item(&mut item, capabilities);
+    item
+}
+
+/// Converts snippets to plain text for clients without `snippetSupport`, and insert-and-replace
+/// edits to plain edits for clients without `insertReplaceSupport`.
+fn restrict_completion_item(item: &mut lsp::CompletionItem, capabilities: &lsp::ClientCapabilities) {
+    let supported = capabilities
+        .text_document
+        .as_ref()
+        .and_then(|text_document| text_document.completion.as_ref())
+        .and_then(|completion| completion.completion_item.as_ref());
+
+    let snippets = supported.and_then(|supported| supported.snippet_support) == Some(true);
+    if !snippets && item.insert_text_format == Some(lsp::InsertTextFormat::SNIPPET) {
+        item.insert_text = item.insert_text.as_deref().map(snippet_to_text);
+        match &mut item.text_edit {
+            Some(lsp::CompletionTextEdit::Edit(edit)) => edit.new_text = snippet_to_text(&edit.new_text),
+            Some(lsp::CompletionTextEdit::InsertAndReplace(edit)) => edit.new_text = snippet_to_text(&edit.new_text),
+            None => {},
+        }
+        item.insert_text_format = Some(lsp::InsertTextFormat::PLAIN_TEXT);
+    }
+
+    if supported.and_then(|supported| supported.insert_replace_support) != Some(true) {
+        item.text_edit = item.text_edit.take().map(|edit| match edit {
+            lsp::CompletionTextEdit::InsertAndReplace(edit) => {
+                lsp::CompletionTextEdit::Edit(lsp::TextEdit::new(edit.insert, edit.new_text))
+            },
+            edit => edit,
+        });
+    }
+
+    restrict_tags(&mut item.tags, supported.and_then(|supported| supported.tag_support.as_ref()));
+}
+
+/// Flattens document symbols for clients without `hierarchicalDocumentSymbolSupport`, and removes
+/// the tags the client does not support.
+fn document_symbols(
+    params: &lsp::DocumentSymbolParams,
+    response: Option<lsp::DocumentSymbolResponse>,
+    capabilities: &lsp::ClientCapabilities,
+) -> Option<lsp::DocumentSymbolResponse> {
+    let supported = capabilities
+        .text_document
+        .as_ref()
+        .and_then(|text_document| text_document.document_symbol.as_ref());
+    let hierarchical = supported.and_then(|supported| supported.hierarchical_document_symbol_support) == Some(true);
+    let tags = supported.and_then(|supported| supported.tag_support.as_ref());
+
+    let response = match response? {
+        lsp::DocumentSymbolResponse::Nested(symbols) if !hierarchical => {
+            let mut flat = Vec::new();
+            flatten_symbols(&params.text_document.uri, symbols, None, &mut flat);
+            lsp::DocumentSymbolResponse::Flat(flat)
+        },
+        response => response,
+    };
+
+    Some(match response {
+        lsp::DocumentSymbolResponse::Flat(mut symbols) => {
+            for symbol in &mut symbols {
+                restrict_tags(&mut symbol.tags, tags);
+            }
+            lsp::DocumentSymbolResponse::Flat(symbols)
+        },
+        lsp::DocumentSymbolResponse::Nested(mut symbols) => {
+            restrict_nested_tags(&mut symbols, tags);
+            lsp::DocumentSymbolResponse::Nested(symbols)
+        },
+    })
+}
+
+#[allow(deprecated)]
+fn flatten_symbols(
+    uri: &lsp::Url,
+    symbols: Vec<lsp::DocumentSymbol>,
+    container: Option<&str>,
+    flat: &mut Vec<lsp::SymbolInformation>,
+) {
+    for symbol in symbols {
+        flat.push(lsp::SymbolInformation {
+            name: symbol.name.clone(),
+            kind: symbol.kind,
+            tags: symbol.tags,
+            deprecated: symbol.deprecated,
+            location: lsp::Location::new(uri.clone(), symbol.range),
+            container_name: container.map(Into::into),
+        });
+        if let Some(children) = symbol.children {
+            flatten_symbols(uri, children, Some(&symbol.name), flat);
+        }
+    }
+}
+
+fn restrict_nested_tags(symbols: &mut [lsp::DocumentSymbol], supported: Option<&lsp::TagSupport<lsp::SymbolTag>>) {
+    for symbol in symbols {
+        restrict_tags(&mut symbol.tags, supported);
+        if let Some(children) = &mut symbol.children {
+            restrict_nested_tags(children, supported);
+        }
+    }
+}
+
+fn workspace_symbols(
+    _: &lsp::WorkspaceSymbolParams,
+    symbols: Option<Vec<lsp::SymbolInformation>>,
+    capabilities: &lsp::ClientCapabilities,
+) -> Option<Vec<lsp::SymbolInformation>> {
+    let tags = capabilities
+        .workspace
+        .as_ref()
+        .and_then(|workspace| workspace.symbol.as_ref())
+        .and_then(|symbol| symbol.tag_support.as_ref());
+    let mut symbols = symbols?;
+    for symbol in &mut symbols {
+        restrict_tags(&mut symbol.tags, tags);
+    }
+    Some(symbols)
+}
+
+/// Removes the tags missing from the value set declared by the client, or all tags if the client
+/// declared no tag support.
+fn restrict_tags<T: PartialEq>(tags: &mut Option<Vec<T>>, supported: Option<&lsp::TagSupport<T>>) {
+    if let Some(values) = tags {
+        values.retain(|tag| supported.is_some_and(|supported| supported.value_set.contains(tag)));
+        if values.is_empty() {
+            *tags = None;
+        }
+    }
+}
+
+/// Adjusts folding ranges to the `textDocument.foldingRange` capabilities declared by the client.
+///
+/// * Kinds missing from `foldingRangeKind.valueSet` are removed, leaving the ranges themselves.
+/// * Start and end characters are removed if the client declared `lineFoldingOnly`.
+/// * Ranges in excess of `rangeLimit` are dropped, keeping the ranges the server returned first.
+///
+/// Since the `ClientCapabilities` of `lsp-types` do not retain `foldingRangeKind`, which was
+/// introduced in version 3.17 of the specification, the kinds are read from the capabilities as
+/// sent by the client.
+fn folding_ranges(
+    _: &lsp::FoldingRangeParams,
+    ranges: Option<Vec<lsp::FoldingRange>>,
+    capabilities: &Capabilities,
+) -> Option<Vec<lsp::FoldingRange>> {
+    let mut ranges = ranges?;
+    let kinds = capabilities.declared.pointer("/textDocument/foldingRange/foldingRangeKind/valueSet");
+    if let Some(Value::Array(kinds)) = kinds {
+        for range in &mut ranges {
+            let supported = range
+                .kind
+                .as_ref()
+                .is_none_or(|kind| kinds.contains(&crate::jsonrpc::lsp_value(kind)));
+            if !supported {
+                range.kind = None;
+            }
+        }
+    }
+
+    let folding = capabilities
+        .typed
+        .text_document
+        .as_ref()
+        .and_then(|text_document| text_document.folding_range.as_ref());
+    let folding = match folding {
+        Some(folding) => folding,
+        None => return Some(ranges),
+    };
+
+    if folding.line_folding_only == Some(true) {
+        for range in &mut ranges {
+            range.start_character = None;
+            range.end_character = None;
+        }
+    }
+
+    if let Some(limit) = folding.range_limit {
+        let limit = limit as usize;
+        if ranges.len() > limit {
+            log::debug!("dropping {} folding ranges beyond the client limit of {}", ranges.len() - limit, limit);
+            ranges.truncate(limit);
+        }
+    }
+
+    Some(ranges)
+}
+
+/// Converts a snippet into the text it expands to, with every placeholder set to its default.
+fn snippet_to_text(snippet: &str) -> String {
+    let mut text = String::new();
+    expand_snippet(&mut snippet.chars().peekable(), &mut text, false);
+    text
+}
+
+fn expand_snippet(chars: &mut Peekable<Chars>, text: &mut String, nested: bool) {
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next_if(|&c| matches!(c, '$' | '}' | '\\')) {
+                Some(escaped) => text.push(escaped),
+                None => text.push('\\'),
+            },
+            '}' if nested => return,
+            '$' if chars.next_if_eq(&'{').is_some() => {
+                while chars.next_if(|&c| c.is_alphanumeric() || c == '_').is_some() {}
+                match chars.next() {
+                    // Placeholders and variables with a default value.
+                    Some(':') => expand_snippet(chars, text, true),
+                    // Choices, of which the first one is taken.
+                    Some('|') => {
+                        let mut first = true;
+                        while let Some(c) = chars.next() {
+                            match c {
+                                '\\' => {
+                                    if let Some(escaped) = chars.next() {
+                                        if first {
+                                            text.push(escaped);
+                                        }
+                                    }
+                                },
+                                ',' => first = false,
+                                '|' => {
+                                    chars.next_if_eq(&'}');
+                                    break;
+                                },
+                                c if first => text.push(c),
+                                _ => {},
+                            }
+                        }
+                    },
+                    Some('}') | None => {},
+                    // Transformations of variables, which can't be evaluated here.
+                    Some(_) => {
+                        while let Some(c) = chars.next() {
+                            match c {
+                                '\\' => drop(chars.next()),
+                                '}' => break,
+                                _ => {},
+                            }
+                        }
+                    },
+                }
+            },
+            '$' if chars.peek().is_some_and(|&c| c.is_alphanumeric() || c == '_') => {
+                while chars.next_if(|&c| c.is_alphanumeric() || c == '_').is_some() {}
+            },
+            c => text.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn capabilities(value: Value) -> lsp::ClientCapabilities {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn declared(value: Value) -> Capabilities {
+        Capabilities {
+            typed: capabilities(value.clone()),
+            declared: Arc::new(value),
+        }
+    }
+
+    #[test]
+    fn snippets() {
+        assert_eq!(snippet_to_text("fn ${1:name}($2) {\n\t$0\n}"), "fn name() {\n\t\n}");
+        assert_eq!(snippet_to_text("${1:outer ${2:inner}} ${3|a,b|}"), "outer inner a");
+        assert_eq!(snippet_to_text(r"\$x \} ${TM_FILENAME/(.*)/$1/} $TM_LINE_NUMBER"), "$x }  ");
+    }
+
+    #[test]
+    fn completions() {
+        let item = lsp::CompletionItem {
+            label: "format".into(),
+            insert_text: Some("format!(\"$1\")$0".into()),
+            insert_text_format: Some(lsp::InsertTextFormat::SNIPPET),
+            ..Default::default()
+        };
+        let restricted = resolved_completion(&item, item.clone(), &capabilities(json!({})));
+        assert_eq!(restricted.insert_text.as_deref(), Some("format!(\"\")"));
+        assert_eq!(restricted.insert_text_format, Some(lsp::InsertTextFormat::PLAIN_TEXT));
+
+        let supported = capabilities(json!({
+            "textDocument": { "completion": { "completionItem": { "snippetSupport": true } } },
+        }));
+        assert_eq!(resolved_completion(&item, item.clone(), &supported), item);
+    }
+
+    #[test]
+    fn code_actions() {
+        let command = lsp::Command::new("Run".into(), "run".into(), None);
+        let actions = vec![
+            lsp::CodeActionOrCommand::CodeAction(lsp::CodeAction {
+                title: "Fix".into(),
+                ..Default::default()
+            }),
+            lsp::CodeActionOrCommand::CodeAction(lsp::CodeAction {
+                title: "Run".into(),
+                command: Some(command.clone()),
+                ..Default::default()
+            }),
+        ];
+        let params: lsp::CodeActionParams = serde_json::from_value(json!({
+            "textDocument": { "uri": "file:///a.rs" },
+            "range": { "start": { "line": 0, "character": 0 }, "end": { "line": 0, "character": 0 } },
+            "context": { "diagnostics": [] },
+        }))
+        .unwrap();
+
+        let restricted = super::code_actions(&params, Some(actions.clone()), &capabilities(json!({})));
+        assert_eq!(restricted, Some(vec![lsp::CodeActionOrCommand::Command(command)]));
+
+        let supported = capabilities(json!({
+            "textDocument": { "codeAction": { "codeActionLiteralSupport": { "codeActionKind": { "valueSet": [] } } } },
+        }));
+        assert_eq!(super::code_actions(&params, Some(actions.clone()), &supported), Some(actions));
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn document_symbols() {
+        let range = lsp::Range::new(lsp::Position::new(0, 0), lsp::Position::new(1, 0));
+        let symbol = |name: &str, children| lsp::DocumentSymbol {
+            name: name.into(),
+            detail: None,
+            kind: lsp::SymbolKind::FUNCTION,
+            tags: Some(vec![lsp::SymbolTag::DEPRECATED]),
+            deprecated: None,
+            range,
+            selection_range: range,
+            children,
+        };
+        let nested = lsp::DocumentSymbolResponse::Nested(vec![symbol("outer", Some(vec![symbol("inner", None)]))]);
+        let uri = lsp::Url::parse("file:///a.rs").unwrap();
+        let params = lsp::DocumentSymbolParams {
+            text_document: lsp::TextDocumentIdentifier::new(uri.clone()),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        };
+
+        let flat = super::document_symbols(&params, Some(nested.clone()), &capabilities(json!({})));
+        let information = |name: &str, container_name: Option<&str>| lsp::SymbolInformation {
+            name: name.into(),
+            kind: lsp::SymbolKind::FUNCTION,
+            tags: None,
+            deprecated: None,
+            location: lsp::Location::new(uri.clone(), range),
+            container_name: container_name.map(Into::into),
+        };
+        assert_eq!(
+            flat,
+            Some(lsp::DocumentSymbolResponse::Flat(vec![
+                information("outer", None),
+                information("inner", Some("outer")),
+            ]))
+        );
+
+        let supported = capabilities(json!({
+            "textDocument": {
+                "documentSymbol": { "hierarchicalDocumentSymbolSupport": true, "tagSupport": { "valueSet": [1] } },
+            },
+        }));
+        assert_eq!(super::document_symbols(&params, Some(nested.clone()), &supported), Some(nested));
+    }
+
+    #[test]
+    fn folding_ranges() {
+        let range = |start_line, kind| lsp::FoldingRange {
+            start_line,
+            start_character: Some(4),
+            end_line: start_line + 1,
+            end_character: Some(0),
+            kind,
+        };
+        let ranges = vec![
+            range(0, Some(lsp::FoldingRangeKind::Imports)),
+            range(2, Some(lsp::FoldingRangeKind::Comment)),
+            range(4, None),
+        ];
+        let params = lsp::FoldingRangeParams {
+            text_document: lsp::TextDocumentIdentifier::new(lsp::Url::parse("file:///a.rs").unwrap()),
+            work_done_progress_params: Default::default(),
+            partial_result_params: Default::default(),
+        };
+        assert_eq!(
+            super::folding_ranges(&params, Some(ranges.clone()), &declared(json!({}))),
+            Some(ranges.clone())
+        );
+
+        let kinds = json!({ "valueSet": ["comment"] });
+        let folding = json!({ "rangeLimit": 2, "lineFoldingOnly": true, "foldingRangeKind": kinds });
+        let limited = declared(json!({ "textDocument": { "foldingRange": folding } }));
+        assert_eq!(
+            super::folding_ranges(&params, Some(ranges), &limited),
+            Some(vec![
+                // The client did not declare the `imports` kind.
+                lsp::FoldingRange {
+                    start_character: None,
+                    end_character: None,
+                    ..range(0, None)
+                },
+                lsp::FoldingRange {
+                    start_character: None,
+                    end_character: None,
+                    ..range(2, Some(lsp::FoldingRangeKind::Comment))
+                },
+            ])
+        );
+    }
+}
@@ -0,0 +1,73 @@
+//! Lifecycle hooks invoked by the service at state transitions.
+
+use crate::Client;
+use futures::future::{BoxFuture, FutureExt};
+use std::{
+    fmt::{self, Debug, Formatter},
+    future::Future,
+    sync::Arc,
+};
+
+type Hook = Arc<dyn Fn(Client) -> BoxFuture<'static, ()> + Send + Sync>;
+
+/// The state transition at which a lifecycle hook is invoked.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Transition {
+    Initialized,
+    Shutdown,
+    Exit,
+}
+
+/// Hooks registered through the [`LspServiceBuilder`], in registration order.
+///
+/// [`LspServiceBuilder`]: crate::LspServiceBuilder
+#[derive(Clone, Default)]
+pub(crate) struct LifecycleHooks {
+    initialized: Vec<Hook>,
+    shutdown: Vec<Hook>,
+    exit: Vec<Hook>,
+}
+
+impl LifecycleHooks {
+    pub(crate) fn insert<H, Fut>(&mut self, transition: Transition, hook: H)
+    where
+        H: Fn(Client) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let hook: Hook = Arc::new(move |client| hook(client).boxed());
+        self.hooks_mut(transition).push(hook);
+    }
+
+    /// Runs the hooks registered for the given transition one after another.
+    pub(crate) async fn run(&self, transition: Transition, client: &Client) {
+        for hook in self.hooks(transition) {
+            hook(client.clone()).await;
+        }
+    }
+
+    fn hooks(&self, transition: Transition) -> &[Hook] {
+        match transition {
+            Transition::Initialized => &self.initialized,
+            Transition::Shutdown => &self.shutdown,
+            Transition::Exit => &self.exit,
+        }
+    }
+
+    fn hooks_mut(&mut self, transition: Transition) -> &mut Vec<Hook> {
+        match transition {
+            Transition::Initialized => &mut self.initialized,
+            Transition::Shutdown => &mut self.shutdown,
+            Transition::Exit => &mut self.exit,
+        }
+    }
+}
+
+impl Debug for LifecycleHooks {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct(stringify!(LifecycleHooks))
+            .field("initialized", &self.initialized.len())
+            .field("shutdown", &self.shutdown.len())
+            .field("exit", &self.exit.len())
+            .finish()
+    }
+}
@@ -0,0 +1,184 @@
+//! Hooks which rewrite or veto client messages before they are dispatched.
+
+use crate::jsonrpc::{Error, Response, Result, ServerRequest};
+use futures::future::BoxFuture;
+use serde_json::Value;
+use std::{
+    fmt::{self, Debug, Formatter},
+    ops::ControlFlow,
+    sync::Arc,
+};
+
+type Hook = Arc<dyn Fn(&mut ServerRequest) -> ControlFlow<Response> + Send + Sync>;
+
+type Interceptor = Arc<dyn Fn(&ServerRequest) -> Interception + Send + Sync>;
+
+/// How a request is handled, as decided by the interceptor registered with
+/// [`LspServiceBuilder::on_request`].
+///
+/// [`LspServiceBuilder::on_request`]: crate::LspServiceBuilder::on_request
+pub enum Interception {
+    /// The request is dispatched to the [`LanguageServer`] implementation as usual.
+    ///
+    /// [`LanguageServer`]: crate::LanguageServer
+    PassThrough,
+    /// The request is answered with the result of the given future instead, which can be
+    /// canceled by the client like any other request handler.
+    Respond(BoxFuture<'static, Result<Value>>),
+    /// The request is answered with the given error.
+    Reject(Error),
+}
+
+impl Debug for Interception {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Interception::PassThrough => f.write_str("PassThrough"),
+            Interception::Respond(_) => f.debug_tuple("Respond").finish_non_exhaustive(),
+            Interception::Reject(error) => f.debug_tuple("Reject").field(error).finish(),
+        }
+    }
+}
+
+/// Interceptors registered through [`LspServiceBuilder::on_request`], in registration order.
+///
+/// [`LspServiceBuilder::on_request`]: crate::LspServiceBuilder::on_request
+#[derive(Clone, Default)]
+pub(crate) struct RequestInterceptors(Vec<Interceptor>);
+
+impl RequestInterceptors {
+    pub(crate) fn insert<F>(&mut self, interceptor: F)
+    where
+        F: Fn(&ServerRequest) -> Interception + Send + Sync + 'static,
+    {
+        self.0.push(Arc::new(interceptor));
+    }
+
+    /// Decides how the given request is handled, asking the interceptors one after another until
+    /// one of them does not pass it through.
+    ///
+    /// The `shutdown` request and notifications are always passed through, since the service
+    /// relies on them to track the protocol lifecycle.
+    pub(crate) fn intercept(&self, request: &ServerRequest) -> Interception {
+        if request.id().is_none() || request.method() == "shutdown" {
+            return Interception::PassThrough;
+        }
+        for interceptor in &self.0 {
+            match interceptor(request) {
+                Interception::PassThrough => {},
+                interception => return interception,
+            }
+        }
+        Interception::PassThrough
+    }
+}
+
+impl Debug for RequestInterceptors {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_tuple(stringify!(RequestInterceptors)).field(&self.0.len()).finish()
+    }
+}
+
+/// Hooks registered through [`LspServiceBuilder::before_dispatch`], in registration order.
+///
+/// [`LspServiceBuilder::before_dispatch`]: crate::LspServiceBuilder::before_dispatch
+#[derive(Clone, Default)]
+pub(crate) struct DispatchHooks(Vec<Hook>);
+
+impl DispatchHooks {
+    pub(crate) fn insert<H>(&mut self, hook: H)
+    where
+        H: Fn(&mut ServerRequest) -> ControlFlow<Response> + Send + Sync + 'static,
+    {
+        self.0.push(Arc::new(hook));
+    }
+
+    /// Runs the hooks on the given message one after another, until one of them vetoes it.
+    ///
+    /// Returns `Some` with the response to send instead of dispatching the message, which is
+    /// `None` if a notification was vetoed, or `None` if all hooks let the message through.
+    pub(crate) fn run(&self, request: &mut ServerRequest) -> Option<Option<Response>> {
+        for hook in &self.0 {
+            if let ControlFlow::Break(response) = hook(request) {
+                if request.id().is_none() {
+                    log::debug!("dropping response to vetoed {:?} notification", request.method());
+                    return Some(None);
+                }
+                return Some(Some(response));
+            }
+        }
+        None
+    }
+}
+
+impl Debug for DispatchHooks {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_tuple(stringify!(DispatchHooks)).field(&self.0.len()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jsonrpc::{Error, Id};
+    use serde_json::json;
+
+    #[test]
+    fn run() {
+        let mut hooks = DispatchHooks::default();
+        hooks.insert(|request| {
+            if let Some(mut params) = request.params_value() {
+                params["query"] = json!("rewritten");
+                request.set_params(params).unwrap();
+            }
+            ControlFlow::Continue(())
+        });
+        hooks.insert(|request| match request.id() {
+            Some(id) if request.method() == "workspace/symbol" => {
+                ControlFlow::Break(Response::error(Some(id.clone()), Error::request_cancelled()))
+            },
+            _ => ControlFlow::Continue(()),
+        });
+
+        let mut request = ServerRequest::new("custom/request", Some(json!({ "query": "foo" })), Some(Id::Number(1))).unwrap();
+        assert_eq!(hooks.run(&mut request), None);
+        assert_eq!(request.params_value(), Some(json!({ "query": "rewritten" })));
+
+        let mut request = ServerRequest::new("workspace/symbol", Some(json!({ "query": "foo" })), Some(Id::Number(2))).unwrap();
+        let response = Response::error(Some(Id::Number(2)), Error::request_cancelled());
+        assert_eq!(hooks.run(&mut request), Some(Some(response)));
+        assert_eq!(request.params_value(), Some(json!({ "query": "rewritten" })));
+
+        let mut hooks = DispatchHooks::default();
+        hooks.insert(|_| ControlFlow::Break(Response::error(None, Error::invalid_request())));
+        let mut request = ServerRequest::new("initialized", Some(json!({})), None).unwrap();
+        assert_eq!(hooks.run(&mut request), Some(None));
+    }
+
+    #[tokio::test]
+    async fn intercept() {
+        let mut interceptor = RequestInterceptors::default();
+        interceptor.insert(|request| match request.method() {
+            "custom/reject" => Interception::Reject(Error::method_not_found()),
+            _ => Interception::PassThrough,
+        });
+        interceptor.insert(|request| match request.method() {
+            "custom/reject" => Interception::Reject(Error::internal_error()),
+            "custom/ping" => Interception::Respond(Box::pin(async { Ok(json!("pong")) })),
+            _ => Interception::PassThrough,
+        });
+
+        let request = |method, id| ServerRequest::new(method, Some(json!({})), id).unwrap();
+        let reject = request("custom/reject", Some(Id::Number(1)));
+        assert!(matches!(interceptor.intercept(&reject), Interception::Reject(error) if error == Error::method_not_found()));
+        match interceptor.intercept(&request("custom/ping", Some(Id::Number(2)))) {
+            Interception::Respond(response) => assert_eq!(response.await, Ok(json!("pong"))),
+            other => panic!("expected a response, got {:?}", other),
+        }
+
+        let shutdown = ServerRequest::new("shutdown", None, Some(Id::Number(3))).unwrap();
+        assert!(matches!(interceptor.intercept(&shutdown), Interception::PassThrough));
+        assert!(matches!(interceptor.intercept(&request("initialized", None)), Interception::PassThrough));
+        assert!(matches!(interceptor.intercept(&request("custom/other", Some(Id::Number(4)))), Interception::PassThrough));
+        assert!(matches!(RequestInterceptors::default().intercept(&reject), Interception::PassThrough));
+    }
+}
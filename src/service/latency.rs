@@ -0,0 +1,127 @@
+//! Logging requests whose handlers exceed their latency budget.
+
+use super::ExitedError;
+use crate::{
+    generated_impl::ServerRequest,
+    jsonrpc::{Id, Outgoing},
+    Client,
+    Clock,
+};
+use futures::future::{BoxFuture, FutureExt};
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+type Dispatch = BoxFuture<'static, Result<Option<Outgoing>, ExitedError>>;
+
+/// Latency budgets for client-to-server requests.
+///
+/// When a request takes longer than the budget of its method to be handled, a warning is logged
+/// with the method, the request ID, the time spent in the handler and the time the request was
+/// queued before its handler started. Optionally, the warning is also sent to the client as a
+/// `window/logMessage` notification, which is useful to spot slow handlers while debugging the
+/// server from within an editor.
+///
+/// Requests to methods without a budget of their own use the default budget, if any.
+/// Notifications are never timed.
+///
+/// # Example
+///
+/// ```rust
+/// # use lspower::LatencyBudget;
+/// # use lspower::lsp::request::{Completion, HoverRequest, Request};
+/// # use std::time::Duration;
+/// let budget = LatencyBudget::new()
+///     .default_budget(Duration::from_secs(1))
+///     .budget(Completion::METHOD, Duration::from_millis(100))
+///     .budget(HoverRequest::METHOD, Duration::from_millis(50))
+///     .log_to_client(cfg!(debug_assertions));
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LatencyBudget {
+    default: Option<Duration>,
+    methods: HashMap<String, Duration>,
+    log_to_client: bool,
+}
+
+impl LatencyBudget {
+    /// Creates an empty set of budgets, under which no request is timed.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Sets the budget of requests to methods without a budget of their own.
+    pub fn default_budget(mut self, budget: Duration) -> Self {
+        self.default = Some(budget);
+        self
+    }
+
+    /// Sets the budget of requests to the given method.
+    pub fn budget(mut self, method: impl Into<String>, budget: Duration) -> Self {
+        self.methods.insert(method.into(), budget);
+        self
+    }
+
+    /// Sets whether slow requests are also reported to the client with `window/logMessage`.
+    ///
+    /// Defaults to `false`.
+    pub fn log_to_client(mut self, enabled: bool) -> Self {
+        self.log_to_client = enabled;
+        self
+    }
+
+    /// Starts timing the given request upon its receipt, if it has a budget.
+    pub(crate) fn start(&self, request: &ServerRequest, client: &Client) -> Option<Stopwatch> {
+        let id = request.id()?;
+        let method = request.method();
+        let budget = self.methods.get(method).copied().or(self.default)?;
+        let clock = client.clock();
+        Some(Stopwatch {
+            id: id.clone(),
+            method: method.to_owned(),
+            budget,
+            received: clock.now(),
+            clock,
+            client: self.log_to_client.then(|| client.clone()),
+        })
+    }
+}
+
+/// Times a single request against its budget.
+pub(crate) struct Stopwatch {
+    id: Id,
+    method: String,
+    budget: Duration,
+    received: Instant,
+    clock: Arc<dyn Clock>,
+    client: Option<Client>,
+}
+
+impl Stopwatch {
+    /// Wraps the dispatched handler, reporting it once it resolves later than its budget allows.
+    pub(crate) fn time(self, dispatch: Dispatch) -> Dispatch {
+        async move {
+            let started = self.clock.now();
+            let response = dispatch.await;
+            let elapsed = self.clock.now().saturating_duration_since(started);
+            if elapsed > self.budget {
+                let queued = started.saturating_duration_since(self.received);
+                let message = format!(
+                    "slow request: method={:?} id={} elapsed={:?} queued={:?} budget={:?}",
+                    self.method, self.id, elapsed, queued, self.budget
+                );
+                log::warn!("{}", message);
+                // The warning is sent in the background, so that it does not delay the response.
+                if let Some(client) = self.client {
+                    let logger = client.clone();
+                    let log = async move { logger.log_message(lsp::MessageType::WARNING, message).await };
+                    client.spawn_background(log);
+                }
+            }
+            response
+        }
+        .boxed()
+    }
+}
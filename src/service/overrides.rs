@@ -0,0 +1,184 @@
+//! Decorators wrapping the handlers of single requests of the [`LanguageServer`] implementation.
+//!
+//! [`LanguageServer`]: crate::LanguageServer
+
+use crate::jsonrpc::Result;
+use futures::future::{BoxFuture, FutureExt};
+use std::{
+    any::{Any, TypeId},
+    collections::BTreeMap,
+    fmt::{self, Debug, Formatter},
+    future::Future,
+    marker::PhantomData,
+    sync::Arc,
+};
+
+type Handler<P, T> = Box<dyn FnOnce(P) -> BoxFuture<'static, Result<T>> + Send>;
+
+type Override<P, T> = dyn Fn(P, Handler<P, T>) -> BoxFuture<'static, Result<T>> + Send + Sync;
+
+/// Requests which the service answers itself, so that their handlers cannot be overridden.
+const RESERVED: &[&str] = &["initialize", "shutdown"];
+
+/// The handler of the [`LanguageServer`] implementation for requests of type `R`, passed to the
+/// override registered with [`LspServiceBuilder::override_method`].
+///
+/// [`LanguageServer`]: crate::LanguageServer
+/// [`LspServiceBuilder::override_method`]: crate::LspServiceBuilder::override_method
+pub struct DefaultHandler<R: lsp::request::Request> {
+    handler: Handler<R::Params, R::Result>,
+    _marker: PhantomData<fn() -> R>,
+}
+
+impl<R: lsp::request::Request> DefaultHandler<R> {
+    /// Calls the handler of the `LanguageServer` implementation with the given parameters.
+    pub async fn call(self, params: R::Params) -> Result<R::Result> {
+        (self.handler)(params).await
+    }
+}
+
+impl<R: lsp::request::Request> Debug for DefaultHandler<R> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct(stringify!(DefaultHandler)).finish_non_exhaustive()
+    }
+}
+
+/// Overrides registered through [`LspServiceBuilder::override_method`], by method.
+///
+/// [`LspServiceBuilder::override_method`]: crate::LspServiceBuilder::override_method
+#[derive(Clone, Default)]
+pub(crate) struct MethodOverrides(BTreeMap<&'static str, MethodOverride>);
+
+/// The override of the handler of a single method, holding an `Arc<Override<P, T>>` for the
+/// parameter and result types of the handler.
+#[derive(Clone)]
+pub(crate) struct MethodOverride(Arc<dyn Any + Send + Sync>);
+
+impl MethodOverrides {
+    /// Registers an override for requests of type `R`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `R` is a request which the service answers itself, or if its method or types do
+    /// not match a request handler of the `LanguageServer` trait.
+    pub(crate) fn insert<R, F, Fut>(&mut self, f: F)
+    where
+        R: lsp::request::Request,
+        R::Params: Send + 'static,
+        R::Result: Send + 'static,
+        F: Fn(DefaultHandler<R>, R::Params) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<R::Result>> + Send + 'static,
+    {
+        assert!(
+            !RESERVED.contains(&R::METHOD),
+            "the {:?} request is handled by the service and cannot be overridden",
+            R::METHOD
+        );
+        let types = (TypeId::of::<R::Params>(), TypeId::of::<Result<R::Result>>());
+        match crate::request_handler_types(R::METHOD) {
+            Some(expected) => assert!(
+                types == expected,
+                "the types of {} do not match the handler of the {:?} request",
+                std::any::type_name::<R>(),
+                R::METHOD
+            ),
+            None => panic!("the {:?} request has no handler which can be overridden", R::METHOD),
+        }
+
+        let f: Arc<Override<R::Params, R::Result>> = Arc::new(move |params, handler| {
+            let handler = DefaultHandler {
+                handler,
+                _marker: PhantomData,
+            };
+            f(handler, params).boxed()
+        });
+        self.0.insert(R::METHOD, MethodOverride(Arc::new(f)));
+    }
+
+    /// Returns the override registered for the given method, if any.
+    pub(crate) fn get(&self, method: &str) -> Option<&MethodOverride> {
+        self.0.get(method)
+    }
+}
+
+impl MethodOverride {
+    /// Answers a request with the override, which may call the given handler of the
+    /// `LanguageServer` implementation.
+    pub(crate) fn call<P, T, H, Fut>(&self, params: P, handler: H) -> BoxFuture<'static, Result<T>>
+    where
+        P: 'static,
+        T: 'static,
+        H: FnOnce(P) -> Fut + Send + 'static,
+        Fut: Future<Output = Result<T>> + Send + 'static,
+    {
+        // The types were checked against those of the handler when the override was registered.
+        let f = self
+            .0
+            .downcast_ref::<Arc<Override<P, T>>>()
+            .expect("override registered with mismatched types");
+        f(params, Box::new(move |params| handler(params).boxed()))
+    }
+}
+
+impl Debug for MethodOverrides {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_tuple(stringify!(MethodOverrides)).field(&self.0.keys()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lsp::request::{HoverRequest, Initialize};
+    use serde_json::json;
+
+    fn hover(text: &str) -> lsp::Hover {
+        lsp::Hover {
+            contents: lsp::HoverContents::Scalar(lsp::MarkedString::String(text.into())),
+            range: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn call() {
+        let mut overrides = MethodOverrides::default();
+        overrides.insert::<HoverRequest, _, _>(|inner, params: lsp::HoverParams| async move {
+            match params.text_document_position_params.position.line {
+                0 => Ok(Some(hover("overridden"))),
+                _ => inner.call(params).await,
+            }
+        });
+        assert_eq!(overrides.0.keys().collect::<Vec<_>>(), [&"textDocument/hover"]);
+
+        let params = |line| {
+            let raw = json!({ "textDocument": { "uri": "file:///a.rs" }, "position": { "line": line, "character": 0 } });
+            serde_json::from_value::<lsp::HoverParams>(raw).unwrap()
+        };
+        let handler = |_: lsp::HoverParams| async { Ok(Some(hover("default"))) };
+
+        let method = overrides.get("textDocument/hover").unwrap();
+        assert_eq!(method.call(params(0), handler).await, Ok(Some(hover("overridden"))));
+        assert_eq!(method.call(params(1), handler).await, Ok(Some(hover("default"))));
+        assert!(overrides.get("textDocument/definition").is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot be overridden")]
+    fn reserved() {
+        MethodOverrides::default().insert::<Initialize, _, _>(|_, _| async { Ok(Default::default()) });
+    }
+
+    #[test]
+    #[should_panic(expected = "do not match the handler")]
+    fn mismatched_types() {
+        enum Hover {}
+
+        impl lsp::request::Request for Hover {
+            type Params = serde_json::Value;
+            type Result = serde_json::Value;
+            const METHOD: &'static str = "textDocument/hover";
+        }
+
+        MethodOverrides::default().insert::<Hover, _, _>(|inner, params| inner.call(params));
+    }
+}
@@ -0,0 +1,227 @@
+//! Machine-readable log of the protocol exchanged with the client.
+
+use crate::{
+    jsonrpc::{ClientRequests, Id, Incoming, Outgoing},
+    server::StateKind,
+    traffic::Direction,
+};
+use serde_json::{json, Value};
+use std::{
+    fmt::{self, Debug, Formatter},
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+/// Methods which the server may send to the client before it responded to `initialize`.
+const ALLOWED_BEFORE_INITIALIZE: &[&str] = &[
+    "window/showMessage",
+    "window/logMessage",
+    "telemetry/event",
+    "window/showMessageRequest",
+    "$/progress",
+];
+
+/// Conformance log of the messages exchanged with the client, written as JSON lines.
+///
+/// Each message received from or sent to the client is recorded as one JSON object per line, with
+/// the following fields:
+///
+/// * `direction`: either `"incoming"` or `"outgoing"`.
+/// * `kind`: either `"request"`, `"notification"` or `"response"`.
+/// * `method`: the method of the message, or of the request a response belongs to, if known.
+/// * `id`: the request ID, or `null` for notifications.
+/// * `state`: the state of the server when the message was received or sent, i.e. one of
+///   `"uninitialized"`, `"initializing"`, `"initialized"`, `"shutdown"` or `"exited"`.
+/// * `warnings`: the violations of the protocol lifecycle detected for this message, such as a
+///   request received before `initialize`.
+/// * `error`: the error code of error responses.
+///
+/// Parameters and results are never recorded, so the log can be attached to bug reports without
+/// leaking the contents of the workspace. Failures to write the log are logged and otherwise
+/// ignored.
+///
+/// # Example
+///
+/// ```rust,no_run
+/// # use lspower::{jsonrpc::Result, lsp::*, LanguageServer, LspService, ProtocolLog};
+/// # #[derive(Debug)]
+/// # struct Backend;
+/// # #[lspower::async_trait]
+/// # impl LanguageServer for Backend {
+/// #     async fn initialize(&self, _: InitializeParams) -> Result<InitializeResult> {
+/// #         Ok(InitializeResult::default())
+/// #     }
+/// #     async fn shutdown(&self) -> Result<()> {
+/// #         Ok(())
+/// #     }
+/// # }
+/// # fn main() -> std::io::Result<()> {
+/// let (service, messages) = LspService::build(|_| Backend)
+///     .protocol_log(ProtocolLog::create("protocol.jsonl")?)
+///     .finish();
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct ProtocolLog {
+    writer: Arc<Mutex<Box<dyn Write + Send>>>,
+}
+
+impl ProtocolLog {
+    /// Creates a new `ProtocolLog` writing to the given writer.
+    pub fn new<W>(writer: W) -> Self
+    where
+        W: Write + Send + 'static,
+    {
+        ProtocolLog {
+            writer: Arc::new(Mutex::new(Box::new(writer))),
+        }
+    }
+
+    /// Creates a new `ProtocolLog` writing to the file at the given path, truncating it if it
+    /// already exists.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        File::create(path).map(|file| ProtocolLog::new(BufWriter::new(file)))
+    }
+
+    /// Records a message received from the client, given the state of the server upon its receipt.
+    pub(crate) fn incoming(&self, message: &Incoming, state: StateKind, pending: &ClientRequests) {
+        let record = match message {
+            Incoming::Request(req) => {
+                let method = req.method();
+                let kind = if req.id().is_some() { "request" } else { "notification" };
+                let warnings = incoming_warnings(method, kind, state);
+                Record::new(Direction::Incoming, kind, Some(method), req.id(), state, warnings)
+            },
+            Incoming::Response(res) => {
+                let method = res.id().and_then(|id| pending.method(id));
+                let warnings = match method {
+                    Some(_) => Vec::new(),
+                    None => vec!["response to unknown request".to_owned()],
+                };
+                let mut record = Record::new(Direction::Incoming, "response", method, res.id(), state, warnings);
+                record.error = res.clone().into_parts().1.err().map(|error| error.code.code());
+                record
+            },
+        };
+        self.write(record);
+    }
+
+    /// Records a message sent to the client, given the state of the server when it was sent.
+    ///
+    /// The `method` is that of the request answered by a response.
+    pub(crate) fn outgoing(&self, message: &Outgoing, method: Option<&str>, state: StateKind) {
+        let record = match message {
+            Outgoing::Response(res) => {
+                let mut record = Record::new(Direction::Outgoing, "response", method, res.id(), state, Vec::new());
+                record.error = res.clone().into_parts().1.err().map(|error| error.code.code());
+                record
+            },
+            Outgoing::Request(req) => {
+                let method = req.method();
+                let kind = if req.id().is_some() { "request" } else { "notification" };
+                let mut warnings = Vec::new();
+                let initialized = !matches!(state, StateKind::Uninitialized | StateKind::Initializing);
+                if !initialized && !ALLOWED_BEFORE_INITIALIZE.contains(&method) {
+                    warnings.push(format!("{} sent before initialize response", kind));
+                }
+                Record::new(Direction::Outgoing, kind, Some(method), req.id(), state, warnings)
+            },
+        };
+        self.write(record);
+    }
+
+    fn write(&self, record: Record) {
+        let mut writer = self.writer.lock().unwrap();
+        let result = serde_json::to_writer(&mut *writer, &record.into_value())
+            .map_err(io::Error::from)
+            .and_then(|_| writer.write_all(b"\n"))
+            .and_then(|_| writer.flush());
+        if let Err(error) = result {
+            log::error!("failed to write protocol log: {}", error);
+        }
+    }
+}
+
+impl Debug for ProtocolLog {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct(stringify!(ProtocolLog)).finish_non_exhaustive()
+    }
+}
+
+/// Returns the lifecycle violations of a message received from the client in the given state.
+fn incoming_warnings(method: &str, kind: &str, state: StateKind) -> Vec<String> {
+    let warning = match state {
+        StateKind::Uninitialized if method != "initialize" && method != "exit" => {
+            Some(format!("{} before initialize", kind))
+        },
+        StateKind::Initializing if method == "initialize" => Some("duplicate initialize".to_owned()),
+        StateKind::Initializing if method != "exit" && method != "$/cancelRequest" => {
+            Some(format!("{} while initializing", kind))
+        },
+        StateKind::Initialized if method == "initialize" => Some("duplicate initialize".to_owned()),
+        StateKind::ShutDown if method != "exit" => Some(format!("{} after shutdown", kind)),
+        StateKind::Exited => Some(format!("{} after exit", kind)),
+        _ => None,
+    };
+    warning.into_iter().collect()
+}
+
+struct Record<'a> {
+    direction: Direction,
+    kind: &'static str,
+    method: Option<&'a str>,
+    id: Option<&'a Id>,
+    state: StateKind,
+    warnings: Vec<String>,
+    error: Option<i64>,
+}
+
+impl<'a> Record<'a> {
+    fn new(
+        direction: Direction,
+        kind: &'static str,
+        method: Option<&'a str>,
+        id: Option<&'a Id>,
+        state: StateKind,
+        warnings: Vec<String>,
+    ) -> Self {
+        Record {
+            direction,
+            kind,
+            method,
+            id,
+            state,
+            warnings,
+            error: None,
+        }
+    }
+
+    fn into_value(self) -> Value {
+        let direction = match self.direction {
+            Direction::Incoming => "incoming",
+            Direction::Outgoing => "outgoing",
+        };
+        let state = match self.state {
+            StateKind::Uninitialized => "uninitialized",
+            StateKind::Initializing => "initializing",
+            StateKind::Initialized => "initialized",
+            StateKind::ShutDown => "shutdown",
+            StateKind::Exited => "exited",
+        };
+        let mut value = json!({
+            "direction": direction,
+            "kind": self.kind,
+            "method": self.method,
+            "id": self.id,
+            "state": state,
+            "warnings": self.warnings,
+        });
+        if let Some(code) = self.error {
+            value["error"] = code.into();
+        }
+        value
+    }
+}
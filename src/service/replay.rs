@@ -0,0 +1,106 @@
+//! Queueing requests received while the server is initializing.
+
+use super::ExitedError;
+use crate::{
+    generated_impl::ServerRequest,
+    jsonrpc::{Error, Outgoing, Response},
+    server::{State, StateKind},
+};
+use futures::{
+    channel::oneshot,
+    future::{self, BoxFuture, FutureExt},
+};
+use std::{
+    fmt::{self, Debug, Formatter},
+    sync::Mutex,
+};
+
+type Dispatch = BoxFuture<'static, Result<Option<Outgoing>, ExitedError>>;
+
+/// Methods which are never queued, since they drive the initialization itself.
+const UNQUEUED_METHODS: &[&str] = &["initialize", "exit"];
+
+/// How the service handles messages which arrive after the `initialize` request, but before the
+/// server responded to it.
+///
+/// Clients should not send requests or notifications other than `exit` in this state, but some do
+/// anyway.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum InitializingPolicy {
+    /// Requests fail with an "invalid request" error (`-32600`) and notifications are dropped.
+    #[default]
+    Reject,
+    /// Messages are queued and handled in the order they arrived once the server responded to the
+    /// `initialize` request.
+    ///
+    /// Queued requests canceled with `$/cancelRequest` are removed from the queue and fail with a
+    /// "request canceled" error (`-32800`) right away.
+    Queue,
+}
+
+/// Messages queued according to [`InitializingPolicy::Queue`], along with the senders through
+/// which their dispatched handlers are handed back to the callers.
+#[derive(Default)]
+pub(crate) struct ReplayQueue(Mutex<Vec<(Box<ServerRequest>, oneshot::Sender<Dispatch>)>>);
+
+impl ReplayQueue {
+    /// Queues the given message if the server is initializing or earlier messages are still queued,
+    /// returning a future which resolves once the message was replayed. Otherwise, the message is
+    /// handed back to be dispatched immediately.
+    ///
+    /// A `$/cancelRequest` notification for a queued request removes the request from the queue
+    /// instead, so that it resolves to a "canceled" error response right away.
+    pub(crate) fn push(&self, request: Box<ServerRequest>, state: &State) -> Result<Dispatch, Box<ServerRequest>> {
+        let mut queue = self.0.lock().unwrap();
+        if UNQUEUED_METHODS.contains(&request.method()) || (state.get() != StateKind::Initializing && queue.is_empty())
+        {
+            return Err(request);
+        }
+
+        if let Some(id) = request.cancelled_id() {
+            if let Some(index) = queue.iter().position(|(queued, _)| queued.id() == Some(id)) {
+                log::info!("successfully cancelled queued request with ID: {}", id);
+                queue.remove(index);
+                return Ok(future::ok(None).boxed());
+            }
+        }
+
+        log::info!("queueing {:?} until the server is initialized", request.method());
+        let id = request.id().cloned();
+        let (tx, rx) = oneshot::channel();
+        queue.push((request, tx));
+
+        Ok(async move {
+            match rx.await {
+                Ok(dispatch) => dispatch.await,
+                Err(_) => Ok(id.map(|id| Outgoing::Response(Response::error(Some(id), Error::request_cancelled())))),
+            }
+        }
+        .boxed())
+    }
+
+    /// Dispatches all queued messages in the order they arrived.
+    pub(crate) fn replay<F>(&self, dispatch: F)
+    where
+        F: Fn(Box<ServerRequest>) -> Dispatch,
+    {
+        let mut queue = self.0.lock().unwrap();
+        for (request, tx) in queue.drain(..) {
+            let _ = tx.send(dispatch(request));
+        }
+    }
+
+    /// Drops all queued messages, resolving queued requests to a "canceled" error response.
+    pub(crate) fn clear(&self) {
+        self.0.lock().unwrap().clear();
+    }
+}
+
+impl Debug for ReplayQueue {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let queue = self.0.lock().unwrap();
+        f.debug_list()
+            .entries(queue.iter().map(|(request, _)| request.method()))
+            .finish()
+    }
+}
@@ -0,0 +1,208 @@
+//! Restricting the methods a client is allowed to invoke.
+
+use crate::{
+    generated_impl::ServerRequest,
+    jsonrpc::{Error, Outgoing, Response},
+};
+use futures::future::{self, BoxFuture, FutureExt};
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    fmt::{self, Debug, Formatter},
+    future::Future,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+type Authenticator = Arc<dyn Fn(Option<Value>) -> BoxFuture<'static, bool> + Send + Sync>;
+
+/// Methods which are required by the protocol itself and can therefore not be denied.
+const LIFECYCLE_METHODS: &[&str] = &["initialize", "initialized", "shutdown", "exit", "$/cancelRequest"];
+
+/// Methods which are honored before the client authenticated.
+const UNAUTHENTICATED_METHODS: &[&str] = &["initialize", "exit", SecurityPolicy::AUTHENTICATE_METHOD];
+
+/// Whether a method may be invoked by the client.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Access {
+    Allow,
+    Deny,
+}
+
+/// A policy restricting which methods a client may invoke, for servers which are accessible by
+/// untrusted clients, e.g. over the network.
+///
+/// Methods are either allowed or denied individually, falling back to the default access of the
+/// policy for all other methods. Requests to denied methods fail with a "method not found" error
+/// (`-32601`), so that clients cannot distinguish them from methods the server does not
+/// implement, and denied notifications are dropped. The lifecycle methods `initialize`,
+/// `initialized`, `shutdown`, `exit` and `$/cancelRequest` are always allowed.
+///
+/// A policy can additionally require the client to authenticate itself with an
+/// `lspower/authenticate` request, see [`SecurityPolicy::authenticate`].
+///
+/// # Example
+///
+/// ```rust
+/// # use lspower::SecurityPolicy;
+/// # use lspower::lsp::request::{ExecuteCommand, Request};
+/// let policy = SecurityPolicy::allow_all()
+///     .deny(ExecuteCommand::METHOD)
+///     .authenticate(|params| async move { params == Some(serde_json::json!({ "token": "secret" })) });
+/// ```
+#[derive(Clone)]
+pub struct SecurityPolicy {
+    default: Access,
+    rules: HashMap<String, Access>,
+    authenticator: Option<Authenticator>,
+}
+
+impl SecurityPolicy {
+    /// The method of the request clients send to authenticate themselves.
+    pub const AUTHENTICATE_METHOD: &'static str = "lspower/authenticate";
+
+    /// Creates a policy which allows all methods unless they are denied explicitly.
+    pub fn allow_all() -> Self {
+        SecurityPolicy {
+            default: Access::Allow,
+            rules: HashMap::new(),
+            authenticator: None,
+        }
+    }
+
+    /// Creates a policy which denies all methods unless they are allowed explicitly.
+    pub fn deny_all() -> Self {
+        SecurityPolicy {
+            default: Access::Deny,
+            ..SecurityPolicy::allow_all()
+        }
+    }
+
+    /// Allows the client to invoke the given method.
+    pub fn allow(mut self, method: impl Into<String>) -> Self {
+        self.rules.insert(method.into(), Access::Allow);
+        self
+    }
+
+    /// Denies the client to invoke the given method.
+    pub fn deny(mut self, method: impl Into<String>) -> Self {
+        self.rules.insert(method.into(), Access::Deny);
+        self
+    }
+
+    /// Requires the client to authenticate before any method other than `initialize` and `exit`
+    /// is honored.
+    ///
+    /// The client authenticates by sending an `lspower/authenticate` request, whose parameters are
+    /// passed to `handler`. If the handler returns `true`, the request succeeds with a `null`
+    /// result and all methods allowed by the policy are honored from then on. Otherwise, the
+    /// request fails with error code `-32000`, as do all other requests sent before the client
+    /// authenticated. Notifications sent before the client authenticated are dropped, so clients
+    /// should authenticate before sending the `initialized` notification.
+    pub fn authenticate<H, Fut>(mut self, handler: H) -> Self
+    where
+        H: Fn(Option<Value>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = bool> + Send + 'static,
+    {
+        self.authenticator = Some(Arc::new(move |params| handler(params).boxed()));
+        self
+    }
+
+    /// Returns `true` if the policy allows the client to invoke the given method, not taking
+    /// authentication into account.
+    pub fn is_allowed(&self, method: &str) -> bool {
+        LIFECYCLE_METHODS.contains(&method) || self.rules.get(method).copied().unwrap_or(self.default) == Access::Allow
+    }
+
+    /// Handles requests which are rejected by the policy as well as authentication requests.
+    ///
+    /// Returns `None` for requests which are passed on to the server.
+    pub(crate) fn intercept(
+        &self,
+        request: &ServerRequest,
+        authenticated: &Arc<AtomicBool>,
+    ) -> Option<BoxFuture<'static, Option<Outgoing>>> {
+        let method = request.method();
+
+        if let Some(authenticator) = &self.authenticator {
+            if method == SecurityPolicy::AUTHENTICATE_METHOD {
+                let id = request.id().cloned()?;
+                let response = authenticator(request.other_params().cloned());
+                let authenticated = authenticated.clone();
+                return Some(
+                    async move {
+                        let response = if response.await {
+                            authenticated.store(true, Ordering::SeqCst);
+                            Response::ok(id, Value::Null)
+                        } else {
+                            log::warn!("client failed to authenticate");
+                            Response::error(Some(id), authentication_error("Authentication failed"))
+                        };
+                        Some(Outgoing::Response(response))
+                    }
+                    .boxed(),
+                );
+            }
+
+            if !authenticated.load(Ordering::SeqCst) && !UNAUTHENTICATED_METHODS.contains(&method) {
+                log::warn!("rejected {:?} from unauthenticated client", method);
+                let response = request.id().cloned().map(|id| {
+                    let error = authentication_error("Authentication required");
+                    Outgoing::Response(Response::error(Some(id), error))
+                });
+                return Some(future::ready(response).boxed());
+            }
+        }
+
+        if self.is_allowed(method) {
+            return None;
+        }
+
+        log::warn!("rejected {:?} denied by the security policy", method);
+        let response = request
+            .id()
+            .cloned()
+            .map(|id| Outgoing::Response(Response::error(Some(id), Error::method_not_found())));
+        Some(future::ready(response).boxed())
+    }
+}
+
+impl Default for SecurityPolicy {
+    fn default() -> Self {
+        SecurityPolicy::allow_all()
+    }
+}
+
+impl Debug for SecurityPolicy {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct(stringify!(SecurityPolicy))
+            .field("default", &self.default)
+            .field("rules", &self.rules)
+            .field("authenticate", &self.authenticator.is_some())
+            .finish()
+    }
+}
+
+fn authentication_error(message: &'static str) -> Error {
+    Error::server_error(-32000, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn is_allowed() {
+        let policy = SecurityPolicy::allow_all().deny("workspace/executeCommand");
+        assert!(policy.is_allowed("textDocument/hover"));
+        assert!(!policy.is_allowed("workspace/executeCommand"));
+
+        let policy = SecurityPolicy::deny_all().allow("textDocument/hover").deny("shutdown");
+        assert!(policy.is_allowed("textDocument/hover"));
+        assert!(!policy.is_allowed("workspace/executeCommand"));
+        assert!(policy.is_allowed("initialize"));
+        assert!(policy.is_allowed("shutdown"));
+    }
+}
@@ -0,0 +1,60 @@
+//! Resetting the session of a service whose client reconnected.
+
+use crate::{jsonrpc::ClientRequests, server::StateKind, Client};
+use std::{
+    fmt::{self, Debug, Formatter},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+/// Handle resetting the session of an [`LspService`] once its client reconnected, returned by
+/// [`LspService::session`].
+///
+/// A client connecting anew knows nothing about the session of the previous connection, so it
+/// sends another `initialize` request and opens its documents again. Calling [`reconnected`] from
+/// the hook passed to [`Server::reconnect`] prepares the service for this: the backend is kept,
+/// but the lifecycle starts over, so that it receives the `initialize` request of the new client.
+///
+/// [`LspService`]: crate::LspService
+/// [`LspService::session`]: crate::LspService::session
+/// [`reconnected`]: SessionHandle::reconnected
+/// [`Server::reconnect`]: crate::Server::reconnect
+#[derive(Clone)]
+pub struct SessionHandle {
+    pub(crate) state: Arc<crate::server::State>,
+    pub(crate) client: Client,
+    pub(crate) pending_client: Arc<ClientRequests>,
+    pub(crate) authenticated: Arc<AtomicBool>,
+    pub(crate) reconnected: Arc<AtomicBool>,
+}
+
+impl SessionHandle {
+    /// Starts a new session for a client which connected anew.
+    ///
+    /// Requests sent to the previous client which are still waiting for a response fail with a
+    /// "canceled" error. The capabilities the previous client declared, its dynamic registrations
+    /// and its authentication are forgotten, as are the documents it opened. Does nothing once the
+    /// server has exited.
+    pub fn reconnected(&self) {
+        if self.state.get() == StateKind::Exited {
+            return;
+        }
+
+        log::info!("client reconnected, starting a new session");
+        self.pending_client.cancel_all();
+        self.client.reset();
+        self.authenticated.store(false, Ordering::SeqCst);
+        self.reconnected.store(true, Ordering::SeqCst);
+        self.state.set(StateKind::Uninitialized);
+    }
+}
+
+impl Debug for SessionHandle {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct(stringify!(SessionHandle))
+            .field("state", &self.state.get())
+            .finish_non_exhaustive()
+    }
+}
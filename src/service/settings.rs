@@ -0,0 +1,216 @@
+//! Typed settings delivered from `workspace/didChangeConfiguration` notifications.
+
+use crate::Client;
+use futures::future::{self, BoxFuture, FutureExt, Shared};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::{
+    fmt::{self, Debug, Formatter},
+    future::Future,
+    sync::{Arc, Mutex},
+};
+
+type Hook = Arc<dyn Fn(Client, Option<Value>, Value) -> Option<BoxFuture<'static, ()>> + Send + Sync>;
+
+/// A change of the settings registered with [`LspServiceBuilder::on_settings_changed`].
+///
+/// [`LspServiceBuilder::on_settings_changed`]: crate::LspServiceBuilder::on_settings_changed
+#[derive(Clone, Debug, PartialEq)]
+pub struct SettingsChange<S> {
+    previous: Option<S>,
+    current: S,
+    changed: Vec<String>,
+}
+
+impl<S> SettingsChange<S> {
+    /// Returns the previous settings, or `None` if these are the first settings received since the
+    /// server was initialized.
+    pub fn previous(&self) -> Option<&S> {
+        self.previous.as_ref()
+    }
+
+    /// Returns the new settings.
+    pub fn current(&self) -> &S {
+        &self.current
+    }
+
+    /// Returns the names of the top-level fields of the settings whose values changed, in the order
+    /// of the new settings followed by removed fields.
+    ///
+    /// All fields of the new settings are reported as changed if there are no previous settings.
+    /// The list is empty if the settings are not JSON objects.
+    pub fn changed_sections(&self) -> &[String] {
+        &self.changed
+    }
+
+    /// Returns whether the value of the given top-level field of the settings changed.
+    pub fn is_changed(&self, section: &str) -> bool {
+        self.changed.iter().any(|changed| changed == section)
+    }
+
+    /// Returns the previous and the new settings.
+    pub fn into_parts(self) -> (Option<S>, S) {
+        (self.previous, self.current)
+    }
+}
+
+/// A typed settings section registered through the [`LspServiceBuilder`], with the JSON of the
+/// settings last delivered to its hook.
+///
+/// [`LspServiceBuilder`]: crate::LspServiceBuilder
+struct Registration {
+    section: Option<String>,
+    current: Arc<Mutex<Option<Value>>>,
+    hook: Hook,
+}
+
+/// Settings registered through the [`LspServiceBuilder`], in registration order.
+///
+/// [`LspServiceBuilder`]: crate::LspServiceBuilder
+#[derive(Clone)]
+pub(crate) struct SettingsHooks {
+    registrations: Vec<Arc<Registration>>,
+    /// The latest delivery of settings, which the next one waits for, so that the hooks receive
+    /// the settings in the order of the notifications although they run in the background.
+    last: Arc<Mutex<Shared<BoxFuture<'static, ()>>>>,
+}
+
+impl Default for SettingsHooks {
+    fn default() -> Self {
+        SettingsHooks {
+            registrations: Vec::new(),
+            last: Arc::new(Mutex::new(future::ready(()).boxed().shared())),
+        }
+    }
+}
+
+impl SettingsHooks {
+    pub(crate) fn insert<S, H, Fut>(&mut self, section: Option<&str>, hook: H)
+    where
+        S: DeserializeOwned + Send + 'static,
+        H: Fn(Client, SettingsChange<S>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let section = section.map(str::to_owned);
+        let name = section.clone();
+        let hook: Hook = Arc::new(move |client, old, new| {
+            let name = name.as_deref().unwrap_or("settings");
+            let new_settings = match serde_json::from_value(new.clone()) {
+                Ok(settings) => settings,
+                Err(error) => {
+                    log::warn!("failed to deserialize {:?} settings: {}", name, error);
+                    return None;
+                },
+            };
+            let change = SettingsChange {
+                changed: changed_sections(old.as_ref(), &new),
+                previous: old.and_then(|old| serde_json::from_value(old).ok()),
+                current: new_settings,
+            };
+            Some(hook(client, change).boxed())
+        });
+        self.registrations.push(Arc::new(Registration {
+            section,
+            current: Default::default(),
+            hook,
+        }));
+    }
+
+    /// Delivers the settings of a `workspace/didChangeConfiguration` notification to the hooks of
+    /// the sections which changed.
+    ///
+    /// Clients which send no settings with the notification are asked for the registered sections
+    /// with a `workspace/configuration` request instead.
+    ///
+    /// The returned future waits for the delivery of the previous settings to complete first.
+    pub(crate) fn changed(&self, settings: &Value, client: &Client) -> BoxFuture<'static, ()> {
+        if self.registrations.is_empty() {
+            return future::ready(()).boxed();
+        }
+
+        let registrations = self.registrations.clone();
+        let settings = settings.clone();
+        let client = client.clone();
+        let mut last = self.last.lock().unwrap();
+        let previous = last.clone();
+        let delivery = async move {
+            previous.await;
+            for registration in registrations {
+                let new = match (&registration.section, &settings) {
+                    (Some(section), Value::Null) => match client.configuration_scoped(None, section).await {
+                        Ok(new) => new,
+                        Err(error) => {
+                            log::warn!("failed to fetch {:?} settings: {}", section, error);
+                            continue;
+                        },
+                    },
+                    (Some(section), settings) => settings.get(section).cloned().unwrap_or_default(),
+                    (None, Value::Null) => continue,
+                    (None, settings) => settings.clone(),
+                };
+
+                let old = {
+                    let mut current = registration.current.lock().unwrap();
+                    if current.as_ref() == Some(&new) {
+                        continue;
+                    }
+                    current.replace(new.clone())
+                };
+                if let Some(hook) = (registration.hook)(client.clone(), old, new) {
+                    hook.await;
+                }
+            }
+        }
+        .boxed()
+        .shared();
+        *last = delivery.clone();
+        delivery.boxed()
+    }
+
+    /// Forgets the settings last delivered to the hooks, so that the next ones are reported as the
+    /// first.
+    pub(crate) fn clear(&self) {
+        for registration in &self.registrations {
+            registration.current.lock().unwrap().take();
+        }
+    }
+}
+
+impl Debug for SettingsHooks {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let sections = self.registrations.iter().map(|registration| &registration.section);
+        f.debug_tuple(stringify!(SettingsHooks)).field(&sections.collect::<Vec<_>>()).finish()
+    }
+}
+
+/// Returns the names of the top-level fields which differ between the old and the new settings.
+fn changed_sections(old: Option<&Value>, new: &Value) -> Vec<String> {
+    let empty = serde_json::Map::new();
+    let new = match new {
+        Value::Object(new) => new,
+        _ => return Vec::new(),
+    };
+    let old = match old {
+        Some(Value::Object(old)) => old,
+        _ => &empty,
+    };
+
+    let changed = new.iter().filter(|(key, value)| old.get(*key) != Some(*value)).map(|(key, _)| key);
+    let removed = old.keys().filter(|key| !new.contains_key(*key));
+    changed.chain(removed).cloned().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    #[test]
+    fn changed_sections() {
+        let old = json!({ "a": 1, "b": { "c": true }, "d": "x" });
+        let new = json!({ "a": 1, "b": { "c": false }, "e": [] });
+        assert_eq!(super::changed_sections(Some(&old), &new), vec!["b", "e", "d"]);
+        assert_eq!(super::changed_sections(None, &new), vec!["a", "b", "e"]);
+        assert_eq!(super::changed_sections(Some(&old), &old), Vec::<String>::new());
+        assert_eq!(super::changed_sections(Some(&old), &json!(3)), Vec::<String>::new());
+    }
+}
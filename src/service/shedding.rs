@@ -0,0 +1,127 @@
+//! Shedding low-priority requests while the server is overloaded.
+
+use crate::{
+    generated_impl::ServerRequest,
+    jsonrpc::{Error, Outgoing, Response, ServerRequests},
+};
+use serde_json::json;
+use std::collections::HashSet;
+
+/// Requests whose clients retrigger them when they fail with `retriggerRequest` set in the error
+/// data.
+const RETRIGGER_METHODS: &[&str] = &["textDocument/diagnostic", "workspace/diagnostic"];
+
+/// A policy answering low-priority requests with a "server cancelled" error (`-32802`) while too
+/// many requests are in flight, so that the client retries them later and the latency of
+/// interactive requests stays bounded.
+///
+/// Only requests to the methods listed with [`LoadSheddingPolicy::shed`] are affected, and only
+/// while at least `max_in_flight` client-to-server requests are being handled. Notifications are
+/// never shed. Shed `textDocument/diagnostic` and `workspace/diagnostic` requests ask the client to
+/// retrigger them through the error data.
+///
+/// # Example
+///
+/// ```rust
+/// # use lspower::LoadSheddingPolicy;
+/// # use lspower::lsp::request::{CodeLensRequest, DocumentLinkRequest, Request};
+/// let policy = LoadSheddingPolicy::new(32)
+///     .shed(CodeLensRequest::METHOD)
+///     .shed(DocumentLinkRequest::METHOD);
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct LoadSheddingPolicy {
+    max_in_flight: usize,
+    methods: HashSet<String>,
+}
+
+impl LoadSheddingPolicy {
+    /// Creates a policy which sheds requests once `max_in_flight` requests are in flight.
+    pub fn new(max_in_flight: usize) -> Self {
+        LoadSheddingPolicy {
+            max_in_flight,
+            methods: HashSet::new(),
+        }
+    }
+
+    /// Sheds requests to the given method while the server is overloaded.
+    pub fn shed(mut self, method: impl Into<String>) -> Self {
+        self.methods.insert(method.into());
+        self
+    }
+
+    /// Answers the given request with a "server cancelled" error if it is to be shed.
+    ///
+    /// Returns `None` for requests which are passed on to the server.
+    pub(crate) fn intercept(&self, request: &ServerRequest, pending: &ServerRequests) -> Option<Outgoing> {
+        let method = request.method();
+        let id = request.id()?;
+        if !self.methods.contains(method) || pending.in_flight() < self.max_in_flight {
+            return None;
+        }
+
+        log::warn!(
+            "shedding {:?} request while {} requests are in flight",
+            method,
+            pending.in_flight()
+        );
+        let mut error = Error::server_cancelled();
+        if RETRIGGER_METHODS.contains(&method) {
+            error = error.with_data(json!({ "retriggerRequest": true }));
+        }
+        Some(Outgoing::Response(Response::error(Some(id.clone()), error)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jsonrpc::Id;
+    use futures::future;
+
+    #[test]
+    fn intercept() {
+        let policy = LoadSheddingPolicy::new(1)
+            .shed("textDocument/codeLens")
+            .shed("workspace/diagnostic");
+        let pending = ServerRequests::new();
+        let parse = |raw| serde_json::from_value::<ServerRequest>(raw).unwrap();
+        let code_lens = parse(json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/codeLens",
+            "params": { "textDocument": { "uri": "file:///a.rs" } },
+            "id": 2,
+        }));
+        let diagnostic = parse(json!({
+            "jsonrpc": "2.0",
+            "method": "workspace/diagnostic",
+            "params": { "previousResultIds": [] },
+            "id": 3,
+        }));
+        let hover = parse(json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/hover",
+            "params": { "textDocument": { "uri": "file:///a.rs" }, "position": { "line": 0, "character": 0 } },
+            "id": 4,
+        }));
+        assert_eq!(policy.intercept(&code_lens, &pending), None);
+
+        let _fut = pending.execute(
+            Id::Number(1),
+            "textDocument/hover",
+            future::pending::<crate::jsonrpc::Result<()>>(),
+        );
+        let response = Response::error(Some(Id::Number(2)), Error::server_cancelled());
+        assert_eq!(
+            policy.intercept(&code_lens, &pending),
+            Some(Outgoing::Response(response))
+        );
+        let error = Error::server_cancelled().with_data(json!({ "retriggerRequest": true }));
+        let response = Response::error(Some(Id::Number(3)), error);
+        assert_eq!(
+            policy.intercept(&diagnostic, &pending),
+            Some(Outgoing::Response(response))
+        );
+        assert_eq!(policy.intercept(&hover, &pending), None);
+    }
+}
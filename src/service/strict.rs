@@ -0,0 +1,196 @@
+//! Detecting clients which violate the ordering of document notifications.
+
+use crate::{
+    generated_impl::ServerRequest,
+    jsonrpc::{Error, Outgoing, Response},
+};
+use std::{
+    collections::HashSet,
+    error,
+    fmt::{self, Debug, Display, Formatter},
+    sync::Arc,
+};
+
+type Handler = Arc<dyn Fn(&ProtocolViolation) + Send + Sync>;
+
+/// A violation of the protocol by the client, detected by [`StrictMode`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ProtocolViolation {
+    /// A `textDocument/didOpen` notification for a document which is already open.
+    DuplicateOpen {
+        /// The URI of the document.
+        uri: lsp::Url,
+    },
+    /// A notification such as `textDocument/didChange` for a document which is not open.
+    NotificationForClosedDocument {
+        /// The method of the notification.
+        method: String,
+        /// The URI of the document.
+        uri: lsp::Url,
+    },
+    /// A `textDocument/*` request for a document which is not open.
+    RequestForClosedDocument {
+        /// The method of the request.
+        method: String,
+        /// The URI of the document.
+        uri: lsp::Url,
+    },
+}
+
+impl ProtocolViolation {
+    /// Returns the method of the offending message.
+    pub fn method(&self) -> &str {
+        match self {
+            ProtocolViolation::DuplicateOpen { .. } => "textDocument/didOpen",
+            ProtocolViolation::NotificationForClosedDocument { method, .. } => method,
+            ProtocolViolation::RequestForClosedDocument { method, .. } => method,
+        }
+    }
+
+    /// Returns the URI of the document the offending message refers to.
+    pub fn uri(&self) -> &lsp::Url {
+        match self {
+            ProtocolViolation::DuplicateOpen { uri } => uri,
+            ProtocolViolation::NotificationForClosedDocument { uri, .. } => uri,
+            ProtocolViolation::RequestForClosedDocument { uri, .. } => uri,
+        }
+    }
+}
+
+impl Display for ProtocolViolation {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            ProtocolViolation::DuplicateOpen { uri } => write!(f, "document {} was opened twice", uri),
+            ProtocolViolation::NotificationForClosedDocument { method, uri } => {
+                write!(f, "{:?} notification for {}, which is not open", method, uri)
+            },
+            ProtocolViolation::RequestForClosedDocument { method, uri } => {
+                write!(f, "{:?} request for {}, which is not open", method, uri)
+            },
+        }
+    }
+}
+
+impl error::Error for ProtocolViolation {
+}
+
+/// What [`StrictMode`] does with messages which violate the protocol.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum ViolationAction {
+    /// Logs a warning and passes the message on to the server.
+    #[default]
+    Log,
+    /// Logs a warning and answers requests with an "invalid request" error (`-32600`), while
+    /// notifications are dropped.
+    Reject,
+}
+
+/// Validation of the order of the document notifications sent by the client, for debugging
+/// misbehaving clients.
+///
+/// The service tracks which documents are open, and reports the following violations of the
+/// specification:
+///
+/// * a `textDocument/didOpen` notification for a document which is already open,
+/// * any other `textDocument/*` notification, such as `textDocument/didChange`, for a document
+///   which is not open,
+/// * a `textDocument/*` request for a document which is not open.
+///
+/// Violations are logged and handled according to the [`ViolationAction`] of the mode. Handlers
+/// set with [`on_violation`](StrictMode::on_violation) are invoked with each violation before the
+/// action is taken. Since the parameters of all document messages are inspected, this mode is
+/// meant for development rather than production use.
+///
+/// # Example
+///
+/// ```rust
+/// # use lspower::{StrictMode, ViolationAction};
+/// let strict = StrictMode::new(ViolationAction::Reject).on_violation(|violation| {
+///     panic!("misbehaving client: {}", violation);
+/// });
+/// ```
+#[derive(Clone, Default)]
+pub struct StrictMode {
+    action: ViolationAction,
+    handler: Option<Handler>,
+}
+
+impl StrictMode {
+    /// Creates a new `StrictMode` taking the given action.
+    pub fn new(action: ViolationAction) -> Self {
+        StrictMode { action, handler: None }
+    }
+
+    /// Sets a handler which is invoked with each violation.
+    pub fn on_violation<H>(mut self, handler: H) -> Self
+    where
+        H: Fn(&ProtocolViolation) + Send + Sync + 'static,
+    {
+        self.handler = Some(Arc::new(handler));
+        self
+    }
+
+    /// Returns the action taken for messages which violate the protocol.
+    pub fn action(&self) -> ViolationAction {
+        self.action
+    }
+
+    /// Checks the given message against the documents open so far, updating them.
+    ///
+    /// Returns `None` for messages which are passed on to the server, or the response to rejected
+    /// messages, if any.
+    pub(crate) fn intercept(
+        &self,
+        request: &ServerRequest,
+        documents: &mut HashSet<lsp::Url>,
+    ) -> Option<Option<Outgoing>> {
+        let method = request.method();
+        if !method.starts_with("textDocument/") {
+            return None;
+        }
+
+        let params = request.params().or_else(|| request.other_params().cloned())?;
+        let uri = params["textDocument"]["uri"]
+            .as_str()
+            .and_then(|uri| lsp::Url::parse(uri).ok())?;
+        let violation = match (method, request.id()) {
+            ("textDocument/didOpen", _) if !documents.insert(uri.clone()) => ProtocolViolation::DuplicateOpen { uri },
+            ("textDocument/didOpen", _) => return None,
+            ("textDocument/didClose", _) if documents.remove(&uri) => return None,
+            (_, _) if documents.contains(&uri) => return None,
+            (method, None) => ProtocolViolation::NotificationForClosedDocument {
+                method: method.into(),
+                uri,
+            },
+            (method, Some(_)) => ProtocolViolation::RequestForClosedDocument {
+                method: method.into(),
+                uri,
+            },
+        };
+
+        log::warn!("protocol violation: {}", violation);
+        if let Some(handler) = &self.handler {
+            handler(&violation);
+        }
+
+        match self.action {
+            ViolationAction::Log => None,
+            ViolationAction::Reject => {
+                let response = request.id().cloned().map(|id| {
+                    let error = Error::invalid_request().with_message(violation.to_string());
+                    Outgoing::Response(Response::error(Some(id), error))
+                });
+                Some(response)
+            },
+        }
+    }
+}
+
+impl Debug for StrictMode {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct(stringify!(StrictMode))
+            .field("action", &self.action)
+            .field("on_violation", &self.handler.is_some())
+            .finish()
+    }
+}
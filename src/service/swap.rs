@@ -0,0 +1,214 @@
+//! Tracking of the messages handled by a backend, for replacing it while the service runs.
+
+use crate::{server::StateKind, Client};
+use futures::task::AtomicWaker;
+use std::{
+    error::Error,
+    fmt::{self, Debug, Display, Formatter},
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+        RwLock,
+    },
+    task::{Context, Poll},
+};
+
+/// Error that occurs when attempting to replace the backend of a language server which cannot
+/// take a new one.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ReplaceError {
+    /// The language server is handling the `initialize` request.
+    Initializing,
+    /// The language server has already exited.
+    Exited,
+}
+
+impl Display for ReplaceError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            ReplaceError::Initializing => f.write_str("language server is being initialized"),
+            ReplaceError::Exited => f.write_str("language server has exited"),
+        }
+    }
+}
+
+impl Error for ReplaceError {
+}
+
+/// Handle replacing the backend of an [`LspService`] while it is served, returned by
+/// [`LspService::backend`].
+///
+/// Once the service is passed to a [`Server`], it can no longer be borrowed mutably, so this handle
+/// is the way to replace its backend, e.g. from a file watcher reloading language logic.
+///
+/// [`LspService`]: crate::LspService
+/// [`LspService::backend`]: crate::LspService::backend
+/// [`Server`]: crate::Server
+#[derive(Clone)]
+pub struct BackendHandle {
+    pub(crate) backend: SharedBackend,
+    pub(crate) state: Arc<crate::server::State>,
+    pub(crate) client: Client,
+}
+
+impl BackendHandle {
+    /// Replaces the backend of the service.
+    ///
+    /// See [`LspService::replace_backend`] for details.
+    ///
+    /// [`LspService::replace_backend`]: crate::LspService::replace_backend
+    pub fn replace<T, F>(&self, init: F) -> Result<Drained, ReplaceError>
+    where
+        F: FnOnce(Client) -> T,
+        T: crate::LanguageServer,
+    {
+        match self.state.get() {
+            StateKind::Initializing => return Err(ReplaceError::Initializing),
+            StateKind::Exited => return Err(ReplaceError::Exited),
+            _ => {},
+        }
+
+        log::info!("replacing language server backend");
+        Ok(self.backend.replace(Arc::from(init(self.client.clone()))))
+    }
+}
+
+impl Debug for BackendHandle {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct(stringify!(BackendHandle))
+            .field("state", &self.state.get())
+            .finish_non_exhaustive()
+    }
+}
+
+/// The current backend of a service, along with the messages dispatched to it.
+struct Backend {
+    server: Arc<dyn crate::LanguageServer>,
+    dispatched: Dispatched,
+}
+
+/// The backend of a service, shared with the [`BackendHandle`]s replacing it.
+#[derive(Clone)]
+pub(crate) struct SharedBackend(Arc<RwLock<Backend>>);
+
+impl SharedBackend {
+    pub(crate) fn new(server: Arc<dyn crate::LanguageServer>) -> Self {
+        let dispatched = Dispatched::default();
+        SharedBackend(Arc::new(RwLock::new(Backend { server, dispatched })))
+    }
+
+    /// Returns the current backend and the tracker of the messages dispatched to it.
+    pub(crate) fn current(&self) -> (Arc<dyn crate::LanguageServer>, Dispatched) {
+        let backend = self.0.read().unwrap();
+        (backend.server.clone(), backend.dispatched.clone())
+    }
+
+    /// Replaces the current backend, returning a future resolving once the messages dispatched to
+    /// the previous one have been handled.
+    pub(crate) fn replace(&self, server: Arc<dyn crate::LanguageServer>) -> Drained {
+        let mut backend = self.0.write().unwrap();
+        backend.server = server;
+        std::mem::take(&mut backend.dispatched).drained()
+    }
+}
+
+#[derive(Default)]
+struct Counter {
+    in_flight: AtomicUsize,
+    waker: AtomicWaker,
+}
+
+/// Counts the messages dispatched to a backend which are still being handled.
+#[derive(Clone, Default)]
+pub(crate) struct Dispatched(Arc<Counter>);
+
+impl Dispatched {
+    /// Counts the given response future as in flight until it completes or is dropped.
+    pub(crate) fn track<Fut: Future>(&self, future: Fut) -> impl Future<Output = Fut::Output> {
+        self.0.in_flight.fetch_add(1, Ordering::SeqCst);
+        let guard = Guard(self.0.clone());
+        async move {
+            let output = future.await;
+            drop(guard);
+            output
+        }
+    }
+
+    /// Returns a future resolving once no tracked response future is in flight.
+    pub(crate) fn drained(self) -> Drained {
+        Drained(self.0)
+    }
+}
+
+/// Marks a response future as completed once dropped.
+struct Guard(Arc<Counter>);
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        if self.0.in_flight.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.0.waker.wake();
+        }
+    }
+}
+
+/// Future returned by [`LspService::replace_backend`], resolving once the messages dispatched to
+/// the previous backend have been handled.
+///
+/// [`LspService::replace_backend`]: crate::LspService::replace_backend
+#[must_use = "futures do nothing unless polled"]
+pub struct Drained(Arc<Counter>);
+
+impl Drained {
+    /// Returns the number of messages which the previous backend is still handling.
+    pub fn in_flight(&self) -> usize {
+        self.0.in_flight.load(Ordering::SeqCst)
+    }
+}
+
+impl Future for Drained {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        self.0.waker.register(cx.waker());
+        match self.in_flight() {
+            0 => Poll::Ready(()),
+            _ => Poll::Pending,
+        }
+    }
+}
+
+impl Debug for Drained {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct(stringify!(Drained))
+            .field("in_flight", &self.in_flight())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::{channel::oneshot, FutureExt};
+
+    #[tokio::test]
+    async fn drained() {
+        let dispatched = Dispatched::default();
+        let (tx, rx) = oneshot::channel::<()>();
+        let pending = dispatched.track(rx);
+        let dropped = dispatched.track(futures::future::pending::<()>());
+        let completed = dispatched.track(async { 1 }).await;
+        assert_eq!(completed, 1);
+
+        let mut drained = dispatched.drained();
+        assert_eq!(drained.in_flight(), 2);
+        drop(dropped);
+        assert!((&mut drained).now_or_never().is_none());
+
+        let handle = tokio::spawn(pending);
+        tx.send(()).unwrap();
+        drained.await;
+        assert!(handle.await.unwrap().is_ok());
+    }
+}
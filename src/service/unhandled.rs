@@ -0,0 +1,298 @@
+//! Reporting of notifications which the server does not handle.
+
+use crate::feed::{Feed, Subscription};
+use futures::stream::{FusedStream, Stream};
+use serde::Serialize;
+use serde_json::Value;
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt::{self, Debug, Formatter},
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// The number of notifications buffered for each subscriber before the oldest ones are dropped.
+const CAPACITY: usize = 64;
+
+thread_local! {
+    static CURRENT: RefCell<Option<UnhandledNotifications>> = const { RefCell::new(None) };
+}
+
+/// A policy setting the level at which notifications which the server does not handle are logged.
+///
+/// By default, notifications whose [`LanguageServer`] method is not implemented are logged as
+/// warnings, notifications to unknown methods are logged as errors, and notifications to unknown
+/// methods starting with `$/`, which servers may ignore, are not logged at all. Chatty clients can fill
+/// the logs of servers which deliberately ignore some notifications, so the level can be lowered
+/// or logging disabled, either for all notifications or for single methods.
+///
+/// Regardless of the policy, unhandled notifications are reported through
+/// [`LspService::unhandled_notifications`].
+///
+/// # Example
+///
+/// ```rust
+/// # use lspower::UnhandledNotificationPolicy;
+/// # use lspower::lsp::notification::{DidChangeWatchedFiles, Notification};
+/// let policy = UnhandledNotificationPolicy::new()
+///     .level(Some(log::Level::Debug))
+///     .method(DidChangeWatchedFiles::METHOD, None);
+/// ```
+///
+/// [`LanguageServer`]: crate::LanguageServer
+/// [`LspService::unhandled_notifications`]: crate::LspService::unhandled_notifications
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct UnhandledNotificationPolicy {
+    level: Option<Option<log::Level>>,
+    methods: HashMap<String, Option<log::Level>>,
+}
+
+impl UnhandledNotificationPolicy {
+    /// Creates a policy logging unhandled notifications at their default levels.
+    pub fn new() -> Self {
+        UnhandledNotificationPolicy::default()
+    }
+
+    /// Logs all unhandled notifications at the given level, or not at all if `None`.
+    pub fn level(mut self, level: Option<log::Level>) -> Self {
+        self.level = Some(level);
+        self
+    }
+
+    /// Logs unhandled notifications to the given method at the given level, or not at all if
+    /// `None`.
+    ///
+    /// This takes precedence over the level set with [`level`](Self::level).
+    pub fn method(mut self, method: impl Into<String>, level: Option<log::Level>) -> Self {
+        self.methods.insert(method.into(), level);
+        self
+    }
+
+    fn level_of(&self, method: &str, default: Option<log::Level>) -> Option<log::Level> {
+        match self.methods.get(method) {
+            Some(level) => *level,
+            None => self.level.unwrap_or(default),
+        }
+    }
+}
+
+/// A notification which the server did not handle, as reported by
+/// [`LspService::unhandled_notifications`].
+///
+/// [`LspService::unhandled_notifications`]: crate::LspService::unhandled_notifications
+#[derive(Clone, Debug, PartialEq)]
+pub struct UnhandledNotification {
+    method: String,
+    params: Option<Value>,
+    known: bool,
+}
+
+impl UnhandledNotification {
+    /// Returns the method of the notification.
+    pub fn method(&self) -> &str {
+        &self.method
+    }
+
+    /// Returns the parameters of the notification, if it had any.
+    pub fn params(&self) -> Option<&Value> {
+        self.params.as_ref()
+    }
+
+    /// Returns `true` if the notification has a method on the [`LanguageServer`] trait which was
+    /// not implemented, or `false` if its method is unknown.
+    ///
+    /// [`LanguageServer`]: crate::LanguageServer
+    pub fn is_known(&self) -> bool {
+        self.known
+    }
+}
+
+/// Logs unhandled notifications according to the policy and sends them to all subscribers.
+///
+/// The router reports notifications to unknown methods directly. The default notification methods
+/// of [`LanguageServer`](crate::LanguageServer) cannot be passed these, so the router runs them
+/// within a [`scope`](Self::scope) instead.
+#[derive(Clone, Debug)]
+pub(crate) struct UnhandledNotifications {
+    policy: UnhandledNotificationPolicy,
+    feed: Feed<UnhandledNotification>,
+}
+
+impl UnhandledNotifications {
+    pub(crate) fn new(policy: UnhandledNotificationPolicy) -> Self {
+        UnhandledNotifications {
+            policy,
+            feed: Feed::new(CAPACITY),
+        }
+    }
+
+    pub(crate) fn subscribe(&self) -> UnhandledNotificationStream {
+        UnhandledNotificationStream(self.feed.subscribe())
+    }
+
+    /// Reports a notification to a method without a dedicated handler.
+    pub(crate) fn not_found(&self, method: &str, params: Option<Value>) {
+        self.report(method, || params, false);
+    }
+
+    /// Makes these the notifications reported to while the future created by `f` is being created
+    /// and polled.
+    pub(crate) fn scope<Fut: Future>(&self, f: impl FnOnce() -> Fut) -> Scoped<Fut> {
+        Scoped {
+            future: Box::pin(self.enter(f)),
+            unhandled: self.clone(),
+        }
+    }
+
+    fn enter<T>(&self, f: impl FnOnce() -> T) -> T {
+        let previous = CURRENT.with(|current| current.replace(Some(self.clone())));
+        let _guard = Restore(previous);
+        f()
+    }
+
+    fn report(&self, method: &str, params: impl FnOnce() -> Option<Value>, known: bool) {
+        let level = match (known, method.starts_with("$/")) {
+            (true, _) => Some(log::Level::Warn),
+            (false, false) => Some(log::Level::Error),
+            (false, true) => None,
+        };
+        if let Some(level) = self.policy.level_of(method, level) {
+            match known {
+                true => log::log!(level, "Got a {} notification, but it is not implemented", method),
+                false => log::log!(level, "method {:?} not found", method),
+            }
+        }
+
+        self.feed.publish(|| UnhandledNotification {
+            method: method.into(),
+            params: params(),
+            known,
+        });
+    }
+}
+
+impl Default for UnhandledNotifications {
+    fn default() -> Self {
+        UnhandledNotifications::new(Default::default())
+    }
+}
+
+/// Future returned by [`UnhandledNotifications::scope`].
+pub(crate) struct Scoped<Fut> {
+    future: Pin<Box<Fut>>,
+    unhandled: UnhandledNotifications,
+}
+
+impl<Fut: Future> Future for Scoped<Fut> {
+    type Output = Fut::Output;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        let this = &mut *self;
+        this.unhandled.enter(|| this.future.as_mut().poll(cx))
+    }
+}
+
+/// Restores the previous notifications reported to once dropped.
+struct Restore(Option<UnhandledNotifications>);
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        let previous = self.0.take();
+        CURRENT.with(|current| *current.borrow_mut() = previous);
+    }
+}
+
+/// Reports a notification whose [`LanguageServer`](crate::LanguageServer) method is not
+/// implemented.
+///
+/// Outside of the notification handlers of an [`LspService`](crate::LspService), e.g. when a
+/// server calls the method itself, the notification is logged as a warning.
+pub(crate) fn not_implemented<P: Serialize>(method: &str, params: &P) {
+    CURRENT.with(|current| match &*current.borrow() {
+        Some(unhandled) => unhandled.report(method, || serde_json::to_value(params).ok(), true),
+        None => log::warn!("Got a {} notification, but it is not implemented", method),
+    })
+}
+
+/// Reports a notification to a method without a dedicated handler from the default
+/// [`LanguageServer::notification_else`](crate::LanguageServer::notification_else).
+pub(crate) fn not_found(method: &str, params: Option<Value>) {
+    CURRENT.with(|current| match &*current.borrow() {
+        Some(unhandled) => unhandled.not_found(method, params),
+        None if !method.starts_with("$/") => log::error!("method {:?} not found", method),
+        None => {},
+    })
+}
+
+/// Stream of the notifications which the server did not handle, returned by
+/// [`LspService::unhandled_notifications`].
+///
+/// Each stream buffers the most recent notifications which were not received yet. A stream which
+/// falls further behind misses the oldest ones.
+///
+/// [`LspService::unhandled_notifications`]: crate::LspService::unhandled_notifications
+#[must_use = "streams do nothing unless polled"]
+pub struct UnhandledNotificationStream(Subscription<UnhandledNotification>);
+
+impl Stream for UnhandledNotificationStream {
+    type Item = UnhandledNotification;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.0).poll_next(cx)
+    }
+}
+
+impl FusedStream for UnhandledNotificationStream {
+    fn is_terminated(&self) -> bool {
+        self.0.is_terminated()
+    }
+}
+
+impl Debug for UnhandledNotificationStream {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct(stringify!(UnhandledNotificationStream)).finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+    use serde_json::json;
+
+    #[test]
+    fn level_of() {
+        let policy = UnhandledNotificationPolicy::new().method("workspace/didChangeWatchedFiles", None);
+        assert_eq!(policy.level_of("textDocument/didSave", Some(log::Level::Warn)), Some(log::Level::Warn));
+        assert_eq!(policy.level_of("workspace/didChangeWatchedFiles", Some(log::Level::Warn)), None);
+
+        let policy = policy.level(Some(log::Level::Debug));
+        assert_eq!(policy.level_of("textDocument/didSave", Some(log::Level::Warn)), Some(log::Level::Debug));
+        assert_eq!(policy.level_of("$/setTrace", None), Some(log::Level::Debug));
+        assert_eq!(policy.level_of("workspace/didChangeWatchedFiles", Some(log::Level::Warn)), None);
+    }
+
+    #[tokio::test]
+    async fn report() {
+        let unhandled = UnhandledNotifications::default();
+        let mut stream = unhandled.subscribe();
+
+        not_implemented("textDocument/didSave", &json!({ "textDocument": {} }));
+        unhandled.not_found("$/setTrace", Some(json!({ "value": "off" })));
+        unhandled
+            .scope(|| async { not_implemented("textDocument/didSave", &json!({ "textDocument": {} })) })
+            .await;
+        drop(unhandled);
+
+        let reported = stream.by_ref().collect::<Vec<_>>().await;
+        assert_eq!(reported.len(), 2);
+        assert_eq!(reported[0].method(), "$/setTrace");
+        assert_eq!(reported[0].params(), Some(&json!({ "value": "off" })));
+        assert!(!reported[0].is_known());
+        assert_eq!(reported[1].method(), "textDocument/didSave");
+        assert!(reported[1].is_known());
+        assert!(stream.is_terminated());
+    }
+}
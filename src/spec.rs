@@ -0,0 +1,64 @@
+//! Tracking of the optional parts of the LSP specification supported by this build.
+
+/// The version of the Language Server Protocol specification which is fully supported.
+///
+/// Parts of newer, not yet finalized versions of the specification may be available through the
+/// features listed in [`SpecFeature`].
+pub const SPEC_VERSION: &str = "3.16";
+
+/// An optional part of the LSP specification whose availability depends on the enabled crate
+/// features.
+///
+/// Trait methods, client methods and types belonging to such a part are only compiled when the
+/// corresponding crate feature is enabled. This allows tracking additions to the specification
+/// without forcing breaking changes on servers which do not opt in.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[non_exhaustive]
+pub enum SpecFeature {
+    /// Proposed additions to the upcoming specification version, enabled by the `proposed` crate
+    /// feature.
+    ///
+    /// This currently covers inlay hints (`textDocument/inlayHint`, `inlayHint/resolve` and
+    /// `workspace/inlayHint/refresh`) and inline completions (`textDocument/inlineCompletion`).
+    /// Types which `lsp-types` does not provide yet are defined in the `proposed` module.
+    Proposed,
+}
+
+impl SpecFeature {
+    /// All optional parts of the specification known to this version of the crate.
+    pub const ALL: &'static [SpecFeature] = &[SpecFeature::Proposed];
+
+    /// Returns the name of the crate feature which enables this part of the specification.
+    pub const fn name(self) -> &'static str {
+        match self {
+            SpecFeature::Proposed => "proposed",
+        }
+    }
+
+    /// Returns whether this part of the specification is enabled in the current build.
+    pub const fn is_enabled(self) -> bool {
+        match self {
+            SpecFeature::Proposed => cfg!(feature = "proposed"),
+        }
+    }
+}
+
+/// Returns an iterator over the optional parts of the specification enabled in the current build.
+pub fn enabled_features() -> impl Iterator<Item = SpecFeature> {
+    SpecFeature::ALL.iter().copied().filter(|feature| feature.is_enabled())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn proposed() {
+        assert_eq!(SpecFeature::Proposed.name(), "proposed");
+        assert_eq!(SpecFeature::Proposed.is_enabled(), cfg!(feature = "proposed"));
+        assert_eq!(
+            enabled_features().any(|feature| feature == SpecFeature::Proposed),
+            cfg!(feature = "proposed")
+        );
+    }
+}
@@ -0,0 +1,248 @@
+//! Aggregation of `workspace/symbol` results across multiple workspace folders.
+
+mod convert;
+
+pub use self::convert::{flatten_document_symbols, SymbolConverter, WorkspaceLocation, WorkspaceSymbol};
+
+use crate::{
+    jsonrpc::{Error, Result},
+    CancellationToken,
+};
+use futures::future::{self, BoxFuture, Either, FutureExt};
+use std::{
+    cmp::Ordering,
+    fmt::{self, Debug, Formatter},
+    future::Future,
+    sync::Arc,
+};
+
+type SymbolSource = Arc<dyn Fn(String) -> BoxFuture<'static, Result<Vec<lsp::SymbolInformation>>> + Send + Sync>;
+
+/// Helper for servers which answer `workspace/symbol` from one index per workspace folder.
+///
+/// Each folder is backed by a user-provided closure which searches the index of that folder. A
+/// query is fanned out to all folders concurrently, and the results are merged and ranked by how
+/// well the symbol names match the query:
+///
+/// 1. exact matches (ignoring case),
+/// 2. prefix matches,
+/// 3. substring matches,
+/// 4. fuzzy matches, where the characters of the query appear in order,
+/// 5. all other symbols returned by an index.
+///
+/// Within each group, shorter names are ranked first. Folders whose index fails are logged and
+/// skipped, so that one broken index does not hide the results of the others.
+///
+/// # Example
+///
+/// ```rust
+/// # use lspower::{jsonrpc::Result, lsp::*, CancellationToken, WorkspaceSymbolAggregator};
+/// # async fn search(_folder: &str, _query: String) -> Result<Vec<SymbolInformation>> { Ok(vec![]) }
+/// # async fn symbol(params: WorkspaceSymbolParams) -> Result<Option<Vec<SymbolInformation>>> {
+/// let aggregator = WorkspaceSymbolAggregator::new()
+///     .folder(Url::parse("file:///a").unwrap(), |query| search("a", query))
+///     .folder(Url::parse("file:///b").unwrap(), |query| search("b", query))
+///     .limit(128);
+///
+/// aggregator.query(&params.query, CancellationToken::default()).await
+/// # }
+/// ```
+#[derive(Clone, Default)]
+pub struct WorkspaceSymbolAggregator {
+    folders: Vec<(lsp::Url, SymbolSource)>,
+    limit: Option<usize>,
+}
+
+impl WorkspaceSymbolAggregator {
+    /// Creates a new `WorkspaceSymbolAggregator` without any folders.
+    pub fn new() -> Self {
+        WorkspaceSymbolAggregator::default()
+    }
+
+    /// Adds a workspace folder whose symbols are searched with the given closure.
+    ///
+    /// If a folder with the same URI was added before, its closure is replaced.
+    pub fn folder<F, Fut>(mut self, uri: lsp::Url, search: F) -> Self
+    where
+        F: Fn(String) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Vec<lsp::SymbolInformation>>> + Send + 'static,
+    {
+        self.insert_folder(uri, search);
+        self
+    }
+
+    /// Adds a workspace folder, e.g. in response to `workspace/didChangeWorkspaceFolders`.
+    ///
+    /// If a folder with the same URI was added before, its closure is replaced.
+    pub fn insert_folder<F, Fut>(&mut self, uri: lsp::Url, search: F)
+    where
+        F: Fn(String) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Vec<lsp::SymbolInformation>>> + Send + 'static,
+    {
+        let source: SymbolSource = Arc::new(move |query| search(query).boxed());
+        match self.folders.iter_mut().find(|(folder, _)| *folder == uri) {
+            Some((_, existing)) => *existing = source,
+            None => self.folders.push((uri, source)),
+        }
+    }
+
+    /// Removes a workspace folder, e.g. in response to `workspace/didChangeWorkspaceFolders`.
+    pub fn remove_folder(&mut self, uri: &lsp::Url) {
+        self.folders.retain(|(folder, _)| folder != uri);
+    }
+
+    /// Limits the number of symbols returned from a query.
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Queries all workspace folders concurrently and returns the merged and ranked results.
+    ///
+    /// If `token` is cancelled before all folders answered, this returns a "request cancelled"
+    /// error (`-32800`). Dropping the returned future, as happens when the client cancels the
+    /// `workspace/symbol` request, cancels all outstanding folder queries as well.
+    pub async fn query(&self, query: &str, token: CancellationToken) -> Result<Option<Vec<lsp::SymbolInformation>>> {
+        let searches = self.folders.iter().map(|(uri, search)| {
+            search(query.to_owned()).map(move |result| match result {
+                Ok(symbols) => symbols,
+                Err(error) => {
+                    log::warn!("failed to search symbols in workspace folder {}: {}", uri, error);
+                    Vec::new()
+                },
+            })
+        });
+
+        let symbols = match future::select(future::join_all(searches), token.wait()).await {
+            Either::Left((results, _)) => results.into_iter().flatten().collect(),
+            Either::Right(_) => return Err(Error::request_cancelled()),
+        };
+
+        Ok(Some(rank(query, symbols, self.limit)))
+    }
+}
+
+impl Debug for WorkspaceSymbolAggregator {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let folders: Vec<_> = self.folders.iter().map(|(uri, _)| uri.as_str()).collect();
+        f.debug_struct(stringify!(WorkspaceSymbolAggregator))
+            .field("folders", &folders)
+            .field("limit", &self.limit)
+            .finish()
+    }
+}
+
+/// Sorts `symbols` by how well their names match `query`, keeping at most `limit` of them.
+fn rank(query: &str, symbols: Vec<lsp::SymbolInformation>, limit: Option<usize>) -> Vec<lsp::SymbolInformation> {
+    let query = query.to_lowercase();
+    let mut scored: Vec<_> = symbols
+        .into_iter()
+        .map(|symbol| (score(&query, &symbol.name.to_lowercase()), symbol))
+        .collect();
+
+    // The sort is stable, so symbols with equal rank keep the order of the folders.
+    scored.sort_by(|(a, x), (b, y)| match a.cmp(b) {
+        Ordering::Equal => x.name.len().cmp(&y.name.len()),
+        ordering => ordering,
+    });
+
+    let limit = limit.unwrap_or(usize::MAX);
+    scored.into_iter().take(limit).map(|(_, symbol)| symbol).collect()
+}
+
+/// Returns the rank of a symbol name for the query, where lower is better.
+fn score(query: &str, name: &str) -> u8 {
+    if name == query {
+        0
+    } else if name.starts_with(query) {
+        1
+    } else if name.contains(query) {
+        2
+    } else if is_subsequence(query, name) {
+        3
+    } else {
+        4
+    }
+}
+
+fn is_subsequence(query: &str, name: &str) -> bool {
+    let mut chars = name.chars();
+    query.chars().all(|c| chars.any(|n| n == c))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::TokenCanceller;
+
+    #[allow(deprecated)]
+    fn symbol(name: &str) -> lsp::SymbolInformation {
+        lsp::SymbolInformation {
+            name: name.into(),
+            kind: lsp::SymbolKind::FUNCTION,
+            tags: None,
+            deprecated: None,
+            location: lsp::Location {
+                uri: lsp::Url::parse("inmemory::///test").unwrap(),
+                range: Default::default(),
+            },
+            container_name: None,
+        }
+    }
+
+    fn names(symbols: Option<Vec<lsp::SymbolInformation>>) -> Vec<String> {
+        symbols.unwrap().into_iter().map(|symbol| symbol.name).collect()
+    }
+
+    #[tokio::test]
+    async fn merge_and_rank() {
+        let aggregator = WorkspaceSymbolAggregator::new()
+            .folder(lsp::Url::parse("file:///a").unwrap(), |_| async {
+                Ok(vec![symbol("fmt_foo"), symbol("f_o_o"), symbol("bar")])
+            })
+            .folder(lsp::Url::parse("file:///b").unwrap(), |_| async {
+                Ok(vec![symbol("foobar"), symbol("Foo")])
+            });
+
+        let symbols = aggregator.query("foo", CancellationToken::default()).await.unwrap();
+        assert_eq!(names(symbols), vec!["Foo", "foobar", "fmt_foo", "f_o_o", "bar"]);
+    }
+
+    #[tokio::test]
+    async fn limit_and_failures() {
+        let aggregator = WorkspaceSymbolAggregator::new()
+            .folder(lsp::Url::parse("file:///a").unwrap(), |_| async {
+                Err(Error::internal_error())
+            })
+            .folder(lsp::Url::parse("file:///b").unwrap(), |query| async move {
+                Ok(vec![symbol("other"), symbol(&query)])
+            })
+            .limit(1);
+
+        let symbols = aggregator.query("foo", CancellationToken::default()).await.unwrap();
+        assert_eq!(names(symbols), vec!["foo"]);
+    }
+
+    #[tokio::test]
+    async fn remove_folder() {
+        let uri = lsp::Url::parse("file:///a").unwrap();
+        let mut aggregator =
+            WorkspaceSymbolAggregator::new().folder(uri.clone(), |_| async { Ok(vec![symbol("foo")]) });
+        aggregator.remove_folder(&uri);
+
+        let symbols = aggregator.query("foo", CancellationToken::default()).await.unwrap();
+        assert!(names(symbols).is_empty());
+    }
+
+    #[tokio::test]
+    async fn cancellation() {
+        let aggregator = WorkspaceSymbolAggregator::new()
+            .folder(lsp::Url::parse("file:///a").unwrap(), |_| future::pending());
+
+        let mut canceller = TokenCanceller::new();
+        let token = canceller.token();
+        canceller.cancel();
+        let result = aggregator.query("foo", token).await;
+        assert_eq!(result, Err(Error::request_cancelled()));
+    }
+}
@@ -0,0 +1,314 @@
+//! Conversions between the symbol representations of different versions of the specification.
+
+use lsp::{OneOf, SymbolTag};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// The location of a [`WorkspaceSymbol`] without a range, which the client resolves with a
+/// `workspaceSymbol/resolve` request.
+///
+/// @since 3.17.0
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+pub struct WorkspaceLocation {
+    /// The URI of the document containing the symbol.
+    pub uri: lsp::Url,
+}
+
+/// A symbol found by a `workspace/symbol` request, which may omit the range of its location.
+///
+/// This replaces [`SymbolInformation`] in `workspace/symbol` responses, which `lsp-types` does not
+/// provide yet. Backends can use it as their single representation of workspace symbols and
+/// convert it with [`SymbolConverter::workspace_symbols`] for the client at hand.
+///
+/// @since 3.17.0
+///
+/// [`SymbolInformation`]: lsp::SymbolInformation
+#[derive(Clone, Debug, Eq, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WorkspaceSymbol {
+    /// The name of this symbol.
+    pub name: String,
+
+    /// The kind of this symbol.
+    pub kind: lsp::SymbolKind,
+
+    /// Tags for this symbol.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<SymbolTag>>,
+
+    /// The name of the symbol containing this symbol, for display purposes only.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub container_name: Option<String>,
+
+    /// The location of this symbol, whose range may be omitted if the client supports resolving
+    /// it lazily.
+    pub location: OneOf<lsp::Location, WorkspaceLocation>,
+
+    /// A data entry field that is preserved between a `workspace/symbol` request and a
+    /// `workspaceSymbol/resolve` request.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+impl From<lsp::SymbolInformation> for WorkspaceSymbol {
+    /// Converts the symbol, replacing its deprecated `deprecated` field with the equivalent tag.
+    #[allow(deprecated)]
+    fn from(symbol: lsp::SymbolInformation) -> Self {
+        WorkspaceSymbol {
+            tags: with_deprecated_tag(symbol.tags, symbol.deprecated),
+            name: symbol.name,
+            kind: symbol.kind,
+            container_name: symbol.container_name,
+            location: OneOf::Left(symbol.location),
+            data: None,
+        }
+    }
+}
+
+impl From<WorkspaceSymbol> for lsp::SymbolInformation {
+    /// Converts the symbol, locating symbols without a range at the start of their document.
+    #[allow(deprecated)]
+    fn from(symbol: WorkspaceSymbol) -> Self {
+        let location = match symbol.location {
+            OneOf::Left(location) => location,
+            OneOf::Right(WorkspaceLocation { uri }) => lsp::Location::new(uri, lsp::Range::default()),
+        };
+        lsp::SymbolInformation {
+            name: symbol.name,
+            kind: symbol.kind,
+            tags: symbol.tags,
+            deprecated: None,
+            location,
+            container_name: symbol.container_name,
+        }
+    }
+}
+
+/// Flattens a hierarchy of document symbols in the given document into a list of symbols, in
+/// pre-order.
+///
+/// The name of the parent of each symbol becomes its container name, and the deprecated
+/// `deprecated` fields are replaced with the equivalent tag.
+pub fn flatten_document_symbols(uri: &lsp::Url, symbols: Vec<lsp::DocumentSymbol>) -> Vec<lsp::SymbolInformation> {
+    let mut flat = Vec::new();
+    flatten(uri, symbols, None, &mut flat);
+    flat
+}
+
+#[allow(deprecated)]
+fn flatten(
+    uri: &lsp::Url,
+    symbols: Vec<lsp::DocumentSymbol>,
+    container: Option<&str>,
+    flat: &mut Vec<lsp::SymbolInformation>,
+) {
+    for symbol in symbols {
+        flat.push(lsp::SymbolInformation {
+            name: symbol.name.clone(),
+            kind: symbol.kind,
+            tags: with_deprecated_tag(symbol.tags, symbol.deprecated),
+            deprecated: None,
+            location: lsp::Location::new(uri.clone(), symbol.range),
+            container_name: container.map(Into::into),
+        });
+        if let Some(children) = symbol.children {
+            flatten(uri, children, Some(&symbol.name), flat);
+        }
+    }
+}
+
+/// Adds the deprecated tag to the given tags if the deprecated `deprecated` field is set.
+fn with_deprecated_tag(tags: Option<Vec<SymbolTag>>, deprecated: Option<bool>) -> Option<Vec<SymbolTag>> {
+    match (tags, deprecated) {
+        (Some(tags), Some(true)) if tags.contains(&SymbolTag::DEPRECATED) => Some(tags),
+        (tags, Some(true)) => Some(tags.into_iter().flatten().chain(Some(SymbolTag::DEPRECATED)).collect()),
+        (tags, _) => tags,
+    }
+}
+
+/// Adapts symbols from a single representation to the capabilities of the client, so that a
+/// backend can serve clients implementing different versions of the specification.
+///
+/// Backends represent document symbols as a hierarchy of [`DocumentSymbol`]s and workspace
+/// symbols as [`WorkspaceSymbol`]s, marking deprecated symbols with [`SymbolTag::DEPRECATED`]. The
+/// converter then
+///
+/// * flattens document symbols for clients without `hierarchicalDocumentSymbolSupport`,
+/// * replaces the deprecated tag with the `deprecated` field for clients without `tagSupport`,
+/// * converts workspace symbols to [`SymbolInformation`], as `workspace/symbol` responses of
+///   `lsp-types` require.
+///
+/// # Example
+///
+/// ```rust
+/// # use lspower::{jsonrpc::Result, lsp::*, Client, SymbolConverter};
+/// # fn index(_: &Url) -> Vec<DocumentSymbol> { vec![] }
+/// # type Response = Result<Option<DocumentSymbolResponse>>;
+/// # async fn document_symbol(client: &Client, params: DocumentSymbolParams) -> Response {
+/// let capabilities = client.client_capabilities().unwrap_or_default();
+/// let uri = params.text_document.uri;
+/// let symbols = index(&uri);
+/// Ok(Some(SymbolConverter::new(&capabilities).document_symbols(&uri, symbols)))
+/// # }
+/// ```
+///
+/// [`DocumentSymbol`]: lsp::DocumentSymbol
+/// [`SymbolInformation`]: lsp::SymbolInformation
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SymbolConverter {
+    hierarchical: bool,
+    document_tags: bool,
+    workspace_tags: bool,
+}
+
+impl SymbolConverter {
+    /// Creates a converter for a client with the given capabilities.
+    pub fn new(capabilities: &lsp::ClientCapabilities) -> Self {
+        let document = capabilities
+            .text_document
+            .as_ref()
+            .and_then(|text_document| text_document.document_symbol.as_ref());
+        let workspace = capabilities
+            .workspace
+            .as_ref()
+            .and_then(|workspace| workspace.symbol.as_ref());
+        SymbolConverter {
+            hierarchical: document.and_then(|c| c.hierarchical_document_symbol_support) == Some(true),
+            document_tags: document.and_then(|c| c.tag_support.as_ref()).is_some(),
+            workspace_tags: workspace.and_then(|c| c.tag_support.as_ref()).is_some(),
+        }
+    }
+
+    /// Converts the document symbols of the given document into a `textDocument/documentSymbol`
+    /// response the client supports.
+    #[allow(deprecated)]
+    pub fn document_symbols(
+        &self,
+        uri: &lsp::Url,
+        mut symbols: Vec<lsp::DocumentSymbol>,
+    ) -> lsp::DocumentSymbolResponse {
+        if self.hierarchical {
+            self.adapt_nested(&mut symbols);
+            return lsp::DocumentSymbolResponse::Nested(symbols);
+        }
+
+        let mut symbols = flatten_document_symbols(uri, symbols);
+        for symbol in &mut symbols {
+            self.adapt(self.document_tags, &mut symbol.tags, &mut symbol.deprecated);
+        }
+        lsp::DocumentSymbolResponse::Flat(symbols)
+    }
+
+    /// Converts workspace symbols into a `workspace/symbol` response the client supports.
+    ///
+    /// Symbols without a range are located at the start of their document.
+    #[allow(deprecated)]
+    pub fn workspace_symbols(&self, symbols: Vec<WorkspaceSymbol>) -> Vec<lsp::SymbolInformation> {
+        let mut symbols: Vec<_> = symbols.into_iter().map(lsp::SymbolInformation::from).collect();
+        for symbol in &mut symbols {
+            self.adapt(self.workspace_tags, &mut symbol.tags, &mut symbol.deprecated);
+        }
+        symbols
+    }
+
+    #[allow(deprecated)]
+    fn adapt_nested(&self, symbols: &mut [lsp::DocumentSymbol]) {
+        for symbol in symbols {
+            self.adapt(self.document_tags, &mut symbol.tags, &mut symbol.deprecated);
+            if let Some(children) = &mut symbol.children {
+                self.adapt_nested(children);
+            }
+        }
+    }
+
+    /// Marks a deprecated symbol with the tag if the client supports tags, or with the deprecated
+    /// `deprecated` field otherwise.
+    fn adapt(&self, supported: bool, tags: &mut Option<Vec<SymbolTag>>, deprecated: &mut Option<bool>) {
+        let all = with_deprecated_tag(tags.take(), deprecated.take());
+        if supported {
+            *tags = all;
+        } else if all.is_some_and(|all| all.contains(&SymbolTag::DEPRECATED)) {
+            *deprecated = Some(true);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[allow(deprecated)]
+    fn document_symbol(name: &str, line: u32, children: Option<Vec<lsp::DocumentSymbol>>) -> lsp::DocumentSymbol {
+        let range = lsp::Range::new(lsp::Position::new(line, 0), lsp::Position::new(line + 1, 0));
+        lsp::DocumentSymbol {
+            name: name.into(),
+            detail: None,
+            kind: lsp::SymbolKind::FUNCTION,
+            tags: None,
+            deprecated: None,
+            range,
+            selection_range: range,
+            children,
+        }
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn document_symbols() {
+        let uri = lsp::Url::parse("file:///main.rs").unwrap();
+        let mut old = document_symbol("old", 1, None);
+        old.deprecated = Some(true);
+        let symbols = vec![document_symbol("outer", 0, Some(vec![old])), document_symbol("main", 2, None)];
+
+        let legacy = SymbolConverter::default();
+        let flat = match legacy.document_symbols(&uri, symbols.clone()) {
+            lsp::DocumentSymbolResponse::Flat(flat) => flat,
+            response => panic!("unexpected response: {:?}", response),
+        };
+        let names: Vec<_> = flat.iter().map(|s| (s.name.as_str(), s.container_name.as_deref())).collect();
+        assert_eq!(names, vec![("outer", None), ("old", Some("outer")), ("main", None)]);
+        assert_eq!(flat[1].location.range.start.line, 1);
+        assert_eq!((flat[1].tags.clone(), flat[1].deprecated), (None, Some(true)));
+
+        let capabilities = serde_json::from_value(json!({
+            "textDocument": {
+                "documentSymbol": { "hierarchicalDocumentSymbolSupport": true, "tagSupport": { "valueSet": [1] } }
+            }
+        }))
+        .unwrap();
+        let nested = match SymbolConverter::new(&capabilities).document_symbols(&uri, symbols) {
+            lsp::DocumentSymbolResponse::Nested(nested) => nested,
+            response => panic!("unexpected response: {:?}", response),
+        };
+        let old = &nested[0].children.as_ref().unwrap()[0];
+        assert_eq!((old.tags.clone(), old.deprecated), (Some(vec![SymbolTag::DEPRECATED]), None));
+    }
+
+    #[test]
+    fn workspace_symbols() {
+        let uri = lsp::Url::parse("file:///main.rs").unwrap();
+        let symbol = WorkspaceSymbol {
+            name: "main".into(),
+            kind: lsp::SymbolKind::FUNCTION,
+            tags: Some(vec![SymbolTag::DEPRECATED]),
+            container_name: None,
+            location: OneOf::Right(WorkspaceLocation { uri: uri.clone() }),
+            data: None,
+        };
+        let value = json!({ "name": "main", "kind": 12, "tags": [1], "location": { "uri": "file:///main.rs" } });
+        assert_eq!(serde_json::to_value(&symbol).unwrap(), value);
+        assert_eq!(serde_json::from_value::<WorkspaceSymbol>(value).unwrap(), symbol);
+
+        let converted = SymbolConverter::default().workspace_symbols(vec![symbol]);
+        let information = &converted[0];
+        assert_eq!(information.location, lsp::Location::new(uri, lsp::Range::default()));
+        #[allow(deprecated)]
+        let deprecated = information.deprecated;
+        assert_eq!((information.tags.clone(), deprecated), (None, Some(true)));
+
+        let symbol = WorkspaceSymbol::from(information.clone());
+        assert_eq!(symbol.tags, Some(vec![SymbolTag::DEPRECATED]));
+        assert!(matches!(symbol.location, OneOf::Left(_)));
+    }
+}
@@ -0,0 +1,281 @@
+//! Background tasks tied to the lifetime of an [`LspService`].
+//!
+//! [`LspService`]: crate::LspService
+
+use crate::Clock;
+use futures::{
+    channel::oneshot,
+    executor::ThreadPool,
+    future::{self, AbortHandle, Abortable, BoxFuture, Either, FutureExt, Shared},
+};
+use std::{
+    fmt::{self, Debug, Formatter},
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+        Mutex,
+        OnceLock,
+    },
+    time::Duration,
+};
+
+type BoxTask = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// Function used to hand a background task over to an executor.
+pub(crate) type SpawnFn = Arc<dyn Fn(BoxTask) + Send + Sync>;
+
+struct Task {
+    handle: AbortHandle,
+    finished: Shared<BoxFuture<'static, ()>>,
+}
+
+/// Background tasks spawned by the server, aborted once the service exits.
+pub(crate) struct BackgroundTasks {
+    spawn: SpawnFn,
+    clock: Arc<dyn Clock>,
+    tasks: Mutex<Vec<Task>>,
+    aborted: AtomicBool,
+}
+
+impl BackgroundTasks {
+    pub(crate) fn new(spawn: Option<SpawnFn>, clock: Arc<dyn Clock>) -> Self {
+        BackgroundTasks {
+            spawn: spawn.unwrap_or_else(|| Arc::new(spawn_default)),
+            clock,
+            tasks: Mutex::new(Vec::new()),
+            aborted: AtomicBool::new(false),
+        }
+    }
+
+    pub(crate) fn spawn<F>(&self, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        if self.aborted.load(Ordering::SeqCst) {
+            log::debug!("service has exited, dropping background task");
+            return;
+        }
+
+        let (handle, registration) = AbortHandle::new_pair();
+        let (tx, rx) = oneshot::channel::<()>();
+        let task = Abortable::new(task, registration).map(move |_| drop(tx));
+        let finished = rx.map(|_| ()).boxed().shared();
+
+        let mut tasks = self.tasks.lock().unwrap();
+        tasks.retain(|task| task.finished.peek().is_none());
+        tasks.push(Task { handle, finished });
+        drop(tasks);
+
+        (self.spawn)(Box::pin(task));
+    }
+
+    /// Waits for all background tasks to finish, aborting the remaining ones after `timeout`.
+    pub(crate) async fn join(&self, timeout: Duration) {
+        let finished: Vec<_> = {
+            let tasks = self.tasks.lock().unwrap();
+            tasks.iter().map(|task| task.finished.clone()).collect()
+        };
+
+        if finished.is_empty() {
+            return;
+        }
+
+        let all = future::join_all(finished);
+        let timeout = self.clock.sleep(timeout);
+        if let Either::Right(_) = future::select(all, timeout).await {
+            log::warn!("background tasks did not finish in time, aborting them");
+            for task in self.tasks.lock().unwrap().iter() {
+                task.handle.abort();
+            }
+        }
+    }
+
+    /// Aborts all background tasks and refuses to spawn new ones.
+    pub(crate) fn abort_all(&self) {
+        self.aborted.store(true, Ordering::SeqCst);
+        self.abort_running();
+    }
+
+    /// Aborts all background tasks, but keeps accepting new ones.
+    pub(crate) fn abort_running(&self) {
+        for task in self.tasks.lock().unwrap().drain(..) {
+            task.handle.abort();
+        }
+    }
+
+    fn len(&self) -> usize {
+        let tasks = self.tasks.lock().unwrap();
+        tasks.iter().filter(|task| task.finished.peek().is_none()).count()
+    }
+}
+
+impl Debug for BackgroundTasks {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct(stringify!(BackgroundTasks))
+            .field("tasks", &self.len())
+            .field("aborted", &self.aborted)
+            .finish()
+    }
+}
+
+/// The number of threads running background tasks outside of a Tokio runtime.
+const POOL_SIZE: usize = 4;
+
+/// Spawns the task onto the current Tokio runtime, if any, or onto the shared pool otherwise.
+#[cfg(feature = "runtime-tokio")]
+fn spawn_default(task: BoxTask) {
+    match tokio::runtime::Handle::try_current() {
+        Ok(handle) => drop(handle.spawn(task)),
+        Err(_) => spawn_pooled(task),
+    }
+}
+
+/// Spawns the task onto the shared pool.
+#[cfg(all(feature = "runtime-agnostic", not(feature = "runtime-tokio")))]
+fn spawn_default(task: BoxTask) {
+    spawn_pooled(task)
+}
+
+/// Spawns the task onto a small pool of threads shared by all services, created on first use.
+fn spawn_pooled(task: BoxTask) {
+    static POOL: OnceLock<Option<ThreadPool>> = OnceLock::new();
+    let pool = POOL.get_or_init(|| {
+        let pool = ThreadPool::builder()
+            .pool_size(POOL_SIZE)
+            .name_prefix("lspower-background-")
+            .create();
+        pool.map_err(|error| log::error!("failed to create background task pool: {}", error))
+            .ok()
+    });
+    match pool {
+        Some(pool) => pool.spawn_ok(task),
+        None => log::error!("failed to spawn background task without a thread pool"),
+    }
+}
+
+/// Handle for spawning background tasks tied to the lifetime of an [`LspService`].
+///
+/// Background tasks, such as indexers or file watchers, are awaited when the client requests a
+/// `shutdown`, so that the server can finish outstanding work before the response is sent. Tasks
+/// still running after the timeout set with [`LspServiceBuilder::shutdown_timeout`] are aborted.
+/// All remaining tasks are aborted once the `exit` notification is received, and tasks spawned
+/// afterwards are dropped without being run.
+///
+/// By default, tasks are spawned onto the current Tokio runtime when the `runtime-tokio` feature
+/// is enabled, and onto a small pool of threads otherwise, so tasks should not block. A different executor can be set with
+/// [`LspServiceBuilder::spawner`].
+///
+/// This is obtained from [`LspService::spawner`]. Tasks can also be spawned from within the server
+/// with [`Client::spawn_background`].
+///
+/// [`LspService`]: crate::LspService
+/// [`LspService::spawner`]: crate::LspService::spawner
+/// [`LspServiceBuilder::shutdown_timeout`]: crate::LspServiceBuilder::shutdown_timeout
+/// [`LspServiceBuilder::spawner`]: crate::LspServiceBuilder::spawner
+/// [`Client::spawn_background`]: crate::Client::spawn_background
+#[derive(Clone, Debug)]
+pub struct Spawner {
+    tasks: Arc<BackgroundTasks>,
+}
+
+impl Spawner {
+    pub(crate) fn new(tasks: Arc<BackgroundTasks>) -> Self {
+        Spawner { tasks }
+    }
+
+    /// Spawns a background task tied to the lifetime of the service.
+    pub fn spawn<F>(&self, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.tasks.spawn(task);
+    }
+}
+
+/// Returns a task which never finishes, along with a receiver which fails once the task is dropped,
+/// e.g. because it was aborted.
+#[cfg(test)]
+pub(crate) fn pending_task() -> (impl Future<Output = ()> + Send + 'static, oneshot::Receiver<()>) {
+    let (tx, rx) = oneshot::channel::<()>();
+    let task = async move {
+        future::pending::<()>().await;
+        drop(tx);
+    };
+    (task, rx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SystemClock;
+    use std::sync::atomic::AtomicUsize;
+
+    #[tokio::test]
+    async fn join() {
+        let tasks = BackgroundTasks::new(None, Arc::new(SystemClock));
+        let counter = Arc::new(AtomicUsize::new(0));
+        for _ in 0 .. 3 {
+            let counter = counter.clone();
+            tasks.spawn(async move {
+                crate::time::sleep(Duration::from_millis(10)).await;
+                counter.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+        tasks.join(Duration::from_secs(5)).await;
+        assert_eq!(counter.load(Ordering::SeqCst), 3);
+        assert_eq!(tasks.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn join_timeout() {
+        let tasks = BackgroundTasks::new(None, Arc::new(SystemClock));
+        tasks.spawn(future::pending());
+        tasks.join(Duration::from_millis(10)).await;
+        tasks.join(Duration::from_secs(5)).await;
+        assert_eq!(tasks.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn join_timeout_mock_clock() {
+        let clock = crate::MockClock::new();
+        let tasks = BackgroundTasks::new(None, Arc::new(clock.clone()));
+        tasks.spawn(future::pending());
+        let mut join = tasks.join(Duration::from_secs(60)).boxed();
+        tokio::task::yield_now().await;
+        assert!((&mut join).now_or_never().is_none());
+        clock.advance(Duration::from_secs(60));
+        join.await;
+        tasks.join(Duration::from_secs(5)).await;
+        assert_eq!(tasks.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn abort_all() {
+        let tasks = BackgroundTasks::new(None, Arc::new(SystemClock));
+        let (task, rx) = pending_task();
+        tasks.spawn(task);
+        tasks.abort_all();
+        assert!(rx.await.is_err());
+
+        let spawned = Arc::new(AtomicBool::new(false));
+        let flag = spawned.clone();
+        tasks.spawn(async move { flag.store(true, Ordering::SeqCst) });
+        tokio::task::yield_now().await;
+        assert!(!spawned.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn custom_spawner() {
+        let spawned = Arc::new(AtomicUsize::new(0));
+        let counter = spawned.clone();
+        let spawn: SpawnFn = Arc::new(move |task| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            futures::executor::block_on(task);
+        });
+        let spawner = Spawner::new(Arc::new(BackgroundTasks::new(Some(spawn), Arc::new(SystemClock))));
+        spawner.spawn(async {});
+        assert_eq!(spawned.load(Ordering::SeqCst), 1);
+    }
+}
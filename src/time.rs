@@ -0,0 +1,171 @@
+//! Runtime-independent timer utilities.
+
+use futures::{
+    channel::oneshot,
+    future::{self, BoxFuture, FutureExt},
+};
+use std::{
+    fmt::{self, Debug, Formatter},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// Waits until the given duration has elapsed.
+#[cfg(feature = "runtime-tokio")]
+pub(crate) async fn sleep(duration: Duration) {
+    tokio::time::sleep(duration).await
+}
+
+/// Waits until the given duration has elapsed.
+#[cfg(all(feature = "runtime-agnostic", not(feature = "runtime-tokio")))]
+pub(crate) async fn sleep(duration: Duration) {
+    futures_timer::Delay::new(duration).await
+}
+
+/// Source of time for timeouts, delays and other time-dependent behavior of the service.
+///
+/// The service uses [`SystemClock`] unless a different clock is set with
+/// [`LspServiceBuilder::clock`]. Tests can use a [`MockClock`] to control the passage of time
+/// without waiting for real timers.
+///
+/// [`LspServiceBuilder::clock`]: crate::LspServiceBuilder::clock
+pub trait Clock: Debug + Send + Sync + 'static {
+    /// Returns the current instant.
+    fn now(&self) -> Instant;
+
+    /// Returns a future which completes once the given duration has elapsed.
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()>;
+}
+
+/// A [`Clock`] backed by the timers of the async runtime selected through the crate features.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        sleep(duration).boxed()
+    }
+}
+
+/// A [`Clock`] whose time only passes when it is advanced manually.
+///
+/// Futures returned from [`Clock::sleep`] complete once the clock was advanced past their deadline.
+/// Clones of a `MockClock` share the same time.
+///
+/// # Example
+///
+/// ```rust
+/// # use lspower::{Clock, MockClock};
+/// # use std::time::Duration;
+/// # futures::executor::block_on(async {
+/// let clock = MockClock::new();
+/// let sleep = clock.sleep(Duration::from_secs(60));
+/// clock.advance(Duration::from_secs(60));
+/// sleep.await;
+/// assert_eq!(clock.elapsed(), Duration::from_secs(60));
+/// # });
+/// ```
+#[derive(Clone)]
+pub struct MockClock {
+    inner: Arc<Mutex<MockClockInner>>,
+}
+
+struct MockClockInner {
+    start: Instant,
+    elapsed: Duration,
+    sleepers: Vec<(Duration, oneshot::Sender<()>)>,
+}
+
+impl MockClock {
+    /// Creates a new `MockClock`, starting at the current instant.
+    pub fn new() -> Self {
+        MockClock {
+            inner: Arc::new(Mutex::new(MockClockInner {
+                start: Instant::now(),
+                elapsed: Duration::default(),
+                sleepers: Vec::new(),
+            })),
+        }
+    }
+
+    /// Advances the clock by the given duration, completing all sleeps whose deadline has passed.
+    pub fn advance(&self, duration: Duration) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.elapsed += duration;
+        let elapsed = inner.elapsed;
+        let (expired, pending) = inner.sleepers.drain(..).partition(|(deadline, _)| *deadline <= elapsed);
+        inner.sleepers = pending;
+        drop(inner);
+
+        for (_, sleeper) in expired {
+            sleeper.send(()).ok();
+        }
+    }
+
+    /// Returns the total duration the clock has been advanced by.
+    pub fn elapsed(&self) -> Duration {
+        self.inner.lock().unwrap().elapsed
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        let inner = self.inner.lock().unwrap();
+        inner.start + inner.elapsed
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        if duration == Duration::default() {
+            return future::ready(()).boxed();
+        }
+
+        let (tx, rx) = oneshot::channel();
+        let mut inner = self.inner.lock().unwrap();
+        let deadline = inner.elapsed + duration;
+        inner.sleepers.push((deadline, tx));
+        rx.map(drop).boxed()
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        MockClock::new()
+    }
+}
+
+impl Debug for MockClock {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let inner = self.inner.lock().unwrap();
+        f.debug_struct(stringify!(MockClock))
+            .field("elapsed", &inner.elapsed)
+            .field("sleepers", &inner.sleepers.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mock_clock() {
+        let clock = MockClock::new();
+        let start = clock.now();
+        let mut short = clock.sleep(Duration::from_secs(1));
+        let mut long = clock.sleep(Duration::from_secs(10));
+        assert!((&mut short).now_or_never().is_none());
+
+        clock.advance(Duration::from_secs(5));
+        assert_eq!(clock.now() - start, Duration::from_secs(5));
+        assert!((&mut short).now_or_never().is_some());
+        assert!((&mut long).now_or_never().is_none());
+
+        clock.advance(Duration::from_secs(5));
+        assert!(long.now_or_never().is_some());
+        assert!(clock.sleep(Duration::default()).now_or_never().is_some());
+    }
+}
@@ -0,0 +1,227 @@
+//! Serving a language server over TLS.
+
+use crate::{ConnectionInfo, ExitReason, LanguageServer, LspService, MessageStream, Server, TransportKind};
+use std::{
+    io,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    net::TcpListener,
+};
+use tokio_rustls::{rustls::ServerConfig, TlsAcceptor};
+
+/// Performs the TLS handshake on the given stream and serves the service over the encrypted
+/// connection until the client sends `exit` or closes the connection.
+///
+/// The stream can be any connected byte stream, such as a `TcpStream` accepted by the caller or
+/// one end of a [`tokio::io::duplex`] pipe. Fails if the handshake fails, and otherwise returns why
+/// the [`Server`] stopped.
+///
+/// Once the server stopped, a `close_notify` alert is sent before the stream is shut down. A client
+/// which closes the connection without sending `close_notify` is reported as
+/// [`ExitReason::TransportClosed`], like a client which does.
+///
+/// This function is only available with the `tls` crate feature enabled.
+///
+/// # Example
+///
+/// ```rust
+/// # use lspower::{jsonrpc::Result, lsp::*, LanguageServer, LspService};
+/// # use lspower::tokio_rustls::{rustls::ServerConfig, TlsAcceptor};
+/// # use std::sync::Arc;
+/// # #[derive(Debug)]
+/// # struct Backend;
+/// # #[lspower::async_trait]
+/// # impl LanguageServer for Backend {
+/// #     async fn initialize(&self, _: InitializeParams) -> Result<InitializeResult> {
+/// #         Ok(InitializeResult::default())
+/// #     }
+/// #     async fn shutdown(&self) -> Result<()> {
+/// #         Ok(())
+/// #     }
+/// # }
+/// # async fn run(config: Arc<ServerConfig>, stream: tokio::net::TcpStream) -> std::io::Result<()> {
+/// let acceptor = TlsAcceptor::from(config);
+/// let (service, messages) = LspService::new(|_| Backend);
+/// let reason = lspower::serve_tls(stream, &acceptor, service, messages).await?;
+/// log::info!("server stopped: {:?}", reason);
+/// # Ok(())
+/// # }
+/// ```
+pub async fn serve_tls<S>(
+    stream: S,
+    acceptor: &TlsAcceptor,
+    service: LspService,
+    messages: MessageStream,
+) -> io::Result<ExitReason>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let stream = acceptor.accept(stream).await?;
+    let (read, write) = tokio::io::split(stream);
+    // Closing the output of the server shuts down the TLS stream, which sends `close_notify`.
+    let server = Server::new(Truncated(read), write).interleave(messages);
+    Ok(server.serve(service).await)
+}
+
+/// Accepts the next TCP connection on the listener and serves a new service over TLS with the given
+/// configuration, as with [`serve_tls`].
+///
+/// The service is created by calling `init` like [`LspService::new_with_connection`], with a
+/// [`ConnectionInfo`] holding the address of the peer. Fails if accepting the connection or the
+/// handshake fails.
+///
+/// This function is only available with the `tls` crate feature enabled.
+pub async fn accept_tls<T, F>(listener: &TcpListener, config: Arc<ServerConfig>, init: F) -> io::Result<ExitReason>
+where
+    F: FnOnce(crate::Client, &ConnectionInfo) -> T,
+    T: LanguageServer,
+{
+    let (stream, peer) = listener.accept().await?;
+    let acceptor = TlsAcceptor::from(config);
+    let connection = ConnectionInfo::new(TransportKind::Tcp).with_peer_addr(peer);
+    let (service, messages) = LspService::new_with_connection(connection, init);
+    serve_tls(stream, &acceptor, service, messages).await
+}
+
+/// Reads from a TLS stream, treating a connection closed without `close_notify` as its end.
+struct Truncated<R>(R);
+
+impl<R: AsyncRead + Unpin> AsyncRead for Truncated<R> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context, buf: &mut ReadBuf) -> Poll<io::Result<()>> {
+        match Pin::new(&mut self.0).poll_read(cx, buf) {
+            Poll::Ready(Err(error)) if error.kind() == io::ErrorKind::UnexpectedEof => {
+                log::warn!("client closed the TLS connection without sending close_notify");
+                Poll::Ready(Ok(()))
+            },
+            poll => poll,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::LanguageServerCodec;
+    use futures::{SinkExt, StreamExt};
+    use serde_json::{json, Value};
+    use tokio::io::AsyncReadExt;
+    use tokio_rustls::{
+        rustls::{
+            pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, ServerName},
+            ClientConfig,
+            RootCertStore,
+        },
+        TlsConnector,
+    };
+    use tokio_util::codec::{Framed, FramedRead, FramedWrite};
+
+    #[derive(Debug)]
+    struct Mock;
+
+    #[async_trait::async_trait]
+    impl LanguageServer for Mock {
+        async fn initialize(&self, _: lsp::InitializeParams) -> crate::jsonrpc::Result<lsp::InitializeResult> {
+            Ok(lsp::InitializeResult::default())
+        }
+
+        async fn shutdown(&self) -> crate::jsonrpc::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn configs() -> (Arc<ServerConfig>, Arc<ClientConfig>) {
+        let certified = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+        let cert = CertificateDer::from(certified.cert.der().to_vec());
+        let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(certified.key_pair.serialize_der()));
+
+        let provider = Arc::new(tokio_rustls::rustls::crypto::ring::default_provider());
+        let server = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_no_client_auth()
+            .with_single_cert(vec![cert.clone()], key)
+            .unwrap();
+
+        let mut roots = RootCertStore::empty();
+        roots.add(cert).unwrap();
+        let client = ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+
+        (Arc::new(server), Arc::new(client))
+    }
+
+    #[tokio::test]
+    async fn serve_tls() {
+        let (server_config, client_config) = configs();
+        let (client_stream, server_stream) = tokio::io::duplex(4096);
+
+        let (service, messages) = LspService::new(|_| Mock);
+        let acceptor = TlsAcceptor::from(server_config);
+        let server = tokio::spawn(async move { super::serve_tls(server_stream, &acceptor, service, messages).await });
+
+        let connector = TlsConnector::from(client_config);
+        let name = ServerName::try_from("localhost").unwrap();
+        let stream = connector.connect(name, client_stream).await.unwrap();
+        let (read, write) = tokio::io::split(stream);
+        let mut reader = FramedRead::new(read, LanguageServerCodec::<Value>::default());
+        let mut writer = FramedWrite::new(write, LanguageServerCodec::<Value>::default());
+
+        let initialize = json!({ "jsonrpc": "2.0", "method": "initialize", "params": { "capabilities": {} }, "id": 1 });
+        writer.send(initialize).await.unwrap();
+        let response = reader.next().await.unwrap().unwrap();
+        assert_eq!(response, json!({ "jsonrpc": "2.0", "result": { "capabilities": {} }, "id": 1 }));
+
+        writer.send(json!({ "jsonrpc": "2.0", "method": "exit" })).await.unwrap();
+        assert_eq!(server.await.unwrap().unwrap(), ExitReason::ClientRequested);
+
+        // The server sent `close_notify`, so the client reads the end of the stream without error.
+        let mut rest = Vec::new();
+        let mut read = reader.into_inner();
+        assert_eq!(read.read_to_end(&mut rest).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn truncated() {
+        let (server_config, client_config) = configs();
+        let (client_stream, server_stream) = tokio::io::duplex(4096);
+
+        let (service, messages) = LspService::new(|_| Mock);
+        let acceptor = TlsAcceptor::from(server_config);
+        let server = tokio::spawn(async move { super::serve_tls(server_stream, &acceptor, service, messages).await });
+
+        let connector = TlsConnector::from(client_config);
+        let name = ServerName::try_from("localhost").unwrap();
+        let stream = connector.connect(name, client_stream).await.unwrap();
+        let mut framed = Framed::new(stream, LanguageServerCodec::<Value>::default());
+
+        let initialize = json!({ "jsonrpc": "2.0", "method": "initialize", "params": { "capabilities": {} }, "id": 1 });
+        framed.send(initialize).await.unwrap();
+        assert!(framed.next().await.unwrap().is_ok());
+        drop(framed.into_inner().into_inner().0);
+
+        assert_eq!(server.await.unwrap().unwrap(), ExitReason::TransportClosed);
+    }
+
+    #[tokio::test]
+    async fn handshake_failure() {
+        let (server_config, _) = configs();
+        let (mut client_stream, server_stream) = tokio::io::duplex(4096);
+
+        let (service, messages) = LspService::new(|_| Mock);
+        let acceptor = TlsAcceptor::from(server_config);
+        let server = tokio::spawn(async move { super::serve_tls(server_stream, &acceptor, service, messages).await });
+
+        let request = json!({ "jsonrpc": "2.0", "method": "initialize", "params": {}, "id": 1 }).to_string();
+        let message = format!("Content-Length: {}\r\n\r\n{}", request.len(), request);
+        tokio::io::AsyncWriteExt::write_all(&mut client_stream, message.as_bytes()).await.unwrap();
+
+        assert!(server.await.unwrap().is_err());
+    }
+}
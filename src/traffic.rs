@@ -0,0 +1,240 @@
+//! Configurable logging of the raw protocol traffic.
+
+use serde_json::Value;
+use std::{
+    borrow::Cow,
+    fmt::{self, Debug, Formatter},
+    sync::Arc,
+};
+
+/// The direction in which a message travels over the transport.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Direction {
+    /// A message received from the client.
+    Incoming,
+    /// A message sent to the client.
+    Outgoing,
+}
+
+impl Direction {
+    fn arrow(self) -> &'static str {
+        match self {
+            Direction::Incoming => "<-",
+            Direction::Outgoing => "->",
+        }
+    }
+}
+
+type Redaction = Arc<dyn Fn(&mut Value) + Send + Sync>;
+
+/// Logger for the messages read from and written to a [`Server`] transport.
+///
+/// By default, every message is logged verbatim at the `trace` level. Redactions can be registered
+/// in order to strip sensitive data, such as file contents or paths, before a message is logged.
+/// Redactions are only applied when the message would actually be logged.
+///
+/// [`Server`]: crate::Server
+#[derive(Clone)]
+pub struct TrafficLogger {
+    incoming: bool,
+    outgoing: bool,
+    level: log::Level,
+    redactions: Vec<Redaction>,
+}
+
+impl TrafficLogger {
+    /// Creates a new `TrafficLogger` which logs messages in both directions at the `trace` level.
+    pub fn new() -> Self {
+        TrafficLogger {
+            incoming: true,
+            outgoing: true,
+            level: log::Level::Trace,
+            redactions: Vec::new(),
+        }
+    }
+
+    /// Enables or disables logging of messages received from the client.
+    pub fn incoming(mut self, enabled: bool) -> Self {
+        self.incoming = enabled;
+        self
+    }
+
+    /// Enables or disables logging of messages sent to the client.
+    pub fn outgoing(mut self, enabled: bool) -> Self {
+        self.outgoing = enabled;
+        self
+    }
+
+    /// Sets the level at which messages are logged.
+    pub fn level(mut self, level: log::Level) -> Self {
+        self.level = level;
+        self
+    }
+
+    /// Registers a redaction which may modify each message before it is logged.
+    ///
+    /// Redactions are applied in the order in which they were registered.
+    pub fn redact<F>(mut self, redaction: F) -> Self
+    where
+        F: Fn(&mut Value) + Send + Sync + 'static,
+    {
+        self.redactions.push(Arc::new(redaction));
+        self
+    }
+
+    /// Truncates the contents of every `text` field to at most `max_len` characters.
+    ///
+    /// This keeps the contents of documents sent with `textDocument/didOpen` and
+    /// `textDocument/didChange` out of the logs.
+    pub fn truncate_text(self, max_len: usize) -> Self {
+        self.redact(move |value| {
+            visit_strings(value, &mut |key, text| {
+                if key == "text" && text.chars().count() > max_len {
+                    let mut truncated: String = text.chars().take(max_len).collect();
+                    truncated.push_str("...");
+                    *text = truncated;
+                }
+            })
+        })
+    }
+
+    /// Replaces every URI with a hash of its contents.
+    ///
+    /// This applies to every `uri` field and to every field whose name ends with `Uri`. Equal URIs
+    /// map to equal hashes, so messages concerning the same document can still be correlated. The
+    /// hash is 64-bit FNV-1a, which does not change between runs or releases, so logs of different
+    /// sessions can be correlated too. It is not a cryptographic hash: URIs which can be guessed can
+    /// be recovered from their hashes.
+    pub fn hash_uris(self) -> Self {
+        self.redact(|value| {
+            visit_strings(value, &mut |key, uri| {
+                if key == "uri" || key.ends_with("Uri") {
+                    *uri = format!("uri#{:016x}", fnv1a(uri.as_bytes()));
+                }
+            })
+        })
+    }
+
+    fn is_enabled(&self, direction: Direction) -> bool {
+        match direction {
+            Direction::Incoming => self.incoming,
+            Direction::Outgoing => self.outgoing,
+        }
+    }
+
+    /// Logs the given message if logging is enabled for its direction.
+    pub(crate) fn log(&self, direction: Direction, message: &str) {
+        if self.is_enabled(direction) && log::log_enabled!(self.level) {
+            log::log!(self.level, "{} {}", direction.arrow(), self.apply(message));
+        }
+    }
+
+    fn apply<'a>(&self, message: &'a str) -> Cow<'a, str> {
+        if self.redactions.is_empty() {
+            return Cow::Borrowed(message);
+        }
+
+        match serde_json::from_str::<Value>(message) {
+            Ok(mut value) => {
+                for redaction in &self.redactions {
+                    redaction(&mut value);
+                }
+                Cow::Owned(value.to_string())
+            },
+            Err(_) => Cow::Borrowed("<redacted: malformed message>"),
+        }
+    }
+}
+
+impl Debug for TrafficLogger {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct(stringify!(TrafficLogger))
+            .field("incoming", &self.incoming)
+            .field("outgoing", &self.outgoing)
+            .field("level", &self.level)
+            .field("redactions", &self.redactions.len())
+            .finish()
+    }
+}
+
+impl Default for TrafficLogger {
+    fn default() -> Self {
+        TrafficLogger::new()
+    }
+}
+
+/// Calls `f` with the key and value of every string field found in `value`, recursively.
+fn visit_strings(value: &mut Value, f: &mut dyn FnMut(&str, &mut String)) {
+    match value {
+        Value::Array(values) => values.iter_mut().for_each(|value| visit_strings(value, f)),
+        Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                match value {
+                    Value::String(string) => f(key, string),
+                    value => visit_strings(value, f),
+                }
+            }
+        },
+        _ => {},
+    }
+}
+
+/// Returns the 64-bit FNV-1a hash of the given bytes.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const DID_OPEN: &str = r#"{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":{"uri":"file:///secret.rs","languageId":"rust","version":1,"text":"fn main() {}"}}}"#;
+
+    #[test]
+    fn apply_without_redactions() {
+        let logger = TrafficLogger::new();
+        assert_eq!(logger.apply(DID_OPEN), DID_OPEN);
+    }
+
+    #[test]
+    fn hash_uris() {
+        let logger = TrafficLogger::new().hash_uris();
+        let value: Value = serde_json::from_str(&logger.apply(DID_OPEN)).unwrap();
+        let uri = value["params"]["textDocument"]["uri"].as_str().unwrap();
+        assert!(uri.starts_with("uri#"));
+        assert!(!uri.contains("secret"));
+        assert_eq!(uri, format!("uri#{:016x}", fnv1a(b"file:///secret.rs")));
+        assert_eq!(fnv1a(b"a"), 0xaf63_dc4c_8601_ec8c);
+    }
+
+    #[test]
+    fn malformed_message() {
+        let logger = TrafficLogger::new().hash_uris();
+        assert_eq!(logger.apply(r#"{"uri":"#), "<redacted: malformed message>");
+    }
+
+    #[test]
+    fn redact() {
+        let logger = TrafficLogger::new().redact(|value| value["params"] = Value::Null);
+        let value: Value = serde_json::from_str(&logger.apply(DID_OPEN)).unwrap();
+        assert_eq!(value["params"], Value::Null);
+    }
+
+    #[test]
+    fn truncate_text() {
+        let logger = TrafficLogger::new().truncate_text(2);
+        let value: Value = serde_json::from_str(&logger.apply(DID_OPEN)).unwrap();
+        assert_eq!(value["params"]["textDocument"]["text"], json!("fn..."));
+        assert_eq!(value["params"]["textDocument"]["uri"], json!("file:///secret.rs"));
+    }
+
+    #[test]
+    fn toggles() {
+        let logger = TrafficLogger::new().incoming(false);
+        assert!(!logger.is_enabled(Direction::Incoming));
+        assert!(logger.is_enabled(Direction::Outgoing));
+    }
+}
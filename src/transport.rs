@@ -1,5 +1,22 @@
 //! `tower` server which multiplexes bidirectional traffic over one connection.
 
+mod builder;
+mod decode;
+mod group;
+mod stdio;
+mod watchdog;
+mod write;
+
+pub use self::{
+    builder::ServerBuilder,
+    decode::{DecodeErrorAction, DecodeErrorPolicy},
+    group::{GroupExit, ServerGroup},
+    stdio::{Stdin, Stdout},
+    watchdog::{Watchdog, WatchdogEvent},
+    write::WriteErrorPolicy,
+};
+use self::{watchdog::WatchdogState, write::Reconnector};
+
 #[cfg(feature = "runtime-agnostic")]
 use async_codec_lite::{FramedRead, FramedWrite};
 #[cfg(feature = "runtime-agnostic")]
@@ -10,20 +27,27 @@ use tokio::io::{AsyncRead, AsyncWrite};
 #[cfg(feature = "runtime-tokio")]
 use tokio_util::codec::{FramedRead, FramedWrite};
 
+#[cfg(feature = "compression")]
+use super::codec::Compression;
 use super::{
-    codec::LanguageServerCodec,
-    jsonrpc::{self, Incoming, Outgoing, Response},
+    codec::{FrameInspector, LanguageServerCodec, ParseError},
+    jsonrpc::{self, ClientRequest, Incoming, Outgoing, Response},
+    traffic::TrafficLogger,
+    ExitedError,
 };
 use futures::{
-    channel::mpsc,
+    channel::{mpsc, oneshot},
     future::{self, Either, FutureExt, TryFutureExt},
-    sink::SinkExt,
-    stream::{self, Empty, Stream, StreamExt},
+    sink::{Sink, SinkExt},
+    stream::{self, Empty, Select, Stream, StreamExt},
 };
 use std::{
     error::Error,
+    future::Future,
+    io,
     pin::Pin,
     task::{Context, Poll},
+    time::Duration,
 };
 use tower_service::Service;
 
@@ -33,6 +57,83 @@ pub struct Server<I, O, S = Nothing> {
     stdin: I,
     stdout: O,
     interleave: S,
+    logger: TrafficLogger,
+    watchdog: Option<Watchdog>,
+    drain_timeout: Duration,
+    response_order: ResponseOrder,
+    decode_errors: DecodeErrorPolicy,
+    #[cfg(feature = "compression")]
+    compression: Option<Compression>,
+    inspector: Option<FrameInspector>,
+    max_frame_size: Option<usize>,
+    flush: FlushStrategy,
+    write_errors: WriteErrorPolicy,
+    reconnect: Option<Reconnector<I, O>>,
+    exit_on_eof: bool,
+}
+
+/// The order in which the [`Server`] writes responses to `stdout`, set with
+/// [`ServerBuilder::response_order`].
+///
+/// In either case, up to four request handlers run concurrently. Messages interleaved into the
+/// output, such as notifications and requests from the server to the client, are written as soon
+/// as they are available, regardless of the order of the responses.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum ResponseOrder {
+    /// Responses are written as soon as their handlers complete, as the protocol permits, so that
+    /// a slow request does not hold up the responses to later ones. This must be opted into.
+    Completion,
+    /// Responses are buffered and written in the order their requests were received, for clients
+    /// which assume that responses arrive in request order. This is the default, and matches the
+    /// behavior of servers built before the order was configurable.
+    #[default]
+    Received,
+}
+
+/// When the [`Server`] flushes the messages written to `stdout`, set with
+/// [`ServerBuilder::flush`].
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum FlushStrategy {
+    /// Messages are flushed once no further message is ready to be written, so that bursts of
+    /// messages, such as the diagnostics of many documents, are written together.
+    #[default]
+    Coalesced,
+    /// Each message is flushed as soon as it was written, for clients which measure latency or
+    /// transports which hold back partial writes.
+    Immediate,
+}
+
+/// The reason why a [`Server`] stopped serving, returned by [`Server::serve`].
+///
+/// Supervisors running the server in-process can use it to decide whether to serve a new service,
+/// and after which delay. Only [`ExitReason::ClientRequested`] is part of the regular protocol
+/// lifecycle. The exit code recommended for the server process, which also depends on whether the
+/// client sent a `shutdown` request, is reported by [`LspService::exit_code`].
+///
+/// [`LspService::exit_code`]: crate::LspService::exit_code
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ExitReason {
+    /// The client sent the `exit` notification.
+    ClientRequested,
+    /// The connection closed before the client sent the `exit` notification.
+    TransportClosed,
+    /// The [`Watchdog`] exited the service on behalf of a client which did not follow the protocol
+    /// lifecycle in time.
+    ///
+    /// A closed input stream is reported as [`ExitReason::TransportClosed`] instead.
+    Watchdog(WatchdogEvent),
+    /// A message failed to decode, with the given error, and the [`DecodeErrorPolicy`] of the
+    /// server terminates the connection in this case.
+    DecodeError(String),
+    /// The service failed to become ready, with the given error.
+    InternalError(String),
+}
+
+impl ExitReason {
+    /// Returns whether the server exited as requested by the client.
+    pub fn is_graceful(&self) -> bool {
+        *self == ExitReason::ClientRequested
+    }
 }
 
 impl<I, O> Server<I, O, Nothing>
@@ -40,12 +141,79 @@ where
     I: AsyncRead + Unpin,
     O: AsyncWrite,
 {
+    /// Returns a builder for a `Server` reading messages from `read` and writing messages to
+    /// `write`, such as the two halves of a split socket.
+    ///
+    /// See [`ServerBuilder`] for details.
+    pub fn builder(read: I, write: O) -> ServerBuilder<I, O> {
+        ServerBuilder::new(Server::new(read, write))
+    }
+
     /// Creates a new `Server` with the given `stdin` and `stdout` handles.
     pub fn new(stdin: I, stdout: O) -> Self {
         Server {
             stdin,
             stdout,
             interleave: Nothing::new(),
+            logger: TrafficLogger::default(),
+            watchdog: None,
+            drain_timeout: Duration::from_secs(5),
+            response_order: ResponseOrder::default(),
+            decode_errors: DecodeErrorPolicy::default(),
+            #[cfg(feature = "compression")]
+            compression: None,
+            inspector: None,
+            max_frame_size: None,
+            flush: FlushStrategy::default(),
+            write_errors: WriteErrorPolicy::default(),
+            reconnect: None,
+            exit_on_eof: false,
+        }
+    }
+}
+
+impl Server<Stdin, Stdout, Nothing> {
+    /// Creates a new `Server` communicating over the standard input and output of the process.
+    ///
+    /// Unlike the `stdin` and `stdout` handles of the runtime, which block threads of its pool,
+    /// both are served by dedicated threads, which never delay the shutdown of the runtime. The
+    /// reading thread also tolerates a `stdin` in non-blocking mode, as some editors pass it. See
+    /// [`Stdin`] and [`Stdout`] for details.
+    ///
+    /// Once the client closes `stdin` without sending the `exit` notification, e.g. because the
+    /// editor crashed, the server sends the `exit` notification to the service on its behalf, so
+    /// that it stops like after a regular exit, and reports [`ExitReason::TransportClosed`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the operating system fails to create the threads.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use lspower::{jsonrpc::Result, lsp::*, LanguageServer, LspService, Server};
+    /// # #[derive(Debug)]
+    /// # struct Backend;
+    /// # #[lspower::async_trait]
+    /// # impl LanguageServer for Backend {
+    /// #     async fn initialize(&self, _: InitializeParams) -> Result<InitializeResult> {
+    /// #         Ok(InitializeResult::default())
+    /// #     }
+    /// #     async fn shutdown(&self) -> Result<()> {
+    /// #         Ok(())
+    /// #     }
+    /// # }
+    /// # async fn run() {
+    /// let (service, messages) = LspService::new(|_| Backend);
+    /// Server::stdio().interleave(messages).serve(service).await;
+    /// # }
+    /// ```
+    pub fn stdio() -> Self {
+        let stdin = Stdin::new().expect("failed to spawn the thread reading stdin");
+        let stdout = Stdout::new().expect("failed to spawn the thread writing stdout");
+        Server {
+            exit_on_eof: true,
+            ..Server::new(stdin, stdout)
         }
     }
 }
@@ -65,11 +233,187 @@ where
             stdin: self.stdin,
             stdout: self.stdout,
             interleave: stream,
+            logger: self.logger,
+            watchdog: self.watchdog,
+            drain_timeout: self.drain_timeout,
+            response_order: self.response_order,
+            decode_errors: self.decode_errors,
+            #[cfg(feature = "compression")]
+            compression: self.compression,
+            inspector: self.inspector,
+            max_frame_size: self.max_frame_size,
+            flush: self.flush,
+            write_errors: self.write_errors,
+            reconnect: self.reconnect,
+            exit_on_eof: self.exit_on_eof,
         }
     }
 
+    /// Interleaves the messages sent through the returned handle into `stdout`, in addition to the
+    /// stream already interleaved, buffering at most about `capacity` of them.
+    ///
+    /// Unlike a stream passed to [`interleave`](Server::interleave), whose messages are buffered by
+    /// its producer, the handle makes producers of notifications wait until the buffer has room, so
+    /// that they slow down as the client reads `stdout` more slowly than messages are produced.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use lspower::{lsp::{notification::LogMessage, *}, Server};
+    /// # async fn example(stdin: tokio::io::Stdin, stdout: tokio::io::Stdout) {
+    /// let (server, mut sender) = Server::new(stdin, stdout).interleave_bounded(16);
+    /// let producer = async move {
+    ///     let params = LogMessageParams { typ: MessageType::INFO, message: "indexing".into() };
+    ///     sender.send_notification::<LogMessage>(params).await
+    /// };
+    /// # }
+    /// ```
+    pub fn interleave_bounded(self, capacity: usize) -> (Server<I, O, Interleaved<S>>, InterleaveSender) {
+        let (tx, rx) = mpsc::channel(capacity);
+        let server = Server {
+            stdin: self.stdin,
+            stdout: self.stdout,
+            interleave: Interleaved(stream::select(self.interleave, rx)),
+            logger: self.logger,
+            watchdog: self.watchdog,
+            drain_timeout: self.drain_timeout,
+            response_order: self.response_order,
+            decode_errors: self.decode_errors,
+            #[cfg(feature = "compression")]
+            compression: self.compression,
+            inspector: self.inspector,
+            max_frame_size: self.max_frame_size,
+            flush: self.flush,
+            write_errors: self.write_errors,
+            reconnect: self.reconnect,
+            exit_on_eof: self.exit_on_eof,
+        };
+        (server, InterleaveSender(tx))
+    }
+
+    /// Sets the logger used for the messages read from `stdin` and written to `stdout`.
+    pub fn traffic_logger(mut self, logger: TrafficLogger) -> Self {
+        self.logger = logger;
+        self
+    }
+
+    /// Guards the connection against clients which do not follow the protocol lifecycle.
+    ///
+    /// See [`Watchdog`] for details.
+    pub fn watchdog(mut self, watchdog: Watchdog) -> Self {
+        self.watchdog = Some(watchdog);
+        self
+    }
+
+    /// Sets how long outgoing messages are still written to `stdout` once the server stopped
+    /// reading from `stdin`, defaulting to 5 seconds.
+    ///
+    /// After the `exit` notification, or once `stdin` was closed, the server stops reading and
+    /// writes the pending responses as well as the messages still queued in the interleaved stream,
+    /// such as final diagnostics or log messages, before flushing and closing `stdout`. Messages
+    /// which are not available before the timeout elapses are dropped.
+    pub fn drain_timeout(mut self, timeout: Duration) -> Self {
+        self.drain_timeout = timeout;
+        self
+    }
+
+    /// Sets the order in which responses are written to `stdout`. See [`ResponseOrder`] for the
+    /// default.
+    pub fn response_order(mut self, order: ResponseOrder) -> Self {
+        self.response_order = order;
+        self
+    }
+
+    /// Sets how messages read from `stdin` which fail to decode are handled, defaulting to
+    /// responding with a "parse error".
+    pub fn decode_errors(mut self, policy: DecodeErrorPolicy) -> Self {
+        self.decode_errors = policy;
+        self
+    }
+
+    /// Compresses the messages written to `stdout`, which the client must have agreed to
+    /// out-of-band.
+    ///
+    /// Compressed messages read from `stdin` are decompressed regardless of this setting. See
+    /// [`Compression`] for details.
+    #[cfg(feature = "compression")]
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = Some(compression);
+        self
+    }
+
+    /// Sets how errors writing messages to `stdout` are handled, defaulting to stopping to write
+    /// on the first error.
+    ///
+    /// See [`WriteErrorPolicy`] for details.
+    pub fn write_errors(mut self, policy: WriteErrorPolicy) -> Self {
+        self.write_errors = policy;
+        self
+    }
+
+    /// Continues serving on a new connection returned by the given hook once the connection
+    /// broke, such as when a daemon accepts the next connection of an editor which restarted.
+    ///
+    /// The hook is called once `stdin` was closed or failed to read, or writing to `stdout` failed
+    /// according to the [`write_errors`](Server::write_errors) policy, and returns the read and the
+    /// write half of the new connection. Messages which were not completely written to the broken
+    /// connection are lost. If the hook fails, the server stops as without a hook.
+    ///
+    /// The service, and thereby the backend of the language server, is kept across connections.
+    /// Since a client connecting anew sends another `initialize` request, the hook should start a
+    /// new session of an [`LspService`] with its [`SessionHandle`], which also fails the requests
+    /// still waiting for a response of the previous client.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// # use lspower::{jsonrpc::Result, lsp::*, LanguageServer, LspService, Server};
+    /// # use tokio::net::TcpListener;
+    /// # #[derive(Debug)]
+    /// # struct Backend;
+    /// # #[lspower::async_trait]
+    /// # impl LanguageServer for Backend {
+    /// #     async fn initialize(&self, _: InitializeParams) -> Result<InitializeResult> {
+    /// #         Ok(InitializeResult::default())
+    /// #     }
+    /// #     async fn shutdown(&self) -> Result<()> {
+    /// #         Ok(())
+    /// #     }
+    /// # }
+    /// # async fn example() -> std::io::Result<()> {
+    /// let (service, messages) = LspService::new(|_| Backend);
+    /// let session = service.session();
+    ///
+    /// let listener = std::sync::Arc::new(TcpListener::bind("127.0.0.1:9257").await?);
+    /// let (read, write) = listener.accept().await?.0.into_split();
+    /// let server = Server::new(read, write).interleave(messages).reconnect(move || {
+    ///     let (listener, session) = (listener.clone(), session.clone());
+    ///     async move {
+    ///         let (stream, _) = listener.accept().await?;
+    ///         session.reconnected();
+    ///         Ok(stream.into_split())
+    ///     }
+    /// });
+    /// server.serve(service).await;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`LspService`]: crate::LspService
+    /// [`SessionHandle`]: crate::SessionHandle
+    pub fn reconnect<F, Fut>(mut self, hook: F) -> Self
+    where
+        F: FnMut() -> Fut + Send + 'static,
+        Fut: Future<Output = io::Result<(I, O)>> + Send + 'static,
+    {
+        self.reconnect = Some(Reconnector::new(hook));
+        self
+    }
+
     /// Spawns the service with messages read through `stdin` and responses written to `stdout`.
-    pub async fn serve<T>(self, mut service: T)
+    ///
+    /// Returns why the server stopped once the remaining messages were written.
+    pub async fn serve<T>(self, mut service: T) -> ExitReason
     where
         T: Service<Incoming, Response = Option<Outgoing>> + Send + 'static,
         T::Error: Into<Box<dyn Error + Send + Sync>>,
@@ -77,32 +421,171 @@ where
     {
         let (mut sender, receiver) = mpsc::channel(16);
 
-        let mut framed_stdin = FramedRead::new(self.stdin, LanguageServerCodec::default());
-        let framed_stdout = FramedWrite::new(self.stdout, LanguageServerCodec::default());
-        let responses = receiver.buffered(4).filter_map(future::ready);
+        let codec = LanguageServerCodec::with_logger(self.logger.clone());
+        let codec = codec.with_skipped_bytes(self.decode_errors.skipped_bytes_counter());
+        let codec = codec.with_inspector(self.inspector.clone()).with_max_frame_size(self.max_frame_size);
+        let read_codec = codec.clone();
+        let mut framed_stdin = FramedRead::new(self.stdin, codec);
+        let codec = LanguageServerCodec::with_logger(self.logger).with_inspector(self.inspector);
+        #[cfg(feature = "compression")]
+        let codec = codec.with_compression(self.compression);
+        let write_codec = codec.clone();
+        let framed_stdout = FramedWrite::new(self.stdout, codec);
+        let reconnect = self.reconnect.as_ref();
+        let write_errors = self.write_errors;
+        let flush = self.flush;
+        let responses = match self.response_order {
+            ResponseOrder::Completion => Either::Left(receiver.buffer_unordered(4)),
+            ResponseOrder::Received => Either::Right(receiver.buffered(4)),
+        };
+        let responses = responses.filter_map(future::ready);
         let interleave = self.interleave.fuse();
 
-        let printer = stream::select(responses, interleave)
-            .map(Ok)
-            .forward(framed_stdout.sink_map_err(|e| log::error!("failed to encode message: {}", e)))
-            .map(|_| ());
+        // Once the reader stopped, the remaining messages are drained for at most `drain_timeout`
+        // before the sink is flushed and closed.
+        let (stopped_tx, stopped_rx) = oneshot::channel::<()>();
+        let drain_timeout = self.drain_timeout;
+        let drained = stopped_rx.then(move |_| crate::time::sleep(drain_timeout));
+
+        let messages = stream::select(responses, interleave).take_until(drained);
+        let printer = async move {
+            let mut framed_stdout = Box::pin(framed_stdout);
+            let mut generation = 0;
+            // Messages ready at the same time are flushed together, unless each is flushed on its own.
+            let batches = messages.ready_chunks(16);
+            futures::pin_mut!(batches);
+            while let Some(batch) = batches.next().await {
+                if let Some(stdout) = reconnect.and_then(|reconnect| reconnect.newer_writer(&mut generation)) {
+                    framed_stdout.set(FramedWrite::new(stdout, write_codec.clone()));
+                }
+
+                let mut written = Ok(());
+                for message in batch {
+                    written = write(framed_stdout.as_mut(), Some(message), &write_errors).await;
+                    if written.is_ok() && flush == FlushStrategy::Immediate {
+                        written = write(framed_stdout.as_mut(), None, &write_errors).await;
+                    }
+                    if written.is_err() {
+                        break;
+                    }
+                }
+                if written.is_ok() && flush == FlushStrategy::Coalesced {
+                    written = write(framed_stdout.as_mut(), None, &write_errors).await;
+                }
+
+                if let Err(err) = written {
+                    log::error!("failed to write message: {}", err);
+                    match reconnect {
+                        Some(reconnect) => match reconnect.writer(&mut generation).await {
+                            Ok(stdout) => framed_stdout.set(FramedWrite::new(stdout, write_codec.clone())),
+                            Err(_) => return,
+                        },
+                        None => return,
+                    }
+                }
+            }
+            let _ = framed_stdout.close().await;
+        };
+
+        let mut watchdog = self.watchdog.map(WatchdogState::new);
+        let decode_errors = self.decode_errors;
+        let exit_on_eof = self.exit_on_eof;
 
         let reader = async move {
-            while let Some(msg) = framed_stdin.next().await {
-                let request = match msg {
-                    Ok(req) => req,
-                    Err(err) => {
-                        log::error!("failed to decode message: {}", err);
-                        let response = Response::error(None, jsonrpc::Error::parse_error());
-                        let response_fut = future::ready(Some(Outgoing::Response(response)));
-                        sender.send(Either::Right(response_fut)).await.unwrap();
+            let _stopped = stopped_tx;
+            let mut decode_failed = false;
+            let mut generation = 0;
+
+            loop {
+                // Reading is interrupted once the writer switched to a new connection.
+                let switched = match reconnect {
+                    Some(reconnect) => Either::Left(reconnect.reconnected(generation)),
+                    None => Either::Right(future::pending()),
+                };
+                let read = future::select(framed_stdin.next(), Box::pin(switched)).map(|read| match read {
+                    Either::Left((next, _)) => Some(next),
+                    Either::Right(_) => None,
+                });
+                let next = match &mut watchdog {
+                    Some(watchdog) => match future::select(watchdog.expired().boxed(), read).await {
+                        Either::Left((event, _)) => Err(Some(event)),
+                        Either::Right((next, _)) => Ok(next),
+                    },
+                    None => Ok(read.await),
+                };
+                // Once the connection broke, the reader continues on a new one, if any.
+                let broken = match &next {
+                    Ok(None) => true,
+                    Ok(Some(None)) => !decode_failed,
+                    Ok(Some(Some(Err(err)))) => io_error_kind(err).is_some(),
+                    Ok(Some(Some(Ok(_)))) | Err(_) => false,
+                };
+                if let Some(reconnect) = reconnect.filter(|_| broken) {
+                    if let Ok(stdin) = reconnect.reader(&mut generation).await {
+                        framed_stdin = FramedRead::new(stdin, read_codec.clone());
+                        decode_failed = false;
                         continue;
+                    }
+                }
+
+                let next = next.map(Option::flatten);
+                let next = match next {
+                    // After a message failed to decode, the reader pauses once before reading on.
+                    Ok(None) if decode_failed => {
+                        decode_failed = false;
+                        continue;
+                    },
+                    Ok(next) => next.ok_or_else(|| watchdog.as_ref().and_then(WatchdogState::closed)),
+                    Err(event) => Err(event),
+                };
+
+                let (request, stop) = match next {
+                    Ok(Ok(req)) => {
+                        let exit = matches!(&req, Incoming::Request(req) if req.method() == "exit");
+                        (req, Some(ExitReason::ClientRequested).filter(|_| exit))
+                    },
+                    Ok(Err(err)) => {
+                        let input = framed_stdin.decoder_mut().take_error_snippet();
+                        decode_failed = true;
+                        let report = |err: &_| (decode_errors.report(err, input.as_deref()), err.to_string());
+                        #[cfg(feature = "runtime-agnostic")]
+                        let (action, err) = crate::codec::with_parse_error(&err, report);
+                        #[cfg(feature = "runtime-tokio")]
+                        let (action, err) = report(&err);
+                        match action {
+                            DecodeErrorAction::Respond => {
+                                let response = Response::error(None, jsonrpc::Error::parse_error());
+                                let response_fut = future::ready(Some(Outgoing::Response(response)));
+                                sender.send(Either::Right(response_fut)).await.unwrap();
+                                continue;
+                            },
+                            DecodeErrorAction::Skip => continue,
+                            DecodeErrorAction::Terminate => {
+                                (exit_notification(), Some(ExitReason::DecodeError(err)))
+                            },
+                        }
+                    },
+                    Err(None) if exit_on_eof => (exit_notification(), Some(ExitReason::TransportClosed)),
+                    Err(None) => return ExitReason::TransportClosed,
+                    Err(Some(event)) => {
+                        // The watchdog exits the service on behalf of the client before stopping.
+                        watchdog.as_ref().unwrap().report(event);
+                        let reason = match event {
+                            WatchdogEvent::InputClosed => ExitReason::TransportClosed,
+                            event => ExitReason::Watchdog(event),
+                        };
+                        (exit_notification(), Some(reason))
                     },
                 };
 
+                if let Some(watchdog) = &mut watchdog {
+                    watchdog.observe(&request);
+                }
+
                 if let Err(err) = future::poll_fn(|cx| service.poll_ready(cx)).await {
-                    log::error!("{}", display_sources(err.into().as_ref()));
-                    return;
+                    let error = display_sources(err.into().as_ref());
+                    log::error!("{}", error);
+                    return ExitReason::InternalError(error);
                 }
 
                 let response_fut = service.call(request).unwrap_or_else(|err| {
@@ -111,13 +594,93 @@ where
                 });
 
                 sender.send(Either::Left(response_fut)).await.unwrap();
+
+                if let Some(reason) = stop {
+                    return reason;
+                }
             }
         };
 
-        futures::join!(reader, printer);
+        futures::join!(reader, printer).0
+    }
+}
+
+/// Writes the message to the sink, or flushes the sink if there is none, retrying transient errors
+/// according to the policy.
+async fn write<W>(mut sink: Pin<&mut W>, mut message: Option<Outgoing>, policy: &WriteErrorPolicy) -> Result<(), W::Error>
+where
+    W: Sink<Outgoing>,
+    W::Error: WriteError,
+{
+    let mut retry = 0;
+    loop {
+        let result = match message.take() {
+            Some(next) => {
+                // A copy of the message is only kept as long as a failed write may be retried.
+                if policy.may_retry(retry) {
+                    message = Some(next.clone());
+                }
+                sink.as_mut().feed(next).await
+            },
+            None => sink.as_mut().flush().await,
+        };
+        let err = match result {
+            Ok(()) => return Ok(()),
+            Err(err) => err,
+        };
+        match err.io_error_kind().and_then(|kind| policy.delay(kind, retry)) {
+            Some(delay) => {
+                log::warn!("failed to write message, retrying in {:?}: {}", delay, err);
+                crate::time::sleep(delay).await;
+                retry += 1;
+            },
+            None => return Err(err),
+        }
+    }
+}
+
+/// Errors of the framed `stdout`, some of which are I/O errors of the underlying writer.
+trait WriteError: std::fmt::Display {
+    fn io_error_kind(&self) -> Option<io::ErrorKind>;
+}
+
+#[cfg(feature = "runtime-tokio")]
+impl WriteError for ParseError {
+    fn io_error_kind(&self) -> Option<io::ErrorKind> {
+        io_error_kind(self)
     }
 }
 
+#[cfg(feature = "runtime-agnostic")]
+impl<E: Error + 'static> WriteError for E {
+    fn io_error_kind(&self) -> Option<io::ErrorKind> {
+        io_error_kind(self)
+    }
+}
+
+/// Returns the kind of the I/O error of the underlying reader or writer, if the error is one.
+#[cfg(feature = "runtime-tokio")]
+fn io_error_kind(error: &ParseError) -> Option<io::ErrorKind> {
+    match error {
+        ParseError::Encode(error) => Some(error.kind()),
+        _ => None,
+    }
+}
+
+/// Returns the kind of the I/O error of the underlying reader or writer, if the error is one.
+#[cfg(feature = "runtime-agnostic")]
+fn io_error_kind(error: &(dyn Error + 'static)) -> Option<io::ErrorKind> {
+    crate::codec::with_parse_error(error, |error| match error {
+        ParseError::Encode(error) => Some(error.kind()),
+        _ => None,
+    })
+}
+
+/// Returns an `exit` notification, passed to the service on behalf of the client.
+fn exit_notification() -> Incoming {
+    serde_json::from_value(serde_json::json!({ "jsonrpc": "2.0", "method": "exit" })).unwrap()
+}
+
 fn display_sources(error: &dyn Error) -> String {
     if let Some(source) = error.source() {
         format!("{}: {}", error, display_sources(source))
@@ -126,6 +689,65 @@ fn display_sources(error: &dyn Error) -> String {
     }
 }
 
+/// Handle for interleaving messages into the output of a [`Server`], returned by
+/// [`Server::interleave_bounded`].
+///
+/// Sending waits while the buffer of the server is full, and fails with [`ExitedError`] once the
+/// server stopped writing. Each clone of the handle may buffer one additional message.
+#[derive(Clone, Debug)]
+pub struct InterleaveSender(mpsc::Sender<Outgoing>);
+
+impl InterleaveSender {
+    /// Sends a notification of type `N`, waiting until the buffer of the server has room for it.
+    pub async fn send_notification<N>(&mut self, params: N::Params) -> Result<(), ExitedError>
+    where
+        N: lsp::notification::Notification,
+    {
+        let message = Outgoing::Request(ClientRequest::notification::<N>(params));
+        self.send(message).await
+    }
+
+    /// Returns whether the server stopped writing interleaved messages.
+    pub fn is_closed(&self) -> bool {
+        self.0.is_closed()
+    }
+}
+
+impl Sink<Outgoing> for InterleaveSender {
+    type Error = ExitedError;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.0.poll_ready(cx).map_err(|_| ExitedError)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, message: Outgoing) -> Result<(), Self::Error> {
+        self.0.start_send(message).map_err(|_| ExitedError)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.0).poll_flush(cx).map_err(|_| ExitedError)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.0).poll_close(cx).map_err(|_| ExitedError)
+    }
+}
+
+#[doc(hidden)]
+#[derive(Debug)]
+pub struct Interleaved<S>(Select<S, mpsc::Receiver<Outgoing>>);
+
+impl<S> Stream for Interleaved<S>
+where
+    S: Stream<Item = Outgoing> + Unpin,
+{
+    type Item = Outgoing;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.0).poll_next(cx)
+    }
+}
+
 #[doc(hidden)]
 #[derive(Debug)]
 pub struct Nothing(Empty<Outgoing>);
@@ -149,6 +771,7 @@ impl Stream for Nothing {
 mod tests {
     use super::*;
     use futures::{future, future::Ready, stream};
+    use std::sync::{Arc, Mutex};
 
     #[cfg(feature = "runtime-agnostic")]
     use futures::io::Cursor;
@@ -202,6 +825,51 @@ mod tests {
         assert_eq!(stdout, output);
     }
 
+    #[tokio::test]
+    async fn decode_error_policy() {
+        let invalid = r#"{"jsonrpc":"2.0","method":"#;
+        let invalid = format!("Content-Length: {}\r\n\r\n{}", invalid.len(), invalid).into_bytes();
+        let errors = Arc::new(Mutex::new(Vec::new()));
+        let policy = |action| {
+            let errors = errors.clone();
+            DecodeErrorPolicy::new(action).on_error(move |error, input| {
+                errors.lock().unwrap().push((error.to_string(), input.to_vec()));
+            })
+        };
+
+        let (mut stdin, mut stdout) = (Cursor::new([invalid.clone(), mock_request()].concat()), Vec::new());
+        let reason = Server::new(&mut stdin, &mut stdout)
+            .decode_errors(policy(DecodeErrorAction::Skip))
+            .serve(MockService)
+            .await;
+        assert_eq!(reason, ExitReason::TransportClosed);
+        assert_eq!(stdout, mock_response());
+        assert_eq!(errors.lock().unwrap().pop().unwrap().1, [invalid.clone(), mock_request()].concat());
+
+        let (mut stdin, mut stdout) = (Cursor::new([invalid.clone(), mock_request()].concat()), Vec::new());
+        let reason = Server::new(&mut stdin, &mut stdout)
+            .decode_errors(policy(DecodeErrorAction::Terminate))
+            .serve(MockService)
+            .await;
+        let (error, _) = errors.lock().unwrap().pop().unwrap();
+        assert_eq!(reason, ExitReason::DecodeError(error));
+        // The mock service responds to the `exit` notification passed on behalf of the client, but
+        // the `initialize` request was never read.
+        assert_eq!(stdout, mock_response());
+    }
+
+    #[tokio::test]
+    async fn counts_skipped_bytes() {
+        let policy = DecodeErrorPolicy::new(DecodeErrorAction::Skip);
+        let (mut stdin, mut stdout) = (Cursor::new([b"\0noise".to_vec(), mock_request()].concat()), Vec::new());
+        Server::new(&mut stdin, &mut stdout)
+            .decode_errors(policy.clone())
+            .serve(MockService)
+            .await;
+        assert_eq!(stdout, mock_response());
+        assert_eq!(policy.skipped_bytes(), 6);
+    }
+
     #[tokio::test]
     async fn interleaves_messages() {
         let message = Outgoing::Response(serde_json::from_str(RESPONSE).unwrap());
@@ -218,13 +886,334 @@ mod tests {
         assert_eq!(stdout, output);
     }
 
+    #[tokio::test]
+    async fn interleaves_bounded_messages() {
+        use lsp::notification::LogMessage;
+
+        let params = |message: &str| lsp::LogMessageParams {
+            typ: lsp::MessageType::INFO,
+            message: message.into(),
+        };
+        let (mut stdin, mut stdout) = mock_stdio();
+        let (server, mut sender) = Server::new(&mut stdin, &mut stdout)
+            .drain_timeout(Duration::from_millis(50))
+            .interleave_bounded(0);
+        let producer = async move {
+            for message in ["first", "second", "third"] {
+                sender.send_notification::<LogMessage>(params(message)).await.unwrap();
+            }
+            sender
+        };
+        let ((), mut sender) = futures::join!(server.serve(MockService).map(drop), producer);
+
+        let output = String::from_utf8(stdout).unwrap();
+        assert!(output.contains(r#""message":"first""#));
+        assert!(output.contains(r#""message":"third""#));
+        assert!(sender.is_closed());
+        assert_eq!(sender.send_notification::<LogMessage>(params("late")).await, Err(ExitedError));
+    }
+
+    #[tokio::test]
+    async fn builds_with_framing_options() {
+        use crate::traffic::Direction;
+
+        let padding = "x".repeat(REQUEST.len());
+        let large = format!(r#"{{"jsonrpc":"2.0","method":"initialized","params":{{"padding":"{}"}}}}"#, padding);
+        let large = format!("Content-Length: {}\r\n\r\n{}", large.len(), large).into_bytes();
+        let (mut stdin, mut stdout) = (Cursor::new([large, mock_request()].concat()), Vec::new());
+
+        let frames = Arc::new(Mutex::new(Vec::new()));
+        let inspected = frames.clone();
+        let message = || Outgoing::Response(serde_json::from_str(RESPONSE).unwrap());
+        let reason = Server::builder(&mut stdin, &mut stdout)
+            .interleave(stream::iter(vec![message()]))
+            .interleave(stream::iter(vec![message()]))
+            .max_frame_size(REQUEST.len())
+            .flush(FlushStrategy::Immediate)
+            .decode_errors(DecodeErrorPolicy::new(DecodeErrorAction::Skip))
+            .inspect_frames(move |frame| {
+                let frame = (frame.direction(), frame.headers().to_vec(), frame.body().to_vec());
+                inspected.lock().unwrap().push(frame);
+            })
+            .build()
+            .serve(MockService)
+            .await;
+
+        // The oversized message is skipped without being inspected or passed to the service.
+        assert_eq!(reason, ExitReason::TransportClosed);
+        assert_eq!(stdout, [mock_response(), mock_response(), mock_response()].concat());
+        let frames = frames.lock().unwrap();
+        let headers = |body: &str| format!("Content-Length: {}\r\n\r\n", body.len()).into_bytes();
+        assert_eq!(frames[0], (Direction::Incoming, headers(REQUEST), REQUEST.as_bytes().to_vec()));
+        assert_eq!(frames.len(), 4);
+        assert!(frames[1 ..].iter().all(|frame| *frame == (Direction::Outgoing, headers(RESPONSE), RESPONSE.as_bytes().to_vec())));
+    }
+
     #[tokio::test]
     async fn serves_on_stdio() {
         let (mut stdin, mut stdout) = mock_stdio();
-        Server::new(&mut stdin, &mut stdout).serve(MockService).await;
+        let reason = Server::new(&mut stdin, &mut stdout).serve(MockService).await;
 
         assert_eq!(stdin.position(), 80);
         assert_eq!(stdout, mock_response());
+        assert_eq!(reason, ExitReason::TransportClosed);
+    }
+
+    /// Writer failing with the given errors, from last to first, before writing to the buffer.
+    #[derive(Debug, Default)]
+    struct FlakyWriter {
+        failures: Vec<io::ErrorKind>,
+        written: Arc<Mutex<Vec<u8>>>,
+    }
+
+    impl FlakyWriter {
+        fn write(&mut self, buf: &[u8]) -> Poll<io::Result<usize>> {
+            match self.failures.pop() {
+                Some(kind) => Poll::Ready(Err(kind.into())),
+                None => {
+                    self.written.lock().unwrap().extend_from_slice(buf);
+                    Poll::Ready(Ok(buf.len()))
+                },
+            }
+        }
+    }
+
+    impl AsyncWrite for FlakyWriter {
+        fn poll_write(mut self: Pin<&mut Self>, _: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+            self.write(buf)
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _: &mut Context) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        #[cfg(feature = "runtime-agnostic")]
+        fn poll_close(self: Pin<&mut Self>, _: &mut Context) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        #[cfg(feature = "runtime-tokio")]
+        fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn retries_write_errors() {
+        let stdout = FlakyWriter {
+            failures: vec![io::ErrorKind::WouldBlock, io::ErrorKind::TimedOut],
+            ..Default::default()
+        };
+        let written = stdout.written.clone();
+        let reason = Server::new(Cursor::new(mock_request()), stdout)
+            .write_errors(WriteErrorPolicy::new().retries(2).backoff(Duration::from_millis(1), Duration::from_millis(1)))
+            .serve(MockService)
+            .await;
+
+        assert_eq!(reason, ExitReason::TransportClosed);
+        assert_eq!(*written.lock().unwrap(), mock_response());
+    }
+
+    #[cfg(feature = "runtime-tokio")]
+    #[tokio::test]
+    async fn reconnects_broken_transport() {
+        use tokio::io::AsyncWriteExt;
+
+        // The client keeps the first connection open, so that the failing write breaks it.
+        let (mut client, stdin) = tokio::io::duplex(1024);
+        client.write_all(&mock_request()).await.unwrap();
+        let stdout = FlakyWriter {
+            failures: vec![io::ErrorKind::BrokenPipe],
+            ..Default::default()
+        };
+
+        let (mut reconnected, next_stdin) = tokio::io::duplex(1024);
+        reconnected.write_all(&mock_request()).await.unwrap();
+        drop(reconnected);
+        let next_stdout = FlakyWriter::default();
+        let written = next_stdout.written.clone();
+
+        let mut connections = vec![(next_stdin, next_stdout)];
+        let reason = Server::new(stdin, stdout)
+            .reconnect(move || {
+                let connection = connections.pop().ok_or_else(|| io::ErrorKind::NotConnected.into());
+                future::ready(connection)
+            })
+            .serve(MockService)
+            .await;
+
+        // The response on the broken connection is lost, and the server stops once the hook fails.
+        assert_eq!(reason, ExitReason::TransportClosed);
+        assert_eq!(*written.lock().unwrap(), mock_response());
+        drop(client);
+    }
+
+    #[cfg(feature = "compression")]
+    #[tokio::test]
+    async fn compresses_messages() {
+        use crate::codec::{Compression, ContentEncoding};
+        #[cfg(feature = "runtime-agnostic")]
+        use async_codec_lite::Decoder;
+        #[cfg(feature = "runtime-tokio")]
+        use tokio_util::codec::Decoder;
+
+        let (mut stdin, mut stdout) = mock_stdio();
+        Server::new(&mut stdin, &mut stdout)
+            .compression(Compression::new(ContentEncoding::Zstd).min_size(0))
+            .serve(MockService)
+            .await;
+
+        assert!(String::from_utf8_lossy(&stdout).contains("\r\nContent-Encoding: zstd\r\n\r\n"));
+        let mut output = bytes::BytesMut::from(&stdout[..]);
+        let response = LanguageServerCodec::<serde_json::Value>::default().decode(&mut output).unwrap();
+        assert_eq!(response, Some(serde_json::from_str(RESPONSE).unwrap()));
+    }
+
+    #[tokio::test]
+    async fn stops_reading_after_exit() {
+        let exit = r#"{"jsonrpc":"2.0","method":"exit"}"#;
+        let exit = format!("Content-Length: {}\r\n\r\n{}", exit.len(), exit).into_bytes();
+        let (mut stdin, mut stdout) = (Cursor::new([exit, mock_request()].concat()), Vec::new());
+        let reason = Server::new(&mut stdin, &mut stdout).serve(MockService).await;
+
+        // The mock service responds to every message, so the `initialize` request was never read.
+        assert_eq!(stdout, mock_response());
+        assert_eq!(reason, ExitReason::ClientRequested);
+        assert!(reason.is_graceful());
+    }
+
+    #[tokio::test]
+    async fn drains_messages_with_timeout() {
+        let message = Outgoing::Response(serde_json::from_str(RESPONSE).unwrap());
+        let messages = stream::iter(vec![message]).chain(stream::pending());
+
+        let (mut stdin, mut stdout) = mock_stdio();
+        Server::new(&mut stdin, &mut stdout)
+            .interleave(messages)
+            .drain_timeout(Duration::from_millis(10))
+            .serve(MockService)
+            .await;
+
+        assert_eq!(stdout, [mock_response(), mock_response()].concat());
+    }
+
+    /// Responds to each request after a delay inversely proportional to its ID.
+    #[derive(Debug)]
+    struct DelayedService;
+
+    impl Service<Incoming> for DelayedService {
+        type Error = String;
+        type Future = Pin<Box<dyn std::future::Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+        type Response = Option<Outgoing>;
+
+        fn poll_ready(&mut self, _: &mut Context) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: Incoming) -> Self::Future {
+            let id = match request {
+                Incoming::Request(request) => request.id().cloned(),
+                Incoming::Response(_) => None,
+            };
+            let delay = match &id {
+                Some(jsonrpc::Id::Number(id)) => Duration::from_millis(60 - 20 * id),
+                _ => Duration::default(),
+            };
+            async move {
+                crate::time::sleep(delay).await;
+                Ok(id.map(|id| Outgoing::Response(Response::ok(id, serde_json::Value::Null))))
+            }
+            .boxed()
+        }
+    }
+
+    async fn response_ids(order: ResponseOrder) -> Vec<u64> {
+        let input: Vec<u8> = (1 ..= 2)
+            .flat_map(|id| {
+                let request = format!(r#"{{"jsonrpc":"2.0","method":"shutdown","id":{}}}"#, id);
+                format!("Content-Length: {}\r\n\r\n{}", request.len(), request).into_bytes()
+            })
+            .collect();
+        let (mut stdin, mut stdout) = (Cursor::new(input), Vec::new());
+        Server::new(&mut stdin, &mut stdout)
+            .response_order(order)
+            .serve(DelayedService)
+            .await;
+
+        let output = String::from_utf8(stdout).unwrap();
+        output
+            .split("Content-Length")
+            .filter_map(|message| message.split("\r\n\r\n").nth(1))
+            .map(|body| serde_json::from_str::<serde_json::Value>(body).unwrap()["id"].as_u64().unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn response_order() {
+        assert_eq!(response_ids(ResponseOrder::Completion).await, vec![2, 1]);
+        assert_eq!(response_ids(ResponseOrder::Received).await, vec![1, 2]);
+        assert_eq!(ResponseOrder::default(), ResponseOrder::Received);
+    }
+
+    fn watchdog_events() -> (Watchdog, Arc<Mutex<Vec<WatchdogEvent>>>) {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let recorded = events.clone();
+        let watchdog = Watchdog::new()
+            .clock(crate::MockClock::new())
+            .on_event(move |event| recorded.lock().unwrap().push(event));
+        (watchdog, events)
+    }
+
+    #[tokio::test]
+    async fn watchdog_initialize_timeout() {
+        let (watchdog, events) = watchdog_events();
+        let watchdog = watchdog.initialize_timeout(Some(Duration::default()));
+        let (mut stdin, mut stdout) = mock_stdio();
+        let reason = Server::new(&mut stdin, &mut stdout).watchdog(watchdog).serve(MockService).await;
+
+        assert_eq!(stdin.position(), 0);
+        assert_eq!(*events.lock().unwrap(), vec![WatchdogEvent::InitializeTimeout]);
+        assert_eq!(reason, ExitReason::Watchdog(WatchdogEvent::InitializeTimeout));
+        assert!(!reason.is_graceful());
+    }
+
+    #[tokio::test]
+    async fn watchdog_exit_timeout() {
+        let shutdown = r#"{"jsonrpc":"2.0","method":"shutdown","id":2}"#;
+        let shutdown = format!("Content-Length: {}\r\n\r\n{}", shutdown.len(), shutdown).into_bytes();
+        let (watchdog, events) = watchdog_events();
+        let watchdog = watchdog.exit_timeout(Some(Duration::default()));
+        let (mut stdin, mut stdout) = (Cursor::new([mock_request(), shutdown].concat()), Vec::new());
+        Server::new(&mut stdin, &mut stdout).watchdog(watchdog).serve(MockService).await;
+
+        assert_eq!(*events.lock().unwrap(), vec![WatchdogEvent::ExitTimeout]);
+    }
+
+    #[tokio::test]
+    async fn watchdog_input_closed() {
+        let (watchdog, events) = watchdog_events();
+        let (mut stdin, mut stdout) = mock_stdio();
+        let reason = Server::new(&mut stdin, &mut stdout).watchdog(watchdog).serve(MockService).await;
+
+        assert_eq!(stdin.position(), 80);
+        assert_eq!(*events.lock().unwrap(), vec![WatchdogEvent::InputClosed]);
+        assert_eq!(reason, ExitReason::TransportClosed);
+        // The mock service also responds to the `exit` notification passed on behalf of the client.
+        assert_eq!(stdout, [mock_response(), mock_response()].concat());
+    }
+
+    #[tokio::test]
+    async fn exits_on_eof() {
+        let (mut stdin, mut stdout) = mock_stdio();
+        let server = Server {
+            exit_on_eof: true,
+            ..Server::new(&mut stdin, &mut stdout)
+        };
+        let reason = server.serve(MockService).await;
+
+        assert_eq!(reason, ExitReason::TransportClosed);
+        // The mock service also responds to the `exit` notification passed on behalf of the client.
+        assert_eq!(stdout, [mock_response(), mock_response()].concat());
     }
 
     #[derive(Debug)]
@@ -0,0 +1,221 @@
+//! Builder consolidating the configuration of a [`Server`].
+
+use super::{DecodeErrorPolicy, FlushStrategy, Nothing, ResponseOrder, Server, Watchdog, WriteErrorPolicy};
+#[cfg(feature = "compression")]
+use crate::codec::Compression;
+use crate::{
+    codec::{Frame, FrameInspector},
+    jsonrpc::Outgoing,
+    traffic::TrafficLogger,
+};
+use futures::stream::{BoxStream, SelectAll, Stream, StreamExt};
+use std::{
+    fmt::{self, Debug, Formatter},
+    future::Future,
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+#[cfg(feature = "runtime-agnostic")]
+use futures::io::{AsyncRead, AsyncWrite};
+#[cfg(feature = "runtime-tokio")]
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// Builder for a [`Server`], created with [`Server::builder`].
+///
+/// Besides the settings also available on the `Server` itself, the builder configures the framing
+/// of the transport: an inspector observing every message as it is read or written, the maximum
+/// size of messages read, and when messages written are flushed. Any number of streams can be
+/// interleaved into the output, such as the [`MessageStream`] of the service along with messages
+/// forwarded from another server.
+///
+/// # Example
+///
+/// ```rust
+/// # use lspower::{jsonrpc::Result, lsp::*, Client, FlushStrategy, LanguageServer, LspService, Server};
+/// # #[derive(Debug)]
+/// # struct Backend;
+/// # #[lspower::async_trait]
+/// # impl LanguageServer for Backend {
+/// #     async fn initialize(&self, _: InitializeParams) -> Result<InitializeResult> {
+/// #         Ok(InitializeResult::default())
+/// #     }
+/// #     async fn shutdown(&self) -> Result<()> {
+/// #         Ok(())
+/// #     }
+/// # }
+/// # async fn run(stream: tokio::net::TcpStream) {
+/// let (read, write) = tokio::io::split(stream);
+/// let (service, messages) = LspService::new(|_: Client| Backend);
+/// let server = Server::builder(read, write)
+///     .interleave(messages)
+///     .max_frame_size(16 * 1024 * 1024)
+///     .flush(FlushStrategy::Immediate)
+///     .inspect_frames(|frame| log::trace!("{:?}: {} bytes", frame.direction(), frame.body().len()))
+///     .build();
+/// server.serve(service).await;
+/// # }
+/// ```
+///
+/// [`MessageStream`]: crate::MessageStream
+pub struct ServerBuilder<I, O> {
+    server: Server<I, O, Nothing>,
+    streams: Vec<BoxStream<'static, Outgoing>>,
+}
+
+impl<I, O> ServerBuilder<I, O>
+where
+    I: AsyncRead + Unpin,
+    O: AsyncWrite,
+{
+    pub(super) fn new(server: Server<I, O, Nothing>) -> Self {
+        ServerBuilder {
+            server,
+            streams: Vec::new(),
+        }
+    }
+
+    /// Interleaves the given stream of messages into the output together with the responses and
+    /// the streams interleaved before.
+    pub fn interleave<S>(mut self, stream: S) -> Self
+    where
+        S: Stream<Item = Outgoing> + Send + 'static,
+    {
+        self.streams.push(stream.boxed());
+        self
+    }
+
+    /// Invokes the given inspector with each message read from or written to the transport,
+    /// including its headers.
+    ///
+    /// Messages read are inspected before they are decoded, so that messages which fail to decode
+    /// are inspected as well. Messages exceeding the [`max_frame_size`](ServerBuilder::max_frame_size)
+    /// are not.
+    pub fn inspect_frames<F>(mut self, inspect: F) -> Self
+    where
+        F: Fn(&Frame) + Send + Sync + 'static,
+    {
+        self.server.inspector = Some(FrameInspector::new(inspect));
+        self
+    }
+
+    /// Rejects messages read whose `Content-Length` exceeds the given number of bytes.
+    ///
+    /// The bodies of such messages are skipped as they arrive rather than buffered, and the error
+    /// is handled according to the [`decode_errors`](ServerBuilder::decode_errors) policy.
+    /// Unlimited by default.
+    pub fn max_frame_size(mut self, bytes: usize) -> Self {
+        self.server.max_frame_size = Some(bytes);
+        self
+    }
+
+    /// Sets when the messages written are flushed, defaulting to [`FlushStrategy::Coalesced`].
+    pub fn flush(mut self, strategy: FlushStrategy) -> Self {
+        self.server.flush = strategy;
+        self
+    }
+
+    /// Sets the logger used for the messages read and written.
+    ///
+    /// See [`Server::traffic_logger`].
+    pub fn traffic_logger(mut self, logger: TrafficLogger) -> Self {
+        self.server.logger = logger;
+        self
+    }
+
+    /// Guards the connection against clients which do not follow the protocol lifecycle.
+    ///
+    /// See [`Server::watchdog`].
+    pub fn watchdog(mut self, watchdog: Watchdog) -> Self {
+        self.server.watchdog = Some(watchdog);
+        self
+    }
+
+    /// Sets how long outgoing messages are still written once the server stopped reading.
+    ///
+    /// See [`Server::drain_timeout`].
+    pub fn drain_timeout(mut self, timeout: Duration) -> Self {
+        self.server.drain_timeout = timeout;
+        self
+    }
+
+    /// Sets the order in which responses are written.
+    ///
+    /// See [`Server::response_order`].
+    pub fn response_order(mut self, order: ResponseOrder) -> Self {
+        self.server.response_order = order;
+        self
+    }
+
+    /// Sets how messages read which fail to decode are handled.
+    ///
+    /// See [`Server::decode_errors`].
+    pub fn decode_errors(mut self, policy: DecodeErrorPolicy) -> Self {
+        self.server.decode_errors = policy;
+        self
+    }
+
+    /// Compresses the messages written.
+    ///
+    /// See [`Server::compression`].
+    #[cfg(feature = "compression")]
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.server.compression = Some(compression);
+        self
+    }
+
+    /// Sets how errors writing messages are handled.
+    ///
+    /// See [`Server::write_errors`].
+    pub fn write_errors(mut self, policy: WriteErrorPolicy) -> Self {
+        self.server.write_errors = policy;
+        self
+    }
+
+    /// Continues serving on a new connection returned by the given hook once the connection broke.
+    ///
+    /// See [`Server::reconnect`].
+    pub fn reconnect<F, Fut>(mut self, hook: F) -> Self
+    where
+        F: FnMut() -> Fut + Send + 'static,
+        Fut: Future<Output = io::Result<(I, O)>> + Send + 'static,
+    {
+        self.server = self.server.reconnect(hook);
+        self
+    }
+
+    /// Creates the `Server`.
+    pub fn build(self) -> Server<I, O, InterleavedStreams> {
+        let streams = InterleavedStreams(self.streams.into_iter().collect());
+        self.server.interleave(streams)
+    }
+}
+
+impl<I: Debug, O: Debug> Debug for ServerBuilder<I, O> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct(stringify!(ServerBuilder))
+            .field("server", &self.server)
+            .field("streams", &self.streams.len())
+            .finish()
+    }
+}
+
+/// The streams interleaved into the output of a [`Server`] created with a [`ServerBuilder`].
+#[doc(hidden)]
+pub struct InterleavedStreams(SelectAll<BoxStream<'static, Outgoing>>);
+
+impl Stream for InterleavedStreams {
+    type Item = Outgoing;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.0).poll_next(cx)
+    }
+}
+
+impl Debug for InterleavedStreams {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_tuple(stringify!(InterleavedStreams)).field(&self.0.len()).finish()
+    }
+}
@@ -0,0 +1,111 @@
+//! Handling of messages which fail to decode.
+
+use crate::ParseError;
+use std::{
+    fmt::{self, Debug, Formatter},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+type Handler = Arc<dyn Fn(&ParseError, &[u8]) + Send + Sync>;
+
+/// What a [`Server`] does when a message read from `stdin` fails to decode.
+///
+/// [`Server`]: crate::Server
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum DecodeErrorAction {
+    /// Responds with a "parse error" (`-32700`) without an ID, as the JSON-RPC specification
+    /// requires, and continues reading.
+    #[default]
+    Respond,
+    /// Skips the message and continues reading.
+    Skip,
+    /// Stops reading, passing an `exit` notification to the service on behalf of the client.
+    Terminate,
+}
+
+/// Policy of a [`Server`] for messages read from `stdin` which fail to decode, such as messages
+/// with invalid JSON or without a `Content-Length` header.
+///
+/// By default, the server responds with a "parse error" and continues reading, and the error is
+/// logged. Handlers set with [`on_error`](DecodeErrorPolicy::on_error) are invoked with the error
+/// and the first bytes of the input it occurred at, before the action is taken.
+///
+/// After invalid input, such as garbage or the remains of a message torn by a lossy transport, the
+/// server skips ahead to the next complete message. The number of bytes skipped this way is
+/// counted by the policy and returned by [`skipped_bytes`](DecodeErrorPolicy::skipped_bytes).
+/// Clones of a policy share the same count.
+///
+/// # Example
+///
+/// ```rust
+/// # use lspower::{DecodeErrorAction, DecodeErrorPolicy};
+/// let policy = DecodeErrorPolicy::new(DecodeErrorAction::Terminate).on_error(|error, input| {
+///     eprintln!("invalid message ({}): {}", error, String::from_utf8_lossy(input));
+/// });
+/// ```
+///
+/// [`Server`]: crate::Server
+#[derive(Clone, Default)]
+pub struct DecodeErrorPolicy {
+    action: DecodeErrorAction,
+    handler: Option<Handler>,
+    skipped_bytes: Arc<AtomicU64>,
+}
+
+impl DecodeErrorPolicy {
+    /// Creates a new `DecodeErrorPolicy` taking the given action.
+    pub fn new(action: DecodeErrorAction) -> Self {
+        DecodeErrorPolicy {
+            action,
+            handler: None,
+            skipped_bytes: Default::default(),
+        }
+    }
+
+    /// Sets a handler which is invoked with each error, along with the start of the input which
+    /// failed to decode.
+    pub fn on_error<H>(mut self, handler: H) -> Self
+    where
+        H: Fn(&ParseError, &[u8]) + Send + Sync + 'static,
+    {
+        self.handler = Some(Arc::new(handler));
+        self
+    }
+
+    /// Returns the action taken for messages which fail to decode.
+    pub fn action(&self) -> DecodeErrorAction {
+        self.action
+    }
+
+    /// Returns the total number of bytes of invalid input skipped by servers using this policy.
+    pub fn skipped_bytes(&self) -> u64 {
+        self.skipped_bytes.load(Ordering::SeqCst)
+    }
+
+    /// Returns the counter of skipped bytes, which is shared with the decoder.
+    pub(crate) fn skipped_bytes_counter(&self) -> Arc<AtomicU64> {
+        self.skipped_bytes.clone()
+    }
+
+    /// Reports the given error, returning the action to take.
+    pub(crate) fn report(&self, error: &ParseError, input: Option<&[u8]>) -> DecodeErrorAction {
+        log::error!("failed to decode message: {}", error);
+        if let Some(handler) = &self.handler {
+            handler(error, input.unwrap_or_default());
+        }
+        self.action
+    }
+}
+
+impl Debug for DecodeErrorPolicy {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct(stringify!(DecodeErrorPolicy))
+            .field("action", &self.action)
+            .field("on_error", &self.handler.is_some())
+            .field("skipped_bytes", &self.skipped_bytes())
+            .finish()
+    }
+}
@@ -0,0 +1,228 @@
+//! Running several servers concurrently in one process.
+
+use super::{ExitReason, Server};
+use crate::jsonrpc::{Incoming, Outgoing};
+use futures::{
+    future::{self, BoxFuture, FutureExt},
+    stream::Stream,
+};
+use std::{
+    error::Error,
+    fmt::{self, Debug, Formatter},
+};
+use tower_service::Service;
+
+#[cfg(feature = "runtime-agnostic")]
+use futures::io::{AsyncRead, AsyncWrite};
+#[cfg(feature = "runtime-tokio")]
+use tokio::io::{AsyncRead, AsyncWrite};
+
+/// Runs several independent [`Server`]s concurrently, e.g. language servers for different
+/// languages hosted by one process and listening on separate sockets.
+///
+/// Each server is added under a name with its own transport and service, and
+/// [`serve`](ServerGroup::serve) waits for all of them to stop. With
+/// [`fail_fast`](ServerGroup::fail_fast), the group instead stops as soon as one server exits
+/// ungracefully, dropping the servers which are still running.
+///
+/// # Example
+///
+/// ```rust
+/// # use lspower::{jsonrpc::Result, lsp::*, Client, LanguageServer, LspService, Server, ServerGroup};
+/// # #[derive(Debug)]
+/// # struct Backend;
+/// # #[lspower::async_trait]
+/// # impl LanguageServer for Backend {
+/// #     async fn initialize(&self, _: InitializeParams) -> Result<InitializeResult> {
+/// #         Ok(InitializeResult::default())
+/// #     }
+/// #     async fn shutdown(&self) -> Result<()> {
+/// #         Ok(())
+/// #     }
+/// # }
+/// # async fn run() -> std::io::Result<()> {
+/// use tokio::net::TcpListener;
+///
+/// let mut group = ServerGroup::new();
+/// for (name, port) in [("rust", 9257), ("toml", 9258)] {
+///     let listener = TcpListener::bind(("127.0.0.1", port)).await?;
+///     let (stream, _) = listener.accept().await?;
+///     let (read, write) = tokio::io::split(stream);
+///     let (service, messages) = LspService::new(|_: Client| Backend);
+///     group = group.server(name, Server::new(read, write).interleave(messages), service);
+/// }
+///
+/// let exit = group.serve().await;
+/// for (name, reason) in exit.failures() {
+///     eprintln!("{} stopped: {:?}", name, reason);
+/// }
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Default)]
+pub struct ServerGroup<'a> {
+    servers: Vec<(String, BoxFuture<'a, ExitReason>)>,
+    fail_fast: bool,
+}
+
+impl<'a> ServerGroup<'a> {
+    /// Creates an empty `ServerGroup`.
+    pub fn new() -> Self {
+        ServerGroup::default()
+    }
+
+    /// Adds a server under the given name, serving `service` once the group is served.
+    pub fn server<I, O, S, T>(mut self, name: impl Into<String>, server: Server<I, O, S>, service: T) -> Self
+    where
+        I: AsyncRead + Unpin + Send + 'a,
+        O: AsyncWrite + Send + 'a,
+        S: Stream<Item = Outgoing> + Send + 'a,
+        T: Service<Incoming, Response = Option<Outgoing>> + Send + 'static,
+        T::Error: Into<Box<dyn Error + Send + Sync>>,
+        T::Future: Send,
+    {
+        self.servers.push((name.into(), server.serve(service).boxed()));
+        self
+    }
+
+    /// Sets whether the group stops as soon as one server exits ungracefully.
+    ///
+    /// Disabled by default.
+    pub fn fail_fast(mut self, enabled: bool) -> Self {
+        self.fail_fast = enabled;
+        self
+    }
+
+    /// Serves all servers concurrently and returns why each of them stopped.
+    pub async fn serve(self) -> GroupExit {
+        let (mut names, mut running): (Vec<_>, Vec<_>) = self.servers.into_iter().unzip();
+        let mut exited = Vec::with_capacity(names.len());
+
+        while !running.is_empty() {
+            let (reason, index, rest) = future::select_all(running).await;
+            let name = names.remove(index);
+            running = rest;
+
+            let failed = !reason.is_graceful();
+            if failed {
+                log::warn!("server {} stopped: {:?}", name, reason);
+            }
+            exited.push((name, reason));
+            if failed && self.fail_fast {
+                break;
+            }
+        }
+
+        GroupExit { exited, aborted: names }
+    }
+}
+
+impl Debug for ServerGroup<'_> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let names: Vec<_> = self.servers.iter().map(|(name, _)| name).collect();
+        f.debug_struct(stringify!(ServerGroup))
+            .field("servers", &names)
+            .field("fail_fast", &self.fail_fast)
+            .finish()
+    }
+}
+
+/// Why the servers of a [`ServerGroup`] stopped, returned by [`ServerGroup::serve`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct GroupExit {
+    exited: Vec<(String, ExitReason)>,
+    aborted: Vec<String>,
+}
+
+impl GroupExit {
+    /// Returns the names of the servers which stopped, in the order they stopped, with the reason.
+    pub fn exited(&self) -> &[(String, ExitReason)] {
+        &self.exited
+    }
+
+    /// Returns the names of the servers which were dropped after another server failed, with
+    /// [`ServerGroup::fail_fast`] enabled.
+    pub fn aborted(&self) -> &[String] {
+        &self.aborted
+    }
+
+    /// Returns the servers which did not exit as requested by their client.
+    pub fn failures(&self) -> impl Iterator<Item = (&str, &ExitReason)> {
+        let failed = self.exited.iter().filter(|(_, reason)| !reason.is_graceful());
+        failed.map(|(name, reason)| (name.as_str(), reason))
+    }
+
+    /// Returns whether every server exited as requested by its client.
+    pub fn is_graceful(&self) -> bool {
+        self.aborted.is_empty() && self.failures().next().is_none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::future::Ready;
+    use std::task::{Context, Poll};
+
+    #[cfg(feature = "runtime-agnostic")]
+    use futures::io::Cursor;
+    #[cfg(feature = "runtime-tokio")]
+    use std::io::Cursor;
+
+    #[derive(Debug)]
+    struct MockService;
+
+    impl Service<Incoming> for MockService {
+        type Error = String;
+        type Future = Ready<Result<Self::Response, Self::Error>>;
+        type Response = Option<Outgoing>;
+
+        fn poll_ready(&mut self, _: &mut Context) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _: Incoming) -> Self::Future {
+            future::ok(None)
+        }
+    }
+
+    fn input(messages: &[&str]) -> Cursor<Vec<u8>> {
+        let framed = messages.iter().map(|msg| format!("Content-Length: {}\r\n\r\n{}", msg.len(), msg));
+        Cursor::new(framed.collect::<String>().into_bytes())
+    }
+
+    #[tokio::test]
+    async fn serves_all() {
+        let exit = r#"{"jsonrpc":"2.0","method":"exit"}"#;
+        let exit = ServerGroup::new()
+            .server("a", Server::new(input(&[exit]), Vec::new()), MockService)
+            .server("b", Server::new(input(&[]), Vec::new()), MockService)
+            .serve()
+            .await;
+
+        let mut exited = exit.exited().to_vec();
+        exited.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(exited, vec![
+            ("a".into(), ExitReason::ClientRequested),
+            ("b".into(), ExitReason::TransportClosed),
+        ]);
+        assert_eq!(exit.failures().collect::<Vec<_>>(), vec![("b", &ExitReason::TransportClosed)]);
+        assert!(!exit.is_graceful());
+    }
+
+    #[cfg(feature = "runtime-tokio")]
+    #[tokio::test]
+    async fn fails_fast() {
+        let (pending, _writer) = tokio::io::duplex(64);
+        let exit = ServerGroup::new()
+            .fail_fast(true)
+            .server("pending", Server::new(pending, Vec::new()), MockService)
+            .server("closed", Server::new(input(&[]), Vec::new()), MockService)
+            .serve()
+            .await;
+
+        assert_eq!(exit.exited(), [("closed".into(), ExitReason::TransportClosed)]);
+        assert_eq!(exit.aborted(), ["pending".to_string()]);
+        assert!(!exit.is_graceful());
+    }
+}
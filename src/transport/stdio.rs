@@ -0,0 +1,390 @@
+//! Standard input and output of the process, read and written on dedicated threads.
+
+#[cfg(feature = "runtime-agnostic")]
+use futures::io::{AsyncRead, AsyncWrite};
+#[cfg(feature = "runtime-tokio")]
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use futures::{
+    channel::{mpsc, oneshot},
+    executor,
+    ready,
+    sink::SinkExt,
+    stream::StreamExt,
+};
+use std::{
+    fmt::{self, Debug, Formatter},
+    future::Future,
+    io::{self, Read, Write},
+    pin::Pin,
+    task::{Context, Poll},
+    thread,
+};
+
+/// The number of bytes read from `stdin` at once.
+const CHUNK_SIZE: usize = 8 * 1024;
+
+/// The number of chunks buffered between the threads and the server.
+const CAPACITY: usize = 4;
+
+/// The error code of `ReadFile` on a pipe in `PIPE_NOWAIT` mode which has no data.
+#[cfg(windows)]
+const ERROR_NO_DATA: i32 = 232;
+
+/// Standard input of the process, as used by [`Server::stdio`].
+///
+/// Standard input is read on a dedicated thread rather than on the blocking thread pool of the
+/// runtime, so that the blocking read, which cannot be interrupted, does not hold up the shutdown
+/// of the runtime once the server stopped. The thread ends with the process, or after the next
+/// read once the `Stdin` is dropped.
+///
+/// Some editors pass `stdin` in non-blocking mode. On Unix, the thread then waits for data with
+/// `poll` instead of reading in a loop. On Windows, a pipe in `PIPE_NOWAIT` mode fails reads
+/// without data with `ERROR_NO_DATA`, which is retried after a short delay, since such pipes
+/// cannot be waited on.
+///
+/// [`Server::stdio`]: crate::Server::stdio
+pub struct Stdin {
+    chunks: mpsc::Receiver<io::Result<Vec<u8>>>,
+    chunk: Vec<u8>,
+    read: usize,
+}
+
+impl Stdin {
+    /// Starts reading the standard input of the process.
+    pub fn new() -> io::Result<Self> {
+        Stdin::spawn(io::stdin(), wait_readable)
+    }
+
+    /// Reads from `reader` on a new thread, calling `wait` until it has data whenever a read
+    /// would block.
+    fn spawn<R, W>(mut reader: R, wait: W) -> io::Result<Self>
+    where
+        R: Read + Send + 'static,
+        W: Fn() -> io::Result<()> + Send + 'static,
+    {
+        let (mut tx, chunks) = mpsc::channel(CAPACITY);
+        thread::Builder::new().name("lspower-stdin".into()).spawn(move || {
+            let mut buf = vec![0; CHUNK_SIZE];
+            loop {
+                let chunk = match reader.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(len) => Ok(buf[.. len].to_vec()),
+                    Err(err) if would_block(&err) => match wait() {
+                        Ok(()) => continue,
+                        Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                        Err(err) => Err(err),
+                    },
+                    Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(err) => Err(err),
+                };
+                let failed = chunk.is_err();
+                if executor::block_on(tx.send(chunk)).is_err() || failed {
+                    break;
+                }
+            }
+        })?;
+
+        Ok(Stdin {
+            chunks,
+            chunk: Vec::new(),
+            read: 0,
+        })
+    }
+
+    /// Copies the data read from `stdin` into `buf`, returning `0` at the end of the input.
+    fn poll_read_into(&mut self, cx: &mut Context, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        if self.read == self.chunk.len() {
+            match ready!(self.chunks.poll_next_unpin(cx)) {
+                Some(Ok(chunk)) => {
+                    self.chunk = chunk;
+                    self.read = 0;
+                },
+                Some(Err(err)) => return Poll::Ready(Err(err)),
+                None => return Poll::Ready(Ok(0)),
+            }
+        }
+
+        let len = buf.len().min(self.chunk.len() - self.read);
+        buf[.. len].copy_from_slice(&self.chunk[self.read .. self.read + len]);
+        self.read += len;
+        Poll::Ready(Ok(len))
+    }
+}
+
+/// Returns whether a read failed since the reader is in non-blocking mode and has no data.
+fn would_block(err: &io::Error) -> bool {
+    #[cfg(windows)]
+    if err.raw_os_error() == Some(ERROR_NO_DATA) {
+        return true;
+    }
+    err.kind() == io::ErrorKind::WouldBlock
+}
+
+/// Blocks until the standard input of the process has data or is closed.
+#[cfg(unix)]
+fn wait_readable() -> io::Result<()> {
+    use rustix::event::{poll, PollFd, PollFlags};
+
+    let stdin = io::stdin();
+    poll(&mut [PollFd::new(&stdin, PollFlags::IN)], None)?;
+    Ok(())
+}
+
+/// Waits briefly before reading the standard input of the process again, since it cannot be
+/// waited on.
+#[cfg(not(unix))]
+fn wait_readable() -> io::Result<()> {
+    thread::sleep(std::time::Duration::from_millis(5));
+    Ok(())
+}
+
+#[cfg(feature = "runtime-tokio")]
+impl AsyncRead for Stdin {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context, buf: &mut ReadBuf) -> Poll<io::Result<()>> {
+        let len = ready!(self.poll_read_into(cx, buf.initialize_unfilled()))?;
+        buf.advance(len);
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(feature = "runtime-agnostic")]
+impl AsyncRead for Stdin {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        self.poll_read_into(cx, buf)
+    }
+}
+
+impl Debug for Stdin {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct(stringify!(Stdin))
+            .field("buffered", &(self.chunk.len() - self.read))
+            .finish()
+    }
+}
+
+enum Command {
+    Write(Vec<u8>),
+    Flush(oneshot::Sender<io::Result<()>>),
+}
+
+/// Standard output of the process, as used by [`Server::stdio`].
+///
+/// Standard output is written on a dedicated thread, so that a client which is slow to read does
+/// not block the runtime. Errors of writes are reported when flushing. The thread ends once the
+/// `Stdout` is closed or dropped.
+///
+/// [`Server::stdio`]: crate::Server::stdio
+pub struct Stdout {
+    commands: mpsc::Sender<Command>,
+    flushed: Option<oneshot::Receiver<io::Result<()>>>,
+}
+
+impl Stdout {
+    /// Starts writing to the standard output of the process.
+    pub fn new() -> io::Result<Self> {
+        Stdout::spawn(io::stdout())
+    }
+
+    fn spawn<W: Write + Send + 'static>(mut writer: W) -> io::Result<Self> {
+        let (commands, mut rx) = mpsc::channel(CAPACITY);
+        thread::Builder::new().name("lspower-stdout".into()).spawn(move || {
+            let mut failed = None;
+            while let Some(command) = executor::block_on(rx.next()) {
+                match command {
+                    Command::Write(data) if failed.is_none() => failed = writer.write_all(&data).err(),
+                    Command::Write(_) => {},
+                    Command::Flush(flushed) => {
+                        let result = match failed.take() {
+                            Some(err) => Err(err),
+                            None => writer.flush(),
+                        };
+                        let _ = flushed.send(result);
+                    },
+                }
+            }
+        })?;
+
+        Ok(Stdout {
+            commands,
+            flushed: None,
+        })
+    }
+
+    fn poll_send(&mut self, cx: &mut Context, command: impl FnOnce() -> Command) -> Poll<io::Result<()>> {
+        ready!(self.commands.poll_ready(cx)).map_err(|_| closed())?;
+        self.commands.start_send(command()).map_err(|_| closed())?;
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_write_from(&mut self, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        ready!(self.poll_send(cx, || Command::Write(buf.to_vec())))?;
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    /// Waits until the thread wrote and flushed all data written before.
+    fn poll_flush_all(&mut self, cx: &mut Context) -> Poll<io::Result<()>> {
+        if self.flushed.is_none() {
+            let (tx, rx) = oneshot::channel();
+            ready!(self.poll_send(cx, || Command::Flush(tx)))?;
+            self.flushed = Some(rx);
+        }
+
+        let result = ready!(Pin::new(self.flushed.as_mut().unwrap()).poll(cx));
+        self.flushed = None;
+        Poll::Ready(result.unwrap_or_else(|_| Err(closed())))
+    }
+
+    fn poll_close_all(&mut self, cx: &mut Context) -> Poll<io::Result<()>> {
+        ready!(self.poll_flush_all(cx))?;
+        self.commands.close_channel();
+        Poll::Ready(Ok(()))
+    }
+}
+
+fn closed() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "stdout thread stopped")
+}
+
+#[cfg(feature = "runtime-tokio")]
+impl AsyncWrite for Stdout {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        self.poll_write_from(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        self.poll_flush_all(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        self.poll_close_all(cx)
+    }
+}
+
+#[cfg(feature = "runtime-agnostic")]
+impl AsyncWrite for Stdout {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        self.poll_write_from(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        self.poll_flush_all(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        self.poll_close_all(cx)
+    }
+}
+
+impl Debug for Stdout {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct(stringify!(Stdout)).finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        collections::VecDeque,
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+            Mutex,
+        },
+    };
+
+    #[cfg(feature = "runtime-agnostic")]
+    use futures::io::{AsyncReadExt, AsyncWriteExt};
+    #[cfg(feature = "runtime-tokio")]
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// A reader returning the given results one after another, then the end of the input.
+    struct Script(VecDeque<io::Result<&'static [u8]>>);
+
+    impl Read for Script {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.0.pop_front() {
+                Some(Ok(data)) => {
+                    buf[.. data.len()].copy_from_slice(data);
+                    Ok(data.len())
+                },
+                Some(Err(err)) => Err(err),
+                None => Ok(0),
+            }
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn reads_until_eof() {
+        let script = Script(VecDeque::from([
+            Ok(&b"Content-"[..]),
+            Err(io::ErrorKind::WouldBlock.into()),
+            Err(io::ErrorKind::Interrupted.into()),
+            Ok(&b"Length: 0"[..]),
+        ]));
+        let waits = Arc::new(AtomicUsize::new(0));
+        let waited = waits.clone();
+        let wait = move || {
+            waited.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        };
+        let mut stdin = Stdin::spawn(script, wait).unwrap();
+        let mut input = String::new();
+        stdin.read_to_string(&mut input).await.unwrap();
+        assert_eq!(input, "Content-Length: 0");
+        assert_eq!(waits.load(Ordering::SeqCst), 1);
+
+        let script = Script(VecDeque::from([Err(io::ErrorKind::InvalidData.into())]));
+        let mut stdin = Stdin::spawn(script, || Ok(())).unwrap();
+        let err = stdin.read(&mut [0; 4]).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(stdin.read(&mut [0; 4]).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn wait_fails() {
+        let script = Script(VecDeque::from([Err(io::ErrorKind::WouldBlock.into())]));
+        let mut stdin = Stdin::spawn(script, || Err(io::ErrorKind::BrokenPipe.into())).unwrap();
+        let err = stdin.read(&mut [0; 4]).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+    }
+
+    #[test]
+    fn would_block() {
+        assert!(super::would_block(&io::ErrorKind::WouldBlock.into()));
+        assert!(!super::would_block(&io::ErrorKind::BrokenPipe.into()));
+        #[cfg(windows)]
+        assert!(super::would_block(&io::Error::from_raw_os_error(ERROR_NO_DATA)));
+    }
+
+    #[tokio::test]
+    async fn writes_on_flush() {
+        let output = Shared::default();
+        let mut stdout = Stdout::spawn(output.clone()).unwrap();
+        stdout.write_all(b"Content-").await.unwrap();
+        stdout.write_all(b"Length: 0").await.unwrap();
+        stdout.flush().await.unwrap();
+        assert_eq!(*output.0.lock().unwrap(), b"Content-Length: 0");
+
+        #[cfg(feature = "runtime-tokio")]
+        stdout.shutdown().await.unwrap();
+        #[cfg(feature = "runtime-agnostic")]
+        stdout.close().await.unwrap();
+        assert_eq!(stdout.write(b"\r\n").await.unwrap_err().kind(), io::ErrorKind::BrokenPipe);
+    }
+}
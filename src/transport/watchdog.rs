@@ -0,0 +1,175 @@
+//! Timeouts guarding the lifecycle of a connection.
+
+use crate::{jsonrpc::Incoming, Clock, SystemClock};
+use futures::future::{self, BoxFuture};
+use std::{
+    fmt::{self, Debug, Formatter},
+    sync::Arc,
+    time::Duration,
+};
+
+type Handler = Arc<dyn Fn(WatchdogEvent) + Send + Sync>;
+
+/// An event reported by a [`Watchdog`] before it stops the server.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum WatchdogEvent {
+    /// The client did not send the `initialize` request in time.
+    InitializeTimeout,
+    /// The client did not send the `exit` notification in time after the `shutdown` request.
+    ExitTimeout,
+    /// The input stream closed before the client sent the `exit` notification.
+    InputClosed,
+}
+
+/// Guards a [`Server`] against clients which do not follow the protocol lifecycle.
+///
+/// Without a watchdog, a server whose client connects but never sends `initialize`, or never sends
+/// `exit` after `shutdown`, keeps waiting for further messages forever. A watchdog stops waiting
+/// once the respective timeout elapsed, and also handles the input stream closing before the
+/// client sent `exit`. In each case, the event is reported to the handler set with
+/// [`Watchdog::on_event`], an `exit` notification is passed to the service on behalf of the client
+/// and [`Server::serve`] returns.
+///
+/// By default, clients have 60 seconds to send `initialize` and 5 seconds to send `exit` after the
+/// `shutdown` request.
+///
+/// [`Server`]: crate::Server
+/// [`Server::serve`]: crate::Server::serve
+#[derive(Clone)]
+pub struct Watchdog {
+    initialize_timeout: Option<Duration>,
+    exit_timeout: Option<Duration>,
+    clock: Arc<dyn Clock>,
+    handler: Option<Handler>,
+}
+
+impl Watchdog {
+    /// Creates a new `Watchdog` with the default timeouts.
+    pub fn new() -> Self {
+        Watchdog {
+            initialize_timeout: Some(Duration::from_secs(60)),
+            exit_timeout: Some(Duration::from_secs(5)),
+            clock: Arc::new(SystemClock),
+            handler: None,
+        }
+    }
+
+    /// Sets how long to wait for the `initialize` request, or disables the timeout with `None`.
+    pub fn initialize_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.initialize_timeout = timeout;
+        self
+    }
+
+    /// Sets how long to wait for the `exit` notification after the `shutdown` request, or disables
+    /// the timeout with `None`.
+    pub fn exit_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.exit_timeout = timeout;
+        self
+    }
+
+    /// Sets the clock used for the timeouts, defaulting to [`SystemClock`].
+    pub fn clock<C: Clock>(mut self, clock: C) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Sets a handler which is invoked with each event before the server stops.
+    pub fn on_event<H>(mut self, handler: H) -> Self
+    where
+        H: Fn(WatchdogEvent) + Send + Sync + 'static,
+    {
+        self.handler = Some(Arc::new(handler));
+        self
+    }
+}
+
+impl Default for Watchdog {
+    fn default() -> Self {
+        Watchdog::new()
+    }
+}
+
+impl Debug for Watchdog {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct(stringify!(Watchdog))
+            .field("initialize_timeout", &self.initialize_timeout)
+            .field("exit_timeout", &self.exit_timeout)
+            .field("clock", &self.clock)
+            .field("on_event", &self.handler.is_some())
+            .finish()
+    }
+}
+
+/// Tracks the lifecycle of a connection on behalf of a [`Watchdog`].
+pub(crate) struct WatchdogState {
+    watchdog: Watchdog,
+    deadline: Option<(WatchdogEvent, BoxFuture<'static, ()>)>,
+    exited: bool,
+}
+
+impl WatchdogState {
+    pub(crate) fn new(watchdog: Watchdog) -> Self {
+        let mut state = WatchdogState {
+            watchdog,
+            deadline: None,
+            exited: false,
+        };
+        state.arm(WatchdogEvent::InitializeTimeout);
+        state
+    }
+
+    /// Updates the deadline according to a message received from the client.
+    pub(crate) fn observe(&mut self, message: &Incoming) {
+        let request = match message {
+            Incoming::Request(request) => request,
+            Incoming::Response(_) => return,
+        };
+
+        match request.method() {
+            "initialize" => self.deadline = None,
+            "shutdown" => self.arm(WatchdogEvent::ExitTimeout),
+            "exit" => {
+                self.deadline = None;
+                self.exited = true;
+            },
+            _ => {},
+        }
+    }
+
+    /// Waits until the current deadline elapsed, returning the corresponding event.
+    pub(crate) async fn expired(&mut self) -> WatchdogEvent {
+        match &mut self.deadline {
+            Some((event, sleep)) => {
+                sleep.await;
+                *event
+            },
+            None => future::pending().await,
+        }
+    }
+
+    /// Returns the event to report when the input stream closed, if any.
+    pub(crate) fn closed(&self) -> Option<WatchdogEvent> {
+        if self.exited {
+            None
+        } else {
+            Some(WatchdogEvent::InputClosed)
+        }
+    }
+
+    /// Reports the given event to the handler of the watchdog.
+    pub(crate) fn report(&self, event: WatchdogEvent) {
+        log::warn!("watchdog stopping the server: {:?}", event);
+        if let Some(handler) = &self.watchdog.handler {
+            handler(event);
+        }
+    }
+
+    fn arm(&mut self, event: WatchdogEvent) {
+        let timeout = match event {
+            WatchdogEvent::InitializeTimeout => self.watchdog.initialize_timeout,
+            WatchdogEvent::ExitTimeout => self.watchdog.exit_timeout,
+            WatchdogEvent::InputClosed => None,
+        };
+        self.deadline = timeout.map(|timeout| (event, self.watchdog.clock.sleep(timeout)));
+    }
+}
@@ -0,0 +1,256 @@
+//! Handling of errors writing to the output, and reconnection of broken transports.
+
+use futures::{
+    future::{self, BoxFuture},
+    lock::Mutex,
+    task::AtomicWaker,
+};
+use std::{
+    fmt::{self, Debug, Formatter},
+    future::Future,
+    io,
+    sync::atomic::{AtomicU64, Ordering},
+    task::Poll,
+    time::Duration,
+};
+
+type Hook<I, O> = Box<dyn FnMut() -> BoxFuture<'static, io::Result<(I, O)>> + Send>;
+
+/// Policy of a [`Server`] for errors writing messages to `stdout`.
+///
+/// Writes failing with errors which usually pass, such as [`WouldBlock`] on slow links or
+/// [`Interrupted`], are retried up to the given number of times, waiting for the backoff delay
+/// before each retry, which doubles up to the maximum delay. Other errors, such as
+/// [`BrokenPipe`] once the editor went away, as well as transient errors which persist beyond the
+/// retries, break the connection. If a reconnect hook was set with [`Server::reconnect`], the
+/// server then continues on a new connection, and otherwise stops writing.
+///
+/// By default, writes are not retried.
+///
+/// # Example
+///
+/// ```rust
+/// # use lspower::WriteErrorPolicy;
+/// # use std::time::Duration;
+/// let policy = WriteErrorPolicy::new()
+///     .retries(5)
+///     .backoff(Duration::from_millis(10), Duration::from_secs(1));
+/// ```
+///
+/// [`Server`]: crate::Server
+/// [`Server::reconnect`]: crate::Server::reconnect
+/// [`WouldBlock`]: io::ErrorKind::WouldBlock
+/// [`Interrupted`]: io::ErrorKind::Interrupted
+/// [`BrokenPipe`]: io::ErrorKind::BrokenPipe
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct WriteErrorPolicy {
+    retries: u32,
+    backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl WriteErrorPolicy {
+    /// Creates a new `WriteErrorPolicy` which does not retry writes.
+    pub fn new() -> Self {
+        WriteErrorPolicy {
+            retries: 0,
+            backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_secs(1),
+        }
+    }
+
+    /// Sets how often a write failing with a transient error is retried, defaulting to `0`.
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Sets the delay before the first retry, which doubles with each further retry up to `max`,
+    /// defaulting to 10 milliseconds and 1 second.
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.backoff = initial;
+        self.max_backoff = max.max(initial);
+        self
+    }
+
+    /// Returns whether a write failing before the given retry, counting from `0`, may be retried.
+    pub(crate) fn may_retry(&self, retry: u32) -> bool {
+        retry < self.retries
+    }
+
+    /// Returns the delay before the given retry, counting from `0`, or `None` if the write is not
+    /// retried anymore.
+    pub(crate) fn delay(&self, kind: io::ErrorKind, retry: u32) -> Option<Duration> {
+        if retry >= self.retries || !is_transient(kind) {
+            return None;
+        }
+        let factor = 2u32.saturating_pow(retry);
+        Some(self.backoff.saturating_mul(factor).min(self.max_backoff))
+    }
+}
+
+impl Default for WriteErrorPolicy {
+    fn default() -> Self {
+        WriteErrorPolicy::new()
+    }
+}
+
+fn is_transient(kind: io::ErrorKind) -> bool {
+    matches!(
+        kind,
+        io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted | io::ErrorKind::TimedOut
+    )
+}
+
+/// The reconnect hook of a [`Server`], handing out the halves of each new connection to the reader
+/// and the writer of the server.
+///
+/// Either side asks for a new connection once its half broke. The first side asking for a given
+/// connection calls the hook, and the other side picks up its half of the same connection: the
+/// reader as soon as the connection changed, and the writer before it writes the next messages.
+///
+/// [`Server`]: crate::Server
+pub(crate) struct Reconnector<I, O> {
+    state: Mutex<Connection<I, O>>,
+    generation: AtomicU64,
+    reader: AtomicWaker,
+}
+
+struct Connection<I, O> {
+    hook: Hook<I, O>,
+    generation: u64,
+    reader: Option<I>,
+    writer: Option<O>,
+}
+
+impl<I, O> Reconnector<I, O> {
+    pub(crate) fn new<F, Fut>(mut hook: F) -> Self
+    where
+        F: FnMut() -> Fut + Send + 'static,
+        Fut: Future<Output = io::Result<(I, O)>> + Send + 'static,
+    {
+        let hook: Hook<I, O> = Box::new(move || Box::pin(hook()));
+        Reconnector {
+            state: Mutex::new(Connection {
+                hook,
+                generation: 0,
+                reader: None,
+                writer: None,
+            }),
+            generation: AtomicU64::new(0),
+            reader: AtomicWaker::new(),
+        }
+    }
+
+    /// Resolves once there is a connection newer than the given generation.
+    pub(crate) async fn reconnected(&self, generation: u64) {
+        future::poll_fn(|cx| {
+            self.reader.register(cx.waker());
+            if self.generation.load(Ordering::Acquire) == generation {
+                Poll::Pending
+            } else {
+                Poll::Ready(())
+            }
+        })
+        .await
+    }
+
+    /// Returns the read half of a connection newer than the given generation, connecting anew if
+    /// there is none yet.
+    pub(crate) async fn reader(&self, generation: &mut u64) -> io::Result<I> {
+        let mut state = self.state.lock().await;
+        if state.generation == *generation {
+            state.connect().await?;
+            self.generation.store(state.generation, Ordering::Release);
+        }
+        *generation = state.generation;
+        Ok(state.reader.take().expect("read half of the connection was taken twice"))
+    }
+
+    /// Returns the write half of a connection newer than the given generation, connecting anew if
+    /// there is none yet.
+    pub(crate) async fn writer(&self, generation: &mut u64) -> io::Result<O> {
+        let mut state = self.state.lock().await;
+        if state.generation == *generation {
+            state.connect().await?;
+            self.generation.store(state.generation, Ordering::Release);
+            self.reader.wake();
+        }
+        *generation = state.generation;
+        Ok(state.writer.take().expect("write half of the connection was taken twice"))
+    }
+
+    /// Returns the write half of a connection the reader already switched to, if any.
+    pub(crate) fn newer_writer(&self, generation: &mut u64) -> Option<O> {
+        let mut state = self.state.try_lock()?;
+        if state.generation == *generation {
+            return None;
+        }
+        *generation = state.generation;
+        state.writer.take()
+    }
+}
+
+impl<I, O> Connection<I, O> {
+    async fn connect(&mut self) -> io::Result<()> {
+        log::info!("connection broke, reconnecting");
+        let (reader, writer) = (self.hook)().await.map_err(|error| {
+            log::error!("failed to reconnect: {}", error);
+            error
+        })?;
+        self.generation += 1;
+        self.reader = Some(reader);
+        self.writer = Some(writer);
+        Ok(())
+    }
+}
+
+impl<I, O> Debug for Reconnector<I, O> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct(stringify!(Reconnector)).finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::FutureExt;
+    use std::sync::{atomic::AtomicU32, Arc};
+
+    #[test]
+    fn delay() {
+        let policy = WriteErrorPolicy::new()
+            .retries(3)
+            .backoff(Duration::from_millis(10), Duration::from_millis(25));
+        assert_eq!(policy.delay(io::ErrorKind::WouldBlock, 0), Some(Duration::from_millis(10)));
+        assert_eq!(policy.delay(io::ErrorKind::WouldBlock, 1), Some(Duration::from_millis(20)));
+        assert_eq!(policy.delay(io::ErrorKind::WouldBlock, 2), Some(Duration::from_millis(25)));
+        assert_eq!(policy.delay(io::ErrorKind::WouldBlock, 3), None);
+        assert_eq!(policy.delay(io::ErrorKind::BrokenPipe, 0), None);
+        assert_eq!(WriteErrorPolicy::default().delay(io::ErrorKind::Interrupted, 0), None);
+    }
+
+    #[tokio::test]
+    async fn reconnect() {
+        let connections = Arc::new(AtomicU32::new(0));
+        let counter = connections.clone();
+        let reconnector = Reconnector::new(move || {
+            let n = counter.fetch_add(1, Ordering::SeqCst) + 1;
+            async move { Ok((n, n)) }
+        });
+
+        // The writer picks up the connection the reader asked for.
+        let (mut read, mut write) = (0, 0);
+        assert!(reconnector.reconnected(read).now_or_never().is_none());
+        assert_eq!(reconnector.reader(&mut read).await.unwrap(), 1);
+        assert_eq!(reconnector.newer_writer(&mut write), Some(1));
+        assert_eq!(reconnector.newer_writer(&mut write), None);
+
+        // Both halves broke, but only the first side asking reconnects.
+        assert_eq!(reconnector.writer(&mut write).await.unwrap(), 2);
+        assert!(reconnector.reconnected(read).now_or_never().is_some());
+        assert_eq!(reconnector.reader(&mut read).await.unwrap(), 2);
+        assert_eq!(connections.load(Ordering::SeqCst), 2);
+        assert_eq!((read, write), (2, 2));
+    }
+}
@@ -0,0 +1,383 @@
+//! Conversions between file paths and `file` URIs.
+//!
+//! Clients and servers often disagree on the exact spelling of the URI of a file: drive letters may
+//! be upper or lower case, the colon after the drive letter may be percent-encoded (`c%3A`), and
+//! different characters are escaped. The functions in this module convert between [`Path`]s and
+//! [`Url`]s consistently on all platforms, and [`normalize`] maps all spellings of the URI of a
+//! file to the same [`Url`], which [`UriMap`] uses to look up documents.
+//!
+//! On Windows, drive letter paths (`C:\dir\file`), UNC paths (`\\server\share\file`) and their
+//! extended-length forms (`\\?\C:\dir\file`, `\\?\UNC\server\share\file`) are supported. Drive
+//! letters are written in lower case to URIs and in upper case to paths.
+//!
+//! [`Path`]: std::path::Path
+//! [`Url`]: lsp::Url
+
+use lsp::Url;
+use percent_encoding::{percent_decode_str, percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use std::{
+    collections::{hash_map, HashMap},
+    path::{Path, PathBuf},
+};
+
+/// Characters which are percent-encoded in the path of a `file` URI.
+const PATH: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~')
+    .remove(b'/')
+    .remove(b':');
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Style {
+    Unix,
+    Windows,
+}
+
+impl Style {
+    const NATIVE: Style = if cfg!(windows) { Style::Windows } else { Style::Unix };
+}
+
+/// Converts an absolute file path to a `file` URI.
+///
+/// Returns `None` if the path is relative, or if it is not valid UTF-8 on platforms other than
+/// Unix.
+pub fn from_file_path(path: impl AsRef<Path>) -> Option<Url> {
+    path_to_uri(&path_to_bytes(path.as_ref())?, Style::NATIVE)
+}
+
+/// Converts a `file` URI to an absolute file path.
+///
+/// Returns `None` if the URI has a different scheme, or if it cannot be represented as a path on
+/// the current platform, e.g. a URI with a host on Unix or a URI without a drive letter on Windows.
+pub fn to_file_path(uri: &Url) -> Option<PathBuf> {
+    bytes_to_path(uri_to_path(uri, Style::NATIVE)?)
+}
+
+/// Normalizes the spelling of a `file` URI.
+///
+/// Characters are percent-encoded consistently and drive letters are converted to lower case. URIs
+/// with other schemes are returned unchanged.
+pub fn normalize(uri: &Url) -> Url {
+    normalize_with(uri, false)
+}
+
+fn normalize_with(uri: &Url, case_insensitive: bool) -> Url {
+    if uri.scheme() != "file" {
+        return uri.clone();
+    }
+
+    let mut path: Vec<u8> = percent_decode_str(uri.path()).collect();
+    if let Some(drive) = drive_letter(path.get(1 ..).unwrap_or_default()) {
+        path[1] = drive.to_ascii_lowercase();
+    }
+    let path = if case_insensitive {
+        encode(String::from_utf8_lossy(&path).to_lowercase().as_bytes())
+    } else {
+        encode(&path)
+    };
+
+    let mut normalized = uri.clone();
+    normalized.set_path(&path);
+    normalized
+}
+
+fn path_to_uri(path: &[u8], style: Style) -> Option<Url> {
+    match style {
+        Style::Unix if path.starts_with(b"/") => Url::parse(&format!("file://{}", encode(path))).ok(),
+        Style::Unix => None,
+        Style::Windows => {
+            let mut path: Vec<u8> = path.iter().map(|&b| if b == b'\\' { b'/' } else { b }).collect();
+            if path.starts_with(b"//?/UNC/") {
+                path.drain(2 .. 8);
+            } else if path.starts_with(b"//?/") {
+                path.drain(.. 4);
+            }
+
+            if let Some(unc) = path.strip_prefix(b"//") {
+                let split = unc.iter().position(|&b| b == b'/').unwrap_or(unc.len());
+                let (host, rest) = unc.split_at(split);
+                let host = std::str::from_utf8(host).ok().filter(|host| !host.is_empty())?;
+                let rest = if rest.is_empty() { &b"/"[..] } else { rest };
+                Url::parse(&format!("file://{}{}", host, encode(rest))).ok()
+            } else {
+                let drive = drive_letter(&path)?.to_ascii_lowercase();
+                let rest = match &path[2 ..] {
+                    [] => &b"/"[..],
+                    rest => rest,
+                };
+                Url::parse(&format!("file:///{}:{}", drive as char, encode(rest))).ok()
+            }
+        },
+    }
+}
+
+fn uri_to_path(uri: &Url, style: Style) -> Option<Vec<u8>> {
+    if uri.scheme() != "file" {
+        return None;
+    }
+
+    let host = uri.host_str().filter(|host| !host.is_empty());
+    let path: Vec<u8> = percent_decode_str(uri.path()).collect();
+    match style {
+        Style::Unix if host.is_some() => None,
+        Style::Unix => Some(path),
+        Style::Windows => {
+            let (mut result, rest) = match host {
+                Some(host) => (format!("//{}", host).into_bytes(), &path[..]),
+                None => {
+                    let drive = drive_letter(path.get(1 ..).unwrap_or_default())?;
+                    (vec![drive.to_ascii_uppercase(), b':'], &path[3 ..])
+                },
+            };
+            result.extend_from_slice(if rest.is_empty() { b"/" } else { rest });
+            Some(result.into_iter().map(|b| if b == b'/' { b'\\' } else { b }).collect())
+        },
+    }
+}
+
+/// Returns the drive letter if `path` starts with one, followed by a separator or nothing.
+fn drive_letter(path: &[u8]) -> Option<u8> {
+    match path {
+        [drive, b':'] | [drive, b':', b'/' | b'\\', ..] if drive.is_ascii_alphabetic() => Some(*drive),
+        _ => None,
+    }
+}
+
+fn encode(path: &[u8]) -> String {
+    percent_encode(path, PATH).to_string()
+}
+
+#[cfg(unix)]
+fn path_to_bytes(path: &Path) -> Option<Vec<u8>> {
+    use std::os::unix::ffi::OsStrExt;
+    Some(path.as_os_str().as_bytes().to_vec())
+}
+
+#[cfg(not(unix))]
+fn path_to_bytes(path: &Path) -> Option<Vec<u8>> {
+    path.to_str().map(|path| path.as_bytes().to_vec())
+}
+
+#[cfg(unix)]
+fn bytes_to_path(bytes: Vec<u8>) -> Option<PathBuf> {
+    use std::{ffi::OsString, os::unix::ffi::OsStringExt};
+    Some(OsString::from_vec(bytes).into())
+}
+
+#[cfg(not(unix))]
+fn bytes_to_path(bytes: Vec<u8>) -> Option<PathBuf> {
+    String::from_utf8(bytes).ok().map(PathBuf::from)
+}
+
+/// A map keyed by normalized URIs.
+///
+/// All spellings of the URI of a file, as produced by different clients, refer to the same entry.
+/// See [`normalize`] for details. Keys are returned in their normalized form.
+///
+/// Maps created with [`UriMap::case_insensitive`] additionally ignore the case of `file` URIs, as
+/// appropriate for the default file systems on Windows and macOS.
+#[derive(Clone, Debug)]
+pub struct UriMap<T> {
+    entries: HashMap<Url, T>,
+    case_insensitive: bool,
+}
+
+impl<T> UriMap<T> {
+    /// Creates a new, empty `UriMap`.
+    pub fn new() -> Self {
+        UriMap {
+            entries: HashMap::new(),
+            case_insensitive: false,
+        }
+    }
+
+    /// Creates a new, empty `UriMap` which ignores the case of `file` URIs.
+    pub fn case_insensitive() -> Self {
+        UriMap {
+            entries: HashMap::new(),
+            case_insensitive: true,
+        }
+    }
+
+    fn key(&self, uri: &Url) -> Url {
+        normalize_with(uri, self.case_insensitive)
+    }
+
+    /// Inserts a value, returning the previous value for the same URI, if any.
+    pub fn insert(&mut self, uri: &Url, value: T) -> Option<T> {
+        let key = self.key(uri);
+        self.entries.insert(key, value)
+    }
+
+    /// Returns a reference to the value for the URI.
+    pub fn get(&self, uri: &Url) -> Option<&T> {
+        self.entries.get(&self.key(uri))
+    }
+
+    /// Returns a mutable reference to the value for the URI.
+    pub fn get_mut(&mut self, uri: &Url) -> Option<&mut T> {
+        let key = self.key(uri);
+        self.entries.get_mut(&key)
+    }
+
+    /// Returns whether the map contains a value for the URI.
+    pub fn contains_key(&self, uri: &Url) -> bool {
+        self.entries.contains_key(&self.key(uri))
+    }
+
+    /// Removes the value for the URI and returns it.
+    pub fn remove(&mut self, uri: &Url) -> Option<T> {
+        let key = self.key(uri);
+        self.entries.remove(&key)
+    }
+
+    /// Removes all values.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Returns the number of values in the map.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` if the map contains no values.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns an iterator over the normalized URIs and their values, in arbitrary order.
+    pub fn iter(&self) -> hash_map::Iter<'_, Url, T> {
+        self.entries.iter()
+    }
+}
+
+impl<T> Default for UriMap<T> {
+    fn default() -> Self {
+        UriMap::new()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a UriMap<T> {
+    type IntoIter = hash_map::Iter<'a, Url, T>;
+    type Item = (&'a Url, &'a T);
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn uri(uri: &str) -> Url {
+        Url::parse(uri).unwrap()
+    }
+
+    fn windows_to_uri(path: &str) -> Option<String> {
+        path_to_uri(path.as_bytes(), Style::Windows).map(String::from)
+    }
+
+    fn windows_to_path(uri: &str) -> Option<String> {
+        uri_to_path(&Url::parse(uri).unwrap(), Style::Windows).map(|path| String::from_utf8(path).unwrap())
+    }
+
+    #[test]
+    fn unix() {
+        let converted = path_to_uri(b"/home/user/a b#c%d.rs", Style::Unix).unwrap();
+        assert_eq!(converted.as_str(), "file:///home/user/a%20b%23c%25d.rs");
+        let path = uri_to_path(&converted, Style::Unix).unwrap();
+        assert_eq!(path, b"/home/user/a b#c%d.rs");
+
+        assert_eq!(path_to_uri(b"relative/path", Style::Unix), None);
+        assert_eq!(uri_to_path(&uri("file://server/share"), Style::Unix), None);
+        let localhost = uri_to_path(&uri("file://localhost/tmp"), Style::Unix);
+        assert_eq!(localhost, Some(b"/tmp".to_vec()));
+        assert_eq!(uri_to_path(&uri("untitled:Untitled-1"), Style::Unix), None);
+    }
+
+    #[test]
+    fn windows_drive_letters() {
+        assert_eq!(
+            windows_to_uri(r"C:\Users\a b.rs").as_deref(),
+            Some("file:///c:/Users/a%20b.rs")
+        );
+        assert_eq!(windows_to_uri("C:/Users").as_deref(), Some("file:///c:/Users"));
+        assert_eq!(windows_to_uri(r"C:").as_deref(), Some("file:///c:/"));
+        assert_eq!(windows_to_uri(r"\\?\D:\dir").as_deref(), Some("file:///d:/dir"));
+        assert_eq!(windows_to_uri(r"dir\file"), None);
+        assert_eq!(windows_to_uri(r"\dir\file"), None);
+
+        assert_eq!(
+            windows_to_path("file:///c%3A/Users/a%20b.rs").as_deref(),
+            Some(r"C:\Users\a b.rs")
+        );
+        assert_eq!(windows_to_path("file:///C:/").as_deref(), Some(r"C:\"));
+        assert_eq!(windows_to_path("file:///c:").as_deref(), Some(r"C:\"));
+        assert_eq!(windows_to_path("file:///Users"), None);
+    }
+
+    #[test]
+    fn windows_unc() {
+        assert_eq!(
+            windows_to_uri(r"\\server\share\a b.rs").as_deref(),
+            Some("file://server/share/a%20b.rs")
+        );
+        assert_eq!(
+            windows_to_uri(r"\\?\UNC\server\share").as_deref(),
+            Some("file://server/share")
+        );
+        assert_eq!(windows_to_uri(r"\\server").as_deref(), Some("file://server/"));
+        assert_eq!(windows_to_uri(r"\\"), None);
+
+        assert_eq!(
+            windows_to_path("file://server/share/a%20b.rs").as_deref(),
+            Some(r"\\server\share\a b.rs")
+        );
+        assert_eq!(windows_to_path("file://localhost/c:/dir").as_deref(), Some(r"C:\dir"));
+    }
+
+    #[test]
+    fn round_trip() {
+        let path = if cfg!(windows) {
+            r"C:\dir\file name.rs"
+        } else {
+            "/dir/file name.rs"
+        };
+        let converted = from_file_path(path).unwrap();
+        assert_eq!(to_file_path(&converted), Some(PathBuf::from(path)));
+        assert_eq!(from_file_path("relative"), None);
+    }
+
+    #[test]
+    fn normalization() {
+        let expected = uri("file:///c:/dir/a%20b.rs");
+        assert_eq!(normalize(&uri("file:///C%3A/dir/a%20b.rs")), expected);
+        assert_eq!(normalize(&uri("file:///C:/dir/a b.rs")), expected);
+        assert_eq!(normalize(&uri("file://localhost/c:/dir/a%20b.rs")), expected);
+        assert_eq!(normalize(&uri("file:///c:/dir/%61%20b.rs")), expected);
+        assert_eq!(normalize(&uri("untitled:Untitled-1")), uri("untitled:Untitled-1"));
+    }
+
+    #[test]
+    fn map() {
+        let mut map = UriMap::new();
+        assert_eq!(map.insert(&uri("file:///C%3A/dir/file.rs"), 1), None);
+        assert_eq!(map.insert(&uri("file:///c:/dir/file.rs"), 2), Some(1));
+        assert_eq!(map.get(&uri("file:///C:/dir/file.rs")), Some(&2));
+        assert_eq!(map.get(&uri("file:///C:/Dir/File.rs")), None);
+        assert_eq!(map.iter().next(), Some((&uri("file:///c:/dir/file.rs"), &2)));
+        assert_eq!(map.remove(&uri("file:///C:/dir/file.rs")), Some(2));
+        assert!(map.is_empty());
+
+        let mut map = UriMap::case_insensitive();
+        map.insert(&uri("file:///C:/Dir/File.rs"), 1);
+        assert!(map.contains_key(&uri("file:///c%3a/dir/file.RS")));
+        assert!(!map.contains_key(&uri("file:///c:/dir/other.rs")));
+        map.insert(&uri("file:///C%C3%89.rs"), 2);
+        assert_eq!(map.get(&uri("file:///c%C3%A9.rs")), Some(&2));
+    }
+}
@@ -0,0 +1,500 @@
+//! Indexing of the files in the workspace folders.
+//!
+//! Servers which analyze a whole project, e.g. to answer `workspace/symbol` requests, need to know
+//! all files in the workspace, not just the documents the client opened. A [`FileIndex`] walks the
+//! workspace folders once and keeps its list of files up to date from the
+//! `workspace/didChangeWatchedFiles` and `workspace/didChangeWorkspaceFolders` notifications. Each
+//! change is also published to the [`FileChangeStream`]s of the index, so that analyses can react
+//! to files being created, changed or deleted in a single place.
+//!
+//! # Example
+//!
+//! ```rust
+//! # use lspower::{jsonrpc::Result, lsp::*, selector::Glob, workspace::FileIndex, LanguageServer};
+//! #[derive(Debug)]
+//! struct Backend {
+//!     files: FileIndex,
+//! }
+//!
+//! #[lspower::async_trait]
+//! impl LanguageServer for Backend {
+//!     async fn initialize(&self, params: InitializeParams) -> Result<InitializeResult> {
+//!         // Walking the folders blocks, so it must not run on the threads of the runtime.
+//!         let files = self.files.clone();
+//!         let folders = params.workspace_folders.unwrap_or_default();
+//!         tokio::task::spawn_blocking(move || {
+//!             for folder in folders {
+//!                 if let Err(error) = files.add_folder(&folder.uri) {
+//!                     log::warn!("failed to index {}: {}", folder.uri, error);
+//!                 }
+//!             }
+//!         });
+//!         Ok(InitializeResult::default())
+//!     }
+//!
+//!     async fn shutdown(&self) -> Result<()> {
+//!         Ok(())
+//!     }
+//!
+//!     async fn did_change_watched_files(&self, params: DidChangeWatchedFilesParams) {
+//!         self.files.did_change_watched_files(&params);
+//!     }
+//! }
+//!
+//! let files = FileIndex::new().ignore(Glob::new("**/{.git,target}").unwrap());
+//! let changes = files.changes();
+//! let backend = Backend { files };
+//! ```
+
+use crate::{
+    feed::{Feed, Subscription},
+    selector::Glob,
+    uri,
+};
+use futures::stream::{FusedStream, Stream};
+use lsp::Url;
+use percent_encoding::percent_decode_str;
+use std::{
+    collections::BTreeSet,
+    fmt::{self, Debug, Formatter},
+    fs,
+    io,
+    path::Path,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+/// The number of changes buffered for each [`FileChangeStream`] before the oldest ones are dropped.
+const CHANGES_CAPACITY: usize = 1024;
+
+/// A change of a file in the [`FileIndex`].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum FileChange {
+    /// The file was added to the index, because it was created or its workspace folder was added.
+    Created(Url),
+    /// The contents of an indexed file changed.
+    Changed(Url),
+    /// The file was removed from the index, because it was deleted or its workspace folder was
+    /// removed.
+    Deleted(Url),
+}
+
+impl FileChange {
+    /// Returns the URI of the file.
+    pub fn uri(&self) -> &Url {
+        match self {
+            FileChange::Created(uri) | FileChange::Changed(uri) | FileChange::Deleted(uri) => uri,
+        }
+    }
+}
+
+/// The files under the workspace folders, except for ignored ones.
+///
+/// Files are identified by their [normalized] URIs. Folders are walked on the calling thread, so
+/// servers running on an async runtime should call [`add_folder`] and
+/// [`did_change_workspace_folders`] from a task which may block, such as one spawned with
+/// `tokio::task::spawn_blocking`. Symbolic links to directories are not followed.
+///
+/// Cloning the index returns a handle to the same files.
+///
+/// [normalized]: crate::uri::normalize
+/// [`add_folder`]: FileIndex::add_folder
+/// [`did_change_workspace_folders`]: FileIndex::did_change_workspace_folders
+#[derive(Clone, Default)]
+pub struct FileIndex {
+    ignore: Arc<Vec<Glob>>,
+    inner: Arc<Mutex<IndexInner>>,
+}
+
+struct IndexInner {
+    folders: BTreeSet<Url>,
+    files: BTreeSet<Url>,
+    changes: Feed<FileChange>,
+}
+
+impl Default for IndexInner {
+    fn default() -> Self {
+        IndexInner {
+            folders: BTreeSet::new(),
+            files: BTreeSet::new(),
+            changes: Feed::new(CHANGES_CAPACITY),
+        }
+    }
+}
+
+impl FileIndex {
+    /// Creates an index without folders.
+    pub fn new() -> Self {
+        FileIndex::default()
+    }
+
+    /// Ignores the files and directories whose path matches the glob, along with all files in
+    /// ignored directories.
+    ///
+    /// Ignored directories are not walked at all, so `**/target` is cheaper than `**/target/**`,
+    /// which only ignores the files within. This must be called before the index is cloned.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the index was cloned before.
+    pub fn ignore(mut self, glob: Glob) -> Self {
+        Arc::get_mut(&mut self.ignore)
+            .expect("ignore globs must be set before the index is cloned")
+            .push(glob);
+        self
+    }
+
+    /// Returns a stream of the changes of the index from now on.
+    pub fn changes(&self) -> FileChangeStream {
+        FileChangeStream(self.inner.lock().unwrap().changes.subscribe())
+    }
+
+    /// Adds the files under the given workspace folder to the index.
+    ///
+    /// Fails if the URI is not a `file` URI or the folder cannot be read, in which case the index
+    /// is unchanged. Subdirectories which cannot be read are skipped.
+    pub fn add_folder(&self, folder: &Url) -> io::Result<()> {
+        let folder = uri::normalize(folder);
+        let path = uri::to_file_path(&folder).ok_or_else(|| {
+            let message = format!("not a file URI: {}", folder);
+            io::Error::new(io::ErrorKind::InvalidInput, message)
+        })?;
+
+        let mut files = Vec::new();
+        match self.is_ignored(&folder) {
+            true => fs::read_dir(&path).map(drop)?,
+            false => self.walk(&path, &mut files, true)?,
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+        inner.folders.insert(folder);
+        let created: Vec<_> = files.into_iter().filter(|file| inner.files.insert(file.clone())).collect();
+        inner.publish(created.into_iter().map(FileChange::Created));
+        Ok(())
+    }
+
+    /// Removes the files under the given workspace folder from the index, unless they are also
+    /// under another folder of the index.
+    pub fn remove_folder(&self, folder: &Url) {
+        let folder = uri::normalize(folder);
+        let mut inner = self.inner.lock().unwrap();
+        if !inner.folders.remove(&folder) {
+            return;
+        }
+
+        let deleted: Vec<_> = inner
+            .files
+            .iter()
+            .filter(|file| contains(&folder, file) && !inner.folders.iter().any(|other| contains(other, file)))
+            .cloned()
+            .collect();
+        for file in &deleted {
+            inner.files.remove(file);
+        }
+        inner.publish(deleted.into_iter().map(FileChange::Deleted));
+    }
+
+    /// Updates the folders of the index from a `workspace/didChangeWorkspaceFolders` notification.
+    ///
+    /// Removed folders are removed before added folders are walked. Folders which cannot be read
+    /// are logged and skipped.
+    pub fn did_change_workspace_folders(&self, params: &lsp::DidChangeWorkspaceFoldersParams) {
+        for folder in &params.event.removed {
+            self.remove_folder(&folder.uri);
+        }
+        for folder in &params.event.added {
+            if let Err(error) = self.add_folder(&folder.uri) {
+                log::warn!("failed to index workspace folder {}: {}", folder.uri, error);
+            }
+        }
+    }
+
+    /// Updates the files of the index from a `workspace/didChangeWatchedFiles` notification.
+    ///
+    /// Created files are only added if they are under a folder of the index and not ignored, and
+    /// changes of files which are not indexed are dropped. Since clients report the deletion of a
+    /// directory as a single event, a deleted URI removes all files under it.
+    pub fn did_change_watched_files(&self, params: &lsp::DidChangeWatchedFilesParams) {
+        let mut inner = self.inner.lock().unwrap();
+        for event in &params.changes {
+            let file = uri::normalize(&event.uri);
+            let changes = match event.typ {
+                lsp::FileChangeType::CREATED => {
+                    let indexed = inner.folders.iter().any(|folder| contains(folder, &file)) && !self.is_ignored(&file);
+                    match indexed && is_file(&file) && inner.files.insert(file.clone()) {
+                        true => vec![FileChange::Created(file)],
+                        false => Vec::new(),
+                    }
+                },
+                lsp::FileChangeType::CHANGED => match inner.files.contains(&file) {
+                    true => vec![FileChange::Changed(file)],
+                    false => Vec::new(),
+                },
+                lsp::FileChangeType::DELETED => {
+                    let deleted: Vec<_> = inner
+                        .files
+                        .iter()
+                        .filter(|indexed| **indexed == file || contains(&file, indexed))
+                        .cloned()
+                        .collect();
+                    for file in &deleted {
+                        inner.files.remove(file);
+                    }
+                    deleted.into_iter().map(FileChange::Deleted).collect()
+                },
+                typ => {
+                    log::warn!("unknown file change type {:?} for {}, ignoring", typ, file);
+                    Vec::new()
+                },
+            };
+            inner.publish(changes);
+        }
+    }
+
+    /// Returns whether the file is indexed.
+    pub fn contains(&self, file: &Url) -> bool {
+        self.inner.lock().unwrap().files.contains(&uri::normalize(file))
+    }
+
+    /// Returns the URIs of all indexed files, in lexicographic order.
+    pub fn files(&self) -> Vec<Url> {
+        self.inner.lock().unwrap().files.iter().cloned().collect()
+    }
+
+    /// Returns the URIs of the workspace folders of the index, in lexicographic order.
+    pub fn folders(&self) -> Vec<Url> {
+        self.inner.lock().unwrap().folders.iter().cloned().collect()
+    }
+
+    /// Returns the number of indexed files.
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().files.len()
+    }
+
+    /// Returns `true` if no files are indexed.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Collects the URIs of the files under the given directory which are not ignored.
+    fn walk(&self, dir: &Path, files: &mut Vec<Url>, root: bool) -> io::Result<()> {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(error) if root => return Err(error),
+            Err(error) => {
+                log::debug!("failed to read {}, skipping: {}", dir.display(), error);
+                return Ok(());
+            },
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let uri = match uri::from_file_path(&path) {
+                Some(uri) => uri::normalize(&uri),
+                None => continue,
+            };
+            // The directories containing the entry were checked before they were walked.
+            if self.matches_ignored(&uri) {
+                continue;
+            }
+            match entry.file_type() {
+                Ok(typ) if typ.is_dir() => self.walk(&path, files, false)?,
+                Ok(typ) if typ.is_file() || typ.is_symlink() && path.is_file() => files.push(uri),
+                _ => {},
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns whether the file or directory, or any directory containing it, is ignored.
+    fn is_ignored(&self, uri: &Url) -> bool {
+        if self.ignore.is_empty() {
+            return false;
+        }
+        let path = percent_decode_str(uri.path()).decode_utf8_lossy();
+        let ancestors = path.match_indices('/').skip(1).map(|(index, _)| &path[.. index]);
+        let mut paths = ancestors.chain(std::iter::once(path.trim_end_matches('/')));
+        paths.any(|path| self.ignore.iter().any(|glob| glob.is_match(path)))
+    }
+
+    /// Returns whether the file or directory itself matches an ignore glob.
+    fn matches_ignored(&self, uri: &Url) -> bool {
+        if self.ignore.is_empty() {
+            return false;
+        }
+        let path = percent_decode_str(uri.path()).decode_utf8_lossy();
+        self.ignore.iter().any(|glob| glob.is_match(path.trim_end_matches('/')))
+    }
+}
+
+impl IndexInner {
+    fn publish(&mut self, changes: impl IntoIterator<Item = FileChange>) {
+        for change in changes {
+            self.changes.publish(|| change);
+        }
+    }
+}
+
+impl Debug for FileIndex {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let inner = self.inner.lock().unwrap();
+        f.debug_struct(stringify!(FileIndex))
+            .field("ignore", &self.ignore.iter().map(Glob::as_str).collect::<Vec<_>>())
+            .field("folders", &inner.folders)
+            .field("files", &inner.files.len())
+            .finish()
+    }
+}
+
+/// Returns whether the file is under the given directory.
+fn contains(dir: &Url, file: &Url) -> bool {
+    let dir = dir.as_str().trim_end_matches('/');
+    file.as_str().strip_prefix(dir).is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// Returns whether the URI refers to an existing file, as opposed to a directory.
+fn is_file(uri: &Url) -> bool {
+    uri::to_file_path(uri).is_some_and(|path| path.is_file())
+}
+
+/// A stream of the changes of a [`FileIndex`], returned by [`FileIndex::changes`].
+///
+/// The stream buffers up to 1024 changes which were not received yet. A stream falling further
+/// behind misses the oldest changes, which is logged as a warning, so that a stream which is not
+/// polled does not grow without bounds. The stream ends once all handles to the index are dropped.
+#[must_use = "streams do nothing unless polled"]
+pub struct FileChangeStream(Subscription<FileChange>);
+
+impl Stream for FileChangeStream {
+    type Item = FileChange;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.0).poll_next(cx)
+    }
+}
+
+impl FusedStream for FileChangeStream {
+    fn is_terminated(&self) -> bool {
+        self.0.is_terminated()
+    }
+}
+
+impl Debug for FileChangeStream {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct(stringify!(FileChangeStream)).finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+    use std::path::PathBuf;
+
+    /// A temporary directory which is removed once dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("lspower-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&path);
+            fs::create_dir_all(&path).unwrap();
+            TempDir(path)
+        }
+
+        fn create(&self, file: &str) -> Url {
+            let path = self.0.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, "").unwrap();
+            self.uri(file)
+        }
+
+        fn uri(&self, file: &str) -> Url {
+            uri::normalize(&uri::from_file_path(self.0.join(file)).unwrap())
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn event(uri: &Url, typ: lsp::FileChangeType) -> lsp::DidChangeWatchedFilesParams {
+        lsp::DidChangeWatchedFilesParams {
+            changes: vec![lsp::FileEvent { uri: uri.clone(), typ }],
+        }
+    }
+
+    #[tokio::test]
+    async fn index() {
+        let dir = TempDir::new("file-index");
+        let main = dir.create("src/main.rs");
+        let lib = dir.create("src/lib.rs");
+        dir.create("target/debug/main.d");
+        dir.create(".git/HEAD");
+
+        let index = FileIndex::new().ignore(Glob::new("**/{target,.git}").unwrap());
+        let mut changes = index.changes();
+        index.add_folder(&dir.uri("")).unwrap();
+        assert_eq!(index.files(), [lib.clone(), main.clone()]);
+        assert_eq!(changes.next().await, Some(FileChange::Created(lib.clone())));
+        assert_eq!(changes.next().await, Some(FileChange::Created(main.clone())));
+
+        let new = dir.create("src/new.rs");
+        index.did_change_watched_files(&event(&new, lsp::FileChangeType::CREATED));
+        index.did_change_watched_files(&event(&dir.create("target/new.rs"), lsp::FileChangeType::CREATED));
+        index.did_change_watched_files(&event(&main, lsp::FileChangeType::CHANGED));
+        index.did_change_watched_files(&event(&dir.uri("README.md"), lsp::FileChangeType::CHANGED));
+        assert_eq!(changes.next().await, Some(FileChange::Created(new.clone())));
+        assert_eq!(changes.next().await, Some(FileChange::Changed(main.clone())));
+
+        // Deleting a directory removes all files under it.
+        index.did_change_watched_files(&event(&dir.uri("src"), lsp::FileChangeType::DELETED));
+        assert!(index.is_empty());
+        let deleted: Vec<_> = changes.by_ref().take(3).collect().await;
+        assert_eq!(deleted, [FileChange::Deleted(lib), FileChange::Deleted(main), FileChange::Deleted(new)]);
+
+        index.remove_folder(&dir.uri(""));
+        assert!(index.folders().is_empty());
+        drop(index);
+        assert_eq!(changes.next().await, None);
+    }
+
+    #[test]
+    fn folders() {
+        let dir = TempDir::new("file-index-folders");
+        let a = dir.create("a/file.rs");
+        let b = dir.create("b/file.rs");
+
+        let index = FileIndex::new();
+        index.add_folder(&dir.uri("")).unwrap();
+        index.add_folder(&dir.uri("a")).unwrap();
+        assert_eq!(index.len(), 2);
+
+        // Files remain indexed as long as they are under any folder.
+        index.remove_folder(&dir.uri(""));
+        assert_eq!(index.files(), std::slice::from_ref(&a));
+
+        let params = lsp::DidChangeWorkspaceFoldersParams {
+            event: lsp::WorkspaceFoldersChangeEvent {
+                added: vec![lsp::WorkspaceFolder {
+                    uri: dir.uri("b"),
+                    name: "b".into(),
+                }],
+                removed: vec![lsp::WorkspaceFolder {
+                    uri: dir.uri("a"),
+                    name: "a".into(),
+                }],
+            },
+        };
+        index.did_change_workspace_folders(&params);
+        assert_eq!(index.folders(), [dir.uri("b")]);
+        assert!(index.contains(&b) && !index.contains(&a));
+
+        assert!(index.add_folder(&dir.uri("missing")).is_err());
+        assert!(index.add_folder(&Url::parse("untitled:Untitled-1").unwrap()).is_err());
+        assert_eq!(index.folders(), [dir.uri("b")]);
+    }
+}
@@ -115,7 +115,7 @@ FLAGS:
             let cargo = metadata::cargo()?;
             let mut cmd = Command::new(cargo);
             cmd.current_dir(metadata::project_root());
-            cmd.args(&["build", "--package", "lspower"]);
+            cmd.args(["build", "--package", "lspower"]);
             cmd.args(cargo_args);
             cmd.status()?;
 
@@ -146,9 +146,9 @@ FLAGS:
             let mut cmd = Command::new(cargo);
             cmd.current_dir(metadata::project_root());
             cmd.env("RUSTFLAGS", "-Dwarnings");
-            cmd.args(&["check", "--all-targets"]);
-            cmd.args(&["--package", "xtask"]);
-            cmd.args(&["--package", "lspower"]);
+            cmd.args(["check", "--all-targets"]);
+            cmd.args(["--package", "xtask"]);
+            cmd.args(["--package", "lspower"]);
             cmd.args(cargo_args);
             cmd.status()?;
             Ok(())
@@ -177,11 +177,11 @@ FLAGS:
             let cargo = metadata::cargo()?;
             let mut cmd = Command::new(cargo);
             cmd.current_dir(metadata::project_root());
-            cmd.args(&["clippy", "--all-targets"]);
-            cmd.args(&["--package", "xtask"]);
-            cmd.args(&["--package", "lspower"]);
+            cmd.args(["clippy", "--all-targets"]);
+            cmd.args(["--package", "xtask"]);
+            cmd.args(["--package", "lspower"]);
             cmd.args(cargo_args);
-            cmd.args(&["--", "-D", "warnings"]);
+            cmd.args(["--", "-D", "warnings"]);
             cmd.status()?;
             Ok(())
         }
@@ -209,7 +209,7 @@ FLAGS:
             let cargo = metadata::cargo()?;
             let mut cmd = Command::new(cargo);
             cmd.current_dir(metadata::project_root());
-            cmd.args(&["+nightly", "doc"]);
+            cmd.args(["+nightly", "doc"]);
             cmd.args(cargo_args);
             cmd.status()?;
             Ok(())
@@ -238,7 +238,7 @@ FLAGS:
             let cargo = metadata::cargo()?;
             let mut cmd = Command::new(cargo);
             cmd.current_dir(metadata::project_root());
-            cmd.args(&["+nightly", "fmt", "--all"]);
+            cmd.args(["+nightly", "fmt", "--all"]);
             cmd.args(cargo_args);
             cmd.status()?;
             Ok(())
@@ -267,10 +267,10 @@ FLAGS:
             let cargo = metadata::cargo()?;
             let mut cmd = Command::new(cargo);
             cmd.current_dir(metadata::project_root());
-            cmd.args(&["+nightly", "tarpaulin"]);
-            cmd.args(&["--out", "Xml"]);
-            cmd.args(&["--packages", "xtask", "lspower"]);
-            cmd.args(&["--exclude-files", "xtask", "lspower-macros"]);
+            cmd.args(["+nightly", "tarpaulin"]);
+            cmd.args(["--out", "Xml"]);
+            cmd.args(["--packages", "xtask", "lspower"]);
+            cmd.args(["--exclude-files", "xtask", "lspower-macros"]);
             cmd.args(cargo_args);
             cmd.status()?;
 
@@ -301,9 +301,9 @@ FLAGS:
             let mut cmd = Command::new(cargo);
             cmd.current_dir(metadata::project_root());
             cmd.env("RUSTFLAGS", "-Dwarnings");
-            cmd.args(&["test", "--examples", "--lib", "--tests"]);
-            cmd.args(&["--package", "xtask"]);
-            cmd.args(&["--package", "lspower"]);
+            cmd.args(["test", "--examples", "--lib", "--tests"]);
+            cmd.args(["--package", "xtask"]);
+            cmd.args(["--package", "lspower"]);
             cmd.args(cargo_args);
             cmd.status()?;
 
@@ -333,9 +333,9 @@ FLAGS:
             let cargo = metadata::cargo()?;
             let mut cmd = Command::new(cargo);
             cmd.current_dir(metadata::project_root());
-            cmd.args(&["+nightly", "udeps"]);
-            cmd.args(&["--all-targets"]);
-            cmd.args(&["--package", "lspower"]);
+            cmd.args(["+nightly", "udeps"]);
+            cmd.args(["--all-targets"]);
+            cmd.args(["--package", "lspower"]);
             cmd.args(cargo_args);
             cmd.status()?;
 